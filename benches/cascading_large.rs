@@ -0,0 +1,148 @@
+//! Cascading trees at production sizes, with over a million leaves.
+//!
+//! Building the trees takes a while, so the groups use few samples.
+
+use std::time::Duration;
+
+use criterion::measurement::WallTime;
+use criterion::{
+    criterion_group, criterion_main, BatchSize, BenchmarkGroup, BenchmarkId, Criterion, Throughput,
+};
+use poseidon::Poseidon;
+use semaphore::Field;
+use storage::{GenericStorage, MmapVec};
+use trees::cascading::CascadingMerkleTree;
+use trees::Parallelism;
+
+criterion_main!(cascading_large);
+criterion_group!(
+    name = cascading_large;
+    config = Criterion::default()
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(20));
+    targets = bench_large_init, bench_large_push, bench_large_extend, bench_large_proof
+);
+
+/// Deep enough to keep room for the leaves pushed while measuring.
+const DEPTH: usize = 22;
+const NUM_LEAVES: usize = 1 << 20;
+const EXTEND_BATCH: usize = 1 << 10;
+
+fn initial_values() -> Vec<Field> {
+    (0..NUM_LEAVES).map(Field::from).collect()
+}
+
+fn create_tree<S>(storage: S, values: &[Field]) -> CascadingMerkleTree<Poseidon, S>
+where
+    S: GenericStorage<Field>,
+{
+    CascadingMerkleTree::new_with_leaves(storage, DEPTH, &Field::from(0), values)
+}
+
+fn mmap_storage() -> MmapVec<Field> {
+    MmapVec::create(tempfile::tempfile().unwrap()).unwrap()
+}
+
+/// Initializes trees in `MmapVec` storage on all threads and on a single
+/// one, to show how subtree hashing scales with the number of cores.
+fn bench_large_init(criterion: &mut Criterion) {
+    let values = initial_values();
+    let mut group = criterion.benchmark_group("bench_large_init");
+    group.throughput(Throughput::Elements(NUM_LEAVES as u64));
+    for (name, parallelism) in [
+        ("parallel", Parallelism::Global),
+        ("sequential", Parallelism::Sequential),
+    ] {
+        group.bench_function(BenchmarkId::new(name, NUM_LEAVES), |b| {
+            b.iter_batched(
+                mmap_storage,
+                |storage| {
+                    CascadingMerkleTree::<Poseidon, _>::new_with_leaves_in(
+                        storage,
+                        DEPTH,
+                        &Field::from(0),
+                        &values,
+                        parallelism,
+                    )
+                },
+                BatchSize::PerIteration,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn bench_large_push(criterion: &mut Criterion) {
+    let values = initial_values();
+    let mut group = criterion.benchmark_group("bench_large_push");
+    group.throughput(Throughput::Elements(1));
+    bench_push(&mut group, "vec", create_tree(vec![], &values));
+    bench_push(&mut group, "mmap", create_tree(mmap_storage(), &values));
+    group.finish();
+}
+
+fn bench_push<S>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    name: &str,
+    mut tree: CascadingMerkleTree<Poseidon, S>,
+) where
+    S: GenericStorage<Field>,
+{
+    let leaf = Field::from(234123412341usize);
+    group.bench_function(BenchmarkId::new(name, NUM_LEAVES), |b| {
+        b.iter(|| tree.push(leaf).unwrap());
+    });
+}
+
+fn bench_large_extend(criterion: &mut Criterion) {
+    let values = initial_values();
+    let batch: Vec<Field> = (0..EXTEND_BATCH).map(Field::from).collect();
+    let mut group = criterion.benchmark_group("bench_large_extend");
+    group.throughput(Throughput::Elements(EXTEND_BATCH as u64));
+    bench_extend(&mut group, "vec", create_tree(vec![], &values), &batch);
+    bench_extend(
+        &mut group,
+        "mmap",
+        create_tree(mmap_storage(), &values),
+        &batch,
+    );
+    group.finish();
+}
+
+fn bench_extend<S>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    name: &str,
+    mut tree: CascadingMerkleTree<Poseidon, S>,
+    batch: &[Field],
+) where
+    S: GenericStorage<Field>,
+{
+    group.bench_function(BenchmarkId::new(name, NUM_LEAVES), |b| {
+        b.iter(|| tree.extend_from_slice(batch).unwrap());
+    });
+}
+
+fn bench_large_proof(criterion: &mut Criterion) {
+    let values = initial_values();
+    let mut group = criterion.benchmark_group("bench_large_proof");
+    bench_proof(&mut group, "vec", &create_tree(vec![], &values));
+    bench_proof(&mut group, "mmap", &create_tree(mmap_storage(), &values));
+    group.finish();
+}
+
+fn bench_proof<S>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    name: &str,
+    tree: &CascadingMerkleTree<Poseidon, S>,
+) where
+    S: GenericStorage<Field>,
+{
+    // Step through the leaves with a stride, so proofs don't share cache lines
+    let mut index = 0;
+    group.bench_function(BenchmarkId::new(name, NUM_LEAVES), |b| {
+        b.iter(|| {
+            index = (index + 7919) % NUM_LEAVES;
+            tree.proof(index)
+        });
+    });
+}
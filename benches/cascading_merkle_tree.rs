@@ -38,7 +38,7 @@ fn bench_cascading_proof_from_hash(criterion: &mut Criterion) {
                     &tree_value.empty_value,
                     &tree_value.initial_values,
                 );
-                tree.set_leaf(1 << 13, leaf);
+                tree.set_leaf(1 << 13, leaf).unwrap();
                 tree
             },
             |tree| {
@@ -124,7 +124,7 @@ fn bench_cascading_create_dense_mmap_tree(criterion: &mut Criterion) {
             |bencher: &mut criterion::Bencher, value| {
                 bencher.iter(|| {
                     let tempfile = tempfile::tempfile().unwrap();
-                    let storage: MmapVec<_> = unsafe { MmapVec::create(tempfile).unwrap() };
+                    let storage: MmapVec<_> = MmapVec::create(tempfile).unwrap();
                     let _tree: CascadingMerkleTree<Poseidon, _> =
                         CascadingMerkleTree::new_with_leaves(
                             storage,
@@ -152,7 +152,7 @@ fn bench_cascading_restore_dense_mmap_tree(criterion: &mut Criterion) {
     (0..3).zip(tree_values).for_each(|(id, value)| {
         let tempfile = tempfile::NamedTempFile::new().unwrap();
         let path = tempfile.path();
-        let storage: MmapVec<_> = unsafe { MmapVec::create_from_path(path).unwrap() };
+        let storage: MmapVec<_> = MmapVec::create_from_path(path).unwrap();
         {
             let tree: CascadingMerkleTree<Poseidon, _> = CascadingMerkleTree::new_with_leaves(
                 storage,
@@ -168,7 +168,7 @@ fn bench_cascading_restore_dense_mmap_tree(criterion: &mut Criterion) {
             &(id, value),
             |bencher: &mut criterion::Bencher, (_id, value)| {
                 bencher.iter(|| {
-                    let storage = unsafe { MmapVec::restore_from_path(path).unwrap() };
+                    let storage = MmapVec::restore_from_path(path).unwrap();
                     let _tree: CascadingMerkleTree<Poseidon, _> =
                         CascadingMerkleTree::restore(storage, value.depth, &value.empty_value)
                             .unwrap();
@@ -206,7 +206,7 @@ fn bench_cascading_dense_mmap_tree_reads(criterion: &mut Criterion) {
     let tree_value = create_values_for_tree(14);
     let file = tempfile::tempfile().unwrap();
 
-    let storage = unsafe { MmapVec::create(file).unwrap() };
+    let storage = MmapVec::create(file).unwrap();
     let tree = CascadingMerkleTree::<Poseidon, _>::new_with_leaves(
         storage,
         tree_value.depth,
@@ -240,7 +240,7 @@ fn bench_cascading_dense_tree_writes(criterion: &mut Criterion) {
                 )
             },
             |tree| {
-                tree.set_leaf(9000, value);
+                tree.set_leaf(9000, value).unwrap();
             },
             BatchSize::SmallInput,
         );
@@ -256,7 +256,7 @@ fn bench_cascading_dense_mmap_tree_writes(criterion: &mut Criterion) {
         b.iter_batched_ref(
             || {
                 let file = tempfile::tempfile().unwrap();
-                let storage = unsafe { MmapVec::create(file).unwrap() };
+                let storage = MmapVec::create(file).unwrap();
                 CascadingMerkleTree::<Poseidon, _>::new_with_leaves(
                     storage,
                     tree_value.depth,
@@ -265,7 +265,7 @@ fn bench_cascading_dense_mmap_tree_writes(criterion: &mut Criterion) {
                 )
             },
             |tree| {
-                tree.set_leaf(9000, value);
+                tree.set_leaf(9000, value).unwrap();
             },
             BatchSize::SmallInput,
         );
@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use keccak::keccak::Keccak256;
+use trees::{fixed, imt};
+
+criterion_main!(fixed_merkle_tree);
+criterion_group!(fixed_merkle_tree, bench_fixed_set, bench_fixed_proof);
+
+/// Depth of the compared trees, the largest of small deployments.
+const DEPTH: usize = 20;
+
+fn bench_fixed_set(criterion: &mut Criterion) {
+    let mut fixed = fixed::MerkleTree::<Keccak256, DEPTH>::new([0; 32]);
+    let mut imt = imt::MerkleTree::<Keccak256>::new(DEPTH, [0; 32]);
+
+    let mut group = criterion.benchmark_group("bench_fixed_set");
+    let mut leaf = 0;
+    group.bench_function("fixed", |b| {
+        b.iter(|| {
+            leaf = (leaf + 12_345) % (1 << DEPTH);
+            fixed.set(leaf, [1; 32]);
+        });
+    });
+    group.bench_function("imt", |b| {
+        b.iter(|| {
+            leaf = (leaf + 12_345) % (1 << DEPTH);
+            imt.set(leaf, [1; 32]);
+        });
+    });
+    group.finish();
+}
+
+fn bench_fixed_proof(criterion: &mut Criterion) {
+    let fixed = fixed::MerkleTree::<Keccak256, DEPTH>::new([0; 32]);
+    let imt = imt::MerkleTree::<Keccak256>::new(DEPTH, [0; 32]);
+
+    let mut group = criterion.benchmark_group("bench_fixed_proof");
+    let mut leaf = 0;
+    group.bench_function("fixed", |b| {
+        b.iter(|| {
+            leaf = (leaf + 12_345) % (1 << DEPTH);
+            fixed.proof(leaf)
+        });
+    });
+    group.bench_function("imt", |b| {
+        b.iter(|| {
+            leaf = (leaf + 12_345) % (1 << DEPTH);
+            imt.proof(leaf)
+        });
+    });
+    group.finish();
+}
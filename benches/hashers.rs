@@ -0,0 +1,61 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use hasher::Hasher;
+use keccak::blake3::Blake3;
+use keccak::keccak::Keccak256;
+use keccak::sha256::Sha256;
+use keccak::sha3::Sha3_256;
+use trees::cascading::CascadingMerkleTree;
+
+criterion_main!(hashers);
+criterion_group!(hashers, bench_hash_node, bench_tree_build);
+
+/// Depth of the trees built by `bench_tree_build`, all of its leaves are set.
+const DEPTH: usize = 14;
+
+fn bench_hash_node(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("bench_hash_node");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("keccak256", |b| b.iter(hash_node::<Keccak256>));
+    group.bench_function("sha3_256", |b| b.iter(hash_node::<Sha3_256>));
+    group.bench_function("sha256", |b| b.iter(hash_node::<Sha256>));
+    group.bench_function("blake3", |b| b.iter(hash_node::<Blake3>));
+    group.finish();
+}
+
+fn bench_tree_build(criterion: &mut Criterion) {
+    let leaves: Vec<[u8; 32]> = (0..1_u32 << DEPTH)
+        .map(|n| {
+            let mut leaf = [0; 32];
+            leaf[..4].copy_from_slice(&n.to_be_bytes());
+            leaf
+        })
+        .collect();
+
+    let mut group = criterion.benchmark_group("bench_hasher_tree_build");
+    group.throughput(Throughput::Elements(leaves.len() as u64));
+    group.bench_with_input(
+        BenchmarkId::new("keccak256", DEPTH),
+        &leaves,
+        |b, leaves| {
+            b.iter(|| build_tree::<Keccak256>(leaves));
+        },
+    );
+    group.bench_with_input(BenchmarkId::new("sha256", DEPTH), &leaves, |b, leaves| {
+        b.iter(|| build_tree::<Sha256>(leaves));
+    });
+    group.bench_with_input(BenchmarkId::new("blake3", DEPTH), &leaves, |b, leaves| {
+        b.iter(|| build_tree::<Blake3>(leaves));
+    });
+    group.finish();
+}
+
+fn hash_node<H: Hasher<Hash = [u8; 32]>>() -> [u8; 32] {
+    H::hash_node(
+        criterion::black_box(&[1; 32]),
+        criterion::black_box(&[2; 32]),
+    )
+}
+
+fn build_tree<H: Hasher<Hash = [u8; 32]>>(leaves: &[[u8; 32]]) -> [u8; 32] {
+    CascadingMerkleTree::<H>::new_with_leaves(vec![], DEPTH, &[0; 32], leaves).root()
+}
@@ -3,6 +3,7 @@ use hasher::Hasher;
 use poseidon::Poseidon;
 use semaphore::poseidon_tree::LazyPoseidonTree;
 use semaphore::Field;
+use trees::lazy::DenseLayout;
 
 criterion_main!(lazy_merkle_tree);
 criterion_group!(
@@ -14,6 +15,7 @@ criterion_group!(
     bench_dense_mmap_tree_reads,
     bench_dense_tree_writes,
     bench_dense_mmap_tree_writes,
+    bench_dense_mmap_proof_layouts,
 );
 
 struct TreeValues<H: Hasher> {
@@ -227,6 +229,36 @@ fn bench_dense_mmap_tree_writes(criterion: &mut Criterion) {
     });
 }
 
+fn bench_dense_mmap_proof_layouts(criterion: &mut Criterion) {
+    let tree_value = create_values_for_tree(20);
+    let leaf_count = 1 << tree_value.depth;
+
+    let mut group = criterion.benchmark_group("bench_dense_mmap_proof_layouts");
+
+    for layout in [DenseLayout::Heap, DenseLayout::Blocked] {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let tree = LazyPoseidonTree::new_mmapped_with_layout(
+            tree_value.depth,
+            tree_value.prefix_depth,
+            &tree_value.empty_value,
+            &tree_value.initial_values,
+            file.path().to_str().unwrap(),
+            layout,
+        )
+        .unwrap();
+
+        group.bench_function(BenchmarkId::from_parameter(format!("{layout:?}")), |b| {
+            // Stride through the leaves so consecutive proofs share no pages
+            let mut index = 0;
+            b.iter(|| {
+                index = (index + 104_729) % leaf_count;
+                let _proof = tree.proof(index);
+            })
+        });
+    }
+    group.finish();
+}
+
 fn create_values_for_tree(depth: usize) -> TreeValues<Poseidon> {
     let prefix_depth = depth;
     let empty_value = Field::from(0);
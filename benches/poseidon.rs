@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use poseidon::poseidon::{hash1, hash2};
+use poseidon::sponge::hash_fields;
+use semaphore::Field;
+
+criterion_main!(poseidon_hash);
+criterion_group!(poseidon_hash, bench_hash1, bench_hash2, bench_hash_fields);
+
+fn bench_hash1(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("bench_poseidon");
+    group.throughput(Throughput::Elements(1));
+    let value = Field::from(234123412341usize);
+    group.bench_function("hash1", |b| b.iter(|| hash1(value)));
+    group.finish();
+}
+
+fn bench_hash2(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("bench_poseidon");
+    group.throughput(Throughput::Elements(1));
+    let left = Field::from(234123412341usize);
+    let right = Field::from(98765432123usize);
+    group.bench_function("hash2", |b| b.iter(|| hash2(left, right)));
+    group.finish();
+}
+
+fn bench_hash_fields(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("bench_poseidon_sponge");
+    for len in [4, 16, 64] {
+        let inputs: Vec<Field> = (0..len).map(Field::from).collect();
+        group.throughput(Throughput::Elements(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &inputs, |b, inputs| {
+            b.iter(|| hash_fields(inputs).unwrap());
+        });
+    }
+    group.finish();
+}
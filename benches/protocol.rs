@@ -0,0 +1,136 @@
+use ark_circom::CircomReduction;
+use ark_groth16::{prepare_verifying_key, Groth16};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+use semaphore::identity::Identity;
+use semaphore::poseidon_tree::LazyPoseidonTree;
+use semaphore::protocol::compression::{compress_proof, decompress_proof};
+use semaphore::protocol::{
+    generate_nullifier_hash, generate_proof_rng, generate_witness, prepared_vk, verify_proof,
+};
+use semaphore::{get_prover_depths, hash_to_field_bytes, Field};
+use semaphore_depth_macros::bench_all_depths;
+
+criterion_main!(protocol);
+criterion_group!(
+    name = protocol;
+    config = Criterion::default().sample_size(20);
+    targets = bench_witness_all_depths, bench_verify, bench_compression
+);
+
+struct Inputs {
+    identity: Identity,
+    merkle_proof: trees::Proof<poseidon::Poseidon>,
+    external_nullifier_hash: Field,
+    signal_hash: Field,
+}
+
+fn create_inputs(depth: usize) -> Inputs {
+    let mut secret = *b"secret";
+    let identity = Identity::from_secret(&mut secret, None);
+    let tree = LazyPoseidonTree::new(depth, Field::from(0))
+        .derived()
+        .update(0, &identity.commitment());
+    Inputs {
+        merkle_proof: tree.proof(0),
+        identity,
+        external_nullifier_hash: hash_to_field_bytes(b"appId"),
+        signal_hash: hash_to_field_bytes(b"xxx"),
+    }
+}
+
+#[bench_all_depths(prover)]
+fn bench_witness(criterion: &mut Criterion, depth: usize) {
+    let inputs = create_inputs(depth);
+    // Load the witness graph outside of the measurement
+    let _ = generate_witness(
+        &inputs.identity,
+        &inputs.merkle_proof,
+        inputs.external_nullifier_hash,
+        inputs.signal_hash,
+    );
+    criterion.bench_with_input(
+        BenchmarkId::new("bench_witness", depth),
+        &inputs,
+        |b, inputs| {
+            b.iter(|| {
+                generate_witness(
+                    &inputs.identity,
+                    &inputs.merkle_proof,
+                    inputs.external_nullifier_hash,
+                    inputs.signal_hash,
+                )
+            });
+        },
+    );
+}
+
+/// Compares verifying with the cached prepared verifying key to preparing it
+/// on every call.
+fn bench_verify(criterion: &mut Criterion) {
+    let depth = get_prover_depths()[0];
+    let inputs = create_inputs(depth);
+    let proof = generate_proof_rng(
+        &inputs.identity,
+        &inputs.merkle_proof,
+        inputs.external_nullifier_hash,
+        inputs.signal_hash,
+        &mut ChaChaRng::seed_from_u64(1),
+    )
+    .unwrap();
+    let root = inputs.merkle_proof.root(inputs.identity.commitment());
+    let nullifier_hash = generate_nullifier_hash(&inputs.identity, inputs.external_nullifier_hash);
+    let public_inputs = [
+        root,
+        nullifier_hash,
+        inputs.signal_hash,
+        inputs.external_nullifier_hash,
+    ]
+    .map(|input| ark_bn254::Fr::try_from(input).unwrap());
+    let vk = prepared_vk(depth).unwrap().vk.clone();
+
+    let mut group = criterion.benchmark_group("bench_verify");
+    group.bench_function("cached_vk", |b| {
+        b.iter(|| {
+            verify_proof(
+                root,
+                nullifier_hash,
+                inputs.signal_hash,
+                inputs.external_nullifier_hash,
+                &proof,
+                depth,
+            )
+            .unwrap()
+        });
+    });
+    group.bench_function("prepare_per_call", |b| {
+        b.iter(|| {
+            let pvk = prepare_verifying_key(&vk);
+            Groth16::<_, CircomReduction>::verify_proof(&pvk, &proof.into(), &public_inputs)
+                .unwrap()
+        });
+    });
+    group.finish();
+}
+
+fn bench_compression(criterion: &mut Criterion) {
+    let depth = get_prover_depths()[0];
+    let inputs = create_inputs(depth);
+    let proof = generate_proof_rng(
+        &inputs.identity,
+        &inputs.merkle_proof,
+        inputs.external_nullifier_hash,
+        inputs.signal_hash,
+        &mut ChaChaRng::seed_from_u64(1),
+    )
+    .unwrap();
+    let compressed = compress_proof(proof).unwrap();
+
+    let mut group = criterion.benchmark_group("bench_proof_compression");
+    group.bench_function("compress", |b| b.iter(|| compress_proof(proof).unwrap()));
+    group.bench_function("decompress", |b| {
+        b.iter(|| decompress_proof(compressed).unwrap());
+    });
+    group.finish();
+}
@@ -0,0 +1,108 @@
+//! Proves back to back with and without a `WitnessScratch`, and reports the
+//! bytes allocated per proof next to the timings.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use semaphore::identity::Identity;
+use semaphore::poseidon_tree::LazyPoseidonTree;
+use semaphore::protocol::{
+    generate_proof, generate_proof_with_options, ProverOptions, WitnessScratch,
+};
+use semaphore::{get_prover_depths, hash_to_field_bytes, Field};
+
+/// Counts the bytes allocated, to compare allocations per proof.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Proofs generated to measure the allocations of sustained proving.
+const PROOFS: usize = 10;
+
+criterion_main!(proving_allocations);
+criterion_group!(
+    name = proving_allocations;
+    config = Criterion::default().sample_size(10);
+    targets = bench_proving_allocations
+);
+
+fn bench_proving_allocations(criterion: &mut Criterion) {
+    // Depth 30 if enabled, the witness grows with the depth
+    let depth = *get_prover_depths().iter().max().unwrap();
+    let mut secret = *b"secret";
+    let identity = Identity::from_secret(&mut secret, None);
+    let tree = LazyPoseidonTree::new(depth, Field::from(0))
+        .derived()
+        .update(0, &identity.commitment());
+    let merkle_proof = tree.proof(0);
+    let external_nullifier_hash = hash_to_field_bytes(b"appId");
+    let signal_hash = hash_to_field_bytes(b"xxx");
+
+    let fresh = || {
+        generate_proof(
+            &identity,
+            &merkle_proof,
+            external_nullifier_hash,
+            signal_hash,
+        )
+        .unwrap()
+    };
+    let mut scratch = WitnessScratch::new();
+    let mut reused = || {
+        let options = ProverOptions {
+            scratch: Some(&mut scratch),
+            ..Default::default()
+        };
+        generate_proof_with_options(
+            &identity,
+            &merkle_proof,
+            external_nullifier_hash,
+            signal_hash,
+            options,
+        )
+        .unwrap()
+    };
+
+    // Load the artifacts and fill the scratch outside of the measurement
+    fresh();
+    reused();
+    let start = ALLOCATED.load(Ordering::Relaxed);
+    for _ in 0..PROOFS {
+        fresh();
+    }
+    let middle = ALLOCATED.load(Ordering::Relaxed);
+    for _ in 0..PROOFS {
+        reused();
+    }
+    let end = ALLOCATED.load(Ordering::Relaxed);
+    println!(
+        "depth {depth}: {} bytes allocated per proof without scratch, {} with",
+        (middle - start) / PROOFS,
+        (end - middle) / PROOFS
+    );
+
+    let mut group = criterion.benchmark_group("bench_proving_allocations");
+    group.bench_function(format!("fresh/{depth}"), |b| b.iter(fresh));
+    group.bench_function(format!("scratch/{depth}"), |b| b.iter(&mut reused));
+    group.finish();
+}
@@ -2,11 +2,14 @@ use std::fs::{create_dir, create_dir_all, File};
 use std::path::{absolute, Path, PathBuf};
 
 use color_eyre::eyre::Result;
+use sha2::{Digest, Sha256};
 
 extern crate reqwest;
 
 const SEMAPHORE_FILES_PATH: &str = "semaphore_files";
 const SEMAPHORE_DOWNLOAD_URL: &str = "https://www.trusted-setup-pse.org/semaphore";
+const SEMAPHORE_V4_FILES_PATH: &str = "semaphore_v4_files";
+const SEMAPHORE_V4_DOWNLOAD_URL: &str = "https://snark-artifacts.pse.dev/semaphore/4.0.0";
 
 fn download_and_store_binary(url: &str, path: impl AsRef<Path>) -> Result<()> {
     let path = path.as_ref();
@@ -20,6 +23,8 @@ fn download_and_store_binary(url: &str, path: impl AsRef<Path>) -> Result<()> {
     Ok(())
 }
 
+/// Converts the zkey to an arkzkey, and writes its verifying key next to it
+/// for verifier only builds.
 fn create_arkzkey(path: PathBuf) -> Result<PathBuf> {
     let mut ark_zkey_path = path.clone();
     ark_zkey_path.set_extension("arkzkey");
@@ -28,6 +33,7 @@ fn create_arkzkey(path: PathBuf) -> Result<PathBuf> {
         ark_zkey::read_proving_key_and_matrices_from_zkey(
             path.to_str().expect("Failed to convert path."),
         )?;
+    ark_zkey::write_vk(&original_proving_key.0.vk, &path.with_extension("vk"))?;
 
     ark_zkey::convert_zkey(
         original_proving_key,
@@ -38,6 +44,10 @@ fn create_arkzkey(path: PathBuf) -> Result<PathBuf> {
     Ok(ark_zkey_path)
 }
 
+fn sha256_hex(path: &Path) -> Result<String> {
+    Ok(hex::encode(Sha256::digest(std::fs::read(path)?)))
+}
+
 fn build_circuit(depth: usize) -> Result<()> {
     let out_dir = std::env::var("OUT_DIR").expect("Missing out dir var");
     let base_path = Path::new(&out_dir).join(SEMAPHORE_FILES_PATH);
@@ -61,15 +71,11 @@ fn build_circuit(depth: usize) -> Result<()> {
     let ark_zkey_path = Path::new(&depth_subfolder).join(format!("{filename}.arkzkey"));
 
     // Compute absolute paths
-    let arkzkey_file = absolute(ark_zkey_path)?;
-    let graph_file = absolute(
-        Path::new("graphs")
-            .join(depth.to_string())
-            .join("graph.bin"),
-    )?;
+    let arkzkey_file = absolute(&ark_zkey_path)?;
+    let vk_file = absolute(ark_zkey_path.with_extension("vk"))?;
 
     assert!(arkzkey_file.exists());
-    assert!(graph_file.exists());
+    assert!(vk_file.exists());
 
     // Export generated paths
     println!(
@@ -78,17 +84,113 @@ fn build_circuit(depth: usize) -> Result<()> {
         arkzkey_file.display()
     );
     println!(
-        "cargo:rustc-env=BUILD_RS_GRAPH_FILE_{}={}",
+        "cargo:rustc-env=BUILD_RS_VK_FILE_{}={}",
+        depth,
+        vk_file.display()
+    );
+
+    // Checksums of the artifacts, to validate artifacts loaded at runtime
+    println!(
+        "cargo:rustc-env=BUILD_RS_ARKZKEY_SHA256_{}={}",
         depth,
-        graph_file.display()
+        sha256_hex(&arkzkey_file)?
     );
 
+    // Only proving needs the witness graph, which is not shipped for every
+    // depth. Prover builds leave the other depths out of their tables.
+    if std::env::var_os("CARGO_FEATURE_PROVER").is_some()
+        && semaphore_depth_config::get_prover_depth_index(depth).is_some()
+    {
+        let graph_file = absolute(
+            Path::new("graphs")
+                .join(depth.to_string())
+                .join("graph.bin"),
+        )?;
+        assert!(
+            graph_file.exists(),
+            "Missing witness graph {}, build it from the Semaphore circuit of depth {depth} \
+             with circom-witness-rs",
+            graph_file.display()
+        );
+        println!(
+            "cargo:rustc-env=BUILD_RS_GRAPH_FILE_{}={}",
+            depth,
+            graph_file.display()
+        );
+        println!(
+            "cargo:rustc-env=BUILD_RS_GRAPH_SHA256_{}={}",
+            depth,
+            sha256_hex(&graph_file)?
+        );
+    }
+
+    Ok(())
+}
+
+/// Semaphore v4 has a single circuit per maximum depth, named after it.
+fn build_v4_circuit(depth: usize) -> Result<()> {
+    let out_dir = std::env::var("OUT_DIR").expect("Missing out dir var");
+    let base_path = Path::new(&out_dir).join(SEMAPHORE_V4_FILES_PATH);
+    create_dir_all(&base_path)?;
+
+    let filename = format!("semaphore-{depth}");
+    let download_url = format!("{SEMAPHORE_V4_DOWNLOAD_URL}/{filename}.zkey");
+    let path = base_path.join(format!("{filename}.zkey"));
+    download_and_store_binary(&download_url, &path)?;
+    let arkzkey_file = absolute(create_arkzkey(path)?)?;
+    let vk_file = arkzkey_file.with_extension("vk");
+    assert!(arkzkey_file.exists());
+    assert!(vk_file.exists());
+    println!(
+        "cargo:rustc-env=BUILD_RS_V4_ARKZKEY_FILE_{}={}",
+        depth,
+        arkzkey_file.display()
+    );
+    println!(
+        "cargo:rustc-env=BUILD_RS_V4_VK_FILE_{}={}",
+        depth,
+        vk_file.display()
+    );
+
+    if std::env::var_os("CARGO_FEATURE_V4_PROVER").is_some() {
+        let mut graph_file = absolute(
+            Path::new("graphs")
+                .join("v4")
+                .join(depth.to_string())
+                .join("graph.bin"),
+        )?;
+        // The v4 witness graphs are not shipped yet. Without one, the build
+        // embeds an empty graph and v4 proving reports the depth as
+        // unsupported, while v4 verification keeps working.
+        if !graph_file.exists() {
+            println!(
+                "cargo:warning=Missing Semaphore v4 witness graph {}, v4 proofs of depth {depth} \
+                 can't be generated. Build it from the v4 circuit with circom-witness-rs",
+                graph_file.display()
+            );
+            graph_file = base_path.join(format!("{filename}.graph.bin"));
+            File::create(&graph_file)?;
+        }
+        println!(
+            "cargo:rustc-env=BUILD_RS_V4_GRAPH_FILE_{}={}",
+            depth,
+            graph_file.display()
+        );
+    }
+
     Ok(())
 }
 
 fn main() -> Result<()> {
+    // Only proving and verifying need the circuit artifacts
+    if std::env::var_os("CARGO_FEATURE_VERIFIER").is_none() {
+        return Ok(());
+    }
     for depth in semaphore_depth_config::get_supported_depths() {
         build_circuit(*depth)?;
+        if std::env::var_os("CARGO_FEATURE_V4").is_some() {
+            build_v4_circuit(*depth)?;
+        }
     }
     Ok(())
 }
@@ -0,0 +1,119 @@
+//! Converts a `.zkey` to an `.arkzkey` and checks the result.
+//!
+//! Installed as `cargo-ark-zkey` it runs as `cargo ark-zkey`:
+//!
+//! `cargo ark-zkey <input.zkey> [output.arkzkey] [--compressed|--uncompressed]`
+//!
+//! The output defaults to the input with an `.arkzkey` extension. After
+//! writing, the arkzkey is read back and its verifying key compared with the
+//! one of the zkey.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use ark_zkey::{read_zkey_with_progress, verify_arkzkey, vk_digest, write_arkzkey, Compress};
+use color_eyre::eyre::{bail, Result, WrapErr};
+
+const USAGE: &str =
+    "usage: cargo ark-zkey <input.zkey> [output.arkzkey] [--compressed|--uncompressed]";
+
+struct Args {
+    input: PathBuf,
+    output: PathBuf,
+    compress: Compress,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = std::env::args().skip(1).peekable();
+    // Cargo passes the subcommand name when run as `cargo ark-zkey`
+    if args.peek().map(String::as_str) == Some("ark-zkey") {
+        args.next();
+    }
+
+    let mut paths = Vec::new();
+    let mut compress = Compress::Yes;
+    for arg in args {
+        match arg.as_str() {
+            "--compressed" => compress = Compress::Yes,
+            "--uncompressed" => compress = Compress::No,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            flag if flag.starts_with('-') => bail!("unknown option {flag}\n{USAGE}"),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let mut paths = paths.into_iter();
+    let (Some(input), output, None) = (paths.next(), paths.next(), paths.next()) else {
+        bail!(USAGE);
+    };
+    let output = output.unwrap_or_else(|| input.with_extension("arkzkey"));
+    Ok(Args {
+        input,
+        output,
+        compress,
+    })
+}
+
+/// Prints the progress of reading or writing `total` bytes to stderr, each
+/// time another percent is done.
+fn progress(action: &'static str, total: u64) -> impl FnMut(u64) {
+    let mut last = None;
+    move |bytes| {
+        let percent = (bytes * 100).checked_div(total).unwrap_or(100).min(100);
+        if last != Some(percent) {
+            last = Some(percent);
+            eprint!(
+                "\r{action} {} / {} MiB ({percent}%)",
+                bytes >> 20,
+                total >> 20
+            );
+            let _ = std::io::stderr().flush();
+        }
+    }
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    Ok(std::fs::metadata(path)
+        .wrap_err_with(|| format!("Failed to read metadata of {}", path.display()))?
+        .len())
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    let args = parse_args()?;
+
+    let now = Instant::now();
+    let total = file_size(&args.input)?;
+    let (proving_key, matrices) = read_zkey_with_progress(&args.input, progress("Reading", total))?;
+    eprintln!("\nRead {} in {:?}", args.input.display(), now.elapsed());
+    let digest = vk_digest(&proving_key.0.vk);
+
+    let now = Instant::now();
+    // The output is about as large as the zkey when compressed, and twice
+    // as large otherwise
+    let estimate = match args.compress {
+        Compress::Yes => total,
+        Compress::No => total * 2,
+    };
+    write_arkzkey(
+        &proving_key,
+        &matrices,
+        &args.output,
+        args.compress,
+        progress("Writing", estimate),
+    )?;
+    eprintln!("\nWrote {} in {:?}", args.output.display(), now.elapsed());
+    drop((proving_key, matrices));
+
+    let now = Instant::now();
+    verify_arkzkey(&args.output, args.compress, &digest)?;
+    eprintln!("Verified {} in {:?}", args.output.display(), now.elapsed());
+
+    let digest: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+    println!("{} vk sha256 {digest}", args.output.display());
+    Ok(())
+}
@@ -1,14 +1,55 @@
 use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 
 use ark_bn254::{Bn254, Fr};
 use ark_circom::read_zkey;
 use ark_ff::Field;
-use ark_groth16::ProvingKey;
-use ark_relations::r1cs::ConstraintMatrices;
-use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
-use color_eyre::eyre::{Result, WrapErr};
+use ark_groth16::{ProvingKey, VerifyingKey};
+use ark_relations::r1cs::{ConstraintMatrices, SynthesisError};
+pub use ark_serialize::Compress;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError, Validate};
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+mod streamed;
+
+pub use streamed::{create_proof_streamed, ProvingKeySegments, Segment};
+
+#[derive(Debug, Error)]
+pub enum ZkeyError {
+    #[error("failed to {action} {}", path.display())]
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to read zkey file")]
+    Zkey(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("failed to deserialize {0}")]
+    Deserialize(&'static str, #[source] SerializationError),
+    #[error("failed to serialize {0}")]
+    Serialize(&'static str, #[source] SerializationError),
+    #[error("trailing bytes after constraint matrices, wrong compression?")]
+    TrailingBytes,
+    #[error("verifying key of {} does not match", .0.display())]
+    VkMismatch(PathBuf),
+    #[error("failed to create proof")]
+    Prove(#[source] SynthesisError),
+}
+
+impl ZkeyError {
+    fn io(action: &'static str, path: &Path) -> impl FnOnce(io::Error) -> Self {
+        let path = path.to_owned();
+        move |source| Self::Io {
+            action,
+            path,
+            source,
+        }
+    }
+}
 
 #[derive(CanonicalSerialize, CanonicalDeserialize, Clone, Debug, PartialEq)]
 pub struct SerializableProvingKey(pub ProvingKey<Bn254>);
@@ -31,82 +72,277 @@ pub struct SerializableConstraintMatrices<F: Field> {
     pub c: SerializableMatrix<F>,
 }
 
-// TODO: Return ProvingKey<Bn254>, ConstraintMatrices<Fr>?
+impl<F: Field> From<ConstraintMatrices<F>> for SerializableConstraintMatrices<F> {
+    fn from(matrices: ConstraintMatrices<F>) -> Self {
+        Self {
+            num_instance_variables: matrices.num_instance_variables,
+            num_witness_variables: matrices.num_witness_variables,
+            num_constraints: matrices.num_constraints,
+            a_num_non_zero: matrices.a_num_non_zero,
+            b_num_non_zero: matrices.b_num_non_zero,
+            c_num_non_zero: matrices.c_num_non_zero,
+            a: SerializableMatrix { data: matrices.a },
+            b: SerializableMatrix { data: matrices.b },
+            c: SerializableMatrix { data: matrices.c },
+        }
+    }
+}
+
+impl<F: Field> From<SerializableConstraintMatrices<F>> for ConstraintMatrices<F> {
+    fn from(matrices: SerializableConstraintMatrices<F>) -> Self {
+        Self {
+            num_instance_variables: matrices.num_instance_variables,
+            num_witness_variables: matrices.num_witness_variables,
+            num_constraints: matrices.num_constraints,
+            a_num_non_zero: matrices.a_num_non_zero,
+            b_num_non_zero: matrices.b_num_non_zero,
+            c_num_non_zero: matrices.c_num_non_zero,
+            a: matrices.a.data,
+            b: matrices.b.data,
+            c: matrices.c.data,
+        }
+    }
+}
+
+/// Counts the bytes passing through a reader or writer and reports the
+/// running total to a callback.
+struct Progress<T, F> {
+    inner: T,
+    bytes: u64,
+    report: F,
+}
+
+impl<T, F: FnMut(u64)> Progress<T, F> {
+    fn new(inner: T, report: F) -> Self {
+        Self {
+            inner,
+            bytes: 0,
+            report,
+        }
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        (self.report)(self.bytes);
+    }
+}
+
+impl<R: Read, F: FnMut(u64)> Read for Progress<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.advance(read);
+        Ok(read)
+    }
+}
+
+impl<W: Write, F: FnMut(u64)> Write for Progress<W, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.advance(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 pub fn read_arkzkey_from_bytes(
     arkzkey_bytes: &[u8],
-) -> Result<(ProvingKey<Bn254>, ConstraintMatrices<Fr>)> {
+) -> Result<(ProvingKey<Bn254>, ConstraintMatrices<Fr>), ZkeyError> {
+    read_arkzkey_from_bytes_with(arkzkey_bytes, Compress::Yes)
+}
+
+/// Reads an arkzkey written with the given compression.
+///
+/// Points are not checked to be on the curve, the arkzkey is expected to
+/// come from [`write_arkzkey`] and be checked with [`verify_arkzkey`].
+pub fn read_arkzkey_from_bytes_with(
+    arkzkey_bytes: &[u8],
+    compress: Compress,
+) -> Result<(ProvingKey<Bn254>, ConstraintMatrices<Fr>), ZkeyError> {
     let mut cursor = std::io::Cursor::new(arkzkey_bytes);
 
     let serialized_proving_key =
-        SerializableProvingKey::deserialize_compressed_unchecked(&mut cursor)
-            .wrap_err("Failed to deserialize proving key")?;
+        SerializableProvingKey::deserialize_with_mode(&mut cursor, compress, Validate::No)
+            .map_err(|err| ZkeyError::Deserialize("proving key", err))?;
 
     let serialized_constraint_matrices =
-        SerializableConstraintMatrices::deserialize_compressed_unchecked(&mut cursor)
-            .wrap_err("Failed to deserialize constraint matrices")?;
-
-    // Get on right form for API
-    let proving_key: ProvingKey<Bn254> = serialized_proving_key.0;
-    let constraint_matrices: ConstraintMatrices<Fr> = ConstraintMatrices {
-        num_instance_variables: serialized_constraint_matrices.num_instance_variables,
-        num_witness_variables: serialized_constraint_matrices.num_witness_variables,
-        num_constraints: serialized_constraint_matrices.num_constraints,
-        a_num_non_zero: serialized_constraint_matrices.a_num_non_zero,
-        b_num_non_zero: serialized_constraint_matrices.b_num_non_zero,
-        c_num_non_zero: serialized_constraint_matrices.c_num_non_zero,
-        a: serialized_constraint_matrices.a.data,
-        b: serialized_constraint_matrices.b.data,
-        c: serialized_constraint_matrices.c.data,
-    };
-
-    Ok((proving_key, constraint_matrices))
+        SerializableConstraintMatrices::deserialize_with_mode(&mut cursor, compress, Validate::No)
+            .map_err(|err| ZkeyError::Deserialize("constraint matrices", err))?;
+
+    if cursor.position() != arkzkey_bytes.len() as u64 {
+        return Err(ZkeyError::TrailingBytes);
+    }
+
+    Ok((
+        serialized_proving_key.0,
+        serialized_constraint_matrices.into(),
+    ))
 }
 
 pub fn read_proving_key_and_matrices_from_zkey(
     zkey_path: &str,
-) -> Result<(SerializableProvingKey, SerializableConstraintMatrices<Fr>)> {
-    let zkey_file_path = PathBuf::from(zkey_path);
-    let zkey_file = File::open(zkey_file_path).wrap_err("Failed to open zkey file")?;
+) -> Result<(SerializableProvingKey, SerializableConstraintMatrices<Fr>), ZkeyError> {
+    read_zkey_with_progress(Path::new(zkey_path), |_| {})
+}
 
-    let mut buf_reader = BufReader::new(zkey_file);
+/// Reads a `.zkey`, reporting the number of bytes read so far to `progress`.
+pub fn read_zkey_with_progress(
+    zkey_path: &Path,
+    progress: impl FnMut(u64),
+) -> Result<(SerializableProvingKey, SerializableConstraintMatrices<Fr>), ZkeyError> {
+    let zkey_file = File::open(zkey_path).map_err(ZkeyError::io("open zkey file", zkey_path))?;
 
-    let (proving_key, matrices) =
-        read_zkey(&mut buf_reader).wrap_err("Failed to read zkey file")?;
+    let mut buf_reader = BufReader::new(Progress::new(zkey_file, progress));
 
-    let serializable_proving_key = SerializableProvingKey(proving_key);
-    let serializable_constrain_matrices = SerializableConstraintMatrices {
-        num_instance_variables: matrices.num_instance_variables,
-        num_witness_variables: matrices.num_witness_variables,
-        num_constraints: matrices.num_constraints,
-        a_num_non_zero: matrices.a_num_non_zero,
-        b_num_non_zero: matrices.b_num_non_zero,
-        c_num_non_zero: matrices.c_num_non_zero,
-        a: SerializableMatrix { data: matrices.a },
-        b: SerializableMatrix { data: matrices.b },
-        c: SerializableMatrix { data: matrices.c },
-    };
+    let (proving_key, matrices) =
+        read_zkey(&mut buf_reader).map_err(|err| ZkeyError::Zkey(err.into()))?;
 
-    Ok((serializable_proving_key, serializable_constrain_matrices))
+    Ok((SerializableProvingKey(proving_key), matrices.into()))
 }
 
 pub fn convert_zkey(
     proving_key: SerializableProvingKey,
     constraint_matrices: SerializableConstraintMatrices<Fr>,
     arkzkey_path: &str,
-) -> Result<()> {
+) -> Result<(), ZkeyError> {
     let arkzkey_file_path = PathBuf::from(arkzkey_path);
+    write_arkzkey(
+        &proving_key,
+        &constraint_matrices,
+        &arkzkey_file_path,
+        Compress::Yes,
+        |_| {},
+    )
+}
 
-    let mut file = File::create(&arkzkey_file_path)
-        .wrap_err("Failed to create serialized proving key file")?;
+/// Writes an arkzkey with the given compression, reporting the number of
+/// bytes written so far to `progress`.
+///
+/// Uncompressed arkzkeys are about twice as large but read several times
+/// faster.
+pub fn write_arkzkey(
+    proving_key: &SerializableProvingKey,
+    constraint_matrices: &SerializableConstraintMatrices<Fr>,
+    arkzkey_path: &Path,
+    compress: Compress,
+    progress: impl FnMut(u64),
+) -> Result<(), ZkeyError> {
+    let file =
+        File::create(arkzkey_path).map_err(ZkeyError::io("create arkzkey file", arkzkey_path))?;
+    let mut writer = BufWriter::new(Progress::new(file, progress));
 
     proving_key
-        .serialize_compressed(&mut file)
-        .wrap_err("Failed to serialize proving key")?;
+        .serialize_with_mode(&mut writer, compress)
+        .map_err(|err| ZkeyError::Serialize("proving key", err))?;
 
     constraint_matrices
-        .serialize_compressed(&mut file)
-        .wrap_err("Failed to serialize constraint matrices")?;
+        .serialize_with_mode(&mut writer, compress)
+        .map_err(|err| ZkeyError::Serialize("constraint matrices", err))?;
 
+    writer
+        .flush()
+        .map_err(ZkeyError::io("write arkzkey file", arkzkey_path))?;
+    Ok(())
+}
+
+/// SHA-256 of the compressed verifying key, identifying the circuit a key
+/// proves for.
+#[must_use]
+pub fn vk_digest(vk: &VerifyingKey<Bn254>) -> [u8; 32] {
+    let mut bytes = Vec::with_capacity(vk.compressed_size());
+    vk.serialize_compressed(&mut bytes)
+        .expect("serializing into a Vec can't fail");
+    Sha256::digest(&bytes).into()
+}
+
+/// Writes the compressed verifying key alone, for builds that only verify
+/// proofs. Its SHA-256 is the [`vk_digest`].
+pub fn write_vk(vk: &VerifyingKey<Bn254>, vk_path: &Path) -> Result<(), ZkeyError> {
+    let mut file =
+        File::create(vk_path).map_err(ZkeyError::io("create verifying key file", vk_path))?;
+    vk.serialize_compressed(&mut file)
+        .map_err(|err| ZkeyError::Serialize("verifying key", err))
+}
+
+/// Reads a verifying key written by [`write_vk`].
+///
+/// Unlike proving keys, verifying keys are small enough to be checked to be
+/// on the curve.
+pub fn read_vk_from_bytes(vk_bytes: &[u8]) -> Result<VerifyingKey<Bn254>, ZkeyError> {
+    VerifyingKey::deserialize_compressed(vk_bytes)
+        .map_err(|err| ZkeyError::Deserialize("verifying key", err))
+}
+
+/// An arkzkey file mapped into memory.
+///
+/// Reading a key from the file mapping doesn't copy the file to the heap
+/// first, so the peak memory is the parsed key rather than the key plus the
+/// file. The mapped pages are backed by the file, the OS can drop them at
+/// any time.
+pub struct MappedArkzkey {
+    mmap: Mmap,
+    compress: Compress,
+}
+
+impl MappedArkzkey {
+    /// Maps the arkzkey at `arkzkey_path`, written with the given
+    /// compression.
+    pub fn open(arkzkey_path: &Path, compress: Compress) -> Result<Self, ZkeyError> {
+        let file = File::open(arkzkey_path).map_err(ZkeyError::io("open", arkzkey_path))?;
+        Self::from_file(&file, compress).map_err(ZkeyError::io("map", arkzkey_path))
+    }
+
+    /// Maps an open arkzkey file, written with the given compression.
+    ///
+    /// The file must not be modified while it's mapped.
+    pub fn from_file(file: &File, compress: Compress) -> io::Result<Self> {
+        // SAFETY: Arkzkeys are only read, and like executables they're not
+        // expected to be modified while in use.
+        let mmap = unsafe { Mmap::map(file) }?;
+        Ok(Self { mmap, compress })
+    }
+
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    /// Reads only the verifying key, which is at the start of the file.
+    pub fn verifying_key(&self) -> Result<VerifyingKey<Bn254>, ZkeyError> {
+        VerifyingKey::deserialize_with_mode(self.as_bytes(), self.compress, Validate::No)
+            .map_err(|err| ZkeyError::Deserialize("verifying key", err))
+    }
+
+    /// Reads the proving key and the constraint matrices.
+    pub fn read(&self) -> Result<(ProvingKey<Bn254>, ConstraintMatrices<Fr>), ZkeyError> {
+        // Lets the OS read ahead and drop pages once they're parsed
+        #[cfg(unix)]
+        let _ = self.mmap.advise(memmap2::Advice::Sequential);
+        read_arkzkey_from_bytes_with(self.as_bytes(), self.compress)
+    }
+}
+
+/// Reads an arkzkey file through a [`MappedArkzkey`].
+pub fn read_arkzkey(
+    arkzkey_path: &Path,
+    compress: Compress,
+) -> Result<(ProvingKey<Bn254>, ConstraintMatrices<Fr>), ZkeyError> {
+    MappedArkzkey::open(arkzkey_path, compress)?.read()
+}
+
+/// Reads back an arkzkey and checks that its verifying key has the
+/// `expected` [`vk_digest`].
+pub fn verify_arkzkey(
+    arkzkey_path: &Path,
+    compress: Compress,
+    expected: &[u8; 32],
+) -> Result<(), ZkeyError> {
+    let (proving_key, _) = read_arkzkey(arkzkey_path, compress)?;
+    if vk_digest(&proving_key.vk) != *expected {
+        return Err(ZkeyError::VkMismatch(arkzkey_path.to_owned()));
+    }
     Ok(())
 }
 
@@ -114,6 +350,8 @@ pub fn convert_zkey(
 mod tests {
     use std::time::Instant;
 
+    use color_eyre::Result;
+
     use super::*;
 
     #[test]
@@ -128,4 +366,51 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_write_arkzkey() -> Result<()> {
+        const ARKZKEY_BYTES: &[u8] = include_bytes!("./semaphore.16.arkzkey");
+
+        let (proving_key, matrices) = read_arkzkey_from_bytes(ARKZKEY_BYTES)?;
+        let digest = vk_digest(&proving_key.vk);
+        let proving_key = SerializableProvingKey(proving_key);
+        let matrices = SerializableConstraintMatrices::from(matrices);
+
+        let dir = std::env::temp_dir().join(format!("ark-zkey-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        for (compress, name) in [
+            (Compress::Yes, "compressed"),
+            (Compress::No, "uncompressed"),
+        ] {
+            let path = dir.join(name);
+            let mut written = 0;
+            write_arkzkey(&proving_key, &matrices, &path, compress, |bytes| {
+                written = bytes;
+            })?;
+            assert_eq!(written, std::fs::metadata(&path)?.len());
+            verify_arkzkey(&path, compress, &digest)?;
+        }
+        assert_eq!(
+            std::fs::read(dir.join("compressed"))?,
+            ARKZKEY_BYTES,
+            "compressed output should match the existing format"
+        );
+
+        let vk_path = dir.join("vk");
+        write_vk(&proving_key.0.vk, &vk_path)?;
+        let vk_bytes = std::fs::read(&vk_path)?;
+        assert_eq!(read_vk_from_bytes(&vk_bytes)?, proving_key.0.vk);
+        assert_eq!(<[u8; 32]>::from(Sha256::digest(&vk_bytes)), digest);
+
+        let mapped = MappedArkzkey::open(&dir.join("uncompressed"), Compress::No)?;
+        assert_eq!(vk_digest(&mapped.verifying_key()?), digest);
+        assert_eq!(mapped.read()?.1, matrices.clone().into());
+
+        // Reading with the wrong compression fails
+        assert!(verify_arkzkey(&dir.join("uncompressed"), Compress::Yes, &digest).is_err());
+        assert!(verify_arkzkey(&dir.join("compressed"), Compress::No, &digest).is_err());
+        std::fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
 }
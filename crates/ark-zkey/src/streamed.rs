@@ -0,0 +1,404 @@
+use std::marker::PhantomData;
+use std::ops::Range;
+
+use ark_bn254::{Bn254, Fr, G1Affine, G1Projective, G2Affine, G2Projective};
+use ark_ec::{AffineRepr, CurveGroup, Group, VariableBaseMSM};
+use ark_ff::{PrimeField, Zero};
+use ark_groth16::r1cs_to_qap::R1CSToQAP;
+use ark_groth16::{Proof, VerifyingKey};
+use ark_poly::GeneralEvaluationDomain;
+use ark_relations::r1cs::ConstraintMatrices;
+use ark_serialize::{CanonicalDeserialize, Compress, Validate};
+
+use crate::{SerializableConstraintMatrices, ZkeyError};
+
+/// Smallest number of points read at once by [`create_proof_streamed`],
+/// however small the memory budget.
+const MIN_CHUNK_LEN: usize = 64;
+
+/// A proving key read from arkzkey bytes a segment at a time.
+///
+/// Parsing only reads the verifying key and the points after it, and finds
+/// where the query vectors, which are almost all of the key, start and end.
+/// Their points are deserialized when read, so with the bytes mapped from a
+/// file the key is never in memory in full.
+pub struct ProvingKeySegments<'a> {
+    pub vk: VerifyingKey<Bn254>,
+    pub beta_g1: G1Affine,
+    pub delta_g1: G1Affine,
+    pub a_query: Segment<'a, G1Affine>,
+    pub b_g1_query: Segment<'a, G1Affine>,
+    pub b_g2_query: Segment<'a, G2Affine>,
+    pub h_query: Segment<'a, G1Affine>,
+    pub l_query: Segment<'a, G1Affine>,
+    matrices: &'a [u8],
+    compress: Compress,
+}
+
+impl<'a> ProvingKeySegments<'a> {
+    /// Parses the proving key of an arkzkey written with the given
+    /// compression.
+    ///
+    /// Like [`read_arkzkey_from_bytes_with`](crate::read_arkzkey_from_bytes_with),
+    /// points are not checked to be on the curve.
+    pub fn parse(arkzkey_bytes: &'a [u8], compress: Compress) -> Result<Self, ZkeyError> {
+        let mut bytes = arkzkey_bytes;
+        let vk = VerifyingKey::deserialize_with_mode(&mut bytes, compress, Validate::No)
+            .map_err(|err| ZkeyError::Deserialize("verifying key", err))?;
+        let beta_g1 = G1Affine::deserialize_with_mode(&mut bytes, compress, Validate::No)
+            .map_err(|err| ZkeyError::Deserialize("proving key", err))?;
+        let delta_g1 = G1Affine::deserialize_with_mode(&mut bytes, compress, Validate::No)
+            .map_err(|err| ZkeyError::Deserialize("proving key", err))?;
+        Ok(Self {
+            vk,
+            beta_g1,
+            delta_g1,
+            a_query: Segment::parse(&mut bytes, compress)?,
+            b_g1_query: Segment::parse(&mut bytes, compress)?,
+            b_g2_query: Segment::parse(&mut bytes, compress)?,
+            h_query: Segment::parse(&mut bytes, compress)?,
+            l_query: Segment::parse(&mut bytes, compress)?,
+            matrices: bytes,
+            compress,
+        })
+    }
+
+    /// Reads the constraint matrices following the proving key.
+    pub fn matrices(&self) -> Result<ConstraintMatrices<Fr>, ZkeyError> {
+        let mut bytes = self.matrices;
+        let matrices = SerializableConstraintMatrices::deserialize_with_mode(
+            &mut bytes,
+            self.compress,
+            Validate::No,
+        )
+        .map_err(|err| ZkeyError::Deserialize("constraint matrices", err))?;
+        if !bytes.is_empty() {
+            return Err(ZkeyError::TrailingBytes);
+        }
+        Ok(matrices.into())
+    }
+}
+
+/// The serialized points of one query vector of a [`ProvingKeySegments`].
+pub struct Segment<'a, G> {
+    bytes: &'a [u8],
+    len: usize,
+    point_size: usize,
+    compress: Compress,
+    _point: PhantomData<G>,
+}
+
+impl<'a, G: AffineRepr> Segment<'a, G> {
+    /// Splits the vector at the start of `bytes` off them.
+    fn parse(bytes: &mut &'a [u8], compress: Compress) -> Result<Self, ZkeyError> {
+        let len = u64::deserialize_with_mode(&mut *bytes, compress, Validate::No)
+            .map_err(|err| ZkeyError::Deserialize("proving key", err))?;
+        // Points serialize to the same size whatever their value
+        let point_size = G::zero().serialized_size(compress);
+        let size = usize::try_from(len)
+            .ok()
+            .and_then(|len| len.checked_mul(point_size))
+            .filter(|size| *size <= bytes.len())
+            .ok_or(ZkeyError::Deserialize(
+                "proving key",
+                ark_serialize::SerializationError::InvalidData,
+            ))?;
+        let (segment, rest) = bytes.split_at(size);
+        *bytes = rest;
+        Ok(Self {
+            bytes: segment,
+            len: len as usize,
+            point_size,
+            compress,
+            _point: PhantomData,
+        })
+    }
+
+    /// Returns the number of points.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Deserializes the points in `range`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds.
+    pub fn read(&self, range: Range<usize>) -> Result<Vec<G>, ZkeyError> {
+        assert!(range.start <= range.end && range.end <= self.len);
+        let mut bytes = &self.bytes[range.start * self.point_size..range.end * self.point_size];
+        range
+            .map(|_| {
+                G::deserialize_with_mode(&mut bytes, self.compress, Validate::No)
+                    .map_err(|err| ZkeyError::Deserialize("proving key", err))
+            })
+            .collect()
+    }
+}
+
+/// Creates a proof like `Groth16::create_proof_with_reduction_and_matrices`,
+/// with only a chunk of the points of the proving key in memory at once.
+///
+/// Every multi-scalar multiplication is split into chunks of at most about
+/// `max_memory_bytes` of points and scalars, each deserialized right before
+/// it's multiplied and dropped after. The full assignment, the constraint
+/// matrices and the QAP witness are still held in full, as are the buckets
+/// of each multiplication. Smaller chunks make proving slower: points are
+/// read once per proof instead of once per key, and the multiplications
+/// don't amortize as well.
+pub fn create_proof_streamed<QAP: R1CSToQAP>(
+    pk: &ProvingKeySegments,
+    r: Fr,
+    s: Fr,
+    full_assignment: &[Fr],
+    max_memory_bytes: usize,
+) -> Result<Proof<Bn254>, ZkeyError> {
+    let matrices = pk.matrices()?;
+    let num_inputs = matrices.num_instance_variables;
+    let h = QAP::witness_map_from_matrices::<Fr, GeneralEvaluationDomain<Fr>>(
+        &matrices,
+        num_inputs,
+        matrices.num_constraints,
+        full_assignment,
+    )
+    .map_err(ZkeyError::Prove)?;
+    drop(matrices);
+
+    let g1_chunk_len = chunk_len::<G1Affine>(max_memory_bytes);
+    let g2_chunk_len = chunk_len::<G2Affine>(max_memory_bytes);
+
+    // Compute C
+    let h_acc = msm(&pk.h_query, 0, &h, g1_chunk_len)?;
+    drop(h);
+    let aux_assignment = &full_assignment[num_inputs..];
+    let l_aux_acc = msm(&pk.l_query, 0, aux_assignment, g1_chunk_len)?;
+    let r_s_delta_g1 = pk
+        .delta_g1
+        .into_group()
+        .mul_bigint(r.into_bigint())
+        .mul_bigint(s.into_bigint());
+
+    // The input assignment without the leading one, then the aux assignment
+    let assignment = &full_assignment[1..];
+
+    // Compute A
+    let r_g1 = pk.delta_g1 * r;
+    let g_a = coeff(r_g1, &pk.a_query, pk.vk.alpha_g1, assignment, g1_chunk_len)?;
+    let s_g_a = g_a.mul_bigint(s.into_bigint());
+
+    // Compute B in G1 if needed
+    let g1_b = if r.is_zero() {
+        G1Projective::zero()
+    } else {
+        let s_g1 = pk.delta_g1 * s;
+        coeff(s_g1, &pk.b_g1_query, pk.beta_g1, assignment, g1_chunk_len)?
+    };
+
+    // Compute B in G2
+    let s_g2: G2Projective = pk.vk.delta_g2 * s;
+    let g2_b = coeff(
+        s_g2,
+        &pk.b_g2_query,
+        pk.vk.beta_g2,
+        assignment,
+        g2_chunk_len,
+    )?;
+    let r_g1_b = g1_b.mul_bigint(r.into_bigint());
+
+    let g_c = s_g_a + r_g1_b - r_s_delta_g1 + l_aux_acc + h_acc;
+
+    Ok(Proof {
+        a: g_a.into_affine(),
+        b: g2_b.into_affine(),
+        c: g_c.into_affine(),
+    })
+}
+
+/// Returns the number of points of `G` and their scalars fitting in
+/// `max_memory_bytes`.
+fn chunk_len<G: AffineRepr>(max_memory_bytes: usize) -> usize {
+    let term_size = std::mem::size_of::<G>() + std::mem::size_of::<<Fr as PrimeField>::BigInt>();
+    (max_memory_bytes / term_size).max(MIN_CHUNK_LEN)
+}
+
+/// Multiplies the points of `query` from `offset` on with `scalars`, up to
+/// the shorter of the two, `chunk_len` points at a time.
+fn msm<G>(
+    query: &Segment<G>,
+    offset: usize,
+    scalars: &[Fr],
+    chunk_len: usize,
+) -> Result<G::Group, ZkeyError>
+where
+    G: AffineRepr<ScalarField = Fr>,
+    G::Group: VariableBaseMSM<MulBase = G>,
+{
+    let len = scalars.len().min(query.len().saturating_sub(offset));
+    let mut acc = G::Group::zero();
+    for start in (0..len).step_by(chunk_len) {
+        let end = len.min(start + chunk_len);
+        let bases = query.read(offset + start..offset + end)?;
+        let scalars: Vec<_> = scalars[start..end]
+            .iter()
+            .map(|scalar| scalar.into_bigint())
+            .collect();
+        acc += G::Group::msm_bigint(&bases, &scalars);
+    }
+    Ok(acc)
+}
+
+/// Computes `initial + query[0] + <query[1..], assignment> + vk_param`.
+fn coeff<G>(
+    initial: G::Group,
+    query: &Segment<G>,
+    vk_param: G,
+    assignment: &[Fr],
+    chunk_len: usize,
+) -> Result<G::Group, ZkeyError>
+where
+    G: AffineRepr<ScalarField = Fr>,
+    G::Group: VariableBaseMSM<MulBase = G>,
+{
+    let el = query.read(0..1)?[0];
+    let acc = msm(query, 1, assignment, chunk_len)?;
+    Ok(initial + el + acc + vk_param)
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_groth16::r1cs_to_qap::LibsnarkReduction;
+    use ark_groth16::Groth16;
+    use ark_relations::lc;
+    use ark_relations::r1cs::{
+        ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, SynthesisError,
+    };
+    use ark_serialize::CanonicalSerialize;
+    use ark_std::UniformRand;
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::{read_arkzkey_from_bytes, SerializableProvingKey};
+
+    /// Proves knowledge of `x` with `x^(len + 1) = y` for a public `y`.
+    struct Powers {
+        x: Fr,
+        len: usize,
+    }
+
+    impl ConstraintSynthesizer<Fr> for Powers {
+        fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+            let mut power = self.x;
+            for _ in 0..self.len {
+                power *= self.x;
+            }
+            let y = cs.new_input_variable(|| Ok(power))?;
+            let x = cs.new_witness_variable(|| Ok(self.x))?;
+            let mut power = (self.x, x);
+            for i in 0..self.len {
+                let value = power.0 * self.x;
+                let next = if i + 1 == self.len {
+                    y
+                } else {
+                    cs.new_witness_variable(|| Ok(value))?
+                };
+                cs.enforce_constraint(lc!() + power.1, lc!() + x, lc!() + next)?;
+                power = (value, next);
+            }
+            Ok(())
+        }
+    }
+
+    fn assert_segments_eq<G: AffineRepr>(segment: &Segment<G>, points: &[G]) -> Result<()> {
+        assert_eq!(segment.len(), points.len());
+        assert_eq!(segment.read(0..segment.len())?, points);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_segments() -> Result<()> {
+        const ARKZKEY_BYTES: &[u8] = include_bytes!("./semaphore.16.arkzkey");
+
+        let (proving_key, matrices) = read_arkzkey_from_bytes(ARKZKEY_BYTES)?;
+        let segments = ProvingKeySegments::parse(ARKZKEY_BYTES, Compress::Yes)?;
+        assert_eq!(segments.vk, proving_key.vk);
+        assert_eq!(segments.beta_g1, proving_key.beta_g1);
+        assert_eq!(segments.delta_g1, proving_key.delta_g1);
+        assert_segments_eq(&segments.a_query, &proving_key.a_query)?;
+        assert_segments_eq(&segments.b_g1_query, &proving_key.b_g1_query)?;
+        assert_segments_eq(&segments.b_g2_query, &proving_key.b_g2_query)?;
+        assert_segments_eq(&segments.h_query, &proving_key.h_query)?;
+        assert_segments_eq(&segments.l_query, &proving_key.l_query)?;
+        assert_eq!(segments.l_query.read(3..5)?, proving_key.l_query[3..5]);
+        assert_eq!(segments.matrices()?, matrices);
+
+        assert!(ProvingKeySegments::parse(ARKZKEY_BYTES, Compress::No).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_proof_streamed() -> Result<()> {
+        let mut rng = ark_std::test_rng();
+        let x = Fr::rand(&mut rng);
+        let proving_key =
+            Groth16::<Bn254, LibsnarkReduction>::generate_random_parameters_with_reduction(
+                Powers { x, len: 300 },
+                &mut rng,
+            )?;
+
+        let cs = ConstraintSystem::new_ref();
+        Powers { x, len: 300 }.generate_constraints(cs.clone())?;
+        cs.finalize();
+        let matrices = cs.to_matrices().unwrap();
+        let cs = cs.borrow().unwrap();
+        let full_assignment = [&cs.instance_assignment[..], &cs.witness_assignment].concat();
+        assert_eq!(full_assignment[0], Fr::from(1));
+
+        let r = Fr::rand(&mut rng);
+        let s = Fr::rand(&mut rng);
+        let expected =
+            Groth16::<Bn254, LibsnarkReduction>::create_proof_with_reduction_and_matrices(
+                &proving_key,
+                r,
+                s,
+                &matrices,
+                cs.num_instance_variables,
+                cs.num_constraints,
+                &full_assignment,
+            )?;
+
+        for compress in [Compress::Yes, Compress::No] {
+            let mut bytes = Vec::new();
+            SerializableProvingKey(proving_key.clone())
+                .serialize_with_mode(&mut bytes, compress)?;
+            SerializableConstraintMatrices::from(matrices.clone())
+                .serialize_with_mode(&mut bytes, compress)?;
+            let segments = ProvingKeySegments::parse(&bytes, compress)?;
+
+            // No budget reads the smallest chunks, a large one everything at once
+            for max_memory_bytes in [0, 1 << 30] {
+                let proof = create_proof_streamed::<LibsnarkReduction>(
+                    &segments,
+                    r,
+                    s,
+                    &full_assignment,
+                    max_memory_bytes,
+                )?;
+                assert_eq!(proof, expected);
+            }
+        }
+
+        let pvk = ark_groth16::prepare_verifying_key(&proving_key.vk);
+        assert!(Groth16::<Bn254>::verify_proof(
+            &pvk,
+            &expected,
+            &full_assignment[1..cs.num_instance_variables],
+        )?);
+        Ok(())
+    }
+}
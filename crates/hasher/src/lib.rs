@@ -1,4 +1,5 @@
 use bytemuck::Pod;
+use ruint::aliases::U256;
 
 /// Hash types, values and algorithms for a Merkle tree
 pub trait Hasher {
@@ -15,3 +16,33 @@ pub trait Hasher {
 pub trait Hash: Pod + Eq + Send + Sync {}
 
 impl<T> Hash for T where T: Pod + Eq + Send + Sync {}
+
+/// A hash with a fixed 32-byte big-endian encoding, independent of its
+/// in-memory representation and of the platform
+pub trait HashBytes: Sized {
+    /// Encodes the hash, e.g. a number as 32 big-endian bytes
+    fn to_hash_bytes(&self) -> [u8; 32];
+
+    /// Decodes a hash encoded with [`HashBytes::to_hash_bytes`]
+    fn from_hash_bytes(bytes: [u8; 32]) -> Self;
+}
+
+impl HashBytes for [u8; 32] {
+    fn to_hash_bytes(&self) -> [u8; 32] {
+        *self
+    }
+
+    fn from_hash_bytes(bytes: [u8; 32]) -> Self {
+        bytes
+    }
+}
+
+impl HashBytes for U256 {
+    fn to_hash_bytes(&self) -> [u8; 32] {
+        self.to_be_bytes()
+    }
+
+    fn from_hash_bytes(bytes: [u8; 32]) -> Self {
+        Self::from_be_bytes(bytes)
+    }
+}
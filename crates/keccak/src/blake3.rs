@@ -0,0 +1,39 @@
+use hasher::Hasher;
+
+/// BLAKE3 of the concatenation of two nodes, for trees that are not proven
+/// in circuits and favor hashing throughput.
+pub struct Blake3;
+
+impl Hasher for Blake3 {
+    type Hash = [u8; 32];
+
+    fn hash_node(left: &Self::Hash, right: &Self::Hash) -> Self::Hash {
+        let mut blake3 = ::blake3::Hasher::new();
+
+        blake3.update(left);
+        blake3.update(right);
+
+        blake3.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_node() {
+        let left = [1; 32];
+        let right = [2; 32];
+        let mut concatenated = [1; 64];
+        concatenated[32..].fill(2);
+        assert_eq!(
+            Blake3::hash_node(&left, &right),
+            *::blake3::hash(&concatenated).as_bytes()
+        );
+        assert_ne!(
+            Blake3::hash_node(&left, &right),
+            Blake3::hash_node(&right, &left)
+        );
+    }
+}
@@ -0,0 +1,25 @@
+use std::marker::PhantomData;
+
+use digest::consts::U32;
+use digest::{Digest, OutputSizeUser};
+use hasher::Hasher;
+
+/// A [`Hasher`] hashing the concatenation of two nodes with any
+/// [`Digest`] with 32 byte outputs, e.g. `DigestHasher<blake2::Blake2s256>`.
+pub struct DigestHasher<D>(PhantomData<D>);
+
+impl<D> Hasher for DigestHasher<D>
+where
+    D: Digest + OutputSizeUser<OutputSize = U32>,
+{
+    type Hash = [u8; 32];
+
+    fn hash_node(left: &Self::Hash, right: &Self::Hash) -> Self::Hash {
+        let mut digest = D::new();
+
+        digest.update(left);
+        digest.update(right);
+
+        digest.finalize().into()
+    }
+}
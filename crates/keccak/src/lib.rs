@@ -2,3 +2,12 @@ pub mod keccak;
 
 #[cfg(feature = "sha3")]
 pub mod sha3;
+
+#[cfg(feature = "digest")]
+pub mod digest;
+
+#[cfg(feature = "sha2")]
+pub mod sha256;
+
+#[cfg(feature = "blake3")]
+pub mod blake3;
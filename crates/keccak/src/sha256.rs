@@ -0,0 +1,21 @@
+use crate::digest::DigestHasher;
+
+/// SHA-256 of the concatenation of two nodes, as in the Merkle trees of
+/// Ethereum consensus and Bitcoin.
+pub type Sha256 = DigestHasher<sha2::Sha256>;
+
+#[cfg(test)]
+mod tests {
+    use hasher::Hasher;
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_hash_node() {
+        assert_eq!(
+            Sha256::hash_node(&[0; 32], &[0; 32]),
+            hex!("f5a5fd42d16a20302798ef6ed309979b43003d2320d9f0e8ea9831a92759fb4b")
+        );
+    }
+}
@@ -2,7 +2,9 @@ use hasher::Hasher;
 use ruint::aliases::U256;
 
 pub mod constants;
+pub mod parameters;
 pub mod poseidon;
+pub mod sponge;
 
 pub struct Poseidon;
 
@@ -0,0 +1,479 @@
+//! Poseidon parameters for any state width.
+//!
+//! [`hash1`] and [`hash2`] use hard-coded constants for widths two and
+//! three. [`Parameters`] holds the round constants and MDS matrix of other
+//! widths, generated with the Grain LFSR of the reference script
+//! `generate_parameters_grain.sage` from the Poseidon paper, so their
+//! provenance can be checked. [`Parameters::circom`] are the parameters of
+//! the circomlib `Poseidon(n)` template for `n` from one to sixteen inputs,
+//! which [`hash`] computes.
+//!
+//! [`hash1`]: crate::poseidon::hash1
+//! [`hash2`]: crate::poseidon::hash2
+//! [`hash`]: crate::poseidon::hash
+
+use ark_bn254::Fr;
+use ark_ff::{Field, PrimeField, Zero};
+use once_cell::sync::{Lazy, OnceCell};
+use ruint::aliases::U256;
+use thiserror::Error;
+
+/// The smallest state width, one input and the capacity element.
+pub const MIN_WIDTH: usize = 2;
+
+/// The largest state width of the circomlib `Poseidon(n)` template.
+pub const MAX_WIDTH: usize = 17;
+
+/// Number of full rounds used by circomlib for all widths.
+pub const FULL_ROUNDS: usize = 8;
+
+/// Number of partial rounds used by circomlib, by width from [`MIN_WIDTH`]
+/// to [`MAX_WIDTH`].
+pub const PARTIAL_ROUNDS: [usize; MAX_WIDTH - MIN_WIDTH + 1] = [
+    56, 57, 56, 60, 60, 63, 64, 63, 60, 66, 60, 65, 70, 60, 64, 68,
+];
+
+/// Size of the BN254 scalar field in bits.
+const FIELD_BITS: u16 = 254;
+
+static CIRCOM: Lazy<Vec<OnceCell<Parameters>>> =
+    Lazy::new(|| (MIN_WIDTH..=MAX_WIDTH).map(|_| OnceCell::new()).collect());
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ParametersError {
+    #[error("width {0} is not supported")]
+    UnsupportedWidth(usize),
+    #[error("the number of full rounds must be even, got {0}")]
+    OddFullRounds(usize),
+    #[error("expected {expected} round constants, got {actual}")]
+    RoundConstants { expected: usize, actual: usize },
+    #[error("the MDS matrix must be {0}x{0}")]
+    MdsShape(usize),
+    #[error("constant {0} is not in the field")]
+    NotInField(U256),
+    #[cfg(feature = "circomlibjs")]
+    #[error("invalid circomlibjs constants: {0}")]
+    Json(String),
+}
+
+/// Round constants and MDS matrix of the Poseidon permutation over the BN254
+/// scalar field with `x^5` S-boxes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Parameters {
+    width: usize,
+    full_rounds: usize,
+    partial_rounds: usize,
+    /// `width` constants per round.
+    round_constants: Vec<Fr>,
+    mds: Vec<Vec<Fr>>,
+}
+
+impl Parameters {
+    /// Returns the parameters used by circomlib for a state width, i.e. for
+    /// `width - 1` inputs. They are generated on first use.
+    #[must_use]
+    pub fn circom(width: usize) -> Option<&'static Self> {
+        let partial_rounds = *PARTIAL_ROUNDS.get(width.checked_sub(MIN_WIDTH)?)?;
+        Some(CIRCOM[width - MIN_WIDTH].get_or_init(|| {
+            Self::generate(width, FULL_ROUNDS, partial_rounds)
+                .expect("circomlib parameters are valid")
+        }))
+    }
+
+    /// Generates parameters like the reference script, i.e.
+    /// `sage generate_parameters_grain.sage 1 0 254 <width> <full_rounds>
+    /// <partial_rounds> <modulus>`.
+    ///
+    /// The script also checks the MDS matrix for infinitely long invariant
+    /// subspace trails, and generates a new one if it has any. These checks
+    /// are not implemented, so parameters differ from the script's where it
+    /// rejected the first matrix. The parameters of widths up to 13 were
+    /// compared with circomlibjs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the width is less than [`MIN_WIDTH`] or the
+    /// number of full rounds is odd.
+    pub fn generate(
+        width: usize,
+        full_rounds: usize,
+        partial_rounds: usize,
+    ) -> Result<Self, ParametersError> {
+        if !(MIN_WIDTH..1 << 12).contains(&width) {
+            return Err(ParametersError::UnsupportedWidth(width));
+        }
+        if full_rounds % 2 != 0 {
+            return Err(ParametersError::OddFullRounds(full_rounds));
+        }
+        let mut grain = Grain::new(width, full_rounds, partial_rounds);
+
+        let round_constants = (0..(full_rounds + partial_rounds) * width)
+            .map(|_| grain.next_field_element())
+            .collect();
+
+        let mds = loop {
+            let mut points: Vec<Fr> = (0..2 * width)
+                .map(|_| Fr::from_be_bytes_mod_order(&grain.next_bits().to_be_bytes::<32>()))
+                .collect();
+            while has_duplicates(&points) {
+                for point in &mut points {
+                    *point = Fr::from_be_bytes_mod_order(&grain.next_bits().to_be_bytes::<32>());
+                }
+            }
+            // A Cauchy matrix, which is MDS for distinct points
+            let (xs, ys) = points.split_at(width);
+            let mds: Option<Vec<Vec<Fr>>> = xs
+                .iter()
+                .map(|x| ys.iter().map(|y| (*x + y).inverse()).collect())
+                .collect();
+            if let Some(mds) = mds {
+                break mds;
+            }
+        };
+
+        Ok(Self {
+            width,
+            full_rounds,
+            partial_rounds,
+            round_constants,
+            mds,
+        })
+    }
+
+    /// Creates parameters from constants, e.g. from another implementation.
+    ///
+    /// The round constants are the `width` constants of each round one after
+    /// the other, as in circomlibjs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the numbers of constants don't match the width
+    /// and rounds, or a constant is not in the field.
+    pub fn from_constants(
+        full_rounds: usize,
+        partial_rounds: usize,
+        round_constants: &[U256],
+        mds: &[Vec<U256>],
+    ) -> Result<Self, ParametersError> {
+        let width = mds.len();
+        if width < MIN_WIDTH {
+            return Err(ParametersError::UnsupportedWidth(width));
+        }
+        if full_rounds % 2 != 0 {
+            return Err(ParametersError::OddFullRounds(full_rounds));
+        }
+        let expected = (full_rounds + partial_rounds) * width;
+        if round_constants.len() != expected {
+            return Err(ParametersError::RoundConstants {
+                expected,
+                actual: round_constants.len(),
+            });
+        }
+        if mds.iter().any(|row| row.len() != width) {
+            return Err(ParametersError::MdsShape(width));
+        }
+        let to_field =
+            |value: &U256| Fr::try_from(*value).map_err(|_| ParametersError::NotInField(*value));
+        Ok(Self {
+            width,
+            full_rounds,
+            partial_rounds,
+            round_constants: round_constants
+                .iter()
+                .map(to_field)
+                .collect::<Result<_, _>>()?,
+            mds: mds
+                .iter()
+                .map(|row| row.iter().map(to_field).collect())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Reads the parameters of a width from the unoptimized constants of
+    /// circomlibjs, i.e. `poseidon_constants.json` with the round constants
+    /// in `C` and the MDS matrices in `M`, both indexed by `width - 2`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the JSON is invalid or has no constants for the
+    /// width.
+    #[cfg(feature = "circomlibjs")]
+    pub fn from_circomlibjs_json(json: &str, width: usize) -> Result<Self, ParametersError> {
+        #[derive(serde::Deserialize)]
+        struct Constants {
+            #[serde(rename = "C")]
+            c: Vec<Vec<U256>>,
+            #[serde(rename = "M")]
+            m: Vec<Vec<Vec<U256>>>,
+        }
+
+        let constants: Constants =
+            serde_json::from_str(json).map_err(|err| ParametersError::Json(err.to_string()))?;
+        let index = width
+            .checked_sub(MIN_WIDTH)
+            .ok_or(ParametersError::UnsupportedWidth(width))?;
+        let (Some(round_constants), Some(mds)) = (constants.c.get(index), constants.m.get(index))
+        else {
+            return Err(ParametersError::UnsupportedWidth(width));
+        };
+        let rounds = round_constants.len() / width;
+        let partial_rounds =
+            rounds
+                .checked_sub(FULL_ROUNDS)
+                .ok_or(ParametersError::RoundConstants {
+                    expected: FULL_ROUNDS * width,
+                    actual: round_constants.len(),
+                })?;
+        Self::from_constants(FULL_ROUNDS, partial_rounds, round_constants, mds)
+    }
+
+    #[must_use]
+    pub const fn width(&self) -> usize {
+        self.width
+    }
+
+    #[must_use]
+    pub const fn full_rounds(&self) -> usize {
+        self.full_rounds
+    }
+
+    #[must_use]
+    pub const fn partial_rounds(&self) -> usize {
+        self.partial_rounds
+    }
+
+    /// The `width` constants of each round one after the other.
+    #[must_use]
+    pub fn round_constants(&self) -> Vec<U256> {
+        self.round_constants.iter().map(|&c| c.into()).collect()
+    }
+
+    #[must_use]
+    pub fn mds(&self) -> Vec<Vec<U256>> {
+        self.mds
+            .iter()
+            .map(|row| row.iter().map(|&m| m.into()).collect())
+            .collect()
+    }
+
+    /// Hashes `width - 1` inputs like the circomlib `Poseidon(n)` template,
+    /// i.e. permutes a zero followed by the inputs and returns the first
+    /// element.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of inputs is not `width - 1` or an input is not a
+    /// valid field element.
+    #[must_use]
+    pub fn hash(&self, inputs: &[U256]) -> U256 {
+        assert_eq!(
+            inputs.len(),
+            self.width - 1,
+            "width {} hashes {} inputs",
+            self.width,
+            self.width - 1
+        );
+        let mut state = Vec::with_capacity(self.width);
+        state.push(Fr::zero());
+        state.extend(inputs.iter().map(|input| Fr::try_from(*input).unwrap()));
+        self.permute(&mut state);
+        state[0].into()
+    }
+
+    fn permute(&self, state: &mut [Fr]) {
+        let half = self.full_rounds / 2;
+        let mut mixed = vec![Fr::zero(); self.width];
+        for (round, constants) in self.round_constants.chunks(self.width).enumerate() {
+            // Add round constants
+            for (element, constant) in state.iter_mut().zip(constants) {
+                *element += constant;
+            }
+
+            // SubWords, S-Box: Exponentiate
+            if round < half || round >= half + self.partial_rounds {
+                for element in state.iter_mut() {
+                    *element = element.pow([5]);
+                }
+            } else {
+                state[0] = state[0].pow([5]);
+            }
+
+            // MixLayer: Multiply by maximum distance separable matrix
+            for (mixed, row) in mixed.iter_mut().zip(&self.mds) {
+                *mixed = row.iter().zip(state.iter()).map(|(m, s)| *m * s).sum();
+            }
+            state.copy_from_slice(&mixed);
+        }
+    }
+}
+
+fn has_duplicates(points: &[Fr]) -> bool {
+    points
+        .iter()
+        .enumerate()
+        .any(|(i, point)| points[i + 1..].contains(point))
+}
+
+/// The Grain LFSR of the reference script, seeded with the parameters.
+struct Grain {
+    /// The last 80 bits, the oldest in the lowest bit.
+    state: u128,
+}
+
+impl Grain {
+    fn new(width: usize, full_rounds: usize, partial_rounds: usize) -> Self {
+        let mut grain = Self { state: 0 };
+        let mut position = 0;
+        let mut push = |value: u128, bits: u32| {
+            for bit in (0..bits).rev() {
+                grain.state |= ((value >> bit) & 1) << position;
+                position += 1;
+            }
+        };
+        // Prime field, x^alpha S-box, field size, width and rounds, padded
+        // with ones
+        push(1, 2);
+        push(0, 4);
+        push(FIELD_BITS.into(), 12);
+        push(width as u128, 12);
+        push(full_rounds as u128, 10);
+        push(partial_rounds as u128, 10);
+        push((1 << 30) - 1, 30);
+
+        for _ in 0..160 {
+            grain.step();
+        }
+        grain
+    }
+
+    fn step(&mut self) -> bool {
+        let bit = |i: u32| (self.state >> i) & 1;
+        let new = bit(62) ^ bit(51) ^ bit(38) ^ bit(23) ^ bit(13) ^ bit(0);
+        self.state = (self.state >> 1) | (new << 79);
+        new == 1
+    }
+
+    /// Outputs the second bit of each pair starting with a one.
+    fn next_bit(&mut self) -> bool {
+        while !self.step() {
+            self.step();
+        }
+        self.step()
+    }
+
+    /// The next field size bits, the first one highest.
+    fn next_bits(&mut self) -> U256 {
+        (0..FIELD_BITS).fold(U256::ZERO, |value, _| {
+            (value << 1) | U256::from(u8::from(self.next_bit()))
+        })
+    }
+
+    /// The next bits that are less than the modulus.
+    fn next_field_element(&mut self) -> Fr {
+        loop {
+            if let Ok(element) = Fr::try_from(self.next_bits()) {
+                return element;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruint::uint;
+
+    use super::*;
+    use crate::constants;
+
+    fn flatten<const N: usize>(rounds: &[[U256; N]]) -> Vec<U256> {
+        rounds.iter().flatten().copied().collect()
+    }
+
+    #[test]
+    fn test_hard_coded_constants() {
+        let two = Parameters::circom(2).unwrap();
+        assert_eq!(two.round_constants(), flatten(&constants::C1));
+        assert_eq!(two.mds(), constants::M1.map(Vec::from));
+
+        let three = Parameters::circom(3).unwrap();
+        assert_eq!(three.round_constants(), flatten(&constants::C));
+        assert_eq!(three.mds(), constants::M.map(Vec::from));
+
+        assert!(Parameters::circom(1).is_none());
+        assert!(Parameters::circom(18).is_none());
+    }
+
+    #[test]
+    fn test_from_constants() {
+        let three = Parameters::circom(3).unwrap();
+        let copy = Parameters::from_constants(
+            three.full_rounds(),
+            three.partial_rounds(),
+            &three.round_constants(),
+            &three.mds(),
+        )
+        .unwrap();
+        assert_eq!(&copy, three);
+
+        assert_eq!(
+            Parameters::from_constants(8, 57, &three.round_constants()[1..], &three.mds()),
+            Err(ParametersError::RoundConstants {
+                expected: 195,
+                actual: 194
+            })
+        );
+        let mut mds = three.mds();
+        mds[1][2] = U256::MAX;
+        assert_eq!(
+            Parameters::from_constants(8, 57, &three.round_constants(), &mds),
+            Err(ParametersError::NotInField(U256::MAX))
+        );
+    }
+
+    #[test]
+    fn test_hash() {
+        let ones = |n| vec![U256::from(1); n];
+        let three = Parameters::circom(3).unwrap();
+        let one = U256::from(1);
+        assert_eq!(three.hash(&ones(2)), crate::poseidon::hash2(one, one));
+        // circomlibjs `poseidon` of three and twelve ones
+        assert_eq!(
+            Parameters::circom(4).unwrap().hash(&ones(3)),
+            uint!(0x02c0066e10a72abd2b33c3b214cb3e81bcb1b6e30961cd23c202b18673bf2543_U256)
+        );
+        assert_eq!(
+            Parameters::circom(13).unwrap().hash(&ones(12)),
+            uint!(0x14390be0baef249bd47c65ddac65c2e52e8513c081c1cd72c98006098e9a8fbe_U256)
+        );
+    }
+
+    #[cfg(feature = "circomlibjs")]
+    #[test]
+    fn test_from_circomlibjs_json() {
+        let hex = |values: Vec<U256>| {
+            values
+                .iter()
+                .map(|value| format!("\"{value:#x}\""))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let two = Parameters::circom(2).unwrap();
+        let three = Parameters::circom(3).unwrap();
+        let mds = |parameters: &Parameters| {
+            let rows: Vec<_> = parameters.mds().into_iter().map(hex).collect();
+            format!("[[{}]]", rows.join("],["))
+        };
+        let json = format!(
+            r#"{{"C":[[{}],[{}]],"M":[{},{}]}}"#,
+            hex(two.round_constants()),
+            hex(three.round_constants()),
+            mds(two),
+            mds(three)
+        );
+        assert_eq!(&Parameters::from_circomlibjs_json(&json, 3).unwrap(), three);
+        assert_eq!(
+            Parameters::from_circomlibjs_json(&json, 4),
+            Err(ParametersError::UnsupportedWidth(4))
+        );
+    }
+}
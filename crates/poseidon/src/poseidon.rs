@@ -4,6 +4,7 @@ use once_cell::sync::Lazy;
 use ruint::aliases::U256;
 
 use crate::constants;
+use crate::parameters::Parameters;
 
 static M1: Lazy<[[Fr; 2]; 2]> = Lazy::new(|| {
     constants::M1
@@ -109,7 +110,33 @@ pub fn hash2(left: U256, right: U256) -> U256 {
     let left = left.try_into().unwrap();
     let right = right.try_into().unwrap();
     let mut state = [Fr::zero(), left, right];
+    permute(&mut state);
+    state[0].into()
+}
+
+/// Compute the Poseidon hash of one to sixteen values, like the circomlib
+/// `Poseidon(n)` template.
+///
+/// One and two values are hashed with [`hash1`] and [`hash2`], more with
+/// the [`Parameters`] of their width.
+///
+/// # Panics
+///
+/// Panics if there are no or more than sixteen `inputs`, or any of them is
+/// not a valid field element.
+#[must_use]
+pub fn hash(inputs: &[U256]) -> U256 {
+    match inputs {
+        [value] => hash1(*value),
+        [left, right] => hash2(*left, *right),
+        _ => Parameters::circom(inputs.len() + 1)
+            .unwrap_or_else(|| panic!("can't hash {} values", inputs.len()))
+            .hash(inputs),
+    }
+}
 
+/// Apply the width three Poseidon permutation used by [`hash2`] in place.
+pub(crate) fn permute(state: &mut [Fr; 3]) {
     for i in 0..65 {
         // Add round constants
         state[0] += C[i][0];
@@ -124,13 +151,12 @@ pub fn hash2(left: U256, right: U256) -> U256 {
         }
 
         // MixLayer: Multiply by maximum distance separable matrix
-        state = [
+        *state = [
             M[0][0] * state[0] + M[0][1] * state[1] + M[0][2] * state[2],
             M[1][0] * state[0] + M[1][1] * state[1] + M[1][2] * state[2],
             M[2][0] * state[0] + M[2][1] * state[1] + M[2][2] * state[2],
         ];
     }
-    state[0].into()
 }
 
 #[cfg(test)]
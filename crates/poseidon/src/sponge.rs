@@ -0,0 +1,145 @@
+//! Poseidon sponge for variable-length inputs.
+//!
+//! This is a construction of this crate, not a standard one. circomlib and
+//! circomlibjs have no sponge, only fixed-width hashes of up to 16 inputs, and
+//! the padding and domain separation below differ from the SAFE API. Hashes
+//! are only reproducible with this module and can't be checked in a circuit
+//! or contract without reimplementing it exactly.
+//!
+//! Uses the width three permutation from circomlib (as in [`hash2`]) with a
+//! rate of two elements and a capacity of one. The capacity element is
+//! initialized with the input length, following the domain separation for
+//! fixed-length hashing from the Poseidon paper, so that inputs differing only
+//! by trailing zeros hash to different values.
+//!
+//! Note that because of this domain separation `hash_fields(&[a, b])` is
+//! **not** equal to `hash2(a, b)`.
+//!
+//! [`hash2`]: crate::poseidon::hash2
+
+use ark_bn254::Fr;
+use ark_ff::Zero;
+use ruint::aliases::U256;
+use thiserror::Error;
+
+use crate::poseidon::permute;
+
+/// Number of field elements absorbed per permutation.
+pub const RATE: usize = 2;
+
+/// Number of bytes packed into a single field element by [`hash_bytes`].
+///
+/// 31 bytes always fit in the BN254 scalar field.
+pub const BYTES_PER_ELEMENT: usize = 31;
+
+const DOMAIN_FIELDS: u64 = 0;
+const DOMAIN_BYTES: u64 = 1;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SpongeError {
+    #[error("input {0} is not a field element")]
+    NotInField(usize),
+}
+
+/// Hash a variable number of field elements to a single field element.
+///
+/// # Errors
+///
+/// Returns an error if any of the `inputs` is not a valid field element.
+pub fn hash_fields(inputs: &[U256]) -> Result<U256, SpongeError> {
+    let elements = inputs
+        .iter()
+        .enumerate()
+        .map(|(index, input)| Fr::try_from(*input).map_err(|_| SpongeError::NotInField(index)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(sponge(&elements, inputs.len(), DOMAIN_FIELDS))
+}
+
+/// Hash arbitrary bytes to a field element.
+///
+/// The bytes are packed big-endian into field elements of
+/// [`BYTES_PER_ELEMENT`] bytes each, the last element may be shorter.
+#[must_use]
+pub fn hash_bytes(bytes: &[u8]) -> U256 {
+    let elements = bytes
+        .chunks(BYTES_PER_ELEMENT)
+        .map(|chunk| {
+            // Never panics because 31 bytes always fit.
+            let value = U256::try_from_be_slice(chunk).unwrap();
+            Fr::try_from(value).unwrap()
+        })
+        .collect::<Vec<_>>();
+    sponge(&elements, bytes.len(), DOMAIN_BYTES)
+}
+
+fn sponge(elements: &[Fr], len: usize, domain: u64) -> U256 {
+    let capacity = (U256::from(len) << 64) | U256::from(domain);
+    let mut state = [Fr::try_from(capacity).unwrap(), Fr::zero(), Fr::zero()];
+
+    for block in elements.chunks(RATE) {
+        for (i, element) in block.iter().enumerate() {
+            state[i + 1] += element;
+        }
+        permute(&mut state);
+    }
+
+    // Empty inputs still go through the permutation once
+    if elements.is_empty() {
+        permute(&mut state);
+    }
+
+    state[0].into()
+}
+
+#[cfg(test)]
+mod tests {
+    use ruint::uint;
+
+    use super::*;
+    use crate::poseidon::hash2;
+
+    #[test]
+    fn test_single_block() {
+        // With a zero length tag one block reduces to `hash2`.
+        let mut state = [Fr::zero(), Fr::from(1_u64), Fr::from(2_u64)];
+        permute(&mut state);
+        let result: U256 = state[0].into();
+        assert_eq!(result, hash2(uint!(1_U256), uint!(2_U256)));
+    }
+
+    #[test]
+    fn test_hash_fields_domain_separation() {
+        let empty = hash_fields(&[]).unwrap();
+        let zero = hash_fields(&[U256::ZERO]).unwrap();
+        let zeros = hash_fields(&[U256::ZERO; 2]).unwrap();
+        let three = hash_fields(&[U256::ZERO; 3]).unwrap();
+        assert_ne!(empty, zero);
+        assert_ne!(zero, zeros);
+        assert_ne!(zeros, three);
+        assert_ne!(zeros, hash2(U256::ZERO, U256::ZERO));
+    }
+
+    #[test]
+    fn test_hash_fields_not_in_field() {
+        const MODULUS: U256 = uint!(
+            21888242871839275222246405745257275088548364400416034343698204186575808495617_U256
+        );
+        assert_eq!(
+            hash_fields(&[U256::ZERO, MODULUS]),
+            Err(SpongeError::NotInField(1))
+        );
+        assert_eq!(hash_fields(&[U256::MAX]), Err(SpongeError::NotInField(0)));
+    }
+
+    #[test]
+    fn test_hash_bytes() {
+        assert_eq!(hash_bytes(b"hello"), hash_bytes(b"hello"));
+        assert_ne!(hash_bytes(b"hello"), hash_bytes(b"hellp"));
+        assert_ne!(hash_bytes(&[0]), hash_bytes(&[0, 0]));
+        assert_ne!(hash_bytes(&[]), hash_fields(&[]).unwrap());
+
+        // Inputs spanning multiple elements and blocks
+        let long = [0xff_u8; 100];
+        assert_ne!(hash_bytes(&long), hash_bytes(&long[..99]));
+    }
+}
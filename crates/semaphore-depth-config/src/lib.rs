@@ -10,10 +10,18 @@ pub const fn get_supported_depth_count() -> usize {
     {
         res += 1;
     }
+    #[cfg(feature = "depth_21")]
+    {
+        res += 1;
+    }
     #[cfg(feature = "depth_30")]
     {
         res += 1;
     }
+    #[cfg(feature = "depth_32")]
+    {
+        res += 1;
+    }
     res
 }
 
@@ -31,11 +39,21 @@ const fn gen_supported_depths() -> [usize; get_supported_depth_count()] {
         res[i] = 20;
         i += 1;
     }
+    #[cfg(feature = "depth_21")]
+    {
+        res[i] = 21;
+        i += 1;
+    }
     #[cfg(feature = "depth_30")]
     {
         res[i] = 30;
         i += 1;
     }
+    #[cfg(feature = "depth_32")]
+    {
+        res[i] = 32;
+        i += 1;
+    }
     res
 }
 
@@ -49,6 +67,99 @@ pub fn get_supported_depths() -> &'static [usize] {
 pub const fn get_depth_index(depth: usize) -> Option<usize> {
     let mut i = 0;
 
+    #[cfg(feature = "depth_16")]
+    {
+        if depth == 16 {
+            return Some(i);
+        }
+        i += 1;
+    }
+    #[cfg(feature = "depth_20")]
+    {
+        if depth == 20 {
+            return Some(i);
+        }
+        i += 1;
+    }
+    #[cfg(feature = "depth_21")]
+    {
+        if depth == 21 {
+            return Some(i);
+        }
+        i += 1;
+    }
+    #[cfg(feature = "depth_30")]
+    {
+        if depth == 30 {
+            return Some(i);
+        }
+        i += 1;
+    }
+    #[cfg(feature = "depth_32")]
+    {
+        if depth == 32 {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+// Witness graphs are only shipped for depths 16, 20 and 30, see `graphs`, so
+// `depth_21` and `depth_32` builds verify proofs of these depths but don't
+// create them.
+
+pub const fn get_prover_depth_count() -> usize {
+    let mut res = 0;
+    #[cfg(feature = "depth_16")]
+    {
+        res += 1;
+    }
+    #[cfg(feature = "depth_20")]
+    {
+        res += 1;
+    }
+    #[cfg(feature = "depth_30")]
+    {
+        res += 1;
+    }
+    res
+}
+
+#[allow(unused_assignments)]
+const fn gen_prover_depths() -> [usize; get_prover_depth_count()] {
+    let mut res = [0; get_prover_depth_count()];
+    let mut i = 0;
+    #[cfg(feature = "depth_16")]
+    {
+        res[i] = 16;
+        i += 1;
+    }
+    #[cfg(feature = "depth_20")]
+    {
+        res[i] = 20;
+        i += 1;
+    }
+    #[cfg(feature = "depth_30")]
+    {
+        res[i] = 30;
+        i += 1;
+    }
+    res
+}
+
+static PROVER_DEPTHS: [usize; get_prover_depth_count()] = gen_prover_depths();
+
+/// The supported depths that proofs can be created for, those with a witness
+/// graph.
+pub fn get_prover_depths() -> &'static [usize] {
+    &PROVER_DEPTHS
+}
+
+#[allow(unused_assignments)]
+pub const fn get_prover_depth_index(depth: usize) -> Option<usize> {
+    let mut i = 0;
+
     #[cfg(feature = "depth_16")]
     {
         if depth == 16 {
@@ -1,6 +1,6 @@
 use proc_macro::TokenStream;
 use quote::{format_ident, quote};
-use semaphore_depth_config::get_supported_depths;
+use semaphore_depth_config::{get_prover_depths, get_supported_depths};
 use syn::{
     parse::{Parse, ParseStream},
     parse_macro_input, parse_quote,
@@ -39,20 +39,64 @@ use syn::{
 ///     test_depth_non_zero(30);
 /// }
 /// ```
+///
+/// Async functions are annotated with `#[test_all_depths(async)]`, and their
+/// tests run on a `#[tokio::test]` runtime, so the crate needs `tokio` with
+/// the `macros` and `rt` features:
+/// ```no_run
+/// # use semaphore_depth_macros::test_all_depths;
+/// #[test_all_depths(async)]
+/// async fn test_depth_non_zero(depth: usize) {
+///     assert!(depth > 0);
+/// }
+/// ```
+/// generates tests like
+/// ```no_run
+/// # async fn test_depth_non_zero(depth: usize) {}
+/// #[tokio::test]
+/// async fn test_depth_non_zero_depth_16() {
+///     test_depth_non_zero(16).await;
+/// }
+/// ```
+///
+/// Tests that create proofs are annotated with `#[test_all_depths(prover)]`,
+/// or `#[test_all_depths(async, prover)]`, to only run for the depths with a
+/// witness graph, see `get_prover_depths`.
 #[proc_macro_attribute]
-pub fn test_all_depths(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn test_all_depths(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as TestArgs);
     let fun = parse_macro_input!(item as syn::ItemFn);
     let fun_name = &fun.sig.ident;
 
+    if args.is_async != fun.sig.asyncness.is_some() {
+        let message = if args.is_async {
+            "`#[test_all_depths(async)]` expects an async function"
+        } else {
+            "async functions are annotated with `#[test_all_depths(async)]`"
+        };
+        return syn::Error::new_spanned(&fun.sig, message)
+            .to_compile_error()
+            .into();
+    }
+
     let original_fun = quote! { #fun };
     let mut result = TokenStream::from(original_fun);
 
-    for depth in get_supported_depths() {
+    for depth in args.depths() {
         let fun_name_versioned = format_ident!("{}_depth_{}", fun_name, depth);
-        let tokens = quote! {
-            #[test]
-            fn #fun_name_versioned() {
-                #fun_name(#depth);
+        let tokens = if args.is_async {
+            quote! {
+                #[tokio::test]
+                async fn #fun_name_versioned() {
+                    #fun_name(#depth).await;
+                }
+            }
+        } else {
+            quote! {
+                #[test]
+                fn #fun_name_versioned() {
+                    #fun_name(#depth);
+                }
             }
         };
         result.extend(TokenStream::from(tokens));
@@ -60,6 +104,114 @@ pub fn test_all_depths(_attr: TokenStream, item: TokenStream) -> TokenStream {
     result
 }
 
+/// Arguments of [`macro@test_all_depths`], any of `async` and `prover`.
+#[derive(Default)]
+struct TestArgs {
+    is_async: bool,
+    prover: bool,
+}
+
+impl TestArgs {
+    fn depths(&self) -> &'static [usize] {
+        if self.prover {
+            get_prover_depths()
+        } else {
+            get_supported_depths()
+        }
+    }
+}
+
+impl Parse for TestArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = TestArgs::default();
+        while !input.is_empty() {
+            if input.parse::<Option<Token![async]>>()?.is_some() {
+                args.is_async = true;
+            } else {
+                let ident = input.parse::<Ident>()?;
+                if ident != "prover" {
+                    return Err(syn::Error::new_spanned(
+                        ident,
+                        "expected `async` or `prover`",
+                    ));
+                }
+                args.prover = true;
+            }
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+        Ok(args)
+    }
+}
+
+/// Multi-depth benchmark generator
+///
+/// Annotates a benchmark taking the criterion and a depth, and generates a
+/// benchmark with the name suffixed by `_all_depths` running it for each
+/// supported depth, to list in `criterion_group!`. The benchmark IDs should
+/// include the depth to tell the runs apart.
+///
+/// For example,
+/// ```no_run
+/// # use semaphore_depth_macros::bench_all_depths;
+/// # struct Criterion;
+/// #[bench_all_depths]
+/// fn bench_tree(criterion: &mut Criterion, depth: usize) {
+///     // criterion.bench_function(&format!("tree_depth_{depth}"), ...);
+/// }
+/// ```
+/// with `depth_16` and `depth_30` features active will generate the following
+/// code next to the benchmark:
+/// ```no_run
+/// # struct Criterion;
+/// # fn bench_tree(criterion: &mut Criterion, depth: usize) {}
+/// fn bench_tree_all_depths(criterion: &mut Criterion) {
+///     bench_tree(criterion, 16);
+///     bench_tree(criterion, 30);
+/// }
+/// ```
+///
+/// Like for [`macro@test_all_depths`], benchmarks that create proofs are
+/// annotated with `#[bench_all_depths(prover)]`.
+#[proc_macro_attribute]
+pub fn bench_all_depths(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as TestArgs);
+    let fun = parse_macro_input!(item as syn::ItemFn);
+    let fun_name = &fun.sig.ident;
+    let vis = &fun.vis;
+
+    let criterion = match fun.sig.inputs.first() {
+        Some(syn::FnArg::Typed(arg)) if fun.sig.inputs.len() == 2 => &arg.ty,
+        _ => {
+            return syn::Error::new_spanned(
+                &fun.sig,
+                "`#[bench_all_depths]` expects a function taking the criterion and a depth",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    if args.is_async {
+        return syn::Error::new_spanned(&fun.sig, "benchmarks can't be async")
+            .to_compile_error()
+            .into();
+    }
+
+    let fun_name_all = format_ident!("{}_all_depths", fun_name);
+    let depths = args.depths();
+    quote! {
+        #fun
+
+        #vis fn #fun_name_all(criterion: #criterion) {
+            #(#fun_name(criterion, #depths);)*
+        }
+    }
+    .into()
+}
+
 #[derive(Debug)]
 struct ArrayForDepthsInput {
     replaced_ident: Ident,
@@ -143,10 +295,30 @@ impl Parse for ArrayForDepthsInput {
 /// and `depth_30` supported, will generate `[16 + 5, 30 + 5]`, and
 /// `array_for_depths!(|depth| concat!("foo", depth))` will generate
 /// `[concat!("foo", 16), concat!("foo", 30)]`.
+///
+/// The depths are substituted as `usize` literals and the array holds only
+/// the substituted expressions, so it can initialize a `const` or `static`
+/// whenever the expression is const.
 #[proc_macro]
 pub fn array_for_depths(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as ArrayForDepthsInput);
-    let items = get_supported_depths()
+    array_for(get_supported_depths(), &input)
+}
+
+/// Like [`array_for_depths!`], for the depths with a witness graph only, in
+/// the order of `get_prover_depth_index`.
+///
+/// For example, `array_for_prover_depths!(|depth| depth + 5)`, with
+/// `depth_16`, `depth_21` and `depth_30` supported, will generate
+/// `[16 + 5, 30 + 5]`.
+#[proc_macro]
+pub fn array_for_prover_depths(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as ArrayForDepthsInput);
+    array_for(get_prover_depths(), &input)
+}
+
+fn array_for(depths: &[usize], input: &ArrayForDepthsInput) -> TokenStream {
+    let items = depths
         .iter()
         .map(|depth| {
             let mut replacer = IdentReplacer(input.replaced_ident.clone(), parse_quote!(#depth));
@@ -158,3 +330,65 @@ pub fn array_for_depths(input: TokenStream) -> TokenStream {
     let array = quote! { [#(#items),*] };
     array.into()
 }
+
+#[derive(Debug)]
+struct ArrayForDepthIndicesInput {
+    index_ident: Ident,
+    depth_ident: Ident,
+    expr: syn::Expr,
+}
+
+impl Parse for ArrayForDepthIndicesInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![|]>()?;
+        let index_ident = input.parse::<Ident>()?;
+        input.parse::<Token![,]>()?;
+        let depth_ident = input.parse::<Ident>()?;
+        input.parse::<Token![|]>()?;
+        let expr = input.parse::<syn::Expr>()?;
+        Ok(ArrayForDepthIndicesInput {
+            index_ident,
+            depth_ident,
+            expr,
+        })
+    }
+}
+
+/// Like [`array_for_depths!`], with the index of each depth in the
+/// supported depths, as returned by `get_depth_index`, substituted as well.
+///
+/// For example, `array_for_depth_indices!(|i, depth| (i, depth))`, with only
+/// `depth_16` and `depth_30` supported, will generate `[(0, 16), (1, 30)]`.
+///
+/// Like for [`array_for_depths!`], the array is const when the expression
+/// is, e.g. to check a table against the depth indices at compile time:
+/// ```
+/// use semaphore_depth_config::{get_depth_index, get_supported_depth_count};
+/// use semaphore_depth_macros::array_for_depth_indices;
+///
+/// const fn has_index(index: usize, depth: usize) -> bool {
+///     matches!(get_depth_index(depth), Some(i) if i == index)
+/// }
+///
+/// const INDEXED: [bool; get_supported_depth_count()] =
+///     array_for_depth_indices!(|i, depth| has_index(i, depth));
+/// assert!(INDEXED.iter().all(|indexed| *indexed));
+/// ```
+#[proc_macro]
+pub fn array_for_depth_indices(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as ArrayForDepthIndicesInput);
+    let items = get_supported_depths()
+        .iter()
+        .enumerate()
+        .map(|(index, depth)| {
+            let mut expr = input.expr.clone();
+            IdentReplacer(input.index_ident.clone(), parse_quote!(#index))
+                .visit_expr_mut(&mut expr);
+            IdentReplacer(input.depth_ident.clone(), parse_quote!(#depth))
+                .visit_expr_mut(&mut expr);
+            expr
+        })
+        .collect::<Vec<_>>();
+    let array = quote! { [#(#items),*] };
+    array.into()
+}
@@ -0,0 +1,99 @@
+use std::collections::TryReserveError;
+use std::io;
+
+use thiserror::Error;
+
+use crate::mmap;
+
+pub type Result<T, E = StorageError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    /// Reading, writing, syncing or locking a file failed. Files locked by
+    /// another instance fail with [`io::ErrorKind::WouldBlock`].
+    #[error("{context}")]
+    Io {
+        context: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{context}")]
+    Map {
+        context: &'static str,
+        #[source]
+        source: mmap::Error,
+    },
+    #[error("failed to grow Vec")]
+    Alloc(#[from] TryReserveError),
+    /// The stored data, or the arguments the storage was opened with, are
+    /// not valid.
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Unsupported(&'static str),
+    /// An error of a [`KvStore`](crate::KvStore) backend.
+    #[error("key-value store failed")]
+    Store(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl StorageError {
+    /// An error for a file that is already locked.
+    pub(crate) fn locked(context: &'static str) -> Self {
+        Self::Io {
+            context,
+            source: io::ErrorKind::WouldBlock.into(),
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(source: io::Error) -> Self {
+        Self::Io {
+            context: "file operation failed",
+            source,
+        }
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+impl From<rocksdb::Error> for StorageError {
+    fn from(err: rocksdb::Error) -> Self {
+        Self::Store(Box::new(err))
+    }
+}
+
+#[cfg(feature = "sled")]
+impl From<sled::Error> for StorageError {
+    fn from(err: sled::Error) -> Self {
+        Self::Store(Box::new(err))
+    }
+}
+
+/// Adds a context to the errors of files and memory maps.
+pub(crate) trait Context<T> {
+    fn context(self, context: &'static str) -> Result<T>;
+}
+
+impl<T> Context<T> for std::result::Result<T, io::Error> {
+    fn context(self, context: &'static str) -> Result<T> {
+        self.map_err(|source| StorageError::Io { context, source })
+    }
+}
+
+impl<T> Context<T> for std::result::Result<T, mmap::Error> {
+    fn context(self, context: &'static str) -> Result<T> {
+        self.map_err(|source| StorageError::Map { context, source })
+    }
+}
+
+/// Returns [`StorageError::Invalid`] with the formatted message if the
+/// condition does not hold.
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::StorageError::Invalid(format!($($arg)+)));
+        }
+    };
+}
+
+pub(crate) use ensure;
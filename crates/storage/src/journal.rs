@@ -0,0 +1,518 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut, Range};
+use std::path::Path;
+
+use bytemuck::Pod;
+
+use crate::error::{ensure, Context};
+use crate::mmap::try_lock_exclusive;
+use crate::{GenericStorage, GrowthPolicy, Provenance, ProvenanceStorage, Result, StorageError};
+
+const WORD_SIZE: usize = std::mem::size_of::<usize>();
+const HEADER_SIZE: usize = 3 * WORD_SIZE;
+const BATCH_ID_SIZE: usize = std::mem::size_of::<u64>();
+
+const NO_BATCH_LOG: StorageError = StorageError::Unsupported("JournaledStorage has no batch log");
+
+/// Value of the first header word once an entry has been completely written
+const PENDING: usize = 1;
+
+/// Like [`PENDING`], for entries whose header is followed by a batch id
+const PENDING_BATCH: usize = 2;
+
+/// A write of consecutive leaves that was recorded in a journal but not
+/// committed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalEntry<T> {
+    pub first_leaf: usize,
+    pub leaves: Vec<T>,
+}
+
+/// A write-ahead journal of tree updates, see [`JournaledStorage`].
+///
+/// The trees find the journal of their storage with
+/// [`GenericStorage::journal`].
+pub trait Journal<T> {
+    /// Records that the leaves starting at `first_leaf` are about to be
+    /// written, before any of the writes happen.
+    fn journal_begin(&mut self, first_leaf: usize, leaves: &[T]) -> Result<()>;
+
+    /// Marks the last write recorded with [`Journal::journal_begin`] as
+    /// complete.
+    fn journal_commit(&mut self) -> Result<()>;
+
+    /// Returns the write recorded by [`Journal::journal_begin`] if it was
+    /// never committed.
+    fn journal_pending(&self) -> Result<Option<JournalEntry<T>>>;
+}
+
+/// A [`Journal`] that also records the ids of the batches it applied, so
+/// that each batch is applied exactly once.
+pub trait BatchJournal<T>: Journal<T> {
+    /// Like [`Journal::journal_begin`], also recording that the write applies
+    /// the batch with the given id. Committing the write marks the batch as
+    /// committed.
+    fn journal_begin_batch(&mut self, batch_id: u64, first_leaf: usize, leaves: &[T])
+        -> Result<()>;
+
+    /// Returns whether a write recorded with
+    /// [`BatchJournal::journal_begin_batch`] for the batch with the given id
+    /// was committed.
+    fn batch_committed(&self, batch_id: u64) -> Result<bool>;
+}
+
+/// Storage that records the leaves of every tree update in a write-ahead
+/// journal before the update touches the underlying storage.
+///
+/// If an update is interrupted, e.g. by a crash, the entry stays pending and
+/// is replayed when the tree is restored, so the storage never has to be
+/// rebuilt from partially written nodes.
+///
+/// The journal is written with regular file writes, which survive the
+/// process crashing but not necessarily the machine losing power.
+///
+/// With a batch log, see [`JournaledStorage::with_batch_log`], writes can be
+/// tagged with a batch id, which is recorded in the log when the write is
+/// committed or replayed.
+#[derive(Debug)]
+pub struct JournaledStorage<S> {
+    storage: S,
+    journal: File,
+    batches: Option<BatchLog>,
+    /// Batch id of the pending journal entry
+    pending_batch: Option<u64>,
+}
+
+/// Append-only file of committed batch ids.
+#[derive(Debug)]
+struct BatchLog {
+    file: File,
+    committed: HashSet<u64>,
+}
+
+impl<S> JournaledStorage<S> {
+    /// Wraps freshly created storage, discarding any existing journal
+    /// entries.
+    ///
+    /// See [`JournaledStorage::restore`] for the locking behavior.
+    pub fn create(storage: S, journal: File) -> Result<Self> {
+        let mut s = Self::restore(storage, journal)?;
+        s.journal.set_len(0)?;
+        s.pending_batch = None;
+        Ok(s)
+    }
+
+    /// Wraps freshly created storage with a journal at the given path,
+    /// discarding any existing journal entries.
+    pub fn create_from_path(storage: S, journal_path: impl AsRef<Path>) -> Result<Self> {
+        Self::create(storage, open(journal_path)?)
+    }
+
+    /// Wraps restored storage, keeping a pending journal entry to be replayed.
+    ///
+    /// An exclusive advisory lock is taken on the journal and held for the
+    /// lifetime of the JournaledStorage.
+    pub fn restore(storage: S, journal: File) -> Result<Self> {
+        try_lock_exclusive(&journal)
+            .context("Journal is already locked by another JournaledStorage")?;
+
+        let pending_batch = pending_batch(&journal)?;
+        Ok(Self {
+            storage,
+            journal,
+            batches: None,
+            pending_batch,
+        })
+    }
+
+    /// Wraps restored storage with the journal at the given path, keeping a
+    /// pending journal entry to be replayed.
+    pub fn restore_from_path(storage: S, journal_path: impl AsRef<Path>) -> Result<Self> {
+        Self::restore(storage, open(journal_path)?)
+    }
+
+    /// Records the ids of committed batches in the given file, loading the
+    /// ids already in it.
+    ///
+    /// The batch log must always be used with the same journal and storage.
+    pub fn with_batch_log(mut self, mut file: File) -> Result<Self> {
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut bytes)
+            .context("Failed to read batch log")?;
+
+        // A trailing partial id was never committed
+        let committed = bytes
+            .chunks_exact(BATCH_ID_SIZE)
+            .map(|id| u64::from_ne_bytes(id.try_into().unwrap()))
+            .collect();
+        let complete = bytes.len() - bytes.len() % BATCH_ID_SIZE;
+        file.set_len(complete as u64)?;
+        file.seek(SeekFrom::End(0))?;
+
+        self.batches = Some(BatchLog { file, committed });
+        Ok(self)
+    }
+
+    /// Records the ids of committed batches in the file at the given path,
+    /// see [`JournaledStorage::with_batch_log`].
+    pub fn with_batch_log_path(self, path: impl AsRef<Path>) -> Result<Self> {
+        let file = open(path)?;
+        self.with_batch_log(file)
+    }
+
+    /// Returns the underlying storage, bypassing the journal.
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+
+    fn write_entry<T: Pod>(
+        &mut self,
+        batch_id: Option<u64>,
+        first_leaf: usize,
+        leaves: &[T],
+    ) -> Result<()> {
+        // The entry only becomes pending once it has been written in full
+        self.pending_batch = None;
+        let mut entry =
+            Vec::with_capacity(HEADER_SIZE + BATCH_ID_SIZE + std::mem::size_of_val(leaves));
+        entry.extend_from_slice(&0_usize.to_ne_bytes());
+        entry.extend_from_slice(&first_leaf.to_ne_bytes());
+        entry.extend_from_slice(&leaves.len().to_ne_bytes());
+        if let Some(batch_id) = batch_id {
+            entry.extend_from_slice(&batch_id.to_ne_bytes());
+        }
+        entry.extend_from_slice(bytemuck::cast_slice(leaves));
+
+        self.journal.seek(SeekFrom::Start(0))?;
+        self.journal
+            .write_all(&entry)
+            .context("Failed to write journal entry")?;
+        let state = if batch_id.is_some() {
+            PENDING_BATCH
+        } else {
+            PENDING
+        };
+        self.journal.seek(SeekFrom::Start(0))?;
+        self.journal
+            .write_all(&state.to_ne_bytes())
+            .context("Failed to mark journal entry as pending")?;
+        self.pending_batch = batch_id;
+        Ok(())
+    }
+}
+
+/// Reads the batch id of the pending entry of a journal, if it has one.
+fn pending_batch(mut journal: &File) -> Result<Option<u64>> {
+    let mut header = [0; HEADER_SIZE + BATCH_ID_SIZE];
+    journal.seek(SeekFrom::Start(0))?;
+    let len = journal.read(&mut header)?;
+    let state =
+        (len >= WORD_SIZE).then(|| usize::from_ne_bytes(header[..WORD_SIZE].try_into().unwrap()));
+    if state != Some(PENDING_BATCH) {
+        return Ok(None);
+    }
+    ensure!(len == header.len(), "Journal entry is truncated");
+    let id = header[HEADER_SIZE..].try_into().unwrap();
+    Ok(Some(u64::from_ne_bytes(id)))
+}
+
+fn open(path: impl AsRef<Path>) -> Result<File> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?)
+}
+
+impl<T, S> GenericStorage<T> for JournaledStorage<S>
+where
+    T: Pod + Send + Sync,
+    S: GenericStorage<T>,
+{
+    fn push(&mut self, value: T) {
+        self.storage.push(value);
+    }
+
+    fn extend_from_slice(&mut self, slice: &[T]) {
+        self.storage.extend_from_slice(slice);
+    }
+
+    fn clear(&mut self) {
+        self.storage.clear();
+    }
+
+    fn try_push(&mut self, value: T) -> Result<()> {
+        self.storage.try_push(value)
+    }
+
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<()> {
+        self.storage.try_extend(iter)
+    }
+
+    fn try_extend_from_slice(&mut self, slice: &[T]) -> Result<()> {
+        self.storage.try_extend_from_slice(slice)
+    }
+
+    fn reserve(&mut self, additional: usize) -> Result<()> {
+        self.storage.reserve(additional)
+    }
+
+    fn growth_policy(&self) -> GrowthPolicy {
+        self.storage.growth_policy()
+    }
+
+    fn mark_changed(&mut self, range: Range<usize>) {
+        self.storage.mark_changed(range);
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.storage.flush()?;
+        if let Some(batches) = &self.batches {
+            batches
+                .file
+                .sync_data()
+                .context("Failed to sync batch log")?;
+        }
+        self.journal.sync_data().context("Failed to sync journal")
+    }
+
+    fn journal(&mut self) -> Option<&mut dyn Journal<T>> {
+        Some(self)
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<()> {
+        self.storage.truncate(len)
+    }
+}
+
+impl<T, S> Journal<T> for JournaledStorage<S>
+where
+    T: Pod + Send + Sync,
+    S: GenericStorage<T>,
+{
+    fn journal_begin(&mut self, first_leaf: usize, leaves: &[T]) -> Result<()> {
+        self.write_entry(None, first_leaf, leaves)
+    }
+
+    fn journal_commit(&mut self) -> Result<()> {
+        if let Some(batch_id) = self.pending_batch {
+            let Some(batches) = self.batches.as_mut() else {
+                return Err(NO_BATCH_LOG);
+            };
+            // Committing the same batch again, e.g. after a crash right after
+            // it was logged, appends a duplicate id, which is harmless
+            batches
+                .file
+                .write_all(&batch_id.to_ne_bytes())
+                .context("Failed to record batch")?;
+            batches.committed.insert(batch_id);
+        }
+        self.journal.set_len(0).context("Failed to clear journal")?;
+        self.pending_batch = None;
+        Ok(())
+    }
+
+    fn journal_pending(&self) -> Result<Option<JournalEntry<T>>> {
+        let mut bytes = Vec::new();
+        (&self.journal).seek(SeekFrom::Start(0))?;
+        (&self.journal).read_to_end(&mut bytes)?;
+
+        if bytes.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let header: Vec<usize> = bytes[..HEADER_SIZE]
+            .chunks_exact(WORD_SIZE)
+            .map(|word| usize::from_ne_bytes(word.try_into().unwrap()))
+            .collect();
+        let body_start = match header[0] {
+            PENDING => HEADER_SIZE,
+            PENDING_BATCH => HEADER_SIZE + BATCH_ID_SIZE,
+            _ => return Ok(None),
+        };
+        ensure!(bytes.len() >= body_start, "Journal entry is truncated");
+
+        let (first_leaf, count) = (header[1], header[2]);
+        let body = &bytes[body_start..];
+        ensure!(
+            count
+                .checked_mul(std::mem::size_of::<T>())
+                .is_some_and(|len| len <= body.len()),
+            "Journal entry is truncated"
+        );
+        let leaves = body
+            .chunks_exact(std::mem::size_of::<T>())
+            .take(count)
+            .map(bytemuck::pod_read_unaligned)
+            .collect();
+
+        Ok(Some(JournalEntry { first_leaf, leaves }))
+    }
+}
+
+impl<T, S> BatchJournal<T> for JournaledStorage<S>
+where
+    T: Pod + Send + Sync,
+    S: GenericStorage<T>,
+{
+    fn journal_begin_batch(
+        &mut self,
+        batch_id: u64,
+        first_leaf: usize,
+        leaves: &[T],
+    ) -> Result<()> {
+        if self.batches.is_none() {
+            return Err(NO_BATCH_LOG);
+        }
+        self.write_entry(Some(batch_id), first_leaf, leaves)
+    }
+
+    fn batch_committed(&self, batch_id: u64) -> Result<bool> {
+        let Some(batches) = &self.batches else {
+            return Err(NO_BATCH_LOG);
+        };
+        Ok(batches.committed.contains(&batch_id))
+    }
+}
+
+impl<S: ProvenanceStorage> ProvenanceStorage for JournaledStorage<S> {
+    fn provenance(&self) -> Result<Option<Provenance>> {
+        self.storage.provenance()
+    }
+
+    fn set_provenance(&mut self, provenance: &Provenance) -> Result<()> {
+        self.storage.set_provenance(provenance)
+    }
+}
+
+impl<T, S: Extend<T>> Extend<T> for JournaledStorage<S> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.storage.extend(iter);
+    }
+}
+
+impl<S: Deref> Deref for JournaledStorage<S> {
+    type Target = S::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.storage
+    }
+}
+
+impl<S: DerefMut> DerefMut for JournaledStorage<S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_roundtrip() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let mut storage = JournaledStorage::create(vec![0_u64; 4], f.reopen().unwrap()).unwrap();
+        assert_eq!(storage.journal_pending().unwrap(), None);
+
+        storage.journal_begin(3, &[7, 8, 9]).unwrap();
+        let expected = JournalEntry {
+            first_leaf: 3,
+            leaves: vec![7, 8, 9],
+        };
+        assert_eq!(storage.journal_pending().unwrap(), Some(expected.clone()));
+
+        // A pending entry survives reopening the journal
+        drop(storage);
+        let mut storage = JournaledStorage::restore(vec![0_u64; 4], f.reopen().unwrap()).unwrap();
+        assert_eq!(storage.journal_pending().unwrap(), Some(expected));
+
+        storage.journal_commit().unwrap();
+        assert_eq!(storage.journal_pending().unwrap(), None);
+
+        // Creating discards pending entries
+        storage.journal_begin(0, &[1]).unwrap();
+        drop(storage);
+        let storage = JournaledStorage::create(vec![0_u64; 4], f.reopen().unwrap()).unwrap();
+        assert_eq!(storage.journal_pending().unwrap(), None);
+    }
+
+    #[test]
+    fn test_journal_incomplete_entry() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let mut storage = JournaledStorage::create(vec![0_u64; 4], f.reopen().unwrap()).unwrap();
+        storage.journal_begin(1, &[5, 6]).unwrap();
+
+        // An entry interrupted before it was marked pending is ignored
+        let mut file = f.reopen().unwrap();
+        file.write_all(&0_usize.to_ne_bytes()).unwrap();
+        assert_eq!(storage.journal_pending().unwrap(), None);
+
+        // A pending entry missing leaves is rejected
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&PENDING.to_ne_bytes()).unwrap();
+        file.set_len((HEADER_SIZE + 8) as u64).unwrap();
+        let _ = storage
+            .journal_pending()
+            .expect_err("entry should be truncated");
+    }
+
+    #[test]
+    fn test_batch_log() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let log = tempfile::NamedTempFile::new().unwrap();
+        let mut storage = JournaledStorage::create(vec![0_u64; 4], f.reopen().unwrap()).unwrap();
+        let _ = storage
+            .journal_begin_batch(1, 0, &[1])
+            .expect_err("storage should have no batch log");
+
+        let mut storage = storage.with_batch_log(log.reopen().unwrap()).unwrap();
+        storage.journal_begin_batch(7, 2, &[5, 6]).unwrap();
+        let expected = JournalEntry {
+            first_leaf: 2,
+            leaves: vec![5, 6],
+        };
+        assert_eq!(storage.journal_pending().unwrap(), Some(expected));
+        assert!(!storage.batch_committed(7).unwrap());
+
+        // The batch of a pending entry is committed after restoring
+        drop(storage);
+        let mut storage = JournaledStorage::restore(vec![0_u64; 4], f.reopen().unwrap())
+            .unwrap()
+            .with_batch_log(log.reopen().unwrap())
+            .unwrap();
+        assert!(!storage.batch_committed(7).unwrap());
+        storage.journal_commit().unwrap();
+        assert!(storage.batch_committed(7).unwrap());
+
+        // Writes without a batch id don't record one
+        storage.journal_begin(0, &[1]).unwrap();
+        storage.journal_commit().unwrap();
+        storage.journal_begin_batch(8, 0, &[1]).unwrap();
+        storage.journal_commit().unwrap();
+
+        // Committed ids survive reopening, a partially written id is dropped
+        drop(storage);
+        let mut file = log.reopen().unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(&[9]).unwrap();
+        let storage = JournaledStorage::create(vec![0_u64; 4], f.reopen().unwrap())
+            .unwrap()
+            .with_batch_log(log.reopen().unwrap())
+            .unwrap();
+        assert!(storage.batch_committed(7).unwrap());
+        assert!(storage.batch_committed(8).unwrap());
+        assert_eq!(log.as_file().metadata().unwrap().len(), 16);
+    }
+
+    #[test]
+    fn test_journal_lock() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let _storage = JournaledStorage::create(Vec::<u64>::new(), f.reopen().unwrap()).unwrap();
+        let _ = JournaledStorage::restore(Vec::<u64>::new(), f.reopen().unwrap())
+            .expect_err("journal should be locked");
+    }
+}
@@ -0,0 +1,628 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::{Arc, Mutex, RwLock};
+
+use bytemuck::Pod;
+
+use crate::error::ensure;
+use crate::{GenericStorage, Result, StorageError};
+
+/// Number of elements per page, unless created with
+/// [`KvStorage::create_with_page_len`].
+pub const DEFAULT_PAGE_LEN: usize = 1024;
+
+const META_KEY: &[u8] = b"meta";
+const PAGE_KEY: &[u8] = b"page";
+const META_SIZE: usize = 3 * std::mem::size_of::<u64>();
+
+/// A key-value store that can hold the pages of a [`KvStorage`].
+///
+/// Implemented for [`MemoryKv`], for sled trees and databases with the `sled`
+/// feature and for RocksDB databases with the `rocksdb` feature.
+pub trait KvStore: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Applies all writes of the batch atomically, and durably if the store
+    /// is persistent.
+    fn write(&self, batch: KvBatch) -> Result<()>;
+}
+
+/// Writes applied together by [`KvStore::write`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KvBatch {
+    pub puts: Vec<(Vec<u8>, Vec<u8>)>,
+    pub deletes: Vec<Vec<u8>>,
+}
+
+impl<K: KvStore + ?Sized> KvStore for Arc<K> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        (**self).get(key)
+    }
+
+    fn write(&self, batch: KvBatch) -> Result<()> {
+        (**self).write(batch)
+    }
+}
+
+/// An in-memory key-value store. Clones share the same entries.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryKv {
+    entries: Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl MemoryKv {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of entries in the store.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.read().expect("lock poisoned").len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl KvStore for MemoryKv {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .entries
+            .read()
+            .expect("lock poisoned")
+            .get(key)
+            .cloned())
+    }
+
+    fn write(&self, batch: KvBatch) -> Result<()> {
+        let mut entries = self.entries.write().expect("lock poisoned");
+        for key in batch.deletes {
+            entries.remove(&key);
+        }
+        entries.extend(batch.puts);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+impl KvStore for rocksdb::DB {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(rocksdb::DB::get(self, key)?)
+    }
+
+    fn write(&self, batch: KvBatch) -> Result<()> {
+        let mut rocks_batch = rocksdb::WriteBatch::default();
+        for key in batch.deletes {
+            rocks_batch.delete(key);
+        }
+        for (key, value) in batch.puts {
+            rocks_batch.put(key, value);
+        }
+        let mut options = rocksdb::WriteOptions::default();
+        options.set_sync(true);
+        self.write_opt(rocks_batch, &options)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sled")]
+impl KvStore for sled::Tree {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(sled::Tree::get(self, key)?.map(|value| value.to_vec()))
+    }
+
+    fn write(&self, batch: KvBatch) -> Result<()> {
+        let mut sled_batch = sled::Batch::default();
+        for key in batch.deletes {
+            sled_batch.remove(key);
+        }
+        for (key, value) in batch.puts {
+            sled_batch.insert(key, value);
+        }
+        self.apply_batch(sled_batch)?;
+        self.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sled")]
+impl KvStore for sled::Db {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        KvStore::get(&**self, key)
+    }
+
+    fn write(&self, batch: KvBatch) -> Result<()> {
+        KvStore::write(&**self, batch)
+    }
+}
+
+/// Storage kept in memory and written through to a key-value store in fixed
+/// size pages.
+///
+/// The whole storage is held in memory, so a tree needs as much memory as it
+/// would in a `Vec`. The store only adds durability and replication, it
+/// doesn't let trees grow past the available memory.
+///
+/// Reads and writes go to the in-memory copy. [`GenericStorage::flush`]
+/// writes the pages that changed since the last flush, along with the
+/// length, in one atomic batch, so the store always holds the storage as of
+/// some flush. Restoring reads all pages back into memory.
+///
+/// Pages changed by pushing, extending and truncating are tracked as the
+/// writes happen. Values changed through [`DerefMut`] are only written if
+/// they are recorded with [`GenericStorage::mark_changed`], as the trees do.
+///
+/// All keys start with the prefix the storage was created with, so one store
+/// can hold several trees, as long as none of their prefixes is a prefix of
+/// another.
+pub struct KvStorage<T, K> {
+    data: Vec<T>,
+    store: K,
+    prefix: Vec<u8>,
+    page_len: usize,
+    written: Mutex<Written>,
+}
+
+/// What changed since the last flush.
+#[derive(Default)]
+struct Written {
+    /// Length as of the last flush
+    len: usize,
+    /// Pages changed since the last flush
+    dirty: BTreeSet<usize>,
+}
+
+impl<T: Pod, K: KvStore> KvStorage<T, K> {
+    /// Creates empty storage in `store`, with pages of
+    /// [`DEFAULT_PAGE_LEN`] elements. Any existing data under `prefix` is
+    /// deleted.
+    pub fn create(store: K, prefix: impl Into<Vec<u8>>) -> Result<Self> {
+        Self::create_with_page_len(store, prefix, DEFAULT_PAGE_LEN)
+    }
+
+    /// Like [`KvStorage::create`], with pages of `page_len` elements.
+    pub fn create_with_page_len(
+        store: K,
+        prefix: impl Into<Vec<u8>>,
+        page_len: usize,
+    ) -> Result<Self> {
+        assert!(std::mem::size_of::<T>() != 0);
+        ensure!(page_len > 0, "pages must hold at least one element");
+        let prefix = prefix.into();
+
+        let mut batch = KvBatch::default();
+        if let Some(meta) = read_meta(&store, &prefix)? {
+            let pages = meta.len.div_ceil(meta.page_len);
+            batch
+                .deletes
+                .extend((0..pages).map(|page| page_key(&prefix, page)));
+        }
+        batch.puts.push((
+            meta_key(&prefix),
+            Meta {
+                len: 0,
+                type_tag: std::mem::size_of::<T>(),
+                page_len,
+            }
+            .encode(),
+        ));
+        store.write(batch)?;
+
+        Ok(Self::new(store, prefix, page_len, vec![]))
+    }
+
+    /// Restores storage from `store`. Storage that was never created is
+    /// restored empty, with pages of [`DEFAULT_PAGE_LEN`] elements.
+    ///
+    /// Fails if the storage was created for elements of a different size
+    /// than `T`, or if pages are missing.
+    pub fn restore(store: K, prefix: impl Into<Vec<u8>>) -> Result<Self> {
+        assert!(std::mem::size_of::<T>() != 0);
+        let prefix = prefix.into();
+
+        let Some(meta) = read_meta(&store, &prefix)? else {
+            return Ok(Self::new(store, prefix, DEFAULT_PAGE_LEN, vec![]));
+        };
+        ensure!(
+            meta.type_tag == std::mem::size_of::<T>(),
+            "storage was created for elements of size {}, not {}",
+            meta.type_tag,
+            std::mem::size_of::<T>()
+        );
+
+        let mut data = vec![T::zeroed(); meta.len];
+        for (page, chunk) in data.chunks_mut(meta.page_len).enumerate() {
+            let bytes = store
+                .get(&page_key(&prefix, page))?
+                .ok_or_else(|| StorageError::Invalid(format!("page {page} is missing")))?;
+            let chunk: &mut [u8] = bytemuck::cast_slice_mut(chunk);
+            ensure!(
+                bytes.len() == chunk.len(),
+                "page {page} has {} bytes, expected {}",
+                bytes.len(),
+                chunk.len()
+            );
+            chunk.copy_from_slice(&bytes);
+        }
+
+        let mut s = Self::new(store, prefix, meta.page_len, data);
+        s.written.get_mut().expect("lock poisoned").len = s.data.len();
+        Ok(s)
+    }
+
+    /// Number of elements per page.
+    #[must_use]
+    pub const fn page_len(&self) -> usize {
+        self.page_len
+    }
+
+    /// The underlying key-value store.
+    #[must_use]
+    pub const fn store(&self) -> &K {
+        &self.store
+    }
+
+    fn new(store: K, prefix: Vec<u8>, page_len: usize, data: Vec<T>) -> Self {
+        Self {
+            data,
+            store,
+            prefix,
+            page_len,
+            written: Mutex::new(Written::default()),
+        }
+    }
+
+    /// Marks the pages holding the values in `range` as changed.
+    fn mark_pages(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let pages = range.start / self.page_len..=(range.end - 1) / self.page_len;
+        self.written
+            .get_mut()
+            .expect("lock poisoned")
+            .dirty
+            .extend(pages);
+    }
+
+    fn write_changes(&self) -> Result<()> {
+        let mut written = self.written.lock().expect("lock poisoned");
+        if written.dirty.is_empty() && written.len == self.data.len() {
+            return Ok(());
+        }
+
+        let pages = self.data.len().div_ceil(self.page_len);
+        let mut batch = KvBatch::default();
+        for &page in written.dirty.range(..pages) {
+            let start = page * self.page_len;
+            let end = (start + self.page_len).min(self.data.len());
+            let bytes: &[u8] = bytemuck::cast_slice(&self.data[start..end]);
+            batch
+                .puts
+                .push((page_key(&self.prefix, page), bytes.to_vec()));
+        }
+        batch.deletes.extend(
+            (pages..written.len.div_ceil(self.page_len)).map(|page| page_key(&self.prefix, page)),
+        );
+        batch.puts.push((
+            meta_key(&self.prefix),
+            Meta {
+                len: self.data.len(),
+                type_tag: std::mem::size_of::<T>(),
+                page_len: self.page_len,
+            }
+            .encode(),
+        ));
+
+        self.store.write(batch)?;
+        written.len = self.data.len();
+        written.dirty.clear();
+        Ok(())
+    }
+}
+
+struct Meta {
+    len: usize,
+    type_tag: usize,
+    page_len: usize,
+}
+
+impl Meta {
+    fn encode(&self) -> Vec<u8> {
+        [self.len, self.type_tag, self.page_len]
+            .iter()
+            .flat_map(|&word| (word as u64).to_le_bytes())
+            .collect()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        ensure!(bytes.len() == META_SIZE, "invalid storage metadata");
+        let word = |i: usize| {
+            let mut word = [0; 8];
+            word.copy_from_slice(&bytes[i * 8..(i + 1) * 8]);
+            usize::try_from(u64::from_le_bytes(word))
+                .map_err(|_| StorageError::Invalid("invalid storage metadata".into()))
+        };
+        let meta = Self {
+            len: word(0)?,
+            type_tag: word(1)?,
+            page_len: word(2)?,
+        };
+        ensure!(meta.page_len > 0, "invalid storage metadata");
+        Ok(meta)
+    }
+}
+
+fn read_meta(store: &impl KvStore, prefix: &[u8]) -> Result<Option<Meta>> {
+    store
+        .get(&meta_key(prefix))?
+        .map(|bytes| Meta::decode(&bytes))
+        .transpose()
+}
+
+fn meta_key(prefix: &[u8]) -> Vec<u8> {
+    [prefix, META_KEY].concat()
+}
+
+/// Page keys sort by page index, for stores that iterate in key order.
+fn page_key(prefix: &[u8], page: usize) -> Vec<u8> {
+    [prefix, PAGE_KEY, &(page as u64).to_be_bytes()].concat()
+}
+
+impl<T: Send + Sync + Pod, K: KvStore> GenericStorage<T> for KvStorage<T, K> {
+    fn push(&mut self, value: T) {
+        self.data.push(value);
+        self.mark_pages(self.data.len() - 1..self.data.len());
+    }
+
+    fn extend_from_slice(&mut self, slice: &[T]) {
+        let start = self.data.len();
+        self.data.extend_from_slice(slice);
+        self.mark_pages(start..self.data.len());
+    }
+
+    /// The pages are deleted from the store with the next flush.
+    fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    fn try_push(&mut self, value: T) -> Result<()> {
+        self.data.try_push(value)?;
+        self.mark_pages(self.data.len() - 1..self.data.len());
+        Ok(())
+    }
+
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<()> {
+        let start = self.data.len();
+        self.data.try_extend(iter)?;
+        self.mark_pages(start..self.data.len());
+        Ok(())
+    }
+
+    fn try_extend_from_slice(&mut self, slice: &[T]) -> Result<()> {
+        let start = self.data.len();
+        self.data.try_extend_from_slice(slice)?;
+        self.mark_pages(start..self.data.len());
+        Ok(())
+    }
+
+    fn mark_changed(&mut self, range: Range<usize>) {
+        self.mark_pages(range);
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.write_changes()
+    }
+
+    /// The pages past `len` are deleted from the store with the next flush.
+    fn truncate(&mut self, len: usize) -> Result<()> {
+        if len < self.data.len() && len % self.page_len != 0 {
+            // The last page is shorter now
+            self.mark_pages(len - 1..len);
+        }
+        self.data.truncate(len);
+        self.data.shrink_to_fit();
+        Ok(())
+    }
+}
+
+impl<T: Pod, K: KvStore> Extend<T> for KvStorage<T, K> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let start = self.data.len();
+        self.data.extend(iter);
+        self.mark_pages(start..self.data.len());
+    }
+}
+
+impl<T, K> Deref for KvStorage<T, K> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl<T, K> DerefMut for KvStorage<T, K> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.data
+    }
+}
+
+impl<T: std::fmt::Debug, K> std::fmt::Debug for KvStorage<T, K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KvStorage")
+            .field("data", &self.data)
+            .field("prefix", &self.prefix)
+            .field("page_len", &self.page_len)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flush_and_restore() {
+        let store = MemoryKv::new();
+        let mut storage: KvStorage<u64, _> =
+            KvStorage::create_with_page_len(store.clone(), "tree/", 4).unwrap();
+        storage.extend_from_slice(&(0..10).collect::<Vec<_>>());
+
+        // Nothing but the metadata is written before flushing
+        assert_eq!(store.len(), 1);
+        storage.flush().unwrap();
+        assert_eq!(store.len(), 4);
+
+        storage[5] = 42;
+        storage.mark_changed(5..6);
+        storage.flush().unwrap();
+        let restored: KvStorage<u64, _> = KvStorage::restore(store.clone(), "tree/").unwrap();
+        assert_eq!(restored.page_len(), 4);
+        assert_eq!(&restored[..], &storage[..]);
+
+        storage.clear();
+        storage.push(7);
+        storage.flush().unwrap();
+        assert_eq!(store.len(), 2);
+        let restored: KvStorage<u64, _> = KvStorage::restore(store.clone(), "tree/").unwrap();
+        assert_eq!(&restored[..], &[7]);
+    }
+
+    #[test]
+    fn test_flush_writes_changed_pages() {
+        let store = Arc::new(CountingKv::default());
+        let mut storage: KvStorage<u64, _> =
+            KvStorage::create_with_page_len(store.clone(), "", 4).unwrap();
+        storage.extend_from_slice(&[0; 16]);
+        storage.flush().unwrap();
+        assert_eq!(store.last_puts(), 5);
+
+        storage[9] = 1;
+        storage.mark_changed(9..10);
+        storage.flush().unwrap();
+        // The changed page and the metadata
+        assert_eq!(store.last_puts(), 2);
+
+        let writes = store.writes();
+        storage.flush().unwrap();
+        assert_eq!(store.writes(), writes);
+
+        // Changes that aren't recorded are not written
+        storage[0] = 1;
+        storage.flush().unwrap();
+        assert_eq!(store.writes(), writes);
+
+        storage.push(2);
+        storage[3] = 1;
+        storage.mark_changed(3..4);
+        storage.flush().unwrap();
+        // The new page, the recorded page and the metadata
+        assert_eq!(store.last_puts(), 3);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let store = MemoryKv::new();
+        let mut storage: KvStorage<u64, _> =
+            KvStorage::create_with_page_len(store.clone(), "", 4).unwrap();
+        storage.extend_from_slice(&(0..10).collect::<Vec<_>>());
+        storage.flush().unwrap();
+
+        // Truncating within a page rewrites it and deletes the pages past it
+        storage.truncate(6).unwrap();
+        storage.flush().unwrap();
+        assert_eq!(store.len(), 3);
+        let restored: KvStorage<u64, _> = KvStorage::restore(store.clone(), "").unwrap();
+        assert_eq!(&restored[..], &[0, 1, 2, 3, 4, 5]);
+
+        storage.truncate(4).unwrap();
+        storage.extend_from_slice(&[7, 8]);
+        storage.flush().unwrap();
+        let restored: KvStorage<u64, _> = KvStorage::restore(store, "").unwrap();
+        assert_eq!(&restored[..], &[0, 1, 2, 3, 7, 8]);
+    }
+
+    #[test]
+    fn test_prefixes() {
+        let store = MemoryKv::new();
+        let mut a: KvStorage<u32, _> = KvStorage::create(store.clone(), "a").unwrap();
+        let mut b: KvStorage<u32, _> = KvStorage::create(store.clone(), "b").unwrap();
+        a.extend_from_slice(&[1, 2, 3]);
+        b.push(4);
+        a.flush().unwrap();
+        b.flush().unwrap();
+
+        let a: KvStorage<u32, _> = KvStorage::restore(store.clone(), "a").unwrap();
+        assert_eq!(&a[..], &[1, 2, 3]);
+        let b: KvStorage<u32, _> = KvStorage::create(store.clone(), "b").unwrap();
+        assert!(b.is_empty());
+        let b: KvStorage<u32, _> = KvStorage::restore(store.clone(), "b").unwrap();
+        assert!(b.is_empty());
+
+        let empty: KvStorage<u32, _> = KvStorage::restore(store.clone(), "c").unwrap();
+        assert!(empty.is_empty());
+        assert!(KvStorage::<u64, _>::restore(store, "a").is_err());
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("trees").unwrap();
+        let mut storage: KvStorage<u64, _> =
+            KvStorage::create_with_page_len(tree.clone(), "tree/", 3).unwrap();
+        storage.extend_from_slice(&[1, 2, 3, 4, 5]);
+        storage.flush().unwrap();
+
+        let restored: KvStorage<u64, _> = KvStorage::restore(tree, "tree/").unwrap();
+        assert_eq!(&restored[..], &[1, 2, 3, 4, 5]);
+    }
+
+    #[cfg(feature = "rocksdb")]
+    #[test]
+    fn test_rocksdb() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = rocksdb::DB::open_default(dir.path()).unwrap();
+        let mut storage: KvStorage<u64, _> =
+            KvStorage::create_with_page_len(db, "tree/", 3).unwrap();
+        storage.extend_from_slice(&[1, 2, 3, 4, 5]);
+        storage.flush().unwrap();
+        drop(storage);
+
+        let db = rocksdb::DB::open_default(dir.path()).unwrap();
+        let restored: KvStorage<u64, _> = KvStorage::restore(db, "tree/").unwrap();
+        assert_eq!(&restored[..], &[1, 2, 3, 4, 5]);
+    }
+
+    #[derive(Default)]
+    struct CountingKv {
+        store: MemoryKv,
+        writes: Mutex<Vec<usize>>,
+    }
+
+    impl CountingKv {
+        fn writes(&self) -> usize {
+            self.writes.lock().unwrap().len()
+        }
+
+        fn last_puts(&self) -> usize {
+            *self.writes.lock().unwrap().last().unwrap()
+        }
+    }
+
+    impl KvStore for CountingKv {
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            self.store.get(key)
+        }
+
+        fn write(&self, batch: KvBatch) -> Result<()> {
+            self.writes.lock().unwrap().push(batch.puts.len());
+            self.store.write(batch)
+        }
+    }
+}
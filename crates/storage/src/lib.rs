@@ -1,9 +1,18 @@
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Range};
 
+mod error;
+mod journal;
+mod kv;
+pub mod mmap;
 mod mmap_vec;
+mod provenance;
 
 use bytemuck::Pod;
-pub use mmap_vec::MmapVec;
+pub use error::{Result, StorageError};
+pub use journal::{BatchJournal, Journal, JournalEntry, JournaledStorage};
+pub use kv::{KvBatch, KvStorage, KvStore, MemoryKv, DEFAULT_PAGE_LEN};
+pub use mmap_vec::{GrowthPolicy, MmapVec, MmapVecReader};
+pub use provenance::{Provenance, ProvenanceStorage};
 
 pub trait GenericStorage<T>:
     Deref<Target = [T]> + DerefMut<Target = [T]> + Extend<T> + Send + Sync
@@ -13,6 +22,68 @@ pub trait GenericStorage<T>:
     fn extend_from_slice(&mut self, slice: &[T]);
 
     fn clear(&mut self);
+
+    /// Fallible version of [`GenericStorage::push`], for storage that can fail
+    /// to grow (e.g. a full disk). By default it pushes and never fails.
+    fn try_push(&mut self, value: T) -> Result<()> {
+        self.push(value);
+        Ok(())
+    }
+
+    /// Fallible version of [`Extend::extend`]. On error the storage is left
+    /// unchanged. By default it extends and never fails.
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<()> {
+        self.extend(iter);
+        Ok(())
+    }
+
+    /// Fallible version of [`GenericStorage::extend_from_slice`]. On error the
+    /// storage is left unchanged. By default it extends and never fails.
+    fn try_extend_from_slice(&mut self, slice: &[T]) -> Result<()> {
+        self.extend_from_slice(slice);
+        Ok(())
+    }
+
+    /// Makes room for at least `additional` more values, so they can be
+    /// pushed without growing the storage. Storage without a capacity does
+    /// nothing.
+    fn reserve(&mut self, _additional: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns how the storage grows once it runs out of capacity.
+    fn growth_policy(&self) -> GrowthPolicy {
+        GrowthPolicy::Doubling
+    }
+
+    /// Records that the values in `range` were changed through [`DerefMut`].
+    /// Storage that only writes the changed values on flush, like
+    /// [`KvStorage`], needs this to find them. Other storage does nothing.
+    ///
+    /// Values added or removed by the other methods don't need to be
+    /// recorded.
+    fn mark_changed(&mut self, _range: Range<usize>) {}
+
+    /// Writes pending changes to durable storage, if there is any. In-memory
+    /// storage does nothing.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the journal of the storage, if it has one. The trees record
+    /// every write in it, see [`Journal`].
+    fn journal(&mut self) -> Option<&mut dyn Journal<T>> {
+        None
+    }
+
+    /// Shortens the storage to its first `len` values and releases the memory
+    /// or file space past them. A `len` not less than the current length only
+    /// releases unused capacity.
+    ///
+    /// Storage that can't be shrunk returns an error.
+    fn truncate(&mut self, _len: usize) -> Result<()> {
+        Err(StorageError::Unsupported("Storage can not be truncated"))
+    }
 }
 
 impl<T: Send + Sync + Copy> GenericStorage<T> for Vec<T> {
@@ -27,6 +98,35 @@ impl<T: Send + Sync + Copy> GenericStorage<T> for Vec<T> {
     fn clear(&mut self) {
         self.clear();
     }
+
+    fn try_push(&mut self, value: T) -> Result<()> {
+        self.try_reserve(1)?;
+        self.push(value);
+        Ok(())
+    }
+
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<()> {
+        let iter = iter.into_iter();
+        self.try_reserve(iter.size_hint().0)?;
+        Extend::extend(self, iter);
+        Ok(())
+    }
+
+    fn try_extend_from_slice(&mut self, slice: &[T]) -> Result<()> {
+        self.try_reserve(slice.len())?;
+        Vec::extend_from_slice(self, slice);
+        Ok(())
+    }
+
+    fn reserve(&mut self, additional: usize) -> Result<()> {
+        Ok(self.try_reserve_exact(additional)?)
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<()> {
+        Vec::truncate(self, len);
+        self.shrink_to_fit();
+        Ok(())
+    }
 }
 
 impl<T: Send + Sync + Pod> GenericStorage<T> for MmapVec<T> {
@@ -41,4 +141,42 @@ impl<T: Send + Sync + Pod> GenericStorage<T> for MmapVec<T> {
     fn clear(&mut self) {
         self.clear();
     }
+
+    fn try_push(&mut self, value: T) -> Result<()> {
+        self.try_push(value)
+    }
+
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<()> {
+        self.try_extend(iter)
+    }
+
+    fn try_extend_from_slice(&mut self, slice: &[T]) -> Result<()> {
+        self.try_extend_from_slice(slice)
+    }
+
+    fn reserve(&mut self, additional: usize) -> Result<()> {
+        self.reserve(additional)
+    }
+
+    fn growth_policy(&self) -> GrowthPolicy {
+        self.growth_policy()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.flush()
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<()> {
+        self.truncate(len)
+    }
+}
+
+impl<T: Pod> ProvenanceStorage for MmapVec<T> {
+    fn provenance(&self) -> Result<Option<Provenance>> {
+        Ok(self.provenance())
+    }
+
+    fn set_provenance(&mut self, provenance: &Provenance) -> Result<()> {
+        self.set_provenance(provenance)
+    }
 }
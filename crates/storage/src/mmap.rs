@@ -0,0 +1,164 @@
+//! Memory maps of files, and the advisory locks held on mapped files.
+//!
+//! On most targets this re-exports `mmap-rs`. WebAssembly has no memory
+//! mapped files, and neither `mmap-rs` nor `fs4` build for it, so there
+//! the same API is provided by stand-ins that fail to map and to lock.
+//! [`MmapVec`], [`JournaledStorage`] and the memory mapped trees then compile
+//! but can't be created, while in-memory storage works as everywhere else.
+//!
+//! [`MmapVec`]: crate::MmapVec
+//! [`JournaledStorage`]: crate::JournaledStorage
+
+use std::fs::File;
+use std::io;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use mmap_rs::{Error, Mmap, MmapFlags, MmapMut, MmapOptions};
+
+#[cfg(target_arch = "wasm32")]
+pub use self::unsupported::{Error, Mmap, MmapFlags, MmapMut, MmapOptions};
+
+/// Takes an exclusive advisory lock on `file`, failing if it's already
+/// locked. The lock is released once the file is closed.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn try_lock_exclusive(file: &File) -> io::Result<()> {
+    fs4::FileExt::try_lock_exclusive(file)
+}
+
+/// Takes an exclusive advisory lock on `file`, failing if it's already
+/// locked. The lock is released once the file is closed.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn try_lock_exclusive(_file: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "file locks are not supported on this target",
+    ))
+}
+
+#[cfg(target_arch = "wasm32")]
+mod unsupported {
+    use std::fmt;
+    use std::fs::File;
+    use std::marker::PhantomData;
+    use std::ops::{Deref, DerefMut, Range};
+
+    /// The error of every memory map on this target.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Error;
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("memory maps are not supported on this target")
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct MmapFlags(u32);
+
+    impl MmapFlags {
+        pub const SHARED: Self = Self(1);
+    }
+
+    /// Options of a memory map, which can't be built on this target.
+    #[derive(Debug)]
+    pub struct MmapOptions<'a> {
+        file: PhantomData<&'a File>,
+    }
+
+    impl<'a> MmapOptions<'a> {
+        pub fn new(_size: usize) -> Result<Self, Error> {
+            Err(Error)
+        }
+
+        /// # Safety
+        ///
+        /// See `mmap_rs::MmapOptions::with_file`.
+        #[must_use]
+        pub unsafe fn with_file(self, _file: &'a File, _offset: u64) -> Self {
+            self
+        }
+
+        #[must_use]
+        pub fn with_flags(self, _flags: MmapFlags) -> Self {
+            self
+        }
+
+        pub fn map(self) -> Result<Mmap, Error> {
+            Err(Error)
+        }
+
+        pub fn map_mut(self) -> Result<MmapMut, Error> {
+            Err(Error)
+        }
+    }
+
+    /// A read-only memory map, which can't exist on this target.
+    #[derive(Debug)]
+    pub enum Mmap {}
+
+    impl Mmap {
+        pub fn as_slice(&self) -> &[u8] {
+            match *self {}
+        }
+    }
+
+    // Unmaps when dropped, like the maps of `mmap-rs`
+    impl Drop for Mmap {
+        fn drop(&mut self) {
+            match *self {}
+        }
+    }
+
+    impl Deref for Mmap {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            match *self {}
+        }
+    }
+
+    /// A writable memory map, which can't exist on this target.
+    #[derive(Debug)]
+    pub enum MmapMut {}
+
+    impl MmapMut {
+        pub fn as_slice(&self) -> &[u8] {
+            match *self {}
+        }
+
+        pub fn as_mut_slice(&mut self) -> &mut [u8] {
+            match *self {}
+        }
+
+        pub fn flush(&self, _range: Range<usize>) -> Result<(), Error> {
+            match *self {}
+        }
+
+        pub fn flush_async(&self, _range: Range<usize>) -> Result<(), Error> {
+            match *self {}
+        }
+    }
+
+    // Unmaps when dropped, like the maps of `mmap-rs`
+    impl Drop for MmapMut {
+        fn drop(&mut self) {
+            match *self {}
+        }
+    }
+
+    impl Deref for MmapMut {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            match *self {}
+        }
+    }
+
+    impl DerefMut for MmapMut {
+        fn deref_mut(&mut self) -> &mut [u8] {
+            match *self {}
+        }
+    }
+}
@@ -1,12 +1,81 @@
 use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
 
 use bytemuck::Pod;
-use color_eyre::eyre::{ensure, Context};
-use mmap_rs::{MmapFlags, MmapMut, MmapOptions};
+use same_file::Handle;
+
+use crate::error::{ensure, Context};
+use crate::mmap::{try_lock_exclusive, Mmap, MmapFlags, MmapMut, MmapOptions};
+use crate::provenance::{Provenance, PROVENANCE_SIZE};
+use crate::{Result, StorageError};
+
+const WORD_SIZE: usize = std::mem::size_of::<usize>();
+/// Number of header words
+const HEADER_WORDS: usize = 4;
+/// Size of the header words, followed by a [`Provenance`]
+const WORDS_SIZE: usize = HEADER_WORDS * WORD_SIZE;
+/// Size of the header
+const META_SIZE: usize = WORDS_SIZE + PROVENANCE_SIZE;
+
+/// Index of the header word holding [`MAGIC`]
+const MAGIC_WORD: usize = 0;
+/// Index of the header word holding the format version
+const VERSION_WORD: usize = 1;
+/// Index of the header word holding the number of stored elements
+const LEN_WORD: usize = 2;
+/// Index of the header word holding the type tag
+const TYPE_TAG_WORD: usize = 3;
+
+/// Marks the files of a versioned format. Legacy files, created before the
+/// format was versioned, start with their length instead, which never has
+/// the top bit set.
+const MAGIC: usize = (1 << (usize::BITS - 1)) | 0x6d76;
+/// Version of the header layout, bumped whenever it changes
+const FORMAT_VERSION: usize = 1;
+
+/// Files locked by an [`MmapVec`] of this process, by the id of their
+/// [`FileLock`].
+///
+/// The advisory lock belongs to the open file description, so it is granted
+/// again to a duplicate of a locked file, e.g. from [`File::try_clone`]. Files
+/// are checked against this list before they are locked.
+static LOCKED_FILES: Mutex<Vec<(u64, Handle)>> = Mutex::new(Vec::new());
+
+static NEXT_LOCK_ID: AtomicU64 = AtomicU64::new(0);
+
+/// How an [`MmapVec`] grows its file once it runs out of capacity.
+///
+/// Growing remaps the whole file, so a large storage growing in the middle of
+/// a write stalls that write. The policy is not stored in the file, restored
+/// vectors start with [`GrowthPolicy::Doubling`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GrowthPolicy {
+    /// Grows the capacity to the next power of two, the default.
+    #[default]
+    Doubling,
+    /// Grows the capacity to the next multiple of the given number of
+    /// elements, trading more frequent remaps for less unused file space.
+    FixedIncrement(usize),
+    /// Like [`GrowthPolicy::Doubling`], but trees built on the storage
+    /// reserve the capacity of their full depth upfront, so they never grow
+    /// after construction. See `CascadingMerkleTree::new_with_leaves`.
+    PreallocateFullDepth,
+}
 
-const META_SIZE: usize = std::mem::size_of::<usize>();
+impl GrowthPolicy {
+    /// Returns the capacity to grow to for holding `required` elements.
+    #[must_use]
+    pub fn grown_capacity(self, required: usize) -> usize {
+        match self {
+            Self::Doubling | Self::PreallocateFullDepth => required.next_power_of_two(),
+            Self::FixedIncrement(increment) => required.div_ceil(increment) * increment,
+        }
+    }
+}
 
 pub struct MmapVec<T> {
     // This must be Option to properly uphold aliasing access safety guarantees
@@ -14,7 +83,11 @@ pub struct MmapVec<T> {
     mmap: Option<MmapMut>,
     file: File,
     capacity: usize,
+    growth_policy: GrowthPolicy,
     phantom: std::marker::PhantomData<T>,
+    /// Lock of the file, `None` for vectors created without locking. Dropped
+    /// last, after the mapping.
+    lock: Option<FileLock>,
 }
 
 // Public API
@@ -22,14 +95,15 @@ impl<T: Pod> MmapVec<T> {
     /// Creates a new MmapVec from a file path.
     /// Any existing data in the file will be truncated.
     ///
-    /// # Safety
-    /// Same requirements as `create`
-    pub unsafe fn create_from_path(file_path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+    /// See [`MmapVec::create`] for the locking behavior.
+    pub fn create_from_path(file_path: impl AsRef<Path>) -> Result<Self> {
+        // The file is truncated once locked, so a file locked by another
+        // MmapVec is left intact
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .truncate(true)
+            .truncate(false)
             .open(file_path)?;
 
         Self::create(file)
@@ -38,28 +112,71 @@ impl<T: Pod> MmapVec<T> {
     /// Creates a new MmapVec from a file.
     /// Any existing data in the file will be truncated.
     ///
+    /// An exclusive advisory lock is taken on the file and held for the
+    /// lifetime of the MmapVec. Fails if the file is already locked, e.g. by
+    /// another MmapVec in this process, including through a duplicate of the
+    /// same file, or in any other.
+    pub fn create(file: File) -> Result<Self> {
+        let lock = lock_exclusive(&file)?;
+
+        // Safety: the exclusive lock guarantees that no other MmapVec maps
+        // this file for as long as we hold it.
+        let mut s = unsafe { Self::create_unchecked(file) }?;
+        s.lock = Some(lock);
+        Ok(s)
+    }
+
+    /// Creates a new MmapVec from a file without locking it.
+    /// Any existing data in the file will be truncated.
+    ///
     /// # Safety
-    /// This method requires that the safety requirements of [`mmap_rs::MmapOptions::with_file`](https://docs.rs/mmap-rs/0.6.1/mmap_rs/struct.MmapOptions.html#method.with_file) are upheld.
+    /// This method requires that the safety requirements of [`MmapOptions::with_file`](https://docs.rs/mmap-rs/0.6.1/mmap_rs/struct.MmapOptions.html#method.with_file) are upheld.
     ///
     /// Notably this means that there can exist no other mutable mappings to the
     /// same file in this process or any other
-    pub unsafe fn create(file: File) -> color_eyre::Result<Self> {
+    pub unsafe fn create_unchecked(file: File) -> Result<Self> {
         file.set_len(0)?;
-        file.set_len(META_SIZE as u64)
-            .context("Failed to resize underlying file")?;
 
-        let mut s = Self::restore(file)?;
+        let mut s = Self::restore_unchecked(file)?;
 
         s.set_storage_len(0);
 
         Ok(s)
     }
 
-    /// Restores an MmapVec from a file path.
+    /// Creates a new MmapVec from a file path with room for `capacity`
+    /// elements, so the first `capacity` pushes never grow the file.
+    /// Any existing data in the file will be truncated.
+    ///
+    /// See [`MmapVec::create`] for the locking behavior.
+    pub fn with_capacity_from_path(file_path: impl AsRef<Path>, capacity: usize) -> Result<Self> {
+        let mut s = Self::create_from_path(file_path)?;
+        s.try_resize(capacity)?;
+        Ok(s)
+    }
+
+    /// Opens the file of an MmapVec for reading only, see [`MmapVecReader`].
+    ///
+    /// No lock is taken, so the file can be opened while another MmapVec in
+    /// this process or any other writes it. Fails if the file was never
+    /// initialized by an MmapVec, or was created for elements of a different
+    /// size than `T`.
     ///
     /// # Safety
-    /// Same requirements as `restore`
-    pub unsafe fn restore_from_path(file_path: impl AsRef<Path>) -> color_eyre::Result<Self> {
+    ///
+    /// The file must not be shrunk, e.g. with [`MmapVec::truncate`] or by
+    /// recreating it, for as long as the reader lives. Every read checks the
+    /// length of the file first, but a file shrunk between the check and the
+    /// read faults with `SIGBUS`.
+    pub unsafe fn open_read_only(file_path: impl AsRef<Path>) -> Result<MmapVecReader<T>> {
+        let file = File::open(file_path)?;
+        MmapVecReader::new(file)
+    }
+
+    /// Restores an MmapVec from a file path.
+    ///
+    /// See [`MmapVec::restore`] for the locking and type checking behavior.
+    pub fn restore_from_path(file_path: impl AsRef<Path>) -> Result<Self> {
         let file = OpenOptions::new()
             .read(true)
             .write(true)
@@ -72,49 +189,137 @@ impl<T: Pod> MmapVec<T> {
 
     /// Restores an MmapVec from a file. This should not panic.
     ///
-    /// # Safety
-    /// This method requires that the safety requirements of [`mmap_rs::MmapOptions::with_file`](https://docs.rs/mmap-rs/0.6.1/mmap_rs/struct.MmapOptions.html#method.with_file) are upheld.
+    /// An exclusive advisory lock is taken on the file and held for the
+    /// lifetime of the MmapVec. Fails if the file is already locked, if it
+    /// was created for elements of a different size than `T`, or if it has
+    /// the legacy format, see [`MmapVec::migrate_legacy`].
+    pub fn restore(file: File) -> Result<Self> {
+        let lock = lock_exclusive(&file)?;
+
+        // Safety: the exclusive lock guarantees that no other MmapVec maps
+        // this file for as long as we hold it. Since `T` is `Pod`, any bit
+        // pattern of the right size is a valid `T`, which is checked by the
+        // type tag.
+        let mut s = unsafe { Self::restore_unchecked(file) }?;
+        s.lock = Some(lock);
+        Ok(s)
+    }
+
+    /// Restores an MmapVec from a file without locking it.
     ///
-    /// Additioanally the caller must ensure that the file contains valid data.
+    /// # Safety
+    /// This method requires that the safety requirements of [`MmapOptions::with_file`](https://docs.rs/mmap-rs/0.6.1/mmap_rs/struct.MmapOptions.html#method.with_file) are upheld.
     ///
     /// Notably this means that there can exist no other mutable mappings to the
     /// same file in this process or any other
-    pub unsafe fn restore(file: File) -> color_eyre::Result<Self> {
+    pub unsafe fn restore_unchecked(file: File) -> Result<Self> {
         assert!(std::mem::size_of::<T>() != 0);
 
         let mut byte_len = file.metadata()?.len() as usize;
 
-        if byte_len < META_SIZE {
-            file.set_len(0)?;
-
-            file.set_len(META_SIZE as u64)?;
+        let [magic] = if byte_len < WORD_SIZE {
+            [0]
+        } else {
+            read_words(&file)?
+        };
 
+        let len = if magic == 0 {
+            // Freshly initialized file, or an empty legacy file, start it
+            // over in the current format
+            file.set_len(0)?;
+            file.set_len(META_SIZE as u64)
+                .context("Failed to resize underlying file")?;
             byte_len = META_SIZE;
-        }
+            0
+        } else {
+            ensure!(
+                magic == MAGIC,
+                "file has the legacy format, migrate it with `MmapVec::migrate_legacy`"
+            );
+            ensure!(byte_len >= META_SIZE, "file is shorter than its header");
+            let [_, version, len, type_tag] = read_words(&file)?;
+            check_header::<T>(version, type_tag)?;
+            len
+        };
 
-        let data_len = byte_len.saturating_sub(META_SIZE);
+        let data_len = byte_len - META_SIZE;
         ensure!(
-            data_len % std::mem::size_of::<T>() == 0,
+            data_len.is_multiple_of(std::mem::size_of::<T>()),
             "data must be divisible by size of T"
         );
 
         let capacity = data_len / std::mem::size_of::<T>();
 
-        let mmap = MmapOptions::new(byte_len)?
-            .with_file(&file, 0)
-            .with_flags(MmapFlags::SHARED)
-            .map_mut()?;
+        let mmap = map_file(&file, byte_len)?;
 
-        let s = Self {
+        let mut s = Self {
             mmap: Some(mmap),
             file,
             capacity,
+            growth_policy: GrowthPolicy::default(),
             phantom: std::marker::PhantomData,
+            lock: None,
         };
 
-        let len = s.storage_len();
         ensure!(len <= capacity, "len must be lower than capacity");
 
+        if magic == 0 {
+            // Claim the fresh file for `T`, marking it last
+            let meta = s.meta_mut();
+            meta[VERSION_WORD] = FORMAT_VERSION;
+            meta[TYPE_TAG_WORD] = Self::type_tag();
+            meta[MAGIC_WORD] = MAGIC;
+        }
+
+        Ok(s)
+    }
+
+    /// Copies the elements of a file created by a version of this crate
+    /// whose header only held the length, the legacy format, to a new
+    /// MmapVec at `file_path`. Any existing data at `file_path` will be
+    /// truncated, the legacy file is left untouched.
+    ///
+    /// Legacy files don't record their element type, so `T` must be the type
+    /// the file was created for. No other process may write the legacy file
+    /// meanwhile.
+    ///
+    /// See [`MmapVec::create`] for the locking behavior.
+    pub fn migrate_legacy(
+        legacy_path: impl AsRef<Path>,
+        file_path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        assert!(std::mem::size_of::<T>() != 0);
+        ensure!(
+            !same_file::is_same_file(&legacy_path, &file_path).unwrap_or(false),
+            "legacy file can not be migrated in place"
+        );
+
+        let mut legacy = File::open(legacy_path)?;
+        let byte_len = legacy.metadata()?.len() as usize;
+        ensure!(
+            byte_len >= WORD_SIZE,
+            "legacy file is shorter than its header"
+        );
+        let [len] = read_words(&legacy)?;
+        ensure!(len != MAGIC, "file already has the current format");
+
+        let data_len = byte_len - WORD_SIZE;
+        ensure!(
+            data_len.is_multiple_of(std::mem::size_of::<T>()),
+            "data must be divisible by size of T"
+        );
+        ensure!(
+            len <= data_len / std::mem::size_of::<T>(),
+            "len must be lower than capacity"
+        );
+
+        let mut s = Self::with_capacity_from_path(file_path, len)?;
+        // Reading the length left the legacy file at its first element
+        legacy
+            .read_exact(bytemuck::cast_slice_mut(&mut s.capacity_slice_mut()[..len]))
+            .context("Failed to read legacy file")?;
+        s.set_storage_len(len);
+        s.flush()?;
         Ok(s)
     }
 
@@ -122,38 +327,179 @@ impl<T: Pod> MmapVec<T> {
         self.set_storage_len(0);
     }
 
+    /// Returns the provenance recorded with [`MmapVec::set_provenance`], if
+    /// any.
+    #[must_use]
+    pub fn provenance(&self) -> Option<Provenance> {
+        Provenance::decode(&self.mmap.as_ref().unwrap()[WORDS_SIZE..META_SIZE])
+    }
+
+    /// Records where the elements come from in the header of the file,
+    /// replacing any previous record.
+    ///
+    /// The record is part of the same memory map as the elements and is
+    /// flushed together with them, so writing the elements and the record
+    /// and then flushing persists both.
+    pub fn set_provenance(&mut self, provenance: &Provenance) -> Result<()> {
+        provenance.encode(&mut self.mmap.as_mut().unwrap()[WORDS_SIZE..META_SIZE]);
+        Ok(())
+    }
+
+    /// Returns the number of elements the file has room for.
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns how the file grows once it runs out of capacity.
+    #[must_use]
+    pub const fn growth_policy(&self) -> GrowthPolicy {
+        self.growth_policy
+    }
+
+    /// Sets how the file grows once it runs out of capacity.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the policy is a [`GrowthPolicy::FixedIncrement`] of zero.
+    pub fn set_growth_policy(&mut self, growth_policy: GrowthPolicy) {
+        assert!(
+            growth_policy != GrowthPolicy::FixedIncrement(0),
+            "Growth increment must be greater than 0"
+        );
+        self.growth_policy = growth_policy;
+    }
+
+    /// Like [`MmapVec::set_growth_policy`], returning the vector.
+    #[must_use]
+    pub fn with_growth_policy(mut self, growth_policy: GrowthPolicy) -> Self {
+        self.set_growth_policy(growth_policy);
+        self
+    }
+
+    /// Grows the file to hold at least `additional` more elements than it
+    /// holds now, exactly, regardless of the growth policy. Does nothing if
+    /// the capacity is already sufficient.
+    ///
+    /// Reserving ahead of a burst of writes moves the remap out of the write
+    /// path.
+    pub fn reserve(&mut self, additional: usize) -> Result<()> {
+        let required = self.storage_len() + additional;
+        if required > self.capacity {
+            self.try_resize(required)?;
+        }
+        Ok(())
+    }
+
+    /// Grows the file according to the growth policy to hold `required`
+    /// elements.
+    fn grow(&mut self, required: usize) -> Result<()> {
+        self.try_resize(self.growth_policy.grown_capacity(required))
+    }
+
     pub fn push(&mut self, v: T) {
+        self.try_push(v).expect("Failed to grow MmapVec");
+    }
+
+    /// Appends an element, returning an error instead of panicking if the
+    /// storage can not be grown.
+    pub fn try_push(&mut self, v: T) -> Result<()> {
         let len = self.storage_len();
         let capacity = self.capacity;
         let new_len = len + 1;
 
         if new_len > capacity {
-            self.resize(new_len.next_power_of_two());
+            self.grow(new_len)?;
         }
 
         self.capacity_slice_mut()[len] = v;
         self.set_storage_len(new_len);
+        Ok(())
     }
 
     pub fn extend_from_slice(&mut self, slice: &[T]) {
+        self.try_extend_from_slice(slice)
+            .expect("Failed to grow MmapVec");
+    }
+
+    /// Appends all elements of the slice, returning an error instead of
+    /// panicking if the storage can not be grown. On error the contents are
+    /// left unchanged.
+    pub fn try_extend_from_slice(&mut self, slice: &[T]) -> Result<()> {
         let len = self.storage_len();
         let capacity = self.capacity;
         let new_len = len + slice.len();
 
-        if new_len >= capacity {
-            self.resize(new_len.next_power_of_two());
+        if new_len > capacity {
+            self.grow(new_len)?;
         }
 
         self.capacity_slice_mut()[len..(new_len)].copy_from_slice(slice);
         self.set_storage_len(new_len);
+        Ok(())
+    }
+
+    /// Appends all elements of the iterator, returning an error instead of
+    /// panicking if the storage can not be grown. On error the contents are
+    /// left unchanged.
+    pub fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<()> {
+        let len = self.storage_len();
+        let iter = iter.into_iter();
+
+        let (lower, _) = iter.size_hint();
+        if len + lower > self.capacity {
+            self.grow(len + lower)?;
+        }
+
+        for item in iter {
+            if let Err(e) = self.try_push(item) {
+                self.set_storage_len(len);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Shortens the vector to `len` elements and shrinks the file to fit
+    /// them, releasing the capacity past them. A `len` not less than the
+    /// current length only releases the unused capacity.
+    ///
+    /// The new length is flushed before the file is shrunk, so the file never
+    /// claims more elements than it holds.
+    pub fn truncate(&mut self, len: usize) -> Result<()> {
+        if len < self.storage_len() {
+            self.set_storage_len(len);
+            self.mmap
+                .as_ref()
+                .unwrap()
+                .flush(0..META_SIZE)
+                .context("Failed to flush memory map")?;
+        }
+        let len = self.storage_len();
+        if len < self.capacity {
+            self.try_resize(len)?;
+        }
+        Ok(())
     }
 
     pub fn resize(&mut self, new_capacity: usize) {
+        self.try_resize(new_capacity)
+            .expect("Failed to resize MmapVec");
+    }
+
+    /// Resizes the underlying file and memory map to hold `new_capacity`
+    /// elements.
+    ///
+    /// If the file can not be resized the MmapVec is left untouched. If the
+    /// new memory map can not be built, the file is shrunk back and the
+    /// previous mapping restored.
+    pub fn try_resize(&mut self, new_capacity: usize) -> Result<()> {
+        let old_file_len = META_SIZE + self.capacity * std::mem::size_of::<T>();
         let new_file_len = META_SIZE + new_capacity * std::mem::size_of::<T>();
 
         self.file
             .set_len(new_file_len as u64)
-            .expect("Failed to resize underlying file");
+            .context("Failed to resize underlying file")?;
 
         // # Safety
         // MmapMut requires that no other instance of MmapMut exists that has access
@@ -167,27 +513,69 @@ impl<T: Pod> MmapVec<T> {
         // for its entire lifetime. Therefore it must be upheld here as well.
         unsafe {
             self.mmap = None;
-            self.mmap = Some(
-                MmapOptions::new(new_file_len)
-                    .expect("cannot create memory map")
-                    .with_file(&self.file, 0)
-                    .with_flags(MmapFlags::SHARED)
-                    .map_mut()
-                    .expect("cannot build memory map"),
-            );
+            match map_file(&self.file, new_file_len) {
+                Ok(mmap) => self.mmap = Some(mmap),
+                Err(e) => {
+                    self.file
+                        .set_len(old_file_len as u64)
+                        .context("Failed to restore file size after failed remap")?;
+                    self.mmap = Some(map_file(&self.file, old_file_len)?);
+                    return Err(e);
+                }
+            }
         }
 
         self.capacity = new_capacity;
+        Ok(())
+    }
+
+    /// Writes all changes to the underlying file, blocking until they are on
+    /// disk.
+    pub fn flush(&self) -> Result<()> {
+        let mmap = self.mmap.as_ref().unwrap();
+        mmap.flush(0..mmap.len())
+            .context("Failed to flush memory map")
+    }
+
+    /// Starts writing all changes to the underlying file without waiting for
+    /// them to reach the disk.
+    pub fn flush_async(&self) -> Result<()> {
+        let mmap = self.mmap.as_ref().unwrap();
+        mmap.flush_async(0..mmap.len())
+            .context("Failed to flush memory map")
+    }
+
+    /// The type tag identifies the element type a file was created for.
+    ///
+    /// Since `T` is `Pod`, any bit pattern is valid and only the element size
+    /// matters for soundness.
+    const fn type_tag() -> usize {
+        std::mem::size_of::<T>()
+    }
+
+    fn meta_mut(&mut self) -> &mut [usize] {
+        bytemuck::cast_slice_mut(&mut self.mmap.as_mut().unwrap()[..WORDS_SIZE])
+    }
+
+    fn meta(&self) -> &[usize] {
+        bytemuck::cast_slice(&self.mmap.as_ref().unwrap()[..WORDS_SIZE])
     }
 
     fn set_storage_len(&mut self, new_len: usize) {
-        let slice: &mut [usize] =
-            bytemuck::cast_slice_mut(&mut self.mmap.as_mut().unwrap()[..META_SIZE]);
-        slice[0] = new_len;
+        // Released after the elements below it are written, see
+        // [`MmapVecReader`]
+        let word = self
+            .mmap
+            .as_mut()
+            .unwrap()
+            .as_mut_ptr()
+            .cast::<AtomicUsize>();
+        // Safety: the mapping is page aligned and longer than the header
+        unsafe { &*word.add(LEN_WORD) }.store(new_len, Ordering::Release);
     }
 
     fn storage_len(&self) -> usize {
-        bytemuck::cast_slice(&self.mmap.as_ref().unwrap()[..META_SIZE])[0]
+        self.meta()[LEN_WORD]
     }
 
     fn capacity_slice(&self) -> &[T] {
@@ -199,6 +587,244 @@ impl<T: Pod> MmapVec<T> {
     }
 }
 
+/// A read-only view of the file of an [`MmapVec`] that may be written
+/// concurrently, by another process or in this one.
+///
+/// Created with [`MmapVec::open_read_only`]. Elements below the length
+/// loaded by the last [`MmapVecReader::refresh`] are read by value with
+/// [`MmapVecReader::get`], the view never hands out references to memory the
+/// writer may be changing.
+///
+/// # Protocol
+///
+/// The writer stores new elements before it publishes the new length with
+/// release ordering, and refreshing loads the length with acquire ordering,
+/// so every element below the length has been written. Elements below the
+/// length may still be overwritten meanwhile, and a read racing such a write
+/// can observe a mix of the old and the new value. Readers that need
+/// consistent values must validate them, e.g. by checking their hashes.
+///
+/// Flushing is not needed for readers to observe writes, as all mappings of
+/// the file share the same pages. It only makes the writes durable.
+///
+/// The writer may grow the file, readers remap it on the next refresh, but
+/// must never shrink it while readers are mapped, e.g. with
+/// [`MmapVec::truncate`] or by recreating it: reading past the end of the
+/// file faults. Reads fail once they find the file shorter than the mapping.
+pub struct MmapVecReader<T> {
+    mmap: Mmap,
+    file: File,
+    len: usize,
+    capacity: usize,
+    phantom: std::marker::PhantomData<T>,
+}
+
+impl<T: Pod> MmapVecReader<T> {
+    fn new(file: File) -> Result<Self> {
+        assert!(std::mem::size_of::<T>() != 0);
+        assert!(META_SIZE.is_multiple_of(std::mem::align_of::<T>()));
+
+        let byte_len = file.metadata()?.len() as usize;
+        ensure!(byte_len >= META_SIZE, "file is not an initialized MmapVec");
+        let [magic, version, _, type_tag] = read_words(&file)?;
+        ensure!(magic == MAGIC, "file is not an initialized MmapVec");
+        check_header::<T>(version, type_tag)?;
+
+        let mut s = Self {
+            // Safety: the mapping is read-only, writes of other mappings are
+            // expected, see the protocol above
+            mmap: unsafe { map_file_read_only(&file, byte_len)? },
+            file,
+            len: 0,
+            capacity: (byte_len - META_SIZE) / std::mem::size_of::<T>(),
+            phantom: std::marker::PhantomData,
+        };
+        s.refresh()?;
+        Ok(s)
+    }
+
+    /// Loads the length last published by the writer, remapping the file if
+    /// it grew past the mapping, and returns it.
+    pub fn refresh(&mut self) -> Result<usize> {
+        self.check_mapped()?;
+        let len = self.published_len();
+        if len > self.capacity {
+            // The writer grows the file before publishing a length past its
+            // old end
+            let byte_len = self.file.metadata()?.len() as usize;
+            ensure!(byte_len >= META_SIZE, "file was truncated");
+            let capacity = (byte_len - META_SIZE) / std::mem::size_of::<T>();
+            ensure!(len <= capacity, "length ({len}) exceeds the file");
+            // Safety: see `new`
+            self.mmap = unsafe { map_file_read_only(&self.file, byte_len)? };
+            self.capacity = capacity;
+        }
+        self.len = len;
+        Ok(len)
+    }
+
+    /// Returns the length loaded by the last refresh.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the length loaded by the last refresh is zero.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of elements the mapping has room for.
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Reads the element at `index`, or `None` if it is not below the length
+    /// loaded by the last refresh. The value may be torn, see the protocol
+    /// above.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file was shrunk below the mapping.
+    pub fn get(&self, index: usize) -> Result<Option<T>> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        self.check_mapped()?;
+        let data = self.mmap.as_ptr().wrapping_add(META_SIZE).cast::<T>();
+        // Safety: the element is within the mapping, which is page aligned
+        // with a header of a multiple of the alignment of `T`, and any bit
+        // pattern is a valid `T`. Reading it volatile doesn't assume it's
+        // unchanged by other mappings.
+        Ok(Some(unsafe { std::ptr::read_volatile(data.add(index)) }))
+    }
+
+    /// Returns the provenance recorded by the writer, if any, see
+    /// [`MmapVec::provenance`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file was shrunk below the mapping.
+    pub fn provenance(&self) -> Result<Option<Provenance>> {
+        self.check_mapped()?;
+        let mut bytes = [0; PROVENANCE_SIZE];
+        let header = self.mmap.as_ptr().wrapping_add(WORDS_SIZE);
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            // Safety: the header is within the mapping, see `get`
+            *byte = unsafe { std::ptr::read_volatile(header.add(i)) };
+        }
+        Ok(Provenance::decode(&bytes))
+    }
+
+    /// Checks that the file still backs the whole mapping, so reading it
+    /// doesn't fault.
+    fn check_mapped(&self) -> Result<()> {
+        let byte_len = self.file.metadata()?.len() as usize;
+        ensure!(byte_len >= self.mmap.len(), "file was truncated");
+        Ok(())
+    }
+
+    fn published_len(&self) -> usize {
+        let word = self.mmap.as_ptr().cast::<AtomicUsize>();
+        // Safety: the mapping is page aligned and longer than the header
+        unsafe { &*word.add(LEN_WORD) }.load(Ordering::Acquire)
+    }
+}
+
+impl<T> std::fmt::Debug for MmapVecReader<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MmapVecReader")
+            .field("len", &self.len)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+/// # Safety
+///
+/// No other mutable mapping of `file` may exist, see [`MmapVec::try_resize`].
+unsafe fn map_file(file: &File, len: usize) -> Result<MmapMut> {
+    let mmap = MmapOptions::new(len)
+        .context("cannot create memory map")?
+        .with_file(file, 0)
+        .with_flags(MmapFlags::SHARED)
+        .map_mut()
+        .context("cannot build memory map")?;
+    Ok(mmap)
+}
+
+/// # Safety
+///
+/// `file` may be written through other mappings, reads of the mapping may
+/// observe those writes at any time.
+unsafe fn map_file_read_only(file: &File, len: usize) -> Result<Mmap> {
+    let mmap = MmapOptions::new(len)
+        .context("cannot create memory map")?
+        .with_file(file, 0)
+        .with_flags(MmapFlags::SHARED)
+        .map()
+        .context("cannot build memory map")?;
+    Ok(mmap)
+}
+
+/// Reads the first `N` header words of a file that is at least `N` words
+/// long.
+fn read_words<const N: usize>(mut file: &File) -> Result<[usize; N]> {
+    let mut bytes = [0; WORDS_SIZE];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut bytes[..N * WORD_SIZE])
+        .context("Failed to read file header")?;
+    Ok(std::array::from_fn(|i| {
+        usize::from_ne_bytes(bytes[i * WORD_SIZE..][..WORD_SIZE].try_into().unwrap())
+    }))
+}
+
+/// Checks the version and type tag of a header marked with [`MAGIC`].
+fn check_header<T: Pod>(version: usize, type_tag: usize) -> Result<()> {
+    ensure!(
+        version == FORMAT_VERSION,
+        "file has format version {version}, expected {FORMAT_VERSION}"
+    );
+    ensure!(
+        type_tag == MmapVec::<T>::type_tag(),
+        "file was created for elements of size {type_tag}, not {}",
+        MmapVec::<T>::type_tag()
+    );
+    Ok(())
+}
+
+/// The lock of a file mapped by an [`MmapVec`], see [`LOCKED_FILES`].
+/// Dropping it unregisters the file, the advisory lock is released once the
+/// file is closed.
+struct FileLock {
+    id: u64,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        LOCKED_FILES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|(id, _)| *id != self.id);
+    }
+}
+
+const ALREADY_LOCKED: &str = "File is already locked by another MmapVec";
+
+fn lock_exclusive(file: &File) -> Result<FileLock> {
+    let handle = Handle::from_file(file.try_clone()?).context("Failed to identify file")?;
+    let mut locked = LOCKED_FILES.lock().unwrap_or_else(PoisonError::into_inner);
+    if locked.iter().any(|(_, locked)| *locked == handle) {
+        return Err(StorageError::locked(ALREADY_LOCKED));
+    }
+    try_lock_exclusive(file).context(ALREADY_LOCKED)?;
+    let id = NEXT_LOCK_ID.fetch_add(1, Ordering::Relaxed);
+    locked.push((id, handle));
+    Ok(FileLock { id })
+}
+
 impl<T> Extend<T> for MmapVec<T>
 where
     T: Pod,
@@ -256,7 +882,7 @@ mod tests {
         let f = tempfile::NamedTempFile::new().unwrap();
         let file_path = f.path().to_owned();
 
-        let mut storage: MmapVec<u32> = unsafe { MmapVec::create(f.reopen().unwrap()).unwrap() };
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
         assert_eq!(storage.capacity, 0);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len(),
@@ -305,7 +931,7 @@ mod tests {
         let f = tempfile::NamedTempFile::new().unwrap();
         let file_path = f.path().to_owned();
 
-        let mut storage: MmapVec<u32> = unsafe { MmapVec::create(f.reopen().unwrap()).unwrap() };
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
         assert_eq!(storage.capacity, 0);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len(),
@@ -340,7 +966,7 @@ mod tests {
         let f = tempfile::NamedTempFile::new().unwrap();
         let file_path = f.path().to_owned();
 
-        let mut storage: MmapVec<u32> = unsafe { MmapVec::create(f.reopen().unwrap()).unwrap() };
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
         assert_eq!(storage.capacity, 0);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len() as usize,
@@ -354,7 +980,8 @@ mod tests {
             std::mem::size_of::<u32>() * 8 + META_SIZE
         );
 
-        let storage: MmapVec<u32> = unsafe { MmapVec::create(f.reopen().unwrap()).unwrap() };
+        drop(storage);
+        let storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
         assert_eq!(storage.capacity, 0);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len() as usize,
@@ -368,7 +995,7 @@ mod tests {
         let f = tempfile::NamedTempFile::new().unwrap();
         let file_path = f.path().to_owned();
 
-        let mut storage: MmapVec<u32> = unsafe { MmapVec::create(f.reopen().unwrap()).unwrap() };
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
         assert_eq!(storage.capacity, 0);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len() as usize,
@@ -382,7 +1009,8 @@ mod tests {
             std::mem::size_of::<u32>() * 8 + META_SIZE
         );
 
-        let storage: MmapVec<u32> = unsafe { MmapVec::create_from_path(&file_path).unwrap() };
+        drop(storage);
+        let storage: MmapVec<u32> = MmapVec::create_from_path(&file_path).unwrap();
         assert_eq!(storage.capacity, 0);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len() as usize,
@@ -396,7 +1024,7 @@ mod tests {
         let f = tempfile::NamedTempFile::new().unwrap();
         let file_path = f.path().to_owned();
 
-        let mut storage: MmapVec<u32> = unsafe { MmapVec::create(f.reopen().unwrap()).unwrap() };
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
         assert_eq!(storage.capacity, 0);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len() as usize,
@@ -410,7 +1038,8 @@ mod tests {
             std::mem::size_of::<u32>() * 8 + META_SIZE
         );
 
-        let storage: MmapVec<u32> = unsafe { MmapVec::restore(f.reopen().unwrap()).unwrap() };
+        drop(storage);
+        let storage: MmapVec<u32> = MmapVec::restore(f.reopen().unwrap()).unwrap();
         assert_eq!(storage.capacity, 8);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len() as usize,
@@ -424,7 +1053,7 @@ mod tests {
         let f = tempfile::NamedTempFile::new().unwrap();
         let file_path = f.path().to_owned();
 
-        let mut storage: MmapVec<u32> = unsafe { MmapVec::create(f.reopen().unwrap()).unwrap() };
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
         assert_eq!(storage.capacity, 0);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len() as usize,
@@ -438,7 +1067,8 @@ mod tests {
             std::mem::size_of::<u32>() * 8 + META_SIZE
         );
 
-        let storage: MmapVec<u32> = unsafe { MmapVec::restore_from_path(&file_path).unwrap() };
+        drop(storage);
+        let storage: MmapVec<u32> = MmapVec::restore_from_path(&file_path).unwrap();
         assert_eq!(storage.capacity, 8);
         assert_eq!(
             std::fs::metadata(&file_path).unwrap().len() as usize,
@@ -446,11 +1076,110 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_growth_policy() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let file_path = f.path().to_owned();
+
+        let mut storage: MmapVec<u32> = MmapVec::with_capacity_from_path(&file_path, 5).unwrap();
+        assert_eq!(storage.capacity(), 5);
+        assert_eq!(
+            std::fs::metadata(&file_path).unwrap().len() as usize,
+            std::mem::size_of::<u32>() * 5 + META_SIZE
+        );
+        storage.extend_from_slice(&[1, 2, 3, 4, 5]);
+        assert_eq!(storage.capacity(), 5);
+
+        // Reserving is exact and never shrinks
+        storage.reserve(2).unwrap();
+        assert_eq!(storage.capacity(), 7);
+        storage.reserve(1).unwrap();
+        assert_eq!(storage.capacity(), 7);
+
+        storage.set_growth_policy(GrowthPolicy::FixedIncrement(10));
+        storage.extend_from_slice(&[6, 7, 8]);
+        assert_eq!(storage.capacity(), 10);
+        storage.try_extend(0..3).unwrap();
+        assert_eq!(storage.capacity(), 20);
+        storage.push(9);
+        assert_eq!(storage.capacity(), 20);
+        assert_eq!(&storage[..], &[1, 2, 3, 4, 5, 6, 7, 8, 0, 1, 2, 9]);
+
+        storage.set_growth_policy(GrowthPolicy::Doubling);
+        storage.extend_from_slice(&[0; 9]);
+        assert_eq!(storage.capacity(), 32);
+
+        drop(storage);
+        let storage: MmapVec<u32> = MmapVec::restore_from_path(&file_path).unwrap();
+        assert_eq!(storage.growth_policy(), GrowthPolicy::Doubling);
+        assert_eq!(storage.len(), 21);
+    }
+
+    /// Reads all elements below the length loaded by the last refresh.
+    fn read_all(reader: &MmapVecReader<u32>) -> Vec<u32> {
+        (0..reader.len())
+            .map(|i| reader.get(i).unwrap().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        // Safety: the tests never shrink the file while the reader lives
+        let open = || unsafe { MmapVec::<u32>::open_read_only(f.path()) };
+        assert!(open().is_err());
+
+        let mut writer: MmapVec<u32> = MmapVec::create_from_path(f.path()).unwrap();
+        writer.extend_from_slice(&[1, 2, 3]);
+        assert!(unsafe { MmapVec::<u64>::open_read_only(f.path()) }.is_err());
+
+        // Opening doesn't contend for the writer's lock
+        let mut reader = open().unwrap();
+        assert_eq!(read_all(&reader), &[1, 2, 3]);
+
+        // New elements are only observed after a refresh, which remaps the
+        // grown file
+        writer.extend_from_slice(&[4, 5, 6, 7, 8]);
+        writer[0] = 9;
+        assert_eq!(reader.len(), 3);
+        assert_eq!(reader.get(3).unwrap(), None);
+        assert_eq!(reader.refresh().unwrap(), 8);
+        assert_eq!(reader.capacity(), 8);
+        assert_eq!(read_all(&reader), &[9, 2, 3, 4, 5, 6, 7, 8]);
+
+        let provenance = Provenance {
+            chain_id: 1,
+            block_number: 2,
+            contract: [3; 20],
+            root: [4; 32],
+        };
+        assert_eq!(reader.provenance().unwrap(), None);
+        writer.set_provenance(&provenance).unwrap();
+        assert_eq!(reader.provenance().unwrap(), Some(provenance));
+    }
+
+    #[test]
+    fn test_open_read_only_truncated() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let mut writer: MmapVec<u32> = MmapVec::create_from_path(f.path()).unwrap();
+        writer.extend_from_slice(&[1; 1024]);
+        // Safety: the reader doesn't read past the check failing below
+        let mut reader = unsafe { MmapVec::<u32>::open_read_only(f.path()) }.unwrap();
+        assert_eq!(reader.get(1023).unwrap(), Some(1));
+
+        // Reads fail instead of faulting once the file is shorter than the
+        // mapping
+        writer.truncate(1).unwrap();
+        assert!(reader.get(0).is_err());
+        assert!(reader.provenance().is_err());
+        assert!(reader.refresh().is_err());
+    }
+
     #[test]
     fn test_mmap_vec() {
         let f = tempfile::tempfile().unwrap();
 
-        let mut storage: MmapVec<u32> = unsafe { MmapVec::create(f.try_clone().unwrap()).unwrap() };
+        let mut storage: MmapVec<u32> = MmapVec::create(f.try_clone().unwrap()).unwrap();
 
         println!("{storage:?}");
         storage.resize(2);
@@ -478,7 +1207,7 @@ mod tests {
         assert_eq!(storage[3], 4);
 
         drop(storage);
-        let restored: MmapVec<u32> = unsafe { MmapVec::restore(f).unwrap() };
+        let restored: MmapVec<u32> = MmapVec::restore(f).unwrap();
 
         assert_eq!(restored.len(), 4);
 
@@ -487,4 +1216,198 @@ mod tests {
         assert_eq!(restored[2], 42);
         assert_eq!(restored[3], 4);
     }
+
+    #[test]
+    fn test_flush() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
+        storage.flush().unwrap();
+
+        storage.extend_from_slice(&[1, 2, 3]);
+        storage.flush_async().unwrap();
+        storage.push(4);
+        storage.flush().unwrap();
+
+        let bytes = std::fs::read(f.path()).unwrap();
+        let data: &[u32] = bytemuck::cast_slice(&bytes[META_SIZE..]);
+        assert_eq!(&data[..4], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let file_len = || f.as_file().metadata().unwrap().len() as usize;
+
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
+        storage.extend_from_slice(&[7; 100]);
+        assert_eq!(file_len(), META_SIZE + 128 * 4);
+
+        // Clearing keeps the capacity, truncating releases it
+        storage.clear();
+        storage.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(file_len(), META_SIZE + 128 * 4);
+        storage.truncate(10).unwrap();
+        assert_eq!(file_len(), META_SIZE + 3 * 4);
+        assert_eq!(&storage[..], &[1, 2, 3]);
+
+        storage.truncate(1).unwrap();
+        assert_eq!(file_len(), META_SIZE + 4);
+        storage.push(5);
+        drop(storage);
+        let restored: MmapVec<u32> = MmapVec::restore(f.reopen().unwrap()).unwrap();
+        assert_eq!(&restored[..], &[1, 5]);
+    }
+
+    #[test]
+    fn test_exclusive_lock() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+
+        let storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
+        let _ = MmapVec::<u32>::restore(f.reopen().unwrap()).expect_err("file should be locked");
+        let _ = MmapVec::<u32>::create(f.reopen().unwrap()).expect_err("file should be locked");
+
+        drop(storage);
+        let _storage: MmapVec<u32> = MmapVec::restore(f.reopen().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_exclusive_lock_from_path() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+
+        let mut storage: MmapVec<u32> = MmapVec::create_from_path(f.path()).unwrap();
+        storage.extend_from_slice(&[1, 2, 3]);
+        let _ = MmapVec::<u32>::create_from_path(f.path()).expect_err("file should be locked");
+        let _ = MmapVec::<u32>::with_capacity_from_path(f.path(), 8)
+            .expect_err("file should be locked");
+        assert_eq!(&storage[..], &[1, 2, 3]);
+
+        drop(storage);
+        let restored: MmapVec<u32> = MmapVec::restore(f.reopen().unwrap()).unwrap();
+        assert_eq!(&restored[..], &[1, 2, 3]);
+        drop(restored);
+        let storage: MmapVec<u32> = MmapVec::create_from_path(f.path()).unwrap();
+        assert!(storage.is_empty());
+    }
+
+    #[test]
+    fn test_exclusive_lock_duplicate() {
+        let f = tempfile::tempfile().unwrap();
+
+        // A duplicate shares the advisory lock, but not the MmapVec's claim
+        let storage: MmapVec<u32> = MmapVec::create(f.try_clone().unwrap()).unwrap();
+        let _ = MmapVec::<u32>::restore(f.try_clone().unwrap()).expect_err("file should be locked");
+        let _ = MmapVec::<u32>::create(f.try_clone().unwrap()).expect_err("file should be locked");
+
+        drop(storage);
+        let _storage: MmapVec<u32> = MmapVec::restore(f).unwrap();
+    }
+
+    #[test]
+    fn test_type_tag_mismatch() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
+        storage.extend_from_slice(&[1, 2, 3, 4]);
+        drop(storage);
+
+        let _ = MmapVec::<u64>::restore(f.reopen().unwrap()).expect_err("element size differs");
+
+        let restored: MmapVec<u32> = MmapVec::restore(f.reopen().unwrap()).unwrap();
+        assert_eq!(&restored[..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
+        assert_eq!(storage.provenance(), None);
+
+        let provenance = Provenance {
+            chain_id: 1,
+            block_number: 17_000_000,
+            contract: [0xab; 20],
+            root: [7; 32],
+        };
+        storage.extend_from_slice(&[1, 2, 3]);
+        storage.set_provenance(&provenance).unwrap();
+        // Survives growing the file
+        storage.extend_from_slice(&[4, 5, 6, 7, 8]);
+        drop(storage);
+
+        let mut restored: MmapVec<u32> = MmapVec::restore(f.reopen().unwrap()).unwrap();
+        assert_eq!(&restored[..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(restored.provenance(), Some(provenance));
+
+        let updated = Provenance {
+            block_number: 17_000_001,
+            ..provenance
+        };
+        restored.set_provenance(&updated).unwrap();
+        assert_eq!(restored.provenance(), Some(updated));
+    }
+
+    /// Writes a file in the legacy format, holding `len` of `elements`.
+    fn write_legacy(path: &Path, len: usize, elements: &[u32]) {
+        let mut bytes = len.to_ne_bytes().to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(elements));
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn test_restore_legacy() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        write_legacy(f.path(), 2, &[5, 6, 0]);
+
+        let _ = MmapVec::<u32>::restore(f.reopen().unwrap()).expect_err("file has legacy format");
+        assert!(unsafe { MmapVec::<u32>::open_read_only(f.path()) }.is_err());
+        // The file is left untouched
+        assert_eq!(
+            std::fs::metadata(f.path()).unwrap().len() as usize,
+            WORD_SIZE + 3 * 4
+        );
+
+        let migrated = tempfile::NamedTempFile::new().unwrap();
+        let _ = MmapVec::<u32>::migrate_legacy(f.path(), f.path()).expect_err("same file");
+        let _ = MmapVec::<u64>::migrate_legacy(f.path(), migrated.path())
+            .expect_err("element size differs");
+        let mut storage: MmapVec<u32> = MmapVec::migrate_legacy(f.path(), migrated.path()).unwrap();
+        assert_eq!(&storage[..], &[5, 6]);
+        storage.push(7);
+        drop(storage);
+
+        let restored: MmapVec<u32> = MmapVec::restore_from_path(migrated.path()).unwrap();
+        assert_eq!(&restored[..], &[5, 6, 7]);
+        let _ = MmapVec::<u32>::migrate_legacy(migrated.path(), f.path())
+            .expect_err("file already migrated");
+    }
+
+    #[test]
+    fn test_restore_legacy_empty() {
+        // Empty legacy files hold nothing to migrate and are started over
+        let f = tempfile::NamedTempFile::new().unwrap();
+        write_legacy(f.path(), 0, &[5, 6]);
+
+        let mut storage: MmapVec<u32> = MmapVec::restore(f.reopen().unwrap()).unwrap();
+        assert!(storage.is_empty());
+        assert_eq!(storage.capacity(), 0);
+        storage.push(1);
+        drop(storage);
+
+        let restored: MmapVec<u32> = MmapVec::restore(f.reopen().unwrap()).unwrap();
+        assert_eq!(&restored[..], &[1]);
+    }
+
+    #[test]
+    fn test_format_version_mismatch() {
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let mut storage: MmapVec<u32> = MmapVec::create(f.reopen().unwrap()).unwrap();
+        storage.push(1);
+        storage.meta_mut()[VERSION_WORD] = FORMAT_VERSION + 1;
+        drop(storage);
+
+        let _ = MmapVec::<u32>::restore(f.reopen().unwrap()).expect_err("unknown version");
+        assert!(unsafe { MmapVec::<u32>::open_read_only(f.path()) }.is_err());
+    }
 }
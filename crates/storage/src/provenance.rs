@@ -0,0 +1,64 @@
+use crate::Result;
+
+/// Where the leaves of a tree come from, e.g. the chain, block and contract a
+/// tree was synced from and the root the contract had at that block.
+///
+/// Recorded next to the leaves, see [`MmapVec::set_provenance`], so that a
+/// restored tree can be checked against the chain before serving proofs.
+///
+/// [`MmapVec::set_provenance`]: crate::MmapVec::set_provenance
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Provenance {
+    pub chain_id: u64,
+    pub block_number: u64,
+    pub contract: [u8; 20],
+    /// Root of the tree at `block_number`, in the encoding chosen by the
+    /// caller.
+    pub root: [u8; 32],
+}
+
+/// Storage that records the [`Provenance`] of its values.
+pub trait ProvenanceStorage {
+    /// Returns the provenance recorded with
+    /// [`ProvenanceStorage::set_provenance`], if any.
+    fn provenance(&self) -> Result<Option<Provenance>>;
+
+    /// Records where the stored values come from, replacing any previous
+    /// record. The record is persisted with the next flush.
+    fn set_provenance(&mut self, provenance: &Provenance) -> Result<()>;
+}
+
+/// Size of an encoded [`Provenance`], with a leading word marking it as
+/// present and the contract address padded to a whole word
+pub(crate) const PROVENANCE_SIZE: usize = 3 * 8 + 24 + 32;
+
+const CONTRACT_START: usize = 24;
+const ROOT_START: usize = CONTRACT_START + 24;
+
+impl Provenance {
+    pub(crate) fn encode(&self, bytes: &mut [u8]) {
+        // Unmarked while the fields are written, so a partially written record
+        // is not mistaken for a complete one
+        bytes[..8].copy_from_slice(&0_u64.to_ne_bytes());
+        bytes[8..16].copy_from_slice(&self.chain_id.to_ne_bytes());
+        bytes[16..24].copy_from_slice(&self.block_number.to_ne_bytes());
+        bytes[CONTRACT_START..CONTRACT_START + 20].copy_from_slice(&self.contract);
+        bytes[ROOT_START..ROOT_START + 32].copy_from_slice(&self.root);
+        bytes[..8].copy_from_slice(&1_u64.to_ne_bytes());
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Option<Self> {
+        let word = |start: usize| u64::from_ne_bytes(bytes[start..start + 8].try_into().unwrap());
+        if word(0) != 1 {
+            return None;
+        }
+        Some(Self {
+            chain_id: word(8),
+            block_number: word(16),
+            contract: bytes[CONTRACT_START..CONTRACT_START + 20]
+                .try_into()
+                .unwrap(),
+            root: bytes[ROOT_START..ROOT_START + 32].try_into().unwrap(),
+        })
+    }
+}
@@ -0,0 +1,207 @@
+//! Consistency checks over the leaves of a tree.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::hash::{Hash, Hasher as _};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, Write};
+use std::marker::PhantomData;
+
+use bytemuck::Pod;
+use hasher::Hasher;
+
+use crate::cascading::storage_ops::StorageOps;
+use crate::cascading::CascadingMerkleTree;
+use crate::error::{Result, TreeError};
+
+/// Number of distinct leaves [`find_duplicate_leaves`] keeps in memory before
+/// spilling to disk.
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 22;
+
+/// Number of files the leaves are partitioned into when spilling to disk.
+const SPILL_BUCKETS: u64 = 64;
+
+const WORD_SIZE: usize = std::mem::size_of::<usize>();
+
+/// Leaves that occur more than once, each with the indices it occurs at.
+pub type Duplicates<T> = Vec<(T, Vec<usize>)>;
+
+/// Returns every non-empty leaf of the tree that occurs at more than one
+/// index, ordered by the index of its first occurrence.
+///
+/// See [`find_duplicates`] for how large trees are handled.
+///
+/// # Errors
+///
+/// Returns an error if spilling the leaves to disk fails.
+pub fn find_duplicate_leaves<H, S>(tree: &CascadingMerkleTree<H, S>) -> Result<Duplicates<H::Hash>>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync + Hash,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+{
+    find_duplicates(tree.leaves(), &tree.empty_value(), DEFAULT_MEMORY_LIMIT)
+}
+
+/// Returns every leaf other than `empty` that occurs at more than one index,
+/// ordered by the index of its first occurrence.
+///
+/// Leaves are streamed into a hash map. Once it holds more than
+/// `max_in_memory` distinct leaves, the map and the remaining leaves are
+/// partitioned by hash into temporary files, which are then processed one at
+/// a time the same way.
+///
+/// # Errors
+///
+/// Returns an error if spilling the leaves to disk fails.
+pub fn find_duplicates<T>(
+    leaves: impl IntoIterator<Item = T>,
+    empty: &T,
+    max_in_memory: usize,
+) -> Result<Duplicates<T>>
+where
+    T: Pod + Eq + Hash,
+{
+    let records = leaves
+        .into_iter()
+        .enumerate()
+        .filter(|(_, leaf)| leaf != empty)
+        .map(|(index, leaf)| Ok((leaf, index)));
+
+    let mut duplicates = Vec::new();
+    group(records, max_in_memory, 0, &mut duplicates).map_err(TreeError::Spill)?;
+    duplicates.sort_unstable_by_key(|(_, indices)| indices[0]);
+    Ok(duplicates)
+}
+
+/// Collects the leaves occurring more than once among `records`, which
+/// yields each occurrence of a leaf in increasing index order.
+fn group<T, I>(
+    mut records: I,
+    max_in_memory: usize,
+    level: u64,
+    duplicates: &mut Duplicates<T>,
+) -> io::Result<()>
+where
+    T: Pod + Eq + Hash,
+    I: Iterator<Item = io::Result<(T, usize)>>,
+{
+    let mut groups: HashMap<T, Vec<usize>> = HashMap::new();
+    while let Some(record) = records.next() {
+        let (leaf, index) = record?;
+        groups.entry(leaf).or_default().push(index);
+
+        if groups.len() > max_in_memory {
+            // Every occurrence of a leaf ends up in the same bucket, in the
+            // order it was seen
+            let spilled = groups.into_iter().flat_map(|(leaf, indices)| {
+                indices.into_iter().map(move |index| Ok((leaf, index)))
+            });
+            for bucket in spill(spilled.chain(records), level)? {
+                group(bucket, max_in_memory, level + 1, duplicates)?;
+            }
+            return Ok(());
+        }
+    }
+
+    duplicates.extend(groups.into_iter().filter(|(_, indices)| indices.len() > 1));
+    Ok(())
+}
+
+/// Partitions the records into temporary files by the hash of their leaf.
+/// The hash depends on `level`, so that spilling a bucket again splits it.
+fn spill<T, I>(records: I, level: u64) -> io::Result<Vec<Bucket<T>>>
+where
+    T: Pod + Hash,
+    I: Iterator<Item = io::Result<(T, usize)>>,
+{
+    let mut writers = (0..SPILL_BUCKETS)
+        .map(|_| Ok(BufWriter::new(tempfile::tempfile()?)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    for record in records {
+        let (leaf, index) = record?;
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(level);
+        leaf.hash(&mut hasher);
+
+        let writer = &mut writers[(hasher.finish() % SPILL_BUCKETS) as usize];
+        writer.write_all(bytemuck::bytes_of(&leaf))?;
+        writer.write_all(&index.to_ne_bytes())?;
+    }
+
+    writers
+        .into_iter()
+        .map(|writer| {
+            let mut file = writer.into_inner()?;
+            file.rewind()?;
+            Ok(Bucket {
+                reader: BufReader::new(file),
+                _marker: PhantomData,
+            })
+        })
+        .collect()
+}
+
+/// Reads back the records spilled to a file.
+struct Bucket<T> {
+    reader: BufReader<File>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> Iterator for Bucket<T> {
+    type Item = io::Result<(T, usize)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let leaf_size = std::mem::size_of::<T>();
+        let mut record = vec![0; leaf_size + WORD_SIZE];
+        match self.reader.read_exact(&mut record) {
+            Ok(()) => {
+                let leaf = bytemuck::pod_read_unaligned(&record[..leaf_size]);
+                let index = usize::from_ne_bytes(record[leaf_size..].try_into().unwrap());
+                Some(Ok((leaf, index)))
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keccak::keccak::Keccak256;
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    #[test]
+    fn test_find_duplicate_leaves() {
+        let empty = [0; 32];
+        let leaves = [
+            [1; 32], [2; 32], [1; 32], empty, [3; 32], [2; 32], [1; 32], empty,
+        ];
+        let tree = CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], 4, &empty, &leaves);
+
+        let duplicates = find_duplicate_leaves(&tree).unwrap();
+        assert_eq!(
+            duplicates,
+            vec![([1; 32], vec![0, 2, 6]), ([2; 32], vec![1, 5])]
+        );
+    }
+
+    #[test]
+    fn test_find_duplicates_spilled() {
+        let mut rng = thread_rng();
+        let leaves: Vec<u64> = (0..10_000).map(|_| rng.gen_range(0..5_000)).collect();
+
+        let in_memory = find_duplicates(leaves.iter().copied(), &0, usize::MAX).unwrap();
+        assert!(!in_memory.is_empty());
+        assert!(in_memory.iter().all(|(_, indices)| indices.len() > 1));
+
+        // Small enough that buckets are spilled again
+        let spilled = find_duplicates(leaves.iter().copied(), &0, 64).unwrap();
+        assert_eq!(spilled, in_memory);
+    }
+}
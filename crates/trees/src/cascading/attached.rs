@@ -0,0 +1,279 @@
+use std::fmt::Debug;
+use std::path::Path;
+
+use bytemuck::Pod;
+use derive_where::derive_where;
+use hasher::Hasher;
+use storage::{MmapVec, MmapVecReader};
+
+use super::{storage_ops, CascadingMerkleTree};
+use crate::error::{Result, TreeError};
+use crate::proof::{Branch, Proof};
+
+/// Number of reads of an [`AttachedTree`] retried before giving up because
+/// the writer keeps changing the nodes read.
+const MAX_ATTEMPTS: usize = 1024;
+
+/// A read-only view of the file of a [`CascadingMerkleTree`] owned by a
+/// writer in another process, to serve proofs from.
+///
+/// Created with [`CascadingMerkleTree::attach`].
+///
+/// # Protocol
+///
+/// The writer keeps applying writes in place, without any lock shared with
+/// the readers, see [`MmapVecReader`] for what a reader observes:
+///
+/// - Every read loads the storage length published by the writer and
+///   remaps the file if it grew. Reads that race a growth of the storage are
+///   retried.
+/// - Nodes may be read in the middle of a write, or torn. Every proof is
+///   checked against the root read with it, and retried if they disagree.
+///   Since a torn node can't be part of a matching path, the returned root was
+///   the root of the tree at some point during the read, and the proof proves
+///   the returned leaf against it.
+/// - The writer doesn't need to flush for readers to observe its writes, but
+///   must never shrink or recreate the file while readers are attached, see
+///   [`CascadingMerkleTree::attach`].
+///
+/// A writer keeping the same nodes changing for the whole read makes it fail
+/// with [`TreeError::Contended`].
+pub struct AttachedTree<H>
+where
+    H: Hasher,
+{
+    depth: usize,
+    sparse_column: Vec<H::Hash>,
+    storage: MmapVecReader<H::Hash>,
+}
+
+/// A proof read from an [`AttachedTree`].
+#[derive_where(Clone; <H as Hasher>::Hash: Clone)]
+#[derive_where(PartialEq; <H as Hasher>::Hash: PartialEq)]
+#[derive_where(Eq; <H as Hasher>::Hash: Eq)]
+#[derive_where(Debug; <H as Hasher>::Hash: Debug)]
+pub struct AttachedProof<H>
+where
+    H: Hasher,
+{
+    /// The value of the proven leaf.
+    pub leaf: H::Hash,
+    /// The proof of the leaf.
+    pub proof: Proof<H>,
+    /// The root of the tree the proof was read from.
+    pub root: H::Hash,
+}
+
+impl<H> CascadingMerkleTree<H, MmapVec<H::Hash>>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+{
+    /// Attaches to the file of a tree written by another process, or by
+    /// another tree in this one, see [`AttachedTree`].
+    ///
+    /// `depth` and `empty_value` must be the ones of the writer's tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be opened, doesn't hold the storage
+    /// of a tree of this depth, or keeps changing while it's read.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be shrunk or recreated for as long as the attached
+    /// tree lives, see [`MmapVec::open_read_only`].
+    pub unsafe fn attach(
+        path: impl AsRef<Path>,
+        depth: usize,
+        empty_value: &H::Hash,
+    ) -> Result<AttachedTree<H>> {
+        if depth == 0 {
+            return Err(TreeError::ZeroDepth);
+        }
+        let mut tree = AttachedTree {
+            depth,
+            sparse_column: Self::sparse_column(depth, empty_value),
+            // Safety: upheld by the caller
+            storage: unsafe { MmapVec::open_read_only(path)? },
+        };
+        tree.read_consistent(|_| Ok(Some(())))?;
+        Ok(tree)
+    }
+}
+
+impl<H> AttachedTree<H>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+{
+    /// Returns the depth of the tree.
+    #[must_use]
+    pub const fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the current number of leaves of the tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage can't be remapped or turns invalid.
+    pub fn num_leaves(&mut self) -> Result<usize> {
+        self.read_consistent(|tree| tree.read_num_leaves().map(Some))
+    }
+
+    /// Returns the current root of the tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage can't be remapped or turns invalid, or
+    /// if the writer keeps changing the top of the storage.
+    pub fn root(&mut self) -> Result<H::Hash> {
+        self.read_consistent(|tree| {
+            let index = tree.storage.len() >> 1;
+            let root = tree.node(index)?;
+            // A node read while written is torn, a consistent one is the
+            // hash of its children
+            if let Some((left, right)) = storage_ops::children(index) {
+                if H::hash_node(&tree.node(left)?, &tree.node(right)?) != root {
+                    return Ok(None);
+                }
+            }
+            Ok(Some(tree.extend_to_root(root)))
+        })
+    }
+
+    /// Returns the proof of the given leaf against the current root, or
+    /// `None` if the leaf index is not less than the number of leaves.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage can't be remapped or turns invalid, or
+    /// if the writer keeps changing the nodes of the proof.
+    pub fn proof(&mut self, leaf: usize) -> Result<Option<AttachedProof<H>>> {
+        self.read_consistent(|tree| {
+            if leaf >= tree.read_num_leaves()? {
+                return Ok(Some(None));
+            }
+            let storage_depth = tree.storage_depth();
+            let mut index = storage_ops::index_from_leaf(leaf);
+            let Some(value) = tree.storage.get(index)? else {
+                return Ok(None);
+            };
+
+            let mut proof = Vec::with_capacity(tree.depth);
+            for _ in 0..storage_depth {
+                proof.push(match storage_ops::sibling(index) {
+                    Branch::Left(sibling) => Branch::Left(tree.node(sibling)?),
+                    Branch::Right(sibling) => Branch::Right(tree.node(sibling)?),
+                });
+                index = storage_ops::parent(index);
+            }
+            let remainder = &tree.sparse_column[storage_depth..tree.depth];
+            proof.extend(remainder.iter().map(|&hash| Branch::Left(hash)));
+            let proof = Proof(proof);
+
+            let root = tree.extend_to_root(tree.node(tree.storage.len() >> 1)?);
+            Ok((proof.root(value) == root).then_some(Some(AttachedProof {
+                leaf: value,
+                proof,
+                root,
+            })))
+        })
+    }
+
+    /// Runs `read` until the storage is a complete tree of the same length
+    /// before and after it, and it returns a value.
+    fn read_consistent<T>(
+        &mut self,
+        mut read: impl FnMut(&Self) -> Result<Option<T>>,
+    ) -> Result<T> {
+        let max_len = 2usize.checked_pow(self.depth as u32 + 1).unwrap();
+        for _ in 0..MAX_ATTEMPTS {
+            let len = self.storage.refresh()?;
+            if len > max_len {
+                return Err(TreeError::InvalidStorage(format!(
+                    "length ({len}) must be less than or equal to 2^(depth + 1)"
+                )));
+            }
+            // The writer grows the storage an element at a time
+            if len.is_power_of_two() && len > 1 {
+                let value = read(self)?;
+                if self.storage.refresh()? == len {
+                    if let Some(value) = value {
+                        return Ok(value);
+                    }
+                }
+            }
+            std::thread::yield_now();
+        }
+        Err(TreeError::Contended)
+    }
+
+    /// Reads the number of leaves, bounded by the storage.
+    fn read_num_leaves(&self) -> Result<usize> {
+        let first = self.node(0)?;
+        let num_leaves: usize = bytemuck::pod_read_unaligned(
+            &bytemuck::bytes_of(&first)[..std::mem::size_of::<usize>()],
+        );
+        Ok(num_leaves.min(self.storage.len() >> 1))
+    }
+
+    /// Reads a node below the length loaded by the last refresh.
+    fn node(&self, index: usize) -> Result<H::Hash> {
+        self.storage.get(index)?.ok_or_else(|| {
+            TreeError::InvalidStorage(format!("node {index} is past the end of the storage"))
+        })
+    }
+
+    /// Returns the depth of the subtree held by the storage, see
+    /// [`storage_ops::subtree_depth`].
+    fn storage_depth(&self) -> usize {
+        (self.storage.len() >> 1).ilog2() as usize
+    }
+
+    /// Hashes the root of the storage up to the root of the tree.
+    fn extend_to_root(&self, storage_root: H::Hash) -> H::Hash {
+        let storage_depth = self.storage_depth();
+        self.sparse_column[storage_depth..self.depth]
+            .iter()
+            .fold(storage_root, |hash, empty| H::hash_node(&hash, empty))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cascading::tests::TestHasher;
+
+    #[test]
+    fn test_attach() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let storage = MmapVec::create_from_path(file.path()).unwrap();
+        let mut tree = CascadingMerkleTree::<TestHasher, _>::new_with_leaves(storage, 10, &0, &[1]);
+
+        // Safety: the file is never shrunk
+        let mut attached = unsafe { CascadingMerkleTree::attach(file.path(), 10, &0) }.unwrap();
+        assert_eq!(attached.num_leaves().unwrap(), 1);
+        assert_eq!(attached.root().unwrap(), tree.root());
+        assert!(attached.proof(1).unwrap().is_none());
+
+        // Growing the storage is observed without flushing
+        tree.extend_from_slice(&[2, 3, 4, 5, 6]).unwrap();
+        tree.set_leaf(0, 7).unwrap();
+        assert_eq!(attached.num_leaves().unwrap(), 6);
+        assert_eq!(attached.root().unwrap(), tree.root());
+        for leaf in 0..6 {
+            let proof = attached.proof(leaf).unwrap().unwrap();
+            assert_eq!(proof.leaf, tree.get_leaf(leaf));
+            assert_eq!(proof.proof, tree.proof(leaf));
+            assert_eq!(proof.root, tree.root());
+        }
+
+        assert!(matches!(
+            unsafe { CascadingMerkleTree::<TestHasher, _>::attach(file.path(), 1, &0) },
+            Err(TreeError::InvalidStorage(_))
+        ));
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt::Debug;
+
+use bytemuck::Pod;
+use hasher::Hasher;
+
+use super::storage_ops::StorageOps;
+use super::CascadingMerkleTree;
+use crate::error::Result;
+
+/// Number of leaves buffered by a [`TreeBuilder`] and by
+/// [`CascadingMerkleTree::extend_from_iter`] before they are hashed into the
+/// tree.
+pub const DEFAULT_CHUNK_LEN: usize = 1 << 16;
+
+/// Builds a [`CascadingMerkleTree`] from a stream of leaves.
+///
+/// Unlike [`CascadingMerkleTree::new_with_leaves`], the leaves don't have to
+/// be collected first. They are buffered in chunks, and every full chunk is
+/// hashed into the storage, so apart from the storage the builder only holds
+/// one chunk of leaves in memory. With [`storage::MmapVec`] storage this
+/// builds trees with billions of leaves in little memory.
+///
+/// Chunks are not journaled. A build that fails or is interrupted must be
+/// started over with freshly created storage.
+pub struct TreeBuilder<H, S>
+where
+    H: Hasher,
+{
+    tree: CascadingMerkleTree<H, S>,
+    chunk: Vec<H::Hash>,
+    chunk_len: usize,
+}
+
+impl<H, S> TreeBuilder<H, S>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+{
+    /// Starts building an empty tree in the provided storage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is 0.
+    #[must_use]
+    pub fn new(storage: S, depth: usize, empty_value: &H::Hash) -> Self {
+        Self {
+            tree: CascadingMerkleTree::new(storage, depth, empty_value),
+            chunk: Vec::new(),
+            chunk_len: DEFAULT_CHUNK_LEN,
+        }
+    }
+
+    /// Sets the number of leaves buffered before they are hashed into the
+    /// tree. Larger chunks hash with more parallelism.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_len` is 0.
+    #[must_use]
+    pub fn with_chunk_len(mut self, chunk_len: usize) -> Self {
+        assert!(chunk_len > 0, "Chunks must hold at least one leaf");
+        self.chunk_len = chunk_len;
+        self
+    }
+
+    /// Returns the number of leaves added so far.
+    #[must_use]
+    pub fn num_leaves(&self) -> usize {
+        self.tree.num_leaves() + self.chunk.len()
+    }
+
+    /// Adds a leaf.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow.
+    pub fn push(&mut self, leaf: H::Hash) -> Result<()> {
+        self.chunk.push(leaf);
+        if self.chunk.len() >= self.chunk_len {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    /// Adds the leaves of an iterator.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow.
+    pub fn extend<I>(&mut self, leaves: I) -> Result<()>
+    where
+        I: IntoIterator<Item = H::Hash>,
+    {
+        let mut leaves = leaves.into_iter();
+        loop {
+            let missing = self.chunk_len - self.chunk.len();
+            self.chunk.extend(leaves.by_ref().take(missing));
+            if self.chunk.len() < self.chunk_len {
+                return Ok(());
+            }
+            self.write_chunk()?;
+        }
+    }
+
+    /// Hashes the remaining leaves into the tree and returns it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow.
+    pub fn build(mut self) -> Result<CascadingMerkleTree<H, S>> {
+        self.write_chunk()?;
+        Ok(self.tree)
+    }
+
+    fn write_chunk(&mut self) -> Result<()> {
+        if !self.chunk.is_empty() {
+            self.tree.extend_unjournaled(&self.chunk)?;
+            self.chunk.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keccak::keccak::Keccak256;
+
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let empty = [0; 32];
+        let leaves: Vec<_> = (0..=255u8).map(|i| [i; 32]).collect();
+        let expected =
+            CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], 10, &empty, &leaves);
+
+        for chunk_len in [1, 3, 64, 1000] {
+            let mut builder =
+                TreeBuilder::<Keccak256, _>::new(vec![], 10, &empty).with_chunk_len(chunk_len);
+            builder.push(leaves[0]).unwrap();
+            builder.extend(leaves[1..].iter().copied()).unwrap();
+            assert_eq!(builder.num_leaves(), leaves.len());
+            let tree = builder.build().unwrap();
+            assert_eq!(tree.root(), expected.root());
+            tree.validate().unwrap();
+        }
+
+        let tree = TreeBuilder::<Keccak256, Vec<_>>::new(vec![], 10, &empty)
+            .build()
+            .unwrap();
+        assert_eq!(tree.num_leaves(), 0);
+        assert_eq!(
+            tree.root(),
+            CascadingMerkleTree::<Keccak256>::new(vec![], 10, &empty).root()
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::ops::Range;
+
+use bytemuck::Pod;
+use hasher::Hasher;
+
+use super::storage_ops::StorageOps;
+use super::CascadingMerkleTree;
+use crate::error::Result;
+use crate::proof::{Branch, Proof};
+
+/// A [`CascadingMerkleTree`] that keeps the proofs it returned in a least
+/// recently used cache.
+///
+/// Cached proofs are always proofs for the current root: writes through the
+/// cached tree replace the siblings of the cached proofs whose subtrees
+/// contain a changed leaf, which are read from the tree instead of computing
+/// the proofs again. A single write changes one sibling of every proof,
+/// namely the one at the level where the paths of the changed leaf and the
+/// proven leaf meet.
+pub struct CachedTree<H, S = Vec<<H as Hasher>::Hash>>
+where
+    H: Hasher,
+{
+    tree: CascadingMerkleTree<H, S>,
+    capacity: usize,
+    /// Cached proofs and the tick of their last use, by leaf
+    proofs: HashMap<usize, (Proof<H>, u64)>,
+    /// Leaves of the cached proofs by the tick of their last use
+    recency: BTreeMap<u64, usize>,
+    tick: u64,
+}
+
+impl<H, S> CachedTree<H, S>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+{
+    /// Wraps a tree with a cache of at most `capacity` proofs. A capacity of
+    /// zero disables caching.
+    #[must_use]
+    pub fn new(tree: CascadingMerkleTree<H, S>, capacity: usize) -> Self {
+        Self {
+            tree,
+            capacity,
+            proofs: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn tree(&self) -> &CascadingMerkleTree<H, S> {
+        &self.tree
+    }
+
+    /// Returns the tree, dropping the cache.
+    #[must_use]
+    pub fn into_inner(self) -> CascadingMerkleTree<H, S> {
+        self.tree
+    }
+
+    #[must_use]
+    pub const fn root(&self) -> H::Hash {
+        self.tree.root()
+    }
+
+    #[must_use]
+    pub fn num_leaves(&self) -> usize {
+        self.tree.num_leaves()
+    }
+
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of cached proofs.
+    #[must_use]
+    pub fn cached(&self) -> usize {
+        self.proofs.len()
+    }
+
+    /// Drops all cached proofs.
+    pub fn clear(&mut self) {
+        self.proofs.clear();
+        self.recency.clear();
+    }
+
+    /// Like [`CascadingMerkleTree::proof`], returning the cached proof if
+    /// there is one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the leaf index is not less than the current number of
+    /// leaves.
+    pub fn proof(&mut self, leaf: usize) -> Proof<H> {
+        self.tick += 1;
+        if let Some((proof, used)) = self.proofs.get_mut(&leaf) {
+            self.recency.remove(used);
+            *used = self.tick;
+            self.recency.insert(self.tick, leaf);
+            return proof.clone();
+        }
+
+        let proof = self.tree.proof(leaf);
+        if self.capacity == 0 {
+            return proof;
+        }
+        if self.proofs.len() == self.capacity {
+            if let Some((_, evicted)) = self.recency.pop_first() {
+                self.proofs.remove(&evicted);
+            }
+        }
+        self.proofs.insert(leaf, (proof.clone(), self.tick));
+        self.recency.insert(self.tick, leaf);
+        proof
+    }
+
+    /// See [`CascadingMerkleTree::set_leaf`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to journal or flush the write.
+    pub fn set_leaf(&mut self, leaf: usize, value: H::Hash) -> Result<()> {
+        let result = self.tree.set_leaf(leaf, value);
+        self.refresh(leaf..leaf + 1);
+        result
+    }
+
+    /// See [`CascadingMerkleTree::push`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow, journal or flush the
+    /// write.
+    pub fn push(&mut self, leaf: H::Hash) -> Result<()> {
+        let first_leaf = self.tree.num_leaves();
+        let result = self.tree.push(leaf);
+        self.refresh(first_leaf..self.tree.num_leaves());
+        result
+    }
+
+    /// See [`CascadingMerkleTree::extend_from_slice`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow, journal or flush the
+    /// write.
+    pub fn extend_from_slice(&mut self, leaves: &[H::Hash]) -> Result<()> {
+        let first_leaf = self.tree.num_leaves();
+        let result = self.tree.extend_from_slice(leaves);
+        self.refresh(first_leaf..self.tree.num_leaves());
+        result
+    }
+
+    /// Replaces the siblings of the cached proofs whose subtree contains a
+    /// leaf in `changed`.
+    fn refresh(&mut self, changed: Range<usize>) {
+        if changed.is_empty() {
+            return;
+        }
+        let depth = self.tree.depth();
+        for (&leaf, (proof, _)) in &mut self.proofs {
+            for (height, branch) in proof.0.iter_mut().enumerate() {
+                let sibling = (leaf >> height) ^ 1;
+                let first = sibling << height;
+                let last = first + (1 << height) - 1;
+                if first < changed.end && changed.start <= last {
+                    let hash = self.tree.get_node(depth - height, sibling);
+                    *branch = match branch {
+                        Branch::Left(_) => Branch::Left(hash),
+                        Branch::Right(_) => Branch::Right(hash),
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+    use crate::cascading::tests::TestHasher;
+
+    #[test]
+    fn test_proofs_follow_writes() {
+        let tree = CascadingMerkleTree::<TestHasher>::new_with_leaves(vec![], 12, &0, &[1, 2, 3]);
+        let mut cached = CachedTree::new(tree, 8);
+
+        let mut rng = thread_rng();
+        for _ in 0..200 {
+            let num_leaves = cached.num_leaves();
+            match rng.gen_range(0..4) {
+                0 => cached.push(rng.gen_range(1..100)).unwrap(),
+                1 => {
+                    let leaves: Vec<usize> = (0..rng.gen_range(0..20))
+                        .map(|_| rng.gen_range(1..100))
+                        .collect();
+                    cached.extend_from_slice(&leaves).unwrap();
+                }
+                2 => cached
+                    .set_leaf(rng.gen_range(0..num_leaves), rng.gen_range(1..100))
+                    .unwrap(),
+                _ => {}
+            }
+            let leaf = rng.gen_range(0..cached.num_leaves().min(12));
+            assert_eq!(cached.proof(leaf), cached.tree().proof(leaf));
+            assert!(cached.cached() <= cached.capacity());
+        }
+        for leaf in 0..12 {
+            assert_eq!(cached.proof(leaf), cached.tree().proof(leaf));
+        }
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let tree = CascadingMerkleTree::<TestHasher>::new_with_leaves(vec![], 4, &0, &[1; 8]);
+        let mut cached = CachedTree::new(tree, 2);
+        cached.proof(0);
+        cached.proof(1);
+        cached.proof(0);
+        cached.proof(2);
+        assert_eq!(cached.cached(), 2);
+        assert!(cached.proofs.contains_key(&0));
+        assert!(!cached.proofs.contains_key(&1));
+
+        let mut uncached = CachedTree::new(cached.into_inner(), 0);
+        assert_eq!(uncached.proof(3), uncached.tree().proof(3));
+        assert_eq!(uncached.cached(), 0);
+    }
+}
@@ -0,0 +1,223 @@
+//! A persistent hash table from leaf hashes to leaf indices.
+//!
+//! The table is open addressed with linear probing and stored in a
+//! [`GenericStorage<usize>`] as a header followed by the slots. Slots only
+//! hold leaf indices, lookups compare the leaves of candidate slots with the
+//! tree, so the table never has to store the hashes themselves.
+//!
+//! ```markdown
+//! [occupied, num_leaves, root fingerprint, slot 0, slot 1, ...]
+//! ```
+
+use std::ops::{Deref, DerefMut, Range};
+
+use bytemuck::Pod;
+use storage::GenericStorage;
+
+use crate::error::Result;
+
+const OCCUPIED: usize = 0;
+const NUM_LEAVES: usize = 1;
+const FINGERPRINT: usize = 2;
+const HEADER_LEN: usize = 3;
+
+const MIN_CAPACITY: usize = 8;
+
+/// Slot value of a slot that was never used.
+const EMPTY: usize = 0;
+/// Slot value of a slot whose entry was removed.
+const TOMBSTONE: usize = usize::MAX;
+
+/// Fingerprint marking an index that is being updated.
+const INVALID: usize = 0;
+
+/// Object safe part of [`GenericStorage<usize>`] used by the index, so that
+/// the tree type doesn't depend on the index storage.
+trait IndexStorage: Deref<Target = [usize]> + DerefMut + Send + Sync {
+    fn reset(&mut self, len: usize) -> Result<()>;
+
+    fn mark_changed(&mut self, range: Range<usize>);
+
+    fn flush_storage(&self) -> Result<()>;
+}
+
+impl<I: GenericStorage<usize>> IndexStorage for I {
+    fn reset(&mut self, len: usize) -> Result<()> {
+        self.clear();
+        Ok(self.try_extend(std::iter::repeat(EMPTY).take(len))?)
+    }
+
+    fn mark_changed(&mut self, range: Range<usize>) {
+        GenericStorage::mark_changed(self, range);
+    }
+
+    fn flush_storage(&self) -> Result<()> {
+        Ok(self.flush()?)
+    }
+}
+
+pub struct LeafIndex {
+    storage: Box<dyn IndexStorage>,
+}
+
+impl Clone for LeafIndex {
+    /// Clones the index into memory.
+    fn clone(&self) -> Self {
+        Self {
+            storage: Box::new(self.storage.to_vec()),
+        }
+    }
+}
+
+impl std::fmt::Debug for LeafIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LeafIndex")
+            .field("capacity", &self.capacity())
+            .field("occupied", &self.storage.get(OCCUPIED))
+            .finish()
+    }
+}
+
+impl LeafIndex {
+    pub fn new<I: GenericStorage<usize> + 'static>(storage: I) -> Self {
+        Self {
+            storage: Box::new(storage),
+        }
+    }
+
+    /// Returns whether the storage holds a complete index of the tree with
+    /// the given number of leaves and root.
+    pub fn is_current<T: Pod>(&self, num_leaves: usize, root: &T) -> bool {
+        let len = self.storage.len();
+        len >= HEADER_LEN + MIN_CAPACITY
+            && (len - HEADER_LEN).is_power_of_two()
+            && self.storage[OCCUPIED] <= self.capacity()
+            && self.storage[NUM_LEAVES] == num_leaves
+            && self.storage[FINGERPRINT] == fingerprint(root)
+    }
+
+    /// Marks the index as incomplete until [`LeafIndex::commit`] is called,
+    /// so an update interrupted by a crash is detected by
+    /// [`LeafIndex::is_current`].
+    pub fn begin(&mut self) {
+        if self.storage.len() > FINGERPRINT {
+            self.set(FINGERPRINT, INVALID);
+        }
+    }
+
+    /// Marks the index as complete for the tree with the given number of
+    /// leaves and root.
+    pub fn commit<T: Pod>(&mut self, num_leaves: usize, root: &T) {
+        self.set(NUM_LEAVES, num_leaves);
+        self.set(FINGERPRINT, fingerprint(root));
+    }
+
+    /// Returns whether inserting `additional` entries requires a rebuild.
+    pub fn needs_rebuild(&self, additional: usize) -> bool {
+        let capacity = self.capacity();
+        capacity == 0 || (self.storage[OCCUPIED] + additional) * 4 > capacity * 3
+    }
+
+    /// Clears the index and inserts the non-empty leaves out of
+    /// `num_leaves`, with room for `additional` more.
+    pub fn rebuild<T: Pod + Eq>(
+        &mut self,
+        leaves: impl Iterator<Item = T>,
+        num_leaves: usize,
+        empty: &T,
+        additional: usize,
+    ) -> Result<()> {
+        let capacity = ((num_leaves + additional) * 2)
+            .next_power_of_two()
+            .max(MIN_CAPACITY);
+        self.storage.reset(HEADER_LEN + capacity)?;
+        for (leaf, hash) in leaves.enumerate() {
+            if hash != *empty {
+                self.insert(leaf, &hash);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds an entry, which must fit without a rebuild.
+    pub fn insert<T: Pod>(&mut self, leaf: usize, hash: &T) {
+        let mask = self.capacity() - 1;
+        let mut slot = bucket(hash) & mask;
+        while !matches!(self.storage[HEADER_LEN + slot], EMPTY | TOMBSTONE) {
+            slot = (slot + 1) & mask;
+        }
+        if self.storage[HEADER_LEN + slot] == EMPTY {
+            self.set(OCCUPIED, self.storage[OCCUPIED] + 1);
+        }
+        self.set(HEADER_LEN + slot, leaf + 1);
+    }
+
+    /// Removes the entry of a leaf that held `hash`, if there is one.
+    pub fn remove<T: Pod>(&mut self, leaf: usize, hash: &T) {
+        let slot = self
+            .probe(hash)
+            .find(|&slot| self.storage[slot] == leaf + 1);
+        if let Some(slot) = slot {
+            self.set(slot, TOMBSTONE);
+        }
+    }
+
+    /// Returns the largest leaf index holding `hash`, using `get_leaf` to
+    /// check the candidates.
+    pub fn find<T: Pod + Eq>(&self, hash: &T, get_leaf: impl Fn(usize) -> T) -> Option<usize> {
+        self.probe(hash)
+            .filter_map(|slot| match self.storage[slot] {
+                TOMBSTONE => None,
+                value => Some(value - 1),
+            })
+            .filter(|&leaf| get_leaf(leaf) == *hash)
+            .max()
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.storage.flush_storage()
+    }
+
+    /// Writes a value and records the change for the storage.
+    fn set(&mut self, index: usize, value: usize) {
+        self.storage[index] = value;
+        self.storage.mark_changed(index..index + 1);
+    }
+
+    fn capacity(&self) -> usize {
+        self.storage.len().saturating_sub(HEADER_LEN)
+    }
+
+    /// Returns the storage indices of the used slots a hash may be in, up to
+    /// the first empty slot.
+    fn probe<'a, T: Pod>(&'a self, hash: &T) -> impl Iterator<Item = usize> + 'a {
+        let capacity = self.capacity();
+        let start = if capacity == 0 {
+            0
+        } else {
+            bucket(hash) & (capacity - 1)
+        };
+        (0..capacity)
+            .map(move |i| HEADER_LEN + ((start + i) & (capacity - 1)))
+            .take_while(|&slot| self.storage[slot] != EMPTY)
+    }
+}
+
+/// FNV-1a, which unlike the standard library hashers is guaranteed to stay
+/// the same across releases, as the index is persisted.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn bucket<T: Pod>(hash: &T) -> usize {
+    fnv1a(bytemuck::bytes_of(hash)) as usize
+}
+
+fn fingerprint<T: Pod>(root: &T) -> usize {
+    match bucket(root) {
+        INVALID => 1,
+        value => value,
+    }
+}
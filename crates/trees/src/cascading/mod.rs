@@ -1,15 +1,76 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::ops::Range;
 
 use bytemuck::Pod;
-use color_eyre::eyre::{ensure, Result};
 use derive_where::derive_where;
 use hasher::Hasher;
+use rayon::prelude::*;
+use storage::{BatchJournal, GenericStorage, GrowthPolicy, Provenance, ProvenanceStorage};
 
+use crate::error::{Result, TreeError};
+use crate::multi_proof::MultiProof;
+use crate::parallelism::Parallelism;
 use crate::proof::{Branch, Proof};
 
-mod storage_ops;
-
+mod attached;
+mod builder;
+mod cached;
+mod leaf_index;
+mod scrub;
+mod shared;
+pub(crate) mod storage_ops;
+mod watcher;
+
+pub use self::attached::{AttachedProof, AttachedTree};
+pub use self::builder::{TreeBuilder, DEFAULT_CHUNK_LEN};
+pub use self::cached::CachedTree;
+use self::leaf_index::LeafIndex;
+use self::scrub::ScrubCursor;
+pub use self::scrub::{Scrub, ScrubChunk};
+pub use self::shared::{TreeReader, TreeWriter};
 use self::storage_ops::{sparse_fill_partial_subtree, StorageOps};
+use self::watcher::Watchers;
+pub use self::watcher::{RootEvent, TreeWatcher};
+
+/// How [`CascadingMerkleTree::merge_from`] treats leaves that are already
+/// present in the tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Append every non-empty leaf, even if the same hash is already present.
+    AppendAll,
+    /// Append only leaves whose hash is not present yet.
+    SkipDuplicates,
+}
+
+/// A node of the storage that is inconsistent with the rest of the tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeMismatch<T> {
+    /// Index of the node in the storage.
+    pub index: usize,
+    /// Height of the node above the leaves.
+    pub height: usize,
+    /// The value the node should have.
+    pub expected: T,
+    /// The value stored for the node.
+    pub actual: T,
+}
+
+/// When a [`CascadingMerkleTree`] flushes its storage after a write.
+///
+/// Flushing only affects storage backed by a file, such as
+/// [`storage::MmapVec`]. Without flushing, writes survive the process crashing
+/// but may be lost if the machine does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Leave flushing to the operating system.
+    #[default]
+    None,
+    /// Flush after every `n` writes.
+    EveryN(usize),
+    /// Flush after every write.
+    EveryWrite,
+}
 
 /// A dynamically growable array represented merkle tree.
 ///
@@ -47,6 +108,15 @@ where
     empty_value: H::Hash,
     sparse_column: Vec<H::Hash>,
     storage: S,
+    durability: Durability,
+    #[derive_where(skip(EqHashOrd))]
+    unflushed_writes: usize,
+    #[derive_where(skip(EqHashOrd))]
+    leaf_index: Option<LeafIndex>,
+    #[derive_where(skip(EqHashOrd))]
+    watchers: Watchers<H::Hash>,
+    #[derive_where(skip(EqHashOrd))]
+    scrub_cursor: ScrubCursor,
     _marker: std::marker::PhantomData<H>,
 }
 
@@ -58,6 +128,10 @@ where
     S: StorageOps<H>,
 {
     /// Use to open a previously initialized tree
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(depth))
+    )]
     pub fn restore(
         storage: S,
         depth: usize,
@@ -70,23 +144,35 @@ where
         Ok(tree)
     }
 
+    /// Opens a previously initialized tree, repairing inconsistent nodes
+    /// instead of failing validation.
+    ///
+    /// Returns the tree together with the nodes that were repaired, see
+    /// [`Self::repair`].
+    pub fn restore_and_repair(
+        storage: S,
+        depth: usize,
+        empty_value: &H::Hash,
+    ) -> Result<(Self, Vec<NodeMismatch<H::Hash>>)> {
+        let mut tree = Self::restore_unchecked(storage, depth, empty_value)?;
+        let repaired = tree.repair()?;
+        Ok((tree, repaired))
+    }
+
     /// Restores a tree from the provided storage
     ///
+    /// A write left pending in the storage journal is completed first, see
+    /// [`storage::JournaledStorage`].
+    ///
     /// Invalid storage will result in unpredictable behavior
     pub fn restore_unchecked(
         storage: S,
         depth: usize,
         empty_value: &H::Hash,
     ) -> Result<CascadingMerkleTree<H, S>> {
-        let len = storage.len();
-
-        storage.validate_const()?;
-
-        ensure!(depth > 0, "Tree depth must be greater than 0");
-        ensure!(
-            len <= 2usize.checked_pow(depth as u32 + 1).unwrap(),
-            "Storage length must be less than or equal to 2^(depth + 1)"
-        );
+        if depth == 0 {
+            return Err(TreeError::ZeroDepth);
+        }
 
         let sparse_column = Self::sparse_column(depth, empty_value);
 
@@ -96,17 +182,33 @@ where
             empty_value: *empty_value,
             sparse_column,
             storage,
+            durability: Durability::None,
+            unflushed_writes: 0,
+            leaf_index: None,
+            watchers: Watchers::default(),
+            scrub_cursor: ScrubCursor::default(),
             _marker: std::marker::PhantomData,
         };
 
+        tree.replay_journal()?;
+
+        let len = tree.storage.len();
+        tree.storage.validate_const()?;
+        if len > 2usize.checked_pow(depth as u32 + 1).unwrap() {
+            return Err(TreeError::InvalidStorage(format!(
+                "length ({len}) must be less than or equal to 2^(depth + 1)"
+            )));
+        }
+
         tree.recompute_root();
 
         let num_leaves = tree.num_leaves();
-        ensure!(
-            num_leaves <= len >> 1,
-            "Number of leaves ({num_leaves}) must be less than or equal to half the storage \
-             length ({len})"
-        );
+        if num_leaves > len >> 1 {
+            return Err(TreeError::InvalidStorage(format!(
+                "number of leaves ({num_leaves}) must be less than or equal to half the length \
+                 ({len})"
+            )));
+        }
 
         Ok(tree)
     }
@@ -120,7 +222,19 @@ where
     }
 
     /// Create and initialize a tree in the provided storage
+    ///
+    /// The storage grows with the tree according to its [`GrowthPolicy`].
+    /// With [`GrowthPolicy::PreallocateFullDepth`] the storage of the full
+    /// depth is reserved here, so pushes never grow it later.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is zero, or if the storage can't be preallocated.
     #[must_use]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(depth, num_leaves = leaves.len()))
+    )]
     pub fn new_with_leaves(
         mut storage: S,
         depth: usize,
@@ -129,6 +243,16 @@ where
     ) -> CascadingMerkleTree<H, S> {
         assert!(depth > 0, "Tree depth must be greater than 0");
 
+        if storage.growth_policy() == GrowthPolicy::PreallocateFullDepth {
+            let full_len = 2usize
+                .checked_pow(depth as u32 + 1)
+                .expect("Tree depth too large to preallocate");
+            storage.clear();
+            storage
+                .reserve(full_len)
+                .expect("Failed to preallocate storage");
+        }
+
         let sparse_column = Self::sparse_column(depth, empty_value);
         storage.populate_with_leaves(&sparse_column, empty_value, leaves);
 
@@ -138,6 +262,11 @@ where
             empty_value: *empty_value,
             sparse_column,
             storage,
+            durability: Durability::None,
+            unflushed_writes: 0,
+            leaf_index: None,
+            watchers: Watchers::default(),
+            scrub_cursor: ScrubCursor::default(),
             _marker: std::marker::PhantomData,
         };
 
@@ -145,6 +274,182 @@ where
         tree
     }
 
+    /// Like [`Self::new_with_leaves`], hashing the leaves on the thread pool
+    /// chosen by `parallelism`.
+    #[must_use]
+    pub fn new_with_leaves_in(
+        storage: S,
+        depth: usize,
+        empty_value: &H::Hash,
+        leaves: &[H::Hash],
+        parallelism: Parallelism<'_>,
+    ) -> CascadingMerkleTree<H, S>
+    where
+        Self: Send,
+    {
+        parallelism.install(|| Self::new_with_leaves(storage, depth, empty_value, leaves))
+    }
+
+    /// Returns the durability policy of the tree.
+    #[must_use]
+    pub const fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Sets when the storage is flushed after a write. Trees start with
+    /// [`Durability::None`].
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Flushes the storage, regardless of the durability policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to flush.
+    pub fn flush(&mut self) -> Result<()> {
+        self.storage.flush()?;
+        if let Some(index) = &self.leaf_index {
+            index.flush()?;
+        }
+        self.unflushed_writes = 0;
+        Ok(())
+    }
+
+    /// Flushes the storage if the durability policy requires it after
+    /// another write.
+    fn record_write(&mut self) -> Result<()> {
+        self.unflushed_writes += 1;
+        let flush = match self.durability {
+            Durability::None => false,
+            Durability::EveryN(n) => self.unflushed_writes >= n,
+            Durability::EveryWrite => true,
+        };
+        if flush {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Subscribes to the changes of the tree.
+    ///
+    /// The watcher receives a [`RootEvent`] with the new root and the range
+    /// of changed leaves after every [`Self::set_leaf`], [`Self::push`],
+    /// [`Self::extend_from_slice`] and [`Self::apply_batch`], including the
+    /// ones applied through a [`TreeWriter`]. Clones of the tree don't notify
+    /// the watchers of the original.
+    pub fn watch(&mut self) -> TreeWatcher<H::Hash> {
+        self.watchers.subscribe()
+    }
+
+    /// Maintains an index from leaf hashes to leaf indices in `storage`,
+    /// which makes [`Self::get_leaf_from_hash`] and [`Self::proof_from_hash`]
+    /// take constant time.
+    ///
+    /// The index is kept up to date by every write to the tree and flushed
+    /// with it. An index persisted for the current state of the tree is
+    /// reused, otherwise, e.g. if the tree was written to without the index,
+    /// it is rebuilt from the leaves.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index has to be rebuilt and the storage fails
+    /// to grow.
+    pub fn with_leaf_index<I>(mut self, storage: I) -> Result<Self>
+    where
+        I: GenericStorage<usize> + 'static,
+    {
+        let mut index = LeafIndex::new(storage);
+        let num_leaves = self.num_leaves();
+        if !index.is_current(num_leaves, &self.root) {
+            index.begin();
+            index.rebuild(self.storage.leaves(), num_leaves, &self.empty_value, 0)?;
+            index.commit(num_leaves, &self.root);
+        }
+        self.leaf_index = Some(index);
+        Ok(self)
+    }
+
+    /// Returns whether the tree maintains a leaf index, see
+    /// [`Self::with_leaf_index`].
+    #[must_use]
+    pub const fn has_leaf_index(&self) -> bool {
+        self.leaf_index.is_some()
+    }
+
+    /// Updates the leaf index after `first_leaf` was overwritten, replacing
+    /// `replaced`, or after the leaves from `first_leaf` on were appended.
+    ///
+    /// If the index fails to grow it is dropped, as it no longer covers the
+    /// whole tree.
+    fn update_leaf_index(&mut self, first_leaf: usize, replaced: Option<H::Hash>) -> Result<()> {
+        let Some(index) = self.leaf_index.as_mut() else {
+            return Ok(());
+        };
+        let num_leaves = self.storage.num_leaves();
+        let written = match replaced {
+            Some(_) => first_leaf..first_leaf + 1,
+            None => first_leaf..num_leaves,
+        };
+
+        index.begin();
+        if index.needs_rebuild(written.len()) {
+            if let Err(e) = index.rebuild(self.storage.leaves(), num_leaves, &self.empty_value, 0) {
+                self.leaf_index = None;
+                return Err(TreeError::LeafIndex(Box::new(e)));
+            }
+        } else {
+            if let Some(hash) = replaced {
+                index.remove(first_leaf, &hash);
+            }
+            for leaf in written {
+                let hash = self.storage[storage_ops::index_from_leaf(leaf)];
+                if hash != self.empty_value {
+                    index.insert(leaf, &hash);
+                }
+            }
+        }
+        index.commit(num_leaves, &self.root);
+        Ok(())
+    }
+
+    /// Records a write in the journal of the storage, if it has one.
+    fn journal_begin(&mut self, first_leaf: usize, leaves: &[H::Hash]) -> Result<()> {
+        if let Some(journal) = self.storage.journal() {
+            journal.journal_begin(first_leaf, leaves)?;
+        }
+        Ok(())
+    }
+
+    /// Commits the last write recorded with [`Self::journal_begin`].
+    fn journal_commit(&mut self) -> Result<()> {
+        if let Some(journal) = self.storage.journal() {
+            journal.journal_commit()?;
+        }
+        Ok(())
+    }
+
+    /// Completes a write once the tree holds its leaves: commits the journal
+    /// entry, then updates the leaf index, notifies the watchers of the
+    /// `changed` leaves and records the write.
+    ///
+    /// Committing first means an index or flush error never leaves the entry
+    /// pending, where restoring would replay a write the tree already holds,
+    /// or apply a batch a second time.
+    fn finish_write(
+        &mut self,
+        first_leaf: usize,
+        replaced: Option<H::Hash>,
+        changed: Range<usize>,
+    ) -> Result<()> {
+        self.journal_commit()?;
+        let indexed = self.update_leaf_index(first_leaf, replaced);
+        if !changed.is_empty() {
+            self.watchers.notify(self.root, changed);
+        }
+        indexed.and(self.record_write())
+    }
+
     /// Returns the depth of the tree.
     #[must_use]
     pub const fn depth(&self) -> usize {
@@ -157,6 +462,12 @@ where
         self.root
     }
 
+    /// Returns the value of empty leaves.
+    #[must_use]
+    pub const fn empty_value(&self) -> H::Hash {
+        self.empty_value
+    }
+
     /// Returns the the total number of leaves that have been inserted into the
     /// tree. It's important to note that this is not the same as total
     /// capacity of leaves. Leaves that have manually been set to empty
@@ -168,19 +479,47 @@ where
 
     /// Sets the value at the given index.
     ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to journal the write, in which
+    /// case the tree is left unchanged, or if updating the leaf index or
+    /// flushing fails after the write was applied.
+    ///
     /// # Panics
     ///
     /// Panics if the leaf index is not less than the current
     /// number of leaves.
-    pub fn set_leaf(&mut self, leaf: usize, value: H::Hash) {
+    pub fn set_leaf(&mut self, leaf: usize, value: H::Hash) -> Result<()> {
         assert!(leaf < self.num_leaves(), "Leaf index out of bounds");
+        self.journal_begin(leaf, &[value])?;
         let index = storage_ops::index_from_leaf(leaf);
+        let replaced = self.storage[index];
         self.storage[index] = value;
+        self.storage.mark_changed(index..index + 1);
         self.storage.propagate_up(index);
         self.recompute_root();
+        self.finish_write(leaf, Some(replaced), leaf..leaf + 1)
     }
 
+    /// Appends a leaf to the tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow or to journal the write,
+    /// in which case the tree is left unchanged, or if updating the leaf
+    /// index or flushing fails after the write was applied.
     pub fn push(&mut self, leaf: H::Hash) -> Result<()> {
+        let first_leaf = self.num_leaves();
+        self.journal_begin(first_leaf, &[leaf])?;
+        if let Err(e) = self.push_unjournaled(leaf) {
+            // The tree is unchanged, so committing discards the entry
+            self.journal_commit()?;
+            return Err(e);
+        }
+        self.finish_write(first_leaf, None, first_leaf..first_leaf + 1)
+    }
+
+    fn push_unjournaled(&mut self, leaf: H::Hash) -> Result<()> {
         let index = storage_ops::index_from_leaf(self.num_leaves());
         let storage_len = self.storage.len();
 
@@ -189,12 +528,13 @@ where
         if index >= storage_len {
             debug_assert!(storage_len.is_power_of_two());
             self.storage
-                .extend(std::iter::repeat(self.empty_value).take(storage_len));
+                .try_extend(std::iter::repeat(self.empty_value).take(storage_len))?;
             let subtree = &mut self.storage[storage_len..(storage_len << 1)];
             sparse_fill_partial_subtree::<H>(subtree, &self.sparse_column, 0..(storage_len >> 1));
         }
 
         self.storage[index] = leaf;
+        self.storage.mark_changed(index..index + 1);
         self.storage.increment_num_leaves(1);
         self.storage.propagate_up(index);
         self.recompute_root();
@@ -241,9 +581,29 @@ where
         Proof(proof)
     }
 
+    /// Returns a single proof for all the given leaves, see [`MultiProof`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any leaf index is not less than the current number of
+    /// leaves.
+    #[must_use]
+    pub fn multi_proof(&self, leaves: &[usize]) -> MultiProof<H> {
+        let num_leaves = self.num_leaves();
+        assert!(
+            leaves.iter().all(|&leaf| leaf < num_leaves),
+            "Leaf index out of bounds"
+        );
+        MultiProof::build(self.depth, leaves, |height, offset| {
+            self.get_node(self.depth - height, offset)
+        })
+    }
+
     /// Returns the Merkle proof for the given leaf hash.
-    /// Leaves are scanned from right to left.
-    /// This is a slow operation and `proof` should be used when possible.
+    /// Leaves are scanned from right to left, unless the tree maintains a
+    /// leaf index, see [`Self::with_leaf_index`].
+    /// Without the index this is a slow operation and `proof` should be used
+    /// when possible.
     #[must_use]
     pub fn proof_from_hash(&self, leaf: H::Hash) -> Option<Proof<H>> {
         let leaf = self.get_leaf_from_hash(leaf)?;
@@ -284,9 +644,21 @@ where
         self.storage.get(index).copied().unwrap_or(self.empty_value)
     }
 
-    /// Returns the leaf index for the given leaf hash.
+    /// Returns the largest leaf index holding the given leaf hash.
+    ///
+    /// Leaves are scanned from right to left, unless the tree maintains a
+    /// leaf index, see [`Self::with_leaf_index`].
     #[must_use]
     pub fn get_leaf_from_hash(&self, hash: H::Hash) -> Option<usize> {
+        // Empty leaves are not indexed
+        if let Some(index) = self
+            .leaf_index
+            .as_ref()
+            .filter(|_| hash != self.empty_value)
+        {
+            return index.find(&hash, |leaf| self.get_leaf(leaf));
+        }
+
         let num_leaves = self.num_leaves();
         if num_leaves == 0 {
             return None;
@@ -314,7 +686,44 @@ where
 
     /// Returns an iterator over all leaf hashes.
     pub fn leaves(&self) -> impl Iterator<Item = H::Hash> + '_ {
-        self.storage.leaves()
+        self.leaves_range(0..self.num_leaves()).flatten().copied()
+    }
+
+    /// Returns the leaves in `range` without copying them, as consecutive
+    /// slices of the storage.
+    ///
+    /// The storage keeps the leaves of each power of two block, `2^k..2^(k +
+    /// 1)`, next to each other, so a range spans at most one slice per block
+    /// it intersects.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is decreasing or ends after the last leaf.
+    pub fn leaves_range(&self, range: Range<usize>) -> impl Iterator<Item = &[H::Hash]> + '_ {
+        assert!(
+            range.start <= range.end && range.end <= self.num_leaves(),
+            "leaf range {range:?} is out of bounds for {} leaves",
+            self.num_leaves()
+        );
+        let Range { mut start, end } = range;
+        std::iter::from_fn(move || {
+            if start >= end {
+                return None;
+            }
+            let block_end = end.min((start + 1).next_power_of_two());
+            let first = storage_ops::index_from_leaf(start);
+            let last = storage_ops::index_from_leaf(block_end - 1);
+            start = block_end;
+            Some(&self.storage[first..=last])
+        })
+    }
+
+    /// Returns a parallel iterator over all leaf hashes.
+    pub fn par_leaves(&self) -> impl IndexedParallelIterator<Item = H::Hash> + '_ {
+        let storage = &self.storage;
+        (0..self.num_leaves())
+            .into_par_iter()
+            .map(move |leaf| storage[storage_ops::index_from_leaf(leaf)])
     }
 
     /// Returns the `sparse_column` for the given depth and empty_value.
@@ -354,6 +763,10 @@ where
 
     /// Validates all elements of the storage, ensuring that they
     /// correspond to a valid tree.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(num_leaves = self.num_leaves()))
+    )]
     pub fn validate(&self) -> Result<()> {
         debug_assert_eq!(
             self.root,
@@ -363,6 +776,63 @@ where
         self.storage.validate(&self.empty_value)
     }
 
+    /// Returns every node of the storage that is inconsistent with the rest
+    /// of the tree, ordered by height and index. The result is empty exactly
+    /// when [`Self::validate`] succeeds.
+    ///
+    /// Leaves past the last leaf are expected to be empty, and every other
+    /// node to be the hash of its stored children. A single corrupted node
+    /// therefore shows up as a mismatch of its parent.
+    #[must_use]
+    pub fn validate_detailed(&self) -> Vec<NodeMismatch<H::Hash>> {
+        self.storage.mismatches(&self.empty_value)
+    }
+
+    /// Rehashes every node of the storage from the leaves and clears leaves
+    /// past the last leaf, returning the nodes that changed with the values
+    /// they were repaired to.
+    ///
+    /// This takes linear time in the size of the storage, but is much faster
+    /// than rebuilding the tree in new storage. Leaves themselves are trusted
+    /// and never changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing the repaired storage fails.
+    pub fn repair(&mut self) -> Result<Vec<NodeMismatch<H::Hash>>> {
+        let repaired = self.storage.repair(&self.empty_value);
+        self.recompute_root();
+        if !repaired.is_empty() {
+            self.flush()?;
+        }
+        Ok(repaired)
+    }
+
+    /// Shrinks the storage to the smallest power of two that holds the
+    /// current leaves and releases the capacity past it. Storage keeps its
+    /// high-water mark otherwise, e.g. an [`MmapVec`] file that once held a
+    /// larger tree.
+    ///
+    /// The root and the leaves are unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage can't be truncated, see
+    /// [`GenericStorage::truncate`], or flushing fails.
+    ///
+    /// [`MmapVec`]: storage::MmapVec
+    /// [`GenericStorage::truncate`]: storage::GenericStorage::truncate
+    pub fn shrink_to_fit(&mut self) -> Result<()> {
+        let len = self.num_leaves().next_power_of_two() << 1;
+        self.storage.truncate(len)?;
+        debug_assert_eq!(
+            self.root,
+            self.compute_from_storage_tip(0),
+            "Root hash changed by shrinking the storage"
+        );
+        self.record_write()
+    }
+
     /// Extends the tree with the given leaves in parallel.
     ///
     /// ```markdown
@@ -373,10 +843,125 @@ where
     ///   2     5   [  10    11 ]
     /// 1  3  6  7  [12 13 14 15]
     ///  ```
-    pub fn extend_from_slice(&mut self, leaves: &[H::Hash]) {
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow or to journal the write,
+    /// in which case the tree is left unchanged, or if updating the leaf
+    /// index or flushing fails after the write was applied.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(num_leaves = leaves.len()))
+    )]
+    pub fn extend_from_slice(&mut self, leaves: &[H::Hash]) -> Result<()> {
         if leaves.is_empty() {
-            return;
+            return Ok(());
         }
+        let first_leaf = self.num_leaves();
+        self.journal_begin(first_leaf, leaves)?;
+        if let Err(e) = self.extend_unjournaled(leaves) {
+            // The tree is unchanged, so committing discards the entry
+            self.journal_commit()?;
+            return Err(e);
+        }
+        self.finish_write(first_leaf, None, first_leaf..first_leaf + leaves.len())
+    }
+
+    /// Like [`Self::extend_from_slice`], hashing the leaves on the thread
+    /// pool chosen by `parallelism`.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::extend_from_slice`].
+    pub fn extend_from_slice_in(
+        &mut self,
+        leaves: &[H::Hash],
+        parallelism: Parallelism<'_>,
+    ) -> Result<()>
+    where
+        Self: Send,
+    {
+        parallelism.install(|| self.extend_from_slice(leaves))
+    }
+
+    /// Appends the leaves of an iterator, in chunks of [`DEFAULT_CHUNK_LEN`]
+    /// leaves, without collecting them first.
+    ///
+    /// # Errors
+    ///
+    /// Like [`Self::extend_from_slice`] for every chunk. Each chunk is a
+    /// separate write, so the chunks before a failing one stay appended.
+    pub fn extend_from_iter<I>(&mut self, leaves: I) -> Result<()>
+    where
+        I: IntoIterator<Item = H::Hash>,
+    {
+        let mut leaves = leaves.into_iter();
+        let mut chunk = Vec::with_capacity(leaves.size_hint().0.min(DEFAULT_CHUNK_LEN));
+        loop {
+            chunk.extend(leaves.by_ref().take(DEFAULT_CHUNK_LEN));
+            if chunk.is_empty() {
+                return Ok(());
+            }
+            self.extend_from_slice(&chunk)?;
+            chunk.clear();
+        }
+    }
+
+    /// Appends the non-empty leaves of `other` to this tree.
+    ///
+    /// Returns a mapping from each leaf index in `other` to the index of the
+    /// same hash in this tree. Empty leaves map to `None`. With
+    /// [`MergePolicy::SkipDuplicates`], leaves that are already present map to
+    /// the existing index.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow or to journal the write,
+    /// in which case the tree is left unchanged.
+    pub fn merge_from<S2>(
+        &mut self,
+        other: &CascadingMerkleTree<H, S2>,
+        policy: MergePolicy,
+    ) -> Result<Vec<Option<usize>>>
+    where
+        H::Hash: std::hash::Hash,
+        S2: StorageOps<H>,
+    {
+        let mut known: HashMap<H::Hash, usize> = HashMap::new();
+        if policy == MergePolicy::SkipDuplicates {
+            for (index, leaf) in self.leaves().enumerate() {
+                if leaf != self.empty_value {
+                    known.entry(leaf).or_insert(index);
+                }
+            }
+        }
+
+        let mut next_index = self.num_leaves();
+        let mut new_leaves = Vec::new();
+        let mapping = other
+            .leaves()
+            .map(|leaf| {
+                if leaf == other.empty_value {
+                    return None;
+                }
+                if policy == MergePolicy::SkipDuplicates {
+                    if let Some(&index) = known.get(&leaf) {
+                        return Some(index);
+                    }
+                    known.insert(leaf, next_index);
+                }
+                new_leaves.push(leaf);
+                next_index += 1;
+                Some(next_index - 1)
+            })
+            .collect();
+
+        self.extend_from_slice(&new_leaves)?;
+
+        Ok(mapping)
+    }
+
+    fn extend_unjournaled(&mut self, leaves: &[H::Hash]) -> Result<()> {
         let num_new_leaves = leaves.len();
         let storage_len = self.storage.len();
         let current_leaves = self.num_leaves();
@@ -390,7 +975,7 @@ where
             let diff = next_power_of_two - storage_len;
 
             self.storage
-                .extend(std::iter::repeat(self.empty_value).take(diff));
+                .try_extend(std::iter::repeat(self.empty_value).take(diff))?;
         }
 
         // Represense the power of the first subtree that has been modified
@@ -412,6 +997,7 @@ where
                 let (leaf_slice, remaining) = remaining_leaves.split_at(1);
                 remaining_leaves = remaining;
                 self.storage[1] = leaf_slice[0];
+                self.storage.mark_changed(1..2);
                 continue;
             } else {
                 1 << subtree_power
@@ -436,13 +1022,23 @@ where
 
             // Extend the subtree with the new leaves beginning at leaf_start
             let root = if leaf_start == 0 {
-                storage_ops::init_subtree_with_leaves::<H>(
+                let root = storage_ops::init_subtree_with_leaves::<H>(
                     subtree_slice,
                     &self.sparse_column,
                     leaf_slice,
-                )
+                );
+                // Initializing rewrites the whole subtree
+                self.storage.mark_subtree_changed(parent_index, 0..width);
+                root
             } else {
-                storage_ops::extend_subtree_with_leaves::<H>(subtree_slice, leaf_start, leaf_slice)
+                let root = storage_ops::extend_subtree_with_leaves::<H>(
+                    subtree_slice,
+                    leaf_start,
+                    leaf_slice,
+                );
+                self.storage
+                    .mark_subtree_changed(parent_index, leaf_start..leaf_start + leaves_to_take);
+                root
             };
 
             // sibling_hash represents the hash of the sibling of the tip of this subtree.
@@ -450,11 +1046,142 @@ where
 
             // Update the parent node of the tip of this subtree.
             self.storage[parent_index] = H::hash_node(&sibling_hash, &root);
+            self.storage.mark_changed(parent_index..parent_index + 1);
         }
 
         // Update the number of leaves in the tree.
         self.storage.set_num_leaves(total_leaves);
         self.recompute_root();
+
+        Ok(())
+    }
+
+    /// Completes a write that was interrupted before it was committed to the
+    /// storage journal.
+    ///
+    /// Writes to existing leaves only need their paths rehashed. Appends may
+    /// have left the storage partially grown and the leaf count unchanged, so
+    /// they are redone from the first appended leaf.
+    fn replay_journal(&mut self) -> Result<()> {
+        let pending = self
+            .storage
+            .journal()
+            .map(|journal| journal.journal_pending());
+        let Some(entry) = pending.transpose()?.flatten() else {
+            return Ok(());
+        };
+
+        let len = self.storage.len();
+        if len <= 1 {
+            return Err(TreeError::InvalidStorage(format!(
+                "length ({len}) must be greater than 1"
+            )));
+        }
+        let num_leaves = self.num_leaves();
+        if entry.first_leaf > num_leaves {
+            return Err(TreeError::InvalidStorage(format!(
+                "journal entry starts at leaf {}, past the last leaf ({num_leaves})",
+                entry.first_leaf
+            )));
+        }
+
+        if entry.first_leaf + entry.leaves.len() <= num_leaves {
+            for (leaf, value) in (entry.first_leaf..).zip(entry.leaves) {
+                let index = storage_ops::index_from_leaf(leaf);
+                self.storage[index] = value;
+                self.storage.mark_changed(index..index + 1);
+                self.storage.propagate_up(index);
+            }
+        } else {
+            if !len.is_power_of_two() {
+                self.storage.try_extend(
+                    std::iter::repeat(self.empty_value).take(len.next_power_of_two() - len),
+                )?;
+            }
+            self.storage.set_num_leaves(entry.first_leaf);
+            self.extend_unjournaled(&entry.leaves)?;
+        }
+
+        self.journal_commit()
+    }
+}
+
+impl<H, S> CascadingMerkleTree<H, S>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H> + BatchJournal<H::Hash>,
+{
+    /// Appends the leaves of a batch, unless a batch with the same id was
+    /// applied before. Returns whether the leaves were appended.
+    ///
+    /// Batch ids are recorded by the storage together with the journal
+    /// entry, see [`storage::JournaledStorage::with_batch_log`]. A batch
+    /// interrupted by a crash is completed when the tree is restored, so
+    /// retrying a batch applies it exactly once, also across restarts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage doesn't record batch ids, e.g. a
+    /// [`storage::JournaledStorage`] without a batch log, or fails to grow,
+    /// journal or flush the write, or if updating the leaf index fails.
+    /// If the leaves could not be appended, the batch stays pending and is
+    /// completed by a retry or when the tree is restored. Otherwise the batch
+    /// is committed, even if updating the index or flushing fails.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(batch_id, num_leaves = leaves.len()))
+    )]
+    pub fn apply_batch(&mut self, batch_id: u64, leaves: &[H::Hash]) -> Result<bool> {
+        if self.storage.batch_committed(batch_id)? {
+            return Ok(false);
+        }
+        let first_leaf = self.num_leaves();
+        self.storage
+            .journal_begin_batch(batch_id, first_leaf, leaves)?;
+        if !leaves.is_empty() {
+            self.extend_unjournaled(leaves)?;
+        }
+        self.finish_write(first_leaf, None, first_leaf..first_leaf + leaves.len())?;
+        Ok(true)
+    }
+}
+
+impl<H, S> CascadingMerkleTree<H, S>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H> + ProvenanceStorage,
+{
+    /// Returns where the leaves of the tree come from, as recorded with
+    /// [`Self::set_provenance`].
+    ///
+    /// A restored tree can compare the recorded root with the chain before
+    /// serving proofs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to read the record.
+    pub fn provenance(&self) -> Result<Option<Provenance>> {
+        Ok(self.storage.provenance()?)
+    }
+
+    /// Records where the leaves of the tree come from in the storage,
+    /// replacing any previous record. Like a write, the record is flushed
+    /// according to the durability policy.
+    ///
+    /// Call this after writing the leaves of a block, so that flushing
+    /// persists the leaves and the record together.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to record provenance or to
+    /// flush.
+    pub fn set_provenance(&mut self, provenance: &Provenance) -> Result<()> {
+        self.storage.set_provenance(provenance)?;
+        self.record_write()
     }
 }
 
@@ -465,7 +1192,7 @@ mod tests {
     use keccak::keccak::Keccak256;
     use rand::{thread_rng, Rng};
     use serial_test::serial;
-    use storage::{GenericStorage, MmapVec};
+    use storage::{GenericStorage, Journal, JournaledStorage, KvStorage, MemoryKv, MmapVec};
 
     use super::*;
 
@@ -690,6 +1417,11 @@ mod tests {
             empty_value: 0,
             sparse_column: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
             storage: vec![5, 1, 2, 1, 4, 2, 1, 1, 5, 1, 1, 0, 1, 0, 0, 0],
+            durability: Durability::None,
+            unflushed_writes: 0,
+            leaf_index: None,
+            watchers: Watchers::default(),
+            scrub_cursor: ScrubCursor::default(),
             _marker: std::marker::PhantomData,
         };
         debug_tree(&tree);
@@ -697,6 +1429,31 @@ mod tests {
         assert_eq!(tree, expected);
     }
 
+    #[test]
+    fn test_parallelism() {
+        let leaves: Vec<_> = (0..1000).map(|i| [i as u8; 32]).collect();
+        let expected =
+            CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], 12, &[0; 32], &leaves);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        for parallelism in [Parallelism::Sequential, Parallelism::Pool(&pool)] {
+            let mut tree = CascadingMerkleTree::<Keccak256>::new_with_leaves_in(
+                vec![],
+                12,
+                &[0; 32],
+                &leaves[..600],
+                parallelism,
+            );
+            tree.extend_from_slice_in(&leaves[600..], parallelism)
+                .unwrap();
+            tree.validate().unwrap();
+            assert_eq!(tree.root(), expected.root());
+        }
+    }
+
     #[test]
     fn test_even_leaves() {
         let num_leaves = 1 << 3;
@@ -709,6 +1466,11 @@ mod tests {
             empty_value: 0,
             sparse_column: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
             storage: vec![8, 1, 2, 1, 4, 2, 1, 1, 8, 4, 2, 2, 1, 1, 1, 1],
+            durability: Durability::None,
+            unflushed_writes: 0,
+            leaf_index: None,
+            watchers: Watchers::default(),
+            scrub_cursor: ScrubCursor::default(),
             _marker: std::marker::PhantomData,
         };
         debug_tree(&tree);
@@ -727,6 +1489,11 @@ mod tests {
             empty_value: 0,
             sparse_column: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
             storage: vec![0, 0],
+            durability: Durability::None,
+            unflushed_writes: 0,
+            leaf_index: None,
+            watchers: Watchers::default(),
+            scrub_cursor: ScrubCursor::default(),
             _marker: std::marker::PhantomData,
         };
         debug_tree(&tree);
@@ -745,6 +1512,11 @@ mod tests {
             empty_value: 1,
             sparse_column: vec![1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1024],
             storage: vec![0, 1],
+            durability: Durability::None,
+            unflushed_writes: 0,
+            leaf_index: None,
+            watchers: Watchers::default(),
+            scrub_cursor: ScrubCursor::default(),
             _marker: std::marker::PhantomData,
         };
         debug_tree(&tree);
@@ -764,6 +1536,11 @@ mod tests {
             empty_value: 1,
             sparse_column: vec![1, 2, 4, 8, 16],
             storage: vec![8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            durability: Durability::None,
+            unflushed_writes: 0,
+            leaf_index: None,
+            watchers: Watchers::default(),
+            scrub_cursor: ScrubCursor::default(),
             _marker: std::marker::PhantomData,
         };
         debug_tree(&tree);
@@ -971,10 +1748,35 @@ mod tests {
         assert_eq!(tree.leaves().collect::<Vec<_>>(), vec![1, 1, 1, 1]);
     }
 
+    #[test]
+    fn test_leaves_range() {
+        let leaves: Vec<usize> = (1..=37).collect();
+        let tree = CascadingMerkleTree::<TestHasher>::new_with_leaves(vec![], 10, &0, &leaves);
+        assert_eq!(tree.leaves().collect::<Vec<_>>(), leaves);
+        assert_eq!(tree.par_leaves().collect::<Vec<_>>(), leaves);
+
+        for (start, end) in [(0, 37), (0, 0), (5, 5), (3, 4), (4, 8), (7, 33), (31, 37)] {
+            let slices: Vec<&[usize]> = tree.leaves_range(start..end).collect();
+            assert_eq!(slices.concat(), &leaves[start..end]);
+            // One slice per power of two block
+            let blocks = (start..end)
+                .map(|leaf| (leaf + 1).next_power_of_two())
+                .collect::<std::collections::BTreeSet<_>>();
+            assert_eq!(slices.len(), blocks.len());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_leaves_range_out_of_bounds() {
+        let tree = CascadingMerkleTree::<TestHasher>::new_with_leaves(vec![], 10, &0, &[1, 2, 3]);
+        let _ = tree.leaves_range(2..4);
+    }
+
     type Hash = <Keccak256 as Hasher>::Hash;
 
     #[test]
-    fn test_extend_from_slice_keccak() -> color_eyre::Result<()> {
+    fn test_extend_from_slice_keccak() -> Result<()> {
         let leaves = (0..1 << 5)
             .map(|n: u64| {
                 let b = n.to_be_bytes();
@@ -991,7 +1793,7 @@ mod tests {
             CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], 10, &[0; 32], &leaves);
 
         let mut tree = CascadingMerkleTree::<Keccak256>::new(vec![], 10, &[0; 32]);
-        tree.extend_from_slice(&leaves);
+        tree.extend_from_slice(&leaves)?;
 
         assert_eq!(
             tree.leaves().collect::<Vec<Hash>>(),
@@ -1002,6 +1804,20 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sha256() -> Result<()> {
+        use keccak::sha256::Sha256;
+
+        let leaves = (0..20_u8).map(|n| [n; 32]).collect::<Vec<_>>();
+        let mut tree = CascadingMerkleTree::<Sha256>::new(vec![], 10, &[0; 32]);
+        tree.extend_from_slice(&leaves)?;
+
+        let mut expected_tree = crate::imt::MerkleTree::<Sha256>::new(10, [0; 32]);
+        expected_tree.set_range(0, leaves);
+        assert_eq!(tree.root(), expected_tree.root());
+        Ok(())
+    }
+
     #[test]
     fn test_push() {
         let mut tree = CascadingMerkleTree::<TestHasher>::new(vec![], 30, &1);
@@ -1015,13 +1831,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_shrink_to_fit() {
+        let leaves: Vec<_> = (1..=9).map(|i| [i; 32]).collect();
+
+        let storage = Vec::with_capacity(1024);
+        let mut tree =
+            CascadingMerkleTree::<Keccak256>::new_with_leaves(storage, 10, &[0; 32], &leaves);
+        tree.shrink_to_fit().unwrap();
+        assert!(tree.storage.capacity() < 1024);
+
+        // Storage reused for a smaller tree keeps its file size
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let file_len = || file.as_file().metadata().unwrap().len();
+        let mut storage: MmapVec<[u8; 32]> = MmapVec::create(file.reopen().unwrap()).unwrap();
+        storage.extend_from_slice(&[[0; 32]; 1024]);
+        let mut tree =
+            CascadingMerkleTree::<Keccak256, _>::new_with_leaves(storage, 10, &[0; 32], &leaves);
+        let root = tree.root();
+        let grown = file_len();
+        tree.shrink_to_fit().unwrap();
+        assert!(file_len() < grown);
+        assert_eq!(tree.root(), root);
+        tree.validate().unwrap();
+
+        tree.push([10; 32]).unwrap();
+        drop(tree);
+        let storage = MmapVec::restore(file.reopen().unwrap()).unwrap();
+        let tree = CascadingMerkleTree::<Keccak256, _>::restore(storage, 10, &[0; 32]).unwrap();
+        assert_eq!(tree.num_leaves(), 10);
+    }
+
     #[test]
     fn test_extend_from_slice() {
         for increment in 1..20 {
             let mut tree = CascadingMerkleTree::<TestHasher>::new(vec![], 30, &1);
             let mut vec = vec![];
             for _ in 0..20 {
-                tree.extend_from_slice(&vec![2; increment]);
+                tree.extend_from_slice(&vec![2; increment]).unwrap();
                 vec.extend_from_slice(&vec![2; increment]);
                 debug_tree(&tree);
                 tree.validate().unwrap();
@@ -1046,7 +1893,7 @@ mod tests {
                         hash
                     })
                     .collect::<Vec<_>>();
-                tree.extend_from_slice(&slice);
+                tree.extend_from_slice(&slice).unwrap();
                 vec.extend_from_slice(&slice);
                 tree.validate().unwrap();
                 assert_eq!(tree.leaves().collect::<Vec<_>>(), vec);
@@ -1054,6 +1901,588 @@ mod tests {
         }
     }
 
+    /// Storage that fails to grow past a fixed number of elements.
+    struct CappedVec {
+        inner: Vec<usize>,
+        cap: usize,
+    }
+
+    impl std::ops::Deref for CappedVec {
+        type Target = [usize];
+
+        fn deref(&self) -> &Self::Target {
+            &self.inner
+        }
+    }
+
+    impl std::ops::DerefMut for CappedVec {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            &mut self.inner
+        }
+    }
+
+    impl Extend<usize> for CappedVec {
+        fn extend<I: IntoIterator<Item = usize>>(&mut self, iter: I) {
+            self.try_extend(iter).unwrap();
+        }
+    }
+
+    impl GenericStorage<usize> for CappedVec {
+        fn push(&mut self, value: usize) {
+            self.try_push(value).unwrap();
+        }
+
+        fn extend_from_slice(&mut self, slice: &[usize]) {
+            self.try_extend_from_slice(slice).unwrap();
+        }
+
+        fn clear(&mut self) {
+            self.inner.clear();
+        }
+
+        fn try_push(&mut self, value: usize) -> Result<(), storage::StorageError> {
+            self.try_extend_from_slice(&[value])
+        }
+
+        fn try_extend<I: IntoIterator<Item = usize>>(
+            &mut self,
+            iter: I,
+        ) -> Result<(), storage::StorageError> {
+            let values = iter.into_iter().collect::<Vec<_>>();
+            self.try_extend_from_slice(&values)
+        }
+
+        fn try_extend_from_slice(&mut self, slice: &[usize]) -> Result<(), storage::StorageError> {
+            if self.inner.len() + slice.len() > self.cap {
+                return Err(storage::StorageError::Invalid("storage full".into()));
+            }
+            self.inner.extend_from_slice(slice);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_merge_from() {
+        let mut tree = CascadingMerkleTree::<Keccak256>::new_with_leaves(
+            vec![],
+            10,
+            &[0; 32],
+            &[[1; 32], [2; 32], [3; 32]],
+        );
+        let other = CascadingMerkleTree::<Keccak256>::new_with_leaves(
+            vec![],
+            10,
+            &[0; 32],
+            &[[3; 32], [0; 32], [4; 32], [4; 32], [5; 32]],
+        );
+
+        let mut appended = tree.clone();
+        let mapping = appended.merge_from(&other, MergePolicy::AppendAll).unwrap();
+        assert_eq!(mapping, vec![Some(3), None, Some(4), Some(5), Some(6)]);
+        assert_eq!(
+            appended.leaves().collect::<Vec<_>>(),
+            vec![[1; 32], [2; 32], [3; 32], [3; 32], [4; 32], [4; 32], [5; 32]]
+        );
+
+        let mapping = tree
+            .merge_from(&other, MergePolicy::SkipDuplicates)
+            .unwrap();
+        assert_eq!(mapping, vec![Some(2), None, Some(3), Some(3), Some(4)]);
+        let expected = CascadingMerkleTree::<Keccak256>::new_with_leaves(
+            vec![],
+            10,
+            &[0; 32],
+            &[[1; 32], [2; 32], [3; 32], [4; 32], [5; 32]],
+        );
+        assert_eq!(tree.root(), expected.root());
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn test_durability() {
+        let file = tempfile::tempfile().unwrap();
+        let storage: MmapVec<_> = MmapVec::create(file).unwrap();
+        let mut tree = CascadingMerkleTree::<TestHasher, _>::new(storage, 10, &0);
+        assert_eq!(tree.durability(), Durability::None);
+
+        tree.push(1).unwrap();
+        tree.push(2).unwrap();
+        assert_eq!(tree.unflushed_writes, 2);
+
+        tree.set_durability(Durability::EveryN(3));
+        tree.push(3).unwrap();
+        assert_eq!(tree.unflushed_writes, 0);
+        tree.set_leaf(0, 4).unwrap();
+        tree.extend_from_slice(&[5, 6]).unwrap();
+        assert_eq!(tree.unflushed_writes, 2);
+
+        tree.set_durability(Durability::EveryWrite);
+        tree.push(7).unwrap();
+        assert_eq!(tree.unflushed_writes, 0);
+
+        tree.set_durability(Durability::None);
+        tree.push(8).unwrap();
+        tree.flush().unwrap();
+        assert_eq!(tree.unflushed_writes, 0);
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut tree = CascadingMerkleTree::<TestHasher>::new(vec![], 10, &0);
+        let watcher = tree.watch();
+        let dropped = tree.watch();
+        drop(dropped);
+
+        tree.push(1).unwrap();
+        tree.extend_from_slice(&[2, 3, 4]).unwrap();
+        tree.extend_from_slice(&[]).unwrap();
+        tree.set_leaf(1, 5).unwrap();
+        let root = tree.root();
+
+        // Clones don't notify the watchers of the original
+        let mut clone = tree.clone();
+        clone.push(6).unwrap();
+
+        let events: Vec<_> = watcher.try_iter().collect();
+        assert_eq!(
+            events.iter().map(|e| e.changed.clone()).collect::<Vec<_>>(),
+            vec![0..1, 1..4, 1..2]
+        );
+        assert_eq!(events.last().unwrap().root, root);
+
+        let mut writer = tree.into_shared();
+        let shared_watcher = writer.watch();
+        writer.push(7).unwrap();
+        assert_eq!(watcher.try_recv().unwrap().changed, 4..5);
+        assert_eq!(shared_watcher.try_recv().unwrap().root, writer.root());
+
+        drop(writer);
+        assert_eq!(watcher.recv(), None);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let storage: MmapVec<_> = MmapVec::create(file.reopen().unwrap()).unwrap();
+        let mut tree = CascadingMerkleTree::<TestHasher, _>::new(storage, 10, &0);
+        assert_eq!(tree.provenance().unwrap(), None);
+
+        tree.extend_from_slice(&[1, 2, 3]).unwrap();
+        let mut root = [0; 32];
+        root[24..].copy_from_slice(&(tree.root() as u64).to_be_bytes());
+        let provenance = Provenance {
+            chain_id: 10,
+            block_number: 42,
+            contract: [1; 20],
+            root,
+        };
+        tree.set_provenance(&provenance).unwrap();
+        tree.flush().unwrap();
+        drop(tree);
+
+        let storage: MmapVec<_> = MmapVec::restore(file.reopen().unwrap()).unwrap();
+        let tree = CascadingMerkleTree::<TestHasher, _>::restore(storage, 10, &0).unwrap();
+        assert_eq!(tree.provenance().unwrap(), Some(provenance));
+        assert_eq!(tree.num_leaves(), 3);
+    }
+
+    #[test]
+    fn test_growth_policy() {
+        let leaves: Vec<usize> = (1..=5).collect();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let storage = MmapVec::create(file.reopen().unwrap())
+            .unwrap()
+            .with_growth_policy(GrowthPolicy::PreallocateFullDepth);
+        let mut tree =
+            CascadingMerkleTree::<TestHasher, _>::new_with_leaves(storage, 6, &0, &leaves);
+        assert_eq!(tree.storage.capacity(), 128);
+        tree.extend_from_slice(&[6; 59]).unwrap();
+        assert_eq!(tree.num_leaves(), 64);
+        assert_eq!(tree.storage.capacity(), 128);
+        drop(tree);
+
+        let storage = MmapVec::create(file.reopen().unwrap())
+            .unwrap()
+            .with_growth_policy(GrowthPolicy::FixedIncrement(24));
+        let mut tree =
+            CascadingMerkleTree::<TestHasher, _>::new_with_leaves(storage, 6, &0, &leaves);
+        assert_eq!(tree.storage.capacity(), 24);
+        tree.extend_from_slice(&[6; 11]).unwrap();
+        assert_eq!(tree.storage.capacity(), 48);
+
+        let mut expected =
+            CascadingMerkleTree::<TestHasher>::new_with_leaves(vec![], 6, &0, &leaves);
+        expected.extend_from_slice(&[6; 11]).unwrap();
+        assert_eq!(tree.root(), expected.root());
+    }
+
+    #[test]
+    fn test_extend_from_iter() {
+        let mut tree = CascadingMerkleTree::<TestHasher>::new(vec![], 20, &0);
+        tree.push(1).unwrap();
+        tree.extend_from_iter(std::iter::repeat(1).take(DEFAULT_CHUNK_LEN + 10))
+            .unwrap();
+        tree.extend_from_iter(std::iter::empty()).unwrap();
+        assert_eq!(tree.num_leaves(), DEFAULT_CHUNK_LEN + 11);
+        assert_eq!(tree.root(), DEFAULT_CHUNK_LEN + 11);
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn test_kv_storage() {
+        let store = MemoryKv::new();
+        let empty = [0; 32];
+        let leaves: Vec<_> = (0..20u8).map(|i| [i; 32]).collect();
+        let storage = KvStorage::create_with_page_len(store.clone(), "tree/", 8).unwrap();
+        let mut tree =
+            CascadingMerkleTree::<Keccak256, _>::new_with_leaves(storage, 10, &empty, &leaves);
+        tree.set_leaf(3, [42; 32]).unwrap();
+        tree.flush().unwrap();
+
+        let storage = KvStorage::restore(store, "tree/").unwrap();
+        let restored = CascadingMerkleTree::<Keccak256, _>::restore(storage, 10, &empty).unwrap();
+        assert_eq!(restored.root(), tree.root());
+        assert_eq!(restored.num_leaves(), 20);
+    }
+
+    #[test]
+    fn test_kv_storage_writes_all_changes() {
+        // Every write must be recorded, or the store falls behind the tree
+        let store = MemoryKv::new();
+        let storage = KvStorage::create_with_page_len(store.clone(), "tree/", 4).unwrap();
+        let mut tree = CascadingMerkleTree::<TestHasher, _>::new(storage, 12, &0);
+        let mut rng = thread_rng();
+        for round in 0..40 {
+            match round % 4 {
+                0 => tree.push(rng.gen_range(1..1000)).unwrap(),
+                1 => {
+                    let leaves: Vec<_> = (0..rng.gen_range(1..40))
+                        .map(|_| rng.gen_range(1..1000))
+                        .collect();
+                    tree.extend_from_slice(&leaves).unwrap();
+                }
+                2 => {
+                    let leaf = rng.gen_range(0..tree.num_leaves());
+                    tree.set_leaf(leaf, rng.gen_range(1..1000)).unwrap();
+                }
+                _ => tree.shrink_to_fit().unwrap(),
+            }
+            tree.flush().unwrap();
+
+            let restored: KvStorage<usize, _> = KvStorage::restore(store.clone(), "tree/").unwrap();
+            assert_eq!(&restored[..], &tree.storage[..], "round {round}");
+        }
+    }
+
+    #[test]
+    fn test_storage_growth_failure() {
+        let storage = CappedVec {
+            inner: vec![],
+            cap: 16,
+        };
+        let mut tree = CascadingMerkleTree::<TestHasher, _>::new(storage, 10, &1);
+
+        // 16 storage elements hold 8 leaves
+        for _ in 0..8 {
+            tree.push(2).unwrap();
+        }
+        let root = tree.root();
+
+        let _ = tree.push(2).expect_err("storage should be full");
+        let _ = tree
+            .extend_from_slice(&[2; 3])
+            .expect_err("storage should be full");
+
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.num_leaves(), 8);
+        tree.validate().unwrap();
+
+        tree.storage.cap = 32;
+        tree.extend_from_slice(&[2; 3]).unwrap();
+        tree.push(2).unwrap();
+        assert_eq!(tree.num_leaves(), 12);
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let empty = [0; 32];
+        let leaves: Vec<_> = (1..=8_u8).map(|i| [i; 32]).collect();
+        let journal = tempfile::NamedTempFile::new().unwrap();
+        let storage = JournaledStorage::create(vec![], journal.reopen().unwrap()).unwrap();
+        let tree =
+            CascadingMerkleTree::<Keccak256, _>::new_with_leaves(storage, 10, &empty, &leaves);
+
+        let mut expected =
+            CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], 10, &empty, &leaves);
+
+        // Push interrupted while growing the storage, with a stray hash from the
+        // new leaf's path already written.
+        let mut storage = tree.storage;
+        storage.journal_begin(8, &[[9; 32]]).unwrap();
+        storage.extend([empty; 5]);
+        storage[16] = [0xff; 32];
+        let tree = CascadingMerkleTree::<Keccak256, _>::restore(storage, 10, &empty).unwrap();
+        expected.push([9; 32]).unwrap();
+        assert_eq!(tree.root(), expected.root());
+        assert_eq!(tree.num_leaves(), 9);
+
+        // Leaf update interrupted before propagating
+        let mut storage = tree.storage;
+        storage.journal_begin(3, &[[7; 32]]).unwrap();
+        storage[storage_ops::index_from_leaf(3)] = [7; 32];
+        let tree = CascadingMerkleTree::<Keccak256, _>::restore(storage, 10, &empty).unwrap();
+        expected.set_leaf(3, [7; 32]).unwrap();
+        assert_eq!(tree.root(), expected.root());
+
+        // Extension interrupted before any write
+        let mut storage = tree.storage;
+        storage.journal_begin(9, &[[3; 32]; 10]).unwrap();
+        let mut tree = CascadingMerkleTree::<Keccak256, _>::restore(storage, 10, &empty).unwrap();
+        expected.extend_from_slice(&[[3; 32]; 10]).unwrap();
+        assert_eq!(tree.root(), expected.root());
+        assert_eq!(tree.num_leaves(), 19);
+
+        // Completed writes leave nothing to replay
+        tree.push([4; 32]).unwrap();
+        assert_eq!(tree.storage.journal_pending().unwrap(), None);
+    }
+
+    #[test]
+    fn test_leaf_index() {
+        let empty = [0; 32];
+        let leaf = |n: u8| [n; 32];
+        let mut plain = CascadingMerkleTree::<Keccak256>::new(vec![], 10, &empty);
+        let mut indexed = CascadingMerkleTree::<Keccak256>::new(vec![], 10, &empty)
+            .with_leaf_index(vec![])
+            .unwrap();
+        assert!(indexed.has_leaf_index());
+
+        let mut rng = thread_rng();
+        for _ in 0..20 {
+            let leaves: Vec<_> = (0..rng.gen_range(1..20))
+                .map(|_| leaf(rng.gen_range(0..50)))
+                .collect();
+            plain.extend_from_slice(&leaves).unwrap();
+            indexed.extend_from_slice(&leaves).unwrap();
+            let value = leaf(rng.gen_range(0..50));
+            plain.push(value).unwrap();
+            indexed.push(value).unwrap();
+            let target = rng.gen_range(0..plain.num_leaves());
+            let value = leaf(rng.gen_range(0..50));
+            plain.set_leaf(target, value).unwrap();
+            indexed.set_leaf(target, value).unwrap();
+        }
+
+        assert_eq!(indexed, plain);
+        for n in 0..=50 {
+            assert_eq!(
+                indexed.get_leaf_from_hash(leaf(n)),
+                plain.get_leaf_from_hash(leaf(n))
+            );
+        }
+        let value = indexed.get_leaf(7);
+        assert_eq!(indexed.proof_from_hash(value), plain.proof_from_hash(value));
+    }
+
+    #[test]
+    fn test_leaf_index_restore() {
+        let empty = [0; 32];
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let leaves: Vec<_> = (1..=100_u8).map(|n| [n; 32]).collect();
+
+        let mut tree =
+            CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], 10, &empty, &leaves)
+                .with_leaf_index(MmapVec::<usize>::create_from_path(file.path()).unwrap())
+                .unwrap();
+        tree.push([101; 32]).unwrap();
+        tree.flush().unwrap();
+        let storage = tree.storage.clone();
+        drop(tree);
+
+        // The persisted index is reused
+        let index = MmapVec::<usize>::restore_from_path(file.path()).unwrap();
+        let snapshot = index.to_vec();
+        let tree = CascadingMerkleTree::<Keccak256>::restore(storage.clone(), 10, &empty)
+            .unwrap()
+            .with_leaf_index(index)
+            .unwrap();
+        assert_eq!(tree.get_leaf_from_hash([101; 32]), Some(100));
+        drop(tree);
+        let index = MmapVec::<usize>::restore_from_path(file.path()).unwrap();
+        assert_eq!(*index, *snapshot);
+
+        // A write without the index makes it stale, so it is rebuilt
+        let mut tree = CascadingMerkleTree::<Keccak256>::restore(storage, 10, &empty).unwrap();
+        tree.set_leaf(3, [200; 32]).unwrap();
+        let tree = tree.with_leaf_index(index).unwrap();
+        assert_eq!(tree.get_leaf_from_hash([200; 32]), Some(3));
+        assert_eq!(tree.get_leaf_from_hash([4; 32]), None);
+        assert_eq!(tree.get_leaf_from_hash(empty), None);
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let empty = [0; 32];
+        let journal = tempfile::NamedTempFile::new().unwrap();
+        let log = tempfile::NamedTempFile::new().unwrap();
+        let storage = JournaledStorage::create(vec![], journal.reopen().unwrap())
+            .unwrap()
+            .with_batch_log(log.reopen().unwrap())
+            .unwrap();
+        let mut tree = CascadingMerkleTree::<Keccak256, _>::new(storage, 10, &empty);
+        let mut expected = CascadingMerkleTree::<Keccak256>::new(vec![], 10, &empty);
+
+        assert!(tree.apply_batch(1, &[[1; 32]; 3]).unwrap());
+        assert!(!tree.apply_batch(1, &[[1; 32]; 3]).unwrap());
+        expected.extend_from_slice(&[[1; 32]; 3]).unwrap();
+        assert_eq!(tree.root(), expected.root());
+
+        // Batch interrupted before its leaves were written
+        let mut storage = tree.storage;
+        storage.journal_begin_batch(2, 3, &[[2; 32]; 5]).unwrap();
+        let mut tree = CascadingMerkleTree::<Keccak256, _>::restore(storage, 10, &empty).unwrap();
+        assert!(!tree.apply_batch(2, &[[2; 32]; 5]).unwrap());
+        expected.extend_from_slice(&[[2; 32]; 5]).unwrap();
+        assert_eq!(tree.root(), expected.root());
+        assert_eq!(tree.num_leaves(), 8);
+
+        // Committed batches survive reopening the storage
+        let storage = tree.storage.into_inner();
+        let storage = JournaledStorage::restore(storage, journal.reopen().unwrap())
+            .unwrap()
+            .with_batch_log(log.reopen().unwrap())
+            .unwrap();
+        let mut tree = CascadingMerkleTree::<Keccak256, _>::restore(storage, 10, &empty).unwrap();
+        assert!(!tree.apply_batch(1, &[[1; 32]; 3]).unwrap());
+        assert!(tree.apply_batch(3, &[]).unwrap());
+        assert_eq!(tree.root(), expected.root());
+
+        let storage = JournaledStorage::create(vec![], tempfile::tempfile().unwrap()).unwrap();
+        let mut tree = CascadingMerkleTree::<Keccak256, _>::new(storage, 10, &empty);
+        let _ = tree
+            .apply_batch(1, &[[1; 32]])
+            .expect_err("storage should not record batches");
+        assert_eq!(tree.num_leaves(), 0);
+    }
+
+    #[test]
+    fn test_write_committed_before_index_failure() {
+        // Six leaves fill the smallest leaf index, so the next write has to
+        // rebuild it, which the capped index storage fails to do
+        let leaves = [1, 2, 3, 4, 5, 6];
+        let new_tree = || {
+            let storage = JournaledStorage::create(vec![], tempfile::tempfile().unwrap())
+                .unwrap()
+                .with_batch_log(tempfile::tempfile().unwrap())
+                .unwrap();
+            let index = CappedVec {
+                inner: vec![],
+                cap: 11,
+            };
+            let mut tree = CascadingMerkleTree::<TestHasher, _>::new(storage, 10, &0)
+                .with_leaf_index(index)
+                .unwrap();
+            tree.extend_from_slice(&leaves).unwrap();
+            tree
+        };
+        let check = |tree: &CascadingMerkleTree<TestHasher, JournaledStorage<Vec<usize>>>,
+                     watcher: TreeWatcher<usize>,
+                     expected: &[usize]| {
+            assert_eq!(tree.leaves().collect::<Vec<_>>(), expected);
+            assert!(!tree.has_leaf_index());
+            assert_eq!(tree.storage.journal_pending().unwrap(), None);
+            assert_eq!(watcher.try_recv().unwrap().root, tree.root());
+            // The failed write is recorded after the initial one
+            assert_eq!(tree.unflushed_writes, 2);
+            tree.validate().unwrap();
+        };
+
+        let mut tree = new_tree();
+        let watcher = tree.watch();
+        let _ = tree.set_leaf(0, 7).expect_err("leaf index should be full");
+        check(&tree, watcher, &[7, 2, 3, 4, 5, 6]);
+
+        let mut tree = new_tree();
+        let watcher = tree.watch();
+        let _ = tree.push(7).expect_err("leaf index should be full");
+        check(&tree, watcher, &[1, 2, 3, 4, 5, 6, 7]);
+
+        let mut tree = new_tree();
+        let watcher = tree.watch();
+        let _ = tree
+            .extend_from_slice(&[7, 8])
+            .expect_err("leaf index should be full");
+        check(&tree, watcher, &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let mut tree = new_tree();
+        let watcher = tree.watch();
+        let _ = tree
+            .apply_batch(1, &[7, 8])
+            .expect_err("leaf index should be full");
+        check(&tree, watcher, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        // The batch was committed and is not applied again
+        assert!(!tree.apply_batch(1, &[7, 8]).unwrap());
+        assert_eq!(tree.num_leaves(), 8);
+    }
+
+    #[test]
+    fn test_validate_detailed_and_repair() {
+        let empty = [0; 32];
+        let leaves: Vec<_> = (1..=10_u8).map(|i| [i; 32]).collect();
+        let tree = CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], 10, &empty, &leaves);
+        let expected_root = tree.root();
+        assert!(tree.validate_detailed().is_empty());
+
+        // A stray value past the last leaf and a corrupted internal node, both
+        // of which also make their parents inconsistent
+        let mut storage = tree.storage;
+        let stray = storage_ops::index_from_leaf(12);
+        let original = storage[2];
+        storage[stray] = [0xff; 32];
+        storage[2] = [0xee; 32];
+        let _ = CascadingMerkleTree::<Keccak256>::restore(storage.clone(), 10, &empty)
+            .expect_err("storage should be invalid");
+
+        let mut tree =
+            CascadingMerkleTree::<Keccak256>::restore_unchecked(storage.clone(), 10, &empty)
+                .unwrap();
+        let mismatches = tree.validate_detailed();
+        assert_eq!(mismatches.len(), 4);
+        assert_eq!(
+            mismatches[..2],
+            [
+                NodeMismatch {
+                    index: stray,
+                    height: 0,
+                    expected: empty,
+                    actual: [0xff; 32],
+                },
+                NodeMismatch {
+                    index: 2,
+                    height: 1,
+                    expected: Keccak256::hash_node(&leaves[0], &leaves[1]),
+                    actual: [0xee; 32],
+                },
+            ]
+        );
+        assert_eq!(mismatches[2].index, storage_ops::parent(stray));
+        assert_eq!(mismatches[3].index, storage_ops::parent(2));
+
+        let repaired = tree.repair().unwrap();
+        assert_eq!(repaired, mismatches[..2]);
+        assert_eq!(repaired[1].expected, original);
+        assert_eq!(tree.root(), expected_root);
+        assert!(tree.validate_detailed().is_empty());
+        tree.validate().unwrap();
+
+        let (tree, repaired) =
+            CascadingMerkleTree::<Keccak256>::restore_and_repair(storage, 10, &empty).unwrap();
+        assert_eq!(repaired.len(), 2);
+        assert_eq!(tree.root(), expected_root);
+    }
+
     #[test]
     fn test_vec_realloc_speed() {
         let empty = 0;
@@ -1079,7 +2508,7 @@ mod tests {
         println!("Create tempfile");
         let tempfile = tempfile::tempfile().unwrap();
         println!("Init mmap");
-        let mmap_vec: MmapVec<_> = unsafe { MmapVec::restore(tempfile).unwrap() };
+        let mmap_vec: MmapVec<_> = MmapVec::restore(tempfile).unwrap();
 
         println!("Init tree");
         let mut tree = CascadingMerkleTree::<TestHasher, MmapVec<_>>::new_with_leaves(
@@ -1099,7 +2528,7 @@ mod tests {
 
     #[test]
     #[serial]
-    fn test_restore_from_cache() -> color_eyre::Result<()> {
+    fn test_restore_from_cache() -> Result<()> {
         let mut rng = rand::thread_rng();
 
         let leaves: Vec<Hash> = (0..1 << 2)
@@ -1111,11 +2540,11 @@ mod tests {
             .collect::<Vec<Hash>>();
 
         // Create a new tmp file for mmap storage
-        let tempfile = tempfile::NamedTempFile::new()?;
+        let tempfile = tempfile::NamedTempFile::new().unwrap();
         let file_path = tempfile.path().to_owned();
 
         // Initialize the expected tree
-        let mmap_vec: MmapVec<_> = unsafe { MmapVec::restore(tempfile.reopen()?).unwrap() };
+        let mmap_vec: MmapVec<_> = MmapVec::restore(tempfile.reopen().unwrap()).unwrap();
         let expected_tree = CascadingMerkleTree::<Keccak256, MmapVec<_>>::new_with_leaves(
             mmap_vec, 3, &[0; 32], &leaves,
         );
@@ -1126,7 +2555,7 @@ mod tests {
         drop(expected_tree);
 
         // Restore the tree
-        let mmap_vec: MmapVec<_> = unsafe { MmapVec::restore_from_path(file_path).unwrap() };
+        let mmap_vec: MmapVec<_> = MmapVec::restore_from_path(file_path).unwrap();
         let tree = CascadingMerkleTree::<Keccak256, MmapVec<_>>::restore(mmap_vec, 3, &[0; 32])?;
 
         // Assert that the root and the leaves are as expected
@@ -0,0 +1,175 @@
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytemuck::Pod;
+use hasher::Hasher;
+
+use super::storage_ops::StorageOps;
+use super::{CascadingMerkleTree, NodeMismatch};
+
+/// The nodes validated by one step of a [`Scrub`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScrubChunk<T> {
+    /// Storage indices of the validated nodes.
+    pub nodes: Range<usize>,
+    /// Nodes in `nodes` that are inconsistent with the rest of the tree,
+    /// ordered by index.
+    pub mismatches: Vec<NodeMismatch<T>>,
+}
+
+/// Validates the nodes of a tree a chunk at a time, see
+/// [`CascadingMerkleTree::scrub`].
+///
+/// Yields a chunk per call to [`Iterator::next`] and ends once the last node
+/// of the storage was validated. The position is kept by the tree, so a new
+/// scrub continues where the last one stopped, and starts the next pass once
+/// the last one ended.
+pub struct Scrub<'a, H, S>
+where
+    H: Hasher,
+{
+    tree: &'a CascadingMerkleTree<H, S>,
+    chunk_size: usize,
+    done: bool,
+}
+
+impl<H, S> Iterator for Scrub<'_, H, S>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+{
+    type Item = ScrubChunk<H::Hash>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let cursor = &self.tree.scrub_cursor;
+        let len = self.tree.storage.len();
+        let mut start = cursor.get();
+        // Storage shrunk since the last step is scrubbed from the start
+        if start >= len {
+            start = FIRST_NODE;
+        }
+        let end = len.min(start + self.chunk_size);
+        let mismatches = self
+            .tree
+            .storage
+            .mismatches_in(&self.tree.empty_value, start..end);
+        if end == len {
+            cursor.set(FIRST_NODE);
+            self.done = true;
+        } else {
+            cursor.set(end);
+        }
+        Some(ScrubChunk {
+            nodes: start..end,
+            mismatches,
+        })
+    }
+}
+
+/// Storage index of the first node, the element before holds the number of
+/// leaves.
+const FIRST_NODE: usize = 1;
+
+/// Index of the next node to scrub, shared by the scrubs of a tree.
+#[derive(Debug)]
+pub(super) struct ScrubCursor(AtomicUsize);
+
+impl ScrubCursor {
+    fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, index: usize) {
+        self.0.store(index, Ordering::Relaxed);
+    }
+}
+
+impl Default for ScrubCursor {
+    fn default() -> Self {
+        Self(AtomicUsize::new(FIRST_NODE))
+    }
+}
+
+impl Clone for ScrubCursor {
+    fn clone(&self) -> Self {
+        Self(AtomicUsize::new(self.get()))
+    }
+}
+
+impl<H, S> CascadingMerkleTree<H, S>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+{
+    /// Validates the tree incrementally, `chunk_size` nodes per step of the
+    /// returned iterator, see [`Scrub`].
+    ///
+    /// Unlike [`Self::validate`] this doesn't need to stop writes for the
+    /// whole storage: writes can be applied between steps, and nodes written
+    /// after they were scrubbed are checked in the next pass. A full pass
+    /// finds the same mismatches as [`Self::validate_detailed`] if the tree
+    /// is not written meanwhile.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn scrub(&self, chunk_size: usize) -> Scrub<'_, H, S> {
+        assert!(chunk_size > 0, "Scrub chunk size must be greater than 0");
+        Scrub {
+            tree: self,
+            chunk_size,
+            done: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cascading::tests::TestHasher;
+
+    #[test]
+    fn test_scrub() {
+        let leaves: Vec<usize> = (1..=20).collect();
+        let mut tree = CascadingMerkleTree::<TestHasher>::new_with_leaves(vec![], 10, &0, &leaves);
+
+        let chunks: Vec<_> = tree.scrub(5).collect();
+        assert_eq!(chunks.len(), 13);
+        assert_eq!(chunks[0].nodes, 1..6);
+        assert_eq!(chunks[12].nodes, 61..64);
+        assert!(chunks.iter().all(|chunk| chunk.mismatches.is_empty()));
+
+        // Resumes where the last scrub stopped, starting a new pass
+        tree.storage[40] += 1;
+        tree.storage[62] = 1;
+        assert!(tree.scrub(16).next().unwrap().mismatches.is_empty());
+        let mismatches: Vec<_> = tree
+            .scrub(16)
+            .flat_map(|chunk| chunk.mismatches)
+            .map(|mismatch| (mismatch.index, mismatch.height))
+            .collect();
+        assert_eq!(mismatches, [(36, 2), (40, 1), (47, 1), (62, 0)]);
+        assert_eq!(tree.scrub(64).next().unwrap().nodes, 1..64);
+
+        let mut expected: Vec<_> = tree
+            .validate_detailed()
+            .into_iter()
+            .map(|mismatch| mismatch.index)
+            .collect();
+        expected.sort_unstable();
+        let scrubbed: Vec<_> = tree
+            .scrub(7)
+            .flat_map(|chunk| chunk.mismatches)
+            .map(|mismatch| mismatch.index)
+            .collect();
+        assert_eq!(scrubbed, expected);
+    }
+}
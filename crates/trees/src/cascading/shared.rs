@@ -0,0 +1,279 @@
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use bytemuck::Pod;
+use hasher::Hasher;
+
+use super::storage_ops::StorageOps;
+use super::{CascadingMerkleTree, ScrubChunk, TreeWatcher};
+use crate::error::Result;
+use crate::proof::Proof;
+
+/// Number of leaves appended per lock acquisition in
+/// [`TreeWriter::extend_from_slice`].
+const EXTEND_CHUNK: usize = 1 << 14;
+
+/// The only handle allowed to modify a shared [`CascadingMerkleTree`].
+///
+/// Created with [`CascadingMerkleTree::into_shared`]. Any number of
+/// [`TreeReader`]s can compute roots and proofs concurrently, and only block
+/// while a write is being applied. Large extensions are applied in chunks, so
+/// readers are never blocked for the whole extension.
+pub struct TreeWriter<H, S>
+where
+    H: Hasher,
+{
+    tree: Arc<RwLock<CascadingMerkleTree<H, S>>>,
+}
+
+/// A cloneable read-only handle to a shared [`CascadingMerkleTree`].
+///
+/// Every method observes the tree between two writes, never in the middle of
+/// one.
+pub struct TreeReader<H, S>
+where
+    H: Hasher,
+{
+    tree: Arc<RwLock<CascadingMerkleTree<H, S>>>,
+}
+
+impl<H, S> Clone for TreeReader<H, S>
+where
+    H: Hasher,
+{
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<H, S> CascadingMerkleTree<H, S>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+{
+    /// Moves the tree behind a lock shared by a single writer and any number
+    /// of readers.
+    #[must_use]
+    pub fn into_shared(self) -> TreeWriter<H, S> {
+        TreeWriter {
+            tree: Arc::new(RwLock::new(self)),
+        }
+    }
+}
+
+impl<H, S> TreeWriter<H, S>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+{
+    /// Returns a new reader of this tree.
+    #[must_use]
+    pub fn reader(&self) -> TreeReader<H, S> {
+        TreeReader {
+            tree: self.tree.clone(),
+        }
+    }
+
+    /// Returns the root of the tree.
+    #[must_use]
+    pub fn root(&self) -> H::Hash {
+        self.read().root()
+    }
+
+    /// Returns the number of leaves in the tree.
+    #[must_use]
+    pub fn num_leaves(&self) -> usize {
+        self.read().num_leaves()
+    }
+
+    /// See [`CascadingMerkleTree::watch`].
+    pub fn watch(&mut self) -> TreeWatcher<H::Hash> {
+        self.write().watch()
+    }
+
+    /// See [`CascadingMerkleTree::set_leaf`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to journal or flush the write.
+    pub fn set_leaf(&mut self, leaf: usize, value: H::Hash) -> Result<()> {
+        self.write().set_leaf(leaf, value)
+    }
+
+    /// See [`CascadingMerkleTree::push`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow, journal or flush the
+    /// write.
+    pub fn push(&mut self, leaf: H::Hash) -> Result<()> {
+        self.write().push(leaf)
+    }
+
+    /// See [`CascadingMerkleTree::extend_from_slice`].
+    ///
+    /// Leaves are appended in chunks, releasing the lock in between, so
+    /// readers may observe some of the chunks before the call returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to grow, journal or flush a
+    /// chunk, in which case the previous chunks remain appended.
+    pub fn extend_from_slice(&mut self, leaves: &[H::Hash]) -> Result<()> {
+        for chunk in leaves.chunks(EXTEND_CHUNK) {
+            self.write().extend_from_slice(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Takes the tree back, if there are no readers left.
+    ///
+    /// # Errors
+    ///
+    /// Returns the writer unchanged if any reader is still alive.
+    pub fn try_into_inner(self) -> Result<CascadingMerkleTree<H, S>, Self> {
+        match Arc::try_unwrap(self.tree) {
+            Ok(lock) => Ok(lock.into_inner().expect("lock poisoned, terminating")),
+            Err(tree) => Err(Self { tree }),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, CascadingMerkleTree<H, S>> {
+        self.tree.read().expect("lock poisoned, terminating")
+    }
+
+    fn write(&mut self) -> RwLockWriteGuard<'_, CascadingMerkleTree<H, S>> {
+        self.tree.write().expect("lock poisoned, terminating")
+    }
+}
+
+impl<H, S> TreeReader<H, S>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+{
+    /// Returns the root of the tree.
+    #[must_use]
+    pub fn root(&self) -> H::Hash {
+        self.read().root()
+    }
+
+    /// Returns the number of leaves in the tree.
+    #[must_use]
+    pub fn num_leaves(&self) -> usize {
+        self.read().num_leaves()
+    }
+
+    /// Returns the hash at the given leaf index.
+    #[must_use]
+    pub fn get_leaf(&self, leaf: usize) -> H::Hash {
+        self.read().get_leaf(leaf)
+    }
+
+    /// Returns the Merkle proof for the given leaf.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the leaf index is not less than the current number of
+    /// leaves.
+    #[must_use]
+    pub fn proof(&self, leaf: usize) -> Proof<H> {
+        self.read().proof(leaf)
+    }
+
+    /// Returns the root together with the proof for the given leaf, both
+    /// taken from the same version of the tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the leaf index is not less than the current number of
+    /// leaves.
+    #[must_use]
+    pub fn root_and_proof(&self, leaf: usize) -> (H::Hash, Proof<H>) {
+        let tree = self.read();
+        (tree.root(), tree.proof(leaf))
+    }
+
+    /// Returns the Merkle proof for the given leaf hash, see
+    /// [`CascadingMerkleTree::proof_from_hash`].
+    #[must_use]
+    pub fn proof_from_hash(&self, leaf: H::Hash) -> Option<Proof<H>> {
+        self.read().proof_from_hash(leaf)
+    }
+
+    /// Validates the next `chunk_size` nodes of the tree, see
+    /// [`CascadingMerkleTree::scrub`]. Returns `None` once a pass over the
+    /// whole tree ended, after which the next call starts a new pass.
+    ///
+    /// The tree is only locked while a single chunk is validated, so a
+    /// background task can scrub the tree without blocking writes for long.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    #[must_use]
+    pub fn scrub_next(&self, chunk_size: usize) -> Option<ScrubChunk<H::Hash>> {
+        self.read().scrub(chunk_size).next()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, CascadingMerkleTree<H, S>> {
+        self.tree.read().expect("lock poisoned, terminating")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use keccak::keccak::Keccak256;
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_readers() {
+        let empty = [0; 32];
+        let tree = CascadingMerkleTree::<Keccak256>::new(vec![], 20, &empty);
+        let mut writer = tree.into_shared();
+        writer.extend_from_slice(&[[1; 32]; 2]).unwrap();
+
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let reader = writer.reader();
+                let done = &done;
+                scope.spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        // Only the first leaf is ever overwritten
+                        let leaf = reader.num_leaves() - 1;
+                        let (root, proof) = reader.root_and_proof(leaf);
+                        assert_eq!(proof.root(reader.get_leaf(leaf)), root);
+                    }
+                });
+            }
+
+            for i in 2..200_u8 {
+                writer.push([i; 32]).unwrap();
+                writer.set_leaf(0, [i; 32]).unwrap();
+            }
+            writer
+                .extend_from_slice(&vec![[7; 32]; EXTEND_CHUNK + 10])
+                .unwrap();
+            done.store(true, Ordering::Relaxed);
+        });
+
+        let reader = writer.reader();
+        assert_eq!(reader.num_leaves(), 200 + EXTEND_CHUNK + 10);
+        let writer = writer.try_into_inner().map(drop).unwrap_err();
+        drop(reader);
+        let tree = writer.try_into_inner().map_err(drop).unwrap();
+        tree.validate().unwrap();
+    }
+}
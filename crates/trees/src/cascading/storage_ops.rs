@@ -1,14 +1,21 @@
 use std::ops::{Deref, DerefMut, Range};
 
 use bytemuck::Pod;
-use color_eyre::eyre::{bail, ensure};
-use color_eyre::Result;
 use hasher::Hasher;
 use rayon::prelude::*;
 use storage::GenericStorage;
 
+use super::NodeMismatch;
+use crate::error::{Result, TreeError};
 use crate::proof::Branch;
 
+/// Number of parent nodes rehashed at a time by [`StorageOps::repair`].
+const REPAIR_CHUNK: usize = 1 << 16;
+
+/// Depth of the blocks of leaves hashed by a single task when propagating
+/// new leaves, see [`propagate_partial_subtree`].
+const PROPAGATE_BLOCK_DEPTH: usize = 10;
+
 pub trait StorageOps<H>:
     GenericStorage<H::Hash>
     + Deref<Target = [H::Hash]>
@@ -52,6 +59,23 @@ where
         self.set_num_leaves(num_leaves);
     }
 
+    /// Records the nodes changed by setting the leaves in `range` of the
+    /// subtree whose tip is the child of `parent_index`, see
+    /// [`extend_subtree_with_leaves`].
+    fn mark_subtree_changed(&mut self, parent_index: usize, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let (depth, _width) = subtree_depth_width(&self[parent_index..(parent_index << 1)]);
+        for row in 0..=depth {
+            let shift = depth - row;
+            let row_start = parent_index + (1 << row);
+            let start = row_start + (range.start >> shift);
+            let end = row_start + ((range.end - 1) >> shift) + 1;
+            self.mark_changed(start..end);
+        }
+    }
+
     /// Returns an iterator over all leaves including those that have noe been
     /// set.
     fn leaves(&self) -> impl Iterator<Item = H::Hash> + '_ {
@@ -89,6 +113,7 @@ where
     fn set_num_leaves(&mut self, amount: usize) {
         let leaf_counter: &mut [usize] = bytemuck::cast_slice_mut(&mut self[0..1]);
         leaf_counter[0] = amount;
+        self.mark_changed(0..1);
     }
 
     fn num_leaves(&self) -> usize {
@@ -99,6 +124,7 @@ where
     fn increment_num_leaves(&mut self, amount: usize) {
         let leaf_counter: &mut [usize] = bytemuck::cast_slice_mut(&mut self[0..1]);
         leaf_counter[0] += amount;
+        self.mark_changed(0..1);
     }
 
     /// Propagates new hashes up the top of the subtree.
@@ -112,6 +138,7 @@ where
             let right_hash = self.get(right)?;
             let parent_index = parent(index);
             self[parent_index] = H::hash_node(left_hash, right_hash);
+            self.mark_changed(parent_index..parent_index + 1);
             index = parent_index;
         }
     }
@@ -120,11 +147,16 @@ where
     fn validate_const(&self) -> Result<()> {
         let len = self.len();
 
-        ensure!(
-            len.is_power_of_two(),
-            "Storage length ({len}) must be a power of 2"
-        );
-        ensure!(len > 1, "Storage length ({len}) must be greater than 1");
+        if !len.is_power_of_two() {
+            return Err(TreeError::InvalidStorage(format!(
+                "length ({len}) must be a power of 2"
+            )));
+        }
+        if len <= 1 {
+            return Err(TreeError::InvalidStorage(format!(
+                "length ({len}) must be greater than 1"
+            )));
+        }
 
         Ok(())
     }
@@ -142,7 +174,9 @@ where
         if first_empty < len {
             self[first_empty..].par_iter().try_for_each(|hash| {
                 if hash != empty_value {
-                    bail!("Storage contains non-empty values past the last leaf");
+                    return Err(TreeError::InvalidStorage(
+                        "non-empty values past the last leaf".to_string(),
+                    ));
                 }
                 Ok(())
             })?;
@@ -159,7 +193,7 @@ where
                 .try_for_each(|(parent, (left, right))| {
                     let expected = H::hash_node(&left, &right);
                     if parent != expected {
-                        bail!("Invalid hash");
+                        return Err(TreeError::InvalidStorage("invalid hash".to_string()));
                     }
                     Ok(())
                 })?;
@@ -167,6 +201,135 @@ where
 
         Ok(())
     }
+
+    /// Returns every node that is inconsistent with the rest of the storage,
+    /// ordered by height and index.
+    ///
+    /// Nodes past the last leaf are expected to be empty, and every other
+    /// node to be the hash of its stored children.
+    fn mismatches(&self, empty_value: &H::Hash) -> Vec<NodeMismatch<H::Hash>> {
+        let len = self.len();
+        let depth = self.storage_depth();
+        let first_empty = index_from_leaf(self.num_leaves());
+
+        let mut mismatches: Vec<_> = (first_empty.min(len)..len)
+            .into_par_iter()
+            .filter(|&index| self[index] != *empty_value)
+            .map(|index| NodeMismatch {
+                index,
+                height: 0,
+                expected: *empty_value,
+                actual: self[index],
+            })
+            .collect();
+
+        for height in 0..depth {
+            let parents = self.row_indices(height + 1);
+            let row_couple = itertools::Itertools::tuples(self.row_indices(height));
+
+            let row_mismatches: Vec<_> = parents
+                .zip(row_couple)
+                .par_bridge()
+                .filter_map(|(parent, (left, right))| {
+                    let expected = H::hash_node(&self[left], &self[right]);
+                    (self[parent] != expected).then(|| NodeMismatch {
+                        index: parent,
+                        height: height + 1,
+                        expected,
+                        actual: self[parent],
+                    })
+                })
+                .collect();
+            mismatches.extend(row_mismatches);
+        }
+
+        mismatches.sort_unstable_by_key(|mismatch| (mismatch.height, mismatch.index));
+        mismatches
+    }
+
+    /// Like [`StorageOps::mismatches`], for the nodes in a range of storage
+    /// indices, ordered by index.
+    fn mismatches_in(
+        &self,
+        empty_value: &H::Hash,
+        range: Range<usize>,
+    ) -> Vec<NodeMismatch<H::Hash>> {
+        let first_empty = index_from_leaf(self.num_leaves());
+
+        range
+            .into_par_iter()
+            .filter_map(|index| {
+                let expected = if index >= first_empty {
+                    *empty_value
+                } else {
+                    let (left, right) = children(index)?;
+                    H::hash_node(&self[left], &self[right])
+                };
+                (self[index] != expected).then(|| NodeMismatch {
+                    index,
+                    height: height_from_index(index),
+                    expected,
+                    actual: self[index],
+                })
+            })
+            .collect()
+    }
+
+    /// Clears the nodes past the last leaf and rehashes every other node from
+    /// the leaves up, returning the nodes that changed.
+    fn repair(&mut self, empty_value: &H::Hash) -> Vec<NodeMismatch<H::Hash>> {
+        let len = self.len();
+        let depth = self.storage_depth();
+        let first_empty = index_from_leaf(self.num_leaves());
+
+        let mut repaired = Vec::new();
+        for index in first_empty.min(len)..len {
+            if self[index] != *empty_value {
+                repaired.push(NodeMismatch {
+                    index,
+                    height: 0,
+                    expected: *empty_value,
+                    actual: self[index],
+                });
+                self[index] = *empty_value;
+                self.mark_changed(index..index + 1);
+            }
+        }
+
+        for height in 0..depth {
+            let width = 1 << (depth - height);
+            let parents = row_indices(height + 1).take(width >> 1);
+            let row_couple = itertools::Itertools::tuples(row_indices(height).take(width));
+            let mut pairs = parents.zip(row_couple);
+
+            // Hash a bounded number of parents at a time, so repairing large
+            // storage doesn't allocate a whole row
+            loop {
+                let chunk: Vec<_> = pairs.by_ref().take(REPAIR_CHUNK).collect();
+                if chunk.is_empty() {
+                    break;
+                }
+                let hashes: Vec<_> = chunk
+                    .par_iter()
+                    .map(|&(_, (left, right))| H::hash_node(&self[left], &self[right]))
+                    .collect();
+                for ((parent, _), expected) in chunk.into_iter().zip(hashes) {
+                    if self[parent] != expected {
+                        repaired.push(NodeMismatch {
+                            index: parent,
+                            height: height + 1,
+                            expected,
+                            actual: self[parent],
+                        });
+                        self[parent] = expected;
+                        self.mark_changed(parent..parent + 1);
+                    }
+                }
+            }
+        }
+
+        repaired
+    }
 }
 
 impl<H, S> StorageOps<H> for S
@@ -237,7 +400,18 @@ pub fn index_height_offset(height: usize, offset: usize) -> usize {
     offset_node + subtree_size
 }
 
-#[cfg(test)]
+/// Height of the node at a storage index above the leaves.
+pub fn height_from_index(i: usize) -> usize {
+    if i.is_power_of_two() {
+        return i.ilog2() as usize;
+    }
+    // The node is in the subtree hanging right of the power of two below it
+    let prev_pow = i.next_power_of_two() >> 1;
+    let subtree_depth = prev_pow.ilog2() as usize - 1;
+    subtree_depth - (i - prev_pow).ilog2() as usize
+}
+
+/// Returns the children of the node at a storage index, or `None` for leaves.
 pub fn children(i: usize) -> Option<(usize, usize)> {
     let next_pow = i.next_power_of_two();
     if i == next_pow {
@@ -379,13 +553,89 @@ where
 ///   2     5   [  10    11 ]
 /// 1  3  6  7  [12 13 14 15]
 ///  ```
-pub fn propagate_partial_subtree<H>(subtree: &mut [H::Hash], mut range: Range<usize>) -> H::Hash
+pub fn propagate_partial_subtree<H>(subtree: &mut [H::Hash], range: Range<usize>) -> H::Hash
 where
     H: Hasher,
     <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
 {
+    if range.is_empty() {
+        return subtree[1];
+    }
     let depth = subtree_depth(subtree);
+    let block_depth = PROPAGATE_BLOCK_DEPTH.min(depth);
+
+    // Hash the lowest levels block by block, then the levels above the
+    // blocks row by row
+    let range = propagate_blocks::<H>(subtree, depth, block_depth, range);
+    propagate_rows::<H>(subtree, depth - block_depth, range);
+
+    subtree[1]
+}
+
+/// Hashes the lowest `block_depth` levels of the blocks of leaves that
+/// intersect `range`, in parallel. Every block is hashed by a single task,
+/// which keeps its nodes in cache and leaves rayon only one split per block
+/// instead of one per level.
+///
+/// Returns the range of nodes that changed in the row above the blocks.
+fn propagate_blocks<H>(
+    subtree: &mut [H::Hash],
+    depth: usize,
+    block_depth: usize,
+    range: Range<usize>,
+) -> Range<usize>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+{
+    let top = depth - block_depth;
+    let first_block = range.start >> block_depth;
+    let end_block = ((range.end - 1) >> block_depth) + 1;
+
+    // Split the rows into the parts belonging to each block, with the rows
+    // of a block ordered from its top to the leaves
+    let mut blocks: Vec<Vec<&mut [H::Hash]>> = (first_block..end_block)
+        .map(|_| Vec::with_capacity(block_depth + 1))
+        .collect();
+    let mut rest = &mut subtree[(1 << top)..];
+    for row_depth in 0..=block_depth {
+        let (row, tail) = rest.split_at_mut(1 << (top + row_depth));
+        rest = tail;
+        let node_len = 1 << row_depth;
+        let used = &mut row[(first_block * node_len)..(end_block * node_len)];
+        for (block, nodes) in blocks.iter_mut().zip(used.chunks_mut(node_len)) {
+            block.push(nodes);
+        }
+    }
 
+    blocks
+        .into_par_iter()
+        .enumerate()
+        .for_each(|(i, mut rows)| {
+            let block_start = (first_block + i) << block_depth;
+            let mut range = range.start.max(block_start) - block_start
+                ..range.end.min(block_start + (1 << block_depth)) - block_start;
+            for row_depth in (1..=block_depth).rev() {
+                let (parents, children) = rows.split_at_mut(row_depth);
+                let (parent_layer, child_layer) = (&mut parents[row_depth - 1], &children[0]);
+                range.start /= 2;
+                range.end = ((range.end - 1) / 2) + 1;
+                for i in range.clone() {
+                    parent_layer[i] = H::hash_node(&child_layer[2 * i], &child_layer[2 * i + 1]);
+                }
+            }
+        });
+
+    first_block..end_block
+}
+
+/// Hashes the rows from `depth` up to the subtree root, where the nodes in
+/// `range` of the row at `depth` changed.
+fn propagate_rows<H>(subtree: &mut [H::Hash], depth: usize, mut range: Range<usize>)
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+{
     // Iterate over mutable layers of the tree
     for current_depth in (1..=depth).rev() {
         // Split the subtree into relavent layers
@@ -406,8 +656,6 @@ where
                 *value = H::hash_node(left, right);
             });
     }
-
-    subtree[1]
 }
 
 /// Propagates empty hashes up the tree within a given range.
@@ -517,4 +765,44 @@ mod tests {
         let expected = vec![1, 8, 1, 4, 1, 1, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1];
         assert_eq!(storage, expected);
     }
+
+    #[test]
+    fn test_propagate_partial_subtree() {
+        // Deeper than a block, with ranges within, across and at the edges of
+        // blocks
+        let depth = PROPAGATE_BLOCK_DEPTH + 2;
+        let width = 1 << depth;
+        let block = 1 << PROPAGATE_BLOCK_DEPTH;
+        for range in [
+            0..width,
+            3..4,
+            block - 1..block + 1,
+            5..3 * block + 7,
+            width - 1..width,
+        ] {
+            let mut subtree = vec![0; width << 1];
+            for (leaf, value) in subtree[width..].iter_mut().enumerate() {
+                *value = leaf % 7;
+            }
+            let mut expected = subtree.clone();
+            for i in (1..width).rev() {
+                expected[i] = expected[2 * i] + expected[2 * i + 1];
+            }
+            // Only the given range is new, the nodes above the rest are valid
+            for (i, value) in subtree.iter_mut().enumerate().skip(1).take(width - 1) {
+                *value = expected[i];
+            }
+            for leaf in range.clone() {
+                subtree[width + leaf] += 1;
+                expected[width + leaf] += 1;
+            }
+            for i in (1..width).rev() {
+                expected[i] = expected[2 * i] + expected[2 * i + 1];
+            }
+
+            let root = propagate_partial_subtree::<TestHasher>(&mut subtree, range);
+            assert_eq!(root, expected[1]);
+            assert_eq!(subtree, expected);
+        }
+    }
 }
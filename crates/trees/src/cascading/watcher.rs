@@ -0,0 +1,116 @@
+use std::fmt;
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+/// A change of the leaves of a [`CascadingMerkleTree`] and the root after it.
+///
+/// [`CascadingMerkleTree`]: super::CascadingMerkleTree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RootEvent<T> {
+    /// Root of the tree after the change.
+    pub root: T,
+    /// Indices of the leaves that were set or appended.
+    pub changed: Range<usize>,
+}
+
+/// Receives a [`RootEvent`] for every change of a [`CascadingMerkleTree`],
+/// in the order of the changes.
+///
+/// Created with [`CascadingMerkleTree::watch`]. Events are buffered until
+/// they are received, so a watcher that is never read from grows without
+/// bound. Dropping the watcher unsubscribes it.
+///
+/// [`CascadingMerkleTree`]: super::CascadingMerkleTree
+/// [`CascadingMerkleTree::watch`]: super::CascadingMerkleTree::watch
+pub struct TreeWatcher<T> {
+    receiver: Receiver<RootEvent<T>>,
+}
+
+impl<T> TreeWatcher<T> {
+    /// Blocks until the next event. Returns `None` once the tree has been
+    /// dropped and all events were received.
+    #[must_use]
+    pub fn recv(&self) -> Option<RootEvent<T>> {
+        self.receiver.recv().ok()
+    }
+
+    /// Like [`Self::recv`], giving up after `timeout`.
+    #[must_use]
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<RootEvent<T>> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Returns the next event if there is one, without blocking.
+    #[must_use]
+    pub fn try_recv(&self) -> Option<RootEvent<T>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Returns an iterator over the events received so far, without
+    /// blocking.
+    pub fn try_iter(&self) -> impl Iterator<Item = RootEvent<T>> + '_ {
+        self.receiver.try_iter()
+    }
+
+    /// Returns an iterator that blocks for every event and ends once the
+    /// tree has been dropped.
+    pub fn iter(&self) -> impl Iterator<Item = RootEvent<T>> + '_ {
+        self.receiver.iter()
+    }
+}
+
+impl<T> fmt::Debug for TreeWatcher<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TreeWatcher").finish_non_exhaustive()
+    }
+}
+
+/// The subscribers of a tree.
+///
+/// Clones of a tree start without subscribers, since their changes are not
+/// the changes of the watched tree.
+pub(super) struct Watchers<T> {
+    senders: Vec<Sender<RootEvent<T>>>,
+}
+
+impl<T: Clone> Watchers<T> {
+    pub(super) fn subscribe(&mut self) -> TreeWatcher<T> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.push(sender);
+        TreeWatcher { receiver }
+    }
+
+    /// Sends an event to every subscriber, dropping the ones whose watcher
+    /// was dropped.
+    pub(super) fn notify(&mut self, root: T, changed: Range<usize>) {
+        if self.senders.is_empty() {
+            return;
+        }
+        let event = RootEvent { root, changed };
+        self.senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+impl<T> Default for Watchers<T> {
+    fn default() -> Self {
+        Self {
+            senders: Vec::new(),
+        }
+    }
+}
+
+impl<T> Clone for Watchers<T> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl<T> fmt::Debug for Watchers<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watchers")
+            .field("count", &self.senders.len())
+            .finish()
+    }
+}
@@ -0,0 +1,37 @@
+use thiserror::Error;
+
+pub(crate) type Result<T, E = TreeError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum TreeError {
+    #[error("tree depth must be greater than 0")]
+    ZeroDepth,
+    #[error("invalid storage: {0}")]
+    InvalidStorage(String),
+    #[error("storage operation failed")]
+    Storage(#[from] storage::StorageError),
+    #[error("failed to grow the leaf index, it was dropped")]
+    LeafIndex(#[source] Box<TreeError>),
+    #[error("failed to spill leaves to a temporary file")]
+    Spill(#[source] std::io::Error),
+    #[error("invalid sync request: {0}")]
+    InvalidRequest(String),
+    #[error("invalid sync response: {0}")]
+    InvalidResponse(String),
+    #[error("no sync request is pending")]
+    NoPendingRequest,
+    #[error("sync is not complete")]
+    SyncIncomplete,
+    #[error("replica changed during the sync")]
+    ReplicaChanged,
+    #[error("source changed during the sync")]
+    SourceChanged,
+    #[error("diff does not apply to the tree: {0}")]
+    InvalidDiff(String),
+    #[error("the writer kept changing the tree while it was read")]
+    Contended,
+    #[error("leaf index {index} is out of bounds for a tree of {size} leaves")]
+    LeafOutOfBounds { index: usize, size: usize },
+    #[error("leaves cannot be zero")]
+    ZeroLeaf,
+}
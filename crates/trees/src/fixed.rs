@@ -0,0 +1,162 @@
+//! Merkle trees with a depth known at compile time.
+//!
+//! [`MerkleTree`] stores the same nodes as [`imt::MerkleTree`], but with the
+//! depth as a const parameter the loops over the levels have a fixed trip
+//! count the compiler unrolls, and proofs are arrays instead of vectors. The
+//! nodes are allocated once when the tree is created, since stable Rust
+//! can't size an array by `1 << DEPTH`, and updates and proofs don't
+//! allocate. This suits small fixed deployments, e.g. depth 20 or less on
+//! embedded or WebAssembly targets.
+//!
+//! [`imt::MerkleTree`]: crate::imt::MerkleTree
+
+use std::fmt::Debug;
+
+use derive_where::derive_where;
+use hasher::{Hash, Hasher};
+
+use crate::proof::{Branch, Proof};
+
+/// Merkle tree of depth `DEPTH` with all leaf and intermediate hashes stored.
+#[derive_where(Clone; <H as Hasher>::Hash: Clone)]
+#[derive_where(PartialEq; <H as Hasher>::Hash: PartialEq)]
+#[derive_where(Eq; <H as Hasher>::Hash: Eq)]
+#[derive_where(Debug; <H as Hasher>::Hash: Debug)]
+pub struct MerkleTree<H, const DEPTH: usize>
+where
+    H: Hasher,
+{
+    /// Hash values of tree nodes and leaves, breadth first order starting at
+    /// index one, `2 << DEPTH` values.
+    nodes: Box<[H::Hash]>,
+}
+
+impl<H, const DEPTH: usize> MerkleTree<H, DEPTH>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Hash,
+{
+    /// Number of leaves of the tree.
+    pub const LEAVES: usize = 1 << DEPTH;
+
+    /// Creates a tree with all leaves set to `empty_leaf`.
+    #[must_use]
+    pub fn new(empty_leaf: H::Hash) -> Self {
+        let mut nodes = vec![empty_leaf; 2 * Self::LEAVES].into_boxed_slice();
+        let mut empty = empty_leaf;
+        for depth in (0..DEPTH).rev() {
+            empty = H::hash_node(&empty, &empty);
+            nodes[1 << depth..2 << depth].fill(empty);
+        }
+        Self { nodes }
+    }
+
+    #[must_use]
+    pub fn root(&self) -> H::Hash {
+        self.nodes[1]
+    }
+
+    #[must_use]
+    pub fn leaves(&self) -> &[H::Hash] {
+        &self.nodes[Self::LEAVES..]
+    }
+
+    /// Sets a leaf and updates the nodes above it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `leaf` is not less than [`Self::LEAVES`].
+    pub fn set(&mut self, leaf: usize, hash: H::Hash) {
+        assert!(leaf < Self::LEAVES, "leaf {leaf} is out of bounds");
+        let mut index = Self::LEAVES + leaf;
+        self.nodes[index] = hash;
+        for _ in 0..DEPTH {
+            let left = index & !1;
+            let parent = H::hash_node(&self.nodes[left], &self.nodes[left + 1]);
+            index >>= 1;
+            self.nodes[index] = parent;
+        }
+    }
+
+    /// Returns the siblings of a leaf from the bottom up, or `None` if the
+    /// leaf is out of bounds.
+    #[must_use]
+    pub fn proof(&self, leaf: usize) -> Option<[Branch<H::Hash>; DEPTH]> {
+        if leaf >= Self::LEAVES {
+            return None;
+        }
+        let leaf = Self::LEAVES + leaf;
+        Some(std::array::from_fn(|level| {
+            let index = leaf >> level;
+            let sibling = self.nodes[index ^ 1];
+            if index & 1 == 1 {
+                Branch::Right(sibling)
+            } else {
+                Branch::Left(sibling)
+            }
+        }))
+    }
+
+    /// Returns whether the proof of `hash` leads to the root of the tree.
+    #[must_use]
+    pub fn verify(&self, hash: H::Hash, proof: &[Branch<H::Hash>; DEPTH]) -> bool {
+        let root = proof.iter().fold(hash, |hash, branch| match branch {
+            Branch::Left(sibling) => H::hash_node(&hash, sibling),
+            Branch::Right(sibling) => H::hash_node(sibling, &hash),
+        });
+        root == self.root()
+    }
+
+    /// Returns the proof of a leaf as a [`Proof`], like
+    /// [`imt::MerkleTree::proof`].
+    ///
+    /// [`imt::MerkleTree::proof`]: crate::imt::MerkleTree::proof
+    #[must_use]
+    pub fn inclusion_proof(&self, leaf: usize) -> Option<Proof<H>> {
+        self.proof(leaf).map(|path| Proof(path.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keccak::keccak::Keccak256;
+    use poseidon::Poseidon;
+    use rand::{thread_rng, Rng};
+    use ruint::aliases::U256;
+
+    use super::*;
+
+    #[test]
+    fn test_matches_imt() {
+        let mut tree = MerkleTree::<Keccak256, 6>::new([0; 32]);
+        let mut imt = crate::imt::MerkleTree::<Keccak256>::new(6, [0; 32]);
+        assert_eq!(tree.root(), imt.root());
+
+        let mut rng = thread_rng();
+        for _ in 0..50 {
+            let leaf = rng.gen_range(0..64);
+            let hash: [u8; 32] = rng.gen();
+            tree.set(leaf, hash);
+            imt.set(leaf, hash);
+            assert_eq!(tree.root(), imt.root());
+
+            let proof = tree.proof(leaf).unwrap();
+            assert_eq!(tree.inclusion_proof(leaf), imt.proof(leaf));
+            assert!(tree.verify(hash, &proof));
+            assert!(!tree.verify([1; 32], &proof));
+        }
+        assert_eq!(tree.leaves(), &imt.leaves()[1..]);
+        assert!(tree.proof(64).is_none());
+    }
+
+    #[test]
+    fn test_poseidon() {
+        let mut tree = MerkleTree::<Poseidon, 10>::new(U256::ZERO);
+        tree.set(0, U256::from(1));
+        // Same as `imt::test::simple_poseidon`
+        let expected_root = ruint::uint!(
+            467068234150758165281816522946040748310650451788100792957402532717155514893_U256
+        );
+        assert_eq!(tree.root(), expected_root);
+    }
+}
@@ -155,26 +155,6 @@ where
     }
 }
 
-impl<H: Hasher> Proof<H> {
-    /// Compute the leaf index for this proof
-    #[must_use]
-    pub fn leaf_index(&self) -> usize {
-        self.0.iter().rev().fold(0, |index, branch| match branch {
-            Branch::Left(_) => index << 1,
-            Branch::Right(_) => (index << 1) + 1,
-        })
-    }
-
-    /// Compute the Merkle root given a leaf hash
-    #[must_use]
-    pub fn root(&self, hash: H::Hash) -> H::Hash {
-        self.0.iter().fold(hash, |hash, branch| match branch {
-            Branch::Left(sibling) => H::hash_node(&hash, sibling),
-            Branch::Right(sibling) => H::hash_node(sibling, &hash),
-        })
-    }
-}
-
 #[cfg(test)]
 pub mod test {
     use hex_literal::hex;
@@ -0,0 +1,310 @@
+//! Implements indexed Merkle trees, which prove that a value is not in a set
+//!
+//! Leaves form a linked list sorted by value, starting at a sentinel leaf
+//! holding the zero value. Every leaf commits to its value and the next
+//! larger value in the tree, so a value is absent exactly when some leaf
+//! brackets it: the leaf with the largest smaller value, called the low leaf.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use bytemuck::Pod;
+use derive_where::derive_where;
+use hasher::Hasher;
+use thiserror::Error;
+
+use crate::imt::MerkleTree;
+use crate::proof::Proof;
+
+/// A leaf of an [`IndexedMerkleTree`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexedLeaf<T> {
+    pub value: T,
+    /// Index of the leaf holding `next_value`, or 0 for the largest value.
+    pub next_index: usize,
+    /// The next larger value in the tree, or the zero value for the largest
+    /// value.
+    pub next_value: T,
+}
+
+impl<T> IndexedLeaf<T> {
+    /// Returns the hash committed to the tree for this leaf.
+    ///
+    /// `next_index` is not committed to. It only speeds up insertion, the
+    /// sorted order is fully determined by the values.
+    #[must_use]
+    pub fn hash<H: Hasher<Hash = T>>(&self) -> T {
+        H::hash_node(&self.value, &self.next_value)
+    }
+}
+
+/// A leaf of an [`IndexedMerkleTree`] with its Merkle proof.
+#[derive_where(Clone; <H as Hasher>::Hash: Clone)]
+#[derive_where(PartialEq; <H as Hasher>::Hash: PartialEq)]
+#[derive_where(Eq; <H as Hasher>::Hash: Eq)]
+#[derive_where(Debug; <H as Hasher>::Hash: Debug)]
+pub struct LeafProof<H>
+where
+    H: Hasher,
+{
+    pub leaf: IndexedLeaf<H::Hash>,
+    pub proof: Proof<H>,
+}
+
+impl<H> LeafProof<H>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Eq + Ord,
+{
+    /// Returns the root of the tree the leaf is proven to be in.
+    #[must_use]
+    pub fn root(&self) -> H::Hash {
+        self.proof.root(self.leaf.hash::<H>())
+    }
+
+    /// Checks that `value` is in the tree with the given root.
+    #[must_use]
+    pub fn verify_membership(&self, root: H::Hash, value: H::Hash) -> bool {
+        self.leaf.value == value && self.root() == root
+    }
+
+    /// Checks that `value` is not in the tree with the given root, i.e. that
+    /// the proven leaf is the low leaf of `value`.
+    #[must_use]
+    pub fn verify_non_membership(&self, root: H::Hash, value: H::Hash) -> bool {
+        let IndexedLeaf {
+            value: low,
+            next_value: next,
+            ..
+        } = self.leaf;
+        // Only the leaf with the largest value points back to a smaller value
+        let is_last = next <= low;
+        low < value && (is_last || value < next) && self.root() == root
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum IndexedTreeError {
+    #[error("value is already in the tree")]
+    ValueExists,
+    #[error("value must be larger than the zero value")]
+    ValueOutOfRange,
+    #[error("tree is full")]
+    TreeFull,
+}
+
+/// Indexed Merkle tree with all leaf and intermediate hashes stored
+#[derive_where(Clone; <H as Hasher>::Hash: Clone)]
+#[derive_where(Debug; <H as Hasher>::Hash: Debug)]
+pub struct IndexedMerkleTree<H>
+where
+    H: Hasher,
+{
+    tree: MerkleTree<H>,
+    leaves: Vec<IndexedLeaf<H::Hash>>,
+    /// Leaf index by value
+    index: BTreeMap<H::Hash, usize>,
+}
+
+impl<H> IndexedMerkleTree<H>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Clone + Copy + Pod + Eq + Ord + Debug,
+{
+    /// Creates a new `IndexedMerkleTree` holding only the sentinel leaf.
+    ///
+    /// * `depth` - The depth of the tree, excluding the root.
+    /// * `zero` - The zero value. It is used for unused leaves and held by the
+    ///   sentinel, and every inserted value must be larger.
+    #[must_use]
+    pub fn new(depth: usize, zero: H::Hash) -> Self {
+        let sentinel = IndexedLeaf {
+            value: zero,
+            next_index: 0,
+            next_value: zero,
+        };
+        let mut tree = MerkleTree::new(depth, zero);
+        tree.set(0, sentinel.hash::<H>());
+
+        Self {
+            tree,
+            leaves: vec![sentinel],
+            index: BTreeMap::from([(zero, 0)]),
+        }
+    }
+
+    #[must_use]
+    pub fn root(&self) -> H::Hash {
+        self.tree.root()
+    }
+
+    /// Returns the number of used leaves, including the sentinel.
+    #[must_use]
+    pub fn num_leaves(&self) -> usize {
+        self.leaves.len()
+    }
+
+    #[must_use]
+    pub fn leaf(&self, index: usize) -> Option<&IndexedLeaf<H::Hash>> {
+        self.leaves.get(index)
+    }
+
+    #[must_use]
+    pub fn contains(&self, value: &H::Hash) -> bool {
+        self.index.contains_key(value)
+    }
+
+    /// Inserts a value into the next unused leaf, returning its index.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is already present, is not larger than
+    /// the zero value or the tree is full.
+    pub fn insert(&mut self, value: H::Hash) -> Result<usize, IndexedTreeError> {
+        let zero = self.leaves[0].value;
+        if value <= zero {
+            return Err(IndexedTreeError::ValueOutOfRange);
+        }
+        if self.contains(&value) {
+            return Err(IndexedTreeError::ValueExists);
+        }
+        let new_index = self.leaves.len();
+        if new_index >= self.tree.num_leaves() {
+            return Err(IndexedTreeError::TreeFull);
+        }
+
+        let low_index = self.low_leaf_index(&value);
+        let low = &mut self.leaves[low_index];
+        let leaf = IndexedLeaf {
+            value,
+            next_index: low.next_index,
+            next_value: low.next_value,
+        };
+        low.next_index = new_index;
+        low.next_value = value;
+        let low_hash = low.hash::<H>();
+
+        self.tree.set(low_index, low_hash);
+        self.tree.set(new_index, leaf.hash::<H>());
+        self.leaves.push(leaf);
+        self.index.insert(value, new_index);
+        Ok(new_index)
+    }
+
+    /// Returns a proof that `value` is in the tree, or `None` if it isn't.
+    #[must_use]
+    pub fn membership_proof(&self, value: &H::Hash) -> Option<LeafProof<H>> {
+        if *value == self.leaves[0].value {
+            return None;
+        }
+        self.index.get(value).map(|&index| self.leaf_proof(index))
+    }
+
+    /// Returns a proof that `value` is not in the tree, or `None` if it is or
+    /// is not larger than the zero value.
+    #[must_use]
+    pub fn non_membership_proof(&self, value: &H::Hash) -> Option<LeafProof<H>> {
+        if *value <= self.leaves[0].value || self.contains(value) {
+            return None;
+        }
+        Some(self.leaf_proof(self.low_leaf_index(value)))
+    }
+
+    /// Returns the index of the leaf with the largest value less than `value`.
+    fn low_leaf_index(&self, value: &H::Hash) -> usize {
+        let (_, &index) = self
+            .index
+            .range(..value)
+            .next_back()
+            .expect("the sentinel is smaller than every value");
+        index
+    }
+
+    fn leaf_proof(&self, index: usize) -> LeafProof<H> {
+        LeafProof {
+            leaf: self.leaves[index],
+            proof: self.tree.proof(index).expect("leaf index is in the tree"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keccak::keccak::Keccak256;
+
+    use super::*;
+
+    fn value(n: u8) -> [u8; 32] {
+        let mut value = [0; 32];
+        value[31] = n;
+        value
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut tree = IndexedMerkleTree::<Keccak256>::new(3, [0; 32]);
+        assert_eq!(tree.insert(value(30)), Ok(1));
+        assert_eq!(tree.insert(value(10)), Ok(2));
+        assert_eq!(tree.insert(value(20)), Ok(3));
+
+        // The sorted list is 0 -> 10 -> 20 -> 30 -> 0
+        let expected = [(0, 2, 10), (30, 0, 0), (10, 3, 20), (20, 1, 30)];
+        let mut reference = MerkleTree::<Keccak256>::new(3, [0; 32]);
+        for (index, &(v, next_index, next_value)) in expected.iter().enumerate() {
+            let leaf = IndexedLeaf {
+                value: value(v),
+                next_index,
+                next_value: value(next_value),
+            };
+            assert_eq!(tree.leaf(index), Some(&leaf));
+            reference.set(index, leaf.hash::<Keccak256>());
+        }
+        assert_eq!(tree.root(), reference.root());
+
+        assert_eq!(tree.insert(value(20)), Err(IndexedTreeError::ValueExists));
+        assert_eq!(tree.insert([0; 32]), Err(IndexedTreeError::ValueOutOfRange));
+        for n in 1..=4 {
+            tree.insert(value(n)).unwrap();
+        }
+        assert_eq!(tree.insert(value(5)), Err(IndexedTreeError::TreeFull));
+    }
+
+    #[test]
+    fn test_proofs() {
+        let mut tree = IndexedMerkleTree::<Keccak256>::new(4, [0; 32]);
+        let root = tree.root();
+        let proof = tree.non_membership_proof(&value(7)).unwrap();
+        assert!(proof.verify_non_membership(root, value(7)));
+
+        for v in [30, 10, 20] {
+            tree.insert(value(v)).unwrap();
+        }
+        let root = tree.root();
+
+        for v in [10, 20, 30] {
+            assert!(tree.non_membership_proof(&value(v)).is_none());
+            let proof = tree.membership_proof(&value(v)).unwrap();
+            assert!(proof.verify_membership(root, value(v)));
+            assert!(!proof.verify_non_membership(root, value(v)));
+        }
+        assert!(tree.membership_proof(&[0; 32]).is_none());
+
+        for (v, low) in [(5, 0), (15, 10), (25, 20), (35, 30), (255, 30)] {
+            assert!(tree.membership_proof(&value(v)).is_none());
+            let proof = tree.non_membership_proof(&value(v)).unwrap();
+            assert_eq!(proof.leaf.value, value(low));
+            assert!(proof.verify_non_membership(root, value(v)));
+            assert!(!proof.verify_membership(root, value(v)));
+        }
+
+        // A low leaf only proves the absence of values it brackets
+        let proof = tree.non_membership_proof(&value(15)).unwrap();
+        assert!(!proof.verify_non_membership(root, value(25)));
+        assert!(!proof.verify_non_membership(root, value(20)));
+        assert!(!proof.verify_non_membership(tree.root(), value(5)));
+
+        // Proofs are tied to the root they were generated for
+        tree.insert(value(15)).unwrap();
+        assert!(!proof.verify_non_membership(tree.root(), value(15)));
+    }
+}
@@ -0,0 +1,179 @@
+use std::mem::size_of;
+
+use hasher::Hash;
+
+/// Size of the blocks used by [`DenseLayout::Blocked`].
+const BLOCK_BYTES: usize = 4096;
+
+/// Order in which the nodes of a memory mapped dense prefix are stored.
+///
+/// The layout is chosen when the file is created and recorded in the otherwise
+/// unused first slot, so restoring a tree picks it up automatically.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DenseLayout {
+    /// Breadth-first order, with the children of node `i` at `2i` and `2i + 1`.
+    ///
+    /// Every level of a proof lives in a different region of the file, so
+    /// reading a proof touches about one page per level.
+    #[default]
+    Heap,
+
+    /// Subtrees of a few levels are stored contiguously, each fitting in a
+    /// 4 KiB page, with the blocks themselves in breadth-first order.
+    ///
+    /// Reading a proof touches one page per block of levels instead of one
+    /// per level.
+    Blocked,
+}
+
+impl DenseLayout {
+    /// Returns the first slot of a file stored in this layout.
+    ///
+    /// Heap files keep the empty leaf there, which is what files written
+    /// before layouts were introduced contain. Blocked files store its
+    /// bitwise complement, which can never be mistaken for it.
+    pub(crate) fn marker<T: Hash>(self, empty_leaf: &T) -> T {
+        let mut marker = *empty_leaf;
+        if self == Self::Blocked {
+            bytemuck::bytes_of_mut(&mut marker)
+                .iter_mut()
+                .for_each(|byte| *byte = !*byte);
+        }
+        marker
+    }
+
+    /// Detects the layout of a file from its first slot.
+    pub(crate) fn from_marker<T: Hash>(marker: &T, empty_leaf: &T) -> Self {
+        if size_of::<T>() > 0 && *marker == Self::Blocked.marker(empty_leaf) {
+            Self::Blocked
+        } else {
+            Self::Heap
+        }
+    }
+
+    pub(crate) fn node_order<T>(self, depth: usize) -> NodeOrder {
+        let block_height = match self {
+            Self::Heap => 0,
+            Self::Blocked => (BLOCK_BYTES / size_of::<T>().max(1) + 1).ilog2() as usize,
+        };
+        NodeOrder {
+            block_height,
+            levels: depth + 1,
+        }
+    }
+}
+
+/// Maps heap indices of a dense tree to their position in storage.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct NodeOrder {
+    /// Number of levels per block, zero for the heap layout.
+    block_height: usize,
+    levels: usize,
+}
+
+impl NodeOrder {
+    /// Returns the storage position of the node at the given heap index.
+    ///
+    /// Index zero is not a node and maps to itself in every layout.
+    pub(crate) fn position(&self, index: usize) -> usize {
+        if self.block_height == 0 || index == 0 {
+            return index;
+        }
+        let level = index.ilog2() as usize;
+        let level_in_block = level % self.block_height;
+        let top_level = level - level_in_block;
+
+        // All levels above `top_level` are fully packed into the preceding
+        // blocks, which take exactly `2^top_level - 1` slots.
+        let block_root = index >> level_in_block;
+        let block_index = block_root - (1 << top_level);
+        let block_size = (1 << self.block_height.min(self.levels - top_level)) - 1;
+        let index_in_block = (1 << level_in_block) - 1 + (index - (block_root << level_in_block));
+
+        (1 << top_level) + block_index * block_size + index_in_block
+    }
+
+    /// Reorders a heap ordered buffer into this layout.
+    pub(crate) fn permute<T: Copy>(&self, heap: Vec<T>) -> Vec<T> {
+        if self.block_height == 0 {
+            return heap;
+        }
+        let mut storage = heap.clone();
+        for (index, value) in heap.iter().enumerate() {
+            storage[self.position(index)] = *value;
+        }
+        storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::iter::successors;
+
+    use super::*;
+
+    #[test]
+    fn test_heap_is_identity() {
+        let order = DenseLayout::Heap.node_order::<[u8; 32]>(10);
+        for index in 0..(1 << 11) {
+            assert_eq!(order.position(index), index);
+        }
+    }
+
+    #[test]
+    fn test_blocked_is_permutation() {
+        for depth in 0..16 {
+            let order = DenseLayout::Blocked.node_order::<[u8; 32]>(depth);
+            let positions: HashSet<_> = (0..(1 << (depth + 1)))
+                .map(|index| order.position(index))
+                .collect();
+            assert_eq!(positions.len(), 1 << (depth + 1));
+            assert!(positions.iter().all(|&p| p < 1 << (depth + 1)));
+        }
+    }
+
+    #[test]
+    fn test_blocked_keeps_subtrees_together() {
+        let order = DenseLayout::Blocked.node_order::<[u8; 32]>(20);
+        assert_eq!(order.block_height, 7);
+
+        // The top block is identical to the heap layout.
+        for index in 1..(1 << 7) {
+            assert_eq!(order.position(index), index);
+        }
+
+        // The nodes along a path share one block per group of seven levels,
+        // and their siblings are in that block or the one next to it.
+        let leaf: usize = (1 << 20) + 123_456;
+        let path: Vec<_> = successors(Some(leaf), |&i| (i > 1).then_some(i / 2)).collect();
+        for top_level in [0, 7, 14] {
+            let in_group = |i: &&usize| (top_level..top_level + 7).contains(&(i.ilog2() as usize));
+            let nodes: Vec<_> = path
+                .iter()
+                .filter(in_group)
+                .map(|&i| order.position(i))
+                .collect();
+            let siblings: Vec<_> = path
+                .iter()
+                .filter(|&&i| i > 1)
+                .filter(in_group)
+                .map(|&i| order.position(i ^ 1))
+                .collect();
+            let spread = |positions: &[usize]| {
+                positions.iter().max().unwrap() - positions.iter().min().unwrap()
+            };
+            assert!(spread(&nodes) < 127);
+            assert!(spread(&[nodes, siblings].concat()) < 2 * 127);
+        }
+    }
+
+    #[test]
+    fn test_marker_roundtrip() {
+        let empty = [7_u8; 32];
+        for layout in [DenseLayout::Heap, DenseLayout::Blocked] {
+            let marker = layout.marker(&empty);
+            assert_eq!(DenseLayout::from_marker(&marker, &empty), layout);
+        }
+    }
+}
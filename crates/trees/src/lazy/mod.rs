@@ -1,17 +1,21 @@
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::iter::{once, repeat, successors};
 use std::ops::{Deref, DerefMut};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
 use hasher::{Hash, Hasher};
-use mmap_rs::{MmapFlags, MmapMut, MmapOptions};
 use rayon::prelude::*;
+use storage::mmap::{MmapFlags, MmapMut, MmapOptions};
 use thiserror::Error;
 
-use crate::{Branch, Proof};
+pub use self::layout::DenseLayout;
+use self::layout::NodeOrder;
+use crate::{Branch, Parallelism, Proof};
+
+mod layout;
 
 pub trait VersionMarker {}
 #[derive(Debug)]
@@ -88,6 +92,29 @@ where
         }
     }
 
+    /// Like [`Self::new_with_dense_prefix_with_initial_values`], hashing the
+    /// dense prefix on the thread pool chosen by `parallelism`.
+    #[must_use]
+    pub fn new_with_dense_prefix_with_initial_values_in(
+        depth: usize,
+        prefix_depth: usize,
+        empty_value: &H::Hash,
+        initial_values: &[H::Hash],
+        parallelism: Parallelism<'_>,
+    ) -> LazyMerkleTree<H, Canonical>
+    where
+        LazyMerkleTree<H, Canonical>: Send,
+    {
+        parallelism.install(|| {
+            Self::new_with_dense_prefix_with_initial_values(
+                depth,
+                prefix_depth,
+                empty_value,
+                initial_values,
+            )
+        })
+    }
+
     /// Creates a new memory mapped file specified by path and creates a tree
     /// with dense prefix of the given depth with initial values
     pub fn new_mmapped_with_dense_prefix_with_init_values(
@@ -96,6 +123,29 @@ where
         empty_value: &H::Hash,
         initial_values: &[H::Hash],
         file_path: &str,
+    ) -> Result<LazyMerkleTree<H, Canonical>, DenseMMapError> {
+        Self::new_mmapped_with_layout(
+            depth,
+            prefix_depth,
+            empty_value,
+            initial_values,
+            file_path,
+            DenseLayout::Heap,
+        )
+    }
+
+    /// Like [`Self::new_mmapped_with_dense_prefix_with_init_values`], storing
+    /// the dense prefix in the given node layout.
+    ///
+    /// The layout is recorded in the file and detected again by
+    /// [`Self::attempt_dense_mmap_restore`].
+    pub fn new_mmapped_with_layout(
+        depth: usize,
+        prefix_depth: usize,
+        empty_value: &H::Hash,
+        initial_values: &[H::Hash],
+        file_path: &str,
+        layout: DenseLayout,
     ) -> Result<LazyMerkleTree<H, Canonical>, DenseMMapError> {
         Ok(LazyMerkleTree {
             tree: AnyTree::new_mmapped_with_dense_prefix_with_init_values(
@@ -104,6 +154,7 @@ where
                 empty_value,
                 initial_values,
                 file_path,
+                layout,
             )?,
             _version: Canonical,
         })
@@ -120,19 +171,38 @@ where
         file_path: &str,
     ) -> Result<LazyMerkleTree<H, Canonical>, DenseMMapError> {
         Ok(LazyMerkleTree {
-            tree: match AnyTree::try_restore_dense_mmap_tree_state(
+            tree: AnyTree::try_restore_dense_mmap_tree_state(
                 depth,
                 prefix_depth,
                 empty_leaf,
                 file_path,
-            ) {
-                Ok(tree) => tree,
-                Err(e) => return Err(e),
-            },
+            )?,
             _version: Canonical,
         })
     }
 
+    /// Like [`Self::attempt_dense_mmap_restore`], but first rewrites a file
+    /// holding a dense prefix of a different depth to `prefix_depth`.
+    ///
+    /// Growing the prefix moves the stored tree into the leftmost subtree of
+    /// the new prefix. Shrinking it requires every leaf beyond the new prefix
+    /// to be empty. The layout of the file is kept. The new file is written
+    /// through a memory map next to the old one and then moved into place, so
+    /// the old file is left intact if resizing fails.
+    ///
+    /// # Errors
+    /// - resizing the file failed
+    /// - dense mmap tree restore failed
+    pub fn attempt_dense_mmap_restore_with_resize(
+        depth: usize,
+        prefix_depth: usize,
+        empty_leaf: &H::Hash,
+        file_path: &str,
+    ) -> Result<LazyMerkleTree<H, Canonical>, DenseMMapError> {
+        DenseMMapTree::<H>::resize(empty_leaf, prefix_depth, file_path)?;
+        Self::attempt_dense_mmap_restore(depth, prefix_depth, empty_leaf, file_path)
+    }
+
     /// Returns the depth of the tree.
     #[must_use]
     pub const fn depth(&self) -> usize {
@@ -211,6 +281,44 @@ where
         }
     }
 
+    /// Shrinks the memory mapped dense prefix to the smallest depth holding
+    /// every non-empty leaf, returning the number of bytes removed from the
+    /// file.
+    ///
+    /// The empty subtrees at the end of the prefix are replaced with lazy
+    /// empty nodes and the file is rewritten without them, the same way as
+    /// [`Self::attempt_dense_mmap_restore_with_resize`] rewrites it. Empty
+    /// subtrees between non-empty leaves stay in the file. Returns zero if
+    /// the tree has no memory mapped prefix or it cannot shrink. The tree must
+    /// be restored with the new prefix depth afterwards.
+    ///
+    /// Trees derived from this one keep reading the old contents, which are
+    /// only freed on disk once all of them are dropped.
+    ///
+    /// # Errors
+    /// - the compacted file could not be written or mapped
+    pub fn compact(
+        &mut self,
+        empty_leaf: &H::Hash,
+        file_path: &str,
+    ) -> Result<u64, DenseMMapError> {
+        let Some(dense) = self.tree.dense_mmap_prefix() else {
+            return Ok(0);
+        };
+        let prefix_depth = dense.depth;
+        let Some((dense, reclaimed)) = dense.compact(empty_leaf, file_path)? else {
+            return Ok(0);
+        };
+
+        let mut prefix: AnyTree<H> = dense.into();
+        while prefix.depth() < prefix_depth {
+            let depth = prefix.depth();
+            prefix = SparseTree::new(prefix, EmptyTree::new(depth, *empty_leaf).into()).into();
+        }
+        self.tree = self.tree.with_dense_mmap_prefix(prefix);
+        Ok(reclaimed)
+    }
+
     /// Gives a `Derived` version of this tree. Useful for initializing
     /// versioned trees.
     #[must_use]
@@ -289,10 +397,16 @@ where
         empty_value: &H::Hash,
         initial_values: &[H::Hash],
         file_path: &str,
+        layout: DenseLayout,
     ) -> Result<Self, DenseMMapError> {
         assert!(depth >= prefix_depth);
-        let dense =
-            DenseMMapTree::new_with_values(initial_values, empty_value, prefix_depth, file_path)?;
+        let dense = DenseMMapTree::new_with_values(
+            initial_values,
+            empty_value,
+            prefix_depth,
+            file_path,
+            layout,
+        )?;
         let mut result: Self = dense.into();
         let mut current_depth = prefix_depth;
         while current_depth < depth {
@@ -323,6 +437,32 @@ where
         Ok(result)
     }
 
+    /// Returns the memory mapped tree at the bottom of the left spine.
+    fn dense_mmap_prefix(&self) -> Option<&DenseMMapTree<H>> {
+        match self {
+            Self::Sparse(tree) => tree.children.as_ref()?.left.dense_mmap_prefix(),
+            Self::DenseMMap(tree) => Some(tree),
+            Self::Empty(_) | Self::Dense(_) => None,
+        }
+    }
+
+    /// Replaces the memory mapped tree at the bottom of the left spine with a
+    /// tree of the same depth and root.
+    fn with_dense_mmap_prefix(&self, prefix: Self) -> Self {
+        match self {
+            Self::Sparse(SparseTree {
+                children: Some(children),
+                ..
+            }) => SparseTree::new(
+                children.left.with_dense_mmap_prefix(prefix),
+                children.right.as_ref().clone(),
+            )
+            .into(),
+            Self::DenseMMap(_) => prefix,
+            _ => self.clone(),
+        }
+    }
+
     const fn depth(&self) -> usize {
         match self {
             Self::Empty(tree) => tree.depth,
@@ -916,6 +1056,7 @@ where
 struct DenseMMapTree<H: Hasher> {
     depth: usize,
     root_index: usize,
+    order: NodeOrder,
     storage: Arc<Mutex<MmapMutWrapper<H>>>,
 }
 
@@ -924,6 +1065,7 @@ impl<H: Hasher> Clone for DenseMMapTree<H> {
         Self {
             depth: self.depth,
             root_index: self.root_index,
+            order: self.order,
             storage: self.storage.clone(),
         }
     }
@@ -945,19 +1087,24 @@ where
         empty_value: &H::Hash,
         depth: usize,
         mmap_file_path: &str,
+        layout: DenseLayout,
     ) -> Result<Self, DenseMMapError> {
         let path_buf = match PathBuf::from_str(mmap_file_path) {
             Ok(pb) => pb,
             Err(_e) => return Err(DenseMMapError::FailedToCreatePathBuf),
         };
 
-        let storage = DenseTree::<H>::vec_from_values(values, empty_value, depth);
+        let order = layout.node_order::<H::Hash>(depth);
+        let mut storage =
+            order.permute(DenseTree::<H>::vec_from_values(values, empty_value, depth));
+        storage[0] = layout.marker(empty_value);
 
         let mmap = MmapMutWrapper::new_from_storage(path_buf, &storage)?;
 
         Ok(Self {
             depth,
             root_index: 1,
+            order,
             storage: Arc::new(Mutex::new(mmap)),
         })
     }
@@ -984,14 +1131,208 @@ where
         };
 
         let mmap = MmapMutWrapper::attempt_restore(empty_leaf, depth, path_buf)?;
+        let layout = DenseLayout::from_marker(&mmap[0], empty_leaf);
 
         Ok(Self {
             depth,
             root_index: 1,
+            order: layout.node_order::<H::Hash>(depth),
             storage: Arc::new(Mutex::new(mmap)),
         })
     }
 
+    /// Rewrites the file at the given path to hold a dense tree of the given
+    /// depth, keeping its layout and leaves. Does nothing if the file already
+    /// holds a tree of that depth.
+    ///
+    /// # Errors
+    ///
+    /// - returns Err if the file doesn't hold a dense tree
+    /// - returns Err if shrinking would drop non-empty leaves
+    /// - returns Err if the new file cannot be written or moved into place
+    fn resize(
+        empty_leaf: &H::Hash,
+        depth: usize,
+        mmap_file_path: &str,
+    ) -> Result<(), DenseMMapError> {
+        let path_buf = match PathBuf::from_str(mmap_file_path) {
+            Ok(pb) => pb,
+            Err(_e) => return Err(DenseMMapError::FailedToCreatePathBuf),
+        };
+
+        let file_len = std::fs::metadata(&path_buf)
+            .map_err(DenseMMapError::FileDoesntExist)?
+            .len();
+        let node_size = std::mem::size_of::<H::Hash>() as u64;
+        let nodes = file_len / node_size;
+        if file_len % node_size != 0 || nodes < 2 || !nodes.is_power_of_two() {
+            return Err(DenseMMapError::FileSizeShouldMatchTree);
+        }
+        let old_depth = nodes.trailing_zeros() as usize - 1;
+        if old_depth == depth {
+            return Ok(());
+        }
+
+        let old = MmapMutWrapper::<H>::attempt_restore(empty_leaf, old_depth, path_buf.clone())?;
+        let tmp_path = Self::write_resized(&old, old_depth, empty_leaf, depth, &path_buf)?;
+        drop(old);
+        std::fs::rename(&tmp_path, &path_buf).map_err(DenseMMapError::FailedToReplaceFile)
+    }
+
+    /// Writes the tree stored in `old` with its depth changed to `depth` to a
+    /// file next to `path_buf`, returning the path of the new file.
+    ///
+    /// # Errors
+    ///
+    /// - returns Err if shrinking would drop non-empty leaves
+    /// - returns Err if the new file cannot be written
+    fn write_resized(
+        old: &MmapMutWrapper<H>,
+        old_depth: usize,
+        empty_leaf: &H::Hash,
+        depth: usize,
+        path_buf: &Path,
+    ) -> Result<PathBuf, DenseMMapError> {
+        let node_size = std::mem::size_of::<H::Hash>() as u64;
+        let layout = DenseLayout::from_marker(&old[0], empty_leaf);
+        let old_order = layout.node_order::<H::Hash>(old_depth);
+        let old_node = |level: usize, offset: usize| old[old_order.position((1 << level) + offset)];
+
+        // Empty subtree roots by height
+        let empties: Vec<H::Hash> =
+            successors(Some(*empty_leaf), |prev| Some(H::hash_node(prev, prev)))
+                .take(depth.max(old_depth) + 1)
+                .collect();
+
+        // When growing, the nodes above the old root on the left spine
+        let mut spine = Vec::new();
+        if depth > old_depth {
+            let shift = depth - old_depth;
+            spine = vec![old_node(0, 0); shift + 1];
+            for level in (0..shift).rev() {
+                spine[level] = H::hash_node(&spine[level + 1], &empties[depth - level - 1]);
+            }
+        } else {
+            for level in 1..=old_depth - depth {
+                if old_node(level, 1) != empties[old_depth - level] {
+                    return Err(DenseMMapError::LeavesOutsidePrefix);
+                }
+            }
+        }
+        let new_node = |level: usize, offset: usize| {
+            if depth < old_depth {
+                return old_node(level + old_depth - depth, offset);
+            }
+            let shift = depth - old_depth;
+            if level < shift {
+                if offset == 0 {
+                    spine[level]
+                } else {
+                    empties[depth - level]
+                }
+            } else if offset < 1 << (level - shift) {
+                old_node(level - shift, offset)
+            } else {
+                empties[depth - level]
+            }
+        };
+
+        let mut tmp_path = path_buf.as_os_str().to_owned();
+        tmp_path.push(".resize");
+        let tmp_path = PathBuf::from(tmp_path);
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+        {
+            Ok(file) => file,
+            Err(e) => return Err(DenseMMapError::FileCreationFailed(e)),
+        };
+        let len =
+            usize::try_from(node_size << (depth + 1)).map_err(|_| DenseMMapError::FileTooLarge)?;
+        file.set_len(len as u64)
+            .map_err(DenseMMapError::FailedToSetFileSize)?;
+        let mut new = MmapMutWrapper::<H> {
+            mmap: map_file(&file, len)?,
+            phantom: std::marker::PhantomData,
+        };
+
+        let new_order = layout.node_order::<H::Hash>(depth);
+        new[0] = layout.marker(empty_leaf);
+        for level in 0..=depth {
+            for offset in 0..1 << level {
+                new[new_order.position((1 << level) + offset)] = new_node(level, offset);
+            }
+        }
+        new.mmap
+            .flush(0..len)
+            .map_err(DenseMMapError::FailedToMap)?;
+
+        drop(new);
+        Ok(tmp_path)
+    }
+
+    /// Shrinks the file at the given path to the smallest dense tree holding
+    /// every non-empty leaf, returning the compacted tree and the number of
+    /// bytes removed from the file. Returns `None` if the tree cannot shrink,
+    /// or is a subtree of the stored tree.
+    ///
+    /// # Errors
+    ///
+    /// - returns Err if the new file cannot be written, moved into place or
+    ///   mapped
+    ///
+    /// # Panics
+    ///
+    /// - mutex lock is poisoned
+    fn compact(
+        &self,
+        empty_leaf: &H::Hash,
+        mmap_file_path: &str,
+    ) -> Result<Option<(Self, u64)>, DenseMMapError> {
+        if self.root_index != 1 {
+            return Ok(None);
+        }
+        let path_buf = match PathBuf::from_str(mmap_file_path) {
+            Ok(pb) => pb,
+            Err(_e) => return Err(DenseMMapError::FailedToCreatePathBuf),
+        };
+
+        let storage = self.storage.lock().expect("lock poisoned, terminating");
+
+        // The right child of the node at `level` on the left spine covers
+        // the leaves from `2^(depth - level)` on, so the tree can drop every
+        // level whose right child is an empty subtree.
+        let empties: Vec<H::Hash> =
+            successors(Some(*empty_leaf), |prev| Some(H::hash_node(prev, prev)))
+                .take(self.depth + 1)
+                .collect();
+        let mut depth = self.depth;
+        for level in 1..=self.depth {
+            let right = storage[self.order.position((1 << level) + 1)];
+            if right != empties[self.depth - level] {
+                break;
+            }
+            depth -= 1;
+        }
+        if depth == self.depth {
+            return Ok(None);
+        }
+
+        let tmp_path = Self::write_resized(&storage, self.depth, empty_leaf, depth, &path_buf)?;
+        std::fs::rename(&tmp_path, &path_buf).map_err(DenseMMapError::FailedToReplaceFile)?;
+        drop(storage);
+
+        let node_size = std::mem::size_of::<H::Hash>() as u64;
+        let reclaimed = node_size * ((2 << self.depth) - (2 << depth));
+        Ok(Some((
+            Self::attempt_restore(empty_leaf, depth, mmap_file_path)?,
+            reclaimed,
+        )))
+    }
+
     fn with_ref<F, R>(&self, fun: F) -> R
     where
         F: FnOnce(DenseTreeMMapRef<H>) -> R,
@@ -1000,6 +1341,7 @@ where
         let r = DenseTreeMMapRef {
             depth: self.depth,
             root_index: self.root_index,
+            order: self.order,
             storage: &guard,
             locked_storage: &self.storage,
         };
@@ -1013,7 +1355,7 @@ where
     fn get_leaf(&self, index: usize) -> H::Hash {
         self.with_ref(|r| {
             let leaf_index_in_dense_tree = index + (self.root_index << self.depth);
-            r.storage[leaf_index_in_dense_tree]
+            r.storage[self.order.position(leaf_index_in_dense_tree)]
         })
     }
 
@@ -1034,24 +1376,25 @@ where
     fn update_with_mutation(&self, index: usize, value: &H::Hash) {
         let mut storage = self.storage.lock().expect("lock poisoned, terminating");
         let leaf_index_in_dense_tree = index + (self.root_index << self.depth);
-        storage[leaf_index_in_dense_tree] = *value;
+        storage[self.order.position(leaf_index_in_dense_tree)] = *value;
         let mut current = leaf_index_in_dense_tree / 2;
         while current > 0 {
-            let left = &storage[2 * current];
-            let right = &storage[2 * current + 1];
-            storage[current] = H::hash_node(left, right);
+            let left = &storage[self.order.position(2 * current)];
+            let right = &storage[self.order.position(2 * current + 1)];
+            storage[self.order.position(current)] = H::hash_node(left, right);
             current /= 2;
         }
     }
 
     fn root(&self) -> H::Hash {
-        self.storage.lock().expect("lock poisoned")[self.root_index]
+        self.storage.lock().expect("lock poisoned")[self.order.position(self.root_index)]
     }
 }
 
 struct DenseTreeMMapRef<'a, H: Hasher> {
     depth: usize,
     root_index: usize,
+    order: NodeOrder,
     storage: &'a MmapMutWrapper<H>,
     locked_storage: &'a Arc<Mutex<MmapMutWrapper<H>>>,
 }
@@ -1061,6 +1404,7 @@ impl<'a, H: Hasher> From<DenseTreeMMapRef<'a, H>> for DenseMMapTree<H> {
         Self {
             depth: value.depth,
             root_index: value.root_index,
+            order: value.order,
             storage: value.locked_storage.clone(),
         }
     }
@@ -1078,13 +1422,14 @@ where
     <H as Hasher>::Hash: Hash,
 {
     fn root(&self) -> H::Hash {
-        self.storage[self.root_index]
+        self.storage[self.order.position(self.root_index)]
     }
 
     const fn left(&self) -> DenseTreeMMapRef<H> {
         Self {
             depth: self.depth - 1,
             root_index: 2 * self.root_index,
+            order: self.order,
             storage: self.storage,
             locked_storage: self.locked_storage,
         }
@@ -1094,6 +1439,7 @@ where
         Self {
             depth: self.depth - 1,
             root_index: 2 * self.root_index + 1,
+            order: self.order,
             storage: self.storage,
             locked_storage: self.locked_storage,
         }
@@ -1164,14 +1510,9 @@ where
     /// # Errors
     ///
     /// - returns Err if file creation has failed
+    /// - returns Err if file size cannot be set
     /// - returns Err if bytes couldn't be written to file
-    ///
-    /// # Panics
-    ///
-    /// - empty hash value serialization failed
-    /// - file size cannot be set
-    /// - file is too large, possible truncation can occur
-    /// - cannot build memory map
+    /// - returns Err if memory map cannot be built
     pub fn new_from_storage(
         file_path: PathBuf,
         storage: &[H::Hash],
@@ -1189,22 +1530,15 @@ where
             .open(file_path)
         {
             Ok(file) => file,
-            Err(_e) => return Err(DenseMMapError::FileCreationFailed),
+            Err(e) => return Err(DenseMMapError::FileCreationFailed(e)),
         };
 
-        file.set_len(buf_len as u64).expect("cannot set file size");
-        if file.write_all(buf).is_err() {
-            return Err(DenseMMapError::FileCannotWriteBytes);
-        }
+        file.set_len(buf_len as u64)
+            .map_err(DenseMMapError::FailedToSetFileSize)?;
+        file.write_all(buf)
+            .map_err(DenseMMapError::FileCannotWriteBytes)?;
 
-        let mmap = unsafe {
-            MmapOptions::new(usize::try_from(buf_len as u64).expect("file size truncated"))
-                .expect("cannot create memory map")
-                .with_file(&file, 0)
-                .with_flags(MmapFlags::SHARED)
-                .map_mut()
-                .expect("cannot build memory map")
-        };
+        let mmap = map_file(&file, buf_len)?;
 
         Ok(Self {
             mmap,
@@ -1218,13 +1552,9 @@ where
     /// # Errors
     ///
     /// - returns Err if file doesn't exist
+    /// - returns Err if file metadata cannot be read
     /// - returns Err if file size doesn't match the expected tree size
-    ///
-    /// # Panics
-    ///
-    /// - cannot get file metadata to check for file length
-    /// - truncated file size when attempting to build memory map
-    /// - cannot build memory map
+    /// - returns Err if memory map cannot be built
     pub fn attempt_restore(
         empty_leaf: &H::Hash,
         depth: usize,
@@ -1232,26 +1562,21 @@ where
     ) -> Result<Self, DenseMMapError> {
         let file = match OpenOptions::new().read(true).write(true).open(file_path) {
             Ok(file) => file,
-            Err(_e) => return Err(DenseMMapError::FileDoesntExist),
+            Err(e) => return Err(DenseMMapError::FileDoesntExist(e)),
         };
 
         let size_of_empty_leaf = std::mem::size_of_val(empty_leaf);
         let expected_file_size = (1 << (depth + 1)) * size_of_empty_leaf as u64;
 
-        if expected_file_size != file.metadata().expect("cannot get file metadata").len() {
+        let metadata = file
+            .metadata()
+            .map_err(DenseMMapError::FailedToReadMetadata)?;
+        if expected_file_size != metadata.len() {
             return Err(DenseMMapError::FileSizeShouldMatchTree);
         }
 
-        let mmap = unsafe {
-            MmapOptions::new(
-                usize::try_from(expected_file_size).expect("expected file size truncated"),
-            )
-            .expect("cannot create memory map")
-            .with_file(&file, 0)
-            .with_flags(MmapFlags::SHARED)
-            .map_mut()
-            .expect("cannot build memory map")
-        };
+        let len = usize::try_from(expected_file_size).map_err(|_| DenseMMapError::FileTooLarge)?;
+        let mmap = map_file(&file, len)?;
 
         Ok(Self {
             mmap,
@@ -1260,6 +1585,20 @@ where
     }
 }
 
+/// Maps `len` bytes of `file` into memory as shared and writable.
+fn map_file(file: &File, len: usize) -> Result<MmapMut, DenseMMapError> {
+    // Safety: the mapping is shared with the file, which outlives neither the
+    // wrapper nor the tree owning it.
+    unsafe {
+        MmapOptions::new(len)
+            .map_err(DenseMMapError::FailedToMap)?
+            .with_file(file, 0)
+            .with_flags(MmapFlags::SHARED)
+            .map_mut()
+            .map_err(DenseMMapError::FailedToMap)
+    }
+}
+
 impl<H> Deref for MmapMutWrapper<H>
 where
     H: Hasher,
@@ -1287,13 +1626,25 @@ pub enum DenseMMapError {
     #[error("file size should match expected tree size")]
     FileSizeShouldMatchTree,
     #[error("file doesn't exist")]
-    FileDoesntExist,
+    FileDoesntExist(#[source] std::io::Error),
     #[error("failed to create a file")]
-    FileCreationFailed,
+    FileCreationFailed(#[source] std::io::Error),
     #[error("cannot write bytes to file")]
-    FileCannotWriteBytes,
+    FileCannotWriteBytes(#[source] std::io::Error),
+    #[error("cannot set file size")]
+    FailedToSetFileSize(#[source] std::io::Error),
+    #[error("cannot read file metadata")]
+    FailedToReadMetadata(#[source] std::io::Error),
+    #[error("file is too large to be memory mapped")]
+    FileTooLarge,
+    #[error("cannot build memory map")]
+    FailedToMap(#[source] storage::mmap::Error),
     #[error("failed to create pathbuf")]
     FailedToCreatePathBuf,
+    #[error("file has non-empty leaves outside of the new dense prefix")]
+    LeavesOutsidePrefix,
+    #[error("cannot replace file")]
+    FailedToReplaceFile(#[source] std::io::Error),
 }
 
 #[cfg(test)]
@@ -1580,4 +1931,211 @@ mod tests {
         // remove mmap file at the end
         std::fs::remove_file("./testfile").unwrap();
     }
+
+    #[test]
+    fn test_dense_mmap_blocked_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let heap_path = dir.path().join("heap");
+        let blocked_path = dir.path().join("blocked");
+        let initial_values: Vec<u64> = (1..=300).collect();
+
+        let heap = LazyMerkleTree::<TestHasher>::new_mmapped_with_layout(
+            12,
+            10,
+            &0,
+            &initial_values,
+            heap_path.to_str().unwrap(),
+            DenseLayout::Heap,
+        )
+        .unwrap();
+        let blocked = LazyMerkleTree::<TestHasher>::new_mmapped_with_layout(
+            12,
+            10,
+            &0,
+            &initial_values,
+            blocked_path.to_str().unwrap(),
+            DenseLayout::Blocked,
+        )
+        .unwrap();
+
+        let heap = heap.update_with_mutation(700, &42);
+        let blocked = blocked.update_with_mutation(700, &42);
+        let derived = blocked.update(3000, &43);
+        assert_eq!(derived.root(), heap.update(3000, &43).root());
+
+        assert_eq!(blocked.root(), heap.root());
+        for index in [0, 1, 299, 300, 700, 1023, 1024, 4095] {
+            assert_eq!(blocked.get_leaf(index), heap.get_leaf(index));
+            assert_eq!(blocked.proof(index), heap.proof(index));
+        }
+        drop(blocked);
+
+        let restored = LazyMerkleTree::<TestHasher>::attempt_dense_mmap_restore(
+            12,
+            10,
+            &0,
+            blocked_path.to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored.root(), heap.root());
+        assert_eq!(restored.proof(700), heap.proof(700));
+        assert!(restored.leaves().eq(heap.leaves()));
+    }
+
+    #[test]
+    fn test_dense_mmap_restore_with_resize() {
+        let dir = tempfile::tempdir().unwrap();
+        let initial_values: Vec<u64> = (1..=6).collect();
+        let expected = LazyMerkleTree::<TestHasher>::new_with_dense_prefix_with_initial_values(
+            12,
+            3,
+            &0,
+            &initial_values,
+        );
+
+        for layout in [DenseLayout::Heap, DenseLayout::Blocked] {
+            let path = dir.path().join(format!("{layout:?}"));
+            let path = path.to_str().unwrap();
+            LazyMerkleTree::<TestHasher>::new_mmapped_with_layout(
+                12,
+                3,
+                &0,
+                &initial_values,
+                path,
+                layout,
+            )
+            .unwrap();
+
+            let err = LazyMerkleTree::<TestHasher>::attempt_dense_mmap_restore(12, 10, &0, path)
+                .map(drop)
+                .unwrap_err();
+            assert!(matches!(err, DenseMMapError::FileSizeShouldMatchTree));
+
+            // Growing keeps every leaf
+            let grown = LazyMerkleTree::<TestHasher>::attempt_dense_mmap_restore_with_resize(
+                12, 10, &0, path,
+            )
+            .unwrap();
+            assert_eq!(grown.root(), expected.root());
+            assert_eq!(grown.proof(5), expected.proof(5));
+            let grown = grown.update_with_mutation(1000, &7);
+            let expected = expected.update(1000, &7);
+            assert_eq!(grown.root(), expected.root());
+            drop(grown);
+
+            // Shrinking would drop leaf 1000
+            let err = LazyMerkleTree::<TestHasher>::attempt_dense_mmap_restore_with_resize(
+                12, 3, &0, path,
+            )
+            .map(drop)
+            .unwrap_err();
+            assert!(matches!(err, DenseMMapError::LeavesOutsidePrefix));
+
+            let restored = LazyMerkleTree::<TestHasher>::attempt_dense_mmap_restore_with_resize(
+                12, 10, &0, path,
+            )
+            .unwrap();
+            assert_eq!(restored.root(), expected.root());
+            let restored = restored.update_with_mutation(1000, &0);
+            drop(restored);
+
+            let shrunk = LazyMerkleTree::<TestHasher>::attempt_dense_mmap_restore_with_resize(
+                12, 3, &0, path,
+            )
+            .unwrap();
+            assert_eq!(shrunk.root(), expected.update(1000, &0).root());
+            assert!(shrunk.leaves().eq(expected.update(1000, &0).leaves()));
+        }
+    }
+
+    #[test]
+    fn test_dense_mmap_compact() {
+        let dir = tempfile::tempdir().unwrap();
+        let initial_values: Vec<u64> = (1..=6).collect();
+        let expected = LazyMerkleTree::<TestHasher>::new_with_dense_prefix_with_initial_values(
+            12,
+            10,
+            &0,
+            &initial_values,
+        )
+        .update(3000, &9);
+
+        for layout in [DenseLayout::Heap, DenseLayout::Blocked] {
+            let path = dir.path().join(format!("{layout:?}"));
+            let path = path.to_str().unwrap();
+            let tree = LazyMerkleTree::<TestHasher>::new_mmapped_with_layout(
+                12,
+                10,
+                &0,
+                &initial_values,
+                path,
+                layout,
+            )
+            .unwrap();
+            let mut tree = tree
+                .update_with_mutation(700, &8)
+                .update_with_mutation(700, &0)
+                .update_with_mutation(3000, &9);
+
+            // Leaf 5 is the last non-empty leaf, so three levels are enough
+            assert_eq!(tree.compact(&0, path).unwrap(), 8 * ((2 << 10) - (2 << 3)));
+            assert_eq!(std::fs::metadata(path).unwrap().len(), 8 * (2 << 3));
+            assert_eq!(tree.root(), expected.root());
+            assert_eq!(tree.proof(5), expected.proof(5));
+            assert_eq!(tree.proof(3000), expected.proof(3000));
+            assert_eq!(tree.compact(&0, path).unwrap(), 0);
+
+            let tree = tree.update_with_mutation(2, &10);
+            assert_eq!(tree.root(), expected.update(2, &10).root());
+            drop(tree);
+
+            let restored =
+                LazyMerkleTree::<TestHasher>::attempt_dense_mmap_restore(12, 3, &0, path).unwrap();
+            assert_eq!(restored.get_leaf(2), 10);
+            assert_eq!(restored.get_leaf(5), 6);
+        }
+    }
+
+    #[test]
+    fn test_dense_mmap_restore_errors() {
+        let h0 = [0; 32];
+        let dir = tempfile::tempdir().unwrap();
+
+        let missing = dir.path().join("missing");
+        let err = LazyMerkleTree::<Keccak256>::attempt_dense_mmap_restore(
+            3,
+            3,
+            &h0,
+            missing.to_str().unwrap(),
+        )
+        .map(drop)
+        .unwrap_err();
+        let DenseMMapError::FileDoesntExist(source) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+
+        let truncated = dir.path().join("truncated");
+        std::fs::write(&truncated, [0; 32]).unwrap();
+        let err = LazyMerkleTree::<Keccak256>::attempt_dense_mmap_restore(
+            3,
+            3,
+            &h0,
+            truncated.to_str().unwrap(),
+        )
+        .map(drop)
+        .unwrap_err();
+        assert!(matches!(err, DenseMMapError::FileSizeShouldMatchTree));
+
+        let err = LazyMerkleTree::<Keccak256>::new_mmapped_with_dense_prefix_with_init_values(
+            3,
+            3,
+            &h0,
+            &[],
+            dir.path().join("no/such/dir").to_str().unwrap(),
+        )
+        .map(drop)
+        .unwrap_err();
+        assert!(matches!(err, DenseMMapError::FileCreationFailed(_)));
+    }
 }
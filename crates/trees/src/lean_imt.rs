@@ -0,0 +1,421 @@
+//! Lean incremental Merkle tree, as used by the Semaphore v4 contracts.
+//!
+//! Unlike the fixed depth trees in this crate, a lean tree has no empty
+//! leaves: its depth is the smallest that fits its leaves, and a node
+//! without a right sibling is carried up to its parent unchanged instead of
+//! being hashed with a zero value. With the Poseidon hasher, roots match
+//! those of the `LeanIMT` library of `zk-kit` that the contracts use.
+//!
+//! Proofs skip the levels at which the node has no sibling, so they are
+//! ordinary [`Proof`]s that can be shorter than the depth of the tree. Their
+//! length and [`Proof::leaf_index`] are the `merkleProofLength` and
+//! `merkleProofIndex` inputs of the v4 circuit.
+
+use std::fmt::Debug;
+
+use bytemuck::Pod;
+use derive_where::derive_where;
+use hasher::Hasher;
+
+use crate::error::{Result, TreeError};
+use crate::proof::{Branch, Proof};
+
+/// Merkle tree of dynamic depth with all leaf and intermediate hashes stored
+#[derive_where(Clone; <H as Hasher>::Hash: Clone)]
+#[derive_where(PartialEq; <H as Hasher>::Hash: PartialEq)]
+#[derive_where(Eq; <H as Hasher>::Hash: Eq)]
+#[derive_where(Debug; <H as Hasher>::Hash: Debug)]
+pub struct LeanIMT<H>
+where
+    H: Hasher,
+{
+    /// Hash values of tree nodes per level, starting at the leaves. The last
+    /// level holds the root, if any.
+    nodes: Vec<Vec<H::Hash>>,
+}
+
+impl<H> Default for LeanIMT<H>
+where
+    H: Hasher,
+{
+    fn default() -> Self {
+        Self {
+            nodes: vec![vec![]],
+        }
+    }
+}
+
+impl<H> LeanIMT<H>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Clone + Copy + Pod + Eq + Debug,
+{
+    /// Creates an empty tree
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a tree from leaves, as if they were inserted one by one
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TreeError::ZeroLeaf`] if any leaf is zero.
+    pub fn from_leaves<I: IntoIterator<Item = H::Hash>>(leaves: I) -> Result<Self> {
+        let mut tree = Self::new();
+        tree.insert_many(leaves)?;
+        Ok(tree)
+    }
+
+    /// Number of leaves, including removed ones
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.nodes[0].len()
+    }
+
+    /// Number of levels above the leaves
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    /// Returns the root, or `None` if the tree is empty
+    #[must_use]
+    pub fn root(&self) -> Option<H::Hash> {
+        self.nodes[self.depth()].first().copied()
+    }
+
+    #[must_use]
+    pub fn leaves(&self) -> &[H::Hash] {
+        &self.nodes[0]
+    }
+
+    /// Returns the index of the first leaf with the given value
+    #[must_use]
+    pub fn index_of(&self, leaf: &H::Hash) -> Option<usize> {
+        self.nodes[0].iter().position(|node| node == leaf)
+    }
+
+    /// Appends a leaf to the tree
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TreeError::ZeroLeaf`] if the leaf is zero, which marks
+    /// removed leaves.
+    pub fn insert(&mut self, leaf: H::Hash) -> Result<()> {
+        check_leaf(&leaf)?;
+
+        let mut index = self.size();
+        if self.depth() < ceil_log2(index + 1) {
+            self.nodes.push(vec![]);
+        }
+
+        let mut node = leaf;
+        for level in 0..self.depth() {
+            set_node(&mut self.nodes[level], index, node);
+            if index & 1 == 1 {
+                node = H::hash_node(&self.nodes[level][index - 1], &node);
+            }
+            index >>= 1;
+        }
+
+        let depth = self.depth();
+        self.nodes[depth] = vec![node];
+        Ok(())
+    }
+
+    /// Appends leaves to the tree. The result is the same as inserting them
+    /// one by one, as with the `insertMany` function of the contracts.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TreeError::ZeroLeaf`] if any leaf is zero, in which case
+    /// the leaves before it are inserted.
+    pub fn insert_many<I: IntoIterator<Item = H::Hash>>(&mut self, leaves: I) -> Result<()> {
+        leaves.into_iter().try_for_each(|leaf| self.insert(leaf))
+    }
+
+    /// Replaces the leaf at `index`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TreeError::LeafOutOfBounds`] if there is no leaf at `index`
+    /// and [`TreeError::ZeroLeaf`] if the leaf is zero, see
+    /// [`remove`](Self::remove).
+    pub fn update(&mut self, index: usize, leaf: H::Hash) -> Result<()> {
+        check_leaf(&leaf)?;
+        self.set(index, leaf)
+    }
+
+    /// Removes the leaf at `index` by setting it to zero. The size of the
+    /// tree and the indices of other leaves are unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TreeError::LeafOutOfBounds`] if there is no leaf at `index`.
+    pub fn remove(&mut self, index: usize) -> Result<()> {
+        self.set(index, bytemuck::Zeroable::zeroed())
+    }
+
+    fn set(&mut self, mut index: usize, leaf: H::Hash) -> Result<()> {
+        if index >= self.size() {
+            return Err(TreeError::LeafOutOfBounds {
+                index,
+                size: self.size(),
+            });
+        }
+
+        let mut node = leaf;
+        for level in 0..self.depth() {
+            let nodes = &mut self.nodes[level];
+            nodes[index] = node;
+            if index & 1 == 1 {
+                node = H::hash_node(&nodes[index - 1], &node);
+            } else if let Some(sibling) = nodes.get(index + 1) {
+                node = H::hash_node(&node, sibling);
+            }
+            index >>= 1;
+        }
+
+        let depth = self.depth();
+        self.nodes[depth][0] = node;
+        Ok(())
+    }
+
+    /// Returns the proof for the leaf at `index`, or `None` if there is no
+    /// leaf there. Levels at which the node has no sibling are skipped.
+    #[must_use]
+    pub fn proof(&self, mut index: usize) -> Option<Proof<H>> {
+        if index >= self.size() {
+            return None;
+        }
+        let mut path = Vec::with_capacity(self.depth());
+        for nodes in &self.nodes[..self.depth()] {
+            if index & 1 == 1 {
+                path.push(Branch::Right(nodes[index - 1]));
+            } else if let Some(sibling) = nodes.get(index + 1) {
+                path.push(Branch::Left(*sibling));
+            }
+            index >>= 1;
+        }
+        Some(Proof(path))
+    }
+
+    #[must_use]
+    pub fn verify(&self, hash: H::Hash, proof: &Proof<H>) -> bool {
+        self.root() == Some(proof.root(hash))
+    }
+}
+
+fn check_leaf<T: Pod>(leaf: &T) -> Result<()> {
+    if bytemuck::bytes_of(leaf).iter().all(|&byte| byte == 0) {
+        Err(TreeError::ZeroLeaf)
+    } else {
+        Ok(())
+    }
+}
+
+fn set_node<T>(nodes: &mut Vec<T>, index: usize, node: T) {
+    if index == nodes.len() {
+        nodes.push(node);
+    } else {
+        nodes[index] = node;
+    }
+}
+
+/// Number of levels above the leaves of a tree of `size` leaves
+const fn ceil_log2(size: usize) -> usize {
+    if size <= 1 {
+        0
+    } else {
+        (size - 1).ilog2() as usize + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keccak::keccak::Keccak256;
+    use poseidon::Poseidon;
+    use ruint::aliases::U256;
+    use ruint::uint;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::imt::MerkleTree;
+
+    /// Root computed level by level, carrying up nodes without a sibling
+    fn reference_root(leaves: &[U256]) -> U256 {
+        let mut level = leaves.to_vec();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => Poseidon::hash_node(left, right),
+                    [node] => *node,
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        level[0]
+    }
+
+    fn leaves(count: usize) -> Vec<U256> {
+        (1..=count).map(U256::from).collect()
+    }
+
+    #[test_case(0 => 0)]
+    #[test_case(1 => 0)]
+    #[test_case(2 => 1)]
+    #[test_case(3 => 2)]
+    #[test_case(4 => 2)]
+    #[test_case(5 => 3)]
+    fn depth_of(size: usize) -> usize {
+        ceil_log2(size)
+    }
+
+    #[test]
+    fn test_empty() {
+        let tree = LeanIMT::<Poseidon>::new();
+        assert_eq!(tree.size(), 0);
+        assert_eq!(tree.depth(), 0);
+        assert_eq!(tree.root(), None);
+        assert_eq!(tree.proof(0), None);
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut tree = LeanIMT::<Poseidon>::new();
+        for (count, leaf) in leaves(17).into_iter().enumerate() {
+            tree.insert(leaf).unwrap();
+            let leaves = leaves(count + 1);
+            assert_eq!(tree.size(), count + 1);
+            assert_eq!(tree.depth(), ceil_log2(count + 1));
+            assert_eq!(tree.root(), Some(reference_root(&leaves)));
+        }
+
+        // A single leaf is the root
+        let tree = LeanIMT::<Poseidon>::from_leaves([U256::from(7)]).unwrap();
+        assert_eq!(tree.root(), Some(U256::from(7)));
+
+        assert!(matches!(
+            LeanIMT::<Poseidon>::new().insert(U256::ZERO),
+            Err(TreeError::ZeroLeaf)
+        ));
+    }
+
+    #[test]
+    fn test_full_tree_matches_imt() {
+        let leaves = leaves(16);
+        let lean = LeanIMT::<Poseidon>::from_leaves(leaves.iter().copied()).unwrap();
+        let mut tree = MerkleTree::<Poseidon>::new(4, U256::ZERO);
+        tree.set_range(0, leaves);
+        assert_eq!(lean.root(), Some(tree.root()));
+        assert_eq!(lean.proof(5), tree.proof(5));
+    }
+
+    #[test]
+    fn test_update_and_remove() {
+        let mut leaves = leaves(11);
+        let mut tree = LeanIMT::<Poseidon>::from_leaves(leaves.iter().copied()).unwrap();
+
+        for (index, value) in [(0, 100), (10, 101), (7, 102), (8, 103)] {
+            leaves[index] = U256::from(value);
+            tree.update(index, leaves[index]).unwrap();
+            assert_eq!(tree.root(), Some(reference_root(&leaves)));
+        }
+
+        leaves[3] = U256::ZERO;
+        tree.remove(3).unwrap();
+        assert_eq!(tree.size(), 11);
+        assert_eq!(tree.root(), Some(reference_root(&leaves)));
+        assert_eq!(tree.leaves(), leaves);
+
+        assert!(matches!(
+            tree.update(11, U256::from(1)),
+            Err(TreeError::LeafOutOfBounds {
+                index: 11,
+                size: 11
+            })
+        ));
+        assert!(matches!(
+            tree.update(2, U256::ZERO),
+            Err(TreeError::ZeroLeaf)
+        ));
+    }
+
+    #[test]
+    fn test_proof() {
+        for size in 1..=13 {
+            let leaves = leaves(size);
+            let tree = LeanIMT::<Poseidon>::from_leaves(leaves.iter().copied()).unwrap();
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(proof.0.len() <= tree.depth());
+                assert!(tree.verify(*leaf, &proof));
+                assert!(!tree.verify(leaf + U256::from(1), &proof));
+            }
+            assert_eq!(tree.proof(size), None);
+        }
+
+        // The last leaf of 5 has no sibling until the root, so its proof is
+        // a single branch with index 1
+        let tree = LeanIMT::<Poseidon>::from_leaves(leaves(5)).unwrap();
+        let proof = tree.proof(4).unwrap();
+        assert_eq!(proof.0.len(), 1);
+        assert_eq!(proof.leaf_index(), 1);
+        assert_eq!(tree.index_of(&U256::from(5)), Some(4));
+    }
+
+    /// Pinned roots of the leaves `1..=size`. The root of two leaves is the
+    /// `poseidon([1, 2])` test vector of circomlibjs, the others are built on
+    /// it by the LeanIMT rules and pin odd and non power of two sizes.
+    #[test_case(1 => uint!(0x01_U256))]
+    #[test_case(2 => uint!(0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a_U256))]
+    #[test_case(3 => uint!(0x1e8c05563aa22ff357008db7a754ea0404695de07b950ce845b872a8bcff2ca9_U256))]
+    #[test_case(4 => uint!(0x075d30e28d48842bd6c1044b68f982d586e2892ae91c77f8f56111d8f55070ed_U256))]
+    #[test_case(5 => uint!(0x1973be9a0ac928df30c68c1698876c310c8246a3f215d33764045ec9da859b08_U256))]
+    #[test_case(6 => uint!(0x01b79b216443cd546f9490e54c7166940a6d51c3392f8819f452a0899cba3116_U256))]
+    #[test_case(7 => uint!(0x141cc8d21606401270cd199efd6fe78b2b643cbea41b94628c9851507177bbd0_U256))]
+    fn known_root(size: usize) -> U256 {
+        let tree = LeanIMT::<Poseidon>::from_leaves(leaves(size)).unwrap();
+        tree.root().unwrap()
+    }
+
+    #[test]
+    fn test_known_proofs() {
+        let poseidon_1_2 =
+            uint!(0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a_U256);
+        let tree = LeanIMT::<Poseidon>::from_leaves(leaves(3)).unwrap();
+        assert_eq!(
+            tree.proof(2),
+            Some(Proof(vec![Branch::Right(poseidon_1_2)]))
+        );
+
+        // Leaf 5 of five, and the parent of leaves 5 and 6 of six, have no
+        // sibling at the second level and are carried up to the root
+        let first_four = known_root(4);
+        let tree = LeanIMT::<Poseidon>::from_leaves(leaves(5)).unwrap();
+        assert_eq!(tree.proof(4), Some(Proof(vec![Branch::Right(first_four)])));
+        let tree = LeanIMT::<Poseidon>::from_leaves(leaves(6)).unwrap();
+        assert_eq!(
+            tree.proof(5),
+            Some(Proof(vec![
+                Branch::Right(U256::from(5)),
+                Branch::Right(first_four)
+            ]))
+        );
+        let proof = tree.proof(1).unwrap();
+        assert_eq!(proof.0[0], Branch::Right(U256::from(1)));
+        assert_eq!(proof.0.len(), 3);
+        assert!(tree.verify(U256::from(2), &proof));
+    }
+
+    #[test]
+    fn test_keccak() {
+        let leaves = (1..=3u8).map(|i| [i; 32]).collect::<Vec<_>>();
+        let tree = LeanIMT::<Keccak256>::from_leaves(leaves.iter().copied()).unwrap();
+        let expected =
+            Keccak256::hash_node(&Keccak256::hash_node(&leaves[0], &leaves[1]), &leaves[2]);
+        assert_eq!(tree.root(), Some(expected));
+    }
+}
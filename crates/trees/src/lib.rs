@@ -1,6 +1,18 @@
+pub mod audit;
 pub mod cascading;
+mod error;
+pub mod fixed;
 pub mod imt;
+pub mod indexed;
 pub mod lazy;
+pub mod lean_imt;
+pub mod multi_proof;
+pub mod parallelism;
 pub mod proof;
+pub mod subroots;
+pub mod sync;
 
-pub use proof::{Branch, Proof};
+pub use error::TreeError;
+pub use multi_proof::MultiProof;
+pub use parallelism::Parallelism;
+pub use proof::{Branch, InclusionProof, Proof, ProofDecodeError};
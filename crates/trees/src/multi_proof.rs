@@ -0,0 +1,209 @@
+use std::fmt::Debug;
+
+use derive_where::derive_where;
+use hasher::Hasher;
+use serde::{Deserialize, Serialize};
+
+/// Merkle proof for several leaves at once.
+///
+/// Siblings shared by the paths of the leaves, or computable from other
+/// proven leaves, are only included once, so proving `k` leaves that are close
+/// together takes far fewer hashes than `k` separate [`Proof`]s.
+///
+/// [`Proof`]: crate::Proof
+#[derive_where(Clone; <H as Hasher>::Hash: Clone)]
+#[derive_where(PartialEq; <H as Hasher>::Hash: PartialEq)]
+#[derive_where(Eq; <H as Hasher>::Hash: Eq)]
+#[derive_where(Debug; <H as Hasher>::Hash: Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "H::Hash: Serialize",
+    deserialize = "H::Hash: Deserialize<'de>"
+))]
+pub struct MultiProof<H>
+where
+    H: Hasher,
+{
+    /// Depth of the tree, excluding the root.
+    pub depth: usize,
+    /// The siblings that can't be computed from the leaves, level by level
+    /// from the bottom up and from left to right within a level.
+    pub siblings: Vec<H::Hash>,
+}
+
+impl<H> MultiProof<H>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Eq,
+{
+    /// Builds a proof for the given leaves, reading the siblings with
+    /// `get_node(height, offset)`.
+    pub(crate) fn build(
+        depth: usize,
+        leaves: &[usize],
+        get_node: impl Fn(usize, usize) -> H::Hash,
+    ) -> Self {
+        let mut offsets = leaves.to_vec();
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        let mut siblings = Vec::new();
+        for height in 0..depth {
+            let mut i = 0;
+            while i < offsets.len() {
+                let offset = offsets[i];
+                if offset % 2 == 0 && offsets.get(i + 1) == Some(&(offset + 1)) {
+                    i += 2;
+                } else {
+                    siblings.push(get_node(height, offset ^ 1));
+                    i += 1;
+                }
+            }
+            offsets = parents(&offsets);
+        }
+
+        Self { depth, siblings }
+    }
+
+    /// Computes the root of the tree from the proven leaves, given as
+    /// `(index, hash)` pairs in any order.
+    ///
+    /// Returns `None` if the leaves are not the ones the proof was built
+    /// for, i.e. if they don't use exactly the siblings of the proof.
+    #[must_use]
+    pub fn root(&self, leaves: &[(usize, H::Hash)]) -> Option<H::Hash> {
+        let mut nodes = leaves.to_vec();
+        nodes.sort_unstable_by_key(|&(offset, _)| offset);
+        nodes.dedup();
+        // Different hashes for the same leaf
+        if nodes.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return None;
+        }
+        let last = nodes.last()?.0;
+        let width = u32::try_from(self.depth)
+            .ok()
+            .and_then(|depth| 1_usize.checked_shl(depth));
+        if width.is_some_and(|width| last >= width) {
+            return None;
+        }
+
+        let mut siblings = self.siblings.iter();
+        for _ in 0..self.depth {
+            let mut parents = Vec::with_capacity(nodes.len());
+            let mut i = 0;
+            while i < nodes.len() {
+                let (offset, hash) = nodes[i];
+                let parent = if offset % 2 == 0 {
+                    match nodes.get(i + 1) {
+                        Some(&(next, right)) if next == offset + 1 => {
+                            i += 1;
+                            H::hash_node(&hash, &right)
+                        }
+                        _ => H::hash_node(&hash, siblings.next()?),
+                    }
+                } else {
+                    H::hash_node(siblings.next()?, &hash)
+                };
+                parents.push((offset >> 1, parent));
+                i += 1;
+            }
+            nodes = parents;
+        }
+
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(nodes[0].1)
+    }
+
+    /// Checks that the leaves, given as `(index, hash)` pairs in any order,
+    /// are in the tree with the given root.
+    #[must_use]
+    pub fn verify(&self, root: H::Hash, leaves: &[(usize, H::Hash)]) -> bool {
+        self.root(leaves) == Some(root)
+    }
+}
+
+/// Returns the sorted offsets of the parents of the sorted `offsets`.
+fn parents(offsets: &[usize]) -> Vec<usize> {
+    let mut parents: Vec<usize> = offsets.iter().map(|offset| offset >> 1).collect();
+    parents.dedup();
+    parents
+}
+
+#[cfg(test)]
+mod tests {
+    use keccak::keccak::Keccak256;
+
+    use super::*;
+    use crate::cascading::CascadingMerkleTree;
+    use crate::proof::Branch;
+
+    fn tree() -> CascadingMerkleTree<Keccak256> {
+        let leaves: Vec<_> = (1..=40_u8).map(|n| [n; 32]).collect();
+        CascadingMerkleTree::new_with_leaves(vec![], 8, &[0; 32], &leaves)
+    }
+
+    #[test]
+    fn test_multi_proof() {
+        let tree = tree();
+        let root = tree.root();
+
+        for indices in [
+            vec![5],
+            vec![0, 1],
+            vec![3, 17, 4, 39, 18],
+            (0..40).collect(),
+        ] {
+            let proof = tree.multi_proof(&indices);
+            let leaves: Vec<_> = indices.iter().map(|&i| (i, tree.get_leaf(i))).collect();
+            assert!(proof.verify(root, &leaves));
+
+            // Duplicates and order don't matter
+            let mut shuffled = leaves.clone();
+            shuffled.reverse();
+            shuffled.push(leaves[0]);
+            assert!(proof.verify(root, &shuffled));
+
+            // Shared siblings are only included once
+            assert!(proof.siblings.len() <= indices.len() * 8);
+        }
+
+        // Adjacent leaves share all siblings but their own
+        assert_eq!(tree.multi_proof(&[0, 1]).siblings.len(), 7);
+        assert_eq!(tree.multi_proof(&[5]).siblings, {
+            let proof = tree.proof(5);
+            proof
+                .0
+                .into_iter()
+                .map(Branch::into_inner)
+                .collect::<Vec<_>>()
+        });
+    }
+
+    #[test]
+    fn test_invalid_multi_proof() {
+        let tree = tree();
+        let root = tree.root();
+        let proof = tree.multi_proof(&[2, 9]);
+        let leaf = |i| (i, tree.get_leaf(i));
+
+        assert!(!proof.verify(root, &[leaf(2), (9, [0; 32])]));
+        assert!(!proof.verify(root, &[leaf(2)]));
+        assert!(!proof.verify(root, &[leaf(2), leaf(10)]));
+        assert!(!proof.verify(root, &[leaf(2), leaf(9), leaf(10)]));
+        assert!(!proof.verify(root, &[leaf(2), leaf(9), (9, [1; 32])]));
+        assert!(!proof.verify(root, &[]));
+        assert!(!proof.verify(root, &[leaf(2), (9 + 256, tree.get_leaf(9))]));
+    }
+
+    #[test]
+    fn test_serde() {
+        let proof = tree().multi_proof(&[1, 2, 3]);
+        let json = serde_json::to_string(&proof).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MultiProof<Keccak256>>(&json).unwrap(),
+            proof
+        );
+    }
+}
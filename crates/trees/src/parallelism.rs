@@ -0,0 +1,67 @@
+//! Choice of the thread pool trees hash on.
+//!
+//! Building and extending trees hashes on rayon's global thread pool by
+//! default. Services that size their own pools can run this work on one of
+//! them instead, with the `_in` variants of the tree methods.
+
+use once_cell::sync::Lazy;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+static SEQUENTIAL: Lazy<ThreadPool> = Lazy::new(|| {
+    ThreadPoolBuilder::new()
+        .num_threads(1)
+        .thread_name(|_| "trees-sequential".to_owned())
+        .build()
+        .expect("failed to spawn the sequential thread pool")
+});
+
+/// Where a tree runs its parallel work.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Parallelism<'a> {
+    /// The current rayon thread pool, i.e. the global one unless called
+    /// from within another pool.
+    #[default]
+    Global,
+    /// A single thread, shared by all trees, so that tree work never uses
+    /// more than one core.
+    Sequential,
+    /// The given thread pool.
+    Pool(&'a ThreadPool),
+}
+
+impl Parallelism<'_> {
+    /// Runs `op`, with its parallel iterators, on the chosen thread pool.
+    pub fn install<R, F>(self, op: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        match self {
+            Self::Global => op(),
+            Self::Sequential => SEQUENTIAL.install(op),
+            Self::Pool(pool) => pool.install(op),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install() {
+        let pool = ThreadPoolBuilder::new().num_threads(3).build().unwrap();
+        assert_eq!(
+            Parallelism::Pool(&pool).install(rayon::current_num_threads),
+            3
+        );
+        assert_eq!(
+            Parallelism::Sequential.install(rayon::current_num_threads),
+            1
+        );
+        assert_eq!(
+            Parallelism::Global.install(rayon::current_num_threads),
+            rayon::current_num_threads()
+        );
+    }
+}
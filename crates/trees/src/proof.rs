@@ -1,20 +1,35 @@
+use std::cmp::Ordering;
 use std::fmt::Debug;
 
 use derive_where::derive_where;
-use hasher::Hasher;
+use hasher::{HashBytes, Hasher};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Length of the encoding of a sibling in [`Proof::to_bytes`].
+const HASH_LEN: usize = 32;
 
 /// Merkle proof path, bottom to top.
+///
+/// Proofs are ordered lexicographically by their encoding, see
+/// [`Proof::to_bytes`]: shorter proofs first, then by the packed path bits,
+/// then by the big-endian bytes of the siblings from the bottom up. For
+/// numeric hashes such as Poseidon's, siblings therefore compare by value,
+/// and the order is the same on every platform.
 #[derive_where(Clone; <H as Hasher>::Hash: Clone)]
 #[derive_where(PartialEq; <H as Hasher>::Hash: PartialEq)]
 #[derive_where(Eq; <H as Hasher>::Hash: Eq)]
+#[derive_where(Hash; <H as Hasher>::Hash: std::hash::Hash)]
 #[derive_where(Debug; <H as Hasher>::Hash: Debug)]
 pub struct Proof<H>(pub Vec<Branch<H::Hash>>)
 where
     H: Hasher;
 
+/// Proof that a leaf is included in a tree.
+pub type InclusionProof<H> = Proof<H>;
+
 /// Element of a Merkle proof
-#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Branch<T> {
     /// Left branch taken, value is the right sibling hash.
     Left(T),
@@ -23,6 +38,20 @@ pub enum Branch<T> {
     Right(T),
 }
 
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ProofDecodeError {
+    #[error("proof encoding is empty")]
+    Empty,
+    #[error("expected {expected} bytes for a proof of depth {depth}, got {actual}")]
+    InvalidLength {
+        depth: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("unused path bits must be zero")]
+    NonZeroPadding,
+}
+
 impl<H> Serialize for Proof<H>
 where
     H: Hasher,
@@ -50,6 +79,202 @@ where
     }
 }
 
+impl<H: Hasher> Proof<H> {
+    /// Compute the leaf index for this proof
+    #[must_use]
+    pub fn leaf_index(&self) -> usize {
+        self.0.iter().rev().fold(0, |index, branch| match branch {
+            Branch::Left(_) => index << 1,
+            Branch::Right(_) => (index << 1) + 1,
+        })
+    }
+
+    /// Returns the path taken from the leaf to the root, bottom to top.
+    ///
+    /// `true` means the node is the right child of its parent, matching the
+    /// `treePathIndices` input of the Semaphore circuit.
+    #[must_use]
+    pub fn path_index(&self) -> Vec<bool> {
+        self.0
+            .iter()
+            .map(|branch| matches!(branch, Branch::Right(_)))
+            .collect()
+    }
+
+    /// Returns the sibling hashes, bottom to top, matching the
+    /// `treeSiblings` input of the Semaphore circuit.
+    #[must_use]
+    pub fn siblings(&self) -> Vec<H::Hash>
+    where
+        H::Hash: Clone,
+    {
+        self.0
+            .iter()
+            .map(|branch| match branch {
+                Branch::Left(sibling) | Branch::Right(sibling) => sibling.clone(),
+            })
+            .collect()
+    }
+
+    /// Compute the Merkle root given a leaf hash
+    #[must_use]
+    pub fn root(&self, hash: H::Hash) -> H::Hash {
+        self.0.iter().fold(hash, |hash, branch| match branch {
+            Branch::Left(sibling) => H::hash_node(&hash, sibling),
+            Branch::Right(sibling) => H::hash_node(sibling, &hash),
+        })
+    }
+
+    /// Returns whether this proves that `leaf` is included in the tree with
+    /// the given root.
+    #[must_use]
+    pub fn verify(&self, root: H::Hash, leaf: H::Hash) -> bool
+    where
+        H::Hash: PartialEq,
+    {
+        self.root(leaf) == root
+    }
+}
+
+impl<H> PartialOrd for Proof<H>
+where
+    H: Hasher,
+    H::Hash: HashBytes + Eq,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<H> Ord for Proof<H>
+where
+    H: Hasher,
+    H::Hash: HashBytes + Eq,
+{
+    /// Compares the encodings of the proofs without allocating them.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.path_bytes().cmp(other.path_bytes()))
+            .then_with(|| {
+                // Siblings have the same size, so comparing them one by one
+                // compares their concatenation
+                self.0
+                    .iter()
+                    .zip(&other.0)
+                    .map(|(a, b)| match (a, b) {
+                        (
+                            Branch::Left(a) | Branch::Right(a),
+                            Branch::Left(b) | Branch::Right(b),
+                        ) => a.to_hash_bytes().cmp(&b.to_hash_bytes()),
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            })
+    }
+}
+
+impl<H> Proof<H>
+where
+    H: Hasher,
+{
+    /// Returns the packed path bits of the encoding.
+    fn path_bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.0.chunks(8).map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (i, branch)| match branch {
+                    Branch::Left(_) => bits,
+                    Branch::Right(_) => bits | (1 << i),
+                })
+        })
+    }
+
+    /// Encodes the proof as `depth || path bits || siblings`.
+    ///
+    /// The depth takes a single byte. The path bits take `ceil(depth / 8)`
+    /// bytes, packed least significant bit first, bottom to top, with a set
+    /// bit for a right branch and the unused high bits of the last byte zero.
+    /// Siblings follow bottom to top, each as 32 big-endian bytes (see
+    /// [`HashBytes`]), so the encoding is the same on every platform.
+    ///
+    /// A proof of depth 3 for leaf 5 (right, left, right) is `03 05` followed
+    /// by the three siblings, 67 bytes in total.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the proof is deeper than 255 levels.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8>
+    where
+        H::Hash: HashBytes,
+    {
+        let depth = u8::try_from(self.0.len()).expect("proof depth must fit in a byte");
+        let mut bytes = Vec::with_capacity(Self::encoded_len(self.0.len()));
+        bytes.push(depth);
+
+        bytes.extend(self.path_bytes());
+
+        for branch in &self.0 {
+            let sibling = match branch {
+                Branch::Left(sibling) | Branch::Right(sibling) => sibling,
+            };
+            bytes.extend_from_slice(&sibling.to_hash_bytes());
+        }
+
+        bytes
+    }
+
+    /// Decodes a proof previously encoded with [`Proof::to_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the length does not match the encoded depth or the
+    /// unused path bits are set.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProofDecodeError>
+    where
+        H::Hash: HashBytes,
+    {
+        let (&depth, rest) = bytes.split_first().ok_or(ProofDecodeError::Empty)?;
+        let depth = depth as usize;
+
+        let expected = Self::encoded_len(depth);
+        if bytes.len() != expected {
+            return Err(ProofDecodeError::InvalidLength {
+                depth,
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let (bits, siblings) = rest.split_at(depth.div_ceil(8));
+        if depth % 8 != 0 && bits[bits.len() - 1] >> (depth % 8) != 0 {
+            return Err(ProofDecodeError::NonZeroPadding);
+        }
+
+        let branches = siblings
+            .chunks_exact(HASH_LEN)
+            .enumerate()
+            .map(|(i, sibling)| {
+                let sibling = H::Hash::from_hash_bytes(sibling.try_into().unwrap());
+                if bits[i / 8] & (1 << (i % 8)) == 0 {
+                    Branch::Left(sibling)
+                } else {
+                    Branch::Right(sibling)
+                }
+            })
+            .collect();
+
+        Ok(Proof(branches))
+    }
+
+    fn encoded_len(depth: usize) -> usize {
+        1 + depth.div_ceil(8) + depth * HASH_LEN
+    }
+}
+
 impl<T> Branch<T> {
     /// Get the inner value
     #[must_use]
@@ -69,3 +294,134 @@ impl<T: Debug> Debug for Branch<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use keccak::keccak::Keccak256;
+    use poseidon::Poseidon;
+    use ruint::aliases::U256;
+
+    use super::*;
+    use crate::lazy::LazyMerkleTree;
+
+    #[test]
+    fn test_path_and_leaf_index() {
+        let tree = LazyMerkleTree::<Keccak256>::new(10, [0; 32]);
+        let proof = tree.proof(0b10_0110_0101);
+
+        assert_eq!(proof.leaf_index(), 0b10_0110_0101);
+        assert_eq!(
+            proof.path_index(),
+            vec![true, false, true, false, false, true, true, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_siblings_and_verify() {
+        let tree = LazyMerkleTree::<Keccak256>::new(3, [0; 32])
+            .derived()
+            .update(5, &[5; 32])
+            .update(4, &[4; 32]);
+        let proof = tree.proof(5);
+
+        let siblings = proof.siblings();
+        assert_eq!(siblings.len(), 3);
+        assert_eq!(siblings[0], [4; 32]);
+        assert!(proof.verify(tree.root(), [5; 32]));
+        assert!(!proof.verify(tree.root(), [4; 32]));
+        assert!(!proof.verify([1; 32], [5; 32]));
+    }
+
+    #[test]
+    fn test_bytes_roundtrip() {
+        let mut tree = LazyMerkleTree::<Keccak256>::new(10, [0; 32]).derived();
+        for i in 0..20_u8 {
+            tree = tree.update(i as usize, &[i; 32]);
+        }
+
+        for leaf in [0, 1, 7, 19, 1023] {
+            let proof = tree.proof(leaf);
+            let bytes = proof.to_bytes();
+            assert_eq!(bytes.len(), 1 + 2 + 10 * 32);
+
+            let decoded = InclusionProof::<Keccak256>::from_bytes(&bytes).unwrap();
+            assert_eq!(decoded, proof);
+            assert_eq!(decoded.leaf_index(), leaf);
+        }
+    }
+
+    #[test]
+    fn test_bytes_layout() {
+        let proof = Proof::<Poseidon>(vec![
+            Branch::Right(U256::from(1)),
+            Branch::Left(U256::from(0x0203)),
+            Branch::Right(U256::MAX),
+        ]);
+
+        let mut expected = vec![3, 0b101];
+        expected.extend(hex!(
+            "0000000000000000000000000000000000000000000000000000000000000001"
+        ));
+        expected.extend(hex!(
+            "0000000000000000000000000000000000000000000000000000000000000203"
+        ));
+        expected.extend([0xff; 32]);
+        assert_eq!(proof.to_bytes(), expected);
+        assert_eq!(Proof::<Poseidon>::from_bytes(&expected).unwrap(), proof);
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        let tree = LazyMerkleTree::<Keccak256>::new(3, [0; 32]);
+        let bytes = tree.proof(5).to_bytes();
+
+        assert_eq!(
+            Proof::<Keccak256>::from_bytes(&[]),
+            Err(ProofDecodeError::Empty)
+        );
+        assert!(matches!(
+            Proof::<Keccak256>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ProofDecodeError::InvalidLength { depth: 3, .. })
+        ));
+
+        let mut padded = bytes.clone();
+        padded[1] |= 0b1000_0000;
+        assert_eq!(
+            Proof::<Keccak256>::from_bytes(&padded),
+            Err(ProofDecodeError::NonZeroPadding)
+        );
+    }
+
+    #[test]
+    fn test_ordering() {
+        let mut tree = LazyMerkleTree::<Keccak256>::new(10, [0; 32]).derived();
+        for i in 0..20_u8 {
+            tree = tree.update(i as usize, &[i; 32]);
+        }
+        let mut proofs: Vec<_> = (0..20).map(|leaf| tree.proof(leaf)).collect();
+        proofs.push(LazyMerkleTree::<Keccak256>::new(3, [0; 32]).proof(5));
+        proofs.push(tree.proof(3));
+
+        let mut by_bytes = proofs.clone();
+        by_bytes.sort_by_key(Proof::to_bytes);
+        proofs.sort();
+        assert_eq!(proofs, by_bytes);
+        assert_eq!(proofs[0].0.len(), 3);
+
+        let unique: std::collections::HashSet<_> = proofs.into_iter().collect();
+        assert_eq!(unique.len(), 21);
+    }
+
+    #[test]
+    fn test_ordering_by_value() {
+        // 0x100 is stored as [0, 1, ..] in memory but is the larger number
+        let small = Proof::<Poseidon>(vec![Branch::Left(U256::from(0xff))]);
+        let large = Proof::<Poseidon>(vec![Branch::Left(U256::from(0x100))]);
+        assert!(small < large);
+        assert_eq!(small.cmp(&large), small.to_bytes().cmp(&large.to_bytes()));
+
+        let right = Proof::<Poseidon>(vec![Branch::Right(U256::ZERO)]);
+        assert!(large < right);
+    }
+}
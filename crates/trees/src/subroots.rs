@@ -0,0 +1,260 @@
+//! Verifies tree roots against the roots of their subtrees.
+//!
+//! Sharded trees are built by computing the roots of their subtrees, the
+//! shards, independently and hashing them together into the top levels of
+//! the tree. Publishing the sub-roots lets anyone check the claimed root of
+//! the whole tree without its leaves.
+
+use hasher::Hasher;
+
+/// Computes the root of a tree of the given depth from the roots of some of
+/// its subtrees, given as `((offset, height), hash)` pairs in any order.
+///
+/// `height` is the height of a sub-root above the leaves and `offset` its
+/// index among the nodes at that height, so leaves can be given as sub-roots
+/// of height 0. Subtrees that are not covered are empty, i.e. all their
+/// leaves are `empty`, as when the last shards of a tree are not filled yet.
+///
+/// Returns `None` if a sub-root is outside the tree or overlaps another one.
+#[must_use]
+pub fn root_from_subroots<H>(
+    subroots: &[((usize, usize), H::Hash)],
+    depth: usize,
+    empty: &H::Hash,
+) -> Option<H::Hash>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Eq,
+{
+    let mut subroots = subroots.to_vec();
+    subroots.sort_unstable_by_key(|&((offset, height), _)| (height, offset));
+    subroots.dedup();
+    let out_of_range = |offset: usize, height: usize| {
+        height > depth
+            || u32::try_from(depth - height)
+                .ok()
+                .and_then(|shift| offset.checked_shr(shift))
+                .is_some_and(|rest| rest != 0)
+    };
+    if subroots
+        .iter()
+        .any(|&((offset, height), _)| out_of_range(offset, height))
+    {
+        return None;
+    }
+
+    let mut remaining = subroots.as_slice();
+    let mut nodes: Vec<(usize, H::Hash)> = Vec::new();
+    let mut empty_node = *empty;
+    for height in 0..=depth {
+        let count = remaining
+            .iter()
+            .take_while(|&&((_, h), _)| h == height)
+            .count();
+        if count > 0 {
+            let (current, rest) = remaining.split_at(count);
+            remaining = rest;
+            nodes.extend(current.iter().map(|&((offset, _), hash)| (offset, hash)));
+            nodes.sort_unstable_by_key(|&(offset, _)| offset);
+            // Sub-roots at the same position, or inside another sub-root
+            if nodes.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+                return None;
+            }
+        }
+        if height == depth {
+            break;
+        }
+
+        let mut parents = Vec::with_capacity(nodes.len());
+        let mut i = 0;
+        while i < nodes.len() {
+            let (offset, hash) = nodes[i];
+            let parent = if offset % 2 == 0 {
+                match nodes.get(i + 1) {
+                    Some(&(next, right)) if next == offset + 1 => {
+                        i += 1;
+                        H::hash_node(&hash, &right)
+                    }
+                    _ => H::hash_node(&hash, &empty_node),
+                }
+            } else {
+                H::hash_node(&empty_node, &hash)
+            };
+            parents.push((offset >> 1, parent));
+            i += 1;
+        }
+        nodes = parents;
+        empty_node = H::hash_node(&empty_node, &empty_node);
+    }
+
+    Some(nodes.first().map_or(empty_node, |&(_, hash)| hash))
+}
+
+/// Checks that `global_root` is the root of a tree of the given depth with
+/// the given sub-roots, and empty everywhere else.
+///
+/// See [`root_from_subroots`] for how the sub-roots are given.
+#[must_use]
+pub fn verify_root_from_subroots<H>(
+    global_root: H::Hash,
+    subroots: &[((usize, usize), H::Hash)],
+    depth: usize,
+    empty: &H::Hash,
+) -> bool
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Eq,
+{
+    root_from_subroots::<H>(subroots, depth, empty) == Some(global_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use keccak::keccak::Keccak256;
+
+    use super::*;
+    use crate::cascading::CascadingMerkleTree;
+
+    const DEPTH: usize = 10;
+    const SHARD_HEIGHT: usize = 4;
+
+    fn leaves(count: u8) -> Vec<[u8; 32]> {
+        (1..=count).map(|n| [n; 32]).collect()
+    }
+
+    fn root(depth: usize, leaves: &[[u8; 32]]) -> [u8; 32] {
+        CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], depth, &[0; 32], leaves).root()
+    }
+
+    /// Builds the tree the way a sharded builder does, one shard at a time.
+    fn shard_roots(leaves: &[[u8; 32]]) -> Vec<((usize, usize), [u8; 32])> {
+        leaves
+            .chunks(1 << SHARD_HEIGHT)
+            .enumerate()
+            .map(|(shard, leaves)| ((shard, SHARD_HEIGHT), root(SHARD_HEIGHT, leaves)))
+            .collect()
+    }
+
+    #[test]
+    fn test_sharded_root() {
+        // The last shard is partially filled and the rest of the tree empty
+        let leaves = leaves(100);
+        let global_root = root(DEPTH, &leaves);
+        let mut subroots = shard_roots(&leaves);
+        assert!(verify_root_from_subroots::<Keccak256>(
+            global_root,
+            &subroots,
+            DEPTH,
+            &[0; 32]
+        ));
+
+        // Order doesn't matter
+        subroots.reverse();
+        assert!(verify_root_from_subroots::<Keccak256>(
+            global_root,
+            &subroots,
+            DEPTH,
+            &[0; 32]
+        ));
+
+        // Missing or altered shards are detected
+        let missing = &subroots[1..];
+        assert!(!verify_root_from_subroots::<Keccak256>(
+            global_root,
+            missing,
+            DEPTH,
+            &[0; 32]
+        ));
+        let mut altered = subroots.clone();
+        altered[2].1[0] ^= 1;
+        assert!(!verify_root_from_subroots::<Keccak256>(
+            global_root,
+            &altered,
+            DEPTH,
+            &[0; 32]
+        ));
+    }
+
+    #[test]
+    fn test_sparse_shards() {
+        // Empty shards may be left out
+        let mut leaves = leaves(64);
+        leaves[16..32].fill([0; 32]);
+        let global_root = root(DEPTH, &leaves);
+        let mut subroots = shard_roots(&leaves);
+        assert!(verify_root_from_subroots::<Keccak256>(
+            global_root,
+            &subroots,
+            DEPTH,
+            &[0; 32]
+        ));
+        subroots.remove(1);
+        assert!(verify_root_from_subroots::<Keccak256>(
+            global_root,
+            &subroots,
+            DEPTH,
+            &[0; 32]
+        ));
+
+        // The empty tree has no sub-roots
+        assert_eq!(
+            root_from_subroots::<Keccak256>(&[], DEPTH, &[0; 32]),
+            Some(root(DEPTH, &[]))
+        );
+    }
+
+    #[test]
+    fn test_mixed_heights() {
+        let leaves = leaves(40);
+        let global_root = root(DEPTH, &leaves);
+
+        // Two shards, then the remaining leaves one by one
+        let mut subroots = shard_roots(&leaves[..32]);
+        subroots.extend((32..40).map(|i| ((i, 0), leaves[i])));
+        assert!(verify_root_from_subroots::<Keccak256>(
+            global_root,
+            &subroots,
+            DEPTH,
+            &[0; 32]
+        ));
+
+        // Duplicates are fine, overlaps are not
+        let mut duplicated = subroots.clone();
+        duplicated.push(subroots[0]);
+        assert_eq!(
+            root_from_subroots::<Keccak256>(&duplicated, DEPTH, &[0; 32]),
+            Some(global_root)
+        );
+        let mut overlapping = subroots.clone();
+        overlapping.push(((3, 0), leaves[3]));
+        assert_eq!(
+            root_from_subroots::<Keccak256>(&overlapping, DEPTH, &[0; 32]),
+            None
+        );
+        let mut conflicting = subroots.clone();
+        conflicting.push(((32, 0), [0; 32]));
+        assert_eq!(
+            root_from_subroots::<Keccak256>(&conflicting, DEPTH, &[0; 32]),
+            None
+        );
+
+        // Sub-roots must be inside the tree
+        for out_of_range in [
+            ((1 << (DEPTH - SHARD_HEIGHT), SHARD_HEIGHT), [1; 32]),
+            ((0, DEPTH + 1), [1; 32]),
+        ] {
+            assert_eq!(
+                root_from_subroots::<Keccak256>(&[out_of_range], DEPTH, &[0; 32]),
+                None
+            );
+        }
+
+        // The root itself is a sub-root
+        assert!(verify_root_from_subroots::<Keccak256>(
+            global_root,
+            &[((0, DEPTH), global_root)],
+            DEPTH,
+            &[0; 32]
+        ));
+    }
+}
@@ -0,0 +1,461 @@
+//! Brings a replica of a [`CascadingMerkleTree`] up to date with a source
+//! tree, transferring only the leaves that differ.
+//!
+//! The replica drives a [`SyncSession`], which asks the source for the hashes
+//! of some nodes at one height at a time, starting at the root. Only the
+//! children of nodes that differ from the replica are requested next, until
+//! the differing leaves are reached. Requests and responses are plain data, so
+//! they can be sent over any transport. The source answers them with
+//! [`respond`] and keeps no state between requests.
+//!
+//! ```
+//! # use keccak::keccak::Keccak256;
+//! # use trees::cascading::CascadingMerkleTree;
+//! # use trees::sync::{respond, SyncSession, DEFAULT_STRIDE};
+//! # let empty = [0; 32];
+//! # let source = CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], 10, &empty, &[[1; 32]; 5]);
+//! # let mut replica = CascadingMerkleTree::<Keccak256>::new(vec![], 10, &empty);
+//! let mut session = SyncSession::new(&replica, DEFAULT_STRIDE);
+//! while let Some(request) = session.next_request() {
+//!     let response = respond(&source, &request)?;
+//!     session.handle_response(&replica, response)?;
+//! }
+//! session.finish()?.apply(&mut replica)?;
+//! assert_eq!(replica.root(), source.root());
+//! # Ok::<(), trees::TreeError>(())
+//! ```
+
+use std::fmt::Debug;
+use std::ops::Range;
+
+use bytemuck::Pod;
+use hasher::Hasher;
+use serde::{Deserialize, Serialize};
+
+use crate::cascading::storage_ops::StorageOps;
+use crate::cascading::CascadingMerkleTree;
+use crate::error::{Result, TreeError};
+
+/// Number of levels [`diff`] descends per request.
+pub const DEFAULT_STRIDE: usize = 4;
+
+/// A request for the hashes of the source nodes at one height.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeRequest {
+    /// Depth of the trees being synced.
+    pub depth: usize,
+    /// Height of the requested nodes above the leaves.
+    pub height: usize,
+    /// Offsets of the requested nodes within their level.
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// The answer of the source to a [`NodeRequest`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeResponse<T> {
+    /// Root of the source tree.
+    pub root: T,
+    /// Number of leaves of the source tree.
+    pub num_leaves: usize,
+    /// Hashes of the requested nodes, in the order they were requested.
+    pub hashes: Vec<T>,
+}
+
+/// The leaves a replica needs to change to match the source tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeDiff<T> {
+    /// Root of the source tree.
+    pub root: T,
+    /// Number of leaves of the source tree.
+    pub num_leaves: usize,
+    /// Leaves that differ from the replica, by increasing index.
+    pub leaves: Vec<(usize, T)>,
+}
+
+impl<T> TreeDiff<T>
+where
+    T: Copy + Eq + Debug,
+{
+    /// Updates the replica the diff was computed for to match the source.
+    ///
+    /// Changed leaves are set one by one, appended leaves are appended at
+    /// once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tree has more leaves than the source, if the
+    /// storage fails to write the leaves, or if the root doesn't match the
+    /// source afterwards, e.g. because the tree changed since the diff was
+    /// computed.
+    pub fn apply<H, S>(&self, tree: &mut CascadingMerkleTree<H, S>) -> Result<()>
+    where
+        H: Hasher<Hash = T>,
+        T: Pod + Send + Sync,
+        S: StorageOps<H>,
+    {
+        let num_leaves = tree.num_leaves();
+        if num_leaves > self.num_leaves {
+            return Err(TreeError::InvalidDiff(format!(
+                "tree has {num_leaves} leaves, more than the {} of the source",
+                self.num_leaves
+            )));
+        }
+
+        let mut appended = vec![tree.empty_value(); self.num_leaves - num_leaves];
+        for &(leaf, value) in &self.leaves {
+            if leaf < num_leaves {
+                tree.set_leaf(leaf, value)?;
+            } else {
+                *appended.get_mut(leaf - num_leaves).ok_or_else(|| {
+                    TreeError::InvalidDiff(format!("leaf {leaf} is past the end of the source"))
+                })? = value;
+            }
+        }
+        tree.extend_from_slice(&appended)?;
+
+        if tree.root() != self.root {
+            return Err(TreeError::InvalidDiff(format!(
+                "tree root {:?} does not match the source root {:?}",
+                tree.root(),
+                self.root
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Answers a [`NodeRequest`] from the source tree.
+///
+/// # Errors
+///
+/// Returns an error if the request is for a tree of another depth or for
+/// nodes outside the tree.
+pub fn respond<H, S>(
+    source: &CascadingMerkleTree<H, S>,
+    request: &NodeRequest,
+) -> Result<NodeResponse<H::Hash>>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+{
+    let depth = source.depth();
+    if request.depth != depth {
+        return Err(TreeError::InvalidRequest(format!(
+            "requested nodes of a tree of depth {}, but the tree has depth {depth}",
+            request.depth
+        )));
+    }
+    if request.height > depth {
+        return Err(TreeError::InvalidRequest(format!(
+            "height {} is above the root",
+            request.height
+        )));
+    }
+
+    let width = 1 << (depth - request.height);
+    let mut hashes = Vec::new();
+    for range in &request.ranges {
+        if range.end > width {
+            return Err(TreeError::InvalidRequest(format!(
+                "offsets {range:?} are outside the level of width {width}"
+            )));
+        }
+        hashes.extend(
+            range
+                .clone()
+                .map(|offset| source.get_node(depth - request.height, offset)),
+        );
+    }
+
+    Ok(NodeResponse {
+        root: source.root(),
+        num_leaves: source.num_leaves(),
+        hashes,
+    })
+}
+
+/// The replica side of a sync, see the [module documentation](self).
+#[derive(Clone, Debug)]
+pub struct SyncSession<T> {
+    depth: usize,
+    stride: usize,
+    replica_leaves: usize,
+    /// The request awaiting a response, or `None` once the diff is complete.
+    pending: Option<NodeRequest>,
+    /// Root and number of leaves of the source, from the first response.
+    source: Option<(T, usize)>,
+    leaves: Vec<(usize, T)>,
+}
+
+impl<T> SyncSession<T>
+where
+    T: Copy + Eq + Debug,
+{
+    /// Starts syncing the replica, descending `stride` levels of the tree
+    /// with every request.
+    ///
+    /// Larger strides take fewer round trips, but transfer the hashes of up
+    /// to `2^stride` nodes for every differing node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `stride` is zero.
+    #[must_use]
+    pub fn new<H, S>(replica: &CascadingMerkleTree<H, S>, stride: usize) -> Self
+    where
+        H: Hasher<Hash = T>,
+        T: Pod + Send + Sync,
+        S: StorageOps<H>,
+    {
+        assert!(stride > 0, "Stride must be positive");
+        let depth = replica.depth();
+        let root = 0..1;
+        Self {
+            depth,
+            stride,
+            replica_leaves: replica.num_leaves(),
+            pending: Some(NodeRequest {
+                depth,
+                height: depth,
+                ranges: vec![root],
+            }),
+            source: None,
+            leaves: Vec::new(),
+        }
+    }
+
+    /// Returns the next request to send to the source, or `None` once the
+    /// diff is complete.
+    #[must_use]
+    pub fn next_request(&self) -> Option<NodeRequest> {
+        self.pending.clone()
+    }
+
+    /// Compares the source nodes of a response to the replica.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no request pending, the response doesn't
+    /// match the request, the source changed since the first response or has
+    /// fewer leaves than the replica, or the replica changed since the
+    /// session started.
+    pub fn handle_response<H, S>(
+        &mut self,
+        replica: &CascadingMerkleTree<H, S>,
+        response: NodeResponse<T>,
+    ) -> Result<()>
+    where
+        H: Hasher<Hash = T>,
+        T: Pod + Send + Sync,
+        S: StorageOps<H>,
+    {
+        let Some(request) = self.pending.take() else {
+            return Err(TreeError::NoPendingRequest);
+        };
+        if replica.num_leaves() != self.replica_leaves {
+            return Err(TreeError::ReplicaChanged);
+        }
+        match self.source {
+            None => {
+                if response.num_leaves < self.replica_leaves {
+                    return Err(TreeError::InvalidResponse(format!(
+                        "source has {} leaves, fewer than the {} of the replica",
+                        response.num_leaves, self.replica_leaves
+                    )));
+                }
+                self.source = Some((response.root, response.num_leaves));
+            }
+            Some(source) => {
+                if source != (response.root, response.num_leaves) {
+                    return Err(TreeError::SourceChanged);
+                }
+            }
+        }
+
+        let requested: usize = request.ranges.iter().map(ExactSizeIterator::len).sum();
+        if requested != response.hashes.len() {
+            return Err(TreeError::InvalidResponse(format!(
+                "expected {requested} hashes, got {}",
+                response.hashes.len()
+            )));
+        }
+
+        let node_depth = self.depth - request.height;
+        let differing = request
+            .ranges
+            .iter()
+            .cloned()
+            .flatten()
+            .zip(response.hashes)
+            .filter(|&(offset, hash)| replica.get_node(node_depth, offset) != hash);
+        if request.height == 0 {
+            self.leaves.extend(differing);
+            return Ok(());
+        }
+
+        let height = request.height.saturating_sub(self.stride);
+        let shift = request.height - height;
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (offset, _) in differing {
+            let children = (offset << shift)..((offset + 1) << shift);
+            match ranges.last_mut() {
+                Some(last) if last.end == children.start => last.end = children.end,
+                _ => ranges.push(children),
+            }
+        }
+        if !ranges.is_empty() {
+            self.pending = Some(NodeRequest {
+                depth: self.depth,
+                height,
+                ranges,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the diff once all requests have been answered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a request is still pending.
+    pub fn finish(self) -> Result<TreeDiff<T>> {
+        if self.pending.is_some() {
+            return Err(TreeError::SyncIncomplete);
+        }
+        let (root, num_leaves) = self.source.expect("a response was handled");
+        Ok(TreeDiff {
+            root,
+            num_leaves,
+            leaves: self.leaves,
+        })
+    }
+}
+
+/// Computes the diff between two trees in the same process, see
+/// [`SyncSession`].
+///
+/// # Errors
+///
+/// Returns an error if the trees have different depths or the replica has
+/// more leaves than the source.
+pub fn diff<H, S, S2>(
+    source: &CascadingMerkleTree<H, S>,
+    replica: &CascadingMerkleTree<H, S2>,
+) -> Result<TreeDiff<H::Hash>>
+where
+    H: Hasher,
+    <H as Hasher>::Hash: Copy + Pod + Eq + Send + Sync,
+    <H as Hasher>::Hash: Debug,
+    S: StorageOps<H>,
+    S2: StorageOps<H>,
+{
+    let mut session = SyncSession::new(replica, DEFAULT_STRIDE);
+    while let Some(request) = session.next_request() {
+        session.handle_response(replica, respond(source, &request)?)?;
+    }
+    session.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use keccak::keccak::Keccak256;
+
+    use super::*;
+
+    const EMPTY: [u8; 32] = [0; 32];
+
+    fn tree(leaves: &[[u8; 32]]) -> CascadingMerkleTree<Keccak256> {
+        CascadingMerkleTree::new_with_leaves(vec![], 12, &EMPTY, leaves)
+    }
+
+    #[test]
+    fn test_diff_and_apply() {
+        let leaves: Vec<[u8; 32]> = (0..300_u16)
+            .map(|i| {
+                let mut leaf = [0; 32];
+                leaf[..2].copy_from_slice(&(i + 1).to_be_bytes());
+                leaf
+            })
+            .collect();
+        let source = tree(&leaves);
+
+        let mut replica = tree(&leaves[..200]);
+        replica.set_leaf(7, [7; 32]).unwrap();
+        replica.set_leaf(150, EMPTY).unwrap();
+
+        let diff = diff(&source, &replica).unwrap();
+        assert_eq!(diff.num_leaves, 300);
+        let changed: Vec<usize> = diff.leaves.iter().map(|&(leaf, _)| leaf).collect();
+        let expected: Vec<usize> = [7, 150].into_iter().chain(200..300).collect();
+        assert_eq!(changed, expected);
+        assert!(diff.leaves.iter().all(|&(leaf, hash)| hash == leaves[leaf]));
+
+        diff.apply(&mut replica).unwrap();
+        assert_eq!(replica.root(), source.root());
+        assert_eq!(replica.num_leaves(), 300);
+        replica.validate().unwrap();
+
+        let diff = super::diff(&source, &replica).unwrap();
+        assert!(diff.leaves.is_empty());
+    }
+
+    #[test]
+    fn test_requests() {
+        let source = tree(&[[1; 32], [2; 32], [3; 32]]);
+        let replica = tree(&[[1; 32], [2; 32]]);
+
+        let mut session = SyncSession::new(&replica, 5);
+        let mut heights = Vec::new();
+        while let Some(request) = session.next_request() {
+            heights.push(request.height);
+            let response = respond(&source, &request).unwrap();
+            session.handle_response(&replica, response).unwrap();
+        }
+        // Every request only covers the differing nodes of the level above
+        assert_eq!(heights, [12, 7, 2, 0]);
+        assert_eq!(session.finish().unwrap().leaves, [(2, [3; 32])]);
+    }
+
+    #[test]
+    fn test_sync_errors() {
+        let source = tree(&[[1; 32], [2; 32]]);
+        let mut replica = tree(&[[1; 32]]);
+
+        // The replica is ahead of the source
+        assert!(matches!(
+            diff(&replica, &source),
+            Err(TreeError::InvalidResponse(_))
+        ));
+
+        let request = NodeRequest {
+            depth: 12,
+            height: 11,
+            ranges: vec![0..2, 2..3],
+        };
+        assert!(matches!(
+            respond(&source, &request),
+            Err(TreeError::InvalidRequest(_))
+        ));
+
+        // The source changes between responses
+        let mut session = SyncSession::new(&replica, 4);
+        let response = respond(&source, &session.next_request().unwrap()).unwrap();
+        session.handle_response(&replica, response).unwrap();
+        let other = tree(&[[1; 32], [3; 32]]);
+        let response = respond(&other, &session.next_request().unwrap()).unwrap();
+        assert!(matches!(
+            session.handle_response(&replica, response),
+            Err(TreeError::SourceChanged)
+        ));
+
+        // The replica changes after the diff was computed
+        let diff = diff(&source, &replica).unwrap();
+        replica.set_leaf(0, [5; 32]).unwrap();
+        assert!(matches!(
+            diff.apply(&mut replica),
+            Err(TreeError::InvalidDiff(_))
+        ));
+    }
+}
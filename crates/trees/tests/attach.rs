@@ -0,0 +1,125 @@
+//! A reader process attached to the file of a tree written by another.
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::Write;
+use std::process::Command;
+
+use keccak::keccak::Keccak256;
+use storage::MmapVec;
+use trees::cascading::CascadingMerkleTree;
+use trees::TreeError;
+
+const DEPTH: usize = 16;
+const EMPTY: [u8; 32] = [0; 32];
+const NUM_LEAVES: usize = 3000;
+
+/// Set to the tree file for the writer process.
+const WRITER_ENV: &str = "TREES_ATTACH_WRITER";
+
+fn leaf(i: usize) -> [u8; 32] {
+    let mut leaf = [0; 32];
+    leaf[24..].copy_from_slice(&(i as u64 + 1).to_be_bytes());
+    leaf
+}
+
+/// Applies the writes of the writer process to a tree, calling `written`
+/// after every write.
+fn write(
+    tree: &mut CascadingMerkleTree<Keccak256, impl storage::GenericStorage<[u8; 32]>>,
+    mut written: impl FnMut(&[u8; 32]),
+) {
+    for i in 1..NUM_LEAVES {
+        tree.push(leaf(i)).unwrap();
+        written(&tree.root());
+        if i % 7 == 0 {
+            tree.set_leaf(i / 2, leaf(NUM_LEAVES + i)).unwrap();
+            written(&tree.root());
+        }
+    }
+}
+
+fn roots_path(tree_path: &OsString) -> OsString {
+    let mut path = tree_path.clone();
+    path.push(".roots");
+    path
+}
+
+/// The writer process spawned by [`test_attach_two_processes`], records
+/// every root it publishes.
+#[test]
+fn attached_writer() {
+    let Some(path) = std::env::var_os(WRITER_ENV) else {
+        return;
+    };
+    let mut roots = std::fs::File::create(roots_path(&path)).unwrap();
+    let storage = MmapVec::restore_from_path(&path).unwrap();
+    let mut tree = CascadingMerkleTree::<Keccak256, _>::restore(storage, DEPTH, &EMPTY).unwrap();
+    write(&mut tree, |root| roots.write_all(root).unwrap());
+}
+
+#[test]
+fn test_attach_two_processes() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let path = file.path().as_os_str().to_owned();
+    let storage = MmapVec::create_from_path(&path).unwrap();
+    let tree =
+        CascadingMerkleTree::<Keccak256, _>::new_with_leaves(storage, DEPTH, &EMPTY, &[leaf(0)]);
+    let first_root = tree.root();
+    drop(tree);
+
+    // Safety: the writer only grows the file
+    let mut attached =
+        unsafe { CascadingMerkleTree::<Keccak256, MmapVec<_>>::attach(&path, DEPTH, &EMPTY) }
+            .unwrap();
+    let mut writer = Command::new(std::env::current_exe().unwrap())
+        .args(["attached_writer", "--exact", "--nocapture"])
+        .env(WRITER_ENV, &path)
+        .spawn()
+        .unwrap();
+
+    // Serve proofs of the first, middle and last leaf while the writer runs
+    let mut observed = Vec::new();
+    loop {
+        let done = writer.try_wait().unwrap().is_some();
+        let num_leaves = attached.num_leaves().unwrap();
+        for leaf in [0, num_leaves / 2, num_leaves - 1] {
+            match attached.proof(leaf) {
+                Ok(Some(proof)) => {
+                    assert_eq!(proof.proof.leaf_index(), leaf);
+                    assert_eq!(proof.proof.root(proof.leaf), proof.root);
+                    observed.push(proof.root);
+                }
+                Ok(None) => unreachable!("leaf {leaf} of {num_leaves} not found"),
+                Err(TreeError::Contended) => {}
+                Err(e) => panic!("failed to read proof: {e}"),
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    assert!(writer.wait().unwrap().success());
+
+    // Every root served was a root the writer published
+    let roots = std::fs::read(roots_path(&path)).unwrap();
+    let mut published: HashSet<[u8; 32]> = roots
+        .chunks_exact(32)
+        .map(|root| root.try_into().unwrap())
+        .collect();
+    published.insert(first_root);
+    assert!(!observed.is_empty());
+    assert!(observed.iter().all(|root| published.contains(root)));
+
+    let mut expected =
+        CascadingMerkleTree::<Keccak256>::new_with_leaves(vec![], DEPTH, &EMPTY, &[leaf(0)]);
+    write(&mut expected, |_| {});
+    assert_eq!(attached.num_leaves().unwrap(), NUM_LEAVES);
+    assert_eq!(attached.root().unwrap(), expected.root());
+    for leaf in [0, 7, 1500, NUM_LEAVES - 1] {
+        let proof = attached.proof(leaf).unwrap().unwrap();
+        assert_eq!(proof.leaf, expected.get_leaf(leaf));
+        assert_eq!(proof.proof, expected.proof(leaf));
+    }
+    std::fs::remove_file(roots_path(&path)).unwrap();
+}
@@ -0,0 +1,136 @@
+//! Differential tests of the tree implementations.
+//!
+//! Random sequences of operations are applied to a `CascadingMerkleTree`, a
+//! canonical and a derived `LazyMerkleTree` and an `imt::MerkleTree`, and
+//! after each operation their roots must agree. At the end, all proofs must
+//! agree too. Failing sequences are shrunk to a minimal one by proptest.
+
+use keccak::keccak::Keccak256;
+use proptest::prelude::*;
+use trees::cascading::CascadingMerkleTree;
+use trees::imt::MerkleTree;
+use trees::lazy::{Canonical, Derived, LazyMerkleTree};
+
+const DEPTH: usize = 6;
+const DENSE_PREFIX: usize = 3;
+const CAPACITY: usize = 1 << DEPTH;
+const EMPTY: [u8; 32] = [0; 32];
+
+type Leaf = [u8; 32];
+
+#[derive(Clone, Debug)]
+enum Op {
+    Push(Leaf),
+    /// Sets the leaf at the index modulo the number of leaves.
+    Set(usize, Leaf),
+    Extend(Vec<Leaf>),
+    /// Clears the last leaf. Cascading trees can't shrink, so this sets it
+    /// to the empty value.
+    Pop,
+}
+
+fn leaf() -> impl Strategy<Value = Leaf> {
+    // Small values, so equal leaves and empty leaves are common
+    (0_u8..8).prop_map(|byte| [byte; 32])
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => leaf().prop_map(Op::Push),
+        2 => (any::<usize>(), leaf()).prop_map(|(index, leaf)| Op::Set(index, leaf)),
+        1 => prop::collection::vec(leaf(), 0..12).prop_map(Op::Extend),
+        1 => Just(Op::Pop),
+    ]
+}
+
+struct Trees {
+    /// The leaves pushed so far.
+    leaves: Vec<Leaf>,
+    cascading: CascadingMerkleTree<Keccak256>,
+    canonical: LazyMerkleTree<Keccak256, Canonical>,
+    derived: LazyMerkleTree<Keccak256, Derived>,
+    imt: MerkleTree<Keccak256>,
+}
+
+impl Trees {
+    fn new() -> Self {
+        let canonical =
+            LazyMerkleTree::<Keccak256>::new_with_dense_prefix(DEPTH, DENSE_PREFIX, &EMPTY);
+        Self {
+            leaves: Vec::new(),
+            cascading: CascadingMerkleTree::new(vec![], DEPTH, &EMPTY),
+            derived: canonical.derived(),
+            canonical,
+            imt: MerkleTree::new(DEPTH, EMPTY),
+        }
+    }
+
+    fn set(&mut self, index: usize, leaf: Leaf) {
+        self.leaves[index] = leaf;
+        self.cascading.set_leaf(index, leaf).unwrap();
+        self.update(index, leaf);
+    }
+
+    fn push(&mut self, leaf: Leaf) {
+        self.leaves.push(leaf);
+        self.cascading.push(leaf).unwrap();
+        self.update(self.leaves.len() - 1, leaf);
+    }
+
+    fn update(&mut self, index: usize, leaf: Leaf) {
+        let canonical = std::mem::replace(
+            &mut self.canonical,
+            LazyMerkleTree::<Keccak256>::new(DEPTH, EMPTY),
+        );
+        self.canonical = canonical.update_with_mutation(index, &leaf);
+        self.derived = self.derived.update(index, &leaf);
+        self.imt.set(index, leaf);
+    }
+
+    fn apply(&mut self, op: &Op) {
+        match op {
+            Op::Push(leaf) if self.leaves.len() < CAPACITY => self.push(*leaf),
+            Op::Set(index, leaf) if !self.leaves.is_empty() => {
+                self.set(index % self.leaves.len(), *leaf);
+            }
+            Op::Extend(leaves) => {
+                let room = CAPACITY - self.leaves.len();
+                let leaves = &leaves[..leaves.len().min(room)];
+                let start = self.leaves.len();
+                self.cascading.extend_from_slice(leaves).unwrap();
+                self.leaves.extend_from_slice(leaves);
+                for (index, leaf) in leaves.iter().enumerate() {
+                    self.update(start + index, *leaf);
+                }
+            }
+            Op::Pop if !self.leaves.is_empty() => self.set(self.leaves.len() - 1, EMPTY),
+            _ => {}
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn trees_agree(ops in prop::collection::vec(op(), 0..48)) {
+        let mut trees = Trees::new();
+        for (step, op) in ops.iter().enumerate() {
+            trees.apply(op);
+            let root = trees.cascading.root();
+            prop_assert_eq!(trees.canonical.root(), root, "canonical lazy tree, step {} {:?}", step, op);
+            prop_assert_eq!(trees.derived.root(), root, "derived lazy tree, step {} {:?}", step, op);
+            prop_assert_eq!(trees.imt.root(), root, "imt, step {} {:?}", step, op);
+        }
+
+        prop_assert_eq!(trees.cascading.num_leaves(), trees.leaves.len());
+        for (index, leaf) in trees.leaves.iter().enumerate() {
+            let proof = trees.cascading.proof(index);
+            prop_assert_eq!(&trees.canonical.proof(index), &proof, "leaf {}", index);
+            prop_assert_eq!(&trees.derived.proof(index), &proof, "leaf {}", index);
+            prop_assert_eq!(&trees.imt.proof(index).unwrap(), &proof, "leaf {}", index);
+            prop_assert!(trees.cascading.verify(*leaf, &proof));
+            prop_assert_eq!(proof.root(*leaf), trees.cascading.root());
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Golden roots of trees built from fixed leaves.
+//!
+//! Every tree implementation must reproduce the roots recorded in
+//! `snapshots/roots.json`, so a change to the Poseidon constants, the tree
+//! indexing or the storage layout fails here instead of on-chain. After an
+//! intended change, regenerate the file with
+//!
+//! ```sh
+//! UPDATE_SNAPSHOTS=1 cargo test -p trees --test snapshots
+//! ```
+//!
+//! and review the diff.
+
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use bytemuck::Pod;
+use hasher::Hasher;
+use keccak::keccak::Keccak256;
+use poseidon::Poseidon;
+use ruint::aliases::U256;
+use serde::{Deserialize, Serialize};
+use storage::MmapVec;
+use trees::cascading::CascadingMerkleTree;
+use trees::imt::MerkleTree;
+use trees::lazy::LazyMerkleTree;
+
+const DEPTHS: [usize; 4] = [1, 5, 10, 20];
+const NUM_LEAVES: [usize; 4] = [0, 1, 7, 100];
+
+/// Depth of the dense prefix of the lazy trees, if the tree is deeper.
+const DENSE_PREFIX: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Snapshot {
+    hasher: String,
+    depth: usize,
+    empty: String,
+    num_leaves: usize,
+    root: String,
+}
+
+/// A hasher with fixed leaves and empty values to snapshot.
+trait SnapshotHasher: Hasher {
+    const NAME: &'static str;
+
+    fn empty_values() -> [Self::Hash; 2];
+
+    fn leaf(index: usize) -> Self::Hash;
+
+    fn to_bytes(hash: &Self::Hash) -> [u8; 32];
+}
+
+impl SnapshotHasher for Poseidon {
+    const NAME: &'static str = "poseidon";
+
+    fn empty_values() -> [U256; 2] {
+        [U256::ZERO, U256::from(0x1234_5678_u64)]
+    }
+
+    fn leaf(index: usize) -> U256 {
+        U256::from(index as u64 + 1) * U256::from(0x9e37_79b9_7f4a_7c15_u64)
+    }
+
+    fn to_bytes(hash: &U256) -> [u8; 32] {
+        hash.to_be_bytes()
+    }
+}
+
+impl SnapshotHasher for Keccak256 {
+    const NAME: &'static str = "keccak";
+
+    fn empty_values() -> [[u8; 32]; 2] {
+        [[0; 32], [0xff; 32]]
+    }
+
+    fn leaf(index: usize) -> [u8; 32] {
+        let mut leaf = [0; 32];
+        leaf[24..].copy_from_slice(&(index as u64 + 1).to_be_bytes());
+        leaf
+    }
+
+    fn to_bytes(hash: &[u8; 32]) -> [u8; 32] {
+        *hash
+    }
+}
+
+fn snapshot_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/roots.json")
+}
+
+/// Builds the trees of every case with every implementation, checks they
+/// agree and returns their roots.
+fn snapshots<H>() -> Vec<Snapshot>
+where
+    H: SnapshotHasher,
+    H::Hash: Copy + Pod + Eq + Send + Sync + Debug,
+{
+    let mut snapshots = Vec::new();
+    for depth in DEPTHS {
+        for empty in H::empty_values() {
+            for num_leaves in NUM_LEAVES.into_iter().filter(|n| *n <= 1 << depth) {
+                let leaves: Vec<_> = (0..num_leaves).map(H::leaf).collect();
+                let root = roots::<H>(depth, &empty, &leaves);
+                snapshots.push(Snapshot {
+                    hasher: H::NAME.to_string(),
+                    depth,
+                    empty: hex::encode(H::to_bytes(&empty)),
+                    num_leaves,
+                    root: hex::encode(H::to_bytes(&root)),
+                });
+            }
+        }
+    }
+    snapshots
+}
+
+fn roots<H>(depth: usize, empty: &H::Hash, leaves: &[H::Hash]) -> H::Hash
+where
+    H: SnapshotHasher,
+    H::Hash: Copy + Pod + Eq + Send + Sync + Debug,
+{
+    let cascading = CascadingMerkleTree::<H>::new_with_leaves(vec![], depth, empty, leaves);
+    let root = cascading.root();
+
+    // The storage layout is part of the snapshot: a restored file must give
+    // the same root
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let storage = MmapVec::create_from_path(file.path()).unwrap();
+    let mmap = CascadingMerkleTree::<H, _>::new_with_leaves(storage, depth, empty, leaves);
+    assert_eq!(mmap.root(), root, "mmap cascading tree of depth {depth}");
+    drop(mmap);
+    let storage = MmapVec::restore_from_path(file.path()).unwrap();
+    let restored = CascadingMerkleTree::<H, _>::restore(storage, depth, empty).unwrap();
+    assert_eq!(restored.root(), root, "restored tree of depth {depth}");
+
+    let lazy = LazyMerkleTree::<H>::new_with_dense_prefix_with_initial_values(
+        depth,
+        DENSE_PREFIX.min(depth),
+        empty,
+        leaves,
+    );
+    assert_eq!(lazy.root(), root, "lazy tree of depth {depth}");
+    let mut sparse = LazyMerkleTree::<H>::new(depth, *empty).derived();
+    for (index, leaf) in leaves.iter().enumerate() {
+        sparse = sparse.update(index, leaf);
+    }
+    assert_eq!(sparse.root(), root, "sparse lazy tree of depth {depth}");
+
+    let mut imt = MerkleTree::<H>::new(depth, *empty);
+    imt.set_range(0, leaves.iter().copied());
+    assert_eq!(imt.root(), root, "imt of depth {depth}");
+
+    root
+}
+
+#[test]
+fn test_root_snapshots() {
+    let mut actual = snapshots::<Poseidon>();
+    actual.extend(snapshots::<Keccak256>());
+
+    let path = snapshot_path();
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        let json = serde_json::to_string_pretty(&actual).unwrap();
+        std::fs::write(&path, json + "\n").unwrap();
+        return;
+    }
+
+    let json = std::fs::read_to_string(&path).unwrap();
+    let expected: Vec<Snapshot> = serde_json::from_str(&json).unwrap();
+    let changed: Vec<_> = expected
+        .iter()
+        .zip(&actual)
+        .filter(|(expected, actual)| expected != actual)
+        .collect();
+    assert!(
+        changed.is_empty() && expected.len() == actual.len(),
+        "{} of {} roots changed, first {:#?}; run with UPDATE_SNAPSHOTS=1 if this is intended",
+        changed.len() + expected.len().abs_diff(actual.len()),
+        expected.len(),
+        changed.first(),
+    );
+}
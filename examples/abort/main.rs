@@ -5,11 +5,12 @@ use poseidon::Poseidon;
 use rand::Rng;
 use ruint::aliases::U256;
 use std::{env, process::Stdio};
-use storage::MmapVec;
+use storage::{JournaledStorage, MmapVec};
 use trees::cascading::CascadingMerkleTree;
 use trees::lazy::LazyMerkleTree;
 
 static FILE_PATH: &str = "target/debug/examples/abort.mmap";
+static JOURNAL_PATH: &str = "target/debug/examples/abort.journal";
 static BIN_PATH: &str = "target/debug/examples/abort";
 static ITERATIONS: usize = 20;
 static INITIAL_LEAVES: usize = 10;
@@ -85,13 +86,13 @@ fn run_test(prefix: &str) -> Result<u32> {
 }
 
 fn cascade_init() -> Result<()> {
-    let mmap_vec: MmapVec<<Poseidon as Hasher>::Hash> =
-        unsafe { MmapVec::create_from_path(FILE_PATH)? };
+    let mmap_vec: MmapVec<<Poseidon as Hasher>::Hash> = MmapVec::create_from_path(FILE_PATH)?;
+    let storage = JournaledStorage::create_from_path(mmap_vec, JOURNAL_PATH)?;
 
     let leaves = vec![Default::default(); INITIAL_LEAVES];
 
     let mut tree = CascadingMerkleTree::<Poseidon, _>::new_with_leaves(
-        mmap_vec,
+        storage,
         30,
         &Default::default(),
         &leaves,
@@ -116,8 +117,9 @@ fn cascade_restore() -> Result<()> {
         .write(true)
         .open(FILE_PATH)?;
 
-    let mmap_vec: MmapVec<<Poseidon as Hasher>::Hash> = unsafe { MmapVec::restore(file)? };
-    let tree = CascadingMerkleTree::<Poseidon, _>::restore(mmap_vec, 30, &Default::default())?;
+    let mmap_vec: MmapVec<<Poseidon as Hasher>::Hash> = MmapVec::restore(file)?;
+    let storage = JournaledStorage::restore_from_path(mmap_vec, JOURNAL_PATH)?;
+    let tree = CascadingMerkleTree::<Poseidon, _>::restore(storage, 30, &Default::default())?;
     println!("tree length: {}", tree.num_leaves());
     tree.validate()?;
 
@@ -0,0 +1,71 @@
+//! Proves membership in a tree kept by a remote tree service.
+//!
+//! The service must answer `POST <url>/inclusionProof` with a body of
+//! `{"identityCommitment": "0x.."}` like the signup sequencer, see
+//! `semaphore::tree_service` for the accepted responses. The depth of its
+//! tree must be one of the enabled depth features.
+//!
+//! `cargo run --example remote_tree --features depth_30 -- <url> <secret>`
+
+use color_eyre::eyre::{ensure, eyre};
+use color_eyre::Result;
+use reqwest::header::CONTENT_TYPE;
+use semaphore::identity::Identity;
+use semaphore::protocol::{generate_nullifier_hash, generate_proof, verify_proof};
+use semaphore::tree_service::InclusionProofResponse;
+use semaphore::{hash_to_field_bytes, Field};
+use serde_json::json;
+
+fn fetch_inclusion_proof(url: &str, commitment: Field) -> Result<InclusionProofResponse> {
+    let body = json!({ "identityCommitment": commitment });
+    let response = reqwest::blocking::Client::new()
+        .post(format!("{url}/inclusionProof"))
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()?
+        .error_for_status()?;
+    Ok(serde_json::from_str(&response.text()?)?)
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(url), Some(secret)) = (args.next(), args.next()) else {
+        return Err(eyre!("usage: remote_tree <url> <secret>"));
+    };
+
+    let identity = Identity::from_secret(&mut secret.into_bytes(), None);
+    let commitment = identity.commitment();
+
+    // Checks that the proof is for our commitment before using it
+    let (root, merkle_proof) =
+        fetch_inclusion_proof(&url, commitment)?.into_inclusion_proof(commitment)?;
+    let depth = merkle_proof.0.len();
+    println!(
+        "Commitment {commitment:#x} is leaf {} of root {root:#x}",
+        merkle_proof.leaf_index()
+    );
+
+    let signal_hash = hash_to_field_bytes(b"signal");
+    let external_nullifier_hash = hash_to_field_bytes(b"remote_tree example");
+    let nullifier_hash = generate_nullifier_hash(&identity, external_nullifier_hash);
+    let proof = generate_proof(
+        &identity,
+        &merkle_proof,
+        external_nullifier_hash,
+        signal_hash,
+    )?;
+
+    ensure!(
+        verify_proof(
+            root,
+            nullifier_hash,
+            signal_hash,
+            external_nullifier_hash,
+            &proof,
+            depth,
+        )?,
+        "proof does not verify"
+    );
+    println!("Verified proof with nullifier hash {nullifier_hash:#x}");
+    Ok(())
+}
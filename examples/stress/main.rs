@@ -0,0 +1,588 @@
+use std::ops::{Deref, DerefMut, Range};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, fmt};
+
+use color_eyre::eyre::{bail, ensure};
+use color_eyre::Result;
+use hasher::Hasher;
+use poseidon::Poseidon;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use ruint::aliases::U256;
+use storage::{GenericStorage, GrowthPolicy, Journal, JournaledStorage, MmapVec};
+use trees::cascading::CascadingMerkleTree;
+use trees::TreeError;
+
+type Hash = <Poseidon as Hasher>::Hash;
+type MmapTree = CascadingMerkleTree<Poseidon, FaultyStorage<JournaledStorage<MmapVec<Hash>>>>;
+type ReferenceTree = CascadingMerkleTree<Poseidon>;
+
+/// A soak test for the mmap backed cascading tree.
+///
+/// Runs a randomized mix of pushes, updates, proofs, restores and injected
+/// crashes against a tree stored on disk behind a [`JournaledStorage`],
+/// while mirroring every operation on an in-memory tree. The two trees are
+/// compared after every restore and fully validated periodically. A report
+/// is printed at the end and the process exits with an error if any
+/// divergence was detected.
+///
+/// The journal must make every crash recoverable by replaying the
+/// interrupted write, so a restore that fails and needs the tree to be
+/// rebuilt from its leaves is counted as a failure.
+///
+/// Crashes are simulated by panicking from inside the storage in the middle
+/// of a write, so this must be built with unwinding enabled:
+/// `cargo run --profile stress --example stress -- --duration 3600`
+///
+/// Options:
+/// - `--duration <secs>`: how long to run for (default 60)
+/// - `--seed <u64>`: seed for the workload (default random)
+/// - `--depth <n>`: depth of the tree (default 20)
+/// - `--validate-every <ops>`: full validation interval (default 10000)
+/// - `--path <file>`: location of the mmap file (default
+///   `target/stress.mmap`), the journal is stored next to it with a
+///   `.journal` suffix
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let config = Config::from_args()?;
+    println!("Running stress test with {config:?}");
+
+    // Injected faults are expected, don't spam stderr with them.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if info.payload().downcast_ref::<InjectedFault>().is_none() {
+            default_hook(info);
+        }
+    }));
+
+    let report = Soak::new(&config)?.run()?;
+    println!("\n{report}");
+
+    ensure!(report.is_clean(), "Stress test failed");
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Config {
+    duration: Duration,
+    seed: u64,
+    depth: usize,
+    validate_every: u64,
+    path: PathBuf,
+}
+
+impl Config {
+    fn from_args() -> Result<Self> {
+        let mut config = Self {
+            duration: Duration::from_secs(60),
+            seed: rand::random(),
+            depth: 20,
+            validate_every: 10_000,
+            path: PathBuf::from("target/stress.mmap"),
+        };
+
+        let mut args = env::args().skip(1);
+        while let Some(flag) = args.next() {
+            let Some(value) = args.next() else {
+                bail!("Missing value for {flag}");
+            };
+            match flag.as_str() {
+                "--duration" => config.duration = Duration::from_secs(value.parse()?),
+                "--seed" => config.seed = value.parse()?,
+                "--depth" => config.depth = value.parse()?,
+                "--validate-every" => config.validate_every = value.parse()?,
+                "--path" => config.path = PathBuf::from(value),
+                _ => bail!("Unknown argument {flag}"),
+            }
+        }
+
+        ensure!(config.depth > 0, "Depth must be greater than 0");
+        ensure!(
+            config.validate_every > 0,
+            "Validation interval must be positive"
+        );
+        Ok(config)
+    }
+}
+
+/// Panic payload used to simulate a crash.
+struct InjectedFault;
+
+/// Storage wrapper that panics after a configurable number of mutable
+/// accesses.
+///
+/// Every write to the tree goes through `DerefMut`, so arming the fault with
+/// a small countdown interrupts an update half way through, leaving the
+/// underlying storage in the state it would be after a crash.
+struct FaultyStorage<S> {
+    inner: S,
+    countdown: Arc<AtomicUsize>,
+}
+
+impl<S> FaultyStorage<S> {
+    fn new(inner: S, countdown: Arc<AtomicUsize>) -> Self {
+        Self { inner, countdown }
+    }
+
+    fn tick(&self) {
+        let previous = self
+            .countdown
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        if previous == Ok(1) {
+            panic::panic_any(InjectedFault);
+        }
+    }
+}
+
+impl<S: Deref> Deref for FaultyStorage<S> {
+    type Target = S::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<S: DerefMut> DerefMut for FaultyStorage<S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.tick();
+        &mut self.inner
+    }
+}
+
+impl<T, S: Extend<T>> Extend<T> for FaultyStorage<S> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.tick();
+        self.inner.extend(iter);
+    }
+}
+
+impl<T, S: GenericStorage<T>> GenericStorage<T> for FaultyStorage<S> {
+    fn push(&mut self, value: T) {
+        self.tick();
+        self.inner.push(value);
+    }
+
+    fn extend_from_slice(&mut self, slice: &[T]) {
+        self.tick();
+        self.inner.extend_from_slice(slice);
+    }
+
+    fn clear(&mut self) {
+        self.tick();
+        self.inner.clear();
+    }
+
+    fn try_push(&mut self, value: T) -> storage::Result<()> {
+        self.tick();
+        self.inner.try_push(value)
+    }
+
+    fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> storage::Result<()> {
+        self.tick();
+        self.inner.try_extend(iter)
+    }
+
+    fn try_extend_from_slice(&mut self, slice: &[T]) -> storage::Result<()> {
+        self.tick();
+        self.inner.try_extend_from_slice(slice)
+    }
+
+    fn reserve(&mut self, additional: usize) -> storage::Result<()> {
+        self.tick();
+        self.inner.reserve(additional)
+    }
+
+    fn growth_policy(&self) -> GrowthPolicy {
+        self.inner.growth_policy()
+    }
+
+    fn mark_changed(&mut self, range: Range<usize>) {
+        self.inner.mark_changed(range);
+    }
+
+    fn flush(&self) -> storage::Result<()> {
+        self.inner.flush()
+    }
+
+    // Journal writes are not interrupted, so every crash happens after the
+    // write was journaled
+    fn journal(&mut self) -> Option<&mut dyn Journal<T>> {
+        self.inner.journal()
+    }
+
+    fn truncate(&mut self, len: usize) -> storage::Result<()> {
+        self.tick();
+        self.inner.truncate(len)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Push(Hash),
+    Set(usize, Hash),
+}
+
+#[derive(Default)]
+struct Report {
+    elapsed: Duration,
+    seed: u64,
+    pushes: u64,
+    sets: u64,
+    proofs: u64,
+    restores: u64,
+    validations: u64,
+    crashes: u64,
+    crashes_applied: u64,
+    rebuilds: u64,
+    failures: Vec<String>,
+    leaves: usize,
+}
+
+impl Report {
+    fn is_clean(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn ops(&self) -> u64 {
+        self.pushes + self.sets + self.proofs + self.restores + self.crashes
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        writeln!(f, "Stress test report")?;
+        writeln!(f, "  seed:          {}", self.seed)?;
+        writeln!(f, "  elapsed:       {secs:.1}s")?;
+        writeln!(
+            f,
+            "  operations:    {} ({:.0}/s)",
+            self.ops(),
+            self.ops() as f64 / secs
+        )?;
+        writeln!(f, "  pushes:        {}", self.pushes)?;
+        writeln!(f, "  sets:          {}", self.sets)?;
+        writeln!(f, "  proofs:        {}", self.proofs)?;
+        writeln!(f, "  restores:      {}", self.restores)?;
+        writeln!(f, "  validations:   {}", self.validations)?;
+        writeln!(
+            f,
+            "  crashes:       {} ({} applied, {} rebuilt from leaves)",
+            self.crashes, self.crashes_applied, self.rebuilds
+        )?;
+        writeln!(f, "  final leaves:  {}", self.leaves)?;
+        writeln!(f, "  failures:      {}", self.failures.len())?;
+        for failure in &self.failures {
+            writeln!(f, "    - {failure}")?;
+        }
+        Ok(())
+    }
+}
+
+struct Soak<'a> {
+    config: &'a Config,
+    rng: StdRng,
+    countdown: Arc<AtomicUsize>,
+    tree: Option<MmapTree>,
+    reference: ReferenceTree,
+    report: Report,
+}
+
+impl<'a> Soak<'a> {
+    fn new(config: &'a Config) -> Result<Self> {
+        let countdown = Arc::new(AtomicUsize::new(0));
+        let tree = MmapTree::new(
+            create(&config.path, countdown.clone())?,
+            config.depth,
+            &Hash::ZERO,
+        );
+        let reference = ReferenceTree::new(vec![], config.depth, &Hash::ZERO);
+
+        Ok(Self {
+            config,
+            rng: StdRng::seed_from_u64(config.seed),
+            countdown,
+            tree: Some(tree),
+            reference,
+            report: Report {
+                seed: config.seed,
+                ..Default::default()
+            },
+        })
+    }
+
+    fn run(mut self) -> Result<Report> {
+        let start = Instant::now();
+        let mut last_progress = start;
+        let mut iteration = 0_u64;
+
+        while start.elapsed() < self.config.duration {
+            // Restores and crash recovery validate the whole tree, keep them
+            // rare enough for the tree to keep growing.
+            match self.rng.gen_range(0..100) {
+                0..=39 => self.push()?,
+                40..=64 => self.set()?,
+                65..=94 => self.proof(),
+                95..=96 => self.restore()?,
+                _ => self.crash()?,
+            }
+
+            iteration += 1;
+            if iteration % self.config.validate_every == 0 {
+                self.validate();
+            }
+
+            if last_progress.elapsed() > Duration::from_secs(60) {
+                last_progress = Instant::now();
+                println!(
+                    "[{:>6}s] {} ops, {} leaves, {} failures",
+                    start.elapsed().as_secs(),
+                    self.report.ops(),
+                    self.reference.num_leaves(),
+                    self.report.failures.len()
+                );
+            }
+        }
+
+        self.validate();
+        self.report.elapsed = start.elapsed();
+        self.report.leaves = self.reference.num_leaves();
+        Ok(self.report)
+    }
+
+    fn tree(&mut self) -> &mut MmapTree {
+        self.tree
+            .as_mut()
+            .expect("tree is always present between ops")
+    }
+
+    fn random_hash(&mut self) -> Hash {
+        // Keep values well inside the field
+        U256::from(self.rng.gen::<u128>())
+    }
+
+    fn random_op(&mut self) -> Option<Op> {
+        let num_leaves = self.reference.num_leaves();
+        if num_leaves > 0 && (num_leaves >= 1 << self.config.depth || self.rng.gen_bool(0.5)) {
+            let leaf = self.rng.gen_range(0..num_leaves);
+            Some(Op::Set(leaf, self.random_hash()))
+        } else if num_leaves < 1 << self.config.depth {
+            Some(Op::Push(self.random_hash()))
+        } else {
+            None
+        }
+    }
+
+    fn apply(tree: &mut MmapTree, op: Op) -> Result<(), TreeError> {
+        match op {
+            Op::Push(value) => tree.push(value),
+            Op::Set(leaf, value) => tree.set_leaf(leaf, value),
+        }
+    }
+
+    fn apply_reference(&mut self, op: Op) -> Result<(), TreeError> {
+        match op {
+            Op::Push(value) => self.reference.push(value),
+            Op::Set(leaf, value) => self.reference.set_leaf(leaf, value),
+        }
+    }
+
+    fn push(&mut self) -> Result<()> {
+        if self.reference.num_leaves() >= 1 << self.config.depth {
+            return Ok(());
+        }
+        let op = Op::Push(self.random_hash());
+        Self::apply(self.tree(), op)?;
+        self.apply_reference(op)?;
+        self.report.pushes += 1;
+        Ok(())
+    }
+
+    fn set(&mut self) -> Result<()> {
+        let num_leaves = self.reference.num_leaves();
+        if num_leaves == 0 {
+            return Ok(());
+        }
+        let op = Op::Set(self.rng.gen_range(0..num_leaves), self.random_hash());
+        Self::apply(self.tree(), op)?;
+        self.apply_reference(op)?;
+        self.report.sets += 1;
+        Ok(())
+    }
+
+    fn proof(&mut self) {
+        let num_leaves = self.reference.num_leaves();
+        if num_leaves == 0 {
+            return;
+        }
+        let leaf = self.rng.gen_range(0..num_leaves);
+        let tree = self
+            .tree
+            .as_ref()
+            .expect("tree is always present between ops");
+        let proof = tree.proof(leaf);
+        if !self.reference.verify(tree.get_leaf(leaf), &proof) {
+            self.report
+                .failures
+                .push(format!("proof for leaf {leaf} does not verify"));
+        }
+        self.report.proofs += 1;
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        drop(self.tree.take());
+        let tree = match open(&self.config.path, self.countdown.clone(), self.config.depth) {
+            Ok(tree) => tree,
+            Err(err) => {
+                self.report
+                    .failures
+                    .push(format!("clean restore failed: {err}"));
+                self.rebuild()?
+            }
+        };
+        self.tree = Some(tree);
+        self.report.restores += 1;
+        self.compare("restore");
+        Ok(())
+    }
+
+    fn crash(&mut self) -> Result<()> {
+        let Some(op) = self.random_op() else {
+            return Ok(());
+        };
+
+        // A push touches at most `depth + 2` storage locations, plus one for
+        // the resize.
+        let countdown = self.rng.gen_range(1..=self.config.depth + 3);
+        self.countdown.store(countdown, Ordering::SeqCst);
+        let tree = self
+            .tree
+            .as_mut()
+            .expect("tree is always present between ops");
+        let result = panic::catch_unwind(AssertUnwindSafe(|| Self::apply(tree, op)));
+        self.countdown.store(0, Ordering::SeqCst);
+
+        match result {
+            // The countdown outlived the operation
+            Ok(result) => {
+                result?;
+                self.apply_reference(op)?;
+                self.report.crashes_applied += 1;
+            }
+            Err(payload) => {
+                if payload.downcast_ref::<InjectedFault>().is_none() {
+                    panic::resume_unwind(payload);
+                }
+                drop(self.tree.take());
+                let tree = match open(&self.config.path, self.countdown.clone(), self.config.depth)
+                {
+                    Ok(tree) => tree,
+                    Err(err) => {
+                        self.report
+                            .failures
+                            .push(format!("journal replay after crash failed: {err}"));
+                        self.rebuild()?
+                    }
+                };
+
+                // The interrupted write either made it to disk or it didn't.
+                let applied = match op {
+                    Op::Push(_) => tree.num_leaves() > self.reference.num_leaves(),
+                    Op::Set(leaf, value) => tree.get_leaf(leaf) == value,
+                };
+                if applied {
+                    self.apply_reference(op)?;
+                    self.report.crashes_applied += 1;
+                }
+                self.tree = Some(tree);
+            }
+        }
+
+        self.report.crashes += 1;
+        self.compare("crash recovery");
+        Ok(())
+    }
+
+    /// Recovers from a failed restore by rebuilding the tree from its leaves,
+    /// discarding the journal.
+    fn rebuild(&mut self) -> Result<MmapTree> {
+        let storage = MmapVec::restore_from_path(&self.config.path)?;
+        let torn = CascadingMerkleTree::<Poseidon, _>::restore_unchecked(
+            storage,
+            self.config.depth,
+            &Hash::ZERO,
+        )?;
+        let leaves: Vec<Hash> = torn.leaves().collect();
+        drop(torn);
+
+        self.report.rebuilds += 1;
+        Ok(MmapTree::new_with_leaves(
+            create(&self.config.path, self.countdown.clone())?,
+            self.config.depth,
+            &Hash::ZERO,
+            &leaves,
+        ))
+    }
+
+    fn compare(&mut self, context: &str) {
+        let tree = self
+            .tree
+            .as_ref()
+            .expect("tree is always present between ops");
+        if tree.root() != self.reference.root() {
+            self.report.failures.push(format!(
+                "root mismatch after {context} at {} leaves",
+                self.reference.num_leaves()
+            ));
+        }
+        if tree.num_leaves() != self.reference.num_leaves() {
+            self.report.failures.push(format!(
+                "leaf count mismatch after {context}: {} != {}",
+                tree.num_leaves(),
+                self.reference.num_leaves()
+            ));
+        }
+    }
+
+    fn validate(&mut self) {
+        let tree = self
+            .tree
+            .as_ref()
+            .expect("tree is always present between ops");
+        if let Err(err) = tree.validate() {
+            self.report
+                .failures
+                .push(format!("validation failed: {err}"));
+        }
+        self.report.validations += 1;
+        self.compare("validation");
+    }
+}
+
+fn journal_path(path: &Path) -> PathBuf {
+    let mut journal = path.as_os_str().to_owned();
+    journal.push(".journal");
+    PathBuf::from(journal)
+}
+
+fn create(
+    path: &Path,
+    countdown: Arc<AtomicUsize>,
+) -> Result<FaultyStorage<JournaledStorage<MmapVec<Hash>>>> {
+    let storage =
+        JournaledStorage::create_from_path(MmapVec::create_from_path(path)?, journal_path(path))?;
+    Ok(FaultyStorage::new(storage, countdown))
+}
+
+/// Restores the tree, replaying a write left pending in the journal.
+fn open(path: &Path, countdown: Arc<AtomicUsize>, depth: usize) -> Result<MmapTree> {
+    let storage =
+        JournaledStorage::restore_from_path(MmapVec::restore_from_path(path)?, journal_path(path))?;
+    let storage = FaultyStorage::new(storage, countdown);
+    Ok(MmapTree::restore(storage, depth, &Hash::ZERO)?)
+}
@@ -0,0 +1,323 @@
+//! An HTTP service generating and verifying proofs, to run as a sidecar.
+//!
+//! `POST /prove` takes a `ProveRequest` and `POST /verify` a `VerifyRequest`
+//! of `semaphore::protocol::wire`, and both answer with a `Response`. Bodies
+//! are JSON, or postcard with `Content-Type: application/x-postcard`, and
+//! responses are in the format of their request. `GET /health` answers once
+//! the service is up, and `GET /metrics` returns counters in the Prometheus
+//! text format.
+//!
+//! At most `concurrency` proofs, by default one per CPU, are generated or
+//! verified at once. Further requests wait for their turn.
+//!
+//! `cargo run --bin semaphore-prover --features prover-service,depth_20 --
+//! [address] [concurrency]`
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::Router;
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use semaphore::protocol::wire::{
+    self, ErrorResponse, ProveRequest, Response, VerifyRequest, WireError,
+};
+use semaphore::protocol::ProofError;
+use serde::de::DeserializeOwned;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:3000";
+
+const JSON: &str = "application/json";
+const POSTCARD: &str = "application/x-postcard";
+
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    Postcard,
+}
+
+impl Format {
+    fn of(headers: &HeaderMap) -> Self {
+        match headers.get(CONTENT_TYPE) {
+            Some(content_type) if content_type == POSTCARD => Self::Postcard,
+            _ => Self::Json,
+        }
+    }
+
+    const fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => JSON,
+            Self::Postcard => POSTCARD,
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, String> {
+        let message = match self {
+            Self::Json => {
+                let json = std::str::from_utf8(body).map_err(|_| "body is not UTF-8")?;
+                wire::from_json(json)
+            }
+            Self::Postcard => wire::from_bytes(body),
+        };
+        message.map_err(|error| error.to_string())
+    }
+
+    fn encode(self, status: StatusCode, response: &Response) -> axum::response::Response {
+        let body: Result<Vec<u8>, WireError> = match self {
+            Self::Json => wire::to_json(response).map(String::into_bytes),
+            Self::Postcard => wire::to_bytes(response),
+        };
+        match body {
+            Ok(body) => (status, [(CONTENT_TYPE, self.content_type())], body).into_response(),
+            Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Endpoint {
+    Prove,
+    Verify,
+}
+
+impl Endpoint {
+    const ALL: [Self; 2] = [Self::Prove, Self::Verify];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Prove => "prove",
+            Self::Verify => "verify",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    ok: AtomicU64,
+    failed: AtomicU64,
+    micros: AtomicU64,
+}
+
+/// Increments a gauge for as long as it's alive, so the gauge is also
+/// decremented when a request is cancelled or its handler panics.
+struct GaugeGuard(Arc<AtomicU64>);
+
+impl GaugeGuard {
+    fn new(gauge: &Arc<AtomicU64>) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Self(Arc::clone(gauge))
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct Service {
+    permits: Arc<Semaphore>,
+    counters: [Counters; 2],
+    waiting: Arc<AtomicU64>,
+    running: Arc<AtomicU64>,
+}
+
+impl Service {
+    fn new(concurrency: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency)),
+            counters: Default::default(),
+            waiting: Arc::new(AtomicU64::new(0)),
+            running: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Decodes a request and runs `handle` on it on the blocking thread pool,
+    /// once a permit is free.
+    async fn serve<T, F>(
+        &self,
+        endpoint: Endpoint,
+        headers: &HeaderMap,
+        body: &[u8],
+        handle: F,
+    ) -> axum::response::Response
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: FnOnce(T) -> Result<Response, ProofError> + Send + 'static,
+    {
+        let counters = &self.counters[endpoint as usize];
+        let format = Format::of(headers);
+        let request = match format.decode::<T>(body) {
+            Ok(request) => request,
+            Err(message) => {
+                counters.failed.fetch_add(1, Ordering::Relaxed);
+                return format.encode(StatusCode::BAD_REQUEST, &error_response(message));
+            }
+        };
+
+        let waiting = GaugeGuard::new(&self.waiting);
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        drop(waiting);
+
+        // The permit moves into the task, so it is only released when the
+        // work is done, even if the client goes away before.
+        let running = GaugeGuard::new(&self.running);
+        let start = Instant::now();
+        let result = tokio::task::spawn_blocking(move || {
+            let _running = running;
+            let _permit = permit;
+            handle(request)
+        })
+        .await;
+        let micros = u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        counters.micros.fetch_add(micros, Ordering::Relaxed);
+
+        let (status, response) = match result {
+            Ok(Ok(response)) => (StatusCode::OK, response),
+            Ok(Err(
+                error @ (ProofError::DepthMismatch { .. } | ProofError::UnsupportedDepth(_)),
+            )) => (StatusCode::BAD_REQUEST, error_response(error.to_string())),
+            Ok(Err(error)) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                error_response(error.to_string()),
+            ),
+            Err(error) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                error_response(format!("request failed: {error}")),
+            ),
+        };
+        let counter = if status == StatusCode::OK {
+            &counters.ok
+        } else {
+            &counters.failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        format.encode(status, &response)
+    }
+
+    fn metrics(&self) -> String {
+        let mut metrics = String::new();
+        metrics.push_str("# TYPE semaphore_prover_requests_total counter\n");
+        for endpoint in Endpoint::ALL {
+            let counters = &self.counters[endpoint as usize];
+            for (result, counter) in [("ok", &counters.ok), ("failed", &counters.failed)] {
+                let _ = writeln!(
+                    metrics,
+                    "semaphore_prover_requests_total{{endpoint=\"{}\",result=\"{result}\"}} {}",
+                    endpoint.name(),
+                    counter.load(Ordering::Relaxed)
+                );
+            }
+        }
+        metrics.push_str("# TYPE semaphore_prover_seconds_total counter\n");
+        for endpoint in Endpoint::ALL {
+            let micros = self.counters[endpoint as usize]
+                .micros
+                .load(Ordering::Relaxed);
+            let _ = writeln!(
+                metrics,
+                "semaphore_prover_seconds_total{{endpoint=\"{}\"}} {}.{:06}",
+                endpoint.name(),
+                micros / 1_000_000,
+                micros % 1_000_000
+            );
+        }
+        for (name, gauge) in [("waiting", &self.waiting), ("running", &self.running)] {
+            let _ = writeln!(
+                metrics,
+                "# TYPE semaphore_prover_{name} gauge\nsemaphore_prover_{name} {}",
+                gauge.load(Ordering::Relaxed)
+            );
+        }
+        metrics
+    }
+}
+
+fn error_response(message: String) -> Response {
+    Response::Error(ErrorResponse { message })
+}
+
+async fn prove(
+    State(service): State<Arc<Service>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> axum::response::Response {
+    service
+        .serve(Endpoint::Prove, &headers, &body, |request: ProveRequest| {
+            request.prove().map(Response::Prove)
+        })
+        .await
+}
+
+async fn verify(
+    State(service): State<Arc<Service>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> axum::response::Response {
+    service
+        .serve(
+            Endpoint::Verify,
+            &headers,
+            &body,
+            |request: VerifyRequest| request.verify().map(Response::Verify),
+        )
+        .await
+}
+
+async fn metrics(State(service): State<Arc<Service>>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        service.metrics(),
+    )
+}
+
+fn app(service: Arc<Service>) -> Router {
+    Router::new()
+        .route("/prove", post(prove))
+        .route("/verify", post(verify))
+        .route("/health", get(|| async { "ok" }))
+        .route("/metrics", get(metrics))
+        .with_state(service)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    let mut args = std::env::args().skip(1);
+    let address: SocketAddr = args
+        .next()
+        .as_deref()
+        .unwrap_or(DEFAULT_ADDRESS)
+        .parse()
+        .context("invalid address")?;
+    let concurrency = match args.next() {
+        Some(concurrency) => concurrency.parse().context("invalid concurrency")?,
+        None => std::thread::available_parallelism()?.get(),
+    };
+    if concurrency == 0 {
+        return Err(eyre!("concurrency must be at least 1"));
+    }
+
+    let listener = TcpListener::bind(address).await?;
+    println!("Serving proofs on {address} with concurrency {concurrency}");
+    axum::serve(listener, app(Arc::new(Service::new(concurrency))))
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,187 @@
+//! Payloads for propagating identity roots to other chains.
+//!
+//! The World ID state bridges read the latest root from the identity manager
+//! on mainnet and deliver it to a `WorldID` contract on the target chain, which
+//! records the time it was received and verifies proofs for a fixed tree
+//! depth. This module produces the ABI encoded data involved, so operators
+//! don't have to hand-encode calldata.
+
+use ethabi::{decode, encode, short_signature, ParamType, Token};
+use ethers_core::types::U256;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::field::MODULUS;
+use crate::Field;
+
+#[derive(Error, Debug)]
+pub enum BridgeError {
+    #[error("Error decoding payload: {0}")]
+    InvalidEncoding(#[from] ethabi::Error),
+    #[error("root is not a field element")]
+    RootNotInField,
+    #[error("timestamp does not fit in 64 bits")]
+    TimestampOutOfRange,
+    #[error("tree depth does not fit in 8 bits")]
+    DepthOutOfRange,
+}
+
+/// A root as propagated by a state bridge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootPropagation {
+    pub root: Field,
+    /// Time the root was received, in seconds since the unix epoch. Stored
+    /// as a `uint128` by the `WorldID` contracts.
+    pub timestamp: u64,
+    /// Depth of the tree the root belongs to.
+    pub tree_depth: u8,
+}
+
+impl RootPropagation {
+    /// Creates a payload, checking that the root is a field element.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the root is not reduced modulo the field order.
+    pub fn new(root: Field, timestamp: u64, tree_depth: u8) -> Result<Self, BridgeError> {
+        if root >= MODULUS {
+            return Err(BridgeError::RootNotInField);
+        }
+        Ok(Self {
+            root,
+            timestamp,
+            tree_depth,
+        })
+    }
+
+    /// Encodes the payload as `abi.encode(uint256 root, uint128 timestamp,
+    /// uint8 treeDepth)`.
+    #[must_use]
+    pub fn abi_encode(&self) -> Vec<u8> {
+        encode(&[
+            Token::Uint(to_u256(self.root)),
+            Token::Uint(U256::from(self.timestamp)),
+            Token::Uint(U256::from(self.tree_depth)),
+        ])
+    }
+
+    /// Decodes a payload produced by [`RootPropagation::abi_encode`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is not a valid encoding or any value is
+    /// out of range.
+    pub fn abi_decode(data: &[u8]) -> Result<Self, BridgeError> {
+        let tokens = decode(
+            &[
+                ParamType::Uint(256),
+                ParamType::Uint(128),
+                ParamType::Uint(8),
+            ],
+            data,
+        )?;
+        let values: Vec<U256> = tokens
+            .into_iter()
+            .map(|token| token.into_uint().expect("decoded as uint"))
+            .collect();
+
+        let timestamp = u64::try_from(values[1]).map_err(|_| BridgeError::TimestampOutOfRange)?;
+        let tree_depth = u8::try_from(values[2]).map_err(|_| BridgeError::DepthOutOfRange)?;
+        Self::new(from_u256(values[0]), timestamp, tree_depth)
+    }
+
+    /// Calldata for `receiveRoot(uint256)`, the message a state bridge sends
+    /// to the `WorldID` contract on the target chain.
+    #[must_use]
+    pub fn receive_root_calldata(&self) -> Vec<u8> {
+        let mut calldata = short_signature("receiveRoot", &[ParamType::Uint(256)]).to_vec();
+        calldata.extend(encode(&[Token::Uint(to_u256(self.root))]));
+        calldata
+    }
+}
+
+/// Calldata for `propagateRoot()`, which makes a state bridge send the latest
+/// root of the identity manager.
+#[must_use]
+pub fn propagate_root_calldata() -> Vec<u8> {
+    short_signature("propagateRoot", &[]).to_vec()
+}
+
+fn to_u256(value: Field) -> U256 {
+    U256::from_big_endian(&value.to_be_bytes::<32>())
+}
+
+fn from_u256(value: U256) -> Field {
+    let mut bytes = [0; 32];
+    value.to_big_endian(&mut bytes);
+    Field::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_abi_roundtrip() {
+        let payload = RootPropagation::new(Field::from(0x1234), 1_700_000_000, 30).unwrap();
+        let encoded = payload.abi_encode();
+
+        let mut expected = [0_u8; 96];
+        expected[30..32].copy_from_slice(&[0x12, 0x34]);
+        expected[60..64].copy_from_slice(&1_700_000_000_u32.to_be_bytes());
+        expected[95] = 30;
+        assert_eq!(encoded, expected);
+
+        assert_eq!(RootPropagation::abi_decode(&encoded).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_invalid_payloads() {
+        assert!(matches!(
+            RootPropagation::new(MODULUS, 0, 20),
+            Err(BridgeError::RootNotInField)
+        ));
+
+        let mut encoded = RootPropagation::new(Field::from(1), 0, 20)
+            .unwrap()
+            .abi_encode();
+        encoded[40] = 1;
+        assert!(matches!(
+            RootPropagation::abi_decode(&encoded),
+            Err(BridgeError::TimestampOutOfRange)
+        ));
+
+        assert!(matches!(
+            RootPropagation::abi_decode(&encoded[..64]),
+            Err(BridgeError::InvalidEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn test_calldata() {
+        let payload = RootPropagation::new(Field::from(5), 0, 16).unwrap();
+        let calldata = payload.receive_root_calldata();
+        assert_eq!(calldata[..4], hex!("fbde929b"));
+        let mut word = [0_u8; 32];
+        word[31] = 5;
+        assert_eq!(calldata[4..], word);
+
+        assert_eq!(propagate_root_calldata(), hex!("380db829"));
+    }
+
+    #[test]
+    fn test_serde() {
+        let payload = RootPropagation::new(Field::from(0xabcd), 42, 20).unwrap();
+        let json = serde_json::to_value(payload).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "root": "0xabcd", "timestamp": 42, "tree_depth": 20 })
+        );
+        assert_eq!(
+            serde_json::from_value::<RootPropagation>(json).unwrap(),
+            payload
+        );
+    }
+}
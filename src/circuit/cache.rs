@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+use ark_bn254::Bn254;
+#[cfg(feature = "prover")]
+use ark_bn254::Fr;
+#[cfg(feature = "prover")]
+use ark_groth16::ProvingKey;
+use ark_groth16::{prepare_verifying_key, PreparedVerifyingKey, VerifyingKey};
+#[cfg(feature = "prover")]
+use ark_relations::r1cs::ConstraintMatrices;
+use once_cell::sync::Lazy;
+#[cfg(feature = "prover")]
+use witness::Graph;
+
+/// A parsed proving key with its constraint matrices.
+#[cfg(feature = "prover")]
+pub type ZKey = (ProvingKey<Bn254>, ConstraintMatrices<Fr>);
+
+type Slots<T> = RwLock<HashMap<(Circuit, usize), Arc<T>>>;
+
+static GLOBAL: Lazy<ArtifactCache> = Lazy::new(ArtifactCache::default);
+
+/// The circuits artifacts are cached for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Circuit {
+    V3,
+    #[cfg(feature = "v4")]
+    V4,
+}
+
+/// Parsed verifying keys, proving keys and witness graphs, by circuit and
+/// tree depth.
+///
+/// Verifying keys are parsed from the embedded ones, and the other artifacts
+/// from the [`ArtifactSource`], on first use. Others,
+/// e.g. for depths that are not built in, can be inserted at runtime, and
+/// evicted artifacts are parsed again on their next use. Evicting only drops
+/// the cache's reference, proofs in flight keep using the artifacts they
+/// hold.
+///
+/// Protocol functions use [`ArtifactCache::global`].
+///
+/// [`ArtifactSource`]: super::ArtifactSource
+#[derive(Default)]
+pub struct ArtifactCache {
+    vks: Slots<PreparedVerifyingKey<Bn254>>,
+    #[cfg(feature = "prover")]
+    zkeys: Slots<ZKey>,
+    #[cfg(feature = "prover")]
+    graphs: Slots<Graph>,
+    #[cfg(feature = "metrics")]
+    counters: Counters,
+}
+
+/// Cache statistics since the cache was created.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Number of times a lock was held by another thread and had to be
+    /// waited for.
+    pub contended: u64,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    contended: AtomicU64,
+}
+
+#[derive(Clone, Copy)]
+enum Event {
+    Hit,
+    Miss,
+    Eviction,
+    Contention,
+}
+
+impl std::fmt::Debug for ArtifactCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArtifactCache")
+            .field("vks", &self.read(&self.vks).len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ArtifactCache {
+    /// The cache used by the protocol functions.
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &GLOBAL
+    }
+
+    /// Returns the prepared verifying key of a circuit for the given depth,
+    /// parsing the built in one if it is not cached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not cached and not built in.
+    #[must_use]
+    pub fn verifying_key(
+        &self,
+        circuit: Circuit,
+        depth: usize,
+    ) -> Arc<PreparedVerifyingKey<Bn254>> {
+        self.get_or_load(&self.vks, (circuit, depth), || {
+            super::load_verifying_key(circuit, depth)
+        })
+    }
+
+    /// Returns the proving key of a circuit for the given depth, parsing the
+    /// built in one if it is not cached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not cached and not built in.
+    #[cfg(feature = "prover")]
+    #[must_use]
+    pub fn zkey(&self, circuit: Circuit, depth: usize) -> Arc<ZKey> {
+        self.get_or_load(&self.zkeys, (circuit, depth), || {
+            super::load_zkey(circuit, depth)
+        })
+    }
+
+    /// Returns the proving key of a circuit for the given depth if it is
+    /// cached, without parsing the built in one.
+    #[cfg(feature = "prover")]
+    pub(crate) fn cached_zkey(&self, circuit: Circuit, depth: usize) -> Option<Arc<ZKey>> {
+        self.read(&self.zkeys).get(&(circuit, depth)).cloned()
+    }
+
+    /// Returns the witness graph of a circuit for the given depth, parsing
+    /// the built in one if it is not cached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the graph is not cached and not built in.
+    #[cfg(feature = "prover")]
+    #[must_use]
+    pub fn graph(&self, circuit: Circuit, depth: usize) -> Arc<Graph> {
+        self.get_or_load(&self.graphs, (circuit, depth), || {
+            super::load_graph(circuit, depth)
+        })
+    }
+
+    /// Returns whether the verifying key of a circuit is cached for the
+    /// given depth, e.g. because its artifacts were inserted.
+    #[must_use]
+    pub fn contains(&self, circuit: Circuit, depth: usize) -> bool {
+        self.read(&self.vks).contains_key(&(circuit, depth))
+    }
+
+    /// Returns whether the proving key and witness graph of a circuit are
+    /// cached for the given depth, e.g. because they were inserted.
+    #[cfg(feature = "prover")]
+    #[must_use]
+    pub fn contains_prover(&self, circuit: Circuit, depth: usize) -> bool {
+        self.read(&self.zkeys).contains_key(&(circuit, depth))
+            && self.read(&self.graphs).contains_key(&(circuit, depth))
+    }
+
+    /// Caches a verifying key, replacing the one for the same circuit and
+    /// depth.
+    pub fn insert_verifying_key(&self, circuit: Circuit, depth: usize, vk: &VerifyingKey<Bn254>) {
+        self.write(&self.vks)
+            .insert((circuit, depth), Arc::new(prepare_verifying_key(vk)));
+    }
+
+    /// Caches a proving key and its verifying key, replacing the ones for the
+    /// same circuit and depth.
+    #[cfg(feature = "prover")]
+    pub fn insert_zkey(&self, circuit: Circuit, depth: usize, zkey: ZKey) {
+        self.insert_verifying_key(circuit, depth, &zkey.0.vk);
+        self.write(&self.zkeys)
+            .insert((circuit, depth), Arc::new(zkey));
+    }
+
+    /// Caches a witness graph, replacing the one for the same circuit and
+    /// depth.
+    #[cfg(feature = "prover")]
+    pub fn insert_graph(&self, circuit: Circuit, depth: usize, graph: Graph) {
+        self.write(&self.graphs)
+            .insert((circuit, depth), Arc::new(graph));
+    }
+
+    /// Drops the cached artifacts of a circuit for the given depth, returning
+    /// whether any were cached.
+    pub fn evict(&self, circuit: Circuit, depth: usize) -> bool {
+        let key = (circuit, depth);
+        let mut evicted = self.write(&self.vks).remove(&key).is_some();
+        #[cfg(feature = "prover")]
+        {
+            evicted |= self.write(&self.zkeys).remove(&key).is_some();
+            evicted |= self.write(&self.graphs).remove(&key).is_some();
+        }
+        if evicted {
+            self.record(Event::Eviction);
+        }
+        evicted
+    }
+
+    /// Drops all cached artifacts.
+    pub fn clear(&self) {
+        self.write(&self.vks).clear();
+        #[cfg(feature = "prover")]
+        {
+            self.write(&self.zkeys).clear();
+            self.write(&self.graphs).clear();
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[must_use]
+    pub fn metrics(&self) -> CacheMetrics {
+        let counters = &self.counters;
+        CacheMetrics {
+            hits: counters.hits.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+            evictions: counters.evictions.load(Ordering::Relaxed),
+            contended: counters.contended.load(Ordering::Relaxed),
+        }
+    }
+
+    fn get_or_load<T>(
+        &self,
+        slots: &Slots<T>,
+        key: (Circuit, usize),
+        load: impl FnOnce() -> T,
+    ) -> Arc<T> {
+        if let Some(value) = self.read(slots).get(&key) {
+            self.record(Event::Hit);
+            return Arc::clone(value);
+        }
+        self.record(Event::Miss);
+
+        // Parsing takes seconds for large depths, so it's done without
+        // holding the lock. If another thread was faster, its value is kept.
+        let value = Arc::new(load());
+        Arc::clone(self.write(slots).entry(key).or_insert(value))
+    }
+
+    fn read<'a, T>(&self, lock: &'a RwLock<T>) -> RwLockReadGuard<'a, T> {
+        match lock.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                self.record(Event::Contention);
+                lock.read().unwrap_or_else(PoisonError::into_inner)
+            }
+            // The maps are never left half updated
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+        }
+    }
+
+    fn write<'a, T>(&self, lock: &'a RwLock<T>) -> RwLockWriteGuard<'a, T> {
+        match lock.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                self.record(Event::Contention);
+                lock.write().unwrap_or_else(PoisonError::into_inner)
+            }
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+        }
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(clippy::unused_self, unused_variables))]
+    fn record(&self, event: Event) {
+        #[cfg(feature = "metrics")]
+        {
+            let counter = match event {
+                Event::Hit => &self.counters.hits,
+                Event::Miss => &self.counters.misses,
+                Event::Eviction => &self.counters.evictions,
+                Event::Contention => &self.counters.contended,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "external-artifacts")))]
+mod tests {
+    use semaphore_depth_config::{get_prover_depths, get_supported_depths};
+
+    use super::*;
+
+    #[test]
+    fn test_verifying_key() {
+        let cache = ArtifactCache::default();
+        let depth = get_supported_depths()[0];
+
+        let vk = cache.verifying_key(Circuit::V3, depth);
+        assert!(Arc::ptr_eq(&vk, &cache.verifying_key(Circuit::V3, depth)));
+        assert!(cache.evict(Circuit::V3, depth));
+
+        // Keys for depths that are not built in can be registered
+        cache.insert_verifying_key(Circuit::V3, 99, &vk.vk);
+        assert_eq!(cache.verifying_key(Circuit::V3, 99).vk, vk.vk);
+    }
+
+    #[cfg(feature = "prover")]
+    #[test]
+    fn test_cache() {
+        let cache = ArtifactCache::default();
+        let depth = get_prover_depths()[0];
+
+        let zkey = cache.zkey(Circuit::V3, depth);
+        assert!(Arc::ptr_eq(&zkey, &cache.zkey(Circuit::V3, depth)));
+
+        // Evicted keys are parsed again, but stay alive for their users
+        assert!(cache.evict(Circuit::V3, depth));
+        assert!(!cache.evict(Circuit::V3, depth));
+        let reloaded = cache.zkey(Circuit::V3, depth);
+        assert!(!Arc::ptr_eq(&zkey, &reloaded));
+        assert_eq!(zkey.0.vk, reloaded.0.vk);
+
+        // Keys can be registered for depths that are not built in, which
+        // registers their verifying key too
+        cache.insert_zkey(Circuit::V3, 99, super::super::load_zkey(Circuit::V3, depth));
+        assert_eq!(cache.zkey(Circuit::V3, 99).0.vk, zkey.0.vk);
+        assert_eq!(cache.verifying_key(Circuit::V3, 99).vk, zkey.0.vk);
+        // Proving needs the witness graph as well
+        assert!(!cache.contains_prover(Circuit::V3, 99));
+        cache.insert_graph(
+            Circuit::V3,
+            99,
+            super::super::load_graph(Circuit::V3, depth),
+        );
+        assert!(cache.contains_prover(Circuit::V3, 99));
+
+        #[cfg(feature = "metrics")]
+        assert_eq!(
+            cache.metrics(),
+            CacheMetrics {
+                hits: 3,
+                misses: 2,
+                evictions: 1,
+                contended: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Downloading circuit artifacts.
+//!
+//! Builds with the `external-artifacts` feature don't embed proving keys and
+//! witness graphs. [`Fetcher`] downloads them for one depth at a time from
+//! `<base url>/<depth>/semaphore.arkzkey` and `<base url>/<depth>/graph.bin`,
+//! checks them against the SHA-256 checksums embedded at build time, and
+//! keeps them in a cache directory with the layout of
+//! [`ArtifactSource::Dir`], so they are only downloaded once.
+//!
+//! ```rust,ignore
+//! use semaphore::circuit::fetch::Fetcher;
+//!
+//! let fetcher = Fetcher::new("https://artifacts.example.com/semaphore")?;
+//! let context = fetcher.prover_context(16)?;
+//! let proof = context.generate_proof(&identity, &merkle_proof, external_nullifier_hash, signal_hash)?;
+//! ```
+//!
+//! [`ArtifactSource::Dir`]: super::ArtifactSource::Dir
+
+use std::path::{Path, PathBuf};
+
+use semaphore_depth_config::{get_prover_depth_index, get_prover_depths};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::graph_format::{self, GraphError};
+use super::{check, ArtifactCache, ArtifactError, Circuit, GRAPH_SHA256, ZKEY_SHA256};
+use crate::protocol::context::ProverContext;
+
+/// Environment variable [`default_cache_dir`] reads the cache directory from.
+pub const CACHE_DIR_ENV: &str = "SEMAPHORE_CACHE_DIR";
+
+const ZKEY_FILE: &str = "semaphore.arkzkey";
+const GRAPH_FILE: &str = "graph.bin";
+
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error(transparent)]
+    Artifact(#[from] ArtifactError),
+    #[error("failed to download {url}: {source}")]
+    Request {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("no cache directory for circuit artifacts, set {CACHE_DIR_ENV}")]
+    NoCacheDir,
+    #[error("invalid proving key {}: {message}", path.display())]
+    InvalidZkey { path: PathBuf, message: String },
+    #[error("invalid witness graph {}: {source}", path.display())]
+    InvalidGraph {
+        path: PathBuf,
+        #[source]
+        source: GraphError,
+    },
+}
+
+/// Downloads the circuit artifacts of the built in depths into a cache
+/// directory.
+#[derive(Clone, Debug)]
+pub struct Fetcher {
+    client: reqwest::blocking::Client,
+    base_url: String,
+    cache_dir: PathBuf,
+}
+
+impl Fetcher {
+    /// Downloads from `base_url` into the [`default_cache_dir`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no default cache directory.
+    pub fn new(base_url: impl Into<String>) -> Result<Self, FetchError> {
+        let cache_dir = default_cache_dir().ok_or(FetchError::NoCacheDir)?;
+        Ok(Self::with_cache_dir(base_url, cache_dir))
+    }
+
+    #[must_use]
+    pub fn with_cache_dir(base_url: impl Into<String>, cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            client: reqwest::blocking::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+            cache_dir: cache_dir.into(),
+        }
+    }
+
+    /// The directory the artifacts are kept in, which can be passed to
+    /// [`set_artifact_source`] once the artifacts of all supported depths
+    /// are fetched.
+    ///
+    /// [`set_artifact_source`]: super::set_artifact_source
+    #[must_use]
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// Makes sure the artifacts of a depth are in the cache directory,
+    /// downloading the ones that are missing or don't match their checksums.
+    /// Returns the directory of the depth.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the depth is not built in or has no witness graph,
+    /// see [`get_prover_depths`], a download fails or
+    /// doesn't match its checksum, or the cache can't be written.
+    pub fn fetch(&self, depth: usize) -> Result<PathBuf, FetchError> {
+        self.fetch_file(depth, ZKEY_FILE)?;
+        self.fetch_file(depth, GRAPH_FILE)?;
+        Ok(self.depth_dir(depth))
+    }
+
+    /// Fetches the artifacts of all depths with a witness graph.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::fetch`].
+    pub fn fetch_all(&self) -> Result<(), FetchError> {
+        for &depth in get_prover_depths() {
+            self.fetch(depth)?;
+        }
+        Ok(())
+    }
+
+    /// Fetches the artifacts of a depth, inserts them into
+    /// [`ArtifactCache::global`] and returns a [`ProverContext`] for them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if fetching fails, see [`Self::fetch`], or the
+    /// artifacts can't be parsed.
+    pub fn prover_context(&self, depth: usize) -> Result<ProverContext, FetchError> {
+        let zkey_bytes = self.fetch_file(depth, ZKEY_FILE)?;
+        let zkey = ark_zkey::read_arkzkey_from_bytes(&zkey_bytes).map_err(|err| {
+            FetchError::InvalidZkey {
+                path: self.depth_dir(depth).join(ZKEY_FILE),
+                message: format!("{err:#}"),
+            }
+        })?;
+        drop(zkey_bytes);
+
+        let graph_bytes = self.fetch_file(depth, GRAPH_FILE)?;
+        let (graph, _) = graph_format::decode_graph(&graph_bytes).map_err(|source| {
+            FetchError::InvalidGraph {
+                path: self.depth_dir(depth).join(GRAPH_FILE),
+                source,
+            }
+        })?;
+
+        let cache = ArtifactCache::global();
+        cache.insert_zkey(Circuit::V3, depth, zkey);
+        cache.insert_graph(Circuit::V3, depth, graph);
+        Ok(ProverContext::new(depth)?)
+    }
+
+    fn depth_dir(&self, depth: usize) -> PathBuf {
+        self.cache_dir.join(depth.to_string())
+    }
+
+    /// Returns the cached file if it matches its checksum, downloading it
+    /// otherwise.
+    fn fetch_file(&self, depth: usize, name: &str) -> Result<Vec<u8>, FetchError> {
+        let index = get_prover_depth_index(depth).ok_or(ArtifactError::UnsupportedDepth(depth))?;
+        let expected = match name {
+            ZKEY_FILE => ZKEY_SHA256[index],
+            _ => GRAPH_SHA256[index],
+        };
+        let dir = self.depth_dir(depth);
+        let path = dir.join(name);
+        let io_error = |path: &Path| {
+            let path = path.to_owned();
+            move |source| ArtifactError::Io { path, source }
+        };
+
+        if let Ok(bytes) = std::fs::read(&path) {
+            if hex::encode(Sha256::digest(&bytes)) == expected {
+                return Ok(bytes);
+            }
+        }
+
+        let url = format!("{}/{depth}/{name}", self.base_url);
+        let request_error = |source| FetchError::Request {
+            url: url.clone(),
+            source,
+        };
+        let bytes = self
+            .client
+            .get(&url)
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .and_then(reqwest::blocking::Response::bytes)
+            .map_err(request_error)?
+            .to_vec();
+        check(&path, &bytes, expected)?;
+
+        // Written next to the artifact and renamed, so an interrupted write
+        // never leaves a partial artifact behind
+        std::fs::create_dir_all(&dir).map_err(io_error(&dir))?;
+        let partial = dir.join(format!("{name}.part"));
+        std::fs::write(&partial, &bytes).map_err(io_error(&partial))?;
+        std::fs::rename(&partial, &path).map_err(io_error(&path))?;
+        Ok(bytes)
+    }
+}
+
+/// The directory artifacts are cached in by default: the
+/// `SEMAPHORE_CACHE_DIR` environment variable if it is set, and otherwise
+/// `semaphore-rs` in the user's cache directory, `$XDG_CACHE_HOME`,
+/// `$HOME/.cache` or `%LOCALAPPDATA%`.
+#[must_use]
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    if let Some(dir) = var(CACHE_DIR_ENV) {
+        return Some(dir.into());
+    }
+    let user_cache = var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| Path::new(&home).join(".cache")))
+        .or_else(|| var("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(user_cache.join("semaphore-rs"))
+}
+
+#[cfg(all(test, not(feature = "external-artifacts")))]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::circuit::{GRAPH_BYTES, ZKEY_BYTES};
+
+    /// Serves the embedded artifacts over HTTP, counting the requests.
+    fn serve() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines();
+                let request = lines.next().unwrap().unwrap();
+                while lines.next().is_some_and(|line| !line.unwrap().is_empty()) {}
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let path = request.split(' ').nth(1).unwrap();
+                let (depth, name) = path[1..].split_once('/').unwrap();
+                let index = get_prover_depth_index(depth.parse().unwrap()).unwrap();
+                let body = if name == ZKEY_FILE {
+                    ZKEY_BYTES[index]
+                } else {
+                    GRAPH_BYTES[index]
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn test_fetch() {
+        let (url, requests) = serve();
+        let dir = tempfile::tempdir().unwrap();
+        let fetcher = Fetcher::with_cache_dir(url, dir.path());
+        let depth = get_prover_depths()[0];
+
+        let depth_dir = fetcher.fetch(depth).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            std::fs::read(depth_dir.join(ZKEY_FILE)).unwrap(),
+            ZKEY_BYTES[0]
+        );
+
+        // Cached artifacts are not downloaded again, corrupted ones are
+        fetcher.fetch(depth).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        std::fs::write(depth_dir.join(GRAPH_FILE), b"corrupted").unwrap();
+        let context = fetcher.prover_context(depth).unwrap();
+        assert_eq!(context.depth(), depth);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        assert!(matches!(
+            fetcher.fetch(99),
+            Err(FetchError::Artifact(ArtifactError::UnsupportedDepth(99)))
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+//! Running the witness graph generator of `circom-witness-rs` as a
+//! subprocess.
+//!
+//! Witness graphs are built from a circuit's C++ witness generator by the
+//! `generate` binary of `circom-witness-rs`, built with its `build-witness`
+//! feature. The binary reads the path of the `.cpp` file from the
+//! [`WITNESS_CPP_ENV`] environment variable and writes the bare graph to
+//! `graph.bin` in its working directory. [`build_graph`] runs it and turns
+//! the output into a version 2 graph file with [`convert_legacy`].
+//!
+//! Nothing here builds graphs: the generator must be installed separately,
+//! and this module only runs it and wraps its output. The tests run a
+//! stand-in that follows the same contract, not the generator itself.
+//!
+//! ```rust,ignore
+//! use std::process::Command;
+//!
+//! use semaphore::circuit::graph_build::build_graph;
+//! use semaphore::circuit::graph_format::InputSignal;
+//!
+//! let graph = build_graph(
+//!     Command::new("generate"),
+//!     "circuits/rln.cpp",
+//!     vec![InputSignal::new("identitySecret", 1), InputSignal::new("x", 1)],
+//! )?;
+//! std::fs::write("graphs/rln/graph.bin", graph)?;
+//! ```
+//!
+//! [`convert_legacy`]: super::graph_format::convert_legacy
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+use thiserror::Error;
+
+use super::graph_format::{self, GraphError, InputSignal};
+
+/// Environment variable the generator reads the path of the circuit's C++
+/// witness generator from.
+pub const WITNESS_CPP_ENV: &str = "WITNESS_CPP";
+
+/// Generator name recorded in the headers of graphs built by
+/// [`build_graph`].
+pub const GENERATOR: &str = "circom-witness-rs";
+
+const GRAPH_FILE: &str = "graph.bin";
+
+#[derive(Debug, Error)]
+pub enum GraphBuildError {
+    #[error("failed to run the witness graph generator: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("witness graph generator failed with {status}: {stderr}")]
+    Generator { status: ExitStatus, stderr: String },
+    #[error("witness graph generator wrote no graph to {}", path.display())]
+    MissingGraph { path: PathBuf },
+    #[error(transparent)]
+    Graph(#[from] GraphError),
+}
+
+/// Runs the `generate` binary of `circom-witness-rs` for the C++ witness
+/// generator at `circuit_cpp` and returns the graph as a version 2 file with
+/// the given input signals.
+///
+/// `generator` is the command running the binary, e.g. the path of an
+/// installed `generate` or `cargo run --bin generate --features
+/// build-witness` in a checkout of `circom-witness-rs`. Without a working
+/// directory, it runs in a temporary directory. Otherwise the graph is read
+/// from, and left in, the working directory of the command.
+///
+/// # Errors
+///
+/// Returns an error if the generator fails or writes no graph, or if its
+/// output is not a valid bare graph.
+pub fn build_graph(
+    mut generator: Command,
+    circuit_cpp: impl AsRef<Path>,
+    inputs: Vec<InputSignal>,
+) -> Result<Vec<u8>, GraphBuildError> {
+    // The generator may run in another directory
+    let circuit_cpp = std::env::current_dir()?.join(circuit_cpp);
+    let temp_dir;
+    let dir = if let Some(dir) = generator.get_current_dir() {
+        dir.to_path_buf()
+    } else {
+        temp_dir = tempfile::tempdir()?;
+        generator.current_dir(temp_dir.path());
+        temp_dir.path().to_path_buf()
+    };
+
+    let output = generator.env(WITNESS_CPP_ENV, circuit_cpp).output()?;
+    if !output.status.success() {
+        return Err(GraphBuildError::Generator {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    let path = dir.join(GRAPH_FILE);
+    let graph = match std::fs::read(&path) {
+        Ok(graph) => graph,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(GraphBuildError::MissingGraph { path })
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(graph_format::convert_legacy(&graph, GENERATOR, inputs)?)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::circuit::graph_format::read_header;
+
+    /// A bare graph of the repository, passed as a path relative to the
+    /// crate like a circuit would be.
+    const CIRCUIT: &str = "graphs/16/graph.bin";
+
+    /// A stand-in for the generator, which follows its contract but writes
+    /// the file named by [`WITNESS_CPP_ENV`] to `graph.bin` instead of
+    /// building a graph from it.
+    fn generator(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    fn copying() -> Command {
+        generator("cp \"$WITNESS_CPP\" graph.bin")
+    }
+
+    #[test]
+    fn test_build_graph() {
+        let legacy = std::fs::read(CIRCUIT).unwrap();
+        let inputs = vec![InputSignal::new("signalHash", 1)];
+
+        // The generator runs in a temporary directory, where the relative
+        // path of the circuit would not resolve
+        let graph = build_graph(copying(), CIRCUIT, inputs.clone()).unwrap();
+        let (header, body) = read_header(&graph).unwrap().unwrap();
+        assert_eq!(header.generator, GENERATOR);
+        assert_eq!(header.inputs, inputs);
+        assert_eq!(body, legacy);
+
+        // The graph is read from, and left in, the working directory of the
+        // command
+        let dir = tempfile::tempdir().unwrap();
+        let mut command = copying();
+        command.current_dir(dir.path());
+        build_graph(command, CIRCUIT, inputs.clone()).unwrap();
+        assert_eq!(std::fs::read(dir.path().join(GRAPH_FILE)).unwrap(), legacy);
+
+        assert!(matches!(
+            build_graph(
+                generator("test -f \"$WITNESS_CPP\""),
+                CIRCUIT,
+                inputs.clone()
+            ),
+            Err(GraphBuildError::MissingGraph { .. })
+        ));
+        match build_graph(
+            generator("echo failed >&2; exit 3"),
+            CIRCUIT,
+            inputs.clone(),
+        ) {
+            Err(GraphBuildError::Generator { status, stderr }) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr, "failed\n");
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+        assert!(matches!(
+            build_graph(generator("echo not a graph > graph.bin"), CIRCUIT, inputs),
+            Err(GraphBuildError::Graph(_))
+        ));
+    }
+}
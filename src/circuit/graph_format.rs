@@ -0,0 +1,267 @@
+//! A versioned file format for witness graphs.
+//!
+//! `witness::init_graph` deserializes a bare postcard encoded graph, so a
+//! graph built by an incompatible generator fails to load with an obscure
+//! error, or loads and calculates wrong witnesses. Version 2 graph files
+//! start with the [`MAGIC`] bytes and the format version, followed by a
+//! [`GraphHeader`] and the bare graph. The header names the generator,
+//! holds the SHA-256 of the bare graph and lists the input signals the graph
+//! expects.
+//!
+//! [`decode_graph`] reads both formats, so graphs built before version 2
+//! keep working, and [`convert_legacy`] adds a header to them.
+//!
+//! Bare graphs are built from a circuit's C++ witness generator by
+//! `circom-witness-rs`. With the `graph-build` feature,
+//! `circuit::graph_build::build_graph` runs its generator and returns a
+//! version 2 file.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use witness::Graph;
+
+use super::WitnessError;
+use crate::Field;
+
+/// The bytes a version 2 graph file starts with.
+pub const MAGIC: [u8; 4] = *b"wgrf";
+
+/// The version of the graph files written by [`convert_legacy`].
+pub const VERSION: u16 = 2;
+
+#[derive(Error, Debug)]
+pub enum GraphError {
+    #[error(
+        "witness graph format version {0} is not supported, expected {VERSION}, regenerate the \
+         graph with a matching generator"
+    )]
+    UnsupportedVersion(u16),
+    #[error("invalid witness graph header: {0}")]
+    Header(#[from] postcard::Error),
+    #[error("witness graph is corrupted, its hash is {actual}, expected {expected}")]
+    HashMismatch { expected: String, actual: String },
+    #[error("invalid witness graph, it may have been built by an incompatible generator: {0}")]
+    Invalid(#[from] WitnessError),
+    #[error("graph already has a version {VERSION} header")]
+    AlreadyConverted,
+    #[error("missing input signal {0:?}")]
+    MissingInput(String),
+    #[error("unknown input signal {0:?}")]
+    UnknownInput(String),
+    #[error("input signal {name:?} has {actual} values, expected {expected}")]
+    InputSize {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+/// The header of a version 2 graph file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphHeader {
+    /// The tool that built the graph, e.g. `circom-witness-rs 0.2.0`.
+    pub generator: String,
+    /// SHA-256 of the bare graph.
+    pub circuit_hash: [u8; 32],
+    pub inputs: Vec<InputSignal>,
+}
+
+/// An input signal of a circuit and its number of values.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputSignal {
+    pub name: String,
+    pub size: usize,
+}
+
+impl InputSignal {
+    #[must_use]
+    pub fn new(name: impl Into<String>, size: usize) -> Self {
+        Self {
+            name: name.into(),
+            size,
+        }
+    }
+}
+
+impl GraphHeader {
+    /// Checks that inputs match the input signals of the graph, to report
+    /// mistakes before calculating a witness.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the first input signal that is missing, unknown
+    /// or has the wrong number of values.
+    pub fn check_inputs(&self, inputs: &HashMap<String, Vec<Field>>) -> Result<(), GraphError> {
+        for signal in &self.inputs {
+            let values = inputs
+                .get(&signal.name)
+                .ok_or_else(|| GraphError::MissingInput(signal.name.clone()))?;
+            if values.len() != signal.size {
+                return Err(GraphError::InputSize {
+                    name: signal.name.clone(),
+                    expected: signal.size,
+                    actual: values.len(),
+                });
+            }
+        }
+        if let Some(name) = inputs
+            .keys()
+            .find(|name| !self.inputs.iter().any(|signal| &signal.name == *name))
+        {
+            return Err(GraphError::UnknownInput(name.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// Splits a version 2 graph file into its header and the bare graph, after
+/// checking the hash. Returns `None` for bare graphs.
+///
+/// # Errors
+///
+/// Returns an error if the version is not supported, or the header is
+/// invalid or doesn't match the graph.
+pub fn read_header(bytes: &[u8]) -> Result<Option<(GraphHeader, &[u8])>, GraphError> {
+    let Some(bytes) = bytes.strip_prefix(&MAGIC) else {
+        return Ok(None);
+    };
+    let (version, bytes) = postcard::take_from_bytes::<u16>(bytes)?;
+    if version != VERSION {
+        return Err(GraphError::UnsupportedVersion(version));
+    }
+    let (header, graph) = postcard::take_from_bytes::<GraphHeader>(bytes)?;
+    let actual: [u8; 32] = Sha256::digest(graph).into();
+    if actual != header.circuit_hash {
+        return Err(GraphError::HashMismatch {
+            expected: hex::encode(header.circuit_hash),
+            actual: hex::encode(actual),
+        });
+    }
+    Ok(Some((header, graph)))
+}
+
+/// Parses a graph file of either format, returning the header of version 2
+/// files.
+///
+/// # Errors
+///
+/// Returns an error if the header is invalid, or the graph fails to parse.
+pub fn decode_graph(bytes: &[u8]) -> Result<(Graph, Option<GraphHeader>), GraphError> {
+    let (header, graph) = match read_header(bytes)? {
+        Some((header, graph)) => (Some(header), graph),
+        None => (None, bytes),
+    };
+    let graph = witness::init_graph(graph).map_err(WitnessError::from_report)?;
+    Ok((graph, header))
+}
+
+/// Adds a version 2 header to a bare graph.
+///
+/// # Errors
+///
+/// Returns an error if the bytes are already a version 2 file, or don't
+/// parse as a bare graph.
+pub fn convert_legacy(
+    bytes: &[u8],
+    generator: impl Into<String>,
+    inputs: Vec<InputSignal>,
+) -> Result<Vec<u8>, GraphError> {
+    if bytes.starts_with(&MAGIC) {
+        return Err(GraphError::AlreadyConverted);
+    }
+    witness::init_graph(bytes).map_err(WitnessError::from_report)?;
+    Ok(encode(bytes, generator.into(), inputs)?)
+}
+
+fn encode(
+    graph: &[u8],
+    generator: String,
+    inputs: Vec<InputSignal>,
+) -> Result<Vec<u8>, postcard::Error> {
+    let header = GraphHeader {
+        generator,
+        circuit_hash: Sha256::digest(graph).into(),
+        inputs,
+    };
+    let mut file = MAGIC.to_vec();
+    file = postcard::to_extend(&VERSION, file)?;
+    file = postcard::to_extend(&header, file)?;
+    file.extend_from_slice(graph);
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn semaphore_inputs(depth: usize) -> Vec<InputSignal> {
+        vec![
+            InputSignal::new("identityNullifier", 1),
+            InputSignal::new("identityTrapdoor", 1),
+            InputSignal::new("treePathIndices", depth),
+            InputSignal::new("treeSiblings", depth),
+            InputSignal::new("externalNullifier", 1),
+            InputSignal::new("signalHash", 1),
+        ]
+    }
+
+    #[test]
+    fn test_header() {
+        let graph = b"not a real graph";
+        let file = encode(graph, "test".to_owned(), semaphore_inputs(16)).unwrap();
+        let (header, bare) = read_header(&file).unwrap().unwrap();
+        assert_eq!(bare, graph);
+        assert_eq!(header.generator, "test");
+        assert!(read_header(graph).unwrap().is_none());
+
+        let mut tampered = file.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            read_header(&tampered),
+            Err(GraphError::HashMismatch { .. })
+        ));
+        let mut newer = file;
+        newer[MAGIC.len()] = 3;
+        assert!(matches!(
+            read_header(&newer),
+            Err(GraphError::UnsupportedVersion(3))
+        ));
+
+        let mut inputs: HashMap<_, _> = semaphore_inputs(16)
+            .into_iter()
+            .map(|signal| (signal.name, vec![Field::ZERO; signal.size]))
+            .collect();
+        header.check_inputs(&inputs).unwrap();
+        inputs.insert("treeSiblings".to_owned(), vec![Field::ZERO; 20]);
+        assert!(matches!(
+            header.check_inputs(&inputs),
+            Err(GraphError::InputSize {
+                expected: 16,
+                actual: 20,
+                ..
+            })
+        ));
+        inputs.remove("treeSiblings");
+        assert!(matches!(
+            header.check_inputs(&inputs),
+            Err(GraphError::MissingInput(name)) if name == "treeSiblings"
+        ));
+    }
+
+    #[cfg(not(feature = "external-artifacts"))]
+    #[test]
+    fn test_convert_legacy() {
+        let depth = semaphore_depth_config::get_prover_depths()[0];
+        let legacy = super::super::graph_bytes(depth);
+        let file = convert_legacy(legacy, "circom-witness-rs", semaphore_inputs(depth)).unwrap();
+        let (graph, header) = decode_graph(&file).unwrap();
+        assert_eq!(header.unwrap().inputs, semaphore_inputs(depth));
+        let (legacy_graph, header) = decode_graph(legacy).unwrap();
+        assert!(header.is_none());
+        assert_eq!(graph.nodes.len(), legacy_graph.nodes.len());
+        assert!(convert_legacy(&file, "again", Vec::new()).is_err());
+    }
+}
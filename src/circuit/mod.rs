@@ -0,0 +1,481 @@
+//! Circuit artifacts: the verifying keys, proving keys and witness graphs of
+//! each supported depth.
+//!
+//! Verifying keys are always embedded in the binary, and are all that builds
+//! without the `prover` feature include. Witness graphs are not shipped for
+//! every depth, prover builds only hold the proving artifacts of the depths in
+//! [`get_prover_depths`]. Proving keys and witness graphs are
+//! embedded by default too. Building with the `external-artifacts` feature
+//! only embeds their SHA-256 checksums instead, and loads them from a
+//! directory at startup, see [`set_artifact_source`]. With the
+//! `artifact-fetch` feature, the `fetch` module downloads them into a cache
+//! directory. Semaphore v4 artifacts are always embedded.
+//!
+//! Parsed artifacts are kept in the [`ArtifactCache`]. Witness graphs of other
+//! circuits can be registered by name in a [`CircuitRegistry`].
+
+#![allow(unused)]
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ark_bn254::{Bn254, Fr};
+use ark_groth16::{prepare_verifying_key, PreparedVerifyingKey, ProvingKey};
+use ark_relations::r1cs::ConstraintMatrices;
+use ark_zkey::{Compress, MappedArkzkey};
+use once_cell::sync::OnceCell;
+use semaphore_depth_config::{
+    get_depth_index, get_prover_depth_count, get_prover_depth_index, get_prover_depths,
+    get_supported_depth_count, get_supported_depths,
+};
+use semaphore_depth_macros::{array_for_depth_indices, array_for_depths, array_for_prover_depths};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+#[cfg(feature = "prover")]
+use witness::Graph;
+
+#[cfg(feature = "metrics")]
+pub use self::cache::CacheMetrics;
+#[cfg(feature = "prover")]
+pub use self::cache::ZKey;
+pub use self::cache::{ArtifactCache, Circuit};
+#[cfg(feature = "prover")]
+pub use self::registry::{CircuitRegistry, RegistryError};
+
+mod cache;
+#[cfg(feature = "artifact-fetch")]
+pub mod fetch;
+#[cfg(feature = "graph-build")]
+pub mod graph_build;
+#[cfg(feature = "prover")]
+pub mod graph_format;
+#[cfg(feature = "prover")]
+mod registry;
+#[cfg(feature = "prover")]
+pub mod wtns;
+
+/// Environment variable [`ArtifactSource::from_env`] reads the artifact
+/// directory from.
+pub const ARTIFACTS_DIR_ENV: &str = "SEMAPHORE_ARTIFACTS_DIR";
+
+const VK_BYTES: [&[u8]; get_supported_depth_count()] =
+    array_for_depths!(|depth| include_bytes!(env!(concat!("BUILD_RS_VK_FILE_", depth))));
+
+// The proving tables only hold the depths with a witness graph and are
+// looked up with `get_prover_depth_index`
+
+#[cfg(all(feature = "prover", not(feature = "external-artifacts")))]
+const ZKEY_BYTES: [&[u8]; get_prover_depth_count()] = array_for_prover_depths!(|depth| {
+    include_bytes!(env!(concat!("BUILD_RS_ARKZKEY_FILE_", depth)))
+});
+
+#[cfg(all(feature = "prover", not(feature = "external-artifacts")))]
+const GRAPH_BYTES: [&[u8]; get_prover_depth_count()] =
+    array_for_prover_depths!(|depth| include_bytes!(env!(concat!("BUILD_RS_GRAPH_FILE_", depth))));
+
+#[cfg(feature = "prover")]
+const ZKEY_SHA256: [&str; get_prover_depth_count()] =
+    array_for_prover_depths!(|depth| env!(concat!("BUILD_RS_ARKZKEY_SHA256_", depth)));
+
+#[cfg(feature = "prover")]
+const GRAPH_SHA256: [&str; get_prover_depth_count()] =
+    array_for_prover_depths!(|depth| env!(concat!("BUILD_RS_GRAPH_SHA256_", depth)));
+
+// The other tables are built in the order of the supported depths and
+// looked up with `get_depth_index`, which must agree
+const _: () = {
+    let indexed: [bool; get_supported_depth_count()] =
+        array_for_depth_indices!(|index, depth| is_depth_index(index, depth));
+    let mut i = 0;
+    while i < indexed.len() {
+        assert!(
+            indexed[i],
+            "depth tables must be ordered like get_depth_index"
+        );
+        i += 1;
+    }
+};
+
+const fn is_depth_index(index: usize, depth: usize) -> bool {
+    matches!(get_depth_index(depth), Some(i) if i == index)
+}
+
+static ARTIFACTS: OnceCell<Artifacts> = OnceCell::new();
+
+/// Where the circuit artifacts are loaded from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArtifactSource {
+    /// The artifacts embedded in the binary, unless it was built with the
+    /// `external-artifacts` feature.
+    Embedded,
+    /// A directory with a subdirectory per depth of [`get_prover_depths`],
+    /// holding the `semaphore.arkzkey` proving key and the `graph.bin`
+    /// witness graph of that depth. The build writes the proving keys to
+    /// `$OUT_DIR/semaphore_files` and the graphs are in `graphs`.
+    Dir(PathBuf),
+}
+
+impl ArtifactSource {
+    /// Returns the directory in the `SEMAPHORE_ARTIFACTS_DIR` environment
+    /// variable if it is set, and the embedded artifacts otherwise.
+    #[must_use]
+    pub fn from_env() -> Self {
+        std::env::var_os(ARTIFACTS_DIR_ENV).map_or(Self::Embedded, |dir| Self::Dir(dir.into()))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ArtifactError {
+    #[error("circuit artifacts are not embedded in this build, load them from a directory")]
+    NotEmbedded,
+    #[error("circuit artifacts are already loaded")]
+    AlreadyLoaded,
+    #[error("failed to read circuit artifact {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error(
+        "checksum mismatch for circuit artifact {}: expected {expected}, found {actual}",
+        path.display()
+    )]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error("depth {0} is not supported")]
+    UnsupportedDepth(usize),
+    #[error(
+        "proving key for depth {depth} has verifying key digest {actual}, expected {expected}"
+    )]
+    DigestMismatch {
+        depth: usize,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// An error of the `witness` crate, parsing a witness graph or calculating a
+/// witness with it.
+///
+/// The `witness` crate reports errors as `eyre` reports, which are kept as
+/// their message so that `eyre` is not part of the API of this crate.
+#[derive(Clone, Debug, Error)]
+#[error("{0}")]
+pub struct WitnessError(String);
+
+impl WitnessError {
+    #[cfg(feature = "prover")]
+    pub(crate) fn from_report(report: color_eyre::Report) -> Self {
+        Self(format!("{report:#}"))
+    }
+}
+
+/// Artifacts loaded from a directory, by prover depth index.
+///
+/// Proving keys are mapped rather than read, so they're not held in memory
+/// twice while being parsed.
+struct DirArtifacts {
+    #[cfg(feature = "prover")]
+    zkeys: Vec<MappedArkzkey>,
+    graphs: Vec<Vec<u8>>,
+}
+
+enum Artifacts {
+    #[cfg(not(feature = "external-artifacts"))]
+    Embedded,
+    Dir(DirArtifacts),
+}
+
+/// Loads and validates the circuit artifacts of all supported depths.
+///
+/// This must be called before the first proof is generated or verified,
+/// which otherwise loads them from [`ArtifactSource::from_env`].
+///
+/// # Errors
+///
+/// Returns an error if an artifact can't be read or doesn't match the
+/// checksum embedded at build time, or if the artifacts were already loaded.
+pub fn set_artifact_source(source: ArtifactSource) -> Result<(), ArtifactError> {
+    let artifacts = load(source)?;
+    ARTIFACTS
+        .set(artifacts)
+        .map_err(|_| ArtifactError::AlreadyLoaded)
+}
+
+fn load(source: ArtifactSource) -> Result<Artifacts, ArtifactError> {
+    match source {
+        #[cfg(not(feature = "external-artifacts"))]
+        ArtifactSource::Embedded => Ok(Artifacts::Embedded),
+        #[cfg(feature = "external-artifacts")]
+        ArtifactSource::Embedded => Err(ArtifactError::NotEmbedded),
+        ArtifactSource::Dir(dir) => load_dir(&dir).map(Artifacts::Dir),
+    }
+}
+
+fn load_dir(dir: &Path) -> Result<DirArtifacts, ArtifactError> {
+    let mut artifacts = DirArtifacts {
+        #[cfg(feature = "prover")]
+        zkeys: Vec::new(),
+        graphs: Vec::new(),
+    };
+    // Verifying keys are embedded, so verifier only builds load nothing
+    #[cfg(feature = "prover")]
+    for (index, depth) in get_prover_depths().iter().enumerate() {
+        let depth_dir = dir.join(depth.to_string());
+        artifacts.zkeys.push(map_checked(
+            &depth_dir.join("semaphore.arkzkey"),
+            ZKEY_SHA256[index],
+        )?);
+        artifacts.graphs.push(read_checked(
+            &depth_dir.join("graph.bin"),
+            GRAPH_SHA256[index],
+        )?);
+    }
+    Ok(artifacts)
+}
+
+fn read_checked(path: &Path, expected: &str) -> Result<Vec<u8>, ArtifactError> {
+    let bytes = std::fs::read(path).map_err(|source| ArtifactError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    check(path, &bytes, expected)?;
+    Ok(bytes)
+}
+
+fn map_checked(path: &Path, expected: &str) -> Result<MappedArkzkey, ArtifactError> {
+    let io_error = |source| ArtifactError::Io {
+        path: path.to_owned(),
+        source,
+    };
+    let file = File::open(path).map_err(io_error)?;
+    let zkey = MappedArkzkey::from_file(&file, Compress::Yes).map_err(io_error)?;
+    check(path, zkey.as_bytes(), expected)?;
+    Ok(zkey)
+}
+
+fn check(path: &Path, bytes: &[u8], expected: &str) -> Result<(), ArtifactError> {
+    let actual = hex::encode(Sha256::digest(bytes));
+    if actual != expected {
+        return Err(ArtifactError::ChecksumMismatch {
+            path: path.to_owned(),
+            expected: expected.to_owned(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+fn artifacts() -> &'static Artifacts {
+    ARTIFACTS.get_or_init(|| {
+        load(ArtifactSource::from_env())
+            .unwrap_or_else(|err| panic!("Failed to load circuit artifacts: {err}"))
+    })
+}
+
+#[cfg(feature = "prover")]
+pub(crate) fn zkey_bytes(depth: usize) -> &'static [u8] {
+    let index = prover_depth_index(depth);
+    match artifacts() {
+        #[cfg(not(feature = "external-artifacts"))]
+        Artifacts::Embedded => ZKEY_BYTES[index],
+        Artifacts::Dir(artifacts) => artifacts.zkeys[index].as_bytes(),
+    }
+}
+
+#[cfg(feature = "prover")]
+fn graph_bytes(depth: usize) -> &'static [u8] {
+    let index = prover_depth_index(depth);
+    match artifacts() {
+        #[cfg(not(feature = "external-artifacts"))]
+        Artifacts::Embedded => GRAPH_BYTES[index],
+        Artifacts::Dir(artifacts) => &artifacts.graphs[index],
+    }
+}
+
+#[cfg(feature = "prover")]
+fn prover_depth_index(depth: usize) -> usize {
+    get_prover_depth_index(depth)
+        .unwrap_or_else(|| panic!("depth {depth} has no built in witness graph"))
+}
+
+#[cfg(feature = "v4")]
+const V4_VK_BYTES: [&[u8]; get_supported_depth_count()] =
+    array_for_depths!(|depth| include_bytes!(env!(concat!("BUILD_RS_V4_VK_FILE_", depth))));
+
+#[cfg(feature = "v4-prover")]
+const V4_ZKEY_BYTES: [&[u8]; get_supported_depth_count()] =
+    array_for_depths!(|depth| include_bytes!(env!(concat!("BUILD_RS_V4_ARKZKEY_FILE_", depth))));
+
+#[cfg(feature = "v4-prover")]
+const V4_GRAPH_BYTES: [&[u8]; get_supported_depth_count()] =
+    array_for_depths!(|depth| include_bytes!(env!(concat!("BUILD_RS_V4_GRAPH_FILE_", depth))));
+
+/// Returns the SHA-256 of the built in verifying key for the given depth,
+/// or `None` if the depth is not supported.
+///
+/// This is the [`ark_zkey::vk_digest`] of the circuit, as printed by
+/// `cargo ark-zkey`, and identifies the proving keys that create proofs the
+/// verifier accepts.
+#[must_use]
+pub fn artifact_digest(depth: usize) -> Option<[u8; 32]> {
+    get_depth_index(depth).map(|index| Sha256::digest(VK_BYTES[index]).into())
+}
+
+/// Parses the built in verifying key of a circuit for the given depth.
+fn load_verifying_key(circuit: Circuit, depth: usize) -> PreparedVerifyingKey<Bn254> {
+    let index = get_depth_index(depth).unwrap_or_else(|| panic!("depth {depth} is not supported"));
+    let bytes = match circuit {
+        Circuit::V3 => VK_BYTES[index],
+        #[cfg(feature = "v4")]
+        Circuit::V4 => V4_VK_BYTES[index],
+    };
+    let vk = ark_zkey::read_vk_from_bytes(bytes).expect("verifying key should be valid");
+    prepare_verifying_key(&vk)
+}
+
+/// Parses the built in proving key of a circuit for the given depth.
+#[cfg(feature = "prover")]
+fn load_zkey(circuit: Circuit, depth: usize) -> ZKey {
+    let bytes = match circuit {
+        Circuit::V3 => zkey_bytes(depth),
+        #[cfg(feature = "v4-prover")]
+        Circuit::V4 => {
+            let index =
+                get_depth_index(depth).unwrap_or_else(|| panic!("depth {depth} is not supported"));
+            V4_ZKEY_BYTES[index]
+        }
+        #[cfg(all(feature = "v4", not(feature = "v4-prover")))]
+        Circuit::V4 => panic!("Semaphore v4 proving keys require the `v4-prover` feature"),
+    };
+    ark_zkey::read_arkzkey_from_bytes(bytes).expect("zkey should be valid")
+}
+
+/// Parses the built in witness graph of a circuit for the given depth.
+#[cfg(feature = "prover")]
+fn load_graph(circuit: Circuit, depth: usize) -> Graph {
+    let bytes = match circuit {
+        Circuit::V3 => graph_bytes(depth),
+        #[cfg(feature = "v4-prover")]
+        Circuit::V4 => {
+            let index =
+                get_depth_index(depth).unwrap_or_else(|| panic!("depth {depth} is not supported"));
+            V4_GRAPH_BYTES[index]
+        }
+        #[cfg(all(feature = "v4", not(feature = "v4-prover")))]
+        Circuit::V4 => panic!("Semaphore v4 witness graphs require the `v4-prover` feature"),
+    };
+    graph_format::decode_graph(bytes)
+        .unwrap_or_else(|err| panic!("Failed to initialize Graph: {err}"))
+        .0
+}
+
+#[must_use]
+pub(crate) fn verifying_key(depth: usize) -> Arc<PreparedVerifyingKey<Bn254>> {
+    ArtifactCache::global().verifying_key(Circuit::V3, depth)
+}
+
+#[cfg(feature = "prover")]
+#[must_use]
+pub(crate) fn zkey(depth: usize) -> Arc<ZKey> {
+    ArtifactCache::global().zkey(Circuit::V3, depth)
+}
+
+#[cfg(feature = "prover")]
+#[must_use]
+pub(crate) fn graph(depth: usize) -> Arc<Graph> {
+    ArtifactCache::global().graph(Circuit::V3, depth)
+}
+
+/// Returns the Semaphore v4 verifying key for the given maximum depth.
+#[cfg(feature = "v4")]
+#[must_use]
+pub(crate) fn v4_verifying_key(depth: usize) -> Arc<PreparedVerifyingKey<Bn254>> {
+    ArtifactCache::global().verifying_key(Circuit::V4, depth)
+}
+
+/// Returns the Semaphore v4 circuit key for the given maximum depth.
+#[cfg(feature = "v4-prover")]
+#[must_use]
+pub(crate) fn v4_zkey(depth: usize) -> Arc<ZKey> {
+    ArtifactCache::global().zkey(Circuit::V4, depth)
+}
+
+/// Returns whether the build has the Semaphore v4 witness graph for the
+/// given maximum depth. Builds without the graph in `graphs/v4` embed an
+/// empty one.
+#[cfg(feature = "v4-prover")]
+#[must_use]
+pub(crate) fn has_v4_graph(depth: usize) -> bool {
+    get_depth_index(depth).is_some_and(|index| !V4_GRAPH_BYTES[index].is_empty())
+}
+
+#[cfg(feature = "v4-prover")]
+#[must_use]
+pub(crate) fn v4_graph(depth: usize) -> Arc<Graph> {
+    ArtifactCache::global().graph(Circuit::V4, depth)
+}
+
+#[cfg(all(test, feature = "prover", not(feature = "external-artifacts")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verifying_keys() {
+        assert_eq!(artifact_digest(99), None);
+        for &depth in get_prover_depths() {
+            assert_eq!(
+                artifact_digest(depth),
+                Some(ark_zkey::vk_digest(&load_zkey(Circuit::V3, depth).0.vk))
+            );
+            assert_eq!(
+                load_verifying_key(Circuit::V3, depth).vk,
+                load_zkey(Circuit::V3, depth).0.vk
+            );
+            #[cfg(feature = "v4-prover")]
+            assert_eq!(
+                load_verifying_key(Circuit::V4, depth).vk,
+                load_zkey(Circuit::V4, depth).0.vk
+            );
+        }
+    }
+
+    fn write_artifacts(dir: &Path) {
+        for (index, depth) in get_prover_depths().iter().enumerate() {
+            let depth_dir = dir.join(depth.to_string());
+            std::fs::create_dir_all(&depth_dir).unwrap();
+            std::fs::write(depth_dir.join("semaphore.arkzkey"), ZKEY_BYTES[index]).unwrap();
+            std::fs::write(depth_dir.join("graph.bin"), GRAPH_BYTES[index]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        write_artifacts(dir.path());
+        let artifacts = load_dir(dir.path()).unwrap();
+        assert_eq!(artifacts.zkeys[0].as_bytes(), ZKEY_BYTES[0]);
+
+        let path = dir
+            .path()
+            .join(get_prover_depths()[0].to_string())
+            .join("semaphore.arkzkey");
+        let mut corrupted = ZKEY_BYTES[0].to_vec();
+        corrupted[0] ^= 1;
+        std::fs::write(&path, corrupted).unwrap();
+        assert!(matches!(
+            load_dir(dir.path()),
+            Err(ArtifactError::ChecksumMismatch { path: p, .. }) if p == path
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            load_dir(dir.path()),
+            Err(ArtifactError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use thiserror::Error;
+use witness::Graph;
+
+use super::graph_format::{self, GraphError, GraphHeader};
+use super::WitnessError;
+use crate::Field;
+
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("no circuit named {0:?} is registered")]
+    UnknownCircuit(String),
+    #[error("failed to read witness graph {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Graph(#[from] GraphError),
+    #[error("error calculating witness: {0}")]
+    Witness(#[from] WitnessError),
+}
+
+/// Witness graphs by name, so one binary can calculate witnesses for several
+/// circuits, e.g. Semaphore, RLN and application circuits.
+///
+/// Graphs are loaded at runtime from the `graph.bin` files built for each
+/// circuit, in either [`graph_format`]. The inputs of graphs loaded with a
+/// version 2 header are checked against its input signals before
+/// calculating witnesses. The built in Semaphore graphs can be registered from the
+/// [`ArtifactCache`]:
+///
+/// ```rust,ignore
+/// use semaphore::circuit::{ArtifactCache, Circuit, CircuitRegistry};
+///
+/// let registry = CircuitRegistry::new();
+/// registry.insert("semaphore", ArtifactCache::global().graph(Circuit::V3, 20));
+/// registry.load_file("rln", "/opt/circuits/rln/graph.bin")?;
+/// let witness = registry.calculate_witness("rln", inputs)?;
+/// ```
+///
+/// [`ArtifactCache`]: super::ArtifactCache
+#[derive(Default)]
+pub struct CircuitRegistry {
+    graphs: RwLock<HashMap<String, Entry>>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    graph: Arc<Graph>,
+    header: Option<Arc<GraphHeader>>,
+}
+
+impl std::fmt::Debug for CircuitRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CircuitRegistry")
+            .field("names", &self.names())
+            .finish()
+    }
+}
+
+impl CircuitRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a graph, returning the one previously registered under the
+    /// same name.
+    pub fn insert(
+        &self,
+        name: impl Into<String>,
+        graph: impl Into<Arc<Graph>>,
+    ) -> Option<Arc<Graph>> {
+        self.insert_entry(name.into(), graph.into(), None)
+    }
+
+    /// Parses a serialized witness graph and registers it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RegistryError::Graph`] if the graph fails to parse.
+    pub fn load(&self, name: impl Into<String>, bytes: &[u8]) -> Result<(), RegistryError> {
+        let (graph, header) = graph_format::decode_graph(bytes)?;
+        self.insert_entry(name.into(), Arc::new(graph), header.map(Arc::new));
+        Ok(())
+    }
+
+    /// Reads a witness graph from a file and registers it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or the graph fails to
+    /// parse.
+    pub fn load_file(
+        &self,
+        name: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> Result<(), RegistryError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|source| RegistryError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        self.load(name, &bytes)
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Arc<Graph>> {
+        self.graphs
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .map(|entry| Arc::clone(&entry.graph))
+    }
+
+    /// The header of a graph loaded from a version 2 file.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<Arc<GraphHeader>> {
+        self.graphs
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .and_then(|entry| entry.header.clone())
+    }
+
+    /// Unregisters a graph. Witnesses being calculated keep using it.
+    pub fn remove(&self, name: &str) -> Option<Arc<Graph>> {
+        self.graphs
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(name)
+            .map(|entry| entry.graph)
+    }
+
+    /// The names of the registered graphs, sorted.
+    #[must_use]
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self
+            .graphs
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .cloned()
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Calculates the witness of the named circuit for the given signal
+    /// inputs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no graph is registered under the name, or the
+    /// inputs don't match the circuit.
+    pub fn calculate_witness(
+        &self,
+        name: &str,
+        inputs: HashMap<String, Vec<Field>>,
+    ) -> Result<Vec<Field>, RegistryError> {
+        // The lock is not held while calculating
+        let entry = self
+            .graphs
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+            .ok_or_else(|| RegistryError::UnknownCircuit(name.to_owned()))?;
+        if let Some(header) = &entry.header {
+            header.check_inputs(&inputs)?;
+        }
+        Ok(witness::calculate_witness(inputs, &entry.graph).map_err(WitnessError::from_report)?)
+    }
+
+    fn insert_entry(
+        &self,
+        name: String,
+        graph: Arc<Graph>,
+        header: Option<Arc<GraphHeader>>,
+    ) -> Option<Arc<Graph>> {
+        self.graphs
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name, Entry { graph, header })
+            .map(|entry| entry.graph)
+    }
+}
+
+#[cfg(all(test, not(feature = "external-artifacts")))]
+mod tests {
+    use ark_ff::PrimeField;
+    use semaphore_depth_config::get_prover_depths;
+
+    use super::*;
+    use crate::circuit::{ArtifactCache, Circuit};
+    use crate::identity::Identity;
+    use crate::poseidon_tree::LazyPoseidonTree;
+    use crate::protocol::{generate_witness, path_index};
+
+    #[test]
+    fn test_registry() {
+        let registry = CircuitRegistry::new();
+        assert!(matches!(
+            registry.calculate_witness("semaphore", HashMap::new()),
+            Err(RegistryError::UnknownCircuit(name)) if name == "semaphore"
+        ));
+        assert!(matches!(
+            registry.load_file("missing", "/nonexistent/graph.bin"),
+            Err(RegistryError::Io { .. })
+        ));
+
+        let depth = get_prover_depths()[0];
+        let graph = ArtifactCache::global().graph(Circuit::V3, depth);
+        assert!(registry.insert("semaphore", Arc::clone(&graph)).is_none());
+        assert!(registry.insert("other", graph).is_none());
+        assert_eq!(registry.names(), ["other", "semaphore"]);
+        assert!(registry.remove("other").is_some());
+        assert_eq!(registry.names(), ["semaphore"]);
+
+        let mut secret = *b"secret";
+        let identity = Identity::from_secret(&mut secret, None);
+        let tree = LazyPoseidonTree::new(depth, Field::from(0))
+            .derived()
+            .update(0, &identity.commitment());
+        let merkle_proof = tree.proof(0);
+        let inputs = HashMap::from([
+            ("identityNullifier".to_owned(), vec![identity.nullifier]),
+            ("identityTrapdoor".to_owned(), vec![identity.trapdoor]),
+            ("treePathIndices".to_owned(), path_index(&merkle_proof)),
+            ("treeSiblings".to_owned(), merkle_proof.siblings()),
+            ("externalNullifier".to_owned(), vec![Field::from(1)]),
+            ("signalHash".to_owned(), vec![Field::from(2)]),
+        ]);
+        let witness = registry.calculate_witness("semaphore", inputs).unwrap();
+        let expected: Vec<Field> =
+            generate_witness(&identity, &merkle_proof, Field::from(1), Field::from(2))
+                .iter()
+                .map(|x| Field::from_limbs(x.into_bigint().0))
+                .collect();
+        assert_eq!(witness, expected);
+    }
+}
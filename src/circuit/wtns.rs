@@ -0,0 +1,160 @@
+//! The binary `.wtns` witness format of snarkjs.
+//!
+//! Witnesses written with [`write`] can be passed to external provers, e.g.
+//! `rapidsnark prover semaphore.zkey witness.wtns proof.json public.json`,
+//! or checked against `snarkjs wtns calculate` when debugging witness
+//! generation.
+//!
+//! A file starts with the `wtns` magic, the format version and the number of
+//! sections. Each section is its type and byte length, followed by its data.
+//! The header section holds the field element size, the prime and the number
+//! of witness values, and the witness section the values themselves. All
+//! integers are little-endian.
+
+use std::io::{self, Read, Write};
+
+use crate::field::MODULUS;
+use crate::Field;
+
+/// The magic bytes a `.wtns` file starts with.
+pub const MAGIC: [u8; 4] = *b"wtns";
+
+/// The version of the format written by [`write`].
+pub const VERSION: u32 = 2;
+
+const HEADER_SECTION: u32 = 1;
+const WITNESS_SECTION: u32 = 2;
+
+/// Size of a field element in bytes.
+const N8: u32 = 32;
+
+/// Writes a witness, e.g. from [`generate_witness`] or
+/// [`CircuitRegistry::calculate_witness`], in the `.wtns` format.
+///
+/// # Errors
+///
+/// Returns an error if writing fails or the witness has more than
+/// `u32::MAX` values.
+///
+/// [`generate_witness`]: crate::protocol::generate_witness
+/// [`CircuitRegistry::calculate_witness`]: super::CircuitRegistry::calculate_witness
+pub fn write(mut writer: impl Write, witness: &[Field]) -> io::Result<()> {
+    let count = u32::try_from(witness.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "witness has too many values"))?;
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&2_u32.to_le_bytes())?;
+
+    writer.write_all(&HEADER_SECTION.to_le_bytes())?;
+    writer.write_all(&u64::from(N8 + 8).to_le_bytes())?;
+    writer.write_all(&N8.to_le_bytes())?;
+    writer.write_all(&MODULUS.to_le_bytes::<32>())?;
+    writer.write_all(&count.to_le_bytes())?;
+
+    writer.write_all(&WITNESS_SECTION.to_le_bytes())?;
+    writer.write_all(&(u64::from(N8) * u64::from(count)).to_le_bytes())?;
+    for value in witness {
+        writer.write_all(&value.to_le_bytes::<32>())?;
+    }
+    writer.flush()
+}
+
+/// Reads a witness in the `.wtns` format, e.g. written by snarkjs.
+///
+/// # Errors
+///
+/// Returns an error if reading fails, or the file is not a witness for the
+/// BN254 scalar field.
+pub fn read(mut reader: impl Read) -> io::Result<Vec<Field>> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid("not a wtns file"));
+    }
+    let version = read_u32(&mut reader)?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported wtns version {version}")));
+    }
+
+    let sections = read_u32(&mut reader)?;
+    let mut count = None;
+    let mut witness = None;
+    for _ in 0..sections {
+        let kind = read_u32(&mut reader)?;
+        let size = read_u64(&mut reader)?;
+        match kind {
+            HEADER_SECTION => {
+                if read_u32(&mut reader)? != N8 {
+                    return Err(invalid("unsupported field element size"));
+                }
+                if read_field(&mut reader)? != MODULUS {
+                    return Err(invalid("witness is not for the BN254 scalar field"));
+                }
+                count = Some(read_u32(&mut reader)?);
+            }
+            WITNESS_SECTION => {
+                let count = count.ok_or_else(|| invalid("witness section before header"))?;
+                if size != u64::from(N8) * u64::from(count) {
+                    return Err(invalid("witness section size does not match the header"));
+                }
+                witness = Some(
+                    (0..count)
+                        .map(|_| read_field(&mut reader))
+                        .collect::<io::Result<_>>()?,
+                );
+            }
+            _ => {
+                io::copy(&mut (&mut reader).take(size), &mut io::sink())?;
+            }
+        }
+    }
+    witness.ok_or_else(|| invalid("missing witness section"))
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_field(reader: &mut impl Read) -> io::Result<Field> {
+    let mut bytes = [0; 32];
+    reader.read_exact(&mut bytes)?;
+    Ok(Field::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let witness = [Field::from(1), Field::from(42), MODULUS - Field::from(1)];
+        let mut bytes = Vec::new();
+        write(&mut bytes, &witness).unwrap();
+
+        assert_eq!(bytes.len(), 12 + 12 + 40 + 12 + 3 * 32);
+        assert_eq!(&bytes[..12], b"wtns\x02\0\0\0\x02\0\0\0");
+        // The header section: type 1, 40 bytes, n8 = 32
+        assert_eq!(&bytes[12..28], b"\x01\0\0\0\x28\0\0\0\0\0\0\0\x20\0\0\0");
+        assert_eq!(&bytes[60..64], 3_u32.to_le_bytes());
+
+        assert_eq!(read(bytes.as_slice()).unwrap(), witness);
+        bytes[0] = b'x';
+        assert_eq!(
+            read(bytes.as_slice()).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -1,9 +1,12 @@
-use crate::util::keccak256;
+use crate::util::{keccak256, sha3_256};
 use ruint::{aliases::U256, uint};
 
 /// An element of the BN254 scalar field Fr.
 ///
 /// Represented as a big-endian byte vector without Montgomery reduction.
+/// Fields are ordered numerically, which is the lexicographic order of their
+/// big-endian bytes, and can be used as keys of ordered and hashed
+/// collections.
 // TODO: Make sure value is always reduced.
 pub type Field = U256;
 
@@ -11,15 +14,121 @@ pub type Field = U256;
 pub const MODULUS: Field =
     uint!(21888242871839275222246405745257275088548364400416034343698204186575808495617_U256);
 
+/// How [`hash_to_field_with`] hashes data and maps the digest to a field
+/// element.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FieldHashing {
+    /// Keccak-256, shifted right by one byte. Used by [`hash_to_field_bytes`]
+    /// and the Semaphore and World ID contracts.
+    #[default]
+    Keccak256Shr8,
+    /// NIST SHA3-256, reduced modulo the field order.
+    Sha3Mod,
+    /// Keccak-256, reduced modulo the field order.
+    KeccakMod,
+}
+
 /// Hash arbitrary data to a field element.
+#[must_use]
+#[allow(clippy::module_name_repetitions)]
+#[deprecated(note = "use `hash_to_field_bytes`, or `hash_signal` and `hash_external_nullifier`")]
+pub fn hash_to_field(data: &[u8]) -> Field {
+    hash_to_field_bytes(data)
+}
+
+/// Hash arbitrary data to a field element like `hashToField` of the
+/// Semaphore and World ID contracts, `uint256(keccak256(data)) >> 8`.
+#[must_use]
+#[allow(clippy::module_name_repetitions)]
+pub fn hash_to_field_bytes(data: &[u8]) -> Field {
+    hash_to_field_with(data, FieldHashing::Keccak256Shr8)
+}
+
+/// The `signal_hash` of a `bytes` or `string` signal, like
+/// `hashToField(abi.encodePacked(signal))` in the contracts.
+///
+/// Signals of other Solidity types are packed differently, e.g. an
+/// `address` as its 20 bytes, see `protocol::encoding::Signal`.
+#[must_use]
+pub fn hash_signal(signal: &[u8]) -> Field {
+    hash_to_field_bytes(signal)
+}
+
+/// The `external_nullifier_hash` of an action of an app, like World ID
+/// computes it in its contracts:
 ///
-/// This is used to create `signal_hash` and `external_nullifier_hash`.
+/// `hashToField(abi.encodePacked(hashToField(abi.encodePacked(appId)),
+/// action))`
+#[must_use]
+pub fn hash_external_nullifier(app_id: &str, action: &[u8]) -> Field {
+    let app_hash = hash_to_field_bytes(app_id.as_bytes()).to_be_bytes::<32>();
+    hash_to_field_bytes(&[app_hash.as_slice(), action].concat())
+}
+
+/// Hash arbitrary data to a field element using the given hash function and
+/// reduction.
 #[must_use]
 #[allow(clippy::module_name_repetitions)]
 #[allow(clippy::missing_panics_doc)]
-pub fn hash_to_field(data: &[u8]) -> Field {
+pub fn hash_to_field_with(data: &[u8], hashing: FieldHashing) -> Field {
+    let digest = match hashing {
+        FieldHashing::Keccak256Shr8 | FieldHashing::KeccakMod => keccak256(data),
+        FieldHashing::Sha3Mod => sha3_256(data),
+    };
     // Never panics because the target uint is large enough.
-    let n = U256::try_from_be_slice(&keccak256(data)).unwrap();
-    // Shift right one byte to make it fit in the field
-    n >> 8
+    let n = U256::try_from_be_slice(&digest).unwrap();
+    match hashing {
+        // Shift right one byte to make it fit in the field
+        FieldHashing::Keccak256Shr8 => n >> 8,
+        FieldHashing::Sha3Mod | FieldHashing::KeccakMod => n.reduce_mod(MODULUS),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_to_field_variants() {
+        assert_eq!(
+            hash_to_field_bytes(b""),
+            uint!(0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a4_U256)
+        );
+        assert_eq!(
+            hash_to_field_with(b"", FieldHashing::default()),
+            hash_to_field_bytes(b"")
+        );
+        #[allow(deprecated)]
+        let deprecated = hash_to_field(b"");
+        assert_eq!(deprecated, hash_to_field_bytes(b""));
+        assert_eq!(
+            hash_to_field_with(b"", FieldHashing::KeccakMod),
+            uint!(0x4410c360230a295b13d66d8d6c1a24c44311531e39c64f66c7301b49d85a46c_U256)
+        );
+        assert_eq!(
+            hash_to_field_with(b"", FieldHashing::Sha3Mod),
+            uint!(0x16d2dba01b89f6e928d076331bddcd4b7ce54674770ef846b732298fb0f84347_U256)
+        );
+    }
+
+    #[test]
+    fn test_contract_hashes() {
+        // `keccak256("hello")` and `keccak256(abi.encodePacked(uint256(1)))`,
+        // without their last byte
+        assert_eq!(
+            hash_signal(b"hello"),
+            uint!(0x1c8aff950685c2ed4bc3174f3472287b56d9517b9c948127319a09a7a36dea_U256)
+        );
+        assert_eq!(
+            hash_signal(&Field::from(1).to_be_bytes::<32>()),
+            uint!(0xb10e2d527612073b26eecdfd717e6a320cf44b4afac2b0732d9fcbe2b7fa0c_U256)
+        );
+
+        let app_hash = hash_to_field_bytes(b"app_staging_45068dca85829d2fd90e2dd6f0bff997");
+        let packed = [app_hash.to_be_bytes::<32>().as_slice(), b"vote"].concat();
+        assert_eq!(
+            hash_external_nullifier("app_staging_45068dca85829d2fd90e2dd6f0bff997", b"vote"),
+            hash_to_field_bytes(&packed)
+        );
+    }
 }
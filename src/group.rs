@@ -0,0 +1,310 @@
+//! Semaphore groups.
+//!
+//! [`Group`] keeps the members of a group in a Poseidon tree together with an
+//! index of their commitments, like the `Group` class of
+//! `@semaphore-protocol/group`. Members are added at the end of the tree, and
+//! removing a member sets its leaf to the empty leaf, so the indices of the
+//! other members never change.
+//!
+//! ```
+//! use semaphore::group::Group;
+//! use semaphore::identity::Identity;
+//! use semaphore::Field;
+//!
+//! let mut secret = *b"secret";
+//! let identity = Identity::from_secret(&mut secret, None);
+//!
+//! let mut group = Group::new(Field::from(42), 16);
+//! let index = group.add_member(identity.commitment()).unwrap();
+//! assert_eq!(group.index_of(identity.commitment()), Some(index));
+//! let merkle_proof = group.proof(index).unwrap();
+//! assert_eq!(merkle_proof.root(identity.commitment()), group.root());
+//! ```
+
+use std::collections::HashMap;
+
+use poseidon::Poseidon;
+use thiserror::Error;
+use trees::cascading::CascadingMerkleTree;
+
+use crate::poseidon_tree::{Proof, TreeConfig};
+use crate::Field;
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum GroupError {
+    #[error("group is full")]
+    Full,
+    #[error("{0:?} is already a member")]
+    DuplicateMember(Field),
+    #[error("{0:?} is the empty leaf and can't be a member")]
+    EmptyLeaf(Field),
+    #[error("no member at index {0}")]
+    UnknownIndex(usize),
+    #[error("member at index {0} has been removed")]
+    Removed(usize),
+}
+
+/// The members of a Semaphore group and the tree of their commitments.
+pub struct Group {
+    id: Field,
+    config: TreeConfig,
+    tree: CascadingMerkleTree<Poseidon>,
+    indices: HashMap<Field, usize>,
+}
+
+impl Group {
+    /// Creates an empty group with a tree of the given depth, whose empty
+    /// leaf is derived from the group id like in Semaphore v3 groups.
+    #[must_use]
+    pub fn new(id: Field, depth: usize) -> Self {
+        Self::with_config(id, TreeConfig::semaphore_v3_group(id, depth))
+    }
+
+    /// Creates an empty group with the tree parameters of `config`.
+    #[must_use]
+    pub fn with_config(id: Field, config: TreeConfig) -> Self {
+        Self {
+            id,
+            config,
+            tree: CascadingMerkleTree::new(vec![], config.depth, &config.empty_leaf),
+            indices: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn id(&self) -> Field {
+        self.id
+    }
+
+    #[must_use]
+    pub const fn depth(&self) -> usize {
+        self.config.depth
+    }
+
+    /// The leaf of removed members and of the rest of the tree.
+    #[must_use]
+    pub const fn zero_value(&self) -> Field {
+        self.config.empty_leaf
+    }
+
+    #[must_use]
+    pub fn root(&self) -> Field {
+        self.tree.root()
+    }
+
+    /// Returns the number of members added, including removed ones.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.tree.num_leaves()
+    }
+
+    /// Returns the leaves of all members added, with the zero value for
+    /// removed ones.
+    pub fn members(&self) -> impl Iterator<Item = Field> + '_ {
+        self.tree.leaves()
+    }
+
+    /// Returns the index of a member, or `None` if it is not in the group.
+    #[must_use]
+    pub fn index_of(&self, commitment: Field) -> Option<usize> {
+        self.indices.get(&commitment).copied()
+    }
+
+    /// Adds a member at the end of the tree, returning its index.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the group is full, or the commitment is already a
+    /// member or is the zero value.
+    pub fn add_member(&mut self, commitment: Field) -> Result<usize, GroupError> {
+        self.check_new_member(commitment)?;
+        let index = self.size();
+        if index >= 1 << self.depth() {
+            return Err(GroupError::Full);
+        }
+        self.tree
+            .push(commitment)
+            .expect("in-memory tree storage can't fail");
+        self.indices.insert(commitment, index);
+        Ok(index)
+    }
+
+    /// Adds members at the end of the tree, returning the index of the first
+    /// one. Adds none of them if any can't be added.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the members don't fit in the group, or a
+    /// commitment is already a member, is given twice or is the zero value.
+    pub fn add_members(&mut self, commitments: &[Field]) -> Result<usize, GroupError> {
+        let first = self.size();
+        if first + commitments.len() > 1 << self.depth() {
+            return Err(GroupError::Full);
+        }
+        let mut new = HashMap::with_capacity(commitments.len());
+        for (i, &commitment) in commitments.iter().enumerate() {
+            self.check_new_member(commitment)?;
+            if new.insert(commitment, first + i).is_some() {
+                return Err(GroupError::DuplicateMember(commitment));
+            }
+        }
+        self.tree
+            .extend_from_slice(commitments)
+            .expect("in-memory tree storage can't fail");
+        self.indices.extend(new);
+        Ok(first)
+    }
+
+    /// Replaces the member at the given index.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no member at the index or it has been
+    /// removed, or the new commitment is already a member or is the zero
+    /// value.
+    pub fn update_member(&mut self, index: usize, commitment: Field) -> Result<(), GroupError> {
+        let old = self.member(index)?;
+        if old == commitment {
+            return Ok(());
+        }
+        self.check_new_member(commitment)?;
+        self.set_leaf(index, commitment);
+        self.indices.remove(&old);
+        self.indices.insert(commitment, index);
+        Ok(())
+    }
+
+    /// Removes the member at the given index by setting its leaf to the zero
+    /// value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no member at the index or it has already
+    /// been removed.
+    pub fn remove_member(&mut self, index: usize) -> Result<(), GroupError> {
+        let old = self.member(index)?;
+        self.set_leaf(index, self.zero_value());
+        self.indices.remove(&old);
+        Ok(())
+    }
+
+    /// Returns the Merkle proof of the member at the given index, to generate
+    /// Semaphore proofs with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no member at the index or it has been
+    /// removed.
+    pub fn proof(&self, index: usize) -> Result<Proof, GroupError> {
+        self.member(index)?;
+        Ok(self.tree.proof(index))
+    }
+
+    fn member(&self, index: usize) -> Result<Field, GroupError> {
+        if index >= self.size() {
+            return Err(GroupError::UnknownIndex(index));
+        }
+        let leaf = self.tree.get_leaf(index);
+        if leaf == self.zero_value() {
+            return Err(GroupError::Removed(index));
+        }
+        Ok(leaf)
+    }
+
+    fn check_new_member(&self, commitment: Field) -> Result<(), GroupError> {
+        if commitment == self.zero_value() {
+            return Err(GroupError::EmptyLeaf(commitment));
+        }
+        if self.indices.contains_key(&commitment) {
+            return Err(GroupError::DuplicateMember(commitment));
+        }
+        Ok(())
+    }
+
+    fn set_leaf(&mut self, index: usize, value: Field) {
+        self.tree
+            .set_leaf(index, value)
+            .expect("in-memory tree storage can't fail");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn members(n: u64) -> Vec<Field> {
+        (1..=n).map(Field::from).collect()
+    }
+
+    #[test]
+    fn test_matches_tree_config() {
+        let id = Field::from(42);
+        let config = TreeConfig::semaphore_v3_group(id, 10);
+        let mut group = Group::new(id, 10);
+        assert_eq!(group.root(), config.empty_root());
+
+        assert_eq!(group.add_members(&members(5)).unwrap(), 0);
+        assert_eq!(group.add_member(Field::from(6)).unwrap(), 5);
+        assert_eq!(
+            group.root(),
+            config.lazy_tree_with_leaves(&members(6)).root()
+        );
+        assert_eq!(group.size(), 6);
+        assert!(group.members().eq(members(6)));
+
+        let proof = group.proof(3).unwrap();
+        assert_eq!(proof.root(Field::from(4)), group.root());
+    }
+
+    #[test]
+    fn test_update_and_remove() {
+        let mut group = Group::new(Field::from(1), 4);
+        group.add_members(&members(3)).unwrap();
+
+        group.update_member(1, Field::from(20)).unwrap();
+        assert_eq!(group.index_of(Field::from(2)), None);
+        assert_eq!(group.index_of(Field::from(20)), Some(1));
+
+        group.remove_member(0).unwrap();
+        assert_eq!(group.index_of(Field::from(1)), None);
+        assert_eq!(group.size(), 3);
+        let leaves = [group.zero_value(), Field::from(20), Field::from(3)];
+        assert!(group.members().eq(leaves));
+        let config = TreeConfig::semaphore_v3_group(Field::from(1), 4);
+        assert_eq!(group.root(), config.lazy_tree_with_leaves(&leaves).root());
+
+        assert_eq!(group.remove_member(0), Err(GroupError::Removed(0)));
+        assert_eq!(
+            group.update_member(0, Field::from(5)),
+            Err(GroupError::Removed(0))
+        );
+        assert_eq!(group.proof(0), Err(GroupError::Removed(0)));
+        assert_eq!(group.remove_member(3), Err(GroupError::UnknownIndex(3)));
+
+        // Removed members can be added again
+        assert_eq!(group.add_member(Field::from(1)).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut group = Group::new(Field::from(1), 2);
+        group.add_member(Field::from(1)).unwrap();
+        assert_eq!(
+            group.add_member(Field::from(1)),
+            Err(GroupError::DuplicateMember(Field::from(1)))
+        );
+        assert_eq!(
+            group.add_members(&[Field::from(2), Field::from(2)]),
+            Err(GroupError::DuplicateMember(Field::from(2)))
+        );
+        assert_eq!(
+            group.add_member(group.zero_value()),
+            Err(GroupError::EmptyLeaf(group.zero_value()))
+        );
+        assert_eq!(group.size(), 1);
+
+        group.add_members(&members(4)[1..]).unwrap();
+        assert_eq!(group.add_member(Field::from(5)), Err(GroupError::Full));
+        assert_eq!(group.add_members(&[]), Ok(4));
+    }
+}
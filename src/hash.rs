@@ -9,7 +9,9 @@ use num_bigint::{BigInt, Sign};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// Container for 256-bit hash values.
-#[derive(Clone, Copy, PartialEq, Eq, Default)]
+///
+/// Hashes are ordered lexicographically by their big-endian bytes.
+#[derive(Clone, Copy, PartialEq, Eq, std::hash::Hash, PartialOrd, Ord, Default)]
 pub struct Hash(pub [u8; 32]);
 
 impl Hash {
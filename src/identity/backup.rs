@@ -0,0 +1,372 @@
+//! Exporting identities for backups and for other Semaphore SDKs.
+//!
+//! An identity is its trapdoor and nullifier, two field elements of 254 bits
+//! each. All formats hold them losslessly:
+//!
+//! - A mnemonic of 48 English BIP-39 words, the 24 word mnemonic of the
+//!   trapdoor followed by the one of the nullifier, each with the 32
+//!   big-endian bytes of the field element as entropy.
+//! - A protobuf message, versioned so the format can change without breaking
+//!   old backups:
+//!
+//! ```protobuf
+//! message Identity {
+//!   uint32 version = 1;   // 1
+//!   bytes trapdoor = 2;   // 32 bytes, big-endian
+//!   bytes nullifier = 3;  // 32 bytes, big-endian
+//! }
+//! ```
+//!
+//! - The string of `Identity.toString()` in version 3 of the JavaScript SDK,
+//!   `@semaphore-protocol/identity`, a JSON array of the trapdoor and the
+//!   nullifier as `0x` prefixed hexadecimal strings. `new Identity(string)`
+//!   restores identities from it, and the commitments match. The identities
+//!   of version 4 are EdDSA keys without a trapdoor and nullifier, so they
+//!   can't be exchanged with this crate.
+
+use bip39::{Language, Mnemonic};
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
+
+use super::Identity;
+use crate::field::MODULUS;
+use crate::Field;
+
+/// Number of words of an identity mnemonic.
+pub const MNEMONIC_WORDS: usize = 48;
+
+/// Version of the protobuf encoding written by [`Identity::to_protobuf_bytes`].
+pub const ENCODING_VERSION: u64 = 1;
+
+const VERSION_FIELD: u64 = 1;
+const TRAPDOOR_FIELD: u64 = 2;
+const NULLIFIER_FIELD: u64 = 3;
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LEN: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error("invalid mnemonic: {0}")]
+    Mnemonic(#[from] bip39::Error),
+    #[error("identity mnemonic has {0} words, expected {MNEMONIC_WORDS}")]
+    WordCount(usize),
+    #[error("malformed identity encoding")]
+    Malformed,
+    #[error("unsupported identity encoding version {0}")]
+    UnsupportedVersion(u64),
+    #[error("identity secret is not a field element")]
+    NotInField,
+}
+
+impl Identity {
+    /// Encodes the identity as a mnemonic of [`MNEMONIC_WORDS`] words.
+    #[must_use]
+    pub fn to_mnemonic(&self) -> Zeroizing<String> {
+        let mut words = Zeroizing::new(String::new());
+        for field in [self.trapdoor, self.nullifier] {
+            let mut entropy = field.to_be_bytes::<32>();
+            let mnemonic = Mnemonic::from_entropy_in(Language::English, &entropy)
+                .expect("32 bytes are valid entropy");
+            entropy.zeroize();
+            if !words.is_empty() {
+                words.push(' ');
+            }
+            for (i, word) in mnemonic.words().enumerate() {
+                if i > 0 {
+                    words.push(' ');
+                }
+                words.push_str(word);
+            }
+        }
+        words
+    }
+
+    /// Decodes an identity from a mnemonic of [`Self::to_mnemonic`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mnemonic doesn't have [`MNEMONIC_WORDS`]
+    /// words, contains unknown words or wrong checksums, or doesn't encode
+    /// field elements.
+    pub fn from_mnemonic(phrase: &str) -> Result<Self, BackupError> {
+        let words: Vec<&str> = phrase.split_whitespace().collect();
+        if words.len() != MNEMONIC_WORDS {
+            return Err(BackupError::WordCount(words.len()));
+        }
+        let (trapdoor, nullifier) = words.split_at(MNEMONIC_WORDS / 2);
+        Ok(Self {
+            trapdoor: mnemonic_field(trapdoor)?,
+            nullifier: mnemonic_field(nullifier)?,
+        })
+    }
+
+    /// Encodes the identity as the protobuf message of the current
+    /// [`ENCODING_VERSION`].
+    #[must_use]
+    pub fn to_protobuf_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(70));
+        write_varint(&mut bytes, (VERSION_FIELD << 3) | WIRE_VARINT);
+        write_varint(&mut bytes, ENCODING_VERSION);
+        for (field, value) in [
+            (TRAPDOOR_FIELD, self.trapdoor),
+            (NULLIFIER_FIELD, self.nullifier),
+        ] {
+            write_varint(&mut bytes, (field << 3) | WIRE_LEN);
+            write_varint(&mut bytes, 32);
+            bytes.extend_from_slice(&value.to_be_bytes::<32>());
+        }
+        bytes
+    }
+
+    /// Decodes an identity from a protobuf message of
+    /// [`Self::to_protobuf_bytes`]. Unknown fields are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is malformed, misses a field, has an
+    /// unsupported version, or its secrets are not field elements.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, BackupError> {
+        let mut version = None;
+        let mut trapdoor = None;
+        let mut nullifier = None;
+        while !bytes.is_empty() {
+            let key = read_varint(&mut bytes)?;
+            match (key >> 3, key & 7) {
+                (VERSION_FIELD, WIRE_VARINT) => version = Some(read_varint(&mut bytes)?),
+                (TRAPDOOR_FIELD, WIRE_LEN) => trapdoor = Some(read_field(&mut bytes)?),
+                (NULLIFIER_FIELD, WIRE_LEN) => nullifier = Some(read_field(&mut bytes)?),
+                (_, WIRE_VARINT) => {
+                    read_varint(&mut bytes)?;
+                }
+                (_, WIRE_FIXED64) => {
+                    take(&mut bytes, 8)?;
+                }
+                (_, WIRE_LEN) => {
+                    let len = read_varint(&mut bytes)?;
+                    take(
+                        &mut bytes,
+                        usize::try_from(len).map_err(|_| BackupError::Malformed)?,
+                    )?;
+                }
+                (_, WIRE_FIXED32) => {
+                    take(&mut bytes, 4)?;
+                }
+                _ => return Err(BackupError::Malformed),
+            }
+        }
+
+        match version {
+            Some(ENCODING_VERSION) => {}
+            Some(version) => return Err(BackupError::UnsupportedVersion(version)),
+            None => return Err(BackupError::Malformed),
+        }
+        Ok(Self {
+            trapdoor: trapdoor.ok_or(BackupError::Malformed)?,
+            nullifier: nullifier.ok_or(BackupError::Malformed)?,
+        })
+    }
+
+    /// Encodes the identity like `Identity.toString()` of the v3 JavaScript
+    /// SDK, e.g. `["0x1","0x2"]`.
+    #[must_use]
+    pub fn to_js_string(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "[\"{:#x}\",\"{:#x}\"]",
+            self.trapdoor, self.nullifier
+        ))
+    }
+
+    /// Decodes an identity exported by `Identity.toString()` of the v3
+    /// JavaScript SDK, or by [`Self::to_js_string`]. Like the SDK, decimal
+    /// strings are accepted too.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the string is not a JSON array of two numbers, or
+    /// they are not field elements.
+    pub fn from_js_string(string: &str) -> Result<Self, BackupError> {
+        let [trapdoor, nullifier]: [Field; 2] =
+            serde_json::from_str(string).map_err(|_| BackupError::Malformed)?;
+        if trapdoor >= MODULUS || nullifier >= MODULUS {
+            return Err(BackupError::NotInField);
+        }
+        Ok(Self {
+            trapdoor,
+            nullifier,
+        })
+    }
+}
+
+fn mnemonic_field(words: &[&str]) -> Result<Field, BackupError> {
+    let phrase = Zeroizing::new(words.join(" "));
+    let mnemonic = Mnemonic::parse_in(Language::English, phrase.as_str())?;
+    let (entropy, len) = mnemonic.to_entropy_array();
+    let entropy = Zeroizing::new(entropy);
+    checked_field(&entropy[..len])
+}
+
+fn checked_field(bytes: &[u8]) -> Result<Field, BackupError> {
+    let field = Field::try_from_be_slice(bytes).ok_or(BackupError::NotInField)?;
+    if field >= MODULUS {
+        return Err(BackupError::NotInField);
+    }
+    Ok(field)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, BackupError> {
+    let mut value = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or(BackupError::Malformed)?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(BackupError::Malformed)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], BackupError> {
+    if bytes.len() < len {
+        return Err(BackupError::Malformed);
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn read_field(bytes: &mut &[u8]) -> Result<Field, BackupError> {
+    let len = read_varint(bytes)?;
+    if len != 32 {
+        return Err(BackupError::Malformed);
+    }
+    checked_field(take(bytes, 32)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    fn identity() -> Identity {
+        Identity::from_secret(&mut b"secret".to_vec(), None)
+    }
+
+    #[test]
+    fn test_mnemonic() {
+        let identity = identity();
+        let mnemonic = identity.to_mnemonic();
+        assert_eq!(mnemonic.split(' ').count(), MNEMONIC_WORDS);
+        assert_eq!(Identity::from_mnemonic(&mnemonic).unwrap(), identity);
+
+        // Whitespace is not significant, the words and their checksums are
+        let spaced = format!("  {}\n", mnemonic.replace(' ', "\t "));
+        assert_eq!(Identity::from_mnemonic(&spaced).unwrap(), identity);
+        let mut words: Vec<&str> = mnemonic.split(' ').collect();
+        assert!(matches!(
+            Identity::from_mnemonic(&words[..24].join(" ")),
+            Err(BackupError::WordCount(24))
+        ));
+        words.swap(0, 1);
+        assert!(matches!(
+            Identity::from_mnemonic(&words.join(" ")),
+            Err(BackupError::Mnemonic(_))
+        ));
+
+        let unreduced = Mnemonic::from_entropy(&[0xff; 32]).unwrap().to_string();
+        assert!(matches!(
+            Identity::from_mnemonic(&format!("{unreduced} {unreduced}")),
+            Err(BackupError::NotInField)
+        ));
+    }
+
+    #[test]
+    fn test_js_string() {
+        let identity = Identity {
+            trapdoor: Field::from(0xab),
+            nullifier: Field::from(2),
+        };
+        assert_eq!(*identity.to_js_string(), r#"["0xab","0x2"]"#);
+        assert_eq!(
+            Identity::from_js_string(r#"["0xab", "2"]"#).unwrap(),
+            identity
+        );
+
+        let identity = self::identity();
+        let string = identity.to_js_string();
+        assert_eq!(Identity::from_js_string(&string).unwrap(), identity);
+
+        for malformed in [
+            r#"["0x1"]"#,
+            r#"["0x1","0x2","0x3"]"#,
+            "secret",
+            r#"["x","1"]"#,
+        ] {
+            assert!(matches!(
+                Identity::from_js_string(malformed),
+                Err(BackupError::Malformed)
+            ));
+        }
+        assert!(matches!(
+            Identity::from_js_string(&format!(r#"["{MODULUS:#x}","0x1"]"#)),
+            Err(BackupError::NotInField)
+        ));
+    }
+
+    #[test]
+    fn test_protobuf_bytes() {
+        let identity = Identity {
+            trapdoor: Field::from(1),
+            nullifier: Field::from(2),
+        };
+        let bytes = identity.to_protobuf_bytes();
+        let mut expected = hex!("0801 1220").to_vec();
+        expected.extend_from_slice(&Field::from(1).to_be_bytes::<32>());
+        expected.extend_from_slice(&hex!("1a20"));
+        expected.extend_from_slice(&Field::from(2).to_be_bytes::<32>());
+        assert_eq!(*bytes, expected);
+        assert_eq!(Identity::from_bytes(&bytes).unwrap(), identity);
+
+        let identity = self::identity();
+        let bytes = identity.to_protobuf_bytes();
+        assert_eq!(Identity::from_bytes(&bytes).unwrap(), identity);
+
+        // Unknown fields of newer writers are skipped
+        let mut extended = bytes.to_vec();
+        extended.extend_from_slice(&hex!("2096 01 2a03 616263 3504030201"));
+        assert_eq!(Identity::from_bytes(&extended).unwrap(), identity);
+
+        let mut newer = bytes.to_vec();
+        newer[1] = 2;
+        assert!(matches!(
+            Identity::from_bytes(&newer),
+            Err(BackupError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            Identity::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BackupError::Malformed)
+        ));
+        assert!(matches!(
+            Identity::from_bytes(&bytes[..36]),
+            Err(BackupError::Malformed)
+        ));
+        let mut unreduced = bytes.to_vec();
+        unreduced[4..36].copy_from_slice(&MODULUS.to_be_bytes::<32>());
+        assert!(matches!(
+            Identity::from_bytes(&unreduced),
+            Err(BackupError::NotInField)
+        ));
+    }
+}
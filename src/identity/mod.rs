@@ -4,6 +4,11 @@ use zeroize::Zeroize;
 use crate::field::MODULUS;
 use crate::Field;
 
+mod backup;
+pub mod store;
+
+pub use self::backup::{BackupError, ENCODING_VERSION, MNEMONIC_WORDS};
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Identity {
     pub trapdoor: Field,
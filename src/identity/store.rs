@@ -0,0 +1,564 @@
+//! An encrypted, append-only vault of identities.
+//!
+//! Identities are stored under a label, sealed with XChaCha20-Poly1305 under
+//! a key derived from a passphrase with Argon2id. The vault is a byte
+//! [`GenericStorage`], usually an [`MmapVec`] file, laid out as a header
+//! followed by fixed size records:
+//!
+//! ```markdown
+//! header: [magic, version, salt, argon2 m/t/p costs, check nonce, check tag]
+//! record: [nonce, sealed (label length, label, trapdoor, nullifier), tag]
+//! ```
+//!
+//! The header is authenticated by the check block, which also tells a wrong
+//! passphrase apart from a corrupted vault. Every record is bound to the
+//! header and to its position, so records can't be reordered or moved
+//! between vaults. Records are only ever appended, and an append only
+//! becomes visible once the storage length is updated.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use storage::{GenericStorage, MmapVec};
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
+
+use super::Identity;
+use crate::{randomness, Field};
+
+const MAGIC: [u8; 8] = *b"SEMIDVLT";
+const VERSION: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+/// Length of the header up to the check block, which is authenticated as
+/// associated data.
+const PARAMS_LEN: usize = MAGIC.len() + 1 + SALT_LEN + 3 * 4;
+const HEADER_LEN: usize = PARAMS_LEN + NONCE_LEN + TAG_LEN;
+
+/// Maximum length of a label in bytes.
+pub const MAX_LABEL_LEN: usize = 63;
+const PLAINTEXT_LEN: usize = 1 + MAX_LABEL_LEN + 2 * 32;
+const RECORD_LEN: usize = NONCE_LEN + PLAINTEXT_LEN + TAG_LEN;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+    #[error("storage does not hold an identity store")]
+    NotAStore,
+    #[error("unsupported identity store version {0}")]
+    UnsupportedVersion(u8),
+    #[error("identity store is corrupted")]
+    Corrupted,
+    #[error("storage is not empty")]
+    NotEmpty,
+    #[error("label is already in use")]
+    LabelExists,
+    #[error("label is longer than {MAX_LABEL_LEN} bytes")]
+    LabelTooLong,
+    #[error("invalid key derivation parameters: {0}")]
+    InvalidParams(argon2::Error),
+    #[error("key derivation parameters exceed the maximum costs")]
+    ParamsTooCostly,
+    #[error("storage error: {0}")]
+    Storage(#[source] storage::StorageError),
+}
+
+/// Cost parameters of the Argon2id key derivation.
+///
+/// They are stored in the vault, so changing them only affects new vaults.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory size in KiB.
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// Maximum memory size in KiB, 2 GiB.
+    pub const MAX_MEMORY_KIB: u32 = 2 * 1024 * 1024;
+    /// Maximum number of iterations.
+    pub const MAX_ITERATIONS: u32 = 64;
+    /// Maximum degree of parallelism.
+    pub const MAX_PARALLELISM: u32 = 64;
+
+    /// Returns whether any cost exceeds its maximum.
+    ///
+    /// The costs are read from the vault before it is authenticated, so a
+    /// tampered vault could otherwise make unlocking allocate and hash
+    /// without bound.
+    #[must_use]
+    pub const fn exceeds_max(&self) -> bool {
+        self.memory_kib > Self::MAX_MEMORY_KIB
+            || self.iterations > Self::MAX_ITERATIONS
+            || self.parallelism > Self::MAX_PARALLELISM
+    }
+}
+
+impl Default for KdfParams {
+    /// The parameters recommended by OWASP.
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// An identity with its label, in plain text.
+///
+/// Returned by [`IdentityStore::export`] to move identities out of a vault.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedIdentity {
+    pub label: String,
+    pub trapdoor: Field,
+    pub nullifier: Field,
+}
+
+impl ExportedIdentity {
+    #[must_use]
+    pub fn identity(&self) -> Identity {
+        Identity {
+            trapdoor: self.trapdoor,
+            nullifier: self.nullifier,
+        }
+    }
+}
+
+/// An unlocked identity vault.
+///
+/// All identities are decrypted when the vault is unlocked and kept in
+/// memory until it is dropped.
+pub struct IdentityStore<S = MmapVec<u8>> {
+    storage: S,
+    cipher: XChaCha20Poly1305,
+    entries: Entries,
+}
+
+impl<S> std::fmt::Debug for IdentityStore<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdentityStore")
+            .field("len", &self.entries.0.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: GenericStorage<u8>> IdentityStore<S> {
+    /// Creates a vault in empty storage, with the default key derivation
+    /// parameters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage is not empty or can't be written.
+    pub fn create(storage: S, passphrase: &[u8]) -> Result<Self, StoreError> {
+        Self::create_with_params(storage, passphrase, KdfParams::default())
+    }
+
+    /// Creates a vault in empty storage.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage is not empty or can't be written, or
+    /// if the parameters are not accepted by Argon2 or exceed the maximums of
+    /// [`KdfParams`].
+    pub fn create_with_params(
+        mut storage: S,
+        passphrase: &[u8],
+        params: KdfParams,
+    ) -> Result<Self, StoreError> {
+        if !storage.is_empty() {
+            return Err(StoreError::NotEmpty);
+        }
+        if params.exceeds_max() {
+            return Err(StoreError::ParamsTooCostly);
+        }
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(&MAGIC);
+        header.push(VERSION);
+        let mut salt = [0_u8; SALT_LEN];
+        randomness::rng().fill_bytes(&mut salt);
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&params.memory_kib.to_le_bytes());
+        header.extend_from_slice(&params.iterations.to_le_bytes());
+        header.extend_from_slice(&params.parallelism.to_le_bytes());
+
+        let cipher = derive_cipher(passphrase, &salt, params)?;
+        header.extend_from_slice(&seal(&cipher, &[], &header)?);
+
+        storage
+            .try_extend_from_slice(&header)
+            .map_err(StoreError::Storage)?;
+        storage.flush().map_err(StoreError::Storage)?;
+
+        Ok(Self {
+            storage,
+            cipher,
+            entries: Entries::default(),
+        })
+    }
+
+    /// Unlocks an existing vault, decrypting all identities.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StoreError::WrongPassphrase`] if the passphrase doesn't
+    /// match, or another error if the storage doesn't hold a valid vault.
+    pub fn unlock(storage: S, passphrase: &[u8]) -> Result<Self, StoreError> {
+        if storage.len() < HEADER_LEN || storage[..MAGIC.len()] != MAGIC {
+            return Err(StoreError::NotAStore);
+        }
+        let version = storage[MAGIC.len()];
+        if version != VERSION {
+            return Err(StoreError::UnsupportedVersion(version));
+        }
+        if (storage.len() - HEADER_LEN) % RECORD_LEN != 0 {
+            return Err(StoreError::Corrupted);
+        }
+
+        let salt_start = MAGIC.len() + 1;
+        let salt = &storage[salt_start..salt_start + SALT_LEN];
+        let cost = |i: usize| {
+            let start = salt_start + SALT_LEN + 4 * i;
+            u32::from_le_bytes(storage[start..start + 4].try_into().unwrap())
+        };
+        let params = KdfParams {
+            memory_kib: cost(0),
+            iterations: cost(1),
+            parallelism: cost(2),
+        };
+        // Checked before deriving the key, the costs are not authenticated
+        // yet
+        if params.exceeds_max() {
+            return Err(StoreError::Corrupted);
+        }
+
+        let cipher = derive_cipher(passphrase, salt, params)?;
+        open(
+            &cipher,
+            &storage[PARAMS_LEN..HEADER_LEN],
+            &storage[..PARAMS_LEN],
+        )
+        .map_err(|_| StoreError::WrongPassphrase)?;
+
+        let mut store = Self {
+            storage,
+            cipher,
+            entries: Entries::default(),
+        };
+        let records = (store.storage.len() - HEADER_LEN) / RECORD_LEN;
+        for index in 0..records {
+            let start = HEADER_LEN + index * RECORD_LEN;
+            let record = &store.storage[start..start + RECORD_LEN];
+            let aad = record_aad(&store.storage, index);
+            let plaintext = Zeroizing::new(open(&store.cipher, record, &aad)?);
+            let (label, identity) = decode(&plaintext)?;
+            if store.get(&label).is_some() {
+                return Err(StoreError::Corrupted);
+            }
+            store.entries.0.push((label, identity));
+        }
+        Ok(store)
+    }
+
+    /// Seals an identity under a new label and appends it to the vault.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the label is too long or already in use, or if the
+    /// storage can't be written.
+    pub fn insert(&mut self, label: &str, identity: &Identity) -> Result<(), StoreError> {
+        if label.len() > MAX_LABEL_LEN {
+            return Err(StoreError::LabelTooLong);
+        }
+        if self.get(label).is_some() {
+            return Err(StoreError::LabelExists);
+        }
+
+        let plaintext = Zeroizing::new(encode(label, identity));
+        let aad = record_aad(&self.storage, self.entries.0.len());
+        let record = seal(&self.cipher, &plaintext, &aad)?;
+        self.storage
+            .try_extend_from_slice(&record)
+            .map_err(StoreError::Storage)?;
+        self.storage.flush().map_err(StoreError::Storage)?;
+
+        self.entries.0.push((label.to_owned(), identity.clone()));
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn get(&self, label: &str) -> Option<&Identity> {
+        self.entries
+            .0
+            .iter()
+            .find(|(l, _)| l == label)
+            .map(|(_, identity)| identity)
+    }
+
+    /// Iterates over the labels and identities in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Identity)> {
+        self.entries
+            .0
+            .iter()
+            .map(|(label, identity)| (label.as_str(), identity))
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.0.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.0.is_empty()
+    }
+
+    /// Returns all identities in plain text, in insertion order.
+    #[must_use]
+    pub fn export(&self) -> Vec<ExportedIdentity> {
+        self.iter()
+            .map(|(label, identity)| ExportedIdentity {
+                label: label.to_owned(),
+                trapdoor: identity.trapdoor,
+                nullifier: identity.nullifier,
+            })
+            .collect()
+    }
+
+    /// Locks the vault, returning the underlying storage.
+    #[must_use]
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+}
+
+/// Decrypted entries, which are zeroized when dropped.
+#[derive(Default)]
+struct Entries(Vec<(String, Identity)>);
+
+impl Drop for Entries {
+    fn drop(&mut self) {
+        for (label, identity) in &mut self.0 {
+            label.zeroize();
+            identity.trapdoor.zeroize();
+            identity.nullifier.zeroize();
+        }
+    }
+}
+
+fn derive_cipher(
+    passphrase: &[u8],
+    salt: &[u8],
+    params: KdfParams,
+) -> Result<XChaCha20Poly1305, StoreError> {
+    let params = Params::new(
+        params.memory_kib,
+        params.iterations,
+        params.parallelism,
+        Some(KEY_LEN),
+    )
+    .map_err(StoreError::InvalidParams)?;
+    let mut key = Zeroizing::new([0_u8; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, &mut key[..])
+        .map_err(StoreError::InvalidParams)?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key[..])))
+}
+
+/// Encrypts under a fresh random nonce, returning the nonce followed by the
+/// ciphertext and tag.
+fn seal(cipher: &XChaCha20Poly1305, msg: &[u8], aad: &[u8]) -> Result<Vec<u8>, StoreError> {
+    let mut nonce = [0_u8; NONCE_LEN];
+    randomness::rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), Payload { msg, aad })
+        .map_err(|_| StoreError::Corrupted)?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn open(cipher: &XChaCha20Poly1305, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, StoreError> {
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| StoreError::Corrupted)
+}
+
+/// Binds a record to the vault header and its position.
+fn record_aad(storage: &[u8], index: usize) -> Vec<u8> {
+    let mut aad = storage[..HEADER_LEN].to_vec();
+    aad.extend_from_slice(&(index as u64).to_le_bytes());
+    aad
+}
+
+fn encode(label: &str, identity: &Identity) -> Vec<u8> {
+    let mut plaintext = vec![0_u8; PLAINTEXT_LEN];
+    plaintext[0] = label.len() as u8;
+    plaintext[1..=label.len()].copy_from_slice(label.as_bytes());
+    let secrets = 1 + MAX_LABEL_LEN;
+    plaintext[secrets..secrets + 32].copy_from_slice(&identity.trapdoor.to_be_bytes::<32>());
+    plaintext[secrets + 32..].copy_from_slice(&identity.nullifier.to_be_bytes::<32>());
+    plaintext
+}
+
+fn decode(plaintext: &[u8]) -> Result<(String, Identity), StoreError> {
+    let len = usize::from(plaintext[0]);
+    if len > MAX_LABEL_LEN {
+        return Err(StoreError::Corrupted);
+    }
+    let label = std::str::from_utf8(&plaintext[1..=len])
+        .map_err(|_| StoreError::Corrupted)?
+        .to_owned();
+    let secrets = 1 + MAX_LABEL_LEN;
+    let identity = Identity {
+        trapdoor: Field::from_be_bytes::<32>(plaintext[secrets..secrets + 32].try_into().unwrap()),
+        nullifier: Field::from_be_bytes::<32>(plaintext[secrets + 32..].try_into().unwrap()),
+    };
+    Ok((label, identity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters so the tests don't spend their time hashing.
+    const PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    fn identity(secret: &[u8]) -> Identity {
+        Identity::from_secret(&mut secret.to_vec(), None)
+    }
+
+    #[test]
+    fn test_insert_and_unlock() {
+        let mut store =
+            IdentityStore::create_with_params(Vec::new(), b"passphrase", PARAMS).unwrap();
+        store.insert("alice", &identity(b"alice")).unwrap();
+        store.insert("bob", &identity(b"bob")).unwrap();
+        assert!(matches!(
+            store.insert("alice", &identity(b"other")),
+            Err(StoreError::LabelExists)
+        ));
+        assert!(matches!(
+            store.insert(&"x".repeat(MAX_LABEL_LEN + 1), &identity(b"x")),
+            Err(StoreError::LabelTooLong)
+        ));
+
+        let storage = store.into_storage();
+        assert_eq!(storage.len(), HEADER_LEN + 2 * RECORD_LEN);
+        assert!(matches!(
+            IdentityStore::unlock(storage.clone(), b"wrong"),
+            Err(StoreError::WrongPassphrase)
+        ));
+
+        let store = IdentityStore::unlock(storage, b"passphrase").unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get("bob"), Some(&identity(b"bob")));
+        assert_eq!(store.get("carol"), None);
+        let labels: Vec<_> = store.iter().map(|(label, _)| label).collect();
+        assert_eq!(labels, ["alice", "bob"]);
+
+        let exported = store.export();
+        assert_eq!(exported[0].label, "alice");
+        assert_eq!(exported[0].identity(), identity(b"alice"));
+        let json = serde_json::to_string(&exported).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<ExportedIdentity>>(&json).unwrap(),
+            exported
+        );
+    }
+
+    #[test]
+    fn test_tampering() {
+        let mut store =
+            IdentityStore::create_with_params(Vec::new(), b"passphrase", PARAMS).unwrap();
+        store.insert("alice", &identity(b"alice")).unwrap();
+        store.insert("bob", &identity(b"bob")).unwrap();
+        let storage = store.into_storage();
+
+        let mut flipped = storage.clone();
+        flipped[HEADER_LEN + RECORD_LEN + NONCE_LEN] ^= 1;
+        assert!(matches!(
+            IdentityStore::unlock(flipped, b"passphrase"),
+            Err(StoreError::Corrupted)
+        ));
+
+        // Records are bound to their position
+        let mut swapped = storage[..HEADER_LEN].to_vec();
+        swapped.extend_from_slice(&storage[HEADER_LEN + RECORD_LEN..]);
+        swapped.extend_from_slice(&storage[HEADER_LEN..HEADER_LEN + RECORD_LEN]);
+        assert!(matches!(
+            IdentityStore::unlock(swapped, b"passphrase"),
+            Err(StoreError::Corrupted)
+        ));
+
+        let truncated = storage[..storage.len() - 1].to_vec();
+        assert!(matches!(
+            IdentityStore::unlock(truncated, b"passphrase"),
+            Err(StoreError::Corrupted)
+        ));
+        assert!(matches!(
+            IdentityStore::unlock(vec![0; HEADER_LEN], b"passphrase"),
+            Err(StoreError::NotAStore)
+        ));
+
+        // Costs are bounded before they are authenticated
+        let memory_start = MAGIC.len() + 1 + SALT_LEN;
+        let mut costly = storage.clone();
+        costly[memory_start..memory_start + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            IdentityStore::unlock(costly, b"passphrase"),
+            Err(StoreError::Corrupted)
+        ));
+        let params = KdfParams {
+            iterations: KdfParams::MAX_ITERATIONS + 1,
+            ..PARAMS
+        };
+        assert!(matches!(
+            IdentityStore::create_with_params(Vec::new(), b"passphrase", params),
+            Err(StoreError::ParamsTooCostly)
+        ));
+        assert!(matches!(
+            IdentityStore::create_with_params(storage, b"passphrase", PARAMS),
+            Err(StoreError::NotEmpty)
+        ));
+    }
+
+    #[test]
+    fn test_reopen_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let storage = MmapVec::create(file.reopen().unwrap()).unwrap();
+        let mut store = IdentityStore::create_with_params(storage, b"passphrase", PARAMS).unwrap();
+        store.insert("alice", &identity(b"alice")).unwrap();
+        drop(store);
+
+        let storage = MmapVec::restore(file.reopen().unwrap()).unwrap();
+        let mut store = IdentityStore::unlock(storage, b"passphrase").unwrap();
+        store.insert("bob", &identity(b"bob")).unwrap();
+        drop(store);
+
+        let storage = MmapVec::restore(file.reopen().unwrap()).unwrap();
+        let store = IdentityStore::unlock(storage, b"passphrase").unwrap();
+        assert_eq!(store.get("alice"), Some(&identity(b"alice")));
+        assert_eq!(store.get("bob"), Some(&identity(b"bob")));
+    }
+}
@@ -1,26 +1,65 @@
-#![doc = include_str!("../README.md")]
-
-mod circuit;
+// The README example generates a proof
+#![cfg_attr(feature = "prover", doc = include_str!("../README.md"))]
+
+#[cfg(all(
+    feature = "verifier",
+    not(any(
+        feature = "depth_16",
+        feature = "depth_20",
+        feature = "depth_21",
+        feature = "depth_30",
+        feature = "depth_32"
+    ))
+))]
+compile_error!(
+    "Verifying proofs requires at least one tree depth, enable one of the `depth_16`, \
+     `depth_20`, `depth_21`, `depth_30` or `depth_32` features. To only use identities and \
+     trees, disable the default features instead."
+);
+
+pub mod bridge;
+#[cfg(feature = "verifier")]
+pub mod circuit;
 mod field;
+pub mod group;
 pub mod hash;
 pub mod identity;
+pub mod nullifiers;
+#[cfg(feature = "onchain")]
+pub mod onchain;
+#[cfg(feature = "verifier")]
 pub mod packed_proof;
 pub mod poseidon_tree;
+#[cfg(feature = "verifier")]
 pub mod protocol;
+pub mod randomness;
+#[cfg(feature = "verifier")]
+pub mod test_vectors;
+pub mod tree_service;
+pub mod tree_sync;
 pub mod util;
 
+#[cfg(feature = "verifier")]
 use ark_bn254::Config;
+#[cfg(feature = "verifier")]
 use ark_ec::bn::Bn;
-pub use semaphore_depth_config::get_supported_depths;
+pub use semaphore_depth_config::{get_prover_depths, get_supported_depths};
 
 // Export types
-pub use crate::field::{hash_to_field, Field};
-
+#[allow(deprecated)]
+pub use crate::field::hash_to_field;
+pub use crate::field::{
+    hash_external_nullifier, hash_signal, hash_to_field_bytes, hash_to_field_with, Field,
+    FieldHashing,
+};
+
+#[cfg(feature = "verifier")]
 pub type Groth16Proof = ark_groth16::Proof<Bn<Config>>;
+#[cfg(feature = "verifier")]
 pub type EthereumGroth16Proof = ark_circom::ethereum::Proof;
 
 #[allow(dead_code)]
-#[cfg(test)]
+#[cfg(all(test, feature = "prover", not(feature = "external-artifacts")))]
 mod test {
     use std::thread::spawn;
 
@@ -29,13 +68,13 @@ mod test {
     use crate::identity::Identity;
     use crate::poseidon_tree::LazyPoseidonTree;
     use crate::protocol::{generate_nullifier_hash, generate_proof, verify_proof};
-    use crate::{hash_to_field, protocol, Field};
+    use crate::{hash_to_field_bytes, protocol, Field};
 
     #[test]
     fn test_field_serde() {
         let value = Field::from(0x1234_5678);
         let serialized = serde_json::to_value(value).unwrap();
-        let deserialized = serde_json::from_value(serialized).unwrap();
+        let deserialized: Field = serde_json::from_value(serialized).unwrap();
         assert_eq!(value, deserialized);
     }
 
@@ -57,8 +96,8 @@ mod test {
         let merkle_proof = tree.proof(0);
         let root = tree.root();
 
-        let signal_hash = hash_to_field(signal);
-        let external_nullifier_hash = hash_to_field(external_nullifier);
+        let signal_hash = hash_to_field_bytes(signal);
+        let external_nullifier_hash = hash_to_field_bytes(external_nullifier);
         let nullifier_hash = generate_nullifier_hash(&id, external_nullifier_hash);
 
         let proof =
@@ -78,12 +117,12 @@ mod test {
         }
     }
 
-    #[test_all_depths]
+    #[test_all_depths(prover)]
     fn test_auth_flow(depth: usize) {
         let mut secret = *b"oh so secret";
         let id = Identity::from_secret(&mut secret[..], None);
-        let signal_hash = hash_to_field(b"signal");
-        let external_nullifier_hash = hash_to_field(b"appId");
+        let signal_hash = hash_to_field_bytes(b"signal");
+        let external_nullifier_hash = hash_to_field_bytes(b"appId");
         let nullifier_hash = generate_nullifier_hash(&id, external_nullifier_hash);
         let id_commitment = id.commitment();
 
@@ -107,14 +146,14 @@ mod test {
         assert!(success);
     }
 
-    #[test_all_depths]
+    #[test_all_depths(prover)]
     fn test_single(depth: usize) {
         // Note that rust will still run tests in parallel
         let mut hello = *b"hello";
         test_end_to_end(&mut hello, b"appId", b"xxx", depth);
     }
 
-    #[test_all_depths]
+    #[test_all_depths(prover)]
     fn test_parallel(depth: usize) {
         // Note that this does not guarantee a concurrency issue will be detected.
         // For that we need much more sophisticated static analysis tooling like
@@ -126,4 +165,13 @@ mod test {
         a.join().unwrap();
         b.join().unwrap();
     }
+
+    #[test_all_depths(async, prover)]
+    async fn test_spawn_blocking(depth: usize) {
+        // Async services prove on the blocking pool, proving takes seconds
+        let mut id = *b"hello";
+        tokio::task::spawn_blocking(move || test_end_to_end(&mut id, b"appId", b"xxx", depth))
+            .await
+            .unwrap();
+    }
 }
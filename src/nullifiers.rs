@@ -0,0 +1,380 @@
+//! A persistent set of nullifier hashes, to reject double signals.
+//!
+//! Verifying a proof doesn't tell whether its nullifier hash was used
+//! before, so verifiers have to keep the nullifier hashes they accepted.
+//! [`NullifierSet`] keeps them in a [`GenericStorage`], usually an
+//! [`MmapVec`] file, laid out as a header followed by records:
+//!
+//! ```markdown
+//! header: [magic, ttl, current epoch]
+//! record: [nullifier hash, epoch]
+//! ```
+//!
+//! Apps that rotate their external nullifier, e.g. one per day, can give
+//! every nullifier hash the epoch of its external nullifier and the set a
+//! time to live in epochs. Records of expired nullifier hashes are reused, so
+//! the storage only grows with the nullifier hashes of live epochs. Since the
+//! set can't tell whether a nullifier hash of an expired epoch was used,
+//! inserting one fails and the proof must be rejected.
+
+use std::collections::HashMap;
+
+use storage::{GenericStorage, MmapVec};
+use thiserror::Error;
+
+use crate::Field;
+
+const MAGIC: Field = Field::from_limbs([u64::from_be_bytes(*b"NULLISET"), 0, 0, 0]);
+
+const HEADER_LEN: usize = 3;
+const TTL_WORD: usize = 1;
+const EPOCH_WORD: usize = 2;
+const RECORD_LEN: usize = 2;
+
+/// Stored instead of an epoch for nullifier hashes that never expire, and
+/// instead of the time to live of sets without one.
+const NEVER: Field = Field::MAX;
+
+#[derive(Debug, Error)]
+pub enum NullifierError {
+    #[error("storage does not hold a nullifier set")]
+    NotASet,
+    #[error("nullifier set is corrupted")]
+    Corrupted,
+    #[error("storage is not empty")]
+    NotEmpty,
+    #[error("epoch {0} has expired")]
+    Expired(u64),
+    #[error("storage error: {0}")]
+    Storage(#[source] storage::StorageError),
+}
+
+/// A set of nullifier hashes kept in storage.
+///
+/// Inserts are written to the storage right away, so they survive the
+/// process crashing. Call [`NullifierSet::flush`] to make them durable.
+pub struct NullifierSet<S = MmapVec<Field>> {
+    storage: S,
+    ttl: Option<u64>,
+    epoch: u64,
+    /// Record index and epoch of every live nullifier hash.
+    live: HashMap<Field, (usize, Option<u64>)>,
+    /// Records of expired nullifier hashes, which are overwritten first.
+    free: Vec<usize>,
+}
+
+impl<S> std::fmt::Debug for NullifierSet<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NullifierSet")
+            .field("len", &self.live.len())
+            .field("ttl", &self.ttl)
+            .field("epoch", &self.epoch)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: GenericStorage<Field>> NullifierSet<S> {
+    /// Creates a set in empty storage, whose nullifier hashes never expire.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage is not empty or can't be written.
+    pub fn create(storage: S) -> Result<Self, NullifierError> {
+        Self::init(storage, None)
+    }
+
+    /// Creates a set in empty storage, whose nullifier hashes expire `ttl`
+    /// epochs after their own epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage is not empty or can't be written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ttl` is 0.
+    pub fn create_with_ttl(storage: S, ttl: u64) -> Result<Self, NullifierError> {
+        assert!(ttl > 0, "Nullifier hashes must live for at least one epoch");
+        Self::init(storage, Some(ttl))
+    }
+
+    fn init(mut storage: S, ttl: Option<u64>) -> Result<Self, NullifierError> {
+        if !storage.is_empty() {
+            return Err(NullifierError::NotEmpty);
+        }
+        storage
+            .try_extend_from_slice(&[MAGIC, encode_epoch(ttl), Field::ZERO])
+            .map_err(NullifierError::Storage)?;
+        storage.flush().map_err(NullifierError::Storage)?;
+
+        Ok(Self {
+            storage,
+            ttl,
+            epoch: 0,
+            live: HashMap::new(),
+            free: Vec::new(),
+        })
+    }
+
+    /// Restores a set from storage written by a previous set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage doesn't hold a valid set.
+    pub fn restore(storage: S) -> Result<Self, NullifierError> {
+        if storage.len() < HEADER_LEN || storage[0] != MAGIC {
+            return Err(NullifierError::NotASet);
+        }
+        if (storage.len() - HEADER_LEN) % RECORD_LEN != 0 {
+            return Err(NullifierError::Corrupted);
+        }
+        let ttl = decode_epoch(storage[TTL_WORD])?;
+        let epoch = decode_epoch(storage[EPOCH_WORD])?.ok_or(NullifierError::Corrupted)?;
+
+        let mut live = HashMap::new();
+        let mut free = Vec::new();
+        for (slot, record) in storage[HEADER_LEN..].chunks_exact(RECORD_LEN).enumerate() {
+            let nullifier_epoch = decode_epoch(record[1])?;
+            if is_expired(ttl, epoch, nullifier_epoch) {
+                free.push(slot);
+            } else if live.insert(record[0], (slot, nullifier_epoch)).is_some() {
+                return Err(NullifierError::Corrupted);
+            }
+        }
+
+        Ok(Self {
+            storage,
+            ttl,
+            epoch,
+            live,
+            free,
+        })
+    }
+
+    /// Inserts a nullifier hash that never expires. Returns `false` if it was
+    /// already in the set, in which case the signal is a double signal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage can't be written.
+    pub fn insert_if_absent(&mut self, nullifier_hash: Field) -> Result<bool, NullifierError> {
+        self.insert(nullifier_hash, None)
+    }
+
+    /// Inserts a nullifier hash of the given epoch. Returns `false` if it was
+    /// already in the set, in which case the signal is a double signal.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NullifierError::Expired`] if the epoch has expired, or
+    /// another error if the storage can't be written.
+    pub fn insert_if_absent_in_epoch(
+        &mut self,
+        nullifier_hash: Field,
+        epoch: u64,
+    ) -> Result<bool, NullifierError> {
+        self.insert(nullifier_hash, Some(epoch))
+    }
+
+    fn insert(
+        &mut self,
+        nullifier_hash: Field,
+        epoch: Option<u64>,
+    ) -> Result<bool, NullifierError> {
+        if is_expired(self.ttl, self.epoch, epoch) {
+            return Err(NullifierError::Expired(epoch.unwrap_or_default()));
+        }
+        if self.live.contains_key(&nullifier_hash) {
+            return Ok(false);
+        }
+
+        let record = [nullifier_hash, encode_epoch(epoch)];
+        let slot = if let Some(slot) = self.free.pop() {
+            let start = HEADER_LEN + slot * RECORD_LEN;
+            self.storage[start..start + RECORD_LEN].copy_from_slice(&record);
+            slot
+        } else {
+            let slot = (self.storage.len() - HEADER_LEN) / RECORD_LEN;
+            self.storage
+                .try_extend_from_slice(&record)
+                .map_err(NullifierError::Storage)?;
+            slot
+        };
+        self.live.insert(nullifier_hash, (slot, epoch));
+        Ok(true)
+    }
+
+    /// Moves the set to a later epoch, expiring the nullifier hashes that
+    /// have outlived their time to live. Returns the number of expired
+    /// nullifier hashes.
+    ///
+    /// Epochs only move forward, earlier epochs are ignored.
+    pub fn advance_epoch(&mut self, epoch: u64) -> usize {
+        if epoch <= self.epoch {
+            return 0;
+        }
+        self.epoch = epoch;
+        self.storage[EPOCH_WORD] = Field::from(epoch);
+
+        let (ttl, free) = (self.ttl, &mut self.free);
+        let expired = free.len();
+        self.live.retain(|_, &mut (slot, nullifier_epoch)| {
+            let keep = !is_expired(ttl, epoch, nullifier_epoch);
+            if !keep {
+                free.push(slot);
+            }
+            keep
+        });
+        free.len() - expired
+    }
+
+    #[must_use]
+    pub fn contains(&self, nullifier_hash: &Field) -> bool {
+        self.live.contains_key(nullifier_hash)
+    }
+
+    /// The number of live nullifier hashes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty()
+    }
+
+    #[must_use]
+    pub const fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// The number of epochs nullifier hashes live for, if they expire.
+    #[must_use]
+    pub const fn ttl(&self) -> Option<u64> {
+        self.ttl
+    }
+
+    /// Writes the set to durable storage.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails to flush.
+    pub fn flush(&self) -> Result<(), NullifierError> {
+        self.storage.flush().map_err(NullifierError::Storage)
+    }
+
+    #[must_use]
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+}
+
+fn is_expired(ttl: Option<u64>, epoch: u64, nullifier_epoch: Option<u64>) -> bool {
+    match (ttl, nullifier_epoch) {
+        (Some(ttl), Some(nullifier_epoch)) => nullifier_epoch.saturating_add(ttl) <= epoch,
+        _ => false,
+    }
+}
+
+fn encode_epoch(epoch: Option<u64>) -> Field {
+    epoch.map_or(NEVER, Field::from)
+}
+
+fn decode_epoch(value: Field) -> Result<Option<u64>, NullifierError> {
+    if value == NEVER {
+        return Ok(None);
+    }
+    u64::try_from(value)
+        .map(Some)
+        .map_err(|_| NullifierError::Corrupted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_restore() {
+        let mut set = NullifierSet::create(Vec::new()).unwrap();
+        assert!(set.insert_if_absent(Field::from(1)).unwrap());
+        assert!(set.insert_if_absent(Field::from(2)).unwrap());
+        assert!(!set.insert_if_absent(Field::from(1)).unwrap());
+        assert_eq!(set.len(), 2);
+
+        // Without a time to live nothing expires
+        assert_eq!(set.advance_epoch(100), 0);
+        assert!(set.insert_if_absent_in_epoch(Field::from(3), 0).unwrap());
+
+        let storage = set.into_storage();
+        assert_eq!(storage.len(), HEADER_LEN + 3 * RECORD_LEN);
+        let set = NullifierSet::restore(storage.clone()).unwrap();
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.epoch(), 100);
+        assert!(set.contains(&Field::from(3)));
+
+        assert!(matches!(
+            NullifierSet::restore(storage[..storage.len() - 1].to_vec()),
+            Err(NullifierError::Corrupted)
+        ));
+        assert!(matches!(
+            NullifierSet::restore(vec![Field::ZERO; HEADER_LEN]),
+            Err(NullifierError::NotASet)
+        ));
+        assert!(matches!(
+            NullifierSet::create(storage),
+            Err(NullifierError::NotEmpty)
+        ));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let mut set = NullifierSet::create_with_ttl(Vec::new(), 2).unwrap();
+        assert!(set.insert_if_absent_in_epoch(Field::from(1), 0).unwrap());
+        assert!(set.insert_if_absent_in_epoch(Field::from(2), 1).unwrap());
+        assert!(set.insert_if_absent(Field::from(3)).unwrap());
+
+        assert_eq!(set.advance_epoch(1), 0);
+        assert_eq!(set.advance_epoch(2), 1);
+        assert!(!set.contains(&Field::from(1)));
+        assert!(set.contains(&Field::from(2)));
+        assert!(matches!(
+            set.insert_if_absent_in_epoch(Field::from(1), 0),
+            Err(NullifierError::Expired(0))
+        ));
+        assert_eq!(set.advance_epoch(1), 0);
+        assert_eq!(set.epoch(), 2);
+
+        // The expired record is reused
+        assert!(set.insert_if_absent_in_epoch(Field::from(4), 2).unwrap());
+        let storage = set.into_storage();
+        assert_eq!(storage.len(), HEADER_LEN + 3 * RECORD_LEN);
+
+        let mut set = NullifierSet::restore(storage).unwrap();
+        assert_eq!(set.ttl(), Some(2));
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.advance_epoch(10), 2);
+        assert!(set.contains(&Field::from(3)));
+    }
+
+    #[test]
+    fn test_reopen_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let storage = MmapVec::create(file.reopen().unwrap()).unwrap();
+        let mut set = NullifierSet::create_with_ttl(storage, 1).unwrap();
+        assert!(set.insert_if_absent_in_epoch(Field::from(1), 0).unwrap());
+        set.flush().unwrap();
+        drop(set);
+
+        let storage = MmapVec::restore(file.reopen().unwrap()).unwrap();
+        let mut set = NullifierSet::restore(storage).unwrap();
+        assert!(!set.insert_if_absent_in_epoch(Field::from(1), 0).unwrap());
+        assert_eq!(set.advance_epoch(1), 1);
+        drop(set);
+
+        let storage = MmapVec::restore(file.reopen().unwrap()).unwrap();
+        let set = NullifierSet::restore(storage).unwrap();
+        assert!(set.is_empty());
+        assert_eq!(set.epoch(), 1);
+    }
+}
@@ -0,0 +1,328 @@
+//! Checking roots against the chain.
+//!
+//! A proof is only valid for a group if its root is one the group contract
+//! accepts: the latest root, or an earlier root that hasn't expired yet. The
+//! World ID identity manager and the bridged `WorldID` contracts expose this
+//! as `latestRoot()`, `rootHistory(uint256)`, the time a root was replaced,
+//! and `rootHistoryExpiry()`. [`RootReader`] reads them with `eth_call`s over
+//! JSON-RPC, so relayers can check proofs against the current chain state
+//! before submitting them.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ethabi::{decode, encode, short_signature, ParamType, Token};
+use ethers_core::types::{Address, U256};
+use reqwest::header::CONTENT_TYPE;
+use serde::Deserialize;
+use serde_json::json;
+use thiserror::Error;
+
+use crate::protocol::{verify_proof, Proof, ProofError};
+use crate::Field;
+
+#[derive(Error, Debug)]
+pub enum OnchainError {
+    #[error("RPC request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("invalid RPC response: {0}")]
+    InvalidResponse(String),
+    #[error("Error decoding return data: {0}")]
+    InvalidEncoding(#[from] ethabi::Error),
+    #[error("root {root:#x} is not valid on chain: {status:?}")]
+    InvalidRoot { root: Field, status: RootStatus },
+    #[error(transparent)]
+    Proof(#[from] ProofError),
+}
+
+/// State of a root in the root history of a contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RootStatus {
+    /// The current root of the tree.
+    Latest,
+    /// An earlier root, replaced at `replaced_at` and accepted until it
+    /// expires.
+    Valid { replaced_at: u64 },
+    /// An earlier root that is no longer accepted.
+    Expired { replaced_at: u64 },
+    /// A root the contract never had.
+    Unknown,
+}
+
+impl RootStatus {
+    /// The status of a root that is not the latest one, from its
+    /// `rootHistory` entry and the `rootHistoryExpiry` of the contract, at
+    /// the Unix time `now`.
+    #[must_use]
+    pub const fn from_history(replaced_at: u64, expiry: u64, now: u64) -> Self {
+        if replaced_at == 0 {
+            Self::Unknown
+        } else if now.saturating_sub(replaced_at) > expiry {
+            Self::Expired { replaced_at }
+        } else {
+            Self::Valid { replaced_at }
+        }
+    }
+
+    #[must_use]
+    pub const fn is_valid(&self) -> bool {
+        matches!(self, Self::Latest | Self::Valid { .. })
+    }
+}
+
+/// Reads the roots of a World ID or Semaphore group contract.
+#[derive(Clone, Debug)]
+pub struct RootReader {
+    client: reqwest::blocking::Client,
+    rpc_url: String,
+    contract: Address,
+}
+
+impl RootReader {
+    #[must_use]
+    pub fn new(rpc_url: impl Into<String>, contract: Address) -> Self {
+        Self {
+            client: reqwest::blocking::Client::new(),
+            rpc_url: rpc_url.into(),
+            contract,
+        }
+    }
+
+    /// The current root of the tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails or doesn't return an `uint256`.
+    pub fn latest_root(&self) -> Result<Field, OnchainError> {
+        let data = self.call(&latest_root_calldata())?;
+        decode_uint(&data).map(from_u256)
+    }
+
+    /// The time `root` was replaced by a newer root, or `None` if the
+    /// contract never had it. The latest root was not replaced yet, so it is
+    /// `None` too.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails or doesn't return an `uint128`.
+    pub fn root_history(&self, root: Field) -> Result<Option<u64>, OnchainError> {
+        let data = self.call(&root_history_calldata(root))?;
+        let replaced_at = decode_uint(&data)?;
+        if replaced_at > U256::from(u64::MAX) {
+            return Err(OnchainError::InvalidResponse(format!(
+                "timestamp {replaced_at} does not fit in 64 bits"
+            )));
+        }
+        Ok(Some(replaced_at.as_u64()).filter(|&time| time != 0))
+    }
+
+    /// How long, in seconds, replaced roots stay valid.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails or doesn't return an `uint256`.
+    pub fn root_history_expiry(&self) -> Result<u64, OnchainError> {
+        let data = self.call(&short_signature("rootHistoryExpiry", &[]))?;
+        let expiry = decode_uint(&data)?;
+        // Saturate, an expiry beyond 64 bits never expires in practice
+        Ok(if expiry > U256::from(u64::MAX) {
+            u64::MAX
+        } else {
+            expiry.as_u64()
+        })
+    }
+
+    /// The status of `root` at the Unix time `now`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the calls fails.
+    pub fn root_status(&self, root: Field, now: u64) -> Result<RootStatus, OnchainError> {
+        if self.latest_root()? == root {
+            return Ok(RootStatus::Latest);
+        }
+        let Some(replaced_at) = self.root_history(root)? else {
+            return Ok(RootStatus::Unknown);
+        };
+        Ok(RootStatus::from_history(
+            replaced_at,
+            self.root_history_expiry()?,
+            now,
+        ))
+    }
+
+    /// Calls the contract at the latest block and returns the return data.
+    fn call(&self, calldata: &[u8]) -> Result<Vec<u8>, OnchainError> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_call",
+            "params": [
+                {
+                    "to": format!("0x{}", hex::encode(self.contract.as_bytes())),
+                    "data": format!("0x{}", hex::encode(calldata)),
+                },
+                "latest",
+            ],
+        });
+        let response = self
+            .client
+            .post(&self.rpc_url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(request.to_string())
+            .send()?
+            .error_for_status()?;
+        parse_call_response(&response.text()?)
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<String>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+/// The return data of an `eth_call` JSON-RPC response.
+fn parse_call_response(body: &str) -> Result<Vec<u8>, OnchainError> {
+    let response: RpcResponse = serde_json::from_str(body)
+        .map_err(|error| OnchainError::InvalidResponse(error.to_string()))?;
+    if let Some(RpcError { code, message }) = response.error {
+        return Err(OnchainError::Rpc { code, message });
+    }
+    let result = response
+        .result
+        .ok_or_else(|| OnchainError::InvalidResponse("missing result".to_owned()))?;
+    hex::decode(result.trim_start_matches("0x"))
+        .map_err(|error| OnchainError::InvalidResponse(error.to_string()))
+}
+
+fn latest_root_calldata() -> Vec<u8> {
+    short_signature("latestRoot", &[]).to_vec()
+}
+
+fn root_history_calldata(root: Field) -> Vec<u8> {
+    let mut calldata = short_signature("rootHistory", &[ParamType::Uint(256)]).to_vec();
+    calldata.extend(encode(&[Token::Uint(to_u256(root))]));
+    calldata
+}
+
+fn decode_uint(data: &[u8]) -> Result<U256, OnchainError> {
+    match decode(&[ParamType::Uint(256)], data)?.as_slice() {
+        [Token::Uint(value)] => Ok(*value),
+        _ => Err(OnchainError::InvalidResponse(
+            "expected an uint256".to_owned(),
+        )),
+    }
+}
+
+fn to_u256(value: Field) -> U256 {
+    U256::from_big_endian(&value.to_be_bytes::<32>())
+}
+
+fn from_u256(value: U256) -> Field {
+    let mut bytes = [0; 32];
+    value.to_big_endian(&mut bytes);
+    Field::from_be_bytes(bytes)
+}
+
+/// Verifies a semaphore proof and checks that its root is valid on chain.
+///
+/// # Errors
+///
+/// Returns [`OnchainError::InvalidRoot`] if the contract doesn't accept the
+/// root, and other errors if reading the chain or verifying fails.
+pub fn verify_proof_with_onchain_root(
+    reader: &RootReader,
+    root: Field,
+    nullifier_hash: Field,
+    signal_hash: Field,
+    external_nullifier_hash: Field,
+    proof: &Proof,
+    tree_depth: usize,
+) -> Result<bool, OnchainError> {
+    // Verify first, it's local and rejects invalid proofs without any calls
+    if !verify_proof(
+        root,
+        nullifier_hash,
+        signal_hash,
+        external_nullifier_hash,
+        proof,
+        tree_depth,
+    )? {
+        return Ok(false);
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let status = reader.root_status(root, now)?;
+    if !status.is_valid() {
+        return Err(OnchainError::InvalidRoot { root, status });
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use ruint::uint;
+
+    use super::*;
+
+    #[test]
+    fn test_root_status() {
+        let day = 86_400;
+        assert_eq!(RootStatus::from_history(0, day, 1000), RootStatus::Unknown);
+        assert_eq!(
+            RootStatus::from_history(1000, day, 1000 + day),
+            RootStatus::Valid { replaced_at: 1000 }
+        );
+        assert_eq!(
+            RootStatus::from_history(1000, day, 1001 + day),
+            RootStatus::Expired { replaced_at: 1000 }
+        );
+        // Clock skew doesn't expire roots
+        assert!(RootStatus::from_history(1000, day, 0).is_valid());
+    }
+
+    #[test]
+    fn test_calldata() {
+        let root = uint!(0x12345_U256);
+        let calldata = root_history_calldata(root);
+        assert_eq!(calldata.len(), 4 + 32);
+        assert_eq!(
+            &calldata[..4],
+            &short_signature("rootHistory", &[ParamType::Uint(256)])
+        );
+        assert_eq!(from_u256(U256::from_big_endian(&calldata[4..])), root);
+        assert_eq!(latest_root_calldata().len(), 4);
+    }
+
+    #[test]
+    fn test_parse_call_response() {
+        let word = format!("0x{}", hex::encode(encode(&[Token::Uint(U256::from(42))])));
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "result": word }).to_string();
+        let data = parse_call_response(&body).unwrap();
+        assert_eq!(decode_uint(&data).unwrap(), U256::from(42));
+
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": { "code": -32000, "message": "execution reverted" },
+        })
+        .to_string();
+        assert!(matches!(
+            parse_call_response(&body),
+            Err(OnchainError::Rpc { code: -32000, .. })
+        ));
+
+        // Calls to addresses without code return no data
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "result": "0x" }).to_string();
+        assert!(decode_uint(&parse_call_response(&body).unwrap()).is_err());
+    }
+}
@@ -3,18 +3,88 @@ use std::{
     str::{from_utf8, FromStr},
 };
 
+use crate::protocol::compression::{
+    compress_proof, decompress_proof, CompressedProof, CompressionError,
+};
 use crate::protocol::Proof;
 use ethabi::{decode, encode, ParamType, Token};
 use ethers_core::types::U256;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
 
 use crate::util::{bytes_from_hex, bytes_to_hex, deserialize_bytes, serialize_bytes};
 
 /// A packed proof is a representation of the ZKP in a single attribute (as
 /// opposed to array of arrays) which is easier to transport
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+///
+/// Packed proofs are ordered lexicographically by their bytes, the same as
+/// the proofs they encode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct PackedProof(pub [u8; 256]);
 
+#[derive(Error, Debug)]
+pub enum AbiDecodeError {
+    #[error("invalid ABI encoding: {0}")]
+    Abi(#[from] ethabi::Error),
+    #[error("packed proof must be 256 bytes, got {0}")]
+    Length(usize),
+    #[error("ABI encoding is not canonical")]
+    NonCanonical,
+}
+
+impl PackedProof {
+    /// ABI-encodes the packed proof as a Solidity `bytes` value, like
+    /// `abi.encode(proof)` in a contract, e.g. for contracts taking the proof
+    /// as a single `bytes` argument.
+    ///
+    /// The encoding is the offset of the value, 32, its length, 256, then the
+    /// packed proof.
+    #[must_use]
+    pub fn abi_encode(&self) -> Vec<u8> {
+        encode(&[Token::Bytes(self.0.to_vec())])
+    }
+
+    /// Decodes a packed proof ABI-encoded as a Solidity `bytes` value, see
+    /// [`Self::abi_encode`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` is not the canonical encoding of 256 bytes,
+    /// e.g. if it has trailing bytes or non-zero padding.
+    pub fn abi_decode(data: &[u8]) -> Result<Self, AbiDecodeError> {
+        let bytes = decode(&[ParamType::Bytes], data)?
+            .pop()
+            .and_then(Token::into_bytes)
+            .expect("decoded a single bytes token");
+        let proof = Self(
+            bytes
+                .try_into()
+                .map_err(|bytes: Vec<u8>| AbiDecodeError::Length(bytes.len()))?,
+        );
+        // The decoder ignores trailing bytes and padding
+        if proof.abi_encode() != data {
+            return Err(AbiDecodeError::NonCanonical);
+        }
+        Ok(proof)
+    }
+}
+
+impl TryFrom<PackedProof> for CompressedProof {
+    type Error = CompressionError;
+
+    fn try_from(proof: PackedProof) -> Result<Self, Self::Error> {
+        compress_proof(proof.into())
+    }
+}
+
+impl TryFrom<CompressedProof> for PackedProof {
+    type Error = CompressionError;
+
+    fn try_from(proof: CompressedProof) -> Result<Self, Self::Error> {
+        decompress_proof(proof).map(Self::from)
+    }
+}
+
 impl From<Proof> for PackedProof {
     fn from(proof: Proof) -> Self {
         let tokens = Token::FixedArray(vec![
@@ -249,4 +319,81 @@ pub mod test {
         let packed_proof_str = "0x0";
         PackedProof::from_str(packed_proof_str).expect_err("parsing should fail");
     }
+
+    #[test]
+    fn test_abi_encoding() {
+        let proof = Proof(
+            (U256::from(1), U256::from(2)),
+            (
+                [U256::from(3), U256::from(4)],
+                [U256::from(5), U256::from(6)],
+            ),
+            (U256::from(7), U256::from(8)),
+        );
+        let packed_proof = PackedProof::from(proof);
+
+        let encoded = packed_proof.abi_encode();
+        assert_eq!(encoded.len(), 320);
+        assert_eq!(U256::from_big_endian(&encoded[..32]), U256::from(32));
+        assert_eq!(U256::from_big_endian(&encoded[32..64]), U256::from(256));
+        assert_eq!(encoded[64..], packed_proof.0);
+        assert_eq!(PackedProof::abi_decode(&encoded).unwrap(), packed_proof);
+
+        assert!(matches!(
+            PackedProof::abi_decode(&encoded[..319]),
+            Err(AbiDecodeError::Abi(_))
+        ));
+        let mut trailing = encoded.clone();
+        trailing.extend([0; 32]);
+        assert!(matches!(
+            PackedProof::abi_decode(&trailing),
+            Err(AbiDecodeError::NonCanonical)
+        ));
+        let short = encode(&[Token::Bytes(vec![1; 255])]);
+        assert!(matches!(
+            PackedProof::abi_decode(&short),
+            Err(AbiDecodeError::Length(255))
+        ));
+    }
+
+    #[test]
+    fn test_compressed_conversions() {
+        let packed_proof_str = "0x15c1fc6907219676890dfe147ee6f10b580c7881dddacb1567b3bcbfc513a54d233afda3efff43a7631990d2e79470abcbae3ccad4b920476e64745bfe97bb0a0c8c7d7434c382d590d601d951c29c8463d555867db70f9e84f7741c81c2e1e6241d2ddf1c9e6670a24109a0e9c915cd6e07d0248a384dd38d3c91e9b0419f5f0b23c5467a06eff56cc2c246ada1e7d5705afc4dc8b43fd5a6972c679a2019c5091ed6522f7924d3674d08966a008f947f9aa016a4100bb12f911326f3e1befd0acdf5a5996e00933206cbec48f3bbdcee2a4ca75f8db911c00001e5a05474872446d6f1c1506837392a30fdc73d66fd89f4e1b1a5d14b93e2ad0c5f7b777520";
+        let packed_proof = PackedProof::from_str(packed_proof_str).unwrap();
+
+        let compressed = CompressedProof::try_from(packed_proof).unwrap();
+        assert_eq!(compressed, compress_proof(packed_proof.into()).unwrap());
+        assert_eq!(PackedProof::try_from(compressed).unwrap(), packed_proof);
+
+        let invalid = PackedProof([0xff; 256]);
+        assert_eq!(
+            CompressedProof::try_from(invalid),
+            Err(CompressionError::NonCanonical)
+        );
+    }
+
+    #[test]
+    fn test_ordering() {
+        let proof = |words: [u64; 8]| {
+            let [a0, a1, b0, b1, b2, b3, c0, c1] = words.map(U256::from);
+            Proof((a0, a1), ([b0, b1], [b2, b3]), (c0, c1))
+        };
+        let mut proofs = vec![
+            proof([1, 2, 3, 4, 5, 6, 7, 8]),
+            proof([1, 2, 3, 4, 5, 6, 7, 1 << 40]),
+            proof([1, 2, 0, 4, 5, 6, 7, 8]),
+            proof([0, 9, 9, 9, 9, 9, 9, 9]),
+            proof([1, 2, 3, 4, 5, 6, 7, 8]),
+        ];
+
+        let mut packed: Vec<_> = proofs.iter().copied().map(PackedProof::from).collect();
+        packed.sort();
+        proofs.sort();
+        let expected: Vec<_> = proofs.iter().copied().map(PackedProof::from).collect();
+        assert_eq!(packed, expected);
+
+        proofs.dedup();
+        let unique: std::collections::HashSet<_> = packed.into_iter().collect();
+        assert_eq!(unique.len(), proofs.len());
+    }
 }
@@ -1,9 +1,462 @@
+//! Merkle tree types used by Semaphore.
+//!
+//! The `Semaphore*` aliases are generic over the [`Hasher`], so that indexers
+//! mirroring trees built with a different hash function (e.g. checkpointed
+//! Keccak trees) can reuse the same tree machinery and proof types. The
+//! Poseidon aliases are what the Semaphore circuits expect.
+//!
+//! Roots only match a deployment's if the tree uses the same depth and empty
+//! leaf. [`TreeConfig`] keeps these together, and
+//! [`TreeConfig::semaphore_v3_group`] derives the empty leaf of Semaphore v3
+//! groups. Check the parameters of other deployments against the initial
+//! root of their contract with [`TreeConfig::empty_root`].
+//!
+//! [`from_snapshot`] builds a tree in memory mapped storage from a file of
+//! identity commitments, e.g. to bootstrap a large tree.
+
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
 use hasher::Hasher;
+use keccak::keccak::Keccak256;
 use poseidon::Poseidon;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use storage::{GenericStorage, MmapVec};
+use thiserror::Error;
+use trees::cascading::CascadingMerkleTree;
 use trees::imt::MerkleTree;
-use trees::lazy::LazyMerkleTree;
+use trees::lazy::{Canonical, LazyMerkleTree};
+use trees::lean_imt::LeanIMT;
+
+use crate::field::MODULUS;
+use crate::{hash_to_field_bytes, Field};
+
+pub type SemaphoreTree<H> = MerkleTree<H>;
+pub type LazySemaphoreTree<H> = LazyMerkleTree<H>;
+pub type CascadingSemaphoreTree<H, S = Vec<<H as Hasher>::Hash>> = CascadingMerkleTree<H, S>;
+pub type SemaphoreBranch<H> = trees::Branch<<H as Hasher>::Hash>;
+/// Merkle proof of membership, not the `protocol::SemaphoreProof` bundle.
+pub type SemaphoreMerkleProof<H> = trees::Proof<H>;
+
+/// Tree of dynamic depth used by the Semaphore v4 contracts, see
+/// [`trees::lean_imt`].
+pub type LeanSemaphoreTree<H> = LeanIMT<H>;
+
+pub type PoseidonTree = SemaphoreTree<Poseidon>;
+pub type LazyPoseidonTree = LazySemaphoreTree<Poseidon>;
+pub type LeanPoseidonTree = LeanSemaphoreTree<Poseidon>;
+pub type Branch = SemaphoreBranch<Poseidon>;
+pub type Proof = SemaphoreMerkleProof<Poseidon>;
+
+/// Keccak-256 backed trees.
+///
+/// These can not be used to generate Semaphore proofs, since the circuits
+/// verify membership using Poseidon. They share all the tree and proof types
+/// with the Poseidon trees.
+pub type KeccakTree = SemaphoreTree<Keccak256>;
+pub type LazyKeccakTree = LazySemaphoreTree<Keccak256>;
+pub type KeccakBranch = SemaphoreBranch<Keccak256>;
+pub type KeccakProof = SemaphoreMerkleProof<Keccak256>;
+
+/// Dense prefix depth of trees built from a [`TreeConfig`], unless set with
+/// [`TreeConfig::with_dense_prefix`].
+pub const DEFAULT_DENSE_PREFIX: usize = 20;
+
+/// Parameters of a Poseidon tree.
+///
+/// The depth and empty leaf determine the roots and must match the
+/// deployment the tree mirrors. The dense prefix only affects how
+/// [`LazyPoseidonTree`]s store their nodes, see
+/// [`LazyMerkleTree::new_with_dense_prefix`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TreeConfig {
+    pub depth: usize,
+    pub empty_leaf: Field,
+    pub dense_prefix: usize,
+}
+
+impl TreeConfig {
+    /// Parameters of a tree with the given depth and empty leaf, with a
+    /// dense prefix of at most [`DEFAULT_DENSE_PREFIX`].
+    #[must_use]
+    pub const fn new(depth: usize, empty_leaf: Field) -> Self {
+        let dense_prefix = if depth < DEFAULT_DENSE_PREFIX {
+            depth
+        } else {
+            DEFAULT_DENSE_PREFIX
+        };
+        Self {
+            depth,
+            empty_leaf,
+            dense_prefix,
+        }
+    }
+
+    /// Parameters of the tree of a Semaphore v3 group, whose empty leaf is
+    /// derived from the group id like the `zeroValue` of the v3
+    /// `SemaphoreGroups` contract.
+    #[must_use]
+    pub fn semaphore_v3_group(group_id: Field, depth: usize) -> Self {
+        Self::new(depth, hash_to_field_bytes(&group_id.to_be_bytes::<32>()))
+    }
+
+    /// Sets the dense prefix depth.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefix is deeper than the tree.
+    #[must_use]
+    pub const fn with_dense_prefix(mut self, dense_prefix: usize) -> Self {
+        assert!(
+            dense_prefix <= self.depth,
+            "Dense prefix must not be deeper than the tree"
+        );
+        self.dense_prefix = dense_prefix;
+        self
+    }
+
+    /// Returns the root of the empty tree, e.g. to check the parameters
+    /// against the initial root of a contract.
+    #[must_use]
+    pub fn empty_root(&self) -> Field {
+        (0..self.depth).fold(self.empty_leaf, |hash, _| Poseidon::hash_node(&hash, &hash))
+    }
+
+    /// Creates an empty lazy tree, see [`LazyMerkleTree::derived`] for
+    /// versions that can be shared.
+    #[must_use]
+    pub fn lazy_tree(&self) -> LazyMerkleTree<Poseidon, Canonical> {
+        LazyPoseidonTree::new_with_dense_prefix(self.depth, self.dense_prefix, &self.empty_leaf)
+    }
+
+    /// Creates a lazy tree holding the given leaves. Leaves beyond the dense
+    /// prefix are inserted one by one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more leaves than the tree holds.
+    #[must_use]
+    pub fn lazy_tree_with_leaves(&self, leaves: &[Field]) -> LazyMerkleTree<Poseidon, Canonical> {
+        assert!(
+            leaves.len() <= 1 << self.depth,
+            "Tree of depth {} can't hold {} leaves",
+            self.depth,
+            leaves.len()
+        );
+        let prefix_len = 1 << self.dense_prefix;
+        let (dense, sparse) = leaves.split_at(leaves.len().min(prefix_len));
+        let tree = LazyPoseidonTree::new_with_dense_prefix_with_initial_values(
+            self.depth,
+            self.dense_prefix,
+            &self.empty_leaf,
+            dense,
+        );
+        sparse.iter().enumerate().fold(tree, |tree, (i, leaf)| {
+            tree.update_with_mutation(prefix_len + i, leaf)
+        })
+    }
+
+    /// Creates a cascading tree holding the given leaves in `storage`.
+    #[must_use]
+    pub fn cascading_tree_with_leaves<S>(
+        &self,
+        storage: S,
+        leaves: &[Field],
+    ) -> CascadingSemaphoreTree<Poseidon, S>
+    where
+        S: GenericStorage<Field>,
+    {
+        CascadingMerkleTree::new_with_leaves(storage, self.depth, &self.empty_leaf, leaves)
+    }
+}
+
+/// A Poseidon tree in memory mapped storage, as built by [`from_snapshot`].
+pub type MmapPoseidonTree = CascadingSemaphoreTree<Poseidon, MmapVec<Field>>;
+
+/// Number of commitments inserted into the tree at once by
+/// [`from_snapshot`].
+const SNAPSHOT_BATCH: usize = 1 << 16;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("storage error: {0}")]
+    Storage(#[source] storage::StorageError),
+    #[error("tree error: {0}")]
+    Tree(#[from] trees::TreeError),
+    #[error("line {line}: {value:?} is not an identity commitment")]
+    InvalidCommitment { line: usize, value: String },
+    #[error("snapshot has more than {0} commitments")]
+    TooManyCommitments(usize),
+    #[error("can't write manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+}
+
+/// Summary of a tree built by [`from_snapshot`], written next to its storage
+/// to check the tree against later.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub root: Field,
+    pub count: usize,
+    pub depth: usize,
+    pub empty_leaf: Field,
+    pub dense_prefix: usize,
+    /// Hex encoded SHA-256 of the snapshot file.
+    pub snapshot_sha256: String,
+}
+
+impl SnapshotManifest {
+    /// Returns the path of the manifest of a tree stored at `storage_path`,
+    /// which is the storage path with `.manifest.json` appended.
+    #[must_use]
+    pub fn path(storage_path: &Path) -> PathBuf {
+        let mut path = storage_path.as_os_str().to_owned();
+        path.push(".manifest.json");
+        PathBuf::from(path)
+    }
+
+    /// Returns the parameters the tree was built with.
+    #[must_use]
+    pub const fn config(&self) -> TreeConfig {
+        TreeConfig {
+            depth: self.depth,
+            empty_leaf: self.empty_leaf,
+            dense_prefix: self.dense_prefix,
+        }
+    }
+}
+
+/// Builds a tree from a snapshot of identity commitments, storing it in a
+/// new file at `storage_path`, and writes its [`SnapshotManifest`] to
+/// [`SnapshotManifest::path`].
+///
+/// The snapshot is read line by line, so it doesn't have to fit in memory.
+/// Commitments are inserted in the order of the file, one per line, either as
+/// CSV with the commitment in the first column, or as JSON lines of
+/// commitments or of objects with a `commitment` field. Commitments can be
+/// decimal or `0x` prefixed hexadecimal. Empty lines are skipped, and so is
+/// the first line of a CSV file if its first column starts with a letter,
+/// like the header `commitment,index`.
+///
+/// Cascading trees have no sparse levels, so the dense prefix of `config`
+/// doesn't change how the tree is stored. It is recorded in the manifest, so
+/// that [`SnapshotManifest::config`] builds lazy trees of the snapshot with
+/// the same prefix.
+///
+/// # Errors
+///
+/// Returns an error if reading the snapshot or writing the tree or manifest
+/// fails, or a line is not a commitment in the field, or there are more
+/// commitments than the tree holds.
+pub fn from_snapshot(
+    mut reader: impl BufRead,
+    config: &TreeConfig,
+    storage_path: impl AsRef<Path>,
+) -> Result<(MmapPoseidonTree, SnapshotManifest), SnapshotError> {
+    let storage_path = storage_path.as_ref();
+    let storage = MmapVec::create_from_path(storage_path).map_err(SnapshotError::Storage)?;
+    let mut tree = CascadingMerkleTree::new(storage, config.depth, &config.empty_leaf);
+
+    let capacity = 1 << config.depth;
+    let mut hasher = Sha256::new();
+    let mut batch = Vec::with_capacity(SNAPSHOT_BATCH);
+    let mut count = 0;
+    let mut line = String::new();
+    for number in 1.. {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        hasher.update(line.as_bytes());
+        let Some(commitment) = parse_snapshot_line(&line, number)? else {
+            continue;
+        };
+        if count == capacity {
+            return Err(SnapshotError::TooManyCommitments(capacity));
+        }
+        count += 1;
+        batch.push(commitment);
+        if batch.len() == SNAPSHOT_BATCH {
+            tree.extend_from_slice(&batch)?;
+            batch.clear();
+        }
+    }
+    tree.extend_from_slice(&batch)?;
+    tree.flush()?;
+
+    let manifest = SnapshotManifest {
+        root: tree.root(),
+        count,
+        depth: config.depth,
+        empty_leaf: config.empty_leaf,
+        dense_prefix: config.dense_prefix,
+        snapshot_sha256: hex::encode(hasher.finalize()),
+    };
+    std::fs::write(
+        SnapshotManifest::path(storage_path),
+        serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    Ok((tree, manifest))
+}
+
+/// A line of a JSON lines snapshot.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SnapshotEntry {
+    Commitment(Field),
+    Object { commitment: Field },
+}
+
+/// Parses a line of a snapshot, returning `None` for empty lines and the CSV
+/// header.
+fn parse_snapshot_line(line: &str, number: usize) -> Result<Option<Field>, SnapshotError> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || SnapshotError::InvalidCommitment {
+        line: number,
+        value: line.to_owned(),
+    };
+    let commitment = if line.starts_with(['{', '"']) {
+        match serde_json::from_str(line).map_err(|_| invalid())? {
+            SnapshotEntry::Commitment(commitment) | SnapshotEntry::Object { commitment } => {
+                commitment
+            }
+        }
+    } else {
+        let column = line.split(',').next().unwrap_or_default().trim();
+        if number == 1 && column.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Ok(None);
+        }
+        column.parse::<Field>().map_err(|_| invalid())?
+    };
+    if commitment >= MODULUS {
+        return Err(invalid());
+    }
+    Ok(Some(commitment))
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_keccak_trees_agree() {
+        let empty = [0; 32];
+        let leaf = hex!("0000000000000000000000000000000000000000000000000000000000000001");
+
+        let mut tree = KeccakTree::new(2, empty);
+        assert_eq!(
+            tree.root(),
+            hex!("b4c11951957c6f8f642c4af61cd6b24640fec6dc7fc607ee8206a99e92410d30")
+        );
+        tree.set(1, leaf);
+
+        let lazy = LazyKeccakTree::new(2, empty).update(1, &leaf);
+        assert_eq!(tree.root(), lazy.root());
+
+        let proof: KeccakProof = lazy.proof(1);
+        assert!(tree.verify(leaf, &proof));
+    }
+
+    #[test]
+    fn test_tree_config() {
+        let config = TreeConfig::semaphore_v3_group(Field::from(42), 10).with_dense_prefix(4);
+        assert_eq!(
+            config.empty_leaf,
+            hash_to_field_bytes(&Field::from(42).to_be_bytes::<32>())
+        );
+        assert_eq!(config.lazy_tree().root(), config.empty_root());
+        assert_eq!(
+            PoseidonTree::new(10, config.empty_leaf).root(),
+            config.empty_root()
+        );
+
+        let leaves: Vec<_> = (1..=20).map(Field::from).collect();
+        let lazy = config.lazy_tree_with_leaves(&leaves);
+        let cascading = config.cascading_tree_with_leaves(vec![], &leaves);
+        assert_eq!(lazy.root(), cascading.root());
+        let zero = TreeConfig::new(10, Field::ZERO);
+        assert_ne!(lazy.root(), zero.lazy_tree_with_leaves(&leaves).root());
+        assert_eq!(
+            TreeConfig::new(30, Field::ZERO).dense_prefix,
+            DEFAULT_DENSE_PREFIX
+        );
+    }
+
+    #[test]
+    fn test_from_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = TreeConfig::new(10, Field::ZERO).with_dense_prefix(4);
+        let leaves: Vec<_> = (1..=100_u64).map(Field::from).collect();
+        let expected = config.lazy_tree_with_leaves(&leaves).root();
+
+        let csv: String = leaves
+            .iter()
+            .enumerate()
+            .map(|(i, leaf)| format!("{leaf},{i}\n"))
+            .collect();
+        let csv = format!("commitment,index\n{csv}");
+        let path = dir.path().join("csv.bin");
+        let (tree, manifest) = from_snapshot(csv.as_bytes(), &config, &path).unwrap();
+        assert_eq!(tree.root(), expected);
+        assert_eq!(manifest.count, 100);
+        assert_eq!(manifest.config(), config);
+        assert_eq!(manifest.root, expected);
+        assert_eq!(
+            manifest.snapshot_sha256,
+            hex::encode(Sha256::digest(csv.as_bytes()))
+        );
+        let written: SnapshotManifest =
+            serde_json::from_slice(&std::fs::read(SnapshotManifest::path(&path)).unwrap()).unwrap();
+        assert_eq!(written, manifest);
+
+        let json_lines: String = leaves
+            .iter()
+            .enumerate()
+            .map(|(i, leaf)| {
+                if i % 2 == 0 {
+                    format!("\"{leaf:#x}\"\n\n")
+                } else {
+                    format!("{{\"commitment\": \"{leaf}\"}}\n")
+                }
+            })
+            .collect();
+        let path = dir.path().join("json.bin");
+        let (tree, _) = from_snapshot(json_lines.as_bytes(), &config, &path).unwrap();
+        assert_eq!(tree.root(), expected);
 
-pub type PoseidonTree = MerkleTree<Poseidon>;
-pub type LazyPoseidonTree = LazyMerkleTree<Poseidon>;
-pub type Branch = trees::Branch<<Poseidon as Hasher>::Hash>;
-pub type Proof = trees::Proof<Poseidon>;
+        let path = dir.path().join("invalid.bin");
+        let err = from_snapshot("1\n2\nx\n".as_bytes(), &config, &path)
+            .map(drop)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SnapshotError::InvalidCommitment { line: 3, .. }
+        ));
+        // Only a first line starting with a letter is a header
+        for first in ["12x", "0xzz", "-1", "commitment,index\ncommitment"] {
+            let err = from_snapshot(format!("{first}\n1\n").as_bytes(), &config, &path)
+                .map(drop)
+                .unwrap_err();
+            assert!(matches!(err, SnapshotError::InvalidCommitment { .. }));
+        }
+        let err = from_snapshot(format!("{MODULUS}\n").as_bytes(), &config, &path)
+            .map(drop)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SnapshotError::InvalidCommitment { line: 1, .. }
+        ));
+        let small = TreeConfig::new(1, Field::ZERO);
+        let err = from_snapshot("1\n2\n3\n".as_bytes(), &small, &path)
+            .map(drop)
+            .unwrap_err();
+        assert!(matches!(err, SnapshotError::TooManyCommitments(2)));
+    }
+}
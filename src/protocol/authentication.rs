@@ -1,20 +1,149 @@
+//! Proofs of owning an identity, without a group.
+//!
+//! The identity is proven to be the only leaf of an otherwise empty tree, so
+//! the verifier only needs the identity commitment. Proofs use the regular
+//! Semaphore circuit of the given depth, which is why [`verifying_key`] is
+//! the key of that circuit and proofs can be verified on-chain by the
+//! Groth16 verifier generated for it, see [`verify_proof_calldata`].
+
+use ark_bn254::{Bn254, Fq, Fq2, G1Affine, G2Affine};
+use ark_ff::{BigInteger, PrimeField};
+use ark_groth16::VerifyingKey;
+use ethabi::{encode, short_signature, ParamType, Token};
+use ethers_core::types::U256;
+#[cfg(feature = "prover")]
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "prover")]
+use super::compression::compress_proof;
+use super::compression::{decompress_proof, CompressedProof};
+use super::{check_depth, G1, G2};
+use crate::circuit;
+#[cfg(feature = "prover")]
+use crate::identity::Identity;
+#[cfg(feature = "prover")]
+use crate::randomness;
 use crate::{
-    identity::Identity,
     poseidon_tree::LazyPoseidonTree,
     protocol::{Proof, ProofError},
     Field,
 };
 
+/// An authentication proof with its public inputs, see [`verify_proof`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthenticationProof {
+    pub depth: usize,
+    pub id_commitment: Field,
+    pub nullifier_hash: Field,
+    pub signal_hash: Field,
+    pub ext_nullifier_hash: Field,
+    pub proof: Proof,
+}
+
+impl AuthenticationProof {
+    /// Generates a proof of owning `identity` and bundles it with its public
+    /// inputs.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ProofError`] if proving fails.
+    #[cfg(feature = "prover")]
+    pub fn generate(
+        depth: usize,
+        identity: &Identity,
+        ext_nullifier_hash: Field,
+        signal_hash: Field,
+    ) -> Result<Self, ProofError> {
+        let proof = generate_proof(depth, identity, ext_nullifier_hash, signal_hash)?;
+        Ok(Self {
+            depth,
+            id_commitment: identity.commitment(),
+            nullifier_hash: super::generate_nullifier_hash(identity, ext_nullifier_hash),
+            signal_hash,
+            ext_nullifier_hash,
+            proof,
+        })
+    }
+
+    /// Verifies the proof against its public inputs.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ProofError`] if verifying fails.
+    pub fn verify(&self) -> Result<bool, ProofError> {
+        verify_proof(
+            self.depth,
+            self.id_commitment,
+            self.nullifier_hash,
+            self.signal_hash,
+            self.ext_nullifier_hash,
+            &self.proof,
+        )
+    }
+}
+
+/// Generates a proof of owning `identity`.
+///
+/// # Errors
+///
+/// Returns a [`ProofError`] if proving fails.
+#[cfg(feature = "prover")]
 pub fn generate_proof(
     depth: usize,
     identity: &Identity,
     ext_nullifier_hash: Field,
     signal_hash: Field,
+) -> Result<Proof, ProofError> {
+    generate_proof_rng(
+        depth,
+        identity,
+        ext_nullifier_hash,
+        signal_hash,
+        &mut randomness::rng(),
+    )
+}
+
+/// Generates a proof of owning `identity` from entropy.
+///
+/// # Errors
+///
+/// Returns a [`ProofError`] if proving fails.
+#[cfg(feature = "prover")]
+pub fn generate_proof_rng(
+    depth: usize,
+    identity: &Identity,
+    ext_nullifier_hash: Field,
+    signal_hash: Field,
+    rng: &mut impl Rng,
 ) -> Result<Proof, ProofError> {
     let merkle_proof = LazyPoseidonTree::new(depth, Field::from(0))
         .update(0, &identity.commitment())
         .proof(0);
-    super::generate_proof(identity, &merkle_proof, ext_nullifier_hash, signal_hash)
+    super::generate_proof_rng(
+        identity,
+        &merkle_proof,
+        ext_nullifier_hash,
+        signal_hash,
+        rng,
+    )
+}
+
+/// Generates a compressed proof of owning `identity`, see
+/// [`super::compression`].
+///
+/// # Errors
+///
+/// Returns a [`ProofError`] if proving fails.
+#[cfg(feature = "prover")]
+pub fn generate_compressed_proof(
+    depth: usize,
+    identity: &Identity,
+    ext_nullifier_hash: Field,
+    signal_hash: Field,
+) -> Result<CompressedProof, ProofError> {
+    let proof = generate_proof(depth, identity, ext_nullifier_hash, signal_hash)?;
+    Ok(compress_proof(proof)?)
 }
 
 pub fn verify_proof(
@@ -25,11 +154,8 @@ pub fn verify_proof(
     ext_nullifier_hash: Field,
     proof: &Proof,
 ) -> Result<bool, ProofError> {
-    let root = LazyPoseidonTree::new(depth, Field::from(0))
-        .update(0, &id_commitment)
-        .root();
     super::verify_proof(
-        root,
+        root(depth, id_commitment),
         nullifier_hash,
         signal_hash,
         ext_nullifier_hash,
@@ -37,3 +163,252 @@ pub fn verify_proof(
         depth,
     )
 }
+
+/// Verifies a compressed authentication proof, see
+/// [`super::compression`].
+///
+/// # Errors
+///
+/// Returns a [`ProofError`] if decompressing or verifying fails.
+pub fn verify_compressed_proof(
+    depth: usize,
+    id_commitment: Field,
+    nullifier_hash: Field,
+    signal_hash: Field,
+    ext_nullifier_hash: Field,
+    proof: &CompressedProof,
+) -> Result<bool, ProofError> {
+    verify_proof(
+        depth,
+        id_commitment,
+        nullifier_hash,
+        signal_hash,
+        ext_nullifier_hash,
+        &decompress_proof(*proof)?,
+    )
+}
+
+/// The root that authentication proofs for `id_commitment` are verified
+/// against, i.e. the root of a tree holding only the commitment.
+#[must_use]
+pub fn root(depth: usize, id_commitment: Field) -> Field {
+    LazyPoseidonTree::new(depth, Field::from(0))
+        .update(0, &id_commitment)
+        .root()
+}
+
+/// The verifying key of authentication proofs for the given depth.
+///
+/// # Errors
+///
+/// Returns [`ProofError::UnsupportedDepth`] if there is no circuit for the
+/// depth.
+pub fn verifying_key(depth: usize) -> Result<VerifyingKey<Bn254>, ProofError> {
+    check_depth(depth)?;
+    Ok(circuit::verifying_key(depth).vk.clone())
+}
+
+/// A Groth16 verifying key as the constants of a Solidity verifier.
+///
+/// Coordinates are in Ethereum order, i.e. `G2` coordinates have the
+/// imaginary part first, like the points of a [`Proof`]. `ic` holds one
+/// point per public input, plus one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolidityVerifyingKey {
+    pub alpha: G1,
+    pub beta: G2,
+    pub gamma: G2,
+    pub delta: G2,
+    pub ic: Vec<G1>,
+}
+
+impl From<&VerifyingKey<Bn254>> for SolidityVerifyingKey {
+    fn from(vk: &VerifyingKey<Bn254>) -> Self {
+        Self {
+            alpha: g1_words(&vk.alpha_g1),
+            beta: g2_words(&vk.beta_g2),
+            gamma: g2_words(&vk.gamma_g2),
+            delta: g2_words(&vk.delta_g2),
+            ic: vk.gamma_abc_g1.iter().map(g1_words).collect(),
+        }
+    }
+}
+
+fn fq_word(value: &Fq) -> U256 {
+    U256::from_big_endian(&value.into_bigint().to_bytes_be())
+}
+
+fn fq2_words(value: &Fq2) -> [U256; 2] {
+    [fq_word(&value.c1), fq_word(&value.c0)]
+}
+
+/// Ethereum represents the point at infinity as all zeros.
+fn g1_words(point: &G1Affine) -> G1 {
+    if point.infinity {
+        (U256::zero(), U256::zero())
+    } else {
+        (fq_word(&point.x), fq_word(&point.y))
+    }
+}
+
+fn g2_words(point: &G2Affine) -> G2 {
+    if point.infinity {
+        ([U256::zero(); 2], [U256::zero(); 2])
+    } else {
+        (fq2_words(&point.x), fq2_words(&point.y))
+    }
+}
+
+/// Encodes a call of `verifyProof(uint256[2] a, uint256[2][2] b, uint256[2]
+/// c, uint256[4] input)` on the Groth16 verifier of the circuit, with the
+/// public inputs of an authentication proof for `id_commitment`.
+#[must_use]
+pub fn verify_proof_calldata(
+    depth: usize,
+    id_commitment: Field,
+    nullifier_hash: Field,
+    signal_hash: Field,
+    ext_nullifier_hash: Field,
+    proof: &Proof,
+) -> Vec<u8> {
+    let uint_array = |len| ParamType::FixedArray(Box::new(ParamType::Uint(256)), len);
+    let mut calldata = short_signature(
+        "verifyProof",
+        &[
+            uint_array(2),
+            ParamType::FixedArray(Box::new(uint_array(2)), 2),
+            uint_array(2),
+            uint_array(4),
+        ],
+    )
+    .to_vec();
+
+    let words =
+        |words: &[U256]| Token::FixedArray(words.iter().copied().map(Token::Uint).collect());
+    let Proof(a, b, c) = *proof;
+    let inputs = [
+        root(depth, id_commitment),
+        nullifier_hash,
+        signal_hash,
+        ext_nullifier_hash,
+    ]
+    .map(|input| U256::from_big_endian(&input.to_be_bytes::<32>()));
+    calldata.extend(encode(&[
+        words(&[a.0, a.1]),
+        Token::FixedArray(vec![words(&b.0), words(&b.1)]),
+        words(&[c.0, c.1]),
+        words(&inputs),
+    ]));
+    calldata
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_ec::AffineRepr;
+    #[cfg(all(feature = "prover", not(feature = "external-artifacts")))]
+    use rand::SeedableRng;
+    #[cfg(all(feature = "prover", not(feature = "external-artifacts")))]
+    use rand_chacha::ChaCha20Rng;
+    #[cfg(all(feature = "prover", not(feature = "external-artifacts")))]
+    use semaphore_depth_macros::test_all_depths;
+
+    use super::*;
+
+    #[cfg(all(feature = "prover", not(feature = "external-artifacts")))]
+    #[test_all_depths(prover)]
+    fn test_authentication(depth: usize) {
+        let mut secret = *b"secret";
+        let identity = Identity::from_secret(&mut secret, None);
+        let (ext_nullifier_hash, signal_hash) = (Field::from(7), Field::from(8));
+
+        let bundle =
+            AuthenticationProof::generate(depth, &identity, ext_nullifier_hash, signal_hash)
+                .unwrap();
+        assert!(bundle.verify().unwrap());
+        let json = serde_json::to_string(&bundle).unwrap();
+        let parsed: AuthenticationProof = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, bundle);
+        let mut tampered = parsed;
+        tampered.signal_hash = Field::from(9);
+        assert!(!tampered.verify().unwrap());
+
+        // The same entropy gives the same proof
+        let proof = |seed| {
+            let mut rng = ChaCha20Rng::seed_from_u64(seed);
+            generate_proof_rng(depth, &identity, ext_nullifier_hash, signal_hash, &mut rng).unwrap()
+        };
+        assert_eq!(proof(1), proof(1));
+        assert_ne!(proof(1), proof(2));
+
+        let compressed =
+            generate_compressed_proof(depth, &identity, ext_nullifier_hash, signal_hash).unwrap();
+        assert!(verify_compressed_proof(
+            depth,
+            identity.commitment(),
+            bundle.nullifier_hash,
+            signal_hash,
+            ext_nullifier_hash,
+            &compressed,
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_solidity_verifying_key() {
+        let vk = VerifyingKey::<Bn254> {
+            alpha_g1: G1Affine::generator(),
+            beta_g2: G2Affine::generator(),
+            gamma_g2: G2Affine::generator(),
+            delta_g2: G2Affine::zero(),
+            gamma_abc_g1: vec![G1Affine::generator(), G1Affine::zero()],
+        };
+        let words = SolidityVerifyingKey::from(&vk);
+
+        // The generators as used by the Ethereum precompiles
+        assert_eq!(words.alpha, (U256::from(1), U256::from(2)));
+        assert_eq!(
+            words.beta.0,
+            [
+                U256::from_dec_str(
+                    "11559732032986387107991004021392285783925812861821192530917403151452391805634"
+                )
+                .unwrap(),
+                U256::from_dec_str(
+                    "10857046999023057135944570762232829481370756359578518086990519993285655852781"
+                )
+                .unwrap(),
+            ]
+        );
+        assert_eq!(words.delta, ([U256::zero(); 2], [U256::zero(); 2]));
+        assert_eq!(words.ic.len(), 2);
+        assert_eq!(words.ic[1], (U256::zero(), U256::zero()));
+    }
+
+    #[test]
+    fn test_calldata() {
+        let word = |n: u64| U256::from(n);
+        let proof = Proof(
+            (word(1), word(2)),
+            ([word(3), word(4)], [word(5), word(6)]),
+            (word(7), word(8)),
+        );
+        let commitment = Field::from(42);
+        let calldata = verify_proof_calldata(
+            16,
+            commitment,
+            Field::from(9),
+            Field::from(10),
+            Field::from(11),
+            &proof,
+        );
+        assert_eq!(calldata.len(), 4 + 12 * 32);
+        let words: Vec<_> = calldata[4..]
+            .chunks(32)
+            .map(U256::from_big_endian)
+            .collect();
+        assert_eq!(words[..8], [1, 2, 3, 4, 5, 6, 7, 8].map(word));
+        let root = root(16, commitment);
+        assert_eq!(words[8], U256::from_big_endian(&root.to_be_bytes::<32>()));
+        assert_eq!(words[9..], [9, 10, 11].map(word));
+    }
+}
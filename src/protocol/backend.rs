@@ -0,0 +1,126 @@
+//! The proof system proofs are created and verified with.
+//!
+//! Witness generation and the protocol code are the same for every proof
+//! system with Groth16 proofs over BN254, only the prover and verifier
+//! differ. All proving and verification functions of [`protocol`] go through
+//! the global [`ProvingBackend`], so another implementation, e.g. one calling
+//! into rapidsnark over FFI, can be installed with [`set_backend`] to compare
+//! provers without touching the protocol code.
+//!
+//! The default is [`ArkworksBackend`], the Groth16 implementation of
+//! `arkworks`. [`ProverContext::with_backend`] uses a backend for the proofs
+//! of one context, e.g. to run two backends side by side.
+//!
+//! Proofs generated with [`ProverOptions::max_memory_bytes`] read the proving
+//! key a chunk at a time with `ark-zkey`, and don't go through the backend.
+//!
+//! [`protocol`]: super
+//! [`ProverContext::with_backend`]: super::context::ProverContext::with_backend
+//! [`ProverOptions::max_memory_bytes`]: super::ProverOptions::max_memory_bytes
+
+use ark_bn254::{Bn254, Fr};
+use ark_circom::CircomReduction;
+use ark_groth16::{Groth16, PreparedVerifyingKey, Proof as ArkProof};
+use once_cell::sync::OnceCell;
+
+use super::ProofError;
+#[cfg(feature = "prover")]
+use crate::circuit::ZKey;
+
+static BACKEND: OnceCell<Box<dyn ProvingBackend>> = OnceCell::new();
+
+/// A Groth16 prover and verifier for circom circuits over BN254.
+pub trait ProvingBackend: Send + Sync {
+    /// Creates a proof for the full assignment of the circuit's wires.
+    ///
+    /// `r` and `s` are the blinding scalars. Backends drawing their own
+    /// randomness can ignore them, at the cost of proofs from
+    /// [`generate_proof_deterministic`] no longer being deterministic.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if proving fails, see [`ProofError::Backend`] for
+    /// errors of backends other than the default.
+    ///
+    /// [`generate_proof_deterministic`]: super::generate_proof_deterministic
+    #[cfg(feature = "prover")]
+    fn prove(
+        &self,
+        zkey: &ZKey,
+        witness: &[Fr],
+        r: Fr,
+        s: Fr,
+    ) -> Result<ArkProof<Bn254>, ProofError>;
+
+    /// Verifies a proof against its public inputs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if verifying fails. This does not necessarily mean
+    /// the proof is incorrect.
+    fn verify(
+        &self,
+        pvk: &PreparedVerifyingKey<Bn254>,
+        proof: &ArkProof<Bn254>,
+        public_inputs: &[Fr],
+    ) -> Result<bool, ProofError>;
+}
+
+/// The Groth16 implementation of `arkworks`, the default backend.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ArkworksBackend;
+
+impl ProvingBackend for ArkworksBackend {
+    #[cfg(feature = "prover")]
+    fn prove(
+        &self,
+        zkey: &ZKey,
+        witness: &[Fr],
+        r: Fr,
+        s: Fr,
+    ) -> Result<ArkProof<Bn254>, ProofError> {
+        Ok(
+            Groth16::<_, CircomReduction>::create_proof_with_reduction_and_matrices(
+                &zkey.0,
+                r,
+                s,
+                &zkey.1,
+                zkey.1.num_instance_variables,
+                zkey.1.num_constraints,
+                witness,
+            )?,
+        )
+    }
+
+    fn verify(
+        &self,
+        pvk: &PreparedVerifyingKey<Bn254>,
+        proof: &ArkProof<Bn254>,
+        public_inputs: &[Fr],
+    ) -> Result<bool, ProofError> {
+        Ok(Groth16::<_, CircomReduction>::verify_proof(
+            pvk,
+            proof,
+            public_inputs,
+        )?)
+    }
+}
+
+/// Installs the global backend.
+///
+/// The backend can only be installed once, before the first proof is
+/// generated or verified, so all proofs of the process use the same one.
+///
+/// # Errors
+///
+/// Returns the backend back if a backend was already installed or the
+/// default one was already used.
+pub fn set_backend(backend: Box<dyn ProvingBackend>) -> Result<(), Box<dyn ProvingBackend>> {
+    BACKEND.set(backend)
+}
+
+/// Returns the global backend, see [`set_backend`].
+#[must_use]
+pub fn backend() -> &'static dyn ProvingBackend {
+    BACKEND.get_or_init(|| Box::new(ArkworksBackend)).as_ref()
+}
@@ -0,0 +1,420 @@
+//! Proofs bundled with their public inputs.
+//!
+//! [`SemaphoreProof`] serializes like the `SemaphoreProof` objects of the
+//! Semaphore v3 JavaScript SDK, with numbers as decimal strings and the proof
+//! as the 8 words of a packed proof, so services can pass proofs between the
+//! SDK, HTTP APIs and contracts as one object.
+
+use ethabi::{encode, short_signature, ParamType, Token};
+use ethers_core::types::U256;
+#[cfg(feature = "prover")]
+use poseidon::Poseidon;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "prover")]
+use storage::MmapVec;
+#[cfg(feature = "prover")]
+use trees::cascading::CascadingMerkleTree;
+#[cfg(feature = "prover")]
+use trees::lazy::{LazyMerkleTree, VersionMarker};
+
+#[cfg(feature = "prover")]
+use super::{check_prover_depth, generate_nullifier_hash, generate_proof, prover_depths};
+use super::{verify_proof, Proof, ProofError, Signal};
+#[cfg(feature = "prover")]
+use crate::group::Group;
+#[cfg(feature = "prover")]
+use crate::identity::Identity;
+use crate::Field;
+
+/// A proof with its public inputs and the depth of its tree.
+///
+/// The signal and the external nullifier are the raw `uint256` values, the
+/// proof is for their hashes `hashToField(abi.encodePacked(value))`, as the
+/// Semaphore contracts compute them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemaphoreProof {
+    pub merkle_tree_depth: usize,
+    #[serde(with = "decimal")]
+    pub merkle_tree_root: Field,
+    #[serde(with = "decimal", alias = "nullifier")]
+    pub nullifier_hash: Field,
+    #[serde(with = "decimal")]
+    pub signal: Field,
+    #[serde(with = "decimal")]
+    pub external_nullifier: Field,
+    #[serde(
+        serialize_with = "serialize_proof",
+        deserialize_with = "deserialize_proof"
+    )]
+    pub proof: Proof,
+}
+
+impl SemaphoreProof {
+    /// Generates a proof of membership of `identity` for the given signal
+    /// and external nullifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ProofError`] if proving fails.
+    #[cfg(feature = "prover")]
+    pub fn generate(
+        identity: &Identity,
+        merkle_proof: &trees::Proof<Poseidon>,
+        external_nullifier: Field,
+        signal: Field,
+    ) -> Result<Self, ProofError> {
+        let external_nullifier_hash = Signal::Uint(external_nullifier).hash();
+        let proof = generate_proof(
+            identity,
+            merkle_proof,
+            external_nullifier_hash,
+            Signal::Uint(signal).hash(),
+        )?;
+        Ok(Self {
+            merkle_tree_depth: merkle_proof.0.len(),
+            merkle_tree_root: merkle_proof.root(identity.commitment()),
+            nullifier_hash: generate_nullifier_hash(identity, external_nullifier_hash),
+            signal,
+            external_nullifier,
+            proof,
+        })
+    }
+
+    #[must_use]
+    pub fn signal_hash(&self) -> Field {
+        Signal::Uint(self.signal).hash()
+    }
+
+    #[must_use]
+    pub fn external_nullifier_hash(&self) -> Field {
+        Signal::Uint(self.external_nullifier).hash()
+    }
+
+    /// Verifies the proof against its public inputs.
+    ///
+    /// This doesn't check that the root is one of the group, which only the
+    /// caller knows.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ProofError`] if verifying fails, e.g. when an input is not
+    /// a field element.
+    pub fn verify(&self) -> Result<bool, ProofError> {
+        verify_proof(
+            self.merkle_tree_root,
+            self.nullifier_hash,
+            self.signal_hash(),
+            self.external_nullifier_hash(),
+            &self.proof,
+            self.merkle_tree_depth,
+        )
+    }
+
+    /// Encodes a call of `verifyProof(uint256 groupId, uint256
+    /// merkleTreeRoot, uint256 signal, uint256 nullifierHash, uint256
+    /// externalNullifier, uint256[8] proof)` on the Semaphore v3 contract.
+    #[must_use]
+    pub fn verify_proof_calldata(&self, group_id: Field) -> Vec<u8> {
+        let mut calldata = short_signature(
+            "verifyProof",
+            &[
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::FixedArray(Box::new(ParamType::Uint(256)), 8),
+            ],
+        )
+        .to_vec();
+        calldata.extend(encode(&[
+            Token::Uint(to_u256(group_id)),
+            Token::Uint(to_u256(self.merkle_tree_root)),
+            Token::Uint(to_u256(self.signal)),
+            Token::Uint(to_u256(self.nullifier_hash)),
+            Token::Uint(to_u256(self.external_nullifier)),
+            Token::FixedArray(proof_words(&self.proof).map(Token::Uint).to_vec()),
+        ]));
+        calldata
+    }
+}
+
+/// A Poseidon tree of identity commitments, to generate proofs for its
+/// members with [`generate_proof_auto`].
+#[cfg(feature = "prover")]
+pub trait MembershipTree {
+    /// Returns the depth of the tree.
+    fn depth(&self) -> usize;
+
+    /// Returns the Merkle proof of the leaf at the given index, or `None` if
+    /// the tree has no such leaf.
+    fn merkle_proof(&self, leaf: usize) -> Option<trees::Proof<Poseidon>>;
+}
+
+#[cfg(feature = "prover")]
+impl<V: VersionMarker> MembershipTree for LazyMerkleTree<Poseidon, V> {
+    fn depth(&self) -> usize {
+        self.depth()
+    }
+
+    fn merkle_proof(&self, leaf: usize) -> Option<trees::Proof<Poseidon>> {
+        (leaf < 1 << self.depth()).then(|| self.proof(leaf))
+    }
+}
+
+#[cfg(feature = "prover")]
+impl MembershipTree for CascadingMerkleTree<Poseidon> {
+    fn depth(&self) -> usize {
+        self.depth()
+    }
+
+    fn merkle_proof(&self, leaf: usize) -> Option<trees::Proof<Poseidon>> {
+        (leaf < self.num_leaves()).then(|| self.proof(leaf))
+    }
+}
+
+#[cfg(feature = "prover")]
+impl MembershipTree for CascadingMerkleTree<Poseidon, MmapVec<Field>> {
+    fn depth(&self) -> usize {
+        self.depth()
+    }
+
+    fn merkle_proof(&self, leaf: usize) -> Option<trees::Proof<Poseidon>> {
+        (leaf < self.num_leaves()).then(|| self.proof(leaf))
+    }
+}
+
+#[cfg(feature = "prover")]
+impl MembershipTree for Group {
+    fn depth(&self) -> usize {
+        self.depth()
+    }
+
+    fn merkle_proof(&self, leaf: usize) -> Option<trees::Proof<Poseidon>> {
+        self.proof(leaf).ok()
+    }
+}
+
+/// Generates a proof of membership of `identity` at `leaf_index` of the
+/// tree, with the circuit of the depth of the tree.
+///
+/// This fetches the Merkle proof, checks there is a circuit with a witness
+/// graph for its depth and bundles the proof with its public inputs, see
+/// [`SemaphoreProof::generate`].
+///
+/// # Errors
+///
+/// Returns [`ProofError::NoCircuit`] if there is no circuit for the depth of
+/// the tree, [`ProofError::UnknownLeaf`] if the tree has no leaf at the
+/// index, or another [`ProofError`] if proving fails.
+#[cfg(feature = "prover")]
+pub fn generate_proof_auto(
+    identity: &Identity,
+    tree: &impl MembershipTree,
+    leaf_index: usize,
+    external_nullifier: Field,
+    signal: Field,
+) -> Result<SemaphoreProof, ProofError> {
+    let depth = tree.depth();
+    check_prover_depth(depth).map_err(|_| ProofError::NoCircuit {
+        depth,
+        supported: prover_depths().to_vec(),
+    })?;
+    let merkle_proof = tree
+        .merkle_proof(leaf_index)
+        .ok_or(ProofError::UnknownLeaf(leaf_index))?;
+    SemaphoreProof::generate(identity, &merkle_proof, external_nullifier, signal)
+}
+
+fn to_u256(value: Field) -> U256 {
+    U256::from_big_endian(&value.to_be_bytes::<32>())
+}
+
+/// The words of a packed proof, see [`crate::packed_proof::PackedProof`].
+fn proof_words(proof: &Proof) -> [U256; 8] {
+    let Proof(a, b, c) = *proof;
+    [a.0, a.1, b.0[0], b.0[1], b.1[0], b.1[1], c.0, c.1]
+}
+
+fn serialize_proof<S: Serializer>(proof: &Proof, serializer: S) -> Result<S::Ok, S::Error> {
+    proof_words(proof)
+        .map(|word| word.to_string())
+        .serialize(serializer)
+}
+
+fn deserialize_proof<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Proof, D::Error> {
+    let words = <[String; 8]>::deserialize(deserializer)?;
+    let mut parsed = [U256::zero(); 8];
+    for (word, string) in parsed.iter_mut().zip(&words) {
+        let value = string.parse::<Field>().map_err(D::Error::custom)?;
+        *word = to_u256(value);
+    }
+    let [a0, a1, b00, b01, b10, b11, c0, c1] = parsed;
+    Ok(Proof((a0, a1), ([b00, b01], [b10, b11]), (c0, c1)))
+}
+
+/// Numbers as decimal strings, like the JavaScript SDK. Hex strings with a
+/// `0x` prefix are accepted too.
+mod decimal {
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::Field;
+
+    pub fn serialize<S: Serializer>(value: &Field, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Field, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn bundle() -> SemaphoreProof {
+        let word = |n: u64| U256::from(n);
+        SemaphoreProof {
+            merkle_tree_depth: 16,
+            merkle_tree_root: Field::from(1),
+            nullifier_hash: Field::from(2),
+            signal: Field::from(3),
+            external_nullifier: Field::from(0x10),
+            proof: Proof(
+                (word(11), word(12)),
+                ([word(13), word(14)], [word(15), word(16)]),
+                (word(17), word(18)),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_serde() {
+        let bundle = bundle();
+        let json = serde_json::to_value(&bundle).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "merkleTreeDepth": 16,
+                "merkleTreeRoot": "1",
+                "nullifierHash": "2",
+                "signal": "3",
+                "externalNullifier": "16",
+                "proof": ["11", "12", "13", "14", "15", "16", "17", "18"],
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<SemaphoreProof>(json).unwrap(),
+            bundle
+        );
+
+        // Hex numbers and the v4 name of the nullifier are accepted
+        let parsed: SemaphoreProof = serde_json::from_value(json!({
+            "merkleTreeDepth": 16,
+            "merkleTreeRoot": "0x1",
+            "nullifier": "2",
+            "signal": "3",
+            "externalNullifier": "0x10",
+            "proof": ["11", "12", "13", "14", "15", "16", "17", "0x12"],
+        }))
+        .unwrap();
+        assert_eq!(parsed, bundle);
+    }
+
+    #[test]
+    fn test_hashes() {
+        let bundle = bundle();
+        assert_eq!(
+            bundle.signal_hash(),
+            crate::hash_to_field_bytes(&Field::from(3).to_be_bytes::<32>())
+        );
+        assert_eq!(
+            bundle.external_nullifier_hash(),
+            Signal::Uint(Field::from(0x10)).hash()
+        );
+    }
+
+    #[test]
+    fn test_calldata() {
+        let calldata = bundle().verify_proof_calldata(Field::from(42));
+        assert_eq!(calldata.len(), 4 + 13 * 32);
+        let word = |index: usize| U256::from_big_endian(&calldata[4 + index * 32..][..32]);
+        let words: Vec<_> = (0..13).map(word).collect();
+        assert_eq!(
+            words,
+            [42_u64, 1, 3, 2, 16, 11, 12, 13, 14, 15, 16, 17, 18].map(U256::from)
+        );
+    }
+
+    #[cfg(all(feature = "prover", not(feature = "external-artifacts")))]
+    #[test]
+    fn test_generate() {
+        use semaphore_depth_config::get_prover_depths;
+
+        use crate::poseidon_tree::LazyPoseidonTree;
+
+        let mut secret = *b"secret";
+        let identity = Identity::from_secret(&mut secret, None);
+        let depth = get_prover_depths()[0];
+        let tree = LazyPoseidonTree::new(depth, Field::from(0))
+            .derived()
+            .update(3, &identity.commitment());
+
+        let bundle =
+            SemaphoreProof::generate(&identity, &tree.proof(3), Field::from(7), Field::from(8))
+                .unwrap();
+        assert_eq!(bundle.merkle_tree_root, tree.root());
+        assert_eq!(bundle.merkle_tree_depth, depth);
+        assert!(bundle.verify().unwrap());
+
+        // The bundle survives the JSON round trip
+        let json = serde_json::to_string(&bundle).unwrap();
+        let parsed: SemaphoreProof = serde_json::from_str(&json).unwrap();
+        assert!(parsed.verify().unwrap());
+
+        let mut tampered = parsed;
+        tampered.signal = Field::from(9);
+        assert!(!tampered.verify().unwrap());
+    }
+
+    #[cfg(all(feature = "prover", not(feature = "external-artifacts")))]
+    #[test]
+    fn test_generate_auto() {
+        let mut secret = *b"secret";
+        let identity = Identity::from_secret(&mut secret, None);
+        let depth = prover_depths()[0];
+        let mut group = Group::new(Field::from(42), depth);
+        group.add_member(Field::from(1)).unwrap();
+        let index = group.add_member(identity.commitment()).unwrap();
+
+        let bundle =
+            generate_proof_auto(&identity, &group, index, Field::from(7), Field::from(8)).unwrap();
+        assert_eq!(bundle.merkle_tree_root, group.root());
+        assert_eq!(bundle.merkle_tree_depth, depth);
+        assert!(bundle.verify().unwrap());
+
+        assert!(matches!(
+            generate_proof_auto(&identity, &group, 2, Field::from(7), Field::from(8)),
+            Err(ProofError::UnknownLeaf(2))
+        ));
+        let unsupported = (1..)
+            .find(|depth| check_prover_depth(*depth).is_err())
+            .unwrap();
+        let tree = CascadingMerkleTree::<Poseidon>::new(vec![], unsupported, &Field::from(0));
+        match generate_proof_auto(&identity, &tree, 0, Field::from(7), Field::from(8)) {
+            Err(ProofError::NoCircuit { depth, supported }) => {
+                assert_eq!(depth, unsupported);
+                assert_eq!(supported, prover_depths());
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+}
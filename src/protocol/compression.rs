@@ -0,0 +1,550 @@
+//! Point compression for Groth16 proofs.
+//!
+//! A [`Proof`] holds three curve points as eight field elements. Since the
+//! `y` coordinate of a point is determined by its `x` coordinate up to sign,
+//! the proof can be represented with only four field elements and a sign bit
+//! per point, halving calldata costs.
+//!
+//! Coordinates are in Ethereum order, i.e. `G2` coordinates have the
+//! imaginary part first. The sign bit is stored in the least significant bit
+//! of the (shifted) first coordinate word:
+//!
+//! - `G1`: `x << 1 | sign(y)`
+//! - `G2`: `[x.c1 << 1 | sign(y), x.c0]`
+//!
+//! where `sign(y)` is set if `y` is lexicographically larger than `-y`, with
+//! the imaginary part compared first. Points at infinity, which Ethereum
+//! represents as all zeros, compress to all zeros. This is unambiguous
+//! because neither curve has a point with `x = 0`.
+//!
+//! Recovering the `y` coordinate of a `G2` point takes a field inversion.
+//! [`decompress_proofs`] shares a single inversion between all proofs of a
+//! batch, which makes decompressing many stored proofs much cheaper.
+
+use ark_bn254::{Fq, Fq2};
+use ark_ec::short_weierstrass::SWCurveConfig;
+use ark_ff::{batch_inversion_and_mul, BigInteger, Field, PrimeField, Zero};
+use ethers_core::types::U256;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{Proof, G1, G2};
+
+/// A Groth16 proof with compressed points, see the [module
+/// documentation](self) for the encoding.
+///
+/// Compressed proofs are ordered lexicographically by their words, in the
+/// order they are stored, i.e. by their big-endian encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CompressedProof(pub U256, pub [U256; 2], pub U256);
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionError {
+    #[error("coordinate is not a canonical field element")]
+    NonCanonical,
+    #[error("point is not on the curve")]
+    NotOnCurve,
+}
+
+/// Compresses the points of a proof.
+///
+/// # Errors
+///
+/// Returns an error if any coordinate is not reduced modulo the field
+/// modulus, or if a point is not on its curve.
+pub fn compress_proof(proof: Proof) -> Result<CompressedProof, CompressionError> {
+    let Proof(a, b, c) = proof;
+    Ok(CompressedProof(
+        compress_g1(a)?,
+        compress_g2(b)?,
+        compress_g1(c)?,
+    ))
+}
+
+/// Recovers the points of a compressed proof.
+///
+/// # Errors
+///
+/// Returns an error if a coordinate is not reduced modulo the field modulus,
+/// or if no point with the given `x` coordinate exists.
+pub fn decompress_proof(proof: CompressedProof) -> Result<Proof, CompressionError> {
+    let CompressedProof(a, b, c) = proof;
+    Ok(Proof(
+        decompress_g1(a)?,
+        decompress_g2(b)?,
+        decompress_g1(c)?,
+    ))
+}
+
+/// Compresses the points of many proofs, see [`compress_proof`].
+///
+/// Returns the result of every proof, in order, so one invalid proof doesn't
+/// fail the whole batch.
+#[must_use]
+pub fn compress_proofs(proofs: &[Proof]) -> Vec<Result<CompressedProof, CompressionError>> {
+    proofs.iter().copied().map(compress_proof).collect()
+}
+
+/// Recovers the points of many compressed proofs, see [`decompress_proof`].
+///
+/// The inversions of the `G2` square roots are batched with Montgomery's
+/// trick, so the batch only takes one inversion.
+///
+/// Returns the result of every proof, in order, so one invalid proof doesn't
+/// fail the whole batch.
+#[must_use]
+pub fn decompress_proofs(proofs: &[CompressedProof]) -> Vec<Result<Proof, CompressionError>> {
+    let recoveries: Vec<_> = proofs.iter().map(|proof| begin_g2(proof.1)).collect();
+    let mut inverses: Vec<Fq> = recoveries
+        .iter()
+        .map(|recovery| match recovery {
+            Ok(G2Recovery::Pending { c0, .. }) => *c0,
+            _ => Fq::zero(),
+        })
+        .collect();
+    // Zeros are skipped, and a zero c0 fails the check in `finish_g2`
+    batch_inversion_and_mul(&mut inverses, &two_inv());
+
+    proofs
+        .iter()
+        .zip(recoveries)
+        .zip(inverses)
+        .map(|((proof, b), half_c0_inv)| {
+            Ok(Proof(
+                decompress_g1(proof.0)?,
+                finish_g2(b?, half_c0_inv)?,
+                decompress_g1(proof.2)?,
+            ))
+        })
+        .collect()
+}
+
+fn compress_g1((x, y): G1) -> Result<U256, CompressionError> {
+    if x.is_zero() && y.is_zero() {
+        return Ok(U256::zero());
+    }
+
+    let x_fq = to_fq(x)?;
+    let y_fq = to_fq(y)?;
+    if y_fq.square() != g1_rhs(x_fq) {
+        return Err(CompressionError::NotOnCurve);
+    }
+
+    Ok(x << 1 | U256::from(u8::from(is_negative(y_fq))))
+}
+
+fn decompress_g1(compressed: U256) -> Result<G1, CompressionError> {
+    if compressed.is_zero() {
+        return Ok((U256::zero(), U256::zero()));
+    }
+
+    let negative = compressed.bit(0);
+    let x = to_fq(compressed >> 1)?;
+    let y = g1_rhs(x).sqrt().ok_or(CompressionError::NotOnCurve)?;
+    let y = if is_negative(y) == negative { y } else { -y };
+
+    Ok((from_fq(x), from_fq(y)))
+}
+
+fn compress_g2((x, y): G2) -> Result<[U256; 2], CompressionError> {
+    if x.iter().chain(&y).all(U256::is_zero) {
+        return Ok([U256::zero(); 2]);
+    }
+
+    let x_fq2 = to_fq2(x)?;
+    let y_fq2 = to_fq2(y)?;
+    if y_fq2.square() != g2_rhs(x_fq2) {
+        return Err(CompressionError::NotOnCurve);
+    }
+
+    let sign = U256::from(u8::from(is_negative2(y_fq2)));
+    Ok([x[0] << 1 | sign, x[1]])
+}
+
+fn decompress_g2(compressed: [U256; 2]) -> Result<G2, CompressionError> {
+    let recovery = begin_g2(compressed)?;
+    let half_c0_inv = match recovery {
+        G2Recovery::Pending { c0, .. } => c0.double().inverse().unwrap_or_default(),
+        _ => Fq::zero(),
+    };
+    finish_g2(recovery, half_c0_inv)
+}
+
+/// A `G2` point of [`decompress_proofs`] whose `y` coordinate is being
+/// recovered.
+enum G2Recovery {
+    Infinity,
+    Done {
+        x: Fq2,
+        y: Fq2,
+        negative: bool,
+    },
+    /// The real part of `y` is known, the imaginary part is `rhs.c1 / (2 *
+    /// c0)`, see the complex method of [`Fq2::sqrt`].
+    Pending {
+        x: Fq2,
+        rhs: Fq2,
+        c0: Fq,
+        negative: bool,
+    },
+}
+
+fn begin_g2(compressed: [U256; 2]) -> Result<G2Recovery, CompressionError> {
+    if compressed.iter().all(U256::is_zero) {
+        return Ok(G2Recovery::Infinity);
+    }
+
+    let negative = compressed[0].bit(0);
+    let x = to_fq2([compressed[0] >> 1, compressed[1]])?;
+    let rhs = g2_rhs(x);
+    if rhs.c1.is_zero() {
+        // No inversion needed
+        let y = rhs.sqrt().ok_or(CompressionError::NotOnCurve)?;
+        return Ok(G2Recovery::Done { x, y, negative });
+    }
+
+    let alpha = rhs.norm().sqrt().ok_or(CompressionError::NotOnCurve)?;
+    let mut delta = (alpha + rhs.c0) * two_inv();
+    if delta.legendre().is_qnr() {
+        delta -= alpha;
+    }
+    let c0 = delta.sqrt().ok_or(CompressionError::NotOnCurve)?;
+    Ok(G2Recovery::Pending {
+        x,
+        rhs,
+        c0,
+        negative,
+    })
+}
+
+/// Completes a recovery given `1 / (2 * c0)`.
+fn finish_g2(recovery: G2Recovery, half_c0_inv: Fq) -> Result<G2, CompressionError> {
+    let (x, y, negative) = match recovery {
+        G2Recovery::Infinity => return Ok(([U256::zero(); 2], [U256::zero(); 2])),
+        G2Recovery::Done { x, y, negative } => (x, y, negative),
+        G2Recovery::Pending {
+            x,
+            rhs,
+            c0,
+            negative,
+        } => {
+            let y = Fq2::new(c0, rhs.c1 * half_c0_inv);
+            if y.square() != rhs {
+                return Err(CompressionError::NotOnCurve);
+            }
+            (x, y, negative)
+        }
+    };
+    let y = if is_negative2(y) == negative { y } else { -y };
+
+    Ok((from_fq2(x), from_fq2(y)))
+}
+
+/// `1 / 2`, computed as `(p + 1) / 2` without an inversion.
+fn two_inv() -> Fq {
+    let mut two_inv = Fq::MODULUS;
+    two_inv.add_with_carry(&1_u64.into());
+    two_inv.div2();
+    Fq::from(two_inv)
+}
+
+fn g1_rhs(x: Fq) -> Fq {
+    x.square() * x + ark_bn254::g1::Config::COEFF_B
+}
+
+fn g2_rhs(x: Fq2) -> Fq2 {
+    x.square() * x + ark_bn254::g2::Config::COEFF_B
+}
+
+fn is_negative(y: Fq) -> bool {
+    y.into_bigint() > (-y).into_bigint()
+}
+
+fn is_negative2(y: Fq2) -> bool {
+    if y.c1.is_zero() {
+        is_negative(y.c0)
+    } else {
+        is_negative(y.c1)
+    }
+}
+
+fn to_fq(value: U256) -> Result<Fq, CompressionError> {
+    Fq::from_bigint(ark_ff::BigInt(value.0)).ok_or(CompressionError::NonCanonical)
+}
+
+fn from_fq(value: Fq) -> U256 {
+    U256(value.into_bigint().0)
+}
+
+/// Converts Ethereum ordered coordinates, imaginary part first.
+fn to_fq2([c1, c0]: [U256; 2]) -> Result<Fq2, CompressionError> {
+    Ok(Fq2::new(to_fq(c0)?, to_fq(c1)?))
+}
+
+fn from_fq2(value: Fq2) -> [U256; 2] {
+    [from_fq(value.c1), from_fq(value.c0)]
+}
+
+#[cfg(any(test, feature = "test-helpers"))]
+impl Proof {
+    /// A proof with all points at infinity.
+    ///
+    /// Never valid, but useful to exercise edge cases of serialization and
+    /// compression.
+    #[must_use]
+    pub fn dummy_infinity() -> Self {
+        Self(
+            (U256::zero(), U256::zero()),
+            ([U256::zero(); 2], [U256::zero(); 2]),
+            (U256::zero(), U256::zero()),
+        )
+    }
+
+    /// A proof of valid curve points derived from `seed`.
+    ///
+    /// The points are multiples of the generators, so they are in the correct
+    /// subgroups, but the proof does not verify.
+    #[must_use]
+    pub fn dummy_from_seed(seed: u64) -> Self {
+        use ark_bn254::{Fr, G1Projective, G2Projective};
+        use ark_ec::{CurveGroup, Group};
+
+        let scalar = |i: u64| Fr::from(seed) * Fr::from(3_u64) + Fr::from(i + 1);
+        let a = (G1Projective::generator() * scalar(0)).into_affine();
+        let b = (G2Projective::generator() * scalar(1)).into_affine();
+        let c = (G1Projective::generator() * scalar(2)).into_affine();
+
+        Self(
+            (from_fq(a.x), from_fq(a.y)),
+            (from_fq2(b.x), from_fq2(b.y)),
+            (from_fq(c.x), from_fq(c.y)),
+        )
+    }
+
+    /// Returns the proof with `B` replaced by its negation, flipping the sign
+    /// of its `y` coordinate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coordinates of `B` are not canonical.
+    #[must_use]
+    pub fn with_negated_b(self) -> Self {
+        let y = to_fq2(self.1 .1).expect("coordinates must be canonical");
+        Self(self.0, (self.1 .0, from_fq2(-y)), self.2)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn modulus() -> U256 {
+        U256(Fq::MODULUS.0)
+    }
+
+    fn roundtrip(proof: Proof) {
+        let compressed = compress_proof(proof).unwrap();
+        assert_eq!(decompress_proof(compressed).unwrap(), proof);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for seed in 0..32 {
+            roundtrip(Proof::dummy_from_seed(seed));
+        }
+    }
+
+    #[test]
+    fn test_infinity() {
+        let proof = Proof::dummy_infinity();
+        let compressed = compress_proof(proof).unwrap();
+        assert_eq!(
+            compressed,
+            CompressedProof(U256::zero(), [U256::zero(); 2], U256::zero())
+        );
+        roundtrip(proof);
+
+        // Mixed finite and infinite points
+        let finite = Proof::dummy_from_seed(1);
+        roundtrip(Proof(finite.0, Proof::dummy_infinity().1, finite.2));
+        roundtrip(Proof(Proof::dummy_infinity().0, finite.1, finite.2));
+    }
+
+    #[test]
+    fn test_both_signs() {
+        let mut signs_b = [false; 2];
+        let mut signs_a = [false; 2];
+        for seed in 0..32 {
+            let proof = Proof::dummy_from_seed(seed);
+            let negated = proof.with_negated_b();
+            assert_ne!(proof, negated);
+            roundtrip(negated);
+
+            let compressed = compress_proof(proof).unwrap();
+            let compressed_negated = compress_proof(negated).unwrap();
+            assert_eq!(compressed.1[1], compressed_negated.1[1]);
+            assert_ne!(compressed.1[0].bit(0), compressed_negated.1[0].bit(0));
+
+            signs_b[usize::from(compressed.1[0].bit(0))] = true;
+            signs_a[usize::from(compressed.0.bit(0))] = true;
+
+            let a = (proof.0 .0, from_fq(-to_fq(proof.0 .1).unwrap()));
+            roundtrip(Proof(a, proof.1, proof.2));
+        }
+        assert_eq!(signs_a, [true; 2]);
+        assert_eq!(signs_b, [true; 2]);
+    }
+
+    #[test]
+    fn test_non_canonical_coordinates() {
+        let proof = Proof::dummy_from_seed(7);
+
+        // Adding the modulus gives the same field element, but must not
+        // compress silently.
+        let unreduced_x = proof.0 .0 + modulus();
+        assert_eq!(
+            compress_proof(Proof((unreduced_x, proof.0 .1), proof.1, proof.2)),
+            Err(CompressionError::NonCanonical)
+        );
+        let unreduced_y = proof.2 .1 + modulus();
+        assert_eq!(
+            compress_proof(Proof(proof.0, proof.1, (proof.2 .0, unreduced_y))),
+            Err(CompressionError::NonCanonical)
+        );
+        let mut b = proof.1;
+        b.1[0] += modulus();
+        assert_eq!(
+            compress_proof(Proof(proof.0, b, proof.2)),
+            Err(CompressionError::NonCanonical)
+        );
+
+        let compressed = compress_proof(proof).unwrap();
+        let unreduced = (compressed.0 >> 1) + modulus();
+        assert_eq!(
+            decompress_proof(CompressedProof(
+                unreduced << 1 | U256::from(u8::from(compressed.0.bit(0))),
+                compressed.1,
+                compressed.2
+            )),
+            Err(CompressionError::NonCanonical)
+        );
+        assert_eq!(
+            decompress_proof(CompressedProof(
+                compressed.0,
+                [compressed.1[0], compressed.1[1] + modulus()],
+                compressed.2
+            )),
+            Err(CompressionError::NonCanonical)
+        );
+    }
+
+    #[test]
+    fn test_not_on_curve() {
+        let proof = Proof::dummy_from_seed(3);
+
+        let a = (proof.0 .0, proof.0 .1 ^ U256::one());
+        assert_eq!(
+            compress_proof(Proof(a, proof.1, proof.2)),
+            Err(CompressionError::NotOnCurve)
+        );
+        let mut b = proof.1;
+        b.1[1] ^= U256::one();
+        assert_eq!(
+            compress_proof(Proof(proof.0, b, proof.2)),
+            Err(CompressionError::NotOnCurve)
+        );
+
+        // Only one of the two possible infinity encodings is valid.
+        assert_eq!(
+            decompress_proof(CompressedProof(
+                U256::one(),
+                [U256::zero(); 2],
+                U256::zero()
+            )),
+            Err(CompressionError::NotOnCurve)
+        );
+        assert_eq!(
+            decompress_proof(CompressedProof(
+                U256::zero(),
+                [U256::one(), U256::zero()],
+                U256::zero()
+            )),
+            Err(CompressionError::NotOnCurve)
+        );
+
+        // Roughly half of all x coordinates have no point on the curve.
+        let invalid_x = (1_u64..)
+            .map(Fq::from)
+            .find(|x| g1_rhs(*x).sqrt().is_none())
+            .unwrap();
+        assert_eq!(
+            decompress_g1(from_fq(invalid_x) << 1),
+            Err(CompressionError::NotOnCurve)
+        );
+        let invalid_x2 = (1_u64..)
+            .map(|x| Fq2::new(Fq::from(x), Fq::from(x)))
+            .find(|x| g2_rhs(*x).sqrt().is_none())
+            .unwrap();
+        let [c1, c0] = from_fq2(invalid_x2);
+        assert_eq!(
+            decompress_g2([c1 << 1, c0]),
+            Err(CompressionError::NotOnCurve)
+        );
+    }
+
+    #[test]
+    fn test_no_point_with_zero_x() {
+        // Makes the all zero encoding of infinity unambiguous.
+        assert!(g1_rhs(Fq::zero()).sqrt().is_none());
+        assert!(g2_rhs(Fq2::zero()).sqrt().is_none());
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut proofs: Vec<_> = (0..32).map(Proof::dummy_from_seed).collect();
+        proofs.extend((0..8).map(|seed| Proof::dummy_from_seed(seed).with_negated_b()));
+        proofs.push(Proof::dummy_infinity());
+        let compressed: Vec<_> = compress_proofs(&proofs)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        for (proof, compressed) in proofs.iter().zip(&compressed) {
+            assert_eq!(compress_proof(*proof).unwrap(), *compressed);
+        }
+        let decompressed: Vec<_> = decompress_proofs(&compressed)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decompressed, proofs);
+
+        // Invalid proofs fail on their own
+        let mut batch = compressed[..3].to_vec();
+        batch[1].1[1] += modulus();
+        let invalid_x2 = (1_u64..)
+            .map(|x| Fq2::new(Fq::from(x), Fq::from(x)))
+            .find(|x| g2_rhs(*x).sqrt().is_none())
+            .unwrap();
+        let [c1, c0] = from_fq2(invalid_x2);
+        batch.push(CompressedProof(
+            compressed[0].0,
+            [c1 << 1, c0],
+            compressed[0].2,
+        ));
+        let results = decompress_proofs(&batch);
+        assert_eq!(results[0], Ok(proofs[0]));
+        assert_eq!(results[1], Err(CompressionError::NonCanonical));
+        assert_eq!(results[2], Ok(proofs[2]));
+        assert_eq!(results[3], Err(CompressionError::NotOnCurve));
+        assert!(decompress_proofs(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let compressed = compress_proof(Proof::dummy_from_seed(5)).unwrap();
+        let json = serde_json::to_string(&compressed).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CompressedProof>(&json).unwrap(),
+            compressed
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! Proving with checked circuit artifacts.
+//!
+//! A swapped or corrupted proving key creates proofs that verifiers reject,
+//! which is only noticed once they are submitted. [`ProverContext`] checks the
+//! proving key of a depth against the expected verifying key digest when it
+//! is created, and then keeps its artifacts loaded for the proofs it
+//! generates.
+
+use std::sync::Arc;
+
+use ark_std::UniformRand;
+use poseidon::Poseidon;
+use rand::Rng;
+use witness::Graph;
+
+use super::backend::{self, ProvingBackend};
+use super::{check_prover_depth, Proof, ProofError};
+use crate::circuit::{artifact_digest, ArtifactCache, ArtifactError, Circuit, ZKey};
+use crate::identity::Identity;
+use crate::randomness;
+use crate::Field;
+
+/// The checked proving key and witness graph of one depth.
+pub struct ProverContext {
+    depth: usize,
+    zkey: Arc<ZKey>,
+    graph: Arc<Graph>,
+    backend: Option<Arc<dyn ProvingBackend>>,
+}
+
+impl std::fmt::Debug for ProverContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProverContext")
+            .field("depth", &self.depth)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProverContext {
+    /// Loads the artifacts of the given depth and checks that the proving key
+    /// matches the built in verifying key, see [`artifact_digest`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the depth is not supported, has no witness graph,
+    /// see [`check_prover_depth`], or the proving key doesn't match.
+    pub fn new(depth: usize) -> Result<Self, ArtifactError> {
+        let expected = artifact_digest(depth).ok_or(ArtifactError::UnsupportedDepth(depth))?;
+        check_prover_depth(depth).map_err(|_| ArtifactError::UnsupportedDepth(depth))?;
+        Self::with_digest(depth, &expected)
+    }
+
+    /// Loads the artifacts of the given depth and checks that the
+    /// [`ark_zkey::vk_digest`] of the proving key is `expected`, e.g. for keys
+    /// inserted into the [`ArtifactCache`] for depths that are not built in.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the proving key doesn't match.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the artifacts are not cached and not built in.
+    pub fn with_digest(depth: usize, expected: &[u8; 32]) -> Result<Self, ArtifactError> {
+        let cache = ArtifactCache::global();
+        let zkey = cache.zkey(Circuit::V3, depth);
+        let actual = ark_zkey::vk_digest(&zkey.0.vk);
+        if actual != *expected {
+            return Err(ArtifactError::DigestMismatch {
+                depth,
+                expected: hex::encode(expected),
+                actual: hex::encode(actual),
+            });
+        }
+        Ok(Self {
+            depth,
+            zkey,
+            graph: cache.graph(Circuit::V3, depth),
+            backend: None,
+        })
+    }
+
+    /// Generates the proofs of the context with `backend` instead of the
+    /// global [`backend::backend`].
+    #[must_use]
+    pub fn with_backend(mut self, backend: Arc<dyn ProvingBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    #[must_use]
+    pub const fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Generates a semaphore proof, see [`super::generate_proof`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ProofError`] if the Merkle proof is not of the context's
+    /// depth or proving fails.
+    pub fn generate_proof(
+        &self,
+        identity: &Identity,
+        merkle_proof: &trees::Proof<Poseidon>,
+        external_nullifier_hash: Field,
+        signal_hash: Field,
+    ) -> Result<Proof, ProofError> {
+        self.generate_proof_rng(
+            identity,
+            merkle_proof,
+            external_nullifier_hash,
+            signal_hash,
+            &mut randomness::rng(),
+        )
+    }
+
+    /// Generates a semaphore proof from entropy, see
+    /// [`super::generate_proof_rng`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ProofError`] if the Merkle proof is not of the context's
+    /// depth or proving fails.
+    pub fn generate_proof_rng(
+        &self,
+        identity: &Identity,
+        merkle_proof: &trees::Proof<Poseidon>,
+        external_nullifier_hash: Field,
+        signal_hash: Field,
+        rng: &mut impl Rng,
+    ) -> Result<Proof, ProofError> {
+        if merkle_proof.0.len() != self.depth {
+            return Err(ProofError::DepthMismatch {
+                expected: self.depth,
+                actual: merkle_proof.0.len(),
+            });
+        }
+        let backend = match &self.backend {
+            Some(backend) => backend.as_ref(),
+            None => backend::backend(),
+        };
+        let (proof, _) = super::prove(
+            super::ProvingKey::Parsed(&self.zkey, backend),
+            &self.graph,
+            identity,
+            merkle_proof,
+            external_nullifier_hash,
+            signal_hash,
+            ark_bn254::Fr::rand(rng),
+            ark_bn254::Fr::rand(rng),
+            &mut super::WitnessScratch::new(),
+            &(),
+        )?;
+        Ok(proof)
+    }
+}
+
+#[cfg(all(test, not(feature = "external-artifacts")))]
+mod tests {
+    use rand::SeedableRng as _;
+    use rand_chacha::ChaChaRng;
+    use semaphore_depth_config::get_prover_depths;
+
+    use super::*;
+    use crate::hash_to_field_bytes;
+    use crate::poseidon_tree::LazyPoseidonTree;
+    use crate::protocol::{generate_nullifier_hash, generate_proof_rng, verify_proof};
+
+    #[test]
+    fn test_prover_context() {
+        let depth = get_prover_depths()[0];
+        let context = ProverContext::new(depth).unwrap();
+
+        let mut secret = *b"secret";
+        let id = Identity::from_secret(&mut secret, None);
+        let tree = LazyPoseidonTree::new(depth, Field::from(0))
+            .derived()
+            .update(0, &id.commitment());
+        let merkle_proof = tree.proof(0);
+        let external_nullifier_hash = hash_to_field_bytes(b"context");
+        let signal_hash = hash_to_field_bytes(b"signal");
+
+        // Same proofs as the protocol functions
+        let proof = context
+            .generate_proof_rng(
+                &id,
+                &merkle_proof,
+                external_nullifier_hash,
+                signal_hash,
+                &mut ChaChaRng::seed_from_u64(1),
+            )
+            .unwrap();
+        let expected = generate_proof_rng(
+            &id,
+            &merkle_proof,
+            external_nullifier_hash,
+            signal_hash,
+            &mut ChaChaRng::seed_from_u64(1),
+        )
+        .unwrap();
+        assert_eq!(proof, expected);
+        assert!(verify_proof(
+            tree.root(),
+            generate_nullifier_hash(&id, external_nullifier_hash),
+            signal_hash,
+            external_nullifier_hash,
+            &proof,
+            depth,
+        )
+        .unwrap());
+
+        let shallow = LazyPoseidonTree::new(depth - 1, Field::from(0)).proof(0);
+        assert!(matches!(
+            context.generate_proof(&id, &shallow, external_nullifier_hash, signal_hash),
+            Err(ProofError::DepthMismatch { .. })
+        ));
+    }
+
+    /// Counts the proofs created with the default backend
+    #[derive(Default)]
+    struct CountingBackend(std::sync::atomic::AtomicUsize);
+
+    impl ProvingBackend for CountingBackend {
+        fn prove(
+            &self,
+            zkey: &ZKey,
+            witness: &[ark_bn254::Fr],
+            r: ark_bn254::Fr,
+            s: ark_bn254::Fr,
+        ) -> Result<ark_groth16::Proof<ark_bn254::Bn254>, ProofError> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            backend::ArkworksBackend.prove(zkey, witness, r, s)
+        }
+
+        fn verify(
+            &self,
+            pvk: &ark_groth16::PreparedVerifyingKey<ark_bn254::Bn254>,
+            proof: &ark_groth16::Proof<ark_bn254::Bn254>,
+            public_inputs: &[ark_bn254::Fr],
+        ) -> Result<bool, ProofError> {
+            backend::ArkworksBackend.verify(pvk, proof, public_inputs)
+        }
+    }
+
+    #[test]
+    fn test_with_backend() {
+        let depth = get_prover_depths()[0];
+        let backend = Arc::new(CountingBackend::default());
+        let context = ProverContext::new(depth)
+            .unwrap()
+            .with_backend(backend.clone());
+
+        let mut secret = *b"secret";
+        let id = Identity::from_secret(&mut secret, None);
+        let tree = LazyPoseidonTree::new(depth, Field::from(0))
+            .derived()
+            .update(0, &id.commitment());
+        let external_nullifier_hash = hash_to_field_bytes(b"context");
+        let signal_hash = hash_to_field_bytes(b"signal");
+
+        let proof = context
+            .generate_proof(&id, &tree.proof(0), external_nullifier_hash, signal_hash)
+            .unwrap();
+        assert_eq!(backend.0.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert!(verify_proof(
+            tree.root(),
+            generate_nullifier_hash(&id, external_nullifier_hash),
+            signal_hash,
+            external_nullifier_hash,
+            &proof,
+            depth,
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_digest_mismatch() {
+        let depth = get_prover_depths()[0];
+        assert!(matches!(
+            ProverContext::with_digest(depth, &[0; 32]),
+            Err(ArtifactError::DigestMismatch { depth: d, .. }) if d == depth
+        ));
+        assert!(matches!(
+            ProverContext::new(99),
+            Err(ArtifactError::UnsupportedDepth(99))
+        ));
+    }
+}
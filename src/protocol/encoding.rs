@@ -0,0 +1,237 @@
+//! Canonical encodings of external nullifiers and signals.
+//!
+//! Contracts hash the external nullifier and the signal of a proof with
+//! `hashToField(abi.encodePacked(..))`, see [`hash_to_field_bytes`]. Hashing the
+//! same values with a different encoding, e.g. a number as a decimal string
+//! instead of an `uint256`, gives hashes the contract rejects. These types
+//! encode like the Solidity expressions in their docs.
+
+use ethers_core::types::Address;
+
+use crate::{hash_to_field_bytes, Field};
+
+/// Scope of the nullifiers of a proof, so an identity can only prove once
+/// per scope.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ExternalNullifier {
+    /// Arbitrary bytes, hashed as `hashToField(data)`.
+    Raw(Vec<u8>),
+    /// An action of an app, as used by World ID:
+    ///
+    /// `hashToField(abi.encodePacked(hashToField(abi.encodePacked(appId)),
+    /// action))`
+    Action { app_id: String, action: Vec<u8> },
+    /// An action of an app in a period of time, e.g. to allow proving once a
+    /// day. The bucket is the number of the period since the Unix epoch:
+    ///
+    /// `hashToField(abi.encodePacked(hashToField(abi.encodePacked(appId)),
+    /// action, uint64(bucket)))`
+    Bucketed {
+        app_id: String,
+        action: Vec<u8>,
+        bucket: u64,
+    },
+}
+
+impl ExternalNullifier {
+    #[must_use]
+    pub fn action(app_id: impl Into<String>, action: impl Into<Vec<u8>>) -> Self {
+        Self::Action {
+            app_id: app_id.into(),
+            action: action.into(),
+        }
+    }
+
+    /// The action in the period of `period_secs` seconds that contains the
+    /// Unix time `timestamp`, e.g. a period of 86400 for one per day.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period_secs` is zero.
+    #[must_use]
+    pub fn bucketed(
+        app_id: impl Into<String>,
+        action: impl Into<Vec<u8>>,
+        timestamp: u64,
+        period_secs: u64,
+    ) -> Self {
+        assert!(period_secs > 0, "period must not be empty");
+        Self::Bucketed {
+            app_id: app_id.into(),
+            action: action.into(),
+            bucket: timestamp / period_secs,
+        }
+    }
+
+    /// Version of the encoding, which contracts can use to tell them apart.
+    #[must_use]
+    pub const fn version(&self) -> u8 {
+        match self {
+            Self::Raw(_) => 0,
+            Self::Action { .. } => 1,
+            Self::Bucketed { .. } => 2,
+        }
+    }
+
+    /// The bytes that are hashed to the external nullifier hash.
+    #[must_use]
+    pub fn encode_packed(&self) -> Vec<u8> {
+        match self {
+            Self::Raw(data) => data.clone(),
+            Self::Action { app_id, action } => [app_hash(app_id).as_slice(), action].concat(),
+            Self::Bucketed {
+                app_id,
+                action,
+                bucket,
+            } => [
+                app_hash(app_id).as_slice(),
+                action,
+                bucket.to_be_bytes().as_slice(),
+            ]
+            .concat(),
+        }
+    }
+
+    /// The `external_nullifier_hash` to generate and verify proofs with.
+    #[must_use]
+    pub fn hash(&self) -> Field {
+        hash_to_field_bytes(&self.encode_packed())
+    }
+}
+
+fn app_hash(app_id: &str) -> [u8; 32] {
+    hash_to_field_bytes(app_id.as_bytes()).to_be_bytes::<32>()
+}
+
+/// A signal, encoded like its Solidity type.
+///
+/// The signal hash is `hashToField(abi.encodePacked(signal))`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Signal {
+    /// An `address`, packed as its 20 bytes.
+    Address(Address),
+    /// An `uint256`, packed as 32 big-endian bytes.
+    Uint(Field),
+    /// A `string`, packed as its UTF-8 bytes.
+    String(String),
+    /// A `bytes`, packed as is.
+    Bytes(Vec<u8>),
+}
+
+impl Signal {
+    #[must_use]
+    pub fn encode_packed(&self) -> Vec<u8> {
+        match self {
+            Self::Address(address) => address.as_bytes().to_vec(),
+            Self::Uint(value) => value.to_be_bytes::<32>().to_vec(),
+            Self::String(string) => string.as_bytes().to_vec(),
+            Self::Bytes(bytes) => bytes.clone(),
+        }
+    }
+
+    /// The `signal_hash` to generate and verify proofs with.
+    #[must_use]
+    pub fn hash(&self) -> Field {
+        hash_to_field_bytes(&self.encode_packed())
+    }
+}
+
+impl From<Address> for Signal {
+    fn from(address: Address) -> Self {
+        Self::Address(address)
+    }
+}
+
+impl From<Field> for Signal {
+    fn from(value: Field) -> Self {
+        Self::Uint(value)
+    }
+}
+
+impl From<&str> for Signal {
+    fn from(string: &str) -> Self {
+        Self::String(string.to_owned())
+    }
+}
+
+impl From<String> for Signal {
+    fn from(string: String) -> Self {
+        Self::String(string)
+    }
+}
+
+impl From<Vec<u8>> for Signal {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use ruint::uint;
+
+    use super::*;
+
+    #[test]
+    fn test_external_nullifier() {
+        let app_hash = hash_to_field_bytes(b"app_staging_45068dca85829d2fd90e2dd6f0bff997");
+        let action =
+            ExternalNullifier::action("app_staging_45068dca85829d2fd90e2dd6f0bff997", "vote");
+        assert_eq!(action.version(), 1);
+        let mut packed = app_hash.to_be_bytes::<32>().to_vec();
+        packed.extend_from_slice(b"vote");
+        assert_eq!(action.encode_packed(), packed);
+        assert_eq!(action.hash(), hash_to_field_bytes(&packed));
+        assert_eq!(
+            action.hash(),
+            crate::hash_external_nullifier("app_staging_45068dca85829d2fd90e2dd6f0bff997", b"vote")
+        );
+
+        // All times in a bucket give the same nullifier
+        let day = 86_400;
+        let dated = ExternalNullifier::bucketed("app", "vote", 19_000 * day, day);
+        assert_eq!(
+            dated,
+            ExternalNullifier::bucketed("app", "vote", 19_001 * day - 1, day)
+        );
+        assert_ne!(
+            dated.hash(),
+            ExternalNullifier::bucketed("app", "vote", 19_001 * day, day).hash()
+        );
+        assert_eq!(
+            &dated.encode_packed()[32..],
+            [b"vote".as_slice(), &19_000_u64.to_be_bytes()].concat()
+        );
+
+        assert_eq!(
+            ExternalNullifier::Raw(b"vote".to_vec()).hash(),
+            hash_to_field_bytes(b"vote")
+        );
+    }
+
+    #[test]
+    fn test_signal() {
+        let address = Address::from(hex!("b0ee33d8d1b4d4e4c4b3a9a4b5d0c1b0d2e3f4a5"));
+        assert_eq!(
+            Signal::from(address).encode_packed(),
+            hex!("b0ee33d8d1b4d4e4c4b3a9a4b5d0c1b0d2e3f4a5")
+        );
+        assert_eq!(
+            Signal::from(uint!(1_U256)).encode_packed(),
+            uint!(1_U256).to_be_bytes::<32>()
+        );
+        assert_eq!(Signal::from("hello").hash(), hash_to_field_bytes(b"hello"));
+        assert_eq!(Signal::from("hello").hash(), crate::hash_signal(b"hello"));
+        assert_eq!(
+            Signal::from(b"hello".to_vec()).hash(),
+            Signal::from("hello").hash()
+        );
+
+        // Empty signals hash like `hashToField("")` in Solidity
+        assert_eq!(
+            Signal::from("").hash(),
+            uint!(0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a4_U256)
+        );
+    }
+}
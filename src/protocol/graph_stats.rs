@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use witness::graph::Node;
+use witness::Graph;
+
+use crate::circuit::graph_format::GraphError;
+use crate::Field;
+
+/// Names of the input signals of the circuits of this crate. Graphs only
+/// store the hashes of their input names, these are recognized by
+/// [`GraphInputsExt::inputs`].
+const KNOWN_INPUTS: [&str; 12] = [
+    "identityNullifier",
+    "identityTrapdoor",
+    "treePathIndices",
+    "treeSiblings",
+    "externalNullifier",
+    "signalHash",
+    "secret",
+    "merkleProofLength",
+    "merkleProofIndex",
+    "merkleProofSiblings",
+    "message",
+    "scope",
+];
+
+/// Size and shape of a witness graph, for tracking circuit growth across
+/// artifact versions.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphStats {
+    /// Total number of nodes.
+    pub nodes: usize,
+    /// Number of nodes by node kind, with operations counted by operator,
+    /// e.g. `Input`, `Constant` or `Mul`.
+    pub nodes_by_kind: BTreeMap<String, usize>,
+    /// Length of the longest chain of operations from an input or constant.
+    pub depth: usize,
+    /// Number of input signal nodes.
+    pub inputs: usize,
+    /// Number of witness signals computed by the graph.
+    pub outputs: usize,
+    /// Estimated heap memory used by the graph, in bytes.
+    pub memory_bytes: usize,
+}
+
+/// Adds [`GraphStats`] to [`Graph`].
+pub trait GraphStatsExt {
+    fn stats(&self) -> GraphStats;
+}
+
+impl GraphStatsExt for Graph {
+    fn stats(&self) -> GraphStats {
+        let mut stats = GraphStats {
+            nodes: self.nodes.len(),
+            outputs: self.signals.len(),
+            memory_bytes: std::mem::size_of_val(self.nodes.as_slice())
+                + std::mem::size_of_val(self.signals.as_slice())
+                + std::mem::size_of_val(self.input_mapping.as_slice()),
+            ..GraphStats::default()
+        };
+
+        // Nodes only refer to nodes before them, so depths are computed in a
+        // single pass
+        let mut depths: Vec<usize> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let (kind, depth) = match node {
+                Node::Op(op, a, b) => (format!("{op:?}"), 1 + depths[*a].max(depths[*b])),
+                other => {
+                    if matches!(other, Node::Input(_)) {
+                        stats.inputs += 1;
+                    }
+                    let name = format!("{other:?}");
+                    let kind = name.split('(').next().unwrap_or_default().to_owned();
+                    (kind, 0)
+                }
+            };
+            *stats.nodes_by_kind.entry(kind).or_default() += 1;
+            stats.depth = stats.depth.max(depth);
+            depths.push(depth);
+        }
+
+        stats
+    }
+}
+
+/// An input signal of a witness graph.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphInput {
+    /// The 64 bit FNV-1a hash of the signal name, the graph doesn't store
+    /// the name itself.
+    pub hash: u64,
+    /// The name of the signal, if it's an input of a circuit of this crate.
+    pub name: Option<String>,
+    /// Index of the first value of the signal among the witness inputs.
+    pub offset: usize,
+    /// Number of values of the signal.
+    pub size: usize,
+}
+
+impl GraphInput {
+    /// Returns the name of the signal, or its hash if it isn't known.
+    #[must_use]
+    pub fn display_name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("{:#018x}", self.hash))
+    }
+}
+
+/// Adds input introspection to [`Graph`].
+pub trait GraphInputsExt {
+    /// Returns the input signals of the graph, ordered by offset.
+    fn inputs(&self) -> Vec<GraphInput>;
+
+    /// Checks that inputs match the input signals of the graph, to report a
+    /// misnamed or missized signal before calculating a witness, which
+    /// panics on them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the first input that is unknown or has the
+    /// wrong number of values, or else for the first missing signal.
+    fn validate_inputs(&self, inputs: &HashMap<String, Vec<Field>>) -> Result<(), GraphError>;
+}
+
+impl GraphInputsExt for Graph {
+    fn inputs(&self) -> Vec<GraphInput> {
+        let mut inputs: Vec<_> = self
+            .input_mapping
+            .iter()
+            .map(|info| GraphInput {
+                hash: info.hash,
+                name: KNOWN_INPUTS
+                    .into_iter()
+                    .find(|name| fnv1a(name) == info.hash)
+                    .map(str::to_owned),
+                offset: info.signalid as usize,
+                size: info.signalsize as usize,
+            })
+            .collect();
+        inputs.sort_by_key(|input| input.offset);
+        inputs
+    }
+
+    fn validate_inputs(&self, inputs: &HashMap<String, Vec<Field>>) -> Result<(), GraphError> {
+        let signals = self.inputs();
+        let mut names: Vec<_> = inputs.keys().collect();
+        names.sort_unstable();
+        for name in names {
+            let hash = fnv1a(name);
+            let signal = signals
+                .iter()
+                .find(|signal| signal.hash == hash)
+                .ok_or_else(|| GraphError::UnknownInput(name.clone()))?;
+            if inputs[name].len() != signal.size {
+                return Err(GraphError::InputSize {
+                    name: name.clone(),
+                    expected: signal.size,
+                    actual: inputs[name].len(),
+                });
+            }
+        }
+        if let Some(signal) = signals
+            .iter()
+            .find(|signal| !inputs.keys().any(|name| fnv1a(name) == signal.hash))
+        {
+            return Err(GraphError::MissingInput(signal.display_name()));
+        }
+        Ok(())
+    }
+}
+
+/// The hash of input names used by the witness graph generator.
+fn fnv1a(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Returns the statistics of the witness graph embedded for the given tree
+/// depth.
+///
+/// # Panics
+///
+/// Panics if the depth is not supported.
+#[must_use]
+pub fn witness_graph_stats(depth: usize) -> GraphStats {
+    crate::circuit::graph(depth).stats()
+}
+
+#[cfg(all(test, not(feature = "external-artifacts")))]
+mod tests {
+    use semaphore_depth_macros::test_all_depths;
+
+    use super::*;
+
+    #[test_all_depths(prover)]
+    fn test_witness_graph_stats(depth: usize) {
+        let stats = witness_graph_stats(depth);
+
+        assert_eq!(stats.nodes_by_kind.values().sum::<usize>(), stats.nodes);
+        assert_eq!(stats.nodes_by_kind.get("Input"), Some(&stats.inputs));
+        // Nullifier, trapdoor, external nullifier, signal hash and a path index
+        // and sibling per level
+        assert!(stats.inputs >= 4 + 2 * depth);
+        assert!(stats.depth > depth);
+        assert!(stats.outputs > 0);
+        assert!(stats.memory_bytes >= stats.nodes);
+
+        // Graphs grow with the tree depth
+        if let Some(&smaller) = semaphore_depth_config::get_prover_depths()
+            .iter()
+            .find(|&&d| d < depth)
+        {
+            assert!(witness_graph_stats(smaller).nodes < stats.nodes);
+        }
+    }
+
+    #[test_all_depths(prover)]
+    fn test_graph_inputs(depth: usize) {
+        let graph = crate::circuit::graph(depth);
+        let signals = graph.inputs();
+        let names: Vec<_> = signals.iter().map(GraphInput::display_name).collect();
+        let mut sorted = names.clone();
+        sorted.sort_unstable();
+        assert_eq!(
+            sorted,
+            [
+                "externalNullifier",
+                "identityNullifier",
+                "identityTrapdoor",
+                "signalHash",
+                "treePathIndices",
+                "treeSiblings"
+            ]
+        );
+        assert_eq!(
+            signals.iter().map(|signal| signal.size).sum::<usize>(),
+            4 + 2 * depth
+        );
+        // Signals don't overlap
+        for pair in signals.windows(2) {
+            assert!(pair[0].offset + pair[0].size <= pair[1].offset);
+        }
+
+        let mut inputs: HashMap<_, _> = signals
+            .iter()
+            .map(|signal| (signal.display_name(), vec![Field::ZERO; signal.size]))
+            .collect();
+        graph.validate_inputs(&inputs).unwrap();
+
+        let siblings = inputs.remove("treeSiblings").unwrap();
+        inputs.insert("treeSibling".to_owned(), siblings.clone());
+        assert!(matches!(
+            graph.validate_inputs(&inputs),
+            Err(GraphError::UnknownInput(name)) if name == "treeSibling"
+        ));
+        inputs.remove("treeSibling");
+        assert!(matches!(
+            graph.validate_inputs(&inputs),
+            Err(GraphError::MissingInput(name)) if name == "treeSiblings"
+        ));
+        inputs.insert("treeSiblings".to_owned(), siblings[1..].to_vec());
+        assert!(matches!(
+            graph.validate_inputs(&inputs),
+            Err(GraphError::InputSize { expected, actual, .. })
+                if expected == depth && actual == depth - 1
+        ));
+    }
+}
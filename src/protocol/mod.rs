@@ -1,29 +1,81 @@
+#[cfg(feature = "prover")]
+use std::cell::RefCell;
+#[cfg(feature = "prover")]
 use std::collections::HashMap;
+#[cfg(all(feature = "prover", not(target_arch = "wasm32")))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "prover")]
+use std::time::Duration;
+#[cfg(all(feature = "prover", not(target_arch = "wasm32")))]
+use std::time::Instant;
 
-use ark_bn254::{Config, Fr};
+use ark_bn254::Config;
+#[cfg(feature = "prover")]
+use ark_bn254::Fr;
+#[cfg(feature = "prover")]
 use ark_circom::CircomReduction;
 use ark_ec::bn::Bn;
+#[cfg(feature = "prover")]
 use ark_ff::PrimeField;
-use ark_groth16::{prepare_verifying_key, Groth16, Proof as ArkProof};
+use ark_groth16::{PreparedVerifyingKey, Proof as ArkProof};
 use ark_relations::r1cs::SynthesisError;
+#[cfg(feature = "prover")]
 use ark_std::UniformRand;
-use color_eyre::Result;
 use ethers_core::types::U256;
-use once_cell::sync::Lazy;
 use poseidon::Poseidon;
-use rand::{thread_rng, Rng};
-use semaphore_depth_config::{get_depth_index, get_supported_depth_count};
-use semaphore_depth_macros::array_for_depths;
+#[cfg(feature = "prover")]
+use rand::{Rng, SeedableRng};
+#[cfg(feature = "prover")]
+use rand_chacha::ChaCha20Rng;
+#[cfg(all(feature = "prover", not(target_arch = "wasm32")))]
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "prover")]
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 use trees::Branch;
+// The clock of `std` panics in WebAssembly, use the one of JavaScript
+#[cfg(all(feature = "prover", target_arch = "wasm32"))]
+use web_time::Instant;
+#[cfg(feature = "prover")]
 use witness::Graph;
+#[cfg(feature = "prover")]
+use zeroize::Zeroize;
+use zeroize::Zeroizing;
 
-use crate::circuit::zkey;
+#[cfg(feature = "prover")]
+use self::backend::ProvingBackend;
+#[cfg(all(feature = "prover", not(target_arch = "wasm32")))]
+use self::wire::ProveRequest;
+use crate::circuit::{verifying_key, ArtifactCache, Circuit, WitnessError};
+#[cfg(feature = "prover")]
+use crate::circuit::{zkey, ZKey};
 use crate::identity::Identity;
+#[cfg(feature = "prover")]
+use crate::randomness;
 use crate::Field;
 
 pub mod authentication;
+pub mod backend;
+mod bundle;
+pub mod compression;
+#[cfg(feature = "prover")]
+pub mod context;
+mod encoding;
+#[cfg(feature = "prover")]
+pub mod graph_stats;
+pub mod signal;
+#[cfg(feature = "v4")]
+pub mod v4;
+mod validation;
+pub mod wire;
+
+pub use self::bundle::SemaphoreProof;
+#[cfg(feature = "prover")]
+pub use self::bundle::{generate_proof_auto, MembershipTree};
+pub use self::encoding::{ExternalNullifier, Signal};
+pub use self::validation::{ProofPoint, ProofValidationError};
 
 // Matches the private G1Tup type in ark-circom.
 pub type G1 = (U256, U256);
@@ -31,14 +83,20 @@ pub type G1 = (U256, U256);
 // Matches the private G2Tup type in ark-circom.
 pub type G2 = ([U256; 2], [U256; 2]);
 
-static WITHESS_GRAPH: [Lazy<Graph>; get_supported_depth_count()] = array_for_depths!(|depth| {
-    Lazy::new(|| {
-        witness::init_graph(crate::circuit::graph(depth)).expect("Failed to initialize Graph")
-    })
-});
-
 /// Wrap a proof object so we have serde support
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// Proofs are ordered lexicographically by their coordinates, in the order
+/// they are stored. This is the order of their [`PackedProof`] encodings.
+///
+/// With the `strict-serde` feature, deserializing a proof also checks its
+/// points, see [`Proof::validate`].
+///
+/// [`PackedProof`]: crate::packed_proof::PackedProof
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "strict-serde",
+    serde(try_from = "validation::UncheckedProof")
+)]
 pub struct Proof(pub G1, pub G2, pub G1);
 
 impl From<ArkProof<Bn<Config>>> for Proof {
@@ -73,14 +131,9 @@ impl From<Proof> for ArkProof<Bn<Config>> {
 
 /// Helper to merkle proof into a bigint vector
 /// TODO: we should create a From trait for this
+#[cfg(feature = "prover")]
 fn merkle_proof_to_vec(proof: &trees::Proof<Poseidon>) -> Vec<Field> {
-    proof
-        .0
-        .iter()
-        .map(|x| match x {
-            Branch::Left(value) | Branch::Right(value) => *value,
-        })
-        .collect()
+    proof.siblings()
 }
 
 /// Generates the nullifier hash
@@ -94,11 +147,112 @@ pub enum ProofError {
     #[error("Error reading circuit key: {0}")]
     CircuitKeyError(#[from] std::io::Error),
     #[error("Error producing witness: {0}")]
-    WitnessError(color_eyre::Report),
+    WitnessError(#[from] WitnessError),
     #[error("Error producing proof: {0}")]
     SynthesisError(#[from] SynthesisError),
+    #[error("Error proving with streamed key: {0}")]
+    ZkeyError(#[from] ark_zkey::ZkeyError),
     #[error("Error converting public input: {0}")]
     ToFieldError(#[from] ruint::ToFieldError),
+    #[error("Error decompressing proof: {0}")]
+    CompressionError(#[from] compression::CompressionError),
+    #[error("Merkle proof has depth {actual}, expected {expected}")]
+    DepthMismatch { expected: usize, actual: usize },
+    #[error("Tree depth {0} is not supported")]
+    UnsupportedDepth(usize),
+    #[error("No circuit for tree depth {depth}, supported depths are {supported:?}")]
+    NoCircuit { depth: usize, supported: Vec<usize> },
+    #[error("Tree has no leaf at index {0}")]
+    UnknownLeaf(usize),
+    #[error("Error in proving backend: {0}")]
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Tree depths with built in circuits, selected by the depth features.
+///
+/// See [`check_depth`] for depths whose artifacts are inserted at runtime.
+#[must_use]
+pub fn supported_depths() -> &'static [usize] {
+    semaphore_depth_config::get_supported_depths()
+}
+
+/// Checks that proofs for trees of the given depth can be generated and
+/// verified, i.e. that the depth is built in or its artifacts are in the
+/// [`ArtifactCache::global`].
+///
+/// # Errors
+///
+/// Returns [`ProofError::UnsupportedDepth`] otherwise.
+pub fn check_depth(depth: usize) -> Result<(), ProofError> {
+    if supported_depths().contains(&depth) || ArtifactCache::global().contains(Circuit::V3, depth) {
+        Ok(())
+    } else {
+        Err(ProofError::UnsupportedDepth(depth))
+    }
+}
+
+/// Tree depths with built in circuits that proofs can be generated for,
+/// those of [`supported_depths`] with a witness graph.
+///
+/// See [`check_prover_depth`] for depths whose artifacts are inserted at
+/// runtime.
+#[cfg(feature = "prover")]
+#[must_use]
+pub fn prover_depths() -> &'static [usize] {
+    semaphore_depth_config::get_prover_depths()
+}
+
+/// Checks that proofs for trees of the given depth can be generated, i.e.
+/// that the depth has a built in witness graph or its proving artifacts are
+/// in the [`ArtifactCache::global`].
+///
+/// # Errors
+///
+/// Returns [`ProofError::UnsupportedDepth`] otherwise.
+#[cfg(feature = "prover")]
+pub fn check_prover_depth(depth: usize) -> Result<(), ProofError> {
+    if prover_depths().contains(&depth)
+        || ArtifactCache::global().contains_prover(Circuit::V3, depth)
+    {
+        Ok(())
+    } else {
+        Err(ProofError::UnsupportedDepth(depth))
+    }
+}
+
+/// Resizes a Merkle proof to the depth of a circuit, like the
+/// `merkleTreeDepth` parameter of the JavaScript SDK.
+///
+/// A proof of a shallower tree is padded with the roots of empty subtrees,
+/// which makes it a proof for the tree of the given depth that holds the
+/// same leaves. Its root is the root of that deeper tree, see
+/// [`trees::Proof::root`]. A proof of a deeper tree can only be truncated if
+/// the leaf is among the first `2^depth` leaves and all later ones are
+/// empty.
+///
+/// # Errors
+///
+/// Returns [`ProofError::DepthMismatch`] if a proof can't be truncated.
+pub fn resize_merkle_proof(
+    merkle_proof: &trees::Proof<Poseidon>,
+    depth: usize,
+    empty_leaf: Field,
+) -> Result<trees::Proof<Poseidon>, ProofError> {
+    let mut branches = merkle_proof.0.clone();
+    let mut empty = empty_leaf;
+    for level in 0..depth.max(branches.len()) {
+        if level >= branches.len() {
+            branches.push(Branch::Left(empty));
+        } else if level >= depth && branches[level] != Branch::Left(empty) {
+            return Err(ProofError::DepthMismatch {
+                expected: depth,
+                actual: merkle_proof.0.len(),
+            });
+        }
+        empty = poseidon::poseidon::hash2(empty, empty);
+    }
+    branches.truncate(depth);
+    Ok(trees::Proof(branches))
 }
 
 /// Generates a semaphore proof
@@ -106,6 +260,7 @@ pub enum ProofError {
 /// # Errors
 ///
 /// Returns a [`ProofError`] if proving fails.
+#[cfg(feature = "prover")]
 pub fn generate_proof(
     identity: &Identity,
     merkle_proof: &trees::Proof<Poseidon>,
@@ -117,7 +272,7 @@ pub fn generate_proof(
         merkle_proof,
         external_nullifier_hash,
         signal_hash,
-        &mut thread_rng(),
+        &mut randomness::rng(),
     )
 }
 
@@ -126,6 +281,7 @@ pub fn generate_proof(
 /// # Errors
 ///
 /// Returns a [`ProofError`] if proving fails.
+#[cfg(feature = "prover")]
 pub fn generate_proof_rng(
     identity: &Identity,
     merkle_proof: &trees::Proof<Poseidon>,
@@ -133,50 +289,496 @@ pub fn generate_proof_rng(
     signal_hash: Field,
     rng: &mut impl Rng,
 ) -> Result<Proof, ProofError> {
-    generate_proof_rs(
+    let depth = merkle_proof.0.len();
+    check_prover_depth(depth)?;
+    let (proof, _) = prove(
+        ProvingKey::Parsed(&zkey(depth), backend::backend()),
+        &crate::circuit::graph(depth),
         identity,
         merkle_proof,
         external_nullifier_hash,
         signal_hash,
         ark_bn254::Fr::rand(rng),
         ark_bn254::Fr::rand(rng),
+        &mut WitnessScratch::new(),
+        &(),
+    )?;
+    Ok(proof)
+}
+
+/// Generates a semaphore proof whose randomness is derived from `seed`, for
+/// reproducible tests and audits.
+///
+/// The same seed and inputs always give the same proof. The randomness
+/// `r, s` of the proof is drawn from a ChaCha20 DRBG seeded with the SHA-256
+/// of the seed and the public inputs of the proof.
+///
+/// These proofs are not hiding: anyone who knows or guesses the seed can
+/// test guesses of the identity against the proof. Don't use them for
+/// identities that need to stay private.
+///
+/// # Errors
+///
+/// Returns a [`ProofError`] if proving fails.
+#[cfg(feature = "prover")]
+pub fn generate_proof_deterministic(
+    identity: &Identity,
+    merkle_proof: &trees::Proof<Poseidon>,
+    external_nullifier_hash: Field,
+    signal_hash: Field,
+    seed: &[u8; 32],
+) -> Result<Proof, ProofError> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"semaphore-rs deterministic proof");
+    hasher.update(seed);
+    hasher.update(external_nullifier_hash.to_be_bytes::<32>());
+    hasher.update(signal_hash.to_be_bytes::<32>());
+    for sibling in merkle_proof_to_vec(merkle_proof) {
+        hasher.update(sibling.to_be_bytes::<32>());
+    }
+    for index in path_index(merkle_proof) {
+        hasher.update(index.to_be_bytes::<32>());
+    }
+    generate_proof_rng(
+        identity,
+        merkle_proof,
+        external_nullifier_hash,
+        signal_hash,
+        &mut ChaCha20Rng::from_seed(hasher.finalize().into()),
     )
 }
 
-fn generate_proof_rs(
+/// A step of generating a proof, reported to a [`ProgressSink`].
+///
+/// Proving after the witness is done is a single call into arkworks, which
+/// doesn't report the progress of its multi-scalar multiplications, so
+/// there are no events between [`Self::WitnessDone`] and
+/// [`Self::ProofDone`].
+#[cfg(feature = "prover")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofProgress {
+    WitnessStarted,
+    /// The witness is done and proving started.
+    WitnessDone,
+    ProofDone,
+}
+
+/// Receives the steps of generating a proof, e.g. to show a progress bar,
+/// see [`ProverOptions::progress`].
+///
+/// Events are reported on the thread generating the proof. Closures taking a
+/// [`ProofProgress`] are sinks, and `()` ignores all events.
+#[cfg(feature = "prover")]
+pub trait ProgressSink {
+    fn report(&self, progress: ProofProgress);
+}
+
+#[cfg(feature = "prover")]
+impl<F: Fn(ProofProgress)> ProgressSink for F {
+    fn report(&self, progress: ProofProgress) {
+        self(progress);
+    }
+}
+
+#[cfg(feature = "prover")]
+impl ProgressSink for () {
+    fn report(&self, _progress: ProofProgress) {}
+}
+
+/// Time spent in the steps of generating a proof.
+#[cfg(feature = "prover")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProofTimings {
+    pub witness: Duration,
+    pub proving: Duration,
+}
+
+#[cfg(feature = "prover")]
+impl ProofTimings {
+    #[must_use]
+    pub fn total(&self) -> Duration {
+        self.witness + self.proving
+    }
+}
+
+/// Options of [`generate_proof_with_options`].
+#[cfg(feature = "prover")]
+#[derive(Default)]
+pub struct ProverOptions<'a> {
+    /// Proves in low-memory mode, with at most about this many bytes of
+    /// proving key points and their scalars in memory at once, see
+    /// [`ark_zkey::create_proof_streamed`]. `None` proves with the whole
+    /// proving key parsed and cached, which is several times faster.
+    pub max_memory_bytes: Option<usize>,
+    /// Receives the steps of generating the proof. No further events are
+    /// reported after an error.
+    pub progress: Option<&'a dyn ProgressSink>,
+    /// Buffers to reuse instead of allocating new ones, see
+    /// [`WitnessScratch`].
+    pub scratch: Option<&'a mut WitnessScratch>,
+    /// Set to the time spent generating the proof, e.g. to monitor prover
+    /// latency.
+    pub timings: Option<&'a mut ProofTimings>,
+}
+
+#[cfg(feature = "prover")]
+impl std::fmt::Debug for ProverOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProverOptions")
+            .field("max_memory_bytes", &self.max_memory_bytes)
+            .field("progress", &self.progress.is_some())
+            .field("scratch", &self.scratch)
+            .field("timings", &self.timings)
+            .finish()
+    }
+}
+
+/// Generates a semaphore proof with the given [`ProverOptions`].
+///
+/// In low-memory mode the built in proving key is deserialized a chunk at a
+/// time for every proof instead of parsed and cached once, e.g. to prove
+/// for deep trees on phones that can't hold the parsed key. The witness, the
+/// constraint matrices and the QAP witness map are still held in full. A
+/// proving key already in the [`ArtifactCache`] is used as is, since it
+/// takes no further memory.
+///
+/// # Errors
+///
+/// Returns a [`ProofError`] if proving fails.
+#[cfg(feature = "prover")]
+pub fn generate_proof_with_options(
     identity: &Identity,
     merkle_proof: &trees::Proof<Poseidon>,
     external_nullifier_hash: Field,
     signal_hash: Field,
-    r: ark_bn254::Fr,
-    s: ark_bn254::Fr,
+    options: ProverOptions<'_>,
 ) -> Result<Proof, ProofError> {
     let depth = merkle_proof.0.len();
-    let full_assignment =
-        generate_witness(identity, merkle_proof, external_nullifier_hash, signal_hash);
+    check_prover_depth(depth)?;
+    let mut rng = randomness::rng();
+    let streamed = options.max_memory_bytes.filter(|_| {
+        supported_depths().contains(&depth)
+            && ArtifactCache::global()
+                .cached_zkey(Circuit::V3, depth)
+                .is_none()
+    });
+    let parsed;
+    let key = if let Some(max_memory_bytes) = streamed {
+        ProvingKey::Streamed(crate::circuit::zkey_bytes(depth), max_memory_bytes)
+    } else {
+        parsed = zkey(depth);
+        ProvingKey::Parsed(&parsed, backend::backend())
+    };
+    let (proof, timings) = prove(
+        key,
+        &crate::circuit::graph(depth),
+        identity,
+        merkle_proof,
+        external_nullifier_hash,
+        signal_hash,
+        Fr::rand(&mut rng),
+        Fr::rand(&mut rng),
+        options.scratch.unwrap_or(&mut WitnessScratch::new()),
+        options.progress.unwrap_or(&()),
+    )?;
+    if let Some(slot) = options.timings {
+        *slot = timings;
+    }
+    Ok(proof)
+}
+
+/// Generates the proofs of several requests, e.g. for many identities, with
+/// one worker per thread of the rayon pool.
+///
+/// See [`generate_proofs_parallel_with_workers`].
+#[cfg(all(feature = "prover", not(target_arch = "wasm32")))]
+#[must_use]
+pub fn generate_proofs_parallel(requests: &[ProveRequest]) -> Vec<Result<Proof, ProofError>> {
+    generate_proofs_parallel_with_workers(requests, rayon::current_num_threads())
+}
+
+/// Generates the proofs of several requests with at most `workers` proofs in
+/// progress at once, returning the results in the order of the requests.
+///
+/// The proving key and witness graph of every depth are loaded once and
+/// shared by all workers. Every worker generates one proof at a time with its
+/// own [`WitnessScratch`], so at most `workers` witnesses and prover buffers
+/// are allocated at once, unlike when spawning a thread per proof. Proving
+/// itself runs on the threads of the rayon pool, so fewer workers trade
+/// throughput for memory.
+///
+/// Not available in WebAssembly, which has no threads to run the workers on.
+///
+/// # Panics
+///
+/// Panics if `workers` is 0.
+#[cfg(all(feature = "prover", not(target_arch = "wasm32")))]
+#[must_use]
+pub fn generate_proofs_parallel_with_workers(
+    requests: &[ProveRequest],
+    workers: usize,
+) -> Vec<Result<Proof, ProofError>> {
+    assert!(workers > 0, "Proving needs at least one worker");
+    let mut artifacts = HashMap::new();
+    for request in requests {
+        let depth = request.merkle_proof.0.len();
+        if check_prover_depth(depth).is_ok() {
+            artifacts
+                .entry(depth)
+                .or_insert_with(|| (zkey(depth), crate::circuit::graph(depth)));
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let work = |_| {
+        let mut rng = randomness::rng();
+        let mut scratch = WitnessScratch::new();
+        let mut results = Vec::new();
+        loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(request) = requests.get(index) else {
+                return results;
+            };
+            let depth = request.merkle_proof.0.len();
+            let result = check_prover_depth(depth).and_then(|()| {
+                let (zkey, graph) = &artifacts[&depth];
+                prove(
+                    ProvingKey::Parsed(zkey, backend::backend()),
+                    graph,
+                    &request.identity(),
+                    &request.merkle_proof,
+                    request.external_nullifier_hash,
+                    request.signal_hash,
+                    Fr::rand(&mut rng),
+                    Fr::rand(&mut rng),
+                    &mut scratch,
+                    &(),
+                )
+            });
+            results.push((index, result.map(|(proof, _)| proof)));
+        }
+    };
+    let mut results: Vec<_> = (0..workers.min(requests.len()))
+        .into_par_iter()
+        .flat_map_iter(work)
+        .collect();
+    results.sort_unstable_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// The proving key a proof is generated with.
+#[cfg(feature = "prover")]
+pub(crate) enum ProvingKey<'a> {
+    /// A parsed key, proven with the given backend.
+    Parsed(&'a ZKey, &'a dyn ProvingBackend),
+    /// Arkzkey bytes, read a chunk at a time with at most about the given
+    /// number of bytes in memory, see [`ProverOptions::max_memory_bytes`].
+    Streamed(&'a [u8], usize),
+}
+
+/// Generates a proof with the given circuit artifacts.
+#[cfg(feature = "prover")]
+#[allow(clippy::too_many_arguments)]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(depth = merkle_proof.0.len()))
+)]
+fn prove(
+    key: ProvingKey<'_>,
+    graph: &Graph,
+    identity: &Identity,
+    merkle_proof: &trees::Proof<Poseidon>,
+    external_nullifier_hash: Field,
+    signal_hash: Field,
+    r: ark_bn254::Fr,
+    s: ark_bn254::Fr,
+    scratch: &mut WitnessScratch,
+    progress: &dyn ProgressSink,
+) -> Result<(Proof, ProofTimings), ProofError> {
+    progress.report(ProofProgress::WitnessStarted);
+    let start = Instant::now();
+    calculate_witness_into(
+        graph,
+        identity,
+        merkle_proof,
+        external_nullifier_hash,
+        signal_hash,
+        &mut scratch.witness,
+    );
+    let witness = start.elapsed();
+    progress.report(ProofProgress::WitnessDone);
+
+    let start = Instant::now();
+    let ark_proof = {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("groth16_prove").entered();
+        match key {
+            ProvingKey::Parsed(zkey, backend) => backend.prove(zkey, &scratch.witness, r, s),
+            ProvingKey::Streamed(zkey_bytes, max_memory_bytes) => {
+                prove_streamed(zkey_bytes, &scratch.witness, r, s, max_memory_bytes)
+            }
+        }
+    };
+    // The witness holds the identity secrets, wipe it whether proving
+    // succeeded or not
+    scratch.witness.zeroize();
+    let ark_proof = ark_proof?;
+    let timings = ProofTimings {
+        witness,
+        proving: start.elapsed(),
+    };
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        witness_ms = timings.witness.as_millis(),
+        proving_ms = timings.proving.as_millis(),
+        "proof generated"
+    );
+    progress.report(ProofProgress::ProofDone);
+
+    Ok((ark_proof.into(), timings))
+}
 
-    let zkey = zkey(depth);
-    let ark_proof = Groth16::<_, CircomReduction>::create_proof_with_reduction_and_matrices(
-        &zkey.0,
+/// Proves reading the proving key from arkzkey bytes a chunk at a time, see
+/// [`ProverOptions::max_memory_bytes`].
+#[cfg(feature = "prover")]
+fn prove_streamed(
+    zkey_bytes: &[u8],
+    witness: &[Fr],
+    r: Fr,
+    s: Fr,
+    max_memory_bytes: usize,
+) -> Result<ArkProof<Bn<Config>>, ProofError> {
+    let zkey = ark_zkey::ProvingKeySegments::parse(zkey_bytes, ark_zkey::Compress::Yes)?;
+    Ok(ark_zkey::create_proof_streamed::<CircomReduction>(
+        &zkey,
         r,
         s,
-        &zkey.1,
-        zkey.1.num_instance_variables,
-        zkey.1.num_constraints,
-        full_assignment.as_slice(),
-    )?;
-    let proof = ark_proof.into();
-
-    Ok(proof)
+        witness,
+        max_memory_bytes,
+    )?)
 }
 
+/// A vector that is zeroized when dropped.
+///
+/// Witnesses hold the secret inputs of the circuit, e.g. the identity
+/// nullifier and trapdoor, so they are returned in one of these.
+pub type ZeroizingVec<T> = Zeroizing<Vec<T>>;
+
+/// Generates the witness of a proof.
+///
+/// The witness holds the identity secrets and is wiped when dropped. The
+/// inputs passed to the witness graph are copied by the `witness` crate,
+/// which does not wipe its copies.
+#[cfg(feature = "prover")]
 pub fn generate_witness(
     identity: &Identity,
     merkle_proof: &trees::Proof<Poseidon>,
     external_nullifier_hash: Field,
     signal_hash: Field,
-) -> Vec<Fr> {
+) -> ZeroizingVec<Fr> {
     let depth = merkle_proof.0.len();
+    let mut witness = Zeroizing::new(Vec::new());
+    calculate_witness_into(
+        &crate::circuit::graph(depth),
+        identity,
+        merkle_proof,
+        external_nullifier_hash,
+        signal_hash,
+        &mut witness,
+    );
+    witness
+}
+
+/// Generates the witness of a proof into the buffer of `scratch` and returns
+/// it, see [`WitnessScratch`].
+#[cfg(feature = "prover")]
+pub fn generate_witness_with_scratch<'a>(
+    identity: &Identity,
+    merkle_proof: &trees::Proof<Poseidon>,
+    external_nullifier_hash: Field,
+    signal_hash: Field,
+    scratch: &'a mut WitnessScratch,
+) -> &'a [Fr] {
+    let depth = merkle_proof.0.len();
+    calculate_witness_into(
+        &crate::circuit::graph(depth),
+        identity,
+        merkle_proof,
+        external_nullifier_hash,
+        signal_hash,
+        &mut scratch.witness,
+    );
+    &scratch.witness
+}
+
+/// Buffers for generating proofs, to reuse from proof to proof.
+///
+/// Every proof needs its witness as field elements, 32 bytes per wire of the
+/// circuit. Generating proofs with the same scratch, see
+/// [`ProverOptions::scratch`], keeps that buffer instead of allocating
+/// it for every proof. Evaluating the witness graph still allocates a vector
+/// per proof, since the `witness` crate doesn't take a buffer to write into.
+///
+/// The witness holds the identity secrets. Proving wipes it once the proof is
+/// done, and the buffer is wiped when the scratch is dropped.
+#[cfg(feature = "prover")]
+#[derive(Clone, Debug, Default)]
+pub struct WitnessScratch {
+    witness: Vec<Fr>,
+}
+
+#[cfg(feature = "prover")]
+impl Drop for WitnessScratch {
+    fn drop(&mut self) {
+        self.witness.zeroize();
+    }
+}
+
+#[cfg(feature = "prover")]
+thread_local! {
+    static THREAD_SCRATCH: RefCell<WitnessScratch> = RefCell::default();
+}
+
+#[cfg(feature = "prover")]
+impl WitnessScratch {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            witness: Vec::new(),
+        }
+    }
+
+    /// Runs `f` with the scratch of the current thread, so threads that keep
+    /// proving reuse their buffers without passing a scratch around. Nested
+    /// calls get a new scratch.
+    pub fn with_thread_local<R>(f: impl FnOnce(&mut Self) -> R) -> R {
+        THREAD_SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+            Ok(mut scratch) => f(&mut scratch),
+            Err(_) => f(&mut Self::new()),
+        })
+    }
+
+    /// The witness last generated with [`generate_witness_with_scratch`].
+    /// It is empty after generating a proof, which wipes the witness.
+    #[must_use]
+    pub fn witness(&self) -> &[Fr] {
+        &self.witness
+    }
+}
+
+#[cfg(feature = "prover")]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(depth = merkle_proof.0.len()))
+)]
+fn calculate_witness_into(
+    graph: &Graph,
+    identity: &Identity,
+    merkle_proof: &trees::Proof<Poseidon>,
+    external_nullifier_hash: Field,
+    signal_hash: Field,
+    witness: &mut Vec<Fr>,
+) {
     let inputs = HashMap::from([
         ("identityNullifier".to_owned(), vec![identity.nullifier]),
         ("identityTrapdoor".to_owned(), vec![identity.trapdoor]),
@@ -189,35 +791,52 @@ pub fn generate_witness(
         ("signalHash".to_owned(), vec![signal_hash]),
     ]);
 
-    let graph = &WITHESS_GRAPH
-        [get_depth_index(depth).unwrap_or_else(|| panic!("Depth {depth} not supported"))];
-
-    let witness = witness::calculate_witness(inputs, graph).unwrap();
-    witness
-        .into_iter()
-        .map(|x| Fr::from_bigint(x.into()).expect("Couldn't cast U256 to BigInteger"))
-        .collect::<Vec<_>>()
+    let values: ZeroizingVec<Field> =
+        Zeroizing::new(witness::calculate_witness(inputs, graph).unwrap());
+    witness.zeroize();
+    witness.extend(
+        values
+            .iter()
+            .map(|x| Fr::from_bigint((*x).into()).expect("Couldn't cast U256 to BigInteger")),
+    );
 }
 
-/// Compute path index
+/// Compute path index, as field elements, see [`trees::Proof::path_index`]
 #[must_use]
 pub fn path_index(proof: &trees::Proof<Poseidon>) -> Vec<Field> {
     proof
-        .0
-        .iter()
-        .map(|branch| match branch {
-            Branch::Left(_) => Field::from(0),
-            Branch::Right(_) => Field::from(1),
-        })
+        .path_index()
+        .into_iter()
+        .map(|right| Field::from(u8::from(right)))
         .collect()
 }
 
+/// Returns the prepared verifying key for the given depth.
+///
+/// Keys are prepared once per depth and kept in the
+/// [`ArtifactCache::global`], so [`verify_proof`] doesn't prepare them on
+/// every call. Verifiers can call this at startup to move the preparation out
+/// of the first verification.
+///
+/// # Errors
+///
+/// Returns [`ProofError::UnsupportedDepth`] if there is no circuit for the
+/// depth.
+pub fn prepared_vk(depth: usize) -> Result<Arc<PreparedVerifyingKey<Bn<Config>>>, ProofError> {
+    check_depth(depth)?;
+    Ok(verifying_key(depth))
+}
+
 /// Verifies a given semaphore proof
 ///
 /// # Errors
 ///
 /// Returns a [`ProofError`] if verifying fails. Verification failure does not
 /// necessarily mean the proof is incorrect.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(depth = tree_depth))
+)]
 pub fn verify_proof(
     root: Field,
     nullifier_hash: Field,
@@ -226,8 +845,7 @@ pub fn verify_proof(
     proof: &Proof,
     tree_depth: usize,
 ) -> Result<bool, ProofError> {
-    let zkey = zkey(tree_depth);
-    let pvk = prepare_verifying_key(&zkey.0.vk);
+    let pvk = prepared_vk(tree_depth)?;
 
     let public_inputs = [root, nullifier_hash, signal_hash, external_nullifier_hash]
         .iter()
@@ -235,11 +853,34 @@ pub fn verify_proof(
         .collect::<Result<Vec<_>, _>>()?;
 
     let ark_proof = (*proof).into();
-    let result = Groth16::<_, CircomReduction>::verify_proof(&pvk, &ark_proof, &public_inputs[..])?;
-    Ok(result)
+    backend::backend().verify(&pvk, &ark_proof, &public_inputs)
+}
+
+/// Verifies a given compressed semaphore proof
+///
+/// # Errors
+///
+/// Returns a [`ProofError`] if decompressing or verifying fails.
+pub fn verify_compressed_proof(
+    root: Field,
+    nullifier_hash: Field,
+    signal_hash: Field,
+    external_nullifier_hash: Field,
+    proof: &compression::CompressedProof,
+    tree_depth: usize,
+) -> Result<bool, ProofError> {
+    let proof = compression::decompress_proof(*proof)?;
+    verify_proof(
+        root,
+        nullifier_hash,
+        signal_hash,
+        external_nullifier_hash,
+        &proof,
+        tree_depth,
+    )
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "prover", not(feature = "external-artifacts")))]
 #[allow(dead_code)]
 mod test {
     use rand::SeedableRng as _;
@@ -248,7 +889,7 @@ mod test {
     use serde_json::json;
 
     use super::*;
-    use crate::hash_to_field;
+    use crate::hash_to_field_bytes;
     use crate::poseidon_tree::LazyPoseidonTree;
 
     fn arb_proof(seed: u64, depth: usize) -> Proof {
@@ -267,10 +908,10 @@ mod test {
         let merkle_proof = tree.proof(0);
 
         let external_nullifier: [u8; 16] = rng.gen();
-        let external_nullifier_hash = hash_to_field(&external_nullifier);
+        let external_nullifier_hash = hash_to_field_bytes(&external_nullifier);
 
         let signal: [u8; 16] = rng.gen();
-        let signal_hash = hash_to_field(&signal);
+        let signal_hash = hash_to_field_bytes(&signal);
 
         generate_proof_rng(
             &id,
@@ -282,7 +923,357 @@ mod test {
         .unwrap()
     }
 
-    #[test_all_depths]
+    #[test]
+    fn test_deterministic_proof() {
+        let depth = supported_depths()[0];
+        let mut secret = *b"secret";
+        let id = Identity::from_secret(&mut secret, None);
+        let tree = LazyPoseidonTree::new(depth, Field::from(0))
+            .derived()
+            .update(0, &id.commitment());
+        let merkle_proof = tree.proof(0);
+        let prove = |signal_hash, seed| {
+            generate_proof_deterministic(&id, &merkle_proof, Field::from(1), signal_hash, seed)
+                .unwrap()
+        };
+
+        let proof = prove(Field::from(2), &[7; 32]);
+        assert_eq!(proof, prove(Field::from(2), &[7; 32]));
+        assert_ne!(proof, prove(Field::from(2), &[8; 32]));
+        // The randomness depends on the inputs, not only on the seed
+        assert_ne!(proof, prove(Field::from(3), &[7; 32]));
+        assert!(verify_proof(
+            tree.root(),
+            generate_nullifier_hash(&id, Field::from(1)),
+            Field::from(2),
+            Field::from(1),
+            &proof,
+            depth
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_proof_timings() {
+        let depth = supported_depths()[0];
+        let mut secret = *b"secret";
+        let id = Identity::from_secret(&mut secret, None);
+        let tree = LazyPoseidonTree::new(depth, Field::from(0))
+            .derived()
+            .update(0, &id.commitment());
+        let mut timings = ProofTimings::default();
+        let options = ProverOptions {
+            timings: Some(&mut timings),
+            ..Default::default()
+        };
+        let proof = generate_proof_with_options(
+            &id,
+            &tree.proof(0),
+            Field::from(1),
+            Field::from(2),
+            options,
+        )
+        .unwrap();
+        assert!(timings.witness > Duration::ZERO);
+        assert!(timings.proving > Duration::ZERO);
+        assert_eq!(timings.total(), timings.witness + timings.proving);
+        assert!(verify_proof(
+            tree.root(),
+            generate_nullifier_hash(&id, Field::from(1)),
+            Field::from(2),
+            Field::from(1),
+            &proof,
+            depth
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_low_memory_proof() {
+        let depth = supported_depths()[0];
+        let mut secret = *b"secret";
+        let id = Identity::from_secret(&mut secret, None);
+        let tree = LazyPoseidonTree::new(depth, Field::from(0))
+            .derived()
+            .update(0, &id.commitment());
+        let merkle_proof = tree.proof(0);
+        let (r, s) = (Fr::from(3), Fr::from(5));
+
+        let prove_with = |key: ProvingKey<'_>| {
+            prove(
+                key,
+                &crate::circuit::graph(depth),
+                &id,
+                &merkle_proof,
+                Field::from(1),
+                Field::from(2),
+                r,
+                s,
+                &mut WitnessScratch::new(),
+                &(),
+            )
+            .unwrap()
+            .0
+        };
+        let parsed = zkey(depth);
+        let expected = prove_with(ProvingKey::Parsed(&parsed, backend::backend()));
+        for max_memory_bytes in [0, 1 << 20] {
+            let key = ProvingKey::Streamed(crate::circuit::zkey_bytes(depth), max_memory_bytes);
+            assert_eq!(prove_with(key), expected);
+        }
+
+        let options = ProverOptions {
+            max_memory_bytes: Some(1 << 20),
+            ..Default::default()
+        };
+        let proof = generate_proof_with_options(
+            &id,
+            &merkle_proof,
+            Field::from(1),
+            Field::from(2),
+            options,
+        )
+        .unwrap();
+        assert!(verify_proof(
+            tree.root(),
+            generate_nullifier_hash(&id, Field::from(1)),
+            Field::from(2),
+            Field::from(1),
+            &proof,
+            depth
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_proof_progress() {
+        let depth = supported_depths()[0];
+        let mut secret = *b"secret";
+        let id = Identity::from_secret(&mut secret, None);
+        let tree = LazyPoseidonTree::new(depth, Field::from(0))
+            .derived()
+            .update(0, &id.commitment());
+
+        let events = RefCell::new(Vec::new());
+        let sink = |event: ProofProgress| events.borrow_mut().push(event);
+        let options = ProverOptions {
+            progress: Some(&sink),
+            ..Default::default()
+        };
+        generate_proof_with_options(&id, &tree.proof(0), Field::from(1), Field::from(2), options)
+            .unwrap();
+        assert_eq!(
+            events.into_inner(),
+            [
+                ProofProgress::WitnessStarted,
+                ProofProgress::WitnessDone,
+                ProofProgress::ProofDone
+            ]
+        );
+
+        let events = RefCell::new(Vec::new());
+        let sink = |event: ProofProgress| events.borrow_mut().push(event);
+        let merkle_proof = LazyPoseidonTree::new(7, Field::from(0)).proof(0);
+        let options = ProverOptions {
+            progress: Some(&sink),
+            ..Default::default()
+        };
+        assert!(generate_proof_with_options(
+            &id,
+            &merkle_proof,
+            Field::from(1),
+            Field::from(2),
+            options
+        )
+        .is_err());
+        assert!(events.into_inner().is_empty());
+    }
+
+    #[test]
+    fn test_generate_proofs_parallel() {
+        let depth = supported_depths()[0];
+        let ids: Vec<_> = (0..3_u8)
+            .map(|i| Identity::from_secret(&mut [i; 16], None))
+            .collect();
+        let mut tree = LazyPoseidonTree::new(depth, Field::from(0)).derived();
+        for (i, id) in ids.iter().enumerate() {
+            tree = tree.update(i, &id.commitment());
+        }
+        let mut requests: Vec<_> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| ProveRequest::new(id, tree.proof(i), Field::from(1), Field::from(i)))
+            .collect();
+        let unsupported = LazyPoseidonTree::new(7, Field::from(0)).proof(0);
+        requests.push(ProveRequest::new(
+            &ids[0],
+            unsupported,
+            Field::from(1),
+            Field::from(0),
+        ));
+
+        let results = generate_proofs_parallel_with_workers(&requests, 2);
+        assert_eq!(results.len(), requests.len());
+        for (i, id) in ids.iter().enumerate() {
+            assert!(verify_proof(
+                tree.root(),
+                generate_nullifier_hash(id, Field::from(1)),
+                Field::from(i),
+                Field::from(1),
+                results[i].as_ref().unwrap(),
+                depth
+            )
+            .unwrap());
+        }
+        assert!(matches!(results[3], Err(ProofError::UnsupportedDepth(7))));
+    }
+
+    #[test]
+    fn test_witness_scratch() {
+        let depth = supported_depths()[0];
+        let mut secret = *b"secret";
+        let id = Identity::from_secret(&mut secret, None);
+        let tree = LazyPoseidonTree::new(depth, Field::from(0))
+            .derived()
+            .update(0, &id.commitment());
+        let merkle_proof = tree.proof(0);
+        let nullifier_hash = generate_nullifier_hash(&id, Field::from(1));
+
+        let mut scratch = WitnessScratch::new();
+        let witness = generate_witness_with_scratch(
+            &id,
+            &merkle_proof,
+            Field::from(1),
+            Field::from(2),
+            &mut scratch,
+        )
+        .to_vec();
+        assert_eq!(
+            witness,
+            *generate_witness(&id, &merkle_proof, Field::from(1), Field::from(2))
+        );
+
+        let buffer = scratch.witness().as_ptr();
+        for signal_hash in [2_u64, 3].map(Field::from) {
+            let options = ProverOptions {
+                scratch: Some(&mut scratch),
+                ..Default::default()
+            };
+            let proof = generate_proof_with_options(
+                &id,
+                &merkle_proof,
+                Field::from(1),
+                signal_hash,
+                options,
+            )
+            .unwrap();
+            assert!(verify_proof(
+                tree.root(),
+                nullifier_hash,
+                signal_hash,
+                Field::from(1),
+                &proof,
+                depth
+            )
+            .unwrap());
+        }
+        // Proofs reuse the buffer of the first witness, and wipe it
+        assert_eq!(scratch.witness().as_ptr(), buffer);
+        assert!(scratch.witness().is_empty());
+
+        WitnessScratch::with_thread_local(|scratch| {
+            generate_witness_with_scratch(
+                &id,
+                &merkle_proof,
+                Field::from(1),
+                Field::from(2),
+                scratch,
+            );
+            WitnessScratch::with_thread_local(|nested| assert!(nested.witness().is_empty()));
+        });
+        WitnessScratch::with_thread_local(|scratch| assert_eq!(scratch.witness(), witness));
+    }
+
+    #[test]
+    fn test_resize_merkle_proof() {
+        let leaf = Field::from(42);
+        let shallow = LazyPoseidonTree::new(4, Field::from(0))
+            .derived()
+            .update(3, &leaf);
+        let deep = LazyPoseidonTree::new(8, Field::from(0))
+            .derived()
+            .update(3, &leaf);
+
+        let padded = resize_merkle_proof(&shallow.proof(3), 8, Field::from(0)).unwrap();
+        assert_eq!(padded, deep.proof(3));
+        assert_eq!(padded.root(leaf), deep.root());
+        let truncated = resize_merkle_proof(&padded, 4, Field::from(0)).unwrap();
+        assert_eq!(truncated, shallow.proof(3));
+
+        // Leaves beyond the shallow tree can't be truncated away
+        let deep = deep.update(100, &leaf);
+        assert!(matches!(
+            resize_merkle_proof(&deep.proof(3), 4, Field::from(0)),
+            Err(ProofError::DepthMismatch {
+                expected: 4,
+                actual: 8
+            })
+        ));
+        assert!(resize_merkle_proof(&deep.proof(100), 4, Field::from(0)).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_depth() {
+        let depth = 7;
+        assert!(!supported_depths().contains(&depth));
+        let mut secret = *b"secret";
+        let id = Identity::from_secret(&mut secret, None);
+        let merkle_proof = LazyPoseidonTree::new(depth, Field::from(0)).proof(0);
+        assert!(matches!(
+            generate_proof(&id, &merkle_proof, Field::from(1), Field::from(2)),
+            Err(ProofError::UnsupportedDepth(7))
+        ));
+
+        // Proofs of shallower trees can be padded to a supported depth
+        let depth = supported_depths()[0];
+        let tree = LazyPoseidonTree::new(depth - 2, Field::from(0))
+            .derived()
+            .update(1, &id.commitment());
+        let merkle_proof = resize_merkle_proof(&tree.proof(1), depth, Field::from(0)).unwrap();
+        let proof = generate_proof(&id, &merkle_proof, Field::from(1), Field::from(2)).unwrap();
+        let root = merkle_proof.root(id.commitment());
+        let nullifier_hash = generate_nullifier_hash(&id, Field::from(1));
+        assert!(verify_proof(
+            root,
+            nullifier_hash,
+            Field::from(2),
+            Field::from(1),
+            &proof,
+            depth
+        )
+        .unwrap());
+        assert!(matches!(
+            verify_proof(
+                root,
+                nullifier_hash,
+                Field::from(2),
+                Field::from(1),
+                &proof,
+                7
+            ),
+            Err(ProofError::UnsupportedDepth(7))
+        ));
+        assert!(matches!(
+            prepared_vk(7),
+            Err(ProofError::UnsupportedDepth(7))
+        ));
+        assert!(Arc::ptr_eq(
+            &prepared_vk(depth).unwrap(),
+            &prepared_vk(depth).unwrap()
+        ));
+    }
+
+    #[test_all_depths(prover)]
     fn test_proof_cast_roundtrip(depth: usize) {
         let proof = arb_proof(123, depth);
         let ark_proof: ArkProof<Bn<Config>> = proof.into();
@@ -290,7 +1281,7 @@ mod test {
         assert_eq!(proof, result);
     }
 
-    #[test_all_depths]
+    #[test_all_depths(prover)]
     fn test_proof_serialize(depth: usize) {
         let proof = arb_proof(456, depth);
         let json = serde_json::to_value(proof).unwrap();
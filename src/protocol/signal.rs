@@ -0,0 +1,294 @@
+//! Signals made of several fields, which can be disclosed selectively.
+//!
+//! A [`StructuredSignal`] commits to its fields with a Poseidon hash: every
+//! field is hashed with a random salt, and the salted hashes are hashed with
+//! the Poseidon sponge into the commitment. Proofs are generated for the
+//! commitment as an `uint256` signal, i.e. with [`StructuredSignal::hash`] as
+//! the signal hash, so contracts treat it like any other signal.
+//!
+//! The prover can later reveal any subset of the fields with a
+//! [`Disclosure`], which holds the values and salts of the revealed fields
+//! and only the salted hashes of the others. Verifiers recompute the
+//! signal hash from it and compare it with the one the proof was verified
+//! with. The salts keep hidden fields with few possible values, e.g. a
+//! country code, from being guessed from their hashes.
+
+use poseidon::poseidon::hash2;
+use poseidon::sponge::hash_fields;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::Signal;
+use crate::field::MODULUS;
+use crate::Field;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalError {
+    #[error("value {0} is not a field element")]
+    NotInField(usize),
+    #[error("expected {expected} salts, got {actual}")]
+    SaltCount { expected: usize, actual: usize },
+    #[error("field {index} is out of range for a signal of {len} fields")]
+    IndexOutOfRange { index: usize, len: usize },
+}
+
+/// A signal committing to several fields, kept by the prover to disclose
+/// them later.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StructuredSignal {
+    fields: Vec<Field>,
+    salts: Vec<Field>,
+}
+
+impl StructuredSignal {
+    /// Commits to the fields with random salts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a field is not a field element.
+    pub fn new(fields: Vec<Field>) -> Result<Self, SignalError> {
+        let mut rng = crate::randomness::rng();
+        let salts = fields
+            .iter()
+            .map(|_| Field::from_be_bytes(rng.gen::<[u8; 32]>()) % MODULUS)
+            .collect();
+        Self::with_salts(fields, salts)
+    }
+
+    /// Commits to the fields with the given salts, which must be secret and
+    /// unpredictable to keep undisclosed fields hidden.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a field or salt is not a field element, or if
+    /// there isn't one salt per field.
+    pub fn with_salts(fields: Vec<Field>, salts: Vec<Field>) -> Result<Self, SignalError> {
+        if salts.len() != fields.len() {
+            return Err(SignalError::SaltCount {
+                expected: fields.len(),
+                actual: salts.len(),
+            });
+        }
+        check_fields(&fields)?;
+        check_fields(&salts)?;
+        Ok(Self { fields, salts })
+    }
+
+    #[must_use]
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// The Poseidon commitment to all fields.
+    #[must_use]
+    pub fn commitment(&self) -> Field {
+        let hashes: Vec<_> = self
+            .fields
+            .iter()
+            .zip(&self.salts)
+            .map(|(&value, &salt)| salted_hash(value, salt))
+            .collect();
+        hash_fields(&hashes).expect("hashes are field elements")
+    }
+
+    /// The signal the proof is generated for, i.e. the commitment as an
+    /// `uint256`.
+    #[must_use]
+    pub fn signal(&self) -> Signal {
+        Signal::Uint(self.commitment())
+    }
+
+    /// The `signal_hash` to generate and verify proofs with.
+    #[must_use]
+    pub fn hash(&self) -> Field {
+        self.signal().hash()
+    }
+
+    /// Reveals the fields at the given indices and hides all others.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an index is out of range.
+    pub fn disclose(&self, indices: &[usize]) -> Result<Disclosure, SignalError> {
+        let len = self.fields.len();
+        if let Some(&index) = indices.iter().find(|&&index| index >= len) {
+            return Err(SignalError::IndexOutOfRange { index, len });
+        }
+        let items = self
+            .fields
+            .iter()
+            .zip(&self.salts)
+            .enumerate()
+            .map(|(index, (&value, &salt))| {
+                if indices.contains(&index) {
+                    DisclosedField::Revealed { value, salt }
+                } else {
+                    DisclosedField::Hidden {
+                        hash: salted_hash(value, salt),
+                    }
+                }
+            })
+            .collect();
+        Ok(Disclosure { fields: items })
+    }
+}
+
+/// A field of a [`Disclosure`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DisclosedField {
+    Revealed { value: Field, salt: Field },
+    Hidden { hash: Field },
+}
+
+/// Some fields of a [`StructuredSignal`], in order, with only the salted
+/// hashes of the fields that are not revealed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Disclosure {
+    pub fields: Vec<DisclosedField>,
+}
+
+impl Disclosure {
+    /// The revealed fields with their indices.
+    pub fn revealed(&self) -> impl Iterator<Item = (usize, Field)> + '_ {
+        self.fields
+            .iter()
+            .enumerate()
+            .filter_map(|(index, field)| match field {
+                DisclosedField::Revealed { value, .. } => Some((index, *value)),
+                DisclosedField::Hidden { .. } => None,
+            })
+    }
+
+    /// Returns the revealed field at `index`, if it is revealed.
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<Field> {
+        match self.fields.get(index)? {
+            DisclosedField::Revealed { value, .. } => Some(*value),
+            DisclosedField::Hidden { .. } => None,
+        }
+    }
+
+    /// Recomputes the commitment of the signal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a value, salt or hash is not a field element.
+    pub fn commitment(&self) -> Result<Field, SignalError> {
+        let hashes = self
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| {
+                let in_field = |value: Field| {
+                    if value < MODULUS {
+                        Ok(value)
+                    } else {
+                        Err(SignalError::NotInField(index))
+                    }
+                };
+                match *field {
+                    DisclosedField::Revealed { value, salt } => {
+                        Ok(salted_hash(in_field(value)?, in_field(salt)?))
+                    }
+                    DisclosedField::Hidden { hash } => in_field(hash),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(hash_fields(&hashes).expect("hashes are field elements"))
+    }
+
+    /// Returns whether the disclosure belongs to the signal a proof was
+    /// verified with.
+    ///
+    /// This only checks the disclosure. The proof has to be verified with
+    /// `signal_hash` separately.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a value, salt or hash is not a field element.
+    pub fn verify(&self, signal_hash: Field) -> Result<bool, SignalError> {
+        Ok(Signal::Uint(self.commitment()?).hash() == signal_hash)
+    }
+}
+
+fn salted_hash(value: Field, salt: Field) -> Field {
+    hash2(value, salt)
+}
+
+fn check_fields(values: &[Field]) -> Result<(), SignalError> {
+    match values.iter().position(|value| *value >= MODULUS) {
+        Some(index) => Err(SignalError::NotInField(index)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal() -> StructuredSignal {
+        StructuredSignal::with_salts(
+            vec![Field::from(1), Field::from(840), Field::from(1990)],
+            vec![Field::from(11), Field::from(12), Field::from(13)],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_disclosure() {
+        let signal = signal();
+        let disclosure = signal.disclose(&[1]).unwrap();
+        assert_eq!(disclosure.get(1), Some(Field::from(840)));
+        assert_eq!(disclosure.get(0), None);
+        assert_eq!(
+            disclosure.revealed().collect::<Vec<_>>(),
+            [(1, Field::from(840))]
+        );
+        assert_eq!(disclosure.commitment().unwrap(), signal.commitment());
+        assert!(disclosure.verify(signal.hash()).unwrap());
+
+        // Revealing everything or nothing commits to the same signal
+        for indices in [&[][..], &[0, 1, 2]] {
+            assert!(signal
+                .disclose(indices)
+                .unwrap()
+                .verify(signal.hash())
+                .unwrap());
+        }
+
+        // A changed value doesn't match
+        let mut forged = disclosure;
+        forged.fields[1] = DisclosedField::Revealed {
+            value: Field::from(250),
+            salt: Field::from(12),
+        };
+        assert!(!forged.verify(signal.hash()).unwrap());
+
+        assert_eq!(
+            signal.disclose(&[3]),
+            Err(SignalError::IndexOutOfRange { index: 3, len: 3 })
+        );
+    }
+
+    #[test]
+    fn test_salts() {
+        let fields = vec![Field::from(1), Field::from(2)];
+        let a = StructuredSignal::new(fields.clone()).unwrap();
+        let b = StructuredSignal::new(fields.clone()).unwrap();
+        assert_ne!(a.commitment(), b.commitment());
+
+        assert_eq!(
+            StructuredSignal::with_salts(fields.clone(), vec![Field::from(1)]),
+            Err(SignalError::SaltCount {
+                expected: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(
+            StructuredSignal::with_salts(fields, vec![Field::from(1), MODULUS]),
+            Err(SignalError::NotInField(1))
+        );
+    }
+}
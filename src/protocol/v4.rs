@@ -0,0 +1,385 @@
+//! Semaphore v4 proofs.
+//!
+//! Compared to v3, the public inputs are named `message` and `scope` instead
+//! of signal and external nullifier, the nullifier is
+//! `poseidon(scope, secret)` and the depth of the Merkle proof is a witness.
+//! A single circuit proves membership in trees of any depth up to its own, so
+//! proofs for a tree use the circuit of the smallest supported depth that
+//! fits, see [`circuit_depth`].
+//!
+//! v4 identities are Baby Jubjub key pairs. The circuit takes the secret
+//! scalar derived from the private key, and the identity commitment is the
+//! Poseidon hash of the public key. Messages and scopes are hashed to the
+//! field with [`hash_to_field_bytes`] before being passed here.
+//!
+//! The v4 contracts keep groups in a [`LeanPoseidonTree`], whose proofs can be
+//! passed here as they are. Like `merkleTreeDepth` in the JS SDK, the depth
+//! to verify with is the length of the Merkle proof the proof was generated
+//! with, not [`LeanIMT::depth`](trees::lean_imt::LeanIMT::depth): lean
+//! proofs skip the levels without a sibling, so they can be shorter than the
+//! tree is deep and are proven with a smaller circuit.
+//!
+//! [`hash_to_field_bytes`]: crate::hash_to_field_bytes
+//! [`LeanPoseidonTree`]: crate::poseidon_tree::LeanPoseidonTree
+
+#[cfg(feature = "v4-prover")]
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ark_bn254::Bn254;
+#[cfg(feature = "v4-prover")]
+use ark_bn254::Fr;
+#[cfg(feature = "v4-prover")]
+use ark_ff::PrimeField;
+use ark_groth16::PreparedVerifyingKey;
+#[cfg(feature = "v4-prover")]
+use ark_std::UniformRand;
+use poseidon::Poseidon;
+#[cfg(feature = "v4-prover")]
+use rand::Rng;
+use semaphore_depth_config::get_supported_depths;
+use trees::Branch;
+#[cfg(feature = "v4-prover")]
+use zeroize::Zeroizing;
+
+#[cfg(feature = "v4-prover")]
+use super::ZeroizingVec;
+use super::{backend, Proof, ProofError};
+use crate::circuit::v4_verifying_key;
+#[cfg(feature = "v4-prover")]
+use crate::circuit::{has_v4_graph, v4_zkey, WitnessError};
+#[cfg(feature = "v4-prover")]
+use crate::randomness;
+use crate::Field;
+
+/// Returns the depth of the circuit used for trees of the given depth, or
+/// `None` if it is larger than all supported depths.
+#[must_use]
+pub fn circuit_depth(tree_depth: usize) -> Option<usize> {
+    get_supported_depths()
+        .iter()
+        .copied()
+        .filter(|&depth| depth >= tree_depth)
+        .min()
+}
+
+#[cfg(feature = "v4-prover")]
+fn checked_circuit_depth(tree_depth: usize) -> Result<usize, ProofError> {
+    circuit_depth(tree_depth).ok_or(ProofError::UnsupportedDepth(tree_depth))
+}
+
+/// Returns the depths of the circuits with a witness graph, which v4 proofs
+/// can be generated with. These are the supported depths whose graph is in
+/// `graphs/v4` at build time.
+#[cfg(feature = "v4-prover")]
+#[must_use]
+pub fn prover_depths() -> Vec<usize> {
+    get_supported_depths()
+        .iter()
+        .copied()
+        .filter(|&depth| has_v4_graph(depth))
+        .collect()
+}
+
+/// Generates the nullifier of an identity for a scope
+#[must_use]
+pub fn generate_nullifier(secret: Field, scope: Field) -> Field {
+    poseidon::poseidon::hash2(scope, secret)
+}
+
+/// Returns the leaf index proven by a Merkle proof, as the v4 circuit expects
+/// it: bit `i` is set when the node at height `i` is a right child.
+#[must_use]
+pub fn merkle_proof_index(merkle_proof: &trees::Proof<Poseidon>) -> Field {
+    merkle_proof
+        .0
+        .iter()
+        .enumerate()
+        .filter(|(_, branch)| matches!(branch, Branch::Right(_)))
+        .fold(Field::ZERO, |index, (height, _)| {
+            index | (Field::from(1) << height)
+        })
+}
+
+/// Generates a Semaphore v4 proof
+///
+/// # Errors
+///
+/// Returns [`ProofError::NoCircuit`] if the circuit for the depth of the
+/// Merkle proof has no witness graph, see [`prover_depths`],
+/// [`ProofError::UnsupportedDepth`] if it is deeper than all supported
+/// depths, or another [`ProofError`] if proving fails.
+#[cfg(feature = "v4-prover")]
+pub fn generate_proof(
+    secret: Field,
+    merkle_proof: &trees::Proof<Poseidon>,
+    message: Field,
+    scope: Field,
+) -> Result<Proof, ProofError> {
+    generate_proof_rng(secret, merkle_proof, message, scope, &mut randomness::rng())
+}
+
+/// Generates a Semaphore v4 proof from entropy
+///
+/// # Errors
+///
+/// Returns [`ProofError::NoCircuit`] if the circuit for the depth of the
+/// Merkle proof has no witness graph, see [`prover_depths`],
+/// [`ProofError::UnsupportedDepth`] if it is deeper than all supported
+/// depths, or another [`ProofError`] if proving fails.
+#[cfg(feature = "v4-prover")]
+pub fn generate_proof_rng(
+    secret: Field,
+    merkle_proof: &trees::Proof<Poseidon>,
+    message: Field,
+    scope: Field,
+    rng: &mut impl Rng,
+) -> Result<Proof, ProofError> {
+    let depth = checked_circuit_depth(merkle_proof.0.len())?;
+    if !has_v4_graph(depth) {
+        return Err(ProofError::NoCircuit {
+            depth: merkle_proof.0.len(),
+            supported: prover_depths(),
+        });
+    }
+    let full_assignment = generate_witness(secret, merkle_proof, message, scope)?;
+
+    let zkey = v4_zkey(depth);
+    let ark_proof =
+        backend::backend().prove(&zkey, &full_assignment, Fr::rand(rng), Fr::rand(rng))?;
+    Ok(ark_proof.into())
+}
+
+/// Computes the witness of the v4 circuit
+///
+/// The witness holds the secret and is wiped when dropped, see
+/// [`ZeroizingVec`].
+///
+/// # Errors
+///
+/// Returns [`ProofError::UnsupportedDepth`] if the Merkle proof is deeper
+/// than all supported depths, or [`ProofError::WitnessError`] if the witness
+/// can't be calculated.
+///
+/// # Panics
+///
+/// Panics if the circuit for the depth of the Merkle proof has no witness
+/// graph, see [`prover_depths`].
+#[cfg(feature = "v4-prover")]
+pub fn generate_witness(
+    secret: Field,
+    merkle_proof: &trees::Proof<Poseidon>,
+    message: Field,
+    scope: Field,
+) -> Result<ZeroizingVec<Fr>, ProofError> {
+    let depth = merkle_proof.0.len();
+    let circuit_depth = checked_circuit_depth(depth)?;
+
+    // Siblings above the proof are unused, the circuit requires them to be 0
+    let mut siblings = super::merkle_proof_to_vec(merkle_proof);
+    siblings.resize(circuit_depth, Field::ZERO);
+
+    let inputs = HashMap::from([
+        ("secret".to_owned(), vec![secret]),
+        ("merkleProofLength".to_owned(), vec![Field::from(depth)]),
+        (
+            "merkleProofIndex".to_owned(),
+            vec![merkle_proof_index(merkle_proof)],
+        ),
+        ("merkleProofSiblings".to_owned(), siblings),
+        ("message".to_owned(), vec![message]),
+        ("scope".to_owned(), vec![scope]),
+    ]);
+
+    let graph = crate::circuit::v4_graph(circuit_depth);
+
+    let witness = Zeroizing::new(
+        witness::calculate_witness(inputs, &graph).map_err(WitnessError::from_report)?,
+    );
+    Ok(Zeroizing::new(
+        witness
+            .iter()
+            .map(|x| Fr::from_bigint((*x).into()).expect("Couldn't cast U256 to BigInteger"))
+            .collect(),
+    ))
+}
+
+/// Returns the prepared verifying key of the circuit used for trees of the
+/// given depth, see [`circuit_depth`].
+///
+/// Keys are prepared once per circuit and kept in the
+/// [`ArtifactCache::global`], so [`verify_proof`] doesn't prepare them on
+/// every call.
+///
+/// # Errors
+///
+/// Returns [`ProofError::UnsupportedDepth`] if the depth is larger than all
+/// supported depths.
+///
+/// [`ArtifactCache::global`]: crate::circuit::ArtifactCache::global
+pub fn prepared_vk(
+    merkle_tree_depth: usize,
+) -> Result<Arc<PreparedVerifyingKey<Bn254>>, ProofError> {
+    let depth =
+        circuit_depth(merkle_tree_depth).ok_or(ProofError::UnsupportedDepth(merkle_tree_depth))?;
+    Ok(v4_verifying_key(depth))
+}
+
+/// Verifies a given Semaphore v4 proof
+///
+/// `merkle_tree_depth` is the length of the Merkle proof the proof was
+/// generated with, which selects the circuit to verify with, see
+/// [`circuit_depth`]. It is the `merkleTreeDepth` of proofs of the JS SDK.
+///
+/// # Errors
+///
+/// Returns [`ProofError::UnsupportedDepth`] if `merkle_tree_depth` is larger
+/// than all supported depths, or another [`ProofError`] if verifying fails.
+/// Verification failure does not necessarily mean the proof is incorrect.
+pub fn verify_proof(
+    merkle_root: Field,
+    nullifier: Field,
+    message: Field,
+    scope: Field,
+    proof: &Proof,
+    merkle_tree_depth: usize,
+) -> Result<bool, ProofError> {
+    let pvk = prepared_vk(merkle_tree_depth)?;
+
+    let public_inputs = [merkle_root, nullifier, message, scope]
+        .iter()
+        .map(ark_bn254::Fr::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let ark_proof = (*proof).into();
+    backend::backend().verify(&pvk, &ark_proof, &public_inputs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::poseidon_tree::{LazyPoseidonTree, LeanPoseidonTree};
+
+    #[test]
+    fn test_circuit_depth() {
+        let depths = get_supported_depths();
+        let max = *depths.iter().max().unwrap();
+        assert_eq!(circuit_depth(max), Some(max));
+        assert_eq!(circuit_depth(max + 1), None);
+        assert_eq!(circuit_depth(1), depths.iter().min().copied());
+    }
+
+    #[test]
+    fn test_merkle_proof_index() {
+        let tree = LazyPoseidonTree::new(4, Field::ZERO).derived();
+        for index in [0, 1, 6, 15] {
+            assert_eq!(merkle_proof_index(&tree.proof(index)), Field::from(index));
+        }
+
+        // Lean proofs skip levels without a sibling, so the index is
+        // compressed: the last of 5 leaves is only a right child at the top
+        let leaves = (1..=5).map(Field::from);
+        let tree = LeanPoseidonTree::from_leaves(leaves).unwrap();
+        assert_eq!(merkle_proof_index(&tree.proof(4).unwrap()), Field::from(1));
+        assert_eq!(merkle_proof_index(&tree.proof(3).unwrap()), Field::from(3));
+    }
+
+    #[cfg(all(feature = "v4-prover", feature = "depth_16"))]
+    #[test]
+    fn test_lean_proof_depth() {
+        use hasher::Hasher;
+
+        // Of 2^h + 2^(h-1) leaves, the last 2^(h-1) are in a subtree without
+        // a sibling, so their proofs have h siblings in a tree of depth h + 1.
+        // With all other leaves 1, the siblings are the roots of subtrees of
+        // ones, so the proof is built without a tree of that size
+        let ones: Vec<Field> = std::iter::successors(Some(Field::from(1)), |node| {
+            Some(Poseidon::hash_node(node, node))
+        })
+        .take(17)
+        .collect();
+        let lean_proof = |h: usize| {
+            let mut branches: Vec<_> = ones[..h - 1].iter().map(|&n| Branch::Right(n)).collect();
+            branches.push(Branch::Right(ones[h]));
+            trees::Proof(branches)
+        };
+        let size = (1 << 4) + (1 << 3);
+        let mut leaves = vec![Field::from(1); size];
+        leaves[size - 1] = Field::from(2);
+        let tree = LeanPoseidonTree::from_leaves(leaves).unwrap();
+        assert_eq!(tree.depth(), 5);
+        assert_eq!(tree.proof(size - 1).unwrap(), lean_proof(4));
+
+        // At h = 16 the tree has depth 17, and the proof is proven with the
+        // circuit of depth 16, so it must be verified with it
+        let merkle_proof = lean_proof(16);
+        assert_eq!(merkle_proof.0.len(), 16);
+        assert_eq!(circuit_depth(merkle_proof.0.len()), Some(16));
+        if !has_v4_graph(16) {
+            return;
+        }
+
+        let secret = Field::from(0x1234_5678);
+        let (message, scope) = (Field::from(1), Field::from(2));
+        let witness = generate_witness(secret, &trees::Proof(vec![]), message, scope).unwrap();
+        let commitment = Field::from_limbs(witness[1].into_bigint().0);
+        let root = merkle_proof.root(commitment);
+        let nullifier = generate_nullifier(secret, scope);
+        let proof = generate_proof(secret, &merkle_proof, message, scope).unwrap();
+        assert!(verify_proof(root, nullifier, message, scope, &proof, 16).unwrap());
+        assert!(!verify_proof(root, nullifier, message, scope, &proof, 17).unwrap_or(false));
+    }
+
+    #[cfg(feature = "v4-prover")]
+    #[test]
+    fn test_proof() {
+        use crate::hash_to_field_bytes;
+
+        let depth = get_supported_depths()[0];
+        if !has_v4_graph(depth) {
+            assert!(matches!(
+                generate_proof(Field::from(1), &trees::Proof(vec![]), Field::ZERO, Field::ZERO),
+                Err(ProofError::NoCircuit { depth: 0, supported }) if !supported.contains(&depth)
+            ));
+            return;
+        }
+
+        let secret = Field::from(0x1234_5678);
+        let message = hash_to_field_bytes(b"message");
+        let scope = hash_to_field_bytes(b"scope");
+
+        // The root of an empty Merkle proof is the leaf itself, so the
+        // public outputs are the identity commitment and the nullifier
+        let witness = generate_witness(secret, &trees::Proof(vec![]), message, scope).unwrap();
+        let commitment = Field::from_limbs(witness[1].into_bigint().0);
+        let nullifier = Field::from_limbs(witness[2].into_bigint().0);
+        assert_eq!(nullifier, generate_nullifier(secret, scope));
+
+        // Trees smaller than the circuit are padded
+        let depth = depth - 6;
+        let tree = LazyPoseidonTree::new(depth, Field::ZERO)
+            .derived()
+            .update(5, &commitment);
+        let proof = generate_proof(secret, &tree.proof(5), message, scope).unwrap();
+
+        assert!(verify_proof(tree.root(), nullifier, message, scope, &proof, depth).unwrap());
+        let other = hash_to_field_bytes(b"other");
+        assert!(!verify_proof(tree.root(), nullifier, other, scope, &proof, depth).unwrap());
+        assert!(!verify_proof(tree.root(), nullifier, message, other, &proof, depth).unwrap());
+
+        // Proofs of lean trees are shorter than their depth at the edges
+        let leaves = (1..=5).map(Field::from).chain([commitment]);
+        let tree = LeanPoseidonTree::from_leaves(leaves).unwrap();
+        let merkle_proof = tree.proof(5).unwrap();
+        let proof = generate_proof(secret, &merkle_proof, message, scope).unwrap();
+        let root = tree.root().unwrap();
+        let depth = merkle_proof.0.len();
+        assert!(verify_proof(root, nullifier, message, scope, &proof, depth).unwrap());
+
+        let max = *get_supported_depths().iter().max().unwrap();
+        assert!(matches!(
+            generate_proof(secret, &trees::Proof(vec![Branch::Left(Field::ZERO); max + 1]), message, scope),
+            Err(ProofError::UnsupportedDepth(depth)) if depth == max + 1
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+//! Validation of the points of a [`Proof`].
+//!
+//! [`Proof`] holds any eight words, and invalid ones otherwise only show up
+//! as errors of the verifier. [`Proof::validate`] checks that every
+//! coordinate is below the base field modulus, that every point is on its
+//! curve and that `B` is in the prime order subgroup, which the points of
+//! `G1` always are. Points at infinity, which Ethereum represents as all
+//! zeros, are valid.
+//!
+//! With the `strict-serde` feature, proofs are validated when they are
+//! deserialized.
+
+use std::fmt;
+
+use ark_bn254::{Fq, Fq2, G1Affine, G2Affine};
+use ark_ff::{BigInt, PrimeField};
+use ethers_core::types::U256;
+#[cfg(feature = "strict-serde")]
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::{Proof, G1, G2};
+
+/// A point of a [`Proof`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProofPoint {
+    A,
+    B,
+    C,
+}
+
+impl fmt::Display for ProofPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::A => "A",
+            Self::B => "B",
+            Self::C => "C",
+        })
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofValidationError {
+    #[error("coordinate of point {0} is not below the field modulus")]
+    NonCanonical(ProofPoint),
+    #[error("point {0} is not on the curve")]
+    NotOnCurve(ProofPoint),
+    #[error("point {0} is not in the prime order subgroup")]
+    NotInSubgroup(ProofPoint),
+}
+
+impl Proof {
+    /// Checks that the points of the proof are valid curve points.
+    ///
+    /// This doesn't check that the proof verifies, only that it can be passed
+    /// to the verifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the first point that has a coordinate not below
+    /// the field modulus, is not on its curve, or is not in the prime order
+    /// subgroup.
+    pub fn validate(&self) -> Result<(), ProofValidationError> {
+        validate_g1(self.0, ProofPoint::A)?;
+        validate_g2(self.1, ProofPoint::B)?;
+        validate_g1(self.2, ProofPoint::C)
+    }
+}
+
+fn validate_g1((x, y): G1, point: ProofPoint) -> Result<(), ProofValidationError> {
+    if x.is_zero() && y.is_zero() {
+        return Ok(());
+    }
+    let affine = G1Affine::new_unchecked(to_fq(x, point)?, to_fq(y, point)?);
+    if !affine.is_on_curve() {
+        return Err(ProofValidationError::NotOnCurve(point));
+    }
+    Ok(())
+}
+
+fn validate_g2((x, y): G2, point: ProofPoint) -> Result<(), ProofValidationError> {
+    if x.iter().chain(&y).all(U256::is_zero) {
+        return Ok(());
+    }
+    let affine = G2Affine::new_unchecked(to_fq2(x, point)?, to_fq2(y, point)?);
+    if !affine.is_on_curve() {
+        return Err(ProofValidationError::NotOnCurve(point));
+    }
+    if !affine.is_in_correct_subgroup_assuming_on_curve() {
+        return Err(ProofValidationError::NotInSubgroup(point));
+    }
+    Ok(())
+}
+
+fn to_fq(value: U256, point: ProofPoint) -> Result<Fq, ProofValidationError> {
+    Fq::from_bigint(BigInt(value.0)).ok_or(ProofValidationError::NonCanonical(point))
+}
+
+/// Converts Ethereum ordered coordinates, imaginary part first.
+fn to_fq2([c1, c0]: [U256; 2], point: ProofPoint) -> Result<Fq2, ProofValidationError> {
+    Ok(Fq2::new(to_fq(c0, point)?, to_fq(c1, point)?))
+}
+
+/// The serialized form of a [`Proof`], deserialized before validating it.
+#[cfg(feature = "strict-serde")]
+#[derive(Deserialize)]
+#[serde(rename = "Proof")]
+pub(super) struct UncheckedProof(G1, G2, G1);
+
+#[cfg(feature = "strict-serde")]
+impl TryFrom<UncheckedProof> for Proof {
+    type Error = ProofValidationError;
+
+    fn try_from(UncheckedProof(a, b, c): UncheckedProof) -> Result<Self, Self::Error> {
+        let proof = Self(a, b, c);
+        proof.validate()?;
+        Ok(proof)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ark_ec::short_weierstrass::SWCurveConfig;
+    use ark_ff::Field;
+
+    use super::*;
+
+    fn modulus() -> U256 {
+        U256(Fq::MODULUS.0)
+    }
+
+    #[test]
+    fn test_validate() {
+        Proof::dummy_from_seed(1).validate().unwrap();
+        Proof::dummy_infinity().validate().unwrap();
+
+        let mut proof = Proof::dummy_from_seed(2);
+        proof.2 .1 += modulus();
+        assert_eq!(
+            proof.validate(),
+            Err(ProofValidationError::NonCanonical(ProofPoint::C))
+        );
+
+        let mut proof = Proof::dummy_from_seed(3);
+        proof.0 .1 ^= U256::one();
+        assert_eq!(
+            proof.validate(),
+            Err(ProofValidationError::NotOnCurve(ProofPoint::A))
+        );
+
+        let mut proof = Proof::dummy_from_seed(4);
+        proof.1 .0[1] ^= U256::one();
+        assert_eq!(
+            proof.validate(),
+            Err(ProofValidationError::NotOnCurve(ProofPoint::B))
+        );
+    }
+
+    #[test]
+    fn test_not_in_subgroup() {
+        // A point of the twist that is not in the prime order subgroup, found
+        // by taking the first x with a square right hand side.
+        let b = (1_u64..)
+            .map(|x| Fq2::new(Fq::from(x), Fq::from(0)))
+            .find_map(|x| {
+                let rhs = x.square() * x + ark_bn254::g2::Config::COEFF_B;
+                rhs.sqrt().map(|y| G2Affine::new_unchecked(x, y))
+            })
+            .unwrap();
+        assert!(!b.is_in_correct_subgroup_assuming_on_curve());
+
+        let mut proof = Proof::dummy_from_seed(5);
+        proof.1 = (to_words(b.x), to_words(b.y));
+        assert_eq!(
+            proof.validate(),
+            Err(ProofValidationError::NotInSubgroup(ProofPoint::B))
+        );
+    }
+
+    fn to_words(value: Fq2) -> [U256; 2] {
+        [
+            U256(value.c1.into_bigint().0),
+            U256(value.c0.into_bigint().0),
+        ]
+    }
+
+    #[cfg(feature = "strict-serde")]
+    #[test]
+    fn test_strict_serde() {
+        let proof = Proof::dummy_from_seed(6);
+        let json = serde_json::to_string(&proof).unwrap();
+        assert_eq!(serde_json::from_str::<Proof>(&json).unwrap(), proof);
+
+        let mut invalid = proof;
+        invalid.0 .1 ^= U256::one();
+        let json = serde_json::to_string(&invalid).unwrap();
+        let err = serde_json::from_str::<Proof>(&json).unwrap_err();
+        assert!(err.to_string().contains("point A is not on the curve"));
+    }
+}
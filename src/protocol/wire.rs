@@ -0,0 +1,366 @@
+//! Versioned messages between provers and their clients.
+//!
+//! Services that generate or verify proofs for others, e.g. prover sidecars,
+//! exchange a [`Request`] for a [`Response`]. Messages are sent in an
+//! envelope with the wire [`VERSION`], either as JSON with [`to_json`] or in
+//! the binary [postcard](https://docs.rs/postcard) format with [`to_bytes`].
+//! Decoding checks the version before the message, so peers speaking another
+//! version get [`WireError::UnsupportedVersion`] rather than a parse error.
+//!
+//! Fields are hex strings in JSON and big-endian bytes in postcard. The
+//! binary format has no field names, so any change to a message, including
+//! new fields or variants, comes with a new version.
+
+use poseidon::Poseidon;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+#[cfg(feature = "prover")]
+use super::{generate_nullifier_hash, generate_proof};
+use super::{verify_proof, Proof, ProofError};
+use crate::identity::Identity;
+use crate::Field;
+
+/// The version of the messages of this module.
+pub const VERSION: u16 = 1;
+
+#[derive(Error, Debug)]
+pub enum WireError {
+    #[error("unsupported wire version {0}, expected {VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("invalid JSON message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid binary message: {0}")]
+    Binary(#[from] postcard::Error),
+    #[error("binary message has trailing bytes")]
+    TrailingBytes,
+}
+
+/// Asks for a proof of membership of an identity.
+///
+/// This holds the identity secrets, so it must only be sent to trusted
+/// provers over a secure channel. The secrets are left out of the `Debug`
+/// output and zeroized when the request is dropped.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProveRequest {
+    pub trapdoor: Field,
+    pub nullifier: Field,
+    pub merkle_proof: trees::Proof<Poseidon>,
+    pub external_nullifier_hash: Field,
+    pub signal_hash: Field,
+}
+
+impl std::fmt::Debug for ProveRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProveRequest")
+            .field("merkle_proof", &self.merkle_proof)
+            .field("external_nullifier_hash", &self.external_nullifier_hash)
+            .field("signal_hash", &self.signal_hash)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for ProveRequest {
+    fn drop(&mut self) {
+        self.trapdoor.zeroize();
+        self.nullifier.zeroize();
+    }
+}
+
+impl ProveRequest {
+    #[must_use]
+    pub fn new(
+        identity: &Identity,
+        merkle_proof: trees::Proof<Poseidon>,
+        external_nullifier_hash: Field,
+        signal_hash: Field,
+    ) -> Self {
+        Self {
+            trapdoor: identity.trapdoor,
+            nullifier: identity.nullifier,
+            merkle_proof,
+            external_nullifier_hash,
+            signal_hash,
+        }
+    }
+
+    #[must_use]
+    pub fn identity(&self) -> Identity {
+        Identity {
+            trapdoor: self.trapdoor,
+            nullifier: self.nullifier,
+        }
+    }
+
+    /// Generates the requested proof.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ProofError`] if proving fails.
+    #[cfg(feature = "prover")]
+    pub fn prove(&self) -> Result<ProveResponse, ProofError> {
+        let identity = self.identity();
+        let proof = generate_proof(
+            &identity,
+            &self.merkle_proof,
+            self.external_nullifier_hash,
+            self.signal_hash,
+        )?;
+        Ok(ProveResponse {
+            root: self.merkle_proof.root(identity.commitment()),
+            nullifier_hash: generate_nullifier_hash(&identity, self.external_nullifier_hash),
+            proof,
+        })
+    }
+}
+
+/// A proof with the public inputs the prover computed for it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProveResponse {
+    pub root: Field,
+    pub nullifier_hash: Field,
+    pub proof: Proof,
+}
+
+/// Asks whether a proof is valid.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyRequest {
+    pub depth: usize,
+    pub root: Field,
+    pub nullifier_hash: Field,
+    pub signal_hash: Field,
+    pub external_nullifier_hash: Field,
+    pub proof: Proof,
+}
+
+impl VerifyRequest {
+    /// Verifies the proof.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ProofError`] if the depth is not supported or verifying
+    /// fails.
+    pub fn verify(&self) -> Result<VerifyResponse, ProofError> {
+        let valid = verify_proof(
+            self.root,
+            self.nullifier_hash,
+            self.signal_hash,
+            self.external_nullifier_hash,
+            &self.proof,
+            self.depth,
+        )?;
+        Ok(VerifyResponse { valid })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyResponse {
+    pub valid: bool,
+}
+
+/// A request that failed, e.g. for an unsupported depth.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub message: String,
+}
+
+/// A message to a prover.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Request {
+    Prove(ProveRequest),
+    Verify(VerifyRequest),
+}
+
+impl Request {
+    /// Serves the request, turning failures into [`Response::Error`].
+    #[cfg(feature = "prover")]
+    #[must_use]
+    pub fn handle(&self) -> Response {
+        let response = match self {
+            Self::Prove(request) => request.prove().map(Response::Prove),
+            Self::Verify(request) => request.verify().map(Response::Verify),
+        };
+        response.unwrap_or_else(|error| {
+            Response::Error(ErrorResponse {
+                message: error.to_string(),
+            })
+        })
+    }
+}
+
+/// The answer of a prover to a [`Request`].
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Response {
+    Prove(ProveResponse),
+    Verify(VerifyResponse),
+    Error(ErrorResponse),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    version: u16,
+    message: T,
+}
+
+/// Only the version of an envelope, to check it before the message.
+#[derive(Deserialize)]
+struct Version {
+    version: u16,
+}
+
+fn check_version(version: u16) -> Result<(), WireError> {
+    if version == VERSION {
+        Ok(())
+    } else {
+        Err(WireError::UnsupportedVersion(version))
+    }
+}
+
+/// Encodes a message as JSON, e.g.
+/// `{"version":1,"message":{"verify":{"valid":true}}}`.
+///
+/// # Errors
+///
+/// Returns an error if the message fails to serialize.
+pub fn to_json<T: Serialize>(message: &T) -> Result<String, WireError> {
+    Ok(serde_json::to_string(&Envelope {
+        version: VERSION,
+        message,
+    })?)
+}
+
+/// Decodes a message encoded with [`to_json`].
+///
+/// # Errors
+///
+/// Returns an error if the version is not [`VERSION`] or the message is
+/// invalid.
+pub fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, WireError> {
+    check_version(serde_json::from_str::<Version>(json)?.version)?;
+    Ok(serde_json::from_str::<Envelope<T>>(json)?.message)
+}
+
+/// Encodes a message in postcard, prefixed with the version as a varint.
+///
+/// # Errors
+///
+/// Returns an error if the message fails to serialize.
+pub fn to_bytes<T: Serialize>(message: &T) -> Result<Vec<u8>, WireError> {
+    Ok(postcard::to_stdvec(&Envelope {
+        version: VERSION,
+        message,
+    })?)
+}
+
+/// Decodes a message encoded with [`to_bytes`].
+///
+/// # Errors
+///
+/// Returns an error if the version is not [`VERSION`], the message is
+/// invalid or followed by more bytes.
+pub fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, WireError> {
+    let (version, bytes) = postcard::take_from_bytes::<u16>(bytes)?;
+    check_version(version)?;
+    let (message, rest) = postcard::take_from_bytes(bytes)?;
+    if !rest.is_empty() {
+        return Err(WireError::TrailingBytes);
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::U256;
+    use trees::Branch;
+
+    use super::*;
+
+    fn requests() -> Vec<Request> {
+        let word = |n: u64| U256::from(n);
+        let proof = Proof(
+            (word(1), word(2)),
+            ([word(3), word(4)], [word(5), word(6)]),
+            (word(7), word(8)),
+        );
+        vec![
+            Request::Prove(ProveRequest::new(
+                &Identity::from_secret(&mut b"secret".to_vec(), None),
+                trees::Proof(vec![
+                    Branch::Left(Field::from(1)),
+                    Branch::Right(Field::from(2)),
+                ]),
+                Field::from(3),
+                Field::from(4),
+            )),
+            Request::Verify(VerifyRequest {
+                depth: 16,
+                root: Field::from(1),
+                nullifier_hash: Field::from(2),
+                signal_hash: Field::from(3),
+                external_nullifier_hash: Field::from(4),
+                proof,
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for request in requests() {
+            let json = to_json(&request).unwrap();
+            assert_eq!(from_json::<Request>(&json).unwrap(), request);
+            let bytes = to_bytes(&request).unwrap();
+            assert_eq!(from_bytes::<Request>(&bytes).unwrap(), request);
+        }
+
+        let response = Response::Verify(VerifyResponse { valid: true });
+        assert_eq!(
+            to_json(&response).unwrap(),
+            r#"{"version":1,"message":{"verify":{"valid":true}}}"#
+        );
+        // Version 1, variant 1, true
+        assert_eq!(to_bytes(&response).unwrap(), [1, 1, 1]);
+    }
+
+    #[test]
+    fn test_prove_request_debug() {
+        let identity = Identity::from_secret(&mut b"secret".to_vec(), None);
+        let request = ProveRequest::new(
+            &identity,
+            trees::Proof(vec![]),
+            Field::from(3),
+            Field::from(4),
+        );
+        let debug = format!("{:?}", Request::Prove(request));
+        for secret in [identity.trapdoor, identity.nullifier] {
+            assert!(!debug.contains(&format!("{secret:?}")));
+            assert!(!debug.contains(&format!("{secret:x}")));
+        }
+        assert!(debug.contains("signal_hash"));
+    }
+
+    #[test]
+    fn test_version() {
+        let json = r#"{"version":2,"message":{"unknown":{}}}"#;
+        assert!(matches!(
+            from_json::<Response>(json),
+            Err(WireError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            from_bytes::<Response>(&[2, 0xff]),
+            Err(WireError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            from_bytes::<Response>(&[1, 1, 1, 0]),
+            Err(WireError::TrailingBytes)
+        ));
+    }
+}
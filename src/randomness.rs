@@ -0,0 +1,159 @@
+//! The source of randomness of proofs and secrets.
+//!
+//! Proofs are blinded with two random scalars, and identity stores and
+//! structured signals draw random salts and nonces. Unless a function takes
+//! an explicit rng, like [`generate_proof_rng`], all of them draw from the
+//! global [`RandomnessProvider`]. Deployments can install their own with
+//! [`set_provider`], e.g. to source the randomness from an HSM or a secure
+//! enclave, or to audit every draw in one place.
+//!
+//! The default is [`ThreadRngProvider`], the thread-local CSPRNG of `rand`,
+//! which is seeded and periodically reseeded from the operating system.
+//!
+//! [`generate_proof_rng`]: crate::protocol::generate_proof_rng
+
+use once_cell::sync::OnceCell;
+use rand::rngs::OsRng;
+use rand::{thread_rng, CryptoRng, RngCore};
+
+static PROVIDER: OnceCell<Box<dyn RandomnessProvider>> = OnceCell::new();
+
+/// A source of cryptographically secure random bytes.
+pub trait RandomnessProvider: Send + Sync {
+    /// Fills `dest` with random bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source fails. Nothing is derived from `dest`
+    /// then.
+    fn try_fill_bytes(&self, dest: &mut [u8]) -> Result<(), rand::Error>;
+}
+
+/// Draws from the thread-local rng of `rand`, the default provider.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadRngProvider;
+
+impl RandomnessProvider for ThreadRngProvider {
+    fn try_fill_bytes(&self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        thread_rng().try_fill_bytes(dest)
+    }
+}
+
+/// Draws every byte from the random source of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsRngProvider;
+
+impl RandomnessProvider for OsRngProvider {
+    fn try_fill_bytes(&self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        OsRng.try_fill_bytes(dest)
+    }
+}
+
+/// Installs the global provider.
+///
+/// The provider can only be installed once, before the first draw, so all
+/// randomness of the process comes from the same source.
+///
+/// # Errors
+///
+/// Returns the provider back if a provider was already installed or the
+/// default one was already used.
+pub fn set_provider(
+    provider: Box<dyn RandomnessProvider>,
+) -> Result<(), Box<dyn RandomnessProvider>> {
+    PROVIDER.set(provider)
+}
+
+/// Returns the global provider, see [`set_provider`].
+#[must_use]
+pub fn provider() -> &'static dyn RandomnessProvider {
+    PROVIDER
+        .get_or_init(|| Box::new(ThreadRngProvider))
+        .as_ref()
+}
+
+/// Returns an rng drawing from the global provider.
+#[must_use]
+pub fn rng() -> ProviderRng<'static> {
+    ProviderRng(provider())
+}
+
+/// An rng drawing from a [`RandomnessProvider`], to pass it to functions
+/// taking an rng.
+///
+/// # Panics
+///
+/// The infallible methods of [`RngCore`] panic if the provider fails.
+#[derive(Clone, Copy)]
+pub struct ProviderRng<'a>(pub &'a dyn RandomnessProvider);
+
+impl RngCore for ProviderRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest)
+            .expect("randomness provider failed");
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for ProviderRng<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rand::Rng;
+
+    use super::*;
+
+    /// Counts the bytes drawn, and fails once it drew `limit` bytes.
+    struct Counting {
+        drawn: AtomicUsize,
+        limit: usize,
+    }
+
+    impl RandomnessProvider for Counting {
+        fn try_fill_bytes(&self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            let drawn = self.drawn.fetch_add(dest.len(), Ordering::Relaxed);
+            if drawn + dest.len() > self.limit {
+                return Err(rand::Error::new("exhausted"));
+            }
+            dest.fill(0xab);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_provider_rng() {
+        let provider = Counting {
+            drawn: AtomicUsize::new(0),
+            limit: 44,
+        };
+        let mut rng = ProviderRng(&provider);
+        let mut bytes = [0_u8; 32];
+        rng.fill(&mut bytes);
+        assert_eq!(bytes, [0xab; 32]);
+        assert_eq!(rng.next_u64(), 0xabab_abab_abab_abab);
+        assert_eq!(rng.next_u32(), 0xabab_abab);
+        assert_eq!(provider.drawn.load(Ordering::Relaxed), 44);
+        assert!(rng.try_fill_bytes(&mut [0; 1]).is_err());
+
+        let mut bytes = [0; 64];
+        OsRngProvider.try_fill_bytes(&mut bytes).unwrap();
+        assert_ne!(bytes, [0; 64]);
+    }
+}
@@ -0,0 +1,230 @@
+//! Regression test vectors.
+//!
+//! The embedded vectors cover identity derivation, signal and external
+//! nullifier hashing, nullifier hashes, tree roots and Groth16 proofs per
+//! depth. They were produced by this crate, the proofs by its prover, and
+//! have not been checked against `@semaphore-protocol/proof` or the Solidity
+//! verifier. A build matching them behaves like the build that produced
+//! them, which does not by itself show that it matches on-chain behavior.
+//!
+//! Roots are included for every depth. Proofs are only included for the
+//! depths with a witness graph, 16, 20 and 30, so the verify only depths 21
+//! and 32 have none.
+//!
+//! Downstream users can run [`verify_regression`] in their own test suites
+//! to catch mismatched feature flags, circuit artifacts or dependency
+//! versions.
+
+use once_cell::sync::Lazy;
+use semaphore_depth_config::get_supported_depths;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::identity::Identity;
+use crate::poseidon_tree::LazyPoseidonTree;
+use crate::protocol::{generate_nullifier_hash, verify_proof, Proof, ProofError};
+use crate::{hash_to_field_bytes, Field};
+
+static VECTORS: Lazy<TestVectors> = Lazy::new(|| {
+    serde_json::from_str(include_str!("vectors.json")).expect("embedded vectors are valid")
+});
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TestVectors {
+    pub identities: Vec<IdentityVector>,
+    pub hashes: Vec<HashVector>,
+    pub nullifiers: Vec<NullifierVector>,
+    pub roots: Vec<RootVector>,
+    pub proofs: Vec<ProofVector>,
+}
+
+/// An identity derived with [`Identity::from_secret`] and no trapdoor seed.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdentityVector {
+    pub secret: String,
+    pub trapdoor: Field,
+    pub nullifier: Field,
+    pub commitment: Field,
+}
+
+/// A signal or external nullifier hashed with [`hash_to_field_bytes`].
+#[derive(Clone, Debug, Deserialize)]
+pub struct HashVector {
+    pub input: String,
+    pub hash: Field,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NullifierVector {
+    pub secret: String,
+    pub external_nullifier: String,
+    pub nullifier_hash: Field,
+}
+
+/// Root of a tree with a zero empty leaf, holding the commitments of the
+/// identities derived from `secrets` from index zero onwards.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RootVector {
+    pub depth: usize,
+    pub secrets: Vec<String>,
+    pub root: Field,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProofVector {
+    pub depth: usize,
+    pub root: Field,
+    pub nullifier_hash: Field,
+    pub signal_hash: Field,
+    pub external_nullifier_hash: Field,
+    pub proof: Proof,
+}
+
+#[derive(Error, Debug)]
+pub enum RegressionError {
+    #[error("{kind} mismatch for {input:?}: expected {expected:#x}, got {actual:#x}")]
+    Mismatch {
+        kind: &'static str,
+        input: String,
+        expected: Field,
+        actual: Field,
+    },
+    #[error("proof vector for depth {0} was rejected")]
+    InvalidProof(usize),
+    #[error("Error verifying proof vector: {0}")]
+    ProofError(#[from] ProofError),
+}
+
+/// Returns the embedded test vectors.
+#[must_use]
+pub fn vectors() -> &'static TestVectors {
+    &VECTORS
+}
+
+/// Checks this build against the embedded test vectors.
+///
+/// Proof vectors are only checked for the depths enabled through the
+/// `depth_*` features.
+///
+/// # Errors
+///
+/// Returns the first mismatching vector.
+pub fn verify_regression() -> Result<(), RegressionError> {
+    let vectors = vectors();
+
+    for vector in &vectors.identities {
+        let identity = identity(&vector.secret);
+        check(
+            "trapdoor",
+            &vector.secret,
+            vector.trapdoor,
+            identity.trapdoor,
+        )?;
+        check(
+            "nullifier",
+            &vector.secret,
+            vector.nullifier,
+            identity.nullifier,
+        )?;
+        check(
+            "commitment",
+            &vector.secret,
+            vector.commitment,
+            identity.commitment(),
+        )?;
+    }
+
+    for vector in &vectors.hashes {
+        let hash = hash_to_field_bytes(vector.input.as_bytes());
+        check("hash", &vector.input, vector.hash, hash)?;
+    }
+
+    for vector in &vectors.nullifiers {
+        let external_nullifier_hash = hash_to_field_bytes(vector.external_nullifier.as_bytes());
+        let nullifier_hash =
+            generate_nullifier_hash(&identity(&vector.secret), external_nullifier_hash);
+        check(
+            "nullifier hash",
+            &vector.secret,
+            vector.nullifier_hash,
+            nullifier_hash,
+        )?;
+    }
+
+    for vector in &vectors.roots {
+        let mut tree = LazyPoseidonTree::new(vector.depth, Field::ZERO).derived();
+        for (index, secret) in vector.secrets.iter().enumerate() {
+            tree = tree.update(index, &identity(secret).commitment());
+        }
+        check(
+            "root",
+            &format!("depth {} with {:?}", vector.depth, vector.secrets),
+            vector.root,
+            tree.root(),
+        )?;
+    }
+
+    for vector in &vectors.proofs {
+        if !get_supported_depths().contains(&vector.depth) {
+            continue;
+        }
+        let valid = verify_proof(
+            vector.root,
+            vector.nullifier_hash,
+            vector.signal_hash,
+            vector.external_nullifier_hash,
+            &vector.proof,
+            vector.depth,
+        )?;
+        if !valid {
+            return Err(RegressionError::InvalidProof(vector.depth));
+        }
+    }
+
+    Ok(())
+}
+
+fn identity(secret: &str) -> Identity {
+    let mut secret = secret.as_bytes().to_vec();
+    Identity::from_secret(&mut secret, None)
+}
+
+fn check(
+    kind: &'static str,
+    input: &str,
+    expected: Field,
+    actual: Field,
+) -> Result<(), RegressionError> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(RegressionError::Mismatch {
+            kind,
+            input: input.to_owned(),
+            expected,
+            actual,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify_regression() {
+        verify_regression().unwrap();
+    }
+
+    #[test]
+    fn test_vectors_cover_all_depths() {
+        for depth in get_supported_depths() {
+            assert!(vectors().roots.iter().any(|v| v.depth == *depth));
+        }
+        // Proof vectors are generated with the prover, so they cover the
+        // depths with a witness graph
+        for depth in semaphore_depth_config::get_prover_depths() {
+            assert!(vectors().proofs.iter().any(|v| v.depth == *depth));
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Responses of remote tree services.
+//!
+//! Services like the signup sequencer keep the identity tree and return
+//! inclusion proofs for identity commitments. [`InclusionProofResponse`]
+//! parses the response of such an `inclusionProof` endpoint, with the proof
+//! either as a list of branches or as `siblings` and `pathIndices`, and
+//! checks it against the commitment before it's used to generate a proof.
+//!
+//! See `examples/remote_tree.rs` for the full client flow.
+
+use poseidon::Poseidon;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use trees::{Branch, InclusionProof};
+
+use crate::Field;
+
+/// Merkle proof as returned by a tree service.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ProofResponse {
+    /// Branches from the leaf up, e.g. `[{"Left": "0x.."}, {"Right": "0x.."}]`
+    /// as returned by the signup sequencer.
+    Branches(Vec<Branch<Field>>),
+    /// Siblings from the leaf up, with a path index of 1 where the node is
+    /// a right child.
+    #[serde(rename_all = "camelCase")]
+    Paths {
+        siblings: Vec<Field>,
+        path_indices: Vec<u8>,
+    },
+}
+
+/// Response of an `inclusionProof` endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InclusionProofResponse {
+    /// Processing status of the commitment, e.g. `pending` or `mined`.
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Root of the tree the proof is for, missing until the commitment is
+    /// in the tree.
+    #[serde(default)]
+    pub root: Option<Field>,
+    #[serde(default)]
+    pub proof: Option<ProofResponse>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TreeServiceError {
+    #[error("commitment is not in the tree yet (status: {status:?}, message: {message:?})")]
+    NotIncluded {
+        status: Option<String>,
+        message: Option<String>,
+    },
+    #[error("got {siblings} siblings but {path_indices} path indices")]
+    LengthMismatch {
+        siblings: usize,
+        path_indices: usize,
+    },
+    #[error("path index must be 0 or 1, got {0}")]
+    InvalidPathIndex(u8),
+    #[error("proof does not prove the commitment in the returned root")]
+    RootMismatch,
+}
+
+impl TryFrom<ProofResponse> for InclusionProof<Poseidon> {
+    type Error = TreeServiceError;
+
+    fn try_from(proof: ProofResponse) -> Result<Self, Self::Error> {
+        match proof {
+            ProofResponse::Branches(branches) => Ok(Self(branches)),
+            ProofResponse::Paths {
+                siblings,
+                path_indices,
+            } => {
+                if siblings.len() != path_indices.len() {
+                    return Err(TreeServiceError::LengthMismatch {
+                        siblings: siblings.len(),
+                        path_indices: path_indices.len(),
+                    });
+                }
+                siblings
+                    .into_iter()
+                    .zip(path_indices)
+                    .map(|(sibling, index)| match index {
+                        0 => Ok(Branch::Left(sibling)),
+                        1 => Ok(Branch::Right(sibling)),
+                        other => Err(TreeServiceError::InvalidPathIndex(other)),
+                    })
+                    .collect::<Result<_, _>>()
+                    .map(Self)
+            }
+        }
+    }
+}
+
+impl InclusionProofResponse {
+    /// Returns the root and the inclusion proof of `commitment`, after
+    /// checking that the proof leads from the commitment to the root.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the response has no proof yet, if the proof is
+    /// malformed, or if it doesn't prove `commitment` in the root.
+    pub fn into_inclusion_proof(
+        self,
+        commitment: Field,
+    ) -> Result<(Field, InclusionProof<Poseidon>), TreeServiceError> {
+        let (Some(root), Some(proof)) = (self.root, self.proof) else {
+            return Err(TreeServiceError::NotIncluded {
+                status: self.status,
+                message: self.message,
+            });
+        };
+        let proof = InclusionProof::<Poseidon>::try_from(proof)?;
+        if proof.root(commitment) != root {
+            return Err(TreeServiceError::RootMismatch);
+        }
+        Ok((root, proof))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::poseidon_tree::LazyPoseidonTree;
+
+    fn tree() -> (LazyPoseidonTree, Field) {
+        let commitment = Field::from(42);
+        let tree = LazyPoseidonTree::new(4, Field::ZERO)
+            .derived()
+            .update(0, &Field::from(7))
+            .update(5, &commitment);
+        (tree, commitment)
+    }
+
+    #[test]
+    fn test_sequencer_response() {
+        let (tree, commitment) = tree();
+        let response: InclusionProofResponse = serde_json::from_value(json!({
+            "status": "mined",
+            "root": tree.root(),
+            "proof": tree.proof(5),
+            "message": null,
+        }))
+        .unwrap();
+        let (root, proof) = response.into_inclusion_proof(commitment).unwrap();
+        assert_eq!(root, tree.root());
+        assert_eq!(proof, tree.proof(5));
+
+        // Unknown commitments have no proof yet
+        let response: InclusionProofResponse =
+            serde_json::from_value(json!({ "status": "pending" })).unwrap();
+        assert_eq!(
+            response.into_inclusion_proof(commitment),
+            Err(TreeServiceError::NotIncluded {
+                status: Some("pending".to_owned()),
+                message: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_paths_response() {
+        let (tree, commitment) = tree();
+        let proof = tree.proof(5);
+        let siblings = proof.siblings();
+        let parsed: InclusionProofResponse = serde_json::from_value(json!({
+            "root": tree.root(),
+            "proof": {
+                "siblings": siblings,
+                "pathIndices": [1, 0, 1, 0],
+            },
+        }))
+        .unwrap();
+        assert_eq!(
+            parsed.clone().into_inclusion_proof(commitment),
+            Ok((tree.root(), proof))
+        );
+        assert_eq!(
+            parsed.into_inclusion_proof(Field::from(43)),
+            Err(TreeServiceError::RootMismatch)
+        );
+
+        let invalid = ProofResponse::Paths {
+            siblings: siblings.clone(),
+            path_indices: vec![1, 0, 2, 0],
+        };
+        assert_eq!(
+            InclusionProof::<Poseidon>::try_from(invalid),
+            Err(TreeServiceError::InvalidPathIndex(2))
+        );
+        let short = ProofResponse::Paths {
+            siblings,
+            path_indices: vec![1, 0, 1],
+        };
+        assert_eq!(
+            InclusionProof::<Poseidon>::try_from(short),
+            Err(TreeServiceError::LengthMismatch {
+                siblings: 4,
+                path_indices: 3,
+            })
+        );
+    }
+}
@@ -0,0 +1,575 @@
+//! Keeping a tree in sync with the membership events of a group contract.
+//!
+//! Group contracts emit an event for every member that is added, updated or
+//! removed. [`TreeSync`] applies these events, read from an [`EventSource`],
+//! to a [`CascadingMerkleTree`] in memory mapped storage, so indexers get the
+//! tree of the group without re-implementing the bookkeeping.
+//!
+//! Blocks can be reorganized out of the chain, together with their events.
+//! The tree is snapshotted every few blocks by copying its storage file, and
+//! when the last synced block is no longer on the chain, the tree is rolled
+//! back to the newest snapshot that still is and synced again from there.
+//! A sync directory holds:
+//!
+//! ```markdown
+//! tree.bin                 storage of the tree
+//! state.json               last synced block, its root and the snapshots
+//! snapshot-<number>.bin    storage of the tree at block <number>
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ethers_core::types::H256;
+use poseidon::Poseidon;
+use serde::{Deserialize, Serialize};
+use storage::MmapVec;
+use thiserror::Error;
+use trees::cascading::CascadingMerkleTree;
+
+use crate::Field;
+
+pub type SyncedTree = CascadingMerkleTree<Poseidon, MmapVec<Field>>;
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error("event source error: {0}")]
+    Source(Box<dyn std::error::Error + Send + Sync>),
+    #[error("storage error: {0}")]
+    Storage(#[source] storage::StorageError),
+    #[error("tree error: {0}")]
+    Tree(#[from] trees::TreeError),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid sync state: {0}")]
+    State(#[from] serde_json::Error),
+    #[error("block {0} is not on the chain")]
+    MissingBlock(u64),
+    #[error("member {index} was added to a tree of {num_leaves} members")]
+    UnexpectedIndex { index: usize, num_leaves: usize },
+    #[error("member {index} is not in the tree")]
+    UnknownMember { index: usize },
+    #[error("root after the event is {actual:#x}, but the event has {expected:#x}")]
+    RootMismatch { expected: Field, actual: Field },
+}
+
+/// A block on the chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRef {
+    pub number: u64,
+    pub hash: H256,
+}
+
+/// A change of the members of a group, as in the `MemberAdded`,
+/// `MemberUpdated` and `MemberRemoved` events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemberChange {
+    Added {
+        index: usize,
+        commitment: Field,
+    },
+    Updated {
+        index: usize,
+        commitment: Field,
+    },
+    /// Removed members are set to the empty value of the tree.
+    Removed {
+        index: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemberEvent {
+    pub change: MemberChange,
+    /// Root of the tree after the change, if the event has it. It is checked
+    /// against the synced tree.
+    pub root: Option<Field>,
+}
+
+/// Membership events of one group, e.g. read with `eth_getLogs`.
+pub trait EventSource {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// The block to sync up to, e.g. the latest or the latest safe block.
+    fn head(&mut self) -> Result<BlockRef, Self::Error>;
+
+    /// The hash of the block with the given number on the current chain.
+    fn block_hash(&mut self, number: u64) -> Result<Option<H256>, Self::Error>;
+
+    /// The events of the blocks `from..=to`, in the order they were emitted.
+    fn events(&mut self, from: u64, to: u64) -> Result<Vec<MemberEvent>, Self::Error>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncConfig {
+    pub depth: usize,
+    pub empty_value: Field,
+    /// The first block to read events from, e.g. the deployment block of
+    /// the contract.
+    pub start_block: u64,
+    /// Blocks between snapshots.
+    pub snapshot_interval: u64,
+    /// Snapshots to keep. Reorgs deeper than the oldest snapshot resync the
+    /// tree from `start_block`.
+    pub max_snapshots: usize,
+    /// Maximum blocks to read events of at once.
+    pub max_range: u64,
+}
+
+impl SyncConfig {
+    #[must_use]
+    pub const fn new(depth: usize, empty_value: Field) -> Self {
+        Self {
+            depth,
+            empty_value,
+            start_block: 0,
+            snapshot_interval: 64,
+            max_snapshots: 8,
+            max_range: 1000,
+        }
+    }
+}
+
+/// What a call of [`TreeSync::sync`] did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Events applied to the tree.
+    pub applied: usize,
+    /// The block the tree was rolled back to because of a reorg, `None` for
+    /// the start of the chain.
+    pub rolled_back: Option<Option<u64>>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    synced: Option<BlockRef>,
+    root: Option<Field>,
+    /// Oldest first.
+    snapshots: Vec<BlockRef>,
+}
+
+/// A tree synced from membership events, persisted in a directory.
+pub struct TreeSync {
+    dir: PathBuf,
+    config: SyncConfig,
+    tree: SyncedTree,
+    state: SyncState,
+}
+
+impl std::fmt::Debug for TreeSync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TreeSync")
+            .field("dir", &self.dir)
+            .field("synced", &self.state.synced)
+            .field("num_leaves", &self.tree.num_leaves())
+            .finish_non_exhaustive()
+    }
+}
+
+impl TreeSync {
+    /// Opens the synced tree in `dir`, or creates an empty one.
+    ///
+    /// If the process stopped while the tree was written, the tree is rolled
+    /// back to the newest snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory can't be read or written, or holds
+    /// an invalid tree.
+    pub fn open(dir: impl AsRef<Path>, config: SyncConfig) -> Result<Self, SyncError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let state = match fs::read(dir.join("state.json")) {
+            Ok(json) => serde_json::from_slice(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => SyncState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let tree_path = dir.join("tree.bin");
+        let tree = if tree_path.exists() {
+            restore(&tree_path, &config)?
+        } else {
+            create(&tree_path, &config)?
+        };
+        let mut sync = Self {
+            dir,
+            config,
+            tree,
+            state,
+        };
+        let consistent = match sync.state.root {
+            Some(root) => root == sync.tree.root(),
+            None => sync.tree.num_leaves() == 0,
+        };
+        if !consistent {
+            let snapshot = sync.state.snapshots.last().copied();
+            sync.roll_back(snapshot)?;
+        }
+        Ok(sync)
+    }
+
+    #[must_use]
+    pub const fn tree(&self) -> &SyncedTree {
+        &self.tree
+    }
+
+    /// The last block whose events are in the tree.
+    #[must_use]
+    pub const fn synced_block(&self) -> Option<BlockRef> {
+        self.state.synced
+    }
+
+    /// Applies the events up to the head of `source`, rolling back the tree
+    /// first if the last synced block was reorganized out of the chain.
+    ///
+    /// If the chain changes while events are read, the sync stops at the
+    /// last block read before the change, and the next call continues.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading events or writing the tree fails, or if
+    /// an event doesn't match the tree. If an event doesn't match, the tree
+    /// is rolled back to the newest snapshot.
+    pub fn sync<E: EventSource>(&mut self, source: &mut E) -> Result<SyncReport, SyncError> {
+        let mut report = SyncReport::default();
+        if let Some(synced) = self.state.synced {
+            if source.block_hash(synced.number).map_err(source_error)? != Some(synced.hash) {
+                let snapshot = self.newest_snapshot_on_chain(source)?;
+                self.roll_back(snapshot)?;
+                report.rolled_back = Some(snapshot.map(|block| block.number));
+            }
+        }
+
+        let head = source.head().map_err(source_error)?;
+        let mut from = self
+            .state
+            .synced
+            .map_or(self.config.start_block, |block| block.number + 1);
+        while from <= head.number {
+            let to = head.number.min(from + self.config.max_range.max(1) - 1);
+            let hash = source
+                .block_hash(to)
+                .map_err(source_error)?
+                .ok_or(SyncError::MissingBlock(to))?;
+            let events = source.events(from, to).map_err(source_error)?;
+            if source.block_hash(to).map_err(source_error)? != Some(hash) {
+                break;
+            }
+            if let Err(e) = self.apply(&events) {
+                let snapshot = self.state.snapshots.last().copied();
+                self.roll_back(snapshot)?;
+                return Err(e);
+            }
+            report.applied += events.len();
+            self.checkpoint(BlockRef { number: to, hash })?;
+            from = to + 1;
+        }
+        Ok(report)
+    }
+
+    fn apply(&mut self, events: &[MemberEvent]) -> Result<(), SyncError> {
+        for event in events {
+            let num_leaves = self.tree.num_leaves();
+            match event.change {
+                MemberChange::Added { index, commitment } => {
+                    if index != num_leaves {
+                        return Err(SyncError::UnexpectedIndex { index, num_leaves });
+                    }
+                    self.tree.push(commitment)?;
+                }
+                MemberChange::Updated { index, commitment } => {
+                    if index >= num_leaves {
+                        return Err(SyncError::UnknownMember { index });
+                    }
+                    self.tree.set_leaf(index, commitment)?;
+                }
+                MemberChange::Removed { index } => {
+                    if index >= num_leaves {
+                        return Err(SyncError::UnknownMember { index });
+                    }
+                    self.tree.set_leaf(index, self.config.empty_value)?;
+                }
+            }
+            if let Some(expected) = event.root {
+                let actual = self.tree.root();
+                if actual != expected {
+                    return Err(SyncError::RootMismatch { expected, actual });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the tree as synced up to `block`, snapshotting it if the
+    /// newest snapshot is old enough.
+    fn checkpoint(&mut self, block: BlockRef) -> Result<(), SyncError> {
+        self.tree.flush()?;
+        let due = match self.state.snapshots.last() {
+            Some(snapshot) => block.number - snapshot.number >= self.config.snapshot_interval,
+            None => true,
+        };
+        if due && self.config.max_snapshots > 0 {
+            fs::copy(self.dir.join("tree.bin"), self.snapshot_path(block.number))?;
+            self.state.snapshots.push(block);
+            let excess = self
+                .state
+                .snapshots
+                .len()
+                .saturating_sub(self.config.max_snapshots);
+            for snapshot in self.state.snapshots.drain(..excess).collect::<Vec<_>>() {
+                remove_if_exists(&self.snapshot_path(snapshot.number))?;
+            }
+        }
+        self.state.synced = Some(block);
+        self.state.root = Some(self.tree.root());
+        self.save_state()
+    }
+
+    fn newest_snapshot_on_chain<E: EventSource>(
+        &self,
+        source: &mut E,
+    ) -> Result<Option<BlockRef>, SyncError> {
+        for snapshot in self.state.snapshots.iter().rev() {
+            if source.block_hash(snapshot.number).map_err(source_error)? == Some(snapshot.hash) {
+                return Ok(Some(*snapshot));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Restores the tree to `snapshot`, or to an empty tree for `None`, and
+    /// drops the newer snapshots.
+    fn roll_back(&mut self, snapshot: Option<BlockRef>) -> Result<(), SyncError> {
+        // Restore into a new file and move it into place, so the tree is
+        // never mapped from a file that is being overwritten
+        let restored = self.dir.join("tree.bin.restore");
+        remove_if_exists(&restored)?;
+        let tree = match snapshot {
+            Some(block) => {
+                fs::copy(self.snapshot_path(block.number), &restored)?;
+                restore(&restored, &self.config)?
+            }
+            None => create(&restored, &self.config)?,
+        };
+        fs::rename(&restored, self.dir.join("tree.bin"))?;
+        self.tree = tree;
+
+        let keep = snapshot.map_or(0, |block| {
+            self.state
+                .snapshots
+                .iter()
+                .take_while(|s| s.number <= block.number)
+                .count()
+        });
+        for dropped in self.state.snapshots.split_off(keep) {
+            remove_if_exists(&self.snapshot_path(dropped.number))?;
+        }
+        self.state.synced = snapshot;
+        self.state.root = Some(self.tree.root());
+        self.save_state()
+    }
+
+    fn snapshot_path(&self, number: u64) -> PathBuf {
+        self.dir.join(format!("snapshot-{number}.bin"))
+    }
+
+    /// Writes the state to a new file and moves it into place, so a crash
+    /// never leaves a partially written state.
+    fn save_state(&self) -> Result<(), SyncError> {
+        let path = self.dir.join("state.json.tmp");
+        fs::write(&path, serde_json::to_vec(&self.state)?)?;
+        fs::rename(path, self.dir.join("state.json"))?;
+        Ok(())
+    }
+}
+
+fn create(path: &Path, config: &SyncConfig) -> Result<SyncedTree, SyncError> {
+    let storage = MmapVec::create_from_path(path).map_err(SyncError::Storage)?;
+    Ok(CascadingMerkleTree::new(
+        storage,
+        config.depth,
+        &config.empty_value,
+    ))
+}
+
+fn restore(path: &Path, config: &SyncConfig) -> Result<SyncedTree, SyncError> {
+    let storage = MmapVec::restore_from_path(path).map_err(SyncError::Storage)?;
+    Ok(CascadingMerkleTree::restore(
+        storage,
+        config.depth,
+        &config.empty_value,
+    )?)
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn source_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> SyncError {
+    SyncError::Source(Box::new(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    /// A chain of blocks with the events of each block.
+    struct Chain {
+        blocks: Vec<(H256, Vec<MemberEvent>)>,
+    }
+
+    impl Chain {
+        /// Appends blocks adding the given commitments, one per block, on a
+        /// fork identified by `fork`.
+        fn extend(&mut self, fork: u8, commitments: &[u64]) {
+            for &commitment in commitments {
+                let number = self.blocks.len() as u64;
+                let mut hash = [fork; 32];
+                hash[..8].copy_from_slice(&number.to_be_bytes());
+                let index = self.members();
+                let change = MemberChange::Added {
+                    index,
+                    commitment: Field::from(commitment),
+                };
+                let event = MemberEvent { change, root: None };
+                self.blocks.push((H256(hash), vec![event]));
+            }
+        }
+
+        fn members(&self) -> usize {
+            self.blocks.iter().map(|(_, events)| events.len()).sum()
+        }
+
+        fn expected_root(&self) -> Field {
+            let mut tree = CascadingMerkleTree::<Poseidon>::new(vec![], 10, &Field::from(0));
+            for (_, events) in &self.blocks {
+                for event in events {
+                    match event.change {
+                        MemberChange::Added { commitment, .. } => tree.push(commitment).unwrap(),
+                        MemberChange::Updated { index, commitment } => {
+                            tree.set_leaf(index, commitment).unwrap();
+                        }
+                        MemberChange::Removed { index } => {
+                            tree.set_leaf(index, Field::from(0)).unwrap();
+                        }
+                    }
+                }
+            }
+            tree.root()
+        }
+    }
+
+    impl EventSource for Chain {
+        type Error = Infallible;
+
+        fn head(&mut self) -> Result<BlockRef, Infallible> {
+            let number = self.blocks.len() as u64 - 1;
+            Ok(BlockRef {
+                number,
+                hash: self.blocks[number as usize].0,
+            })
+        }
+
+        fn block_hash(&mut self, number: u64) -> Result<Option<H256>, Infallible> {
+            Ok(self.blocks.get(number as usize).map(|(hash, _)| *hash))
+        }
+
+        fn events(&mut self, from: u64, to: u64) -> Result<Vec<MemberEvent>, Infallible> {
+            Ok(self.blocks[from as usize..=to as usize]
+                .iter()
+                .flat_map(|(_, events)| events.clone())
+                .collect())
+        }
+    }
+
+    fn config() -> SyncConfig {
+        SyncConfig {
+            snapshot_interval: 4,
+            max_snapshots: 3,
+            max_range: 3,
+            ..SyncConfig::new(10, Field::from(0))
+        }
+    }
+
+    #[test]
+    fn test_sync_and_reorg() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chain = Chain { blocks: vec![] };
+        chain.extend(0, &(1..=20).collect::<Vec<_>>());
+
+        let mut sync = TreeSync::open(dir.path(), config()).unwrap();
+        let report = sync.sync(&mut chain).unwrap();
+        assert_eq!(report.applied, 20);
+        assert_eq!(report.rolled_back, None);
+        assert_eq!(sync.tree().root(), chain.expected_root());
+        assert_eq!(sync.synced_block().unwrap().number, 19);
+
+        // Replace the last 5 blocks with 7 others
+        chain.blocks.truncate(15);
+        chain.extend(1, &(100..107).collect::<Vec<_>>());
+        let report = sync.sync(&mut chain).unwrap();
+        let rolled_back = report.rolled_back.unwrap().unwrap();
+        assert!(rolled_back < 15);
+        assert_eq!(report.applied, 21 - rolled_back as usize);
+        assert_eq!(sync.tree().num_leaves(), 22);
+        assert_eq!(sync.tree().root(), chain.expected_root());
+
+        // The state survives reopening
+        drop(sync);
+        let mut sync = TreeSync::open(dir.path(), config()).unwrap();
+        assert_eq!(sync.tree().root(), chain.expected_root());
+        chain.extend(1, &[200]);
+        assert_eq!(sync.sync(&mut chain).unwrap().applied, 1);
+        assert_eq!(sync.tree().root(), chain.expected_root());
+    }
+
+    #[test]
+    fn test_deep_reorg_resyncs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chain = Chain { blocks: vec![] };
+        chain.extend(0, &(1..=30).collect::<Vec<_>>());
+        let mut sync = TreeSync::open(dir.path(), config()).unwrap();
+        sync.sync(&mut chain).unwrap();
+
+        // Older than all snapshots kept
+        chain.blocks.truncate(2);
+        chain.extend(1, &[7, 8, 9]);
+        let report = sync.sync(&mut chain).unwrap();
+        assert_eq!(report.rolled_back, Some(None));
+        assert_eq!(report.applied, 5);
+        assert_eq!(sync.tree().root(), chain.expected_root());
+    }
+
+    #[test]
+    fn test_invalid_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chain = Chain { blocks: vec![] };
+        chain.extend(0, &[1, 2, 3]);
+        let mut sync = TreeSync::open(dir.path(), config()).unwrap();
+        sync.sync(&mut chain).unwrap();
+        let root = sync.tree().root();
+
+        chain.extend(0, &[4]);
+        chain.blocks[3].1[0].root = Some(Field::from(1));
+        assert!(matches!(
+            sync.sync(&mut chain),
+            Err(SyncError::RootMismatch { .. })
+        ));
+        // Rolled back to the snapshot of the last synced block
+        assert_eq!(sync.tree().root(), root);
+        assert_eq!(sync.synced_block().unwrap().number, 2);
+
+        chain.blocks[3].1[0] = MemberEvent {
+            change: MemberChange::Removed { index: 9 },
+            root: None,
+        };
+        assert!(matches!(
+            sync.sync(&mut chain),
+            Err(SyncError::UnknownMember { index: 9 })
+        ));
+    }
+}
@@ -6,7 +6,7 @@ use serde::{
     de::{Error as DeError, Visitor},
     Deserializer, Serializer,
 };
-use tiny_keccak::{Hasher as _, Keccak};
+use tiny_keccak::{Hasher as _, Keccak, Sha3};
 
 pub(crate) fn keccak256(bytes: &[u8]) -> [u8; 32] {
     let mut output = [0; 32];
@@ -16,6 +16,14 @@ pub(crate) fn keccak256(bytes: &[u8]) -> [u8; 32] {
     output
 }
 
+pub(crate) fn sha3_256(bytes: &[u8]) -> [u8; 32] {
+    let mut output = [0; 32];
+    let mut hasher = Sha3::v256();
+    hasher.update(bytes);
+    hasher.finalize(&mut output);
+    output
+}
+
 pub(crate) fn bytes_to_hex<const N: usize, const M: usize>(bytes: &[u8; N]) -> [u8; M] {
     // TODO: Replace `M` with a const expression once it's stable.
     debug_assert_eq!(M, 2 * N + 2);
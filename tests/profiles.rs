@@ -0,0 +1,112 @@
+//! Checks that each feature profile documented in the README exposes its
+//! APIs. CI runs this test once per profile, see the `profiles` job. The
+//! `wasm` profile runs it in Node.js, where only the WebAssembly test runs.
+
+use semaphore::identity::Identity;
+use semaphore::poseidon_tree::{LazyPoseidonTree, PoseidonTree};
+use semaphore::{get_supported_depths, hash_to_field_bytes, Field};
+
+#[test]
+fn test_trees_profile() {
+    let mut secret = *b"secret";
+    let id = Identity::from_secret(&mut secret, None);
+
+    let tree = LazyPoseidonTree::new(16, Field::from(0)).update(0, &id.commitment());
+    let proof = tree.proof(0);
+    assert_eq!(proof.root(id.commitment()), tree.root());
+
+    let _ = PoseidonTree::new(4, Field::from(0));
+    let _ = hash_to_field_bytes(b"signal");
+    let _ = semaphore::bridge::propagate_root_calldata();
+}
+
+#[cfg(feature = "verifier")]
+#[test]
+fn test_verifier_profile() {
+    use semaphore::protocol::{generate_nullifier_hash, verify_compressed_proof, verify_proof};
+
+    // Depths are listed in increasing order, e.g. for `protocol::v4::circuit_depth`
+    let depths = get_supported_depths();
+    assert!(!depths.is_empty());
+    assert!(depths.windows(2).all(|pair| pair[0] < pair[1]));
+    for &depth in depths {
+        assert!(semaphore::protocol::check_depth(depth).is_ok());
+    }
+    let _ = generate_nullifier_hash;
+    let _ = verify_proof;
+    let _ = verify_compressed_proof;
+    let _ = semaphore::test_vectors::verify_regression;
+}
+
+#[cfg(not(feature = "verifier"))]
+#[test]
+fn test_verifier_profile() {
+    // Depth features only matter when verifying
+    let _ = get_supported_depths();
+}
+
+#[cfg(feature = "prover")]
+#[test]
+fn test_prover_profile() {
+    use semaphore::protocol::{check_prover_depth, generate_proof, generate_witness};
+
+    // Depths without a witness graph are verify only, every prover profile
+    // has at least one depth to prove
+    let prover_depths = semaphore::get_prover_depths();
+    assert!(!prover_depths.is_empty());
+    assert!(!prover_depths.contains(&21) && !prover_depths.contains(&32));
+    for &depth in get_supported_depths() {
+        let has_graph = prover_depths.contains(&depth);
+        assert_eq!(check_prover_depth(depth).is_ok(), has_graph);
+    }
+    let _ = generate_proof;
+    let _ = generate_witness;
+    let _ = semaphore::protocol::graph_stats::witness_graph_stats;
+}
+
+#[cfg(feature = "v4")]
+#[test]
+fn test_v4_profile() {
+    use semaphore::protocol::v4::{circuit_depth, generate_nullifier, verify_proof};
+
+    assert_eq!(
+        circuit_depth(get_supported_depths()[0]),
+        Some(get_supported_depths()[0])
+    );
+    let _ = generate_nullifier;
+    let _ = verify_proof;
+}
+
+#[cfg(feature = "external-artifacts")]
+#[test]
+fn test_external_artifacts_profile() {
+    use semaphore::circuit::{set_artifact_source, ArtifactError, ArtifactSource};
+
+    assert!(matches!(
+        set_artifact_source(ArtifactSource::Embedded),
+        Err(ArtifactError::NotEmbedded)
+    ));
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "prover"))]
+#[wasm_bindgen_test::wasm_bindgen_test]
+fn test_wasm_profile() {
+    use semaphore::poseidon_tree::{from_snapshot, TreeConfig};
+    use semaphore::protocol::{generate_proof, verify_proof};
+
+    // Randomness comes from `crypto.getRandomValues`
+    let mut secret = [0; 32];
+    semaphore::randomness::provider()
+        .try_fill_bytes(&mut secret)
+        .unwrap();
+    assert_ne!(secret, [0; 32]);
+
+    // The in-memory trees work, the memory mapped ones can't be created
+    let _ = LazyPoseidonTree::new(16, Field::from(0)).proof(0);
+    let config = TreeConfig::new(4, Field::from(0));
+    assert!(from_snapshot(&b"1\n"[..], &config, "tree.bin").is_err());
+
+    assert!(!semaphore::get_prover_depths().is_empty());
+    let _ = generate_proof;
+    let _ = verify_proof;
+}
@@ -0,0 +1,35 @@
+//! Proves and verifies a proof in WebAssembly. CI runs this test on
+//! `wasm32-unknown-unknown` in Node.js, see the `wasm` profile of the
+//! `profiles` job.
+
+#![cfg(all(target_arch = "wasm32", feature = "prover", feature = "depth_16"))]
+
+use semaphore::identity::Identity;
+use semaphore::poseidon_tree::LazyPoseidonTree;
+use semaphore::protocol::{generate_nullifier_hash, generate_proof, verify_proof};
+use semaphore::{hash_to_field_bytes, Field};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn test_proof_depth_16() {
+    let mut secret = *b"oh so secret";
+    let id = Identity::from_secret(&mut secret[..], None);
+
+    let tree = LazyPoseidonTree::new(16, Field::from(0)).update(0, &id.commitment());
+    let merkle_proof = tree.proof(0);
+
+    let signal_hash = hash_to_field_bytes(b"signal");
+    let external_nullifier_hash = hash_to_field_bytes(b"appId");
+    let nullifier_hash = generate_nullifier_hash(&id, external_nullifier_hash);
+
+    let proof = generate_proof(&id, &merkle_proof, external_nullifier_hash, signal_hash).unwrap();
+    assert!(verify_proof(
+        tree.root(),
+        nullifier_hash,
+        signal_hash,
+        external_nullifier_hash,
+        &proof,
+        16,
+    )
+    .unwrap());
+}